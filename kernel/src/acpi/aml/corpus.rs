@@ -0,0 +1,190 @@
+//! Tests of the AML parser against real ACPI tables
+//!
+//! Each table in `corpus/` is a raw dump (with the header) of a `DSDT`/`SSDT`, i.e. from
+//! `/sys/firmware/acpi/tables/` on Linux, with the expected display of the parsed code
+//! and the structured form next to it.
+//!
+//! The `qemu_*` tables are not dumps, they were assembled by hand from the ASL that QEMU generates
+//! (`build_dsdt` in `hw/i386/acpi-build.c` for `q35`, and the CPU hotplug `SSDT` of the older
+//! `i440fx` builds), keeping the same order of declarations, which is what the parser guessing
+//! depends on. They should be replaced by real dumps.
+//!
+//! These run in QEMU with the rest of the kernel tests, the kernel crate only builds for the kernel
+//! target, so they can't be run with `cargo xtask test-host` until `aml` is moved to `libraries/`.
+
+use core::mem;
+
+use alloc::{format, vec, vec::Vec};
+use tracing::{info, trace};
+
+use crate::{acpi::tables::DescriptionHeader, testing};
+
+use super::{
+    execution::ExecutionContext,
+    parser::{self, IntegerData, UnresolvedDataObject},
+    Aml,
+};
+
+const SEED: u64 = 0x5EED_0AC1_D00D_F00D;
+const MUTATIONS_PER_TABLE: usize = 2000;
+
+struct CorpusTable {
+    name: &'static str,
+    table: &'static [u8],
+    code: &'static str,
+    structured: &'static str,
+}
+
+impl CorpusTable {
+    fn body(&self) -> &'static [u8] {
+        &self.table[mem::size_of::<DescriptionHeader>()..]
+    }
+}
+
+macro_rules! corpus_table {
+    ($name:literal) => {
+        CorpusTable {
+            name: $name,
+            table: include_bytes!(concat!("corpus/", $name, ".aml")),
+            code: include_str!(concat!("corpus/", $name, ".code.txt")),
+            structured: include_str!(concat!("corpus/", $name, ".structured.txt")),
+        }
+    };
+}
+
+const CORPUS: &[CorpusTable] = &[
+    corpus_table!("firecracker_dsdt"),
+    corpus_table!("qemu_q35_dsdt"),
+    corpus_table!("qemu_i440fx_ssdt"),
+];
+
+/// `xorshift64`, we only need the values to be spread and reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Parse and display the result, this must never panic, whatever the input is
+fn parse_and_display(body: &[u8]) {
+    if let Ok(aml) = Aml::parse(body) {
+        let _ = format!("{:#}{:#}", aml.code(), aml.structured());
+    }
+}
+
+/// Returns the first line that differs, to not flood the output with the whole table
+fn first_difference<'a>(expected: &'a str, got: &'a str) -> Option<(usize, &'a str, &'a str)> {
+    let mut expected_lines = expected.trim_end().lines();
+    let mut got_lines = got.trim_end().lines();
+
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), got_lines.next()) {
+            (None, None) => return None,
+            (expected, got) if expected == got => line += 1,
+            (expected, got) => {
+                return Some((line, expected.unwrap_or("<EOF>"), got.unwrap_or("<EOF>")));
+            }
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_corpus_parses() {
+    for table in CORPUS {
+        let code = parser::parse_aml(table.body());
+        assert!(code.is_ok(), "{}: {:?}", table.name, code.err());
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_corpus_golden_display() {
+    for table in CORPUS {
+        let aml = Aml::parse(table.body()).unwrap();
+
+        let code = format!("{:#}", aml.code());
+        if let Some((line, expected, got)) = first_difference(table.code, &code) {
+            panic!(
+                "{}: code display changed at line {line}:\nexpected: {expected}\n     got: {got}",
+                table.name
+            );
+        }
+
+        let structured = format!("{:#}", aml.structured());
+        if let Some((line, expected, got)) = first_difference(table.structured, &structured) {
+            panic!(
+                "{}: structured display changed at line {line}:\nexpected: {expected}\n     got: {got}",
+                table.name
+            );
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_corpus_truncated() {
+    for table in CORPUS {
+        let body = table.body();
+        for len in 0..body.len() {
+            parse_and_display(&body[..len]);
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_corpus_fuzz() {
+    let mut rng = Rng(SEED);
+
+    for table in CORPUS {
+        info!(
+            "fuzzing {} with {MUTATIONS_PER_TABLE} mutations, seed {SEED:#x}",
+            table.name
+        );
+        let mut body = Vec::new();
+        for _ in 0..MUTATIONS_PER_TABLE {
+            body.clear();
+            body.extend_from_slice(table.body());
+
+            for _ in 0..1 + rng.below(4) {
+                let pos = rng.below(body.len());
+                match rng.below(5) {
+                    0 => body[pos] = rng.next() as u8,
+                    1 => body[pos] ^= 1 << rng.below(8),
+                    2 => body.insert(pos, rng.next() as u8),
+                    3 if body.len() > 1 => {
+                        body.remove(pos);
+                    }
+                    _ => body.truncate(pos.max(1)),
+                }
+                trace!("{}: mutated at {pos:#x}", table.name);
+            }
+
+            parse_and_display(&body);
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_corpus_execute() {
+    for table in CORPUS {
+        let aml = Aml::parse(table.body()).unwrap();
+
+        // most methods use terms that are not supported yet, these must fail with an error, never panic
+        for (path, num_args) in aml.structured().method_paths() {
+            let args =
+                vec![UnresolvedDataObject::Integer(IntegerData::ConstZero); num_args as usize];
+            let result = aml.execute(&mut ExecutionContext::default(), &path, &args);
+            trace!("{}: {path}: {result:?}", table.name);
+        }
+    }
+}
@@ -1,18 +1,28 @@
 use alloc::{
+    format,
     string::{String, ToString},
     vec::Vec,
 };
+use tracing::debug;
 
 use crate::{
     acpi::aml::{parser::PackageElement, structured::ElementType},
+    sync::spin::mutex::Mutex,
     testing,
 };
 
 use super::{
-    parser::{resource_template::ResourceTemplate, IntegerData, TermArg, UnresolvedDataObject},
+    parser::{
+        resource_template::ResourceTemplate, AmlTerm, IntegerData, MethodObj, RegionSpace, Target,
+        TermArg, UnresolvedDataObject,
+    },
     structured::{StructuredAml, StructuredAmlError},
+    sync::SyncOwner,
 };
 
+/// The maximum depth of nested method calls, so that recursive methods don't overflow the stack
+const MAX_CALL_DEPTH: usize = 32;
+
 #[derive(Debug, Clone)]
 pub struct Package {
     size: IntegerData,
@@ -69,6 +79,46 @@ pub enum AmlExecutionError {
     StructuredAmlError(StructuredAmlError),
     ElementNotExecutable(String),
     UnexpectedTermResultType(TermArg, String),
+    /// The object is not a `Mutex` or `Event` (depending on the operation)
+    NotSyncObject(String),
+    /// Acquiring or releasing a mutex out of `SyncLevel` order
+    MutexOrder(String),
+    MutexNotAcquired(String),
+    /// The term (or target) is not supported by the interpreter yet
+    TermNotSupported(String),
+    /// Reading an `Arg` or `Local` that was not set
+    Uninitialized(String),
+    CallDepthExceeded(String),
+    /// Error from the handler of an operation region
+    RegionAccess(RegionSpace, String),
+}
+
+/// Handles accesses to operation regions that are not memory or IO, i.e. `EmbeddedControl`,
+/// these are provided by the drivers of these devices
+pub trait RegionHandler: Send + Sync {
+    /// Read `width` bytes at `offset` in the region
+    fn read(&self, offset: u64, width: usize) -> Result<u64, AmlExecutionError>;
+    /// Write `width` bytes of `value` at `offset` in the region
+    fn write(&self, offset: u64, width: usize, value: u64) -> Result<(), AmlExecutionError>;
+}
+
+static REGION_HANDLERS: Mutex<Vec<(RegionSpace, &'static dyn RegionHandler)>> =
+    Mutex::new(Vec::new());
+
+/// Set the handler of the `space` operation regions, replacing the old one if any
+pub fn register_region_handler(space: RegionSpace, handler: &'static dyn RegionHandler) {
+    let mut handlers = REGION_HANDLERS.lock();
+    handlers.retain(|(s, _)| *s != space);
+    handlers.push((space, handler));
+}
+
+#[allow(dead_code)]
+fn region_handler(space: &RegionSpace) -> Option<&'static dyn RegionHandler> {
+    REGION_HANDLERS
+        .lock()
+        .iter()
+        .find(|(s, _)| s == space)
+        .map(|(_, handler)| *handler)
 }
 
 impl From<StructuredAmlError> for AmlExecutionError {
@@ -77,58 +127,352 @@ impl From<StructuredAmlError> for AmlExecutionError {
     }
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct ExecutionContext {}
+/// Where execution of a term list continues after a term
+enum Flow {
+    Next,
+    Break,
+    Return(DataObject),
+}
+
+/// The state of a running method, or of the namespace when evaluating a `Name`
+struct Frame {
+    /// Relative names are resolved from this scope
+    scope: String,
+    args: [Option<DataObject>; 7],
+    locals: [Option<DataObject>; 8],
+}
+
+impl Frame {
+    fn new(scope: &str, args: Vec<DataObject>) -> Self {
+        let mut args = args.into_iter();
+        Self {
+            scope: scope.to_string(),
+            args: core::array::from_fn(|_| args.next()),
+            locals: Default::default(),
+        }
+    }
+}
+
+/// The scope containing the object at the absolute `path`
+fn parent_scope(path: &str) -> &str {
+    match path.rsplit_once('.') {
+        Some((parent, _)) => parent,
+        None => "\\",
+    }
+}
+
+fn join_path(scope: &str, name: &str) -> String {
+    if scope.ends_with('\\') {
+        format!("{scope}{name}")
+    } else {
+        format!("{scope}.{name}")
+    }
+}
+
+/// Get the absolute path of `name` used in `scope`, single names are searched for in the scope and
+/// then its parents, other names are relative to the scope (or its parents with `^`)
+fn resolve_path(structured: &StructuredAml, scope: &str, name: &str) -> String {
+    if name.starts_with('\\') {
+        return name.to_string();
+    }
+    let relative_name = name.trim_start_matches('^');
+    if relative_name.len() != name.len() || relative_name.contains('.') {
+        let scope = (relative_name.len()..name.len()).fold(scope, |scope, _| parent_scope(scope));
+        return join_path(scope, relative_name);
+    }
+
+    let mut scope = scope;
+    loop {
+        let path = join_path(scope, name);
+        if scope == "\\" || structured.find_object(&path).is_ok_and(|o| o.is_some()) {
+            return path;
+        }
+        scope = parent_scope(scope);
+    }
+}
+
+/// The name of the term, without its arguments
+fn term_name(term: &AmlTerm) -> String {
+    let debug = format!("{term:?}");
+    debug.split('(').next().unwrap_or_default().to_string()
+}
+
+/// AML booleans are `Ones` for true and `Zero` for false
+fn boolean(value: bool) -> DataObject {
+    DataObject::Integer(if value {
+        IntegerData::ConstOnes
+    } else {
+        IntegerData::ConstZero
+    })
+}
+
+/// An execution of AML, with its own ownership of the AML mutexes.
+///
+/// Methods are interpreted for a subset of the terms: control flow, `Store` to locals and
+/// arguments, logical operators, method calls and the synchronization terms (`Acquire`, `Release`,
+/// `Signal`, `Wait` and `Reset`), the rest fail with [`AmlExecutionError::TermNotSupported`].
+#[derive(Debug, Default)]
+pub struct ExecutionContext {
+    /// Mutexes held by this execution, used by `Acquire`, `Release`, `Signal`, `Wait` and `Reset`
+    sync: SyncOwner,
+    /// Number of methods currently running
+    depth: usize,
+}
+
 impl ExecutionContext {
     pub fn execute(
-        &self,
+        &mut self,
         structured: &StructuredAml,
         label: &str,
-        _args: &[UnresolvedDataObject],
+        args: &[UnresolvedDataObject],
+    ) -> Result<DataObject, AmlExecutionError> {
+        let mut frame = Frame::new(parent_scope(label), Vec::new());
+        let args = args
+            .iter()
+            .map(|arg| self.evaluate_data_object(structured, &mut frame, arg.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.evaluate_path(structured, label, args)
+    }
+
+    /// Evaluate the object at the absolute `path`, methods are called with `args`
+    fn evaluate_path(
+        &mut self,
+        structured: &StructuredAml,
+        path: &str,
+        args: Vec<DataObject>,
     ) -> Result<DataObject, AmlExecutionError> {
-        let element_to_execute = structured
-            .find_object(label)?
-            .ok_or(AmlExecutionError::LableNotFound(label.to_string()))?;
+        let element = structured
+            .find_object(path)?
+            .ok_or(AmlExecutionError::LableNotFound(path.to_string()))?;
 
-        let data = match element_to_execute {
-            ElementType::Method(_) => todo!("Execute method"),
-            ElementType::Name(data) => data,
+        match element {
+            ElementType::Method(method) => self.call_method(structured, path, method, args),
+            ElementType::Name(data) => {
+                let mut frame = Frame::new(parent_scope(path), Vec::new());
+                self.evaluate_data_object(structured, &mut frame, data.clone())
+            }
             ElementType::UnknownElements(_) => {
                 // This label is internal and should never be reached
-                return Err(AmlExecutionError::LableNotFound(label.to_string()));
+                Err(AmlExecutionError::LableNotFound(path.to_string()))
             }
             ElementType::PowerResource(_)
             | ElementType::RegionFields(_, _)
             | ElementType::IndexField(_)
             | ElementType::ScopeOrDevice(_)
-            | ElementType::Processor(_) => {
-                return Err(AmlExecutionError::ElementNotExecutable(label.to_string()))
+            | ElementType::Processor(_)
+            | ElementType::Mutex(_)
+            | ElementType::Event => Err(AmlExecutionError::ElementNotExecutable(path.to_string())),
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        structured: &StructuredAml,
+        path: &str,
+        method: &MethodObj,
+        args: Vec<DataObject>,
+    ) -> Result<DataObject, AmlExecutionError> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(AmlExecutionError::CallDepthExceeded(path.to_string()));
+        }
+        self.depth += 1;
+        let mut frame = Frame::new(parent_scope(path), args);
+        let result = self.execute_term_list(structured, &mut frame, &method.term_list);
+        self.depth -= 1;
+
+        match result? {
+            Flow::Return(value) => Ok(value),
+            // no `Return`
+            Flow::Next | Flow::Break => Ok(DataObject::Integer(IntegerData::ConstZero)),
+        }
+    }
+
+    fn execute_term_list(
+        &mut self,
+        structured: &StructuredAml,
+        frame: &mut Frame,
+        terms: &[AmlTerm],
+    ) -> Result<Flow, AmlExecutionError> {
+        // whether the last `If` ran, for the `Else` after it
+        let mut if_taken = false;
+        for term in terms {
+            let flow = match term {
+                AmlTerm::If(block) => {
+                    if_taken = self.evaluate_integer(structured, frame, &block.predicate)? != 0;
+                    if if_taken {
+                        self.execute_term_list(structured, frame, &block.term_list)?
+                    } else {
+                        Flow::Next
+                    }
+                }
+                AmlTerm::Else(terms) if !if_taken => {
+                    self.execute_term_list(structured, frame, terms)?
+                }
+                AmlTerm::Else(_) | AmlTerm::Noop => Flow::Next,
+                AmlTerm::While(block) => loop {
+                    if self.evaluate_integer(structured, frame, &block.predicate)? == 0 {
+                        break Flow::Next;
+                    }
+                    match self.execute_term_list(structured, frame, &block.term_list)? {
+                        Flow::Next => {}
+                        Flow::Break => break Flow::Next,
+                        Flow::Return(value) => break Flow::Return(value),
+                    }
+                },
+                AmlTerm::Break => Flow::Break,
+                AmlTerm::Return(value) => {
+                    Flow::Return(self.execute_term_arg(structured, frame, value)?)
+                }
+                term => {
+                    self.execute_term(structured, frame, term)?;
+                    Flow::Next
+                }
+            };
+            if !matches!(flow, Flow::Next) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Execute a term that has a result (even if its not used)
+    fn execute_term(
+        &mut self,
+        structured: &StructuredAml,
+        frame: &mut Frame,
+        term: &AmlTerm,
+    ) -> Result<DataObject, AmlExecutionError> {
+        let mut integer = |this: &mut Self, term| this.evaluate_integer(structured, frame, term);
+        let result = match term {
+            AmlTerm::Acquire(target, timeout) => {
+                let path = Self::target_path(structured, frame, target)?;
+                boolean(self.sync.acquire(structured, &path, *timeout)?)
+            }
+            AmlTerm::Release(target) => {
+                let path = Self::target_path(structured, frame, target)?;
+                self.sync.release(&path)?;
+                boolean(false)
+            }
+            AmlTerm::Signal(target) => {
+                let path = Self::target_path(structured, frame, target)?;
+                self.sync.signal(structured, &path)?;
+                boolean(false)
+            }
+            AmlTerm::Wait(target, timeout) => {
+                let timeout = integer(self, timeout)?;
+                let path = Self::target_path(structured, frame, target)?;
+                boolean(self.sync.wait(structured, &path, timeout)?)
+            }
+            AmlTerm::Reset(target) => {
+                let path = Self::target_path(structured, frame, target)?;
+                self.sync.reset(structured, &path)?;
+                boolean(false)
+            }
+            AmlTerm::Store(value, target) => {
+                let value = self.execute_term_arg(structured, frame, value)?;
+                Self::store(frame, target, value.clone())?;
+                value
+            }
+            AmlTerm::LNot(a) => boolean(integer(self, a)? == 0),
+            AmlTerm::LAnd(a, b) => boolean(integer(self, a)? != 0 && integer(self, b)? != 0),
+            AmlTerm::LOr(a, b) => boolean(integer(self, a)? != 0 || integer(self, b)? != 0),
+            AmlTerm::LEqual(a, b) => boolean(integer(self, a)? == integer(self, b)?),
+            AmlTerm::LNotEqual(a, b) => boolean(integer(self, a)? != integer(self, b)?),
+            AmlTerm::LLess(a, b) => boolean(integer(self, a)? < integer(self, b)?),
+            AmlTerm::LLessEqual(a, b) => boolean(integer(self, a)? <= integer(self, b)?),
+            AmlTerm::LGreater(a, b) => boolean(integer(self, a)? > integer(self, b)?),
+            AmlTerm::LGreaterEqual(a, b) => boolean(integer(self, a)? >= integer(self, b)?),
+            AmlTerm::MethodCall(name, args) => {
+                let path = resolve_path(structured, &frame.scope, name);
+                let args = args
+                    .iter()
+                    .map(|arg| self.execute_term_arg(structured, frame, arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.evaluate_path(structured, &path, args)?
             }
+            term => return Err(AmlExecutionError::TermNotSupported(term_name(term))),
         };
+        Ok(result)
+    }
 
-        self.evaluate_data_object(data.clone(), label)
+    /// The absolute path of the `Mutex` or `Event` at `target`
+    fn target_path(
+        structured: &StructuredAml,
+        frame: &Frame,
+        target: &Target,
+    ) -> Result<String, AmlExecutionError> {
+        match target {
+            Target::Name(name) => Ok(resolve_path(structured, &frame.scope, name)),
+            target => Err(AmlExecutionError::TermNotSupported(format!("{target:?}"))),
+        }
+    }
+
+    fn store(
+        frame: &mut Frame,
+        target: &Target,
+        value: DataObject,
+    ) -> Result<(), AmlExecutionError> {
+        match target {
+            Target::None => {}
+            Target::Debug => debug!("AML debug: {value:?}"),
+            Target::Arg(n) => frame.args[*n as usize] = Some(value),
+            Target::Local(n) => frame.locals[*n as usize] = Some(value),
+            target => return Err(AmlExecutionError::TermNotSupported(format!("{target:?}"))),
+        }
+        Ok(())
     }
 
     fn execute_term_arg(
-        &self,
+        &mut self,
+        structured: &StructuredAml,
+        frame: &mut Frame,
         term: &TermArg,
-        _reference_path: &str,
     ) -> Result<DataObject, AmlExecutionError> {
-        todo!("Execute term: {:?}", term)
+        match term {
+            TermArg::Expression(term) => self.execute_term(structured, frame, term),
+            TermArg::DataObject(data) => self.evaluate_data_object(structured, frame, data.clone()),
+            TermArg::Arg(n) => frame.args[*n as usize]
+                .clone()
+                .ok_or(AmlExecutionError::Uninitialized(format!("Arg{n}"))),
+            TermArg::Local(n) => frame.locals[*n as usize]
+                .clone()
+                .ok_or(AmlExecutionError::Uninitialized(format!("Local{n}"))),
+            TermArg::Name(name) => {
+                let path = resolve_path(structured, &frame.scope, name);
+                self.evaluate_path(structured, &path, Vec::new())
+            }
+        }
+    }
+
+    fn evaluate_integer(
+        &mut self,
+        structured: &StructuredAml,
+        frame: &mut Frame,
+        term: &TermArg,
+    ) -> Result<u64, AmlExecutionError> {
+        match self.execute_term_arg(structured, frame, term)? {
+            DataObject::Integer(i) => Ok(i.as_u64()),
+            _ => Err(AmlExecutionError::UnexpectedTermResultType(
+                term.clone(),
+                "Integer".to_string(),
+            )),
+        }
     }
 
     fn convert_package_elements(
-        &self,
+        &mut self,
+        structured: &StructuredAml,
+        frame: &mut Frame,
         elements: Vec<PackageElement<UnresolvedDataObject>>,
-        reference_path: &str,
     ) -> Result<Vec<PackageElement<DataObject>>, AmlExecutionError> {
         elements
             .into_iter()
             .map(|e| {
                 Ok(match e {
-                    PackageElement::DataObject(data) => {
-                        PackageElement::DataObject(self.evaluate_data_object(data, reference_path)?)
-                    }
+                    PackageElement::DataObject(data) => PackageElement::DataObject(
+                        self.evaluate_data_object(structured, frame, data)?,
+                    ),
                     PackageElement::Name(name) => PackageElement::Name(name),
                 })
             })
@@ -136,13 +480,14 @@ impl ExecutionContext {
     }
 
     fn evaluate_data_object(
-        &self,
+        &mut self,
+        structured: &StructuredAml,
+        frame: &mut Frame,
         data: UnresolvedDataObject,
-        reference_path: &str,
     ) -> Result<DataObject, AmlExecutionError> {
         match data {
             UnresolvedDataObject::Buffer(buffer) => {
-                let size_term = self.execute_term_arg(buffer.size.as_ref(), reference_path)?;
+                let size_term = self.execute_term_arg(structured, frame, buffer.size.as_ref())?;
 
                 let size_term = match size_term {
                     DataObject::Integer(i) => i,
@@ -164,10 +509,10 @@ impl ExecutionContext {
             }
             UnresolvedDataObject::Package(size, elements) => Ok(DataObject::Package(Package {
                 size: IntegerData::ByteConst(size),
-                elements: self.convert_package_elements(elements, reference_path)?,
+                elements: self.convert_package_elements(structured, frame, elements)?,
             })),
             UnresolvedDataObject::VarPackage(term, elements) => {
-                let size_term = self.execute_term_arg(term.as_ref(), reference_path)?;
+                let size_term = self.execute_term_arg(structured, frame, term.as_ref())?;
 
                 let size_term = match size_term {
                     DataObject::Integer(i) => i,
@@ -181,7 +526,7 @@ impl ExecutionContext {
 
                 Ok(DataObject::Package(Package {
                     size: size_term,
-                    elements: self.convert_package_elements(elements, reference_path)?,
+                    elements: self.convert_package_elements(structured, frame, elements)?,
                 }))
             }
             UnresolvedDataObject::Integer(i) => Ok(DataObject::Integer(i)),
@@ -281,3 +626,141 @@ fn test_execute_normal_sleep_package() {
         vec![4, 4, 0, 0]
     );
 }
+
+/// Test the synchronization terms in methods
+/// ```
+/// Mutex (MTX1, 1)
+/// Mutex (MTX2, 2)
+/// Event (EVT1)
+/// Method (LOCK) { Return (Acquire (MTX2, 0x0000)) }
+/// Method (UNLK) { Release (MTX2) }
+/// Method (ORDR) { Acquire (MTX1, 0x0000) }
+/// Method (SIGN) { Signal (EVT1) }
+/// Method (RSET) { Reset (EVT1) }
+/// Method (WAIT) { Store (Wait (EVT1, Zero), Local0) Return (Local0) }
+/// Scope (\_SB) {
+///     Method (CHCK) {
+///         If (LEqual (Acquire (MTX1, 0x0000), Zero)) { Release (MTX1) Return (One) }
+///         Else { Return (Zero) }
+///     }
+/// }
+/// ```
+#[macro_rules_attribute::apply(testing::test)]
+fn test_execute_sync_terms() {
+    use super::parser::{AmlCode, PredicateBlock, ScopeObj, ScopeType};
+    use alloc::{boxed::Box, vec};
+
+    fn method(name: &str, term_list: Vec<AmlTerm>) -> AmlTerm {
+        AmlTerm::Method(MethodObj {
+            name: name.to_string(),
+            num_args: 0,
+            is_serialized: false,
+            sync_level: 0,
+            term_list,
+        })
+    }
+    fn target(name: &str) -> Box<Target> {
+        Box::new(Target::Name(name.to_string()))
+    }
+    fn integer(value: IntegerData) -> TermArg {
+        TermArg::DataObject(UnresolvedDataObject::Integer(value))
+    }
+    fn expression(term: AmlTerm) -> TermArg {
+        TermArg::Expression(Box::new(term))
+    }
+    fn execute(ctx: &mut ExecutionContext, structured: &StructuredAml, label: &str) -> u64 {
+        ctx.execute(structured, label, &[])
+            .expect("execute")
+            .as_integer()
+            .expect("integer")
+            .as_u64()
+    }
+
+    let code = AmlCode {
+        term_list: vec![
+            AmlTerm::Mutex("MTX1".to_string(), 1),
+            AmlTerm::Mutex("MTX2".to_string(), 2),
+            AmlTerm::Event("EVT1".to_string()),
+            method(
+                "LOCK",
+                vec![AmlTerm::Return(expression(AmlTerm::Acquire(
+                    target("MTX2"),
+                    0,
+                )))],
+            ),
+            method("UNLK", vec![AmlTerm::Release(target("MTX2"))]),
+            method("ORDR", vec![AmlTerm::Acquire(target("MTX1"), 0)]),
+            method("SIGN", vec![AmlTerm::Signal(target("EVT1"))]),
+            method("RSET", vec![AmlTerm::Reset(target("EVT1"))]),
+            method(
+                "WAIT",
+                vec![
+                    AmlTerm::Store(
+                        expression(AmlTerm::Wait(
+                            target("EVT1"),
+                            integer(IntegerData::ConstZero),
+                        )),
+                        Box::new(Target::Local(0)),
+                    ),
+                    AmlTerm::Return(TermArg::Local(0)),
+                ],
+            ),
+            AmlTerm::Scope(ScopeObj {
+                ty: ScopeType::Scope,
+                name: "\\_SB_".to_string(),
+                term_list: vec![method(
+                    "CHCK",
+                    vec![
+                        AmlTerm::If(PredicateBlock {
+                            predicate: expression(AmlTerm::LEqual(
+                                expression(AmlTerm::Acquire(target("MTX1"), 0)),
+                                integer(IntegerData::ConstZero),
+                            )),
+                            term_list: vec![
+                                AmlTerm::Release(target("MTX1")),
+                                AmlTerm::Return(integer(IntegerData::ConstOne)),
+                            ],
+                        }),
+                        AmlTerm::Else(vec![AmlTerm::Return(integer(IntegerData::ConstZero))]),
+                    ],
+                )],
+            }),
+        ],
+    };
+    let structured = StructuredAml::parse(&code);
+
+    let mut ctx1 = ExecutionContext::default();
+    let mut ctx2 = ExecutionContext::default();
+
+    // `Acquire` returns `Ones` when timed out
+    assert_eq!(execute(&mut ctx1, &structured, "\\LOCK"), 0);
+    assert_eq!(execute(&mut ctx2, &structured, "\\LOCK"), u64::MAX);
+    // lower level while holding a higher one
+    assert!(matches!(
+        ctx1.execute(&structured, "\\ORDR", &[]),
+        Err(AmlExecutionError::MutexOrder(_))
+    ));
+    // `MTX1` is found in the root from `\_SB_`
+    assert_eq!(execute(&mut ctx2, &structured, "\\_SB_.CHCK"), 1);
+    assert!(matches!(
+        ctx1.execute(&structured, "\\_SB_.CHCK", &[]),
+        Err(AmlExecutionError::MutexOrder(_))
+    ));
+    execute(&mut ctx1, &structured, "\\UNLK");
+    assert_eq!(execute(&mut ctx2, &structured, "\\LOCK"), 0);
+    assert!(matches!(
+        ctx1.execute(&structured, "\\UNLK", &[]),
+        Err(AmlExecutionError::MutexNotAcquired(_))
+    ));
+    execute(&mut ctx2, &structured, "\\UNLK");
+
+    // `Wait` returns `Ones` when timed out, and consumes a signal otherwise
+    execute(&mut ctx1, &structured, "\\SIGN");
+    execute(&mut ctx1, &structured, "\\SIGN");
+    assert_eq!(execute(&mut ctx2, &structured, "\\WAIT"), 0);
+    assert_eq!(execute(&mut ctx2, &structured, "\\WAIT"), 0);
+    assert_eq!(execute(&mut ctx2, &structured, "\\WAIT"), u64::MAX);
+    execute(&mut ctx1, &structured, "\\SIGN");
+    execute(&mut ctx1, &structured, "\\RSET");
+    assert_eq!(execute(&mut ctx2, &structured, "\\WAIT"), u64::MAX);
+}
@@ -1,12 +1,17 @@
+#[cfg(test)]
+mod corpus;
 mod display;
 pub mod execution;
 mod parser;
 mod structured;
+mod sync;
 
 use execution::{AmlExecutionError, DataObject, ExecutionContext};
 use parser::UnresolvedDataObject;
 
-pub use parser::{AmlCode, AmlParseError};
+pub use parser::{
+    eisa_id_to_string, resource_template::ResourceMacro, AmlCode, AmlParseError, RegionSpace,
+};
 use structured::StructuredAml;
 
 #[derive(Debug, Clone)]
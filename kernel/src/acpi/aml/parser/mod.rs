@@ -28,6 +28,12 @@ pub enum AmlParseError {
     ResourceTemplateReservedTag,
     ReservedValue,
     InvalidResourceTemplate,
+    InvalidPkgLength,
+    UnknownOpcode(u8),
+    UnknownExtendedOpcode(u8),
+    InvalidTermArg(u8),
+    InvalidNameChar(u8),
+    InvalidName,
 }
 
 pub fn parse_aml(code: &[u8]) -> Result<AmlCode, AmlParseError> {
@@ -274,7 +280,7 @@ impl ScopeObj {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 #[allow(non_camel_case_types)]
 pub enum RegionSpace {
@@ -516,8 +522,8 @@ impl MethodObj {
 
 #[derive(Debug, Clone)]
 pub struct PredicateBlock {
-    predicate: TermArg,
-    term_list: Vec<AmlTerm>,
+    pub(super) predicate: TermArg,
+    pub(super) term_list: Vec<AmlTerm>,
 }
 
 impl PredicateBlock {
@@ -626,9 +632,14 @@ impl<'a> State<'a> {
         }
     }
 
+    /// The last segment of the name, names are always ASCII
+    fn name_segment(name: &str) -> &str {
+        &name[name.len().saturating_sub(4)..]
+    }
+
     fn find_name(&self, name: &str) -> bool {
         trace!("finding name {name:?}, {:?}", self.names);
-        let short_name = &name[name.len() - 4..];
+        let short_name = Self::name_segment(name);
         self.names.contains(name) || self.names.contains(short_name)
     }
 
@@ -636,14 +647,14 @@ impl<'a> State<'a> {
         trace!("finding method {name:?}");
         // all methods are shared here, from all scopes
         // we are assuming that methods with similar names have the same number of arguments
-        let method_name = &name[name.len() - 4..];
+        let method_name = Self::name_segment(name);
         trace!("methods: {:?}", self.methods);
         self.methods.get(method_name).copied()
     }
 
     fn add_method(&mut self, name: &str, arg_count: usize) {
         trace!("adding method {name:?}");
-        let method_name = &name[name.len() - 4..];
+        let method_name = Self::name_segment(name);
         self.methods.insert(String::from(method_name), arg_count);
     }
 
@@ -653,6 +664,11 @@ impl<'a> State<'a> {
     }
 }
 
+/// Convert a compressed `EisaId` (i.e. `_HID` as integer) into its string form, i.e. `PNP0C09`
+pub fn eisa_id_to_string(id: u32) -> String {
+    Parser::parse_eisa_id(id)
+}
+
 pub struct Parser<'a> {
     code: &'a [u8],
     pos: usize,
@@ -689,14 +705,15 @@ impl Parser<'_> {
     }
 
     fn backward(&mut self, n: usize) -> Result<(), AmlParseError> {
-        if self.pos == 0 {
+        if self.pos < n {
             return Err(AmlParseError::CannotMoveBackward);
         }
         self.pos -= n;
         Ok(())
     }
 
-    fn get_pkg_length(&mut self) -> Result<usize, AmlParseError> {
+    /// Returns the encoded value of the `PkgLength` and the number of bytes used to encode it
+    fn get_raw_pkg_length(&mut self) -> Result<(usize, usize), AmlParseError> {
         let lead_byte = self.get_next_byte()?;
         let following_bytes = lead_byte >> 6;
 
@@ -704,8 +721,7 @@ impl Parser<'_> {
 
         let mut length: usize;
         if following_bytes == 0 {
-            // subtract the bytes used for the length
-            return Ok((lead_byte & 0b0011_1111) as usize - 1);
+            return Ok(((lead_byte & 0b0011_1111) as usize, 1));
         } else {
             // bits 4-5 must be zero
             if (lead_byte >> 4) & 0b11 != 0 {
@@ -720,13 +736,23 @@ impl Parser<'_> {
             length |= (byte as usize) << (8 * i + 4);
             trace!("len now: {:x}", length);
         }
+        Ok((length, following_bytes as usize + 1))
+    }
+
+    fn get_pkg_length(&mut self) -> Result<usize, AmlParseError> {
+        let (length, length_bytes) = self.get_raw_pkg_length()?;
         // subtract the bytes used for the length
-        Ok(length - following_bytes as usize - 1)
+        length
+            .checked_sub(length_bytes)
+            .ok_or(AmlParseError::InvalidPkgLength)
     }
 
     fn get_inner_parser(&mut self) -> Result<Parser, AmlParseError> {
         let pkg_length = self.get_pkg_length()?;
         trace!("inner pkg length: {:x}", pkg_length);
+        if pkg_length > self.remaining_bytes() {
+            return Err(AmlParseError::UnexpectedEndOfCode);
+        }
 
         let inner_parser = Parser {
             code: &self.code[self.pos..self.pos + pkg_length],
@@ -757,11 +783,7 @@ impl Parser<'_> {
         let byte = self.get_next_byte()?;
         let term = self.try_parse_term(byte)?;
 
-        if let Some(term) = term {
-            Ok(term)
-        } else {
-            todo!("opcode: {:x}", byte)
-        }
+        term.ok_or(AmlParseError::UnknownOpcode(byte))
     }
 
     fn predict_possible_args(&mut self, expect_data_after: bool, name: &str) -> usize {
@@ -926,7 +948,7 @@ impl Parser<'_> {
                     0x83 => AmlTerm::Processor(ProcessorDeprecated::parse(self)?),
                     0x84 => AmlTerm::PowerResource(PowerResource::parse(self)?),
                     0x86 => AmlTerm::IndexField(IndexFieldDef::parse(self)?),
-                    _ => todo!("extra opcode: {:x}", inner_opcode),
+                    _ => return Err(AmlParseError::UnknownExtendedOpcode(inner_opcode)),
                 }
             }
             0x70 => AmlTerm::Store(self.parse_term_arg()?, self.parse_target()?),
@@ -1011,7 +1033,7 @@ impl Parser<'_> {
             0x86 => AmlTerm::Notify(self.parse_target()?, self.parse_term_arg()?),
             0x87 => AmlTerm::SizeOf(self.parse_target()?),
             0x88 => AmlTerm::Index(
-                self.parse_term_arg()?,
+                self.parse_term_arg_non_method_arg()?,
                 self.parse_term_arg()?,
                 self.parse_target()?,
             ),
@@ -1084,7 +1106,9 @@ impl Parser<'_> {
                 let Some(name) = self.try_parse_name()? else {
                     return Ok(None);
                 };
-                assert!(!name.is_empty());
+                if name.is_empty() {
+                    return Err(AmlParseError::InvalidName);
+                }
                 let n_args = self
                     .state
                     .find_method(&name)
@@ -1107,7 +1131,8 @@ impl Parser<'_> {
     ///
     /// TODO: This should be removed, as in general a method call is a valid term arg, its just
     ///       we break some parts due to us not knowing if a name is a method or not, and prediction predicts wrong and messes up
-    ///       This happens for `+` and `>>` and `<<`, cases I have seen and know of bugs in the parsing
+    ///       This happens for `+` and `>>` and `<<`, cases I have seen and know of bugs in the parsing,
+    ///       and for the source of `Index`, i.e. `Index (CPON, Arg0)` where `CPON` is declared later (QEMU)
     fn parse_term_arg_non_method_arg(&mut self) -> Result<TermArg, AmlParseError> {
         // second arg doesn't matter, not used
         self.parse_term_arg_general(false, true)
@@ -1279,7 +1304,9 @@ impl Parser<'_> {
         } else {
             self.backward(1)?;
             if let Some(name) = self.try_parse_name()? {
-                assert!(!name.is_empty());
+                if name.is_empty() {
+                    return Err(AmlParseError::InvalidName);
+                }
                 let option_nargs = self.state.find_method(&name).or_else(|| {
                     if self.state.find_name(&name) {
                         None
@@ -1316,14 +1343,9 @@ impl Parser<'_> {
                 // didn't work for `name`, we need to go forward to be back to where we were before
                 self.forward(1)?;
 
-                if let Some(term) = self
-                    .try_parse_term(lead_byte)?
+                self.try_parse_term(lead_byte)?
                     .map(|term| TermArg::Expression(Box::new(term)))
-                {
-                    Ok(term)
-                } else {
-                    todo!("term arg lead byte: {:x}", lead_byte)
-                }
+                    .ok_or(AmlParseError::InvalidTermArg(lead_byte))
             }
         }
     }
@@ -1335,12 +1357,12 @@ impl Parser<'_> {
             let byte = parser.get_next_byte()?;
             let mut str = String::new();
 
-            if byte == 0 {
-                return Ok(str);
+            match byte {
+                0 => return Ok(str),
+                b'A'..=b'Z' | b'_' => str.push(byte as char),
+                _ => return Err(AmlParseError::InvalidNameChar(byte)),
             }
 
-            str.push(byte as char);
-
             // add 3 more
             for _ in 0..3 {
                 let byte = parser.get_next_byte()?;
@@ -1348,7 +1370,7 @@ impl Parser<'_> {
                     b'A'..=b'Z' | b'_' | b'0'..=b'9' => {
                         str.push(byte as char);
                     }
-                    _ => panic!("invalid name path char: {:x} so far {str:?}", byte),
+                    _ => return Err(AmlParseError::InvalidNameChar(byte)),
                 }
             }
 
@@ -1407,13 +1429,8 @@ impl Parser<'_> {
 
     fn parse_name(&mut self) -> Result<String, AmlParseError> {
         let peek = self.peek_next_byte()?;
-        let name = self.try_parse_name()?;
-
-        if let Some(name) = name {
-            Ok(name)
-        } else {
-            todo!("char not valid {:X}", peek)
-        }
+        self.try_parse_name()?
+            .ok_or(AmlParseError::InvalidNameChar(peek))
     }
 
     fn try_parse_local(&mut self, lead: u8) -> Result<Option<u8>, AmlParseError> {
@@ -1447,13 +1464,14 @@ impl Parser<'_> {
             0x5b => {
                 self.forward(1)?;
                 let next_byte = self.get_next_byte()?;
-                assert_eq!(next_byte, 0x31);
-                Ok(Target::Debug)
-            }
-            0x71 => {
-                // typeref opcode
-                panic!("typeref opcode")
+                if next_byte == 0x31 {
+                    Ok(Target::Debug)
+                } else {
+                    Err(AmlParseError::InvalidTarget(next_byte))
+                }
             }
+            // typeref opcode, not supported
+            0x71 => Err(AmlParseError::InvalidTarget(lead_byte)),
             _ => {
                 if let Some(local) = self.try_parse_local(lead_byte)? {
                     self.forward(1)?;
@@ -1512,10 +1530,10 @@ impl Parser<'_> {
             let field = match lead {
                 0 => {
                     self.forward(1)?;
-                    let pkg_length = self.get_pkg_length()?;
-                    trace!("reserved field element pkg length: {:x}", pkg_length);
-                    // add 1 since we are not using it as normal pkg length
-                    fields_pos_bits += pkg_length + 1;
+                    // not a normal pkg length, its the size in bits
+                    let (size_bits, _) = self.get_raw_pkg_length()?;
+                    trace!("reserved field element size: {:x}", size_bits);
+                    fields_pos_bits += size_bits;
                     if fields_pos_bits % 8 != 0 {
                         return Err(AmlParseError::UnalignedFieldElementOffset);
                     }
@@ -1578,14 +1596,16 @@ impl Parser<'_> {
                 _ => {
                     let len_now = self.pos;
                     let name = self.parse_name()?;
+                    // must be a name segment
+                    if self.pos - len_now != 4 {
+                        return Err(AmlParseError::InvalidName);
+                    }
                     self.state.add_name(name.clone());
-                    assert_eq!(self.pos - len_now, 4); // must be a name segment
                     trace!("field element name: {}", name);
-                    let pkg_length = self.get_pkg_length()?;
-                    trace!("field element pkg length: {:x}", pkg_length);
-                    let size_bits = pkg_length + 1;
+                    // not a normal pkg length, its the size in bits
+                    let (size_bits, _) = self.get_raw_pkg_length()?;
+                    trace!("field element size: {:x}", size_bits);
                     fields_pos_bits += size_bits;
-                    // add 1 since we are not using it as normal pkg length
                     FieldElement::Named(name, size_bits)
                 }
             };
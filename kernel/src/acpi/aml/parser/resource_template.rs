@@ -73,8 +73,9 @@ impl Parser<'_> {
         if self.pos >= self.buffer.len() {
             return Err(AmlParseError::UnexpectedEndOfCode);
         }
+        let data = &self.buffer[self.pos..];
         self.pos = self.buffer.len();
-        Ok(&self.buffer[self.pos..])
+        Ok(data)
     }
 
     pub fn is_done(&self) -> bool {
@@ -290,7 +291,10 @@ impl<W: AddressWidth> AddressSpace<W> {
             Self::parse_start_flags(parser)?;
 
         let revision = parser.get_next_byte()?;
-        assert_eq!(revision, 1, "only support revision 1");
+        // only support revision 1
+        if revision != 1 {
+            return Err(AmlParseError::InvalidResourceTemplate);
+        }
         let reserved = parser.get_next_byte()?;
         if reserved != 0 {
             return Err(AmlParseError::ReservedValue);
@@ -619,7 +623,9 @@ impl ResourceMacro {
                 return Err(AmlParseError::ResourceTemplateReservedTag);
             }
             0x01 => {
-                assert_eq!(data_len, 9);
+                if data_len != 9 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 let flags = parser.get_next_byte()?;
 
                 let min_addr = parser.get_next_u16()?;
@@ -636,7 +642,9 @@ impl ResourceMacro {
                 })
             }
             0x02 => {
-                assert_eq!(data_len, 12);
+                if data_len != 12 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 let mut address_space = parser.get_next_byte()?.into();
 
                 if let RegionSpace::Other(other) = address_space {
@@ -667,7 +675,9 @@ impl ResourceMacro {
                 data: parser.get_remaining_data()?.to_vec(),
             }),
             0x05 => {
-                assert_eq!(data_len, 17);
+                if data_len != 17 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 let flags = parser.get_next_byte()?;
 
                 let min_addr = parser.get_next_u32()?;
@@ -684,7 +694,9 @@ impl ResourceMacro {
                 })
             }
             0x06 => {
-                assert_eq!(data_len, 9);
+                if data_len != 9 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 let flags = parser.get_next_byte()?;
 
                 let base_addr = parser.get_next_u32()?;
@@ -697,19 +709,25 @@ impl ResourceMacro {
                 })
             }
             0x07 => {
-                assert!(data_len >= 23);
+                if data_len < 23 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 Some(ResourceMacro::AddressSpaceDWord(
                     AddressSpace::<u32>::parse(&mut parser)?,
                 ))
             }
             0x08 => {
-                assert!(data_len >= 13);
+                if data_len < 13 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 Some(ResourceMacro::AddressSpaceWord(AddressSpace::<u16>::parse(
                     &mut parser,
                 )?))
             }
             0x09 => {
-                assert!(data_len >= 6);
+                if data_len < 6 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
 
                 let flags = parser.get_next_byte()?;
                 let is_consumed = flags & 1 != 0;
@@ -738,13 +756,17 @@ impl ResourceMacro {
                 })
             }
             0x0A => {
-                assert!(data_len >= 43);
+                if data_len < 43 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 Some(ResourceMacro::AddressSpaceQWord(
                     AddressSpace::<u64>::parse(&mut parser)?,
                 ))
             }
             0x0B => {
-                assert!(data_len >= 53);
+                if data_len < 53 {
+                    return Err(AmlParseError::InvalidResourceTemplate);
+                }
                 Some(ResourceMacro::AddressSpaceExtended(
                     AddressSpace::<u64>::parse_extended(&mut parser)?,
                 ))
@@ -778,6 +800,10 @@ pub struct ResourceTemplate {
 }
 
 impl ResourceTemplate {
+    pub fn items(&self) -> &[ResourceMacro] {
+        &self.items
+    }
+
     pub fn try_parse_buffer(buf: &Buffer) -> Result<Option<Self>, AmlParseError> {
         let data = buf.data.as_slice();
         // is this a resource template anyway?
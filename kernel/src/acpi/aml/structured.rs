@@ -52,6 +52,21 @@ impl StructuredAml {
         Self { root }
     }
 
+    /// Returns the absolute paths of all the `Device`s, parents come before their children
+    pub fn device_paths(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        self.root.device_paths("\\", &mut result);
+        result
+    }
+
+    /// Returns the absolute paths of all the `Method`s with their number of arguments
+    #[cfg(test)]
+    pub fn method_paths(&self) -> Vec<(String, u8)> {
+        let mut result = Vec::new();
+        self.root.method_paths("\\", &mut result);
+        result
+    }
+
     pub fn find_object(&self, label: &str) -> Result<Option<&ElementType>, StructuredAmlError> {
         if let Some(rest) = label.strip_prefix('\\') {
             if rest.is_empty() {
@@ -74,6 +89,9 @@ pub enum ElementType {
     RegionFields(Option<RegionObj>, Vec<FieldDef>),
     IndexField(IndexFieldDef),
     Name(UnresolvedDataObject),
+    /// `Mutex` with its `SyncLevel`
+    Mutex(u8),
+    Event,
     UnknownElements(Vec<AmlTerm>),
 }
 
@@ -220,6 +238,18 @@ impl Scope {
                         ElementType::Method(method.clone()),
                     );
                 }
+                AmlTerm::Mutex(name, sync_level) => {
+                    handle_add(
+                        current_path,
+                        &mut this,
+                        root,
+                        name,
+                        ElementType::Mutex(*sync_level),
+                    );
+                }
+                AmlTerm::Event(name) => {
+                    handle_add(current_path, &mut this, root, name, ElementType::Event);
+                }
                 AmlTerm::NameObj(name, obj) => {
                     handle_add(
                         current_path,
@@ -268,7 +298,8 @@ impl Scope {
             Entry::Occupied(mut entry) => match entry.get_mut() {
                 ElementType::ScopeOrDevice(scope) => {
                     let ElementType::ScopeOrDevice(mut element) = element else {
-                        panic!("New element: {name:?} is not a scope or device");
+                        warn!("New element: {name:?} is not a scope or device, ignoring");
+                        return;
                     };
 
                     // device always wins if there is any, its a more special version of `Scope`
@@ -283,23 +314,25 @@ impl Scope {
                 }
                 ElementType::RegionFields(region, fields) => {
                     let ElementType::RegionFields(new_region, new_fields) = element else {
-                        panic!("New element: {name:?} is not a region");
+                        warn!("New element: {name:?} is not a region, ignoring");
+                        return;
                     };
 
-                    assert!(
-                        !(region.is_some() && new_region.is_some()),
-                        "Both regions are available, conflict, {region:?} && {new_region:?}"
-                    );
+                    if region.is_some() && new_region.is_some() {
+                        warn!("Both regions are available, conflict, {region:?} && {new_region:?}, ignoring");
+                        return;
+                    }
                     *region = region.clone().or(new_region);
                     fields.extend(new_fields);
                 }
                 ElementType::UnknownElements(elements) => {
                     let ElementType::UnknownElements(new_elements) = element else {
-                        panic!("New element: {name:?} is not an unknown element");
+                        warn!("New element: {name:?} is not an unknown element, ignoring");
+                        return;
                     };
                     elements.extend(new_elements);
                 }
-                _ => panic!("Child: {name:?} is not a scope or device"),
+                _ => warn!("Child: {name:?} is already defined, ignoring"),
             },
         }
     }
@@ -343,8 +376,8 @@ impl Scope {
                 if let ElementType::ScopeOrDevice(scope) = child {
                     scope.add_child(rest, element);
                 } else {
-                    panic!(
-                        "Child: {first_child:?} of  {name:?} is not a scope or device {:?}",
+                    warn!(
+                        "Child: {first_child:?} of  {name:?} is not a scope or device {:?}, ignoring",
                         child
                     );
                 }
@@ -361,6 +394,39 @@ impl Scope {
         }
     }
 
+    fn device_paths(&self, path: &str, result: &mut Vec<String>) {
+        for (name, element) in &self.children {
+            let ElementType::ScopeOrDevice(scope) = element else {
+                continue;
+            };
+            let child_path = if path.ends_with('\\') {
+                format!("{path}{name}")
+            } else {
+                format!("{path}.{name}")
+            };
+            if matches!(scope.ty, ScopeType::Device) {
+                result.push(child_path.clone());
+            }
+            scope.device_paths(&child_path, result);
+        }
+    }
+
+    #[cfg(test)]
+    fn method_paths(&self, path: &str, result: &mut Vec<(String, u8)>) {
+        for (name, element) in &self.children {
+            let child_path = if path.ends_with('\\') {
+                format!("{path}{name}")
+            } else {
+                format!("{path}.{name}")
+            };
+            match element {
+                ElementType::Method(method) => result.push((child_path, method.num_args)),
+                ElementType::ScopeOrDevice(scope) => scope.method_paths(&child_path, result),
+                _ => {}
+            }
+        }
+    }
+
     fn find_object(&self, name: &str) -> Result<Option<&ElementType>, StructuredAmlError> {
         let split_result = name.split_once('.');
 
@@ -442,6 +508,10 @@ impl fmt::Display for Scope {
                     .paren_arg(|f| f.write_str(name))
                     .paren_arg(|f| data_obj.fmt(f))
                     .finish(),
+                ElementType::Mutex(sync_level) => {
+                    write!(f, "Mutex({name}, 0x{sync_level:02X})")
+                }
+                ElementType::Event => write!(f, "Event({name})"),
                 ElementType::UnknownElements(elements) => {
                     let mut d = AmlDisplayer::start(f, "UnknownElements");
 
@@ -0,0 +1,305 @@
+//! Synchronization objects of AML, `Mutex` and `Event`
+//!
+//! These are global (shared between all the tables and executions), and are identified by their absolute path.
+//! They are created on first use from the `Mutex`/`Event` declarations in the namespace.
+//!
+//! Each [`ExecutionContext`](super::execution::ExecutionContext) is a separate owner, mutexes can be
+//! acquired recursively by the same owner, and follow the ordering rules of the spec using `SyncLevel`:
+//! - a mutex can't be acquired if its level is lower than the highest level currently held.
+//! - a mutex can't be released while a mutex of higher level is held.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use tracing::warn;
+
+use crate::{
+    devices::clock::{self, ClockTime, NANOS_PER_SEC},
+    sync::spin::mutex::Mutex,
+    testing,
+};
+
+use super::{
+    execution::AmlExecutionError,
+    structured::{ElementType, StructuredAml},
+};
+
+/// Predefined mutex for the firmware global lock
+// TODO: also take the global lock in the `FACS` table, which is shared with the firmware
+const GLOBAL_LOCK_PATH: &str = "\\_GL_";
+/// Timeout value for waiting forever
+pub const TIMEOUT_FOREVER: u64 = 0xFFFF;
+
+static OBJECTS: Mutex<BTreeMap<String, SyncObject>> = Mutex::new(BTreeMap::new());
+static NEXT_OWNER_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+struct AmlMutex {
+    sync_level: u8,
+    owner: Option<u64>,
+    /// Number of times the owner acquired it
+    depth: usize,
+}
+
+#[derive(Debug, Default)]
+struct AmlEvent {
+    /// Number of pending signals
+    count: u64,
+}
+
+#[derive(Debug)]
+enum SyncObject {
+    Mutex(AmlMutex),
+    Event(AmlEvent),
+}
+
+fn lookup<'a>(
+    objects: &'a mut BTreeMap<String, SyncObject>,
+    structured: &StructuredAml,
+    path: &str,
+) -> Result<&'a mut SyncObject, AmlExecutionError> {
+    if !objects.contains_key(path) {
+        let object = match structured.find_object(path)? {
+            Some(ElementType::Mutex(sync_level)) => SyncObject::Mutex(AmlMutex {
+                sync_level: *sync_level,
+                owner: None,
+                depth: 0,
+            }),
+            Some(ElementType::Event) => SyncObject::Event(AmlEvent::default()),
+            Some(_) => return Err(AmlExecutionError::NotSyncObject(path.to_string())),
+            None if path == GLOBAL_LOCK_PATH => SyncObject::Mutex(AmlMutex {
+                sync_level: 0,
+                owner: None,
+                depth: 0,
+            }),
+            None => return Err(AmlExecutionError::LableNotFound(path.to_string())),
+        };
+        objects.insert(path.to_string(), object);
+    }
+    Ok(objects.get_mut(path).unwrap())
+}
+
+/// Run `f` until it returns `true` or `timeout_ms` passes, returns `true` if timed out
+fn wait_until(
+    timeout_ms: u64,
+    mut f: impl FnMut() -> Result<bool, AmlExecutionError>,
+) -> Result<bool, AmlExecutionError> {
+    if f()? {
+        return Ok(false);
+    }
+    if timeout_ms == 0 {
+        return Ok(true);
+    }
+    // before the clocks are available, we are the only one running AML, so no one will release it
+    let Some(clocks) = clock::try_clocks() else {
+        return Ok(true);
+    };
+    let deadline = (timeout_ms < TIMEOUT_FOREVER).then(|| {
+        clocks.time_since_startup()
+            + ClockTime {
+                seconds: timeout_ms / 1000,
+                nanoseconds: (timeout_ms % 1000) * (NANOS_PER_SEC / 1000),
+            }
+    });
+    loop {
+        if f()? {
+            return Ok(false);
+        }
+        if deadline.is_some_and(|deadline| clocks.time_since_startup() >= deadline) {
+            return Ok(true);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// The mutexes held by an execution
+#[derive(Debug)]
+pub struct SyncOwner {
+    id: u64,
+    /// `(path, sync_level)` in the order of acquiring
+    held: Vec<(String, u8)>,
+}
+
+impl Default for SyncOwner {
+    fn default() -> Self {
+        Self {
+            id: NEXT_OWNER_ID.fetch_add(1, Ordering::Relaxed),
+            held: Vec::new(),
+        }
+    }
+}
+
+impl SyncOwner {
+    fn current_sync_level(&self) -> u8 {
+        self.held.iter().map(|(_, level)| *level).max().unwrap_or(0)
+    }
+
+    /// `Acquire`, returns `true` if timed out
+    pub fn acquire(
+        &mut self,
+        structured: &StructuredAml,
+        path: &str,
+        timeout_ms: u16,
+    ) -> Result<bool, AmlExecutionError> {
+        let current_level = self.current_sync_level();
+        let id = self.id;
+        let mut sync_level = 0;
+        let timed_out = wait_until(timeout_ms as u64, || {
+            let mut objects = OBJECTS.lock();
+            let SyncObject::Mutex(mutex) = lookup(&mut objects, structured, path)? else {
+                return Err(AmlExecutionError::NotSyncObject(path.to_string()));
+            };
+            sync_level = mutex.sync_level;
+            match mutex.owner {
+                Some(owner) if owner == id => {
+                    mutex.depth += 1;
+                    Ok(true)
+                }
+                _ if mutex.sync_level < current_level => {
+                    Err(AmlExecutionError::MutexOrder(path.to_string()))
+                }
+                Some(_) => Ok(false),
+                None => {
+                    mutex.owner = Some(id);
+                    mutex.depth = 1;
+                    Ok(true)
+                }
+            }
+        })?;
+
+        if !timed_out {
+            self.held.push((path.to_string(), sync_level));
+        }
+        Ok(timed_out)
+    }
+
+    /// `Release`
+    pub fn release(&mut self, path: &str) -> Result<(), AmlExecutionError> {
+        let Some(index) = self.held.iter().rposition(|(p, _)| p == path) else {
+            return Err(AmlExecutionError::MutexNotAcquired(path.to_string()));
+        };
+        if self.held[index].1 < self.current_sync_level() {
+            return Err(AmlExecutionError::MutexOrder(path.to_string()));
+        }
+        let (path, _) = self.held.remove(index);
+
+        let mut objects = OBJECTS.lock();
+        let Some(SyncObject::Mutex(mutex)) = objects.get_mut(&path) else {
+            unreachable!("held mutex {path:?} is not registered");
+        };
+        assert_eq!(mutex.owner, Some(self.id));
+        mutex.depth -= 1;
+        if mutex.depth == 0 {
+            mutex.owner = None;
+        }
+        Ok(())
+    }
+
+    /// `Signal`
+    pub fn signal(&self, structured: &StructuredAml, path: &str) -> Result<(), AmlExecutionError> {
+        let mut objects = OBJECTS.lock();
+        let SyncObject::Event(event) = lookup(&mut objects, structured, path)? else {
+            return Err(AmlExecutionError::NotSyncObject(path.to_string()));
+        };
+        event.count += 1;
+        Ok(())
+    }
+
+    /// `Wait`, returns `true` if timed out
+    pub fn wait(
+        &self,
+        structured: &StructuredAml,
+        path: &str,
+        timeout_ms: u64,
+    ) -> Result<bool, AmlExecutionError> {
+        wait_until(timeout_ms, || {
+            let mut objects = OBJECTS.lock();
+            let SyncObject::Event(event) = lookup(&mut objects, structured, path)? else {
+                return Err(AmlExecutionError::NotSyncObject(path.to_string()));
+            };
+            if event.count > 0 {
+                event.count -= 1;
+                Ok(true)
+            } else {
+                Ok(false)
+            }
+        })
+    }
+
+    /// `Reset`
+    pub fn reset(&self, structured: &StructuredAml, path: &str) -> Result<(), AmlExecutionError> {
+        let mut objects = OBJECTS.lock();
+        let SyncObject::Event(event) = lookup(&mut objects, structured, path)? else {
+            return Err(AmlExecutionError::NotSyncObject(path.to_string()));
+        };
+        event.count = 0;
+        Ok(())
+    }
+}
+
+impl Drop for SyncOwner {
+    fn drop(&mut self) {
+        // methods should release everything they acquire, but don't keep them locked forever if they don't
+        while let Some((path, _)) = self.held.last().cloned() {
+            warn!("AML: mutex {path:?} was not released, releasing it");
+            self.release(&path).expect("held mutex");
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_mutex_and_event() {
+    use super::parser::{AmlCode, AmlTerm};
+    use alloc::vec;
+
+    let code = AmlCode {
+        term_list: vec![
+            AmlTerm::Mutex("TMX1".to_string(), 1),
+            AmlTerm::Mutex("TMX2".to_string(), 2),
+            AmlTerm::Event("TEV1".to_string()),
+        ],
+    };
+    let structured = StructuredAml::parse(&code);
+
+    let mut owner1 = SyncOwner::default();
+    let mut owner2 = SyncOwner::default();
+
+    assert!(!owner1.acquire(&structured, "\\TMX2", 0).unwrap());
+    // lower level while holding a higher one
+    assert!(matches!(
+        owner1.acquire(&structured, "\\TMX1", 0),
+        Err(AmlExecutionError::MutexOrder(_))
+    ));
+    // recursive
+    assert!(!owner1.acquire(&structured, "\\TMX2", 0).unwrap());
+    // held by someone else, times out
+    assert!(owner2.acquire(&structured, "\\TMX2", 0).unwrap());
+    owner1.release("\\TMX2").unwrap();
+    assert!(owner2.acquire(&structured, "\\TMX2", 0).unwrap());
+    owner1.release("\\TMX2").unwrap();
+    assert!(matches!(
+        owner1.release("\\TMX2"),
+        Err(AmlExecutionError::MutexNotAcquired(_))
+    ));
+    assert!(!owner2.acquire(&structured, "\\TMX2", 0).unwrap());
+    owner2.release("\\TMX2").unwrap();
+
+    // events count the signals
+    owner1.signal(&structured, "\\TEV1").unwrap();
+    owner1.signal(&structured, "\\TEV1").unwrap();
+    assert!(!owner2.wait(&structured, "\\TEV1", 0).unwrap());
+    assert!(!owner2.wait(&structured, "\\TEV1", 0).unwrap());
+    assert!(owner2.wait(&structured, "\\TEV1", 0).unwrap());
+    owner1.signal(&structured, "\\TEV1").unwrap();
+    owner1.reset(&structured, "\\TEV1").unwrap();
+    assert!(owner2.wait(&structured, "\\TEV1", 0).unwrap());
+
+    assert!(matches!(
+        owner1.signal(&structured, "\\TMX1"),
+        Err(AmlExecutionError::NotSyncObject(_))
+    ));
+}
@@ -0,0 +1,314 @@
+//! Devices described in the `AML` namespace
+//!
+//! We walk the namespace of all the `DSDT`/`SSDT` tables, and collect every `Device` with its identification
+//! (`_HID`, `_CID`), status (`_STA`) and resources (`_CRS`), so that drivers can find their device
+//! (i.e. `PNP0303` for the PS/2 keyboard) and use the IO ports and interrupts assigned to it.
+//!
+//! Only `Name` objects can be evaluated for now (we don't execute methods), if `_STA` is a method,
+//! we assume the device is present, and if `_CRS` is a method, the device will have no resources.
+
+use core::fmt;
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use tracing::{info, warn};
+
+use crate::sync::once::OnceLock;
+
+use super::{
+    aml::{
+        eisa_id_to_string,
+        execution::{AmlExecutionError, DataObject, ExecutionContext},
+        Aml, ResourceMacro,
+    },
+    tables,
+};
+
+/// Default `_STA` value, present, enabled, shown and functioning
+const DEFAULT_STATUS: u64 = 0x0F;
+const STATUS_PRESENT: u64 = 1 << 0;
+
+static DEVICE_TREE: OnceLock<Vec<AcpiDevice>> = OnceLock::new();
+
+/// A resource assigned to a device in its `_CRS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Resource {
+    Io {
+        base: u16,
+        len: u16,
+    },
+    Memory {
+        base: u64,
+        len: u64,
+    },
+    Irq {
+        irq: u32,
+        edge_triggered: bool,
+        active_low: bool,
+        shared: bool,
+    },
+    Dma {
+        channel: u16,
+    },
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Io { base, len } => write!(f, "Io({base:#X}, {len:#X})"),
+            Resource::Memory { base, len } => write!(f, "Memory({base:#X}, {len:#X})"),
+            Resource::Irq { irq, .. } => write!(f, "Irq({irq})"),
+            Resource::Dma { channel } => write!(f, "Dma({channel})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AcpiDevice {
+    /// Absolute path in the namespace, i.e. `\_SB_.PCI0.ISA_.KBD_`
+    pub path: String,
+    /// Index of the parent device in [`devices`], if any
+    pub parent: Option<usize>,
+    pub hid: Option<String>,
+    pub cids: Vec<String>,
+    /// `_STA`
+    pub status: u64,
+    pub resources: Vec<Resource>,
+}
+
+#[allow(dead_code)]
+impl AcpiDevice {
+    fn new(path: String) -> Self {
+        Self {
+            path,
+            parent: None,
+            hid: None,
+            cids: Vec::new(),
+            status: DEFAULT_STATUS,
+            resources: Vec::new(),
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.status & STATUS_PRESENT != 0
+    }
+
+    /// Is `id` the `_HID` or one of the `_CID`s of this device
+    pub fn is_compatible(&self, id: &str) -> bool {
+        self.hid.as_deref() == Some(id) || self.cids.iter().any(|cid| cid == id)
+    }
+
+    pub fn io_ports(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.resources.iter().filter_map(|r| match r {
+            Resource::Io { base, len } => Some((*base, *len)),
+            _ => None,
+        })
+    }
+
+    pub fn irqs(&self) -> impl Iterator<Item = u32> + '_ {
+        self.resources.iter().filter_map(|r| match r {
+            Resource::Irq { irq, .. } => Some(*irq),
+            _ => None,
+        })
+    }
+
+    /// Fill the missing properties from `aml`, a device can be extended in multiple tables
+    fn fill_from(&mut self, aml: &Aml) {
+        if self.hid.is_none() {
+            self.hid = evaluate(aml, &self.path, "_HID").and_then(|obj| id_from_object(&obj));
+        }
+        if self.cids.is_empty() {
+            self.cids = match evaluate(aml, &self.path, "_CID") {
+                Some(DataObject::Package(package)) => package
+                    .iter()
+                    .filter_map(|element| element.as_data().and_then(id_from_object))
+                    .collect(),
+                Some(obj) => id_from_object(&obj).into_iter().collect(),
+                None => Vec::new(),
+            };
+        }
+        if let Some(obj) = evaluate(aml, &self.path, "_STA") {
+            match obj {
+                DataObject::Integer(status) => self.status = status.as_u64(),
+                _ => warn!("{}._STA is not an integer: {obj:?}", self.path),
+            }
+        }
+        if self.resources.is_empty() {
+            if let Some(obj) = evaluate(aml, &self.path, "_CRS") {
+                match obj {
+                    DataObject::ResourceTemplate(template) => {
+                        self.resources = template.items().iter().flat_map(resources_from).collect();
+                    }
+                    _ => warn!("{}._CRS is not a resource template: {obj:?}", self.path),
+                }
+            }
+        }
+    }
+}
+
+fn evaluate(aml: &Aml, path: &str, name: &str) -> Option<DataObject> {
+    let label = format!("{path}.{name}");
+    match aml.execute(&mut ExecutionContext::default(), &label, &[]) {
+        Ok(obj) => Some(obj),
+        // not all devices have all the properties, and most terms are not supported yet
+        Err(AmlExecutionError::LableNotFound(_)) | Err(AmlExecutionError::TermNotSupported(_)) => {
+            None
+        }
+        Err(e) => {
+            warn!("Could not evaluate {label}: {e:?}");
+            None
+        }
+    }
+}
+
+/// `_HID` and `_CID` are either `EisaId` (or its integer form) or a string
+fn id_from_object(obj: &DataObject) -> Option<String> {
+    match obj {
+        DataObject::EisaId(id) | DataObject::String(id) => Some(id.clone()),
+        DataObject::Integer(id) => Some(eisa_id_to_string(id.as_u64() as u32)),
+        _ => None,
+    }
+}
+
+fn resources_from(item: &ResourceMacro) -> Vec<Resource> {
+    fn irqs_from_mask(
+        mask: u16,
+        edge_triggered: bool,
+        active_low: bool,
+        shared: bool,
+    ) -> Vec<Resource> {
+        (0..16)
+            .filter(|i| mask & (1 << i) != 0)
+            .map(|irq| Resource::Irq {
+                irq,
+                edge_triggered,
+                active_low,
+                shared,
+            })
+            .collect()
+    }
+
+    match item {
+        ResourceMacro::Io { min_addr, len, .. } => vec![Resource::Io {
+            base: *min_addr,
+            len: *len as u16,
+        }],
+        ResourceMacro::FixedIo { base, len } => vec![Resource::Io {
+            base: *base,
+            len: *len as u16,
+        }],
+        ResourceMacro::Irq {
+            irqs_mask,
+            edge_triggered,
+            active_low,
+            is_shared,
+            ..
+        } => irqs_from_mask(*irqs_mask, *edge_triggered, *active_low, *is_shared),
+        ResourceMacro::Interrupt {
+            interrupts,
+            edge_triggered,
+            active_low,
+            is_shared,
+            ..
+        } => interrupts
+            .iter()
+            .map(|&irq| Resource::Irq {
+                irq,
+                edge_triggered: *edge_triggered,
+                active_low: *active_low,
+                shared: *is_shared,
+            })
+            .collect(),
+        ResourceMacro::Dma { channels_mask, .. } => (0..8)
+            .filter(|i| channels_mask & (1 << i) != 0)
+            .map(|channel| Resource::Dma { channel })
+            .collect(),
+        ResourceMacro::FixedDma { channel, .. } => vec![Resource::Dma { channel: *channel }],
+        // 24 bit memory is in 256 bytes units
+        ResourceMacro::Memory24 { min_addr, len, .. } => vec![Resource::Memory {
+            base: (*min_addr as u64) << 8,
+            len: (*len as u64) << 8,
+        }],
+        ResourceMacro::Memory32Fixed { base_addr, len, .. } => vec![Resource::Memory {
+            base: *base_addr as u64,
+            len: *len as u64,
+        }],
+        ResourceMacro::Memory32 { min_addr, len, .. } => vec![Resource::Memory {
+            base: *min_addr as u64,
+            len: *len as u64,
+        }],
+        // TODO: support address spaces (used by PCI root bridges), and the rest
+        _ => Vec::new(),
+    }
+}
+
+/// Walk the namespace and build the device tree, must be called after the ACPI tables are loaded
+pub fn init() {
+    let mut devices = BTreeMap::new();
+    for table in tables::get_acpi_tables().rsdt.iter_tables::<tables::Xsdt>() {
+        for path in table.aml.structured().device_paths() {
+            devices
+                .entry(path.clone())
+                .or_insert_with(|| AcpiDevice::new(path))
+                .fill_from(&table.aml);
+        }
+    }
+
+    // sorted by path, so parents come before their children
+    let mut devices = devices.into_values().collect::<Vec<_>>();
+    for i in 0..devices.len() {
+        devices[i].parent = (0..i).rev().find(|&parent| {
+            devices[i]
+                .path
+                .strip_prefix(devices[parent].path.as_str())
+                .is_some_and(|rest| rest.starts_with('.'))
+        });
+    }
+
+    info!("ACPI devices:");
+    for device in &devices {
+        let mut depth = 0;
+        let mut parent = device.parent;
+        while let Some(p) = parent {
+            depth += 1;
+            parent = devices[p].parent;
+        }
+        info!(
+            "{:indent$}{} hid={:?} cid={:?} sta={:#X} resources=[{}]",
+            "",
+            device.path,
+            device.hid.as_deref().unwrap_or("-"),
+            device.cids,
+            device.status,
+            device
+                .resources
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            indent = depth * 2 + 2,
+        );
+    }
+
+    DEVICE_TREE
+        .set(devices)
+        .unwrap_or_else(|_| panic!("ACPI device tree already initialized"));
+}
+
+/// All the devices in the namespace, sorted by path
+pub fn devices() -> &'static [AcpiDevice] {
+    DEVICE_TREE.get()
+}
+
+/// The present devices with `id` as `_HID` or `_CID`
+pub fn find_compatible(id: &str) -> impl Iterator<Item = &'static AcpiDevice> + '_ {
+    devices()
+        .iter()
+        .filter(move |device| device.is_present() && device.is_compatible(id))
+}
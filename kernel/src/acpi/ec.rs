@@ -0,0 +1,272 @@
+//! Embedded Controller (EC)
+//!
+//! Found in laptops, it controls the battery, lid, thermal sensors, etc. The firmware `AML` code accesses it
+//! through `EmbeddedControl` operation regions, and it notifies us of events through a `GPE`, where we
+//! query the event number, and the `_Qxx` method of the EC device handles it.
+//!
+//! The EC is found from the `ECDT` table if present, otherwise from the [`device_tree`] (device with `_HID` `PNP0C09`).
+
+use alloc::{format, string::String};
+use tracing::{error, info, warn};
+
+use crate::{
+    cpu,
+    sync::{once::OnceLock, spin::mutex::Mutex},
+};
+
+use super::{
+    aml::{
+        execution::{
+            register_region_handler, AmlExecutionError, DataObject, ExecutionContext, RegionHandler,
+        },
+        RegionSpace,
+    },
+    device_tree,
+    tables::{self, Facp},
+};
+
+const EC_HID: &str = "PNP0C09";
+/// The ports used by almost all ECs
+const DEFAULT_DATA_PORT: u16 = 0x62;
+const DEFAULT_COMMAND_PORT: u16 = 0x66;
+/// Number of status polls before we give up on the EC
+const MAX_POLLS: usize = 100_000;
+
+#[allow(dead_code)]
+mod status {
+    /// Output buffer full, data is ready to be read
+    pub const OBF: u8 = 1 << 0;
+    /// Input buffer full, the EC didn't read what we wrote yet
+    pub const IBF: u8 = 1 << 1;
+    pub const CMD: u8 = 1 << 3;
+    pub const BURST: u8 = 1 << 4;
+    /// An event is pending, must be queried
+    pub const SCI_EVT: u8 = 1 << 5;
+    pub const SMI_EVT: u8 = 1 << 6;
+}
+
+#[allow(dead_code)]
+mod command {
+    pub const READ: u8 = 0x80;
+    pub const WRITE: u8 = 0x81;
+    pub const BURST_ENABLE: u8 = 0x82;
+    pub const BURST_DISABLE: u8 = 0x83;
+    pub const QUERY: u8 = 0x84;
+}
+
+static EC: OnceLock<EmbeddedController> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum EcError {
+    Timeout,
+}
+
+#[derive(Debug)]
+pub struct EmbeddedController {
+    data_port: u16,
+    command_port: u16,
+    gpe: Option<u8>,
+    /// Path of the device in the namespace, the query methods are inside it
+    path: String,
+    /// The EC handles one transaction at a time
+    lock: Mutex<()>,
+}
+
+impl EmbeddedController {
+    fn status(&self) -> u8 {
+        unsafe { cpu::io_in(self.command_port) }
+    }
+
+    fn wait_status(&self, mut f: impl FnMut(u8) -> bool) -> Result<(), EcError> {
+        for _ in 0..MAX_POLLS {
+            if f(self.status()) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(EcError::Timeout)
+    }
+
+    fn send_command(&self, cmd: u8) -> Result<(), EcError> {
+        self.wait_status(|s| s & status::IBF == 0)?;
+        unsafe { cpu::io_out(self.command_port, cmd) };
+        Ok(())
+    }
+
+    fn write_data(&self, data: u8) -> Result<(), EcError> {
+        self.wait_status(|s| s & status::IBF == 0)?;
+        unsafe { cpu::io_out(self.data_port, data) };
+        Ok(())
+    }
+
+    fn read_data(&self) -> Result<u8, EcError> {
+        self.wait_status(|s| s & status::OBF != 0)?;
+        Ok(unsafe { cpu::io_in(self.data_port) })
+    }
+
+    pub fn read(&self, address: u8) -> Result<u8, EcError> {
+        let _lock = self.lock.lock();
+        self.send_command(command::READ)?;
+        self.write_data(address)?;
+        self.read_data()
+    }
+
+    pub fn write(&self, address: u8, value: u8) -> Result<(), EcError> {
+        let _lock = self.lock.lock();
+        self.send_command(command::WRITE)?;
+        self.write_data(address)?;
+        self.write_data(value)
+    }
+
+    /// Returns the number of the pending event, `None` if there is none
+    fn query(&self) -> Result<Option<u8>, EcError> {
+        let _lock = self.lock.lock();
+        if self.status() & status::SCI_EVT == 0 {
+            return Ok(None);
+        }
+        self.send_command(command::QUERY)?;
+        // 0 means no more events
+        Ok(Some(self.read_data()?).filter(|&n| n != 0))
+    }
+
+    fn handle_query(&self, query: u8) {
+        let method = format!("{}._Q{query:02X}", self.path);
+        let found = tables::get_acpi_tables()
+            .rsdt
+            .iter_tables::<tables::Xsdt>()
+            .any(|table| {
+                table
+                    .aml
+                    .structured()
+                    .find_object(&method)
+                    .is_ok_and(|obj| obj.is_some())
+            });
+
+        if found {
+            // TODO: execute the method when we support method execution
+            info!("EC event {query:#X}, handler {method} is not executed");
+        } else {
+            warn!("EC event {query:#X}, no handler {method}");
+        }
+    }
+}
+
+impl RegionHandler for EmbeddedController {
+    fn read(&self, offset: u64, width: usize) -> Result<u64, AmlExecutionError> {
+        let mut value = 0;
+        for i in 0..width as u64 {
+            let byte = EmbeddedController::read(self, (offset + i) as u8).map_err(|e| {
+                AmlExecutionError::RegionAccess(RegionSpace::EmbeddedControl, format!("{e:?}"))
+            })?;
+            value |= (byte as u64) << (i * 8);
+        }
+        Ok(value)
+    }
+
+    fn write(&self, offset: u64, width: usize, value: u64) -> Result<(), AmlExecutionError> {
+        for i in 0..width as u64 {
+            EmbeddedController::write(self, (offset + i) as u8, (value >> (i * 8)) as u8).map_err(
+                |e| AmlExecutionError::RegionAccess(RegionSpace::EmbeddedControl, format!("{e:?}")),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the EC device in the namespace, and get its ports and GPE
+fn find_in_namespace() -> Option<EmbeddedController> {
+    let device = device_tree::find_compatible(EC_HID).next()?;
+
+    // the first IO resource is the data port, the second is the command/status port
+    let mut ports = [DEFAULT_DATA_PORT, DEFAULT_COMMAND_PORT];
+    if device.io_ports().count() < 2 {
+        warn!(
+            "EC: could not get the resources of {}, using default ports",
+            device.path
+        );
+    }
+    for (port, (base, _)) in ports.iter_mut().zip(device.io_ports()) {
+        *port = base;
+    }
+
+    let gpe_label = format!("{}._GPE", device.path);
+    let gpe = tables::get_acpi_tables()
+        .rsdt
+        .iter_tables::<tables::Xsdt>()
+        .find_map(|table| {
+            match table
+                .aml
+                .execute(&mut ExecutionContext::default(), &gpe_label, &[])
+            {
+                Ok(DataObject::Integer(gpe)) => gpe.as_u8(),
+                Ok(obj) => {
+                    warn!("EC: {gpe_label} is not an integer: {obj:?}");
+                    None
+                }
+                Err(_) => None,
+            }
+        });
+
+    Some(EmbeddedController {
+        data_port: ports[0],
+        command_port: ports[1],
+        gpe,
+        path: device.path.clone(),
+        lock: Mutex::new(()),
+    })
+}
+
+/// Find the EC if there is one, and use it for `EmbeddedControl` regions and events
+pub fn init(facp: &Facp) {
+    let ec = if let Some(ecdt) = tables::get_acpi_tables().rsdt.get_table::<tables::Ecdt>() {
+        EmbeddedController {
+            data_port: ecdt.data.address as u16,
+            command_port: ecdt.control.address as u16,
+            gpe: Some(ecdt.gpe_bit),
+            path: ecdt.ec_id.clone(),
+            lock: Mutex::new(()),
+        }
+    } else if let Some(ec) = find_in_namespace() {
+        ec
+    } else {
+        return;
+    };
+
+    info!(
+        "EC: {} data={:#X}, command={:#X}, gpe={:?}",
+        ec.path, ec.data_port, ec.command_port, ec.gpe
+    );
+    let ec = EC.get_or_init(|| ec);
+    register_region_handler(RegionSpace::EmbeddedControl, ec);
+
+    if let Some(gpe) = ec.gpe {
+        if facp.set_gpe_0_enabled(gpe, true).is_none() {
+            warn!("EC: GPE {gpe} is not available, events will be ignored");
+        }
+    }
+}
+
+/// Handle the EC events if its GPE is set, returns `true` if it was
+pub fn handle_gpe(facp: &Facp) -> bool {
+    let Some(ec) = EC.try_get() else {
+        return false;
+    };
+    let Some(gpe) = ec.gpe else {
+        return false;
+    };
+    if facp.take_gpe_0_status(gpe) != Some(true) {
+        return false;
+    }
+
+    loop {
+        match ec.query() {
+            Ok(Some(query)) => ec.handle_query(query),
+            Ok(None) => break,
+            Err(e) => {
+                error!("EC: failed to query event: {e:?}");
+                break;
+            }
+        }
+    }
+    true
+}
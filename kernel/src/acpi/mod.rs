@@ -1,4 +1,6 @@
 mod aml;
+pub mod device_tree;
+mod ec;
 pub mod tables;
 
 use alloc::format;
@@ -16,6 +18,7 @@ use crate::{
         idt::{BasicInterruptHandler, InterruptStackFrame64},
         interrupts::apic,
     },
+    devices::clock,
     power,
     sync::once::OnceLock,
 };
@@ -27,6 +30,13 @@ pub fn init() {
     ACPI.set(Acpi::init())
         .expect("ACPI was already initialized");
 
+    device_tree::init();
+    let facp = tables::get_acpi_tables()
+        .rsdt
+        .get_table::<tables::Facp>()
+        .expect("No Facp");
+    ec::init(facp);
+
     info!("ACPI initialized");
 }
 
@@ -128,6 +138,7 @@ impl Acpi {
             acpi_handler as BasicInterruptHandler,
             facp.sci_interrupt(),
             cpu::cpu(),
+            "ACPI SCI",
         );
 
         facp.enable_acpi();
@@ -222,6 +233,8 @@ extern "x86-interrupt" fn acpi_handler(_frame: InterruptStackFrame64) {
         .expect("No Facp");
 
     let pm1_event = facp.read_pm1_status();
+    // GPEs don't show up in `PM1` status
+    let handled_gpe = ec::handle_gpe(facp);
 
     if pm1_event & facp::flags::PM_EN_GBL != 0 {
         facp.write_pm1_status(facp::flags::PM_EN_GBL);
@@ -231,7 +244,10 @@ extern "x86-interrupt" fn acpi_handler(_frame: InterruptStackFrame64) {
         warn!("Sleep button ACPI event: {:X}", pm1_event);
     } else if pm1_event & facp::flags::PM_EN_RTC != 0 {
         facp.write_pm1_status(facp::flags::PM_EN_RTC);
-        warn!("RTC ACPI event: {:X}", pm1_event);
+        // the clocks may not be initialized yet
+        if !clock::try_clocks().is_some_and(|clocks| clocks.handle_rtc_interrupt()) {
+            warn!("RTC ACPI event: {:X}", pm1_event);
+        }
     } else if pm1_event & facp::flags::PM_EN_PWRBTN != 0 {
         facp.write_pm1_status(facp::flags::PM_EN_PWRBTN);
         warn!("Power button ACPI event: {:X}", pm1_event);
@@ -241,7 +257,7 @@ extern "x86-interrupt" fn acpi_handler(_frame: InterruptStackFrame64) {
     } else if pm1_event & facp::flags::PM_EN_TMR != 0 {
         facp.write_pm1_status(facp::flags::PM_EN_TMR);
         warn!("Timer ACPI event: {:X}", pm1_event);
-    } else {
+    } else if !handled_gpe {
         warn!("Unknown ACPI event: {:X}", pm1_event);
     }
 
@@ -66,8 +66,8 @@ pub struct Facp {
     flush_stride: u16,
     duty_offset: u8,
     duty_width: u8,
-    day_alarm: u8,
-    month_alarm: u8,
+    pub day_alarm: u8,
+    pub month_alarm: u8,
     pub century: u8,
     iapc_boot_arch: u16,
     reserved2: u8,
@@ -423,4 +423,52 @@ impl Facp {
             self.gpe0_block_length / 2,
         ))
     }
+
+    /// The IO port of the GPE0 block, `None` if not available or not in IO space
+    fn gpe_0_block_port(&self) -> Option<u16> {
+        if !self.x_gpe0_block.is_zero() {
+            // 1 = SystemIO
+            if self.x_gpe0_block.address_space_id != 1 {
+                return None;
+            }
+            return Some(self.x_gpe0_block.address as u16);
+        }
+
+        if self.gpe0_block == 0 {
+            return None;
+        }
+        Some(self.gpe0_block as u16)
+    }
+
+    /// Enable or disable a single GPE in block 0
+    pub fn set_gpe_0_enabled(&self, gpe: u8, enabled: bool) -> Option<()> {
+        if gpe as u16 >= (self.gpe0_block_length as u16 / 2) * 8 {
+            return None;
+        }
+        // the enable register is after the status register
+        let port = self.gpe_0_block_port()? + (self.gpe0_block_length / 2) as u16 + gpe as u16 / 8;
+        let bit = 1 << (gpe % 8);
+        unsafe {
+            let value = cpu::io_in::<u8>(port);
+            cpu::io_out::<u8>(port, if enabled { value | bit } else { value & !bit });
+        }
+        Some(())
+    }
+
+    /// Returns `true` if a single GPE in block 0 is set, and clears it
+    pub fn take_gpe_0_status(&self, gpe: u8) -> Option<bool> {
+        if gpe as u16 >= (self.gpe0_block_length as u16 / 2) * 8 {
+            return None;
+        }
+        let port = self.gpe_0_block_port()? + gpe as u16 / 8;
+        let bit = 1 << (gpe % 8);
+        unsafe {
+            if cpu::io_in::<u8>(port) & bit == 0 {
+                return Some(false);
+            }
+            // write 1 to clear
+            cpu::io_out::<u8>(port, bit);
+        }
+        Some(true)
+    }
 }
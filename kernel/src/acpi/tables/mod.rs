@@ -9,7 +9,7 @@ use core::{
     slice,
 };
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, string::String, vec::Vec};
 use byteorder::{ByteOrder, LittleEndian};
 
 use crate::{
@@ -28,9 +28,10 @@ const BIOS_RO_MEM_END: u64 = 0x000FFFFF;
 /// # Safety
 ///
 /// Must ensure the `physical_addr` is valid and point to correct DescriptionHeader
-/// Must ensure that the `physical_address` is not used in virtual_space before calling this function
 /// We are using `VirtualSpace` on low kernel addresses (i.e. already mapped by the kernel).
 /// Accessing these addresses manually without `VirtualSpace` may lead to undefined behavior due to aliasing memory referenced by other code
+///
+/// Fails if the table is in another `VirtualSpace` that is still alive, see [`VirtualSpace`] for the aliasing checks
 unsafe fn get_acpi_table_bytes(physical_addr: u64) -> (DescriptionHeader, VirtualSpace<[u8]>) {
     let header = VirtualSpace::<DescriptionHeader>::new(physical_addr).expect("Failed to map");
     let len = header.length as usize;
@@ -210,8 +211,8 @@ impl Rsdp {
     ///
     /// # Safety
     ///
-    /// This should only be called once and not overlapping with any operation done to the region containing ACPI tables
-    /// this uses virtual space for the regions that the `rsdt` is inside and all its other children structures
+    /// This should only be called once, it uses virtual space for the regions that the `rsdt` is inside and all its
+    /// other children structures, which fails if any of them is mapped by someone else
     unsafe fn rdst(&self) -> Rsdt {
         // Safety: here we are the first
         let (header, body_bytes) = get_acpi_table_bytes(self.rsdt_address as _);
@@ -229,8 +230,8 @@ impl Rsdp {
 
         let entries = entries_ptrs
             .into_iter()
-            // Safety: `from_physical_ptr` require we don't overlap usage of ACPI memory, we are `deallocating` the memory above
-            //         before going into this function, and it will handle its own deallocation, so we are safe on that side
+            // Safety: the pointers come from the `rsdt`. We are `deallocating` the memory above before going into this
+            //         function, so it can map the tables even if they share pages with the `rsdt`
             .map(|p| unsafe { DescriptorTable::from_physical_ptr(p) })
             .collect();
 
@@ -238,7 +239,7 @@ impl Rsdp {
         // add extra entries
         if let Some(facp) = s.get_table::<Facp>() {
             if facp.dsdt != 0 {
-                // Safety: same as above, we are sure that we are not overlapping with any ACPI memory
+                // Safety: same as above, the pointer comes from the `facp`, which is not mapped anymore
                 s.entries
                     .push(DescriptorTable::from_physical_ptr(facp.dsdt));
             }
@@ -268,6 +269,7 @@ impl Rsdt {
                 DescriptorTableBody::Bgrt(a) => Some(a.as_ref() as &dyn Any),
                 DescriptorTableBody::Waet(a) => Some(a.as_ref() as &dyn Any),
                 DescriptorTableBody::Srat(a) => Some(a.as_ref() as &dyn Any),
+                DescriptorTableBody::Ecdt(a) => Some(a.as_ref() as &dyn Any),
             })
             .find_map(|obj| obj.downcast_ref::<T>())
     }
@@ -285,6 +287,7 @@ impl Rsdt {
                 DescriptorTableBody::Bgrt(a) => Some(a.as_ref() as &dyn Any),
                 DescriptorTableBody::Waet(a) => Some(a.as_ref() as &dyn Any),
                 DescriptorTableBody::Srat(a) => Some(a.as_ref() as &dyn Any),
+                DescriptorTableBody::Ecdt(a) => Some(a.as_ref() as &dyn Any),
             })
             .filter_map(|obj| obj.downcast_ref::<T>())
     }
@@ -324,9 +327,9 @@ pub struct DescriptorTable {
 impl DescriptorTable {
     /// # Safety
     ///
-    /// This should not overlap any reference to the ACPI memory, it will own a reference to virtual space
-    /// that points to the physical address, and then yields the reference before it returns.
-    /// Thus it must never be called concurrently as well
+    /// `ptr` must point to a valid table. It will own a reference to virtual space that points to the physical
+    /// address, and then yields the reference before it returns, so this fails if any reference to the ACPI memory
+    /// that overlaps the table is alive, and it must never be called concurrently as well
     pub unsafe fn from_physical_ptr(ptr: u32) -> Self {
         // Safety: here we are relying on the caller to ensure that the `ptr` is valid and no one is using ACPI memory
         let (header, body_bytes) = unsafe { get_acpi_table_bytes(ptr as _) };
@@ -340,6 +343,7 @@ impl DescriptorTable {
             b"BGRT" => DescriptorTableBody::Bgrt(Box::new(get_table_from_body(&body_bytes))),
             b"WAET" => DescriptorTableBody::Waet(Box::new(get_table_from_body(&body_bytes))),
             b"SRAT" => DescriptorTableBody::Srat(Box::new(Srat::from_body_bytes(&body_bytes))),
+            b"ECDT" => DescriptorTableBody::Ecdt(Box::new(Ecdt::from_body_bytes(&body_bytes))),
             _ => DescriptorTableBody::Unknown(HexArray(body_bytes.to_vec())),
         };
 
@@ -357,6 +361,7 @@ pub enum DescriptorTableBody {
     Bgrt(Box<Bgrt>),
     Waet(Box<Waet>),
     Srat(Box<Srat>),
+    Ecdt(Box<Ecdt>),
     Unknown(HexArray<Vec<u8>>),
 }
 
@@ -523,13 +528,44 @@ impl Xsdt {
 #[repr(C, packed)]
 pub struct Bgrt {
     version: u16,
-    status: u8,
-    image_type: u8,
+    pub status: u8,
+    pub image_type: u8,
     pub image_address: u64,
     pub image_offset_x: u32,
     pub image_offset_y: u32,
 }
 
+/// Embedded Controller Boot Resources Table, so that the EC can be used before the namespace is loaded
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Ecdt {
+    pub control: ApicGenericAddress,
+    pub data: ApicGenericAddress,
+    pub uid: u32,
+    pub gpe_bit: u8,
+    /// Absolute path of the EC device in the namespace
+    pub ec_id: String,
+}
+
+impl Ecdt {
+    fn from_body_bytes(body: &[u8]) -> Self {
+        let gas_size = mem::size_of::<ApicGenericAddress>();
+        let control = get_struct_from_bytes(&body[..gas_size]);
+        let data = get_struct_from_bytes(&body[gas_size..gas_size * 2]);
+        let rest = &body[gas_size * 2..];
+        let ec_id = &rest[5..];
+        let ec_id = ec_id.split(|&c| c == 0).next().unwrap_or_default();
+
+        Self {
+            control,
+            data,
+            uid: LittleEndian::read_u32(rest),
+            gpe_bit: rest[4],
+            ec_id: String::from_utf8_lossy(ec_id).into_owned(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Waet {
@@ -677,7 +713,7 @@ pub struct BiosTables {
 impl BiosTables {
     /// # Safety
     ///
-    /// This should only be called once and not overlapping with any operation done to the region containing ACPI tables
+    /// This should only be called once, see `Rsdp::rdst`
     pub unsafe fn new(rsdp: Rsdp) -> Self {
         Self {
             rsdt: rsdp.rdst(),
@@ -0,0 +1,148 @@
+//! Boot-time measurement
+//!
+//! `kernel_main` marks each [`BootStep`] when it's done with [`mark`]. The timestamps are `TSC`
+//! cycles, since it's available from the first instruction, way before the clocks are initialized.
+//! They are converted to nanoseconds using the rate between two [`TscSyncPoint`]s, one taken when
+//! [`BootStep::Clocks`] is marked, and one in [`report`].
+//!
+//! [`report`] prints a table of the steps when boot is finished, and sends them as `boot_step` and
+//! `boot_total` records over [`debugcon`], which `xtask` uses to track boot-time regressions.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::{info, warn};
+
+use crate::{
+    cpu,
+    devices::clock::TscSyncPoint,
+    hw::debugcon::{self, Value},
+    sync::once::OnceLock,
+};
+
+/// The steps of `kernel_main` in order, each is marked when it's done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BootStep {
+    /// Entered `kernel_main`
+    Entry,
+    /// The cmdline is parsed and the early console is ready
+    EarlyConsole,
+    /// The memory map is read and the physical allocator is ready
+    PhysicalAllocator,
+    /// The kernel page tables and the heap
+    VirtualMemory,
+    /// GDT, IDT and the GDB stub (which waits for GDB if enabled)
+    Interrupts,
+    /// The ACPI tables are found and parsed
+    AcpiTables,
+    /// APIC, the other CPUs and the ACPI interpreter
+    Apic,
+    /// The clocks, including the `TSC` calibration
+    Clocks,
+    /// The PS/2 devices, the display and the late console
+    LegacyDevices,
+    /// The PCI devices are probed
+    PciDevices,
+    /// The initrd and the root filesystem are mounted
+    DiskMount,
+    /// `init` is loaded
+    InitSpawn,
+}
+
+impl BootStep {
+    const COUNT: usize = 12;
+    const ALL: [BootStep; Self::COUNT] = [
+        BootStep::Entry,
+        BootStep::EarlyConsole,
+        BootStep::PhysicalAllocator,
+        BootStep::VirtualMemory,
+        BootStep::Interrupts,
+        BootStep::AcpiTables,
+        BootStep::Apic,
+        BootStep::Clocks,
+        BootStep::LegacyDevices,
+        BootStep::PciDevices,
+        BootStep::DiskMount,
+        BootStep::InitSpawn,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            BootStep::Entry => "entry",
+            BootStep::EarlyConsole => "early_console",
+            BootStep::PhysicalAllocator => "physical_allocator",
+            BootStep::VirtualMemory => "virtual_memory",
+            BootStep::Interrupts => "interrupts",
+            BootStep::AcpiTables => "acpi_tables",
+            BootStep::Apic => "apic",
+            BootStep::Clocks => "clocks",
+            BootStep::LegacyDevices => "legacy_devices",
+            BootStep::PciDevices => "pci_devices",
+            BootStep::DiskMount => "disk_mount",
+            BootStep::InitSpawn => "init_spawn",
+        }
+    }
+}
+
+// only used to initialize `MARKS`
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_MARKED: AtomicU64 = AtomicU64::new(0);
+
+/// The `TSC` value when each step was done, `0` if not marked
+static MARKS: [AtomicU64; BootStep::COUNT] = [NOT_MARKED; BootStep::COUNT];
+static CLOCKS_SYNC: OnceLock<TscSyncPoint> = OnceLock::new();
+
+/// Mark `step` as done
+pub fn mark(step: BootStep) {
+    MARKS[step as usize].store(unsafe { cpu::read_tsc() }, Ordering::Relaxed);
+    if step == BootStep::Clocks {
+        if let Some(point) = TscSyncPoint::now() {
+            CLOCKS_SYNC.set(point).expect("Clocks marked twice");
+        }
+    }
+}
+
+/// Print the duration of each step, and send them over [`debugcon`]
+pub fn report() {
+    let (Some(start), Some(end)) = (CLOCKS_SYNC.try_get(), TscSyncPoint::now()) else {
+        warn!("Boot time: the clocks are not available");
+        return;
+    };
+    let entry = MARKS[BootStep::Entry as usize].load(Ordering::Relaxed);
+    // the time since entry, `None` if the step was not marked
+    let since_entry = |step: BootStep| {
+        let cycles = MARKS[step as usize].load(Ordering::Relaxed);
+        if cycles == 0 {
+            return None;
+        }
+        start.cycles_to_nanos(&end, cycles.saturating_sub(entry))
+    };
+    let ms = |nanos: u64| nanos as f64 / 1_000_000.0;
+
+    info!("Boot time:");
+    info!("  {:<20} {:>12} {:>12}", "step", "duration", "since entry");
+    let mut previous = 0;
+    for step in &BootStep::ALL[1..] {
+        let Some(nanos) = since_entry(*step) else {
+            continue;
+        };
+        let duration = nanos.saturating_sub(previous);
+        previous = nanos;
+        info!(
+            "  {:<20} {:>10.3}ms {:>10.3}ms",
+            step.name(),
+            ms(duration),
+            ms(nanos)
+        );
+        debugcon::send(
+            "boot_step",
+            &[
+                ("name", Value::Str(step.name())),
+                ("nanos", Value::U64(nanos)),
+                ("duration", Value::U64(duration)),
+            ],
+        );
+    }
+    info!("  {:<20} {:>12} {:>10.3}ms", "total", "", ms(previous));
+    debugcon::send("boot_total", &[("nanos", Value::U64(previous))]);
+}
@@ -23,6 +23,13 @@ const fn default_cmdline() -> Cmd<'static> {
         log_file: "/kernel.log",
         allow_hpet: true,
         log_aml: LogAml::Off,
+        verify_binaries: false,
+        boot_logo: true,
+        watchdog_timeout: 30,
+        gdb_stub: false,
+        timezone_offset: 0,
+        rtc_localtime: false,
+        graphics_serial_mirror: false,
     }
 }
 
@@ -76,6 +83,30 @@ pub struct Cmd<'a> {
     /// Log the AML content as ASL code on boot from ACPI tables
     #[default = LogAml::Off]
     pub log_aml: LogAml,
+    /// Verify the `SHA-256` hash of userspace binaries against `/binaries.manifest`
+    /// before executing them, refusing to run on mismatch
+    #[default = false]
+    pub verify_binaries: bool,
+    /// Display the firmware boot logo (from the ACPI `BGRT` table) until the boot finishes
+    #[default = true]
+    pub boot_logo: bool,
+    /// Seconds to wait for a write to `/devices/watchdog` after it is armed before rebooting,
+    /// `0` disables the watchdog
+    #[default = 30]
+    pub watchdog_timeout: u32,
+    /// Enable the GDB remote stub on the second serial port (`COM2`), and wait for GDB on boot
+    #[default = false]
+    pub gdb_stub: bool,
+    /// Offset of the local time from UTC in minutes, i.e. `180` for `UTC+3`
+    #[default = 0]
+    pub timezone_offset: i32,
+    /// The RTC holds the local time (as set by Windows) instead of UTC
+    #[default = false]
+    pub rtc_localtime: bool,
+    /// While a process owns the graphics, also send the output of all the terminals to the UART,
+    /// not only the kernel terminal
+    #[default = false]
+    pub graphics_serial_mirror: bool,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -75,6 +75,24 @@ impl<'a> CmdlineParse<'a> for u32 {
     }
 }
 
+impl<'a> CmdlineParse<'a> for i32 {
+    fn parse_cmdline(tokenizer: &mut Tokenizer<'a>) -> Result<'a, Self> {
+        let (loc, value) = tokenizer.next_value().ok_or_else(|| {
+            ParseError::new(
+                ParseErrorKind::Unexpected {
+                    need: "<number>",
+                    got: None,
+                },
+                tokenizer.current_index(),
+            )
+        })?;
+
+        value
+            .parse()
+            .map_err(|e| ParseError::new(ParseErrorKind::ParseIntError(e), loc))
+    }
+}
+
 impl<'a> CmdlineParse<'a> for &'a str {
     fn parse_cmdline(tokenizer: &mut Tokenizer<'a>) -> Result<'a, Self> {
         let (_loc, value) = tokenizer.next_value().ok_or_else(|| {
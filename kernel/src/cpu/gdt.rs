@@ -2,6 +2,7 @@ use core::{mem, ptr::addr_of};
 
 use crate::{
     memory_management::{
+        kernel_stack,
         memory_layout::{
             is_aligned, INTR_STACK_BASE, INTR_STACK_EMPTY_SIZE, INTR_STACK_ENTRY_SIZE,
             INTR_STACK_SIZE, INTR_STACK_TOTAL_SIZE, PAGE_4K, PROCESS_KERNEL_STACK_END,
@@ -100,6 +101,7 @@ pub fn init_kernel_gdt() {
         // this will be used on transitions from user to kernel
         unsafe { TSS.rsp[KERNEL_RING as usize] = PROCESS_KERNEL_STACK_END as u64 - 8 };
     }
+    kernel_stack::init_interrupt_stacks();
 
     let tss_ptr = unsafe { addr_of!(TSS) } as u64;
 
@@ -1,8 +1,29 @@
-use core::{marker::PhantomData, mem};
-
-use tracing::error;
-
-use super::interrupts::stack_index;
+use core::{
+    marker::PhantomData,
+    mem,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use kernel_user_link::process::{
+    ARITHMETIC_ERROR_EXIT_CODE, BUS_ERROR_EXIT_CODE, ILLEGAL_INSTRUCTION_EXIT_CODE,
+    SEGMENTATION_FAULT_EXIT_CODE, TRAP_EXIT_CODE,
+};
+use tracing::{error, warn};
+
+use crate::{
+    devices::clock,
+    memory_management::{memory_layout, virtual_memory_mapper},
+    process::{procfs::ProcessName, scheduler},
+    tracepoints::{self, Event},
+};
+
+use super::interrupts::{apic, stack_index, stats};
+
+/// Bits of the page fault error code
+mod page_fault_error {
+    pub const PRESENT: u64 = 1 << 0;
+    pub const WRITE: u64 = 1 << 1;
+}
 
 core::arch::global_asm!(include_str!("idt_vectors.S"));
 
@@ -190,10 +211,10 @@ pub(super) struct InterruptDescriptorTable {
     pub machine_check: InterruptDescriptorTableEntry<BasicInterruptHandler>,
     pub simd_floating_point: InterruptDescriptorTableEntry<BasicInterruptHandler>,
     pub reserved_2: InterruptDescriptorTableEntry<()>,
-    pub control_protection: InterruptDescriptorTableEntry<BasicInterruptHandler>,
+    pub control_protection: InterruptDescriptorTableEntry<InterruptHandlerWithError>,
     pub reserved_3: [InterruptDescriptorTableEntry<()>; 6],
     pub hypervisor_injection: InterruptDescriptorTableEntry<BasicInterruptHandler>,
-    pub vmm_communication: InterruptDescriptorTableEntry<BasicInterruptHandler>,
+    pub vmm_communication: InterruptDescriptorTableEntry<InterruptHandlerWithError>,
     pub security_exception: InterruptDescriptorTableEntry<InterruptHandlerWithError>,
     pub reserved_4: InterruptDescriptorTableEntry<()>,
     pub user_defined: [InterruptDescriptorTableEntry<BasicInterruptHandler>; 256 - 32],
@@ -234,52 +255,114 @@ impl InterruptDescriptorTable {
     }
 
     pub fn init_default_handlers(&mut self) {
-        self.divide_by_zero.set_handler(default_handler::<0>);
-        self.debug.set_handler(default_handler::<1>);
+        self.divide_by_zero
+            .set_handler_with_number(exception_handler, 0);
+        self.debug.set_handler_with_number(exception_handler, 1);
         self.non_maskable_interrupt
-            .set_handler(default_handler::<2>);
-        self.breakpoint.set_handler(default_handler::<3>);
-        self.overflow.set_handler(default_handler::<4>);
+            .set_handler_with_number(exception_handler, 2);
+        self.breakpoint
+            .set_handler_with_number(exception_handler, 3);
+        self.overflow.set_handler_with_number(exception_handler, 4);
         self.bound_range_exceeded
-            .set_handler(default_handler::<5>)
+            .set_handler_with_number(exception_handler, 5)
             .set_stack_index(Some(stack_index::FAULTS_STACK));
         self.invalid_opcode
-            .set_handler(default_handler::<6>)
+            .set_handler_with_number(exception_handler, 6)
             .set_stack_index(Some(stack_index::FAULTS_STACK));
         self.device_not_available
-            .set_handler(default_handler::<7>)
+            .set_handler_with_number(exception_handler, 7)
             .set_stack_index(Some(stack_index::FAULTS_STACK));
         self.double_fault
-            .set_handler(default_handler_with_error::<8>)
+            .set_handler(double_fault_handler)
             .set_stack_index(Some(stack_index::DOUBLE_FAULT_STACK));
+        self.coprocessor_segment_overrun
+            .set_handler_with_number(unhandled_interrupt_handler, 9);
         self.invalid_tss
-            .set_handler(default_handler_with_error::<10>);
+            .set_handler_with_number(exception_handler, 10);
         self.segment_not_present
-            .set_handler(default_handler_with_error::<11>);
+            .set_handler_with_number(exception_handler, 11);
         self.stack_exception
-            .set_handler(default_handler_with_error::<12>);
+            .set_handler_with_number(exception_handler, 12);
         self.general_protection_fault
-            .set_handler(default_handler_with_error::<13>);
+            .set_handler_with_number(exception_handler, 13);
         self.page_fault
-            .set_handler(default_handler_with_error::<14>)
+            .set_handler_with_number(page_fault_handler, 14)
             .set_stack_index(Some(stack_index::FAULTS_STACK));
-        self.x87_floating_point.set_handler(default_handler::<16>);
+        // the legacy PIC is masked, but it can still send its spurious interrupt
+        // (IRQ 7), which lands here as it was never remapped
+        self.reserved_1
+            .set_handler_with_number(unhandled_interrupt_handler, 15);
+        self.x87_floating_point
+            .set_handler_with_number(exception_handler, 16);
         self.alignment_check
-            .set_handler(default_handler_with_error::<17>)
+            .set_handler_with_number(exception_handler, 17)
             .set_stack_index(Some(stack_index::FAULTS_STACK));
-        self.machine_check.set_handler(default_handler::<18>);
-        self.simd_floating_point.set_handler(default_handler::<19>);
-        self.control_protection.set_handler(default_handler::<21>);
-        self.hypervisor_injection.set_handler(default_handler::<28>);
-        self.vmm_communication.set_handler(default_handler::<29>);
+        self.machine_check
+            .set_handler_with_number(exception_handler, 18);
+        self.simd_floating_point
+            .set_handler_with_number(exception_handler, 19);
+        self.reserved_2
+            .set_handler_with_number(unhandled_interrupt_handler, 20);
+        self.control_protection
+            .set_handler_with_number(exception_handler, 21);
+        for (i, entry) in self.reserved_3.iter_mut().enumerate() {
+            entry.set_handler_with_number(unhandled_interrupt_handler, 22 + i as u8);
+        }
+        self.hypervisor_injection
+            .set_handler_with_number(exception_handler, 28);
+        self.vmm_communication
+            .set_handler_with_number(exception_handler, 29);
         self.security_exception
-            .set_handler(default_handler_with_error::<30>);
+            .set_handler_with_number(exception_handler, 30);
+        self.reserved_4
+            .set_handler_with_number(unhandled_interrupt_handler, 31);
 
-        for entry in self.user_defined.iter_mut() {
-            entry.set_handler(default_handler::<0xFF>);
+        // replaced when allocated
+        for (i, entry) in self.user_defined.iter_mut().enumerate() {
+            entry.set_handler_with_number(unhandled_interrupt_handler, 32 + i as u8);
+        }
+
+        for vector in 0..32 {
+            if let Some(name) = exception_name(vector) {
+                stats::register(vector, name, None);
+            }
         }
     }
 
+    /// Route all the exceptions to `handler`, used before the default handlers can run,
+    /// see [`init_early_exceptions`](super::interrupts::init_early_exceptions)
+    pub(super) fn init_early_handlers(&mut self, handler: InterruptHandlerWithAllState) {
+        self.divide_by_zero.set_handler_with_number(handler, 0);
+        self.debug.set_handler_with_number(handler, 1);
+        self.non_maskable_interrupt
+            .set_handler_with_number(handler, 2);
+        self.breakpoint.set_handler_with_number(handler, 3);
+        self.overflow.set_handler_with_number(handler, 4);
+        self.bound_range_exceeded
+            .set_handler_with_number(handler, 5);
+        self.invalid_opcode.set_handler_with_number(handler, 6);
+        self.device_not_available
+            .set_handler_with_number(handler, 7);
+        self.double_fault.set_handler_with_number(handler, 8);
+        self.invalid_tss.set_handler_with_number(handler, 10);
+        self.segment_not_present
+            .set_handler_with_number(handler, 11);
+        self.stack_exception.set_handler_with_number(handler, 12);
+        self.general_protection_fault
+            .set_handler_with_number(handler, 13);
+        self.page_fault.set_handler_with_number(handler, 14);
+        self.x87_floating_point.set_handler_with_number(handler, 16);
+        self.alignment_check.set_handler_with_number(handler, 17);
+        self.machine_check.set_handler_with_number(handler, 18);
+        self.simd_floating_point
+            .set_handler_with_number(handler, 19);
+        self.control_protection.set_handler_with_number(handler, 21);
+        self.hypervisor_injection
+            .set_handler_with_number(handler, 28);
+        self.vmm_communication.set_handler_with_number(handler, 29);
+        self.security_exception.set_handler_with_number(handler, 30);
+    }
+
     pub(super) fn apply_idt(&'static self) {
         let idt_ptr = InterruptDescriptorTablePointer {
             limit: mem::size_of::<InterruptDescriptorTable>() as u16 - 1,
@@ -300,6 +383,9 @@ pub(super) struct InterruptDescriptorTablePointer {
 
 #[no_mangle]
 pub extern "cdecl" fn rust_interrupt_handler_for_all_state(mut state: InterruptAllSavedState) {
+    if state.frame.cs & 0x3 == 3 {
+        super::user_access::on_entry_from_user();
+    }
     let handler = unsafe { REDIRECTED_INTERRUPTS[state.number as usize] };
     if let Some(handler) = handler {
         let handler: InterruptHandlerWithAllState = unsafe { mem::transmute(handler) };
@@ -310,27 +396,223 @@ pub extern "cdecl" fn rust_interrupt_handler_for_all_state(mut state: InterruptA
     panic!("Could not find handler for interrupt {}", state.number);
 }
 
-extern "x86-interrupt" fn default_handler<const N: u8>(frame: InterruptStackFrame64) {
-    error!("[{N}] Got exception: \n frame: {:x?}", frame);
+/// Limits how often a message is logged, at most `burst` messages every `interval_secs` seconds,
+/// so that a process or a device that keeps faulting can't flood the log
+pub struct LogRateLimit {
+    burst: u32,
+    interval_secs: u64,
+    window_start: AtomicU64,
+    logged: AtomicU32,
+    suppressed: AtomicU64,
+}
+
+impl LogRateLimit {
+    pub const fn new(burst: u32, interval_secs: u64) -> Self {
+        Self {
+            burst,
+            interval_secs,
+            window_start: AtomicU64::new(0),
+            logged: AtomicU32::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
 
-    crate::panic_handler::print_originating_stack_trace(&frame, super::rbp!());
-    panic!("Unhandled exception");
+    /// Returns `true` if the message should be logged, the number of `what` that were not logged
+    /// before it is logged as well.
+    ///
+    /// Can be called from interrupt handlers, before the clocks are initialized, the window never ends.
+    pub fn check(&self, what: &str) -> bool {
+        if let Some(clocks) = clock::try_clocks() {
+            let now = clocks.time_since_startup().seconds;
+            if now >= self.window_start.load(Ordering::Relaxed) + self.interval_secs {
+                self.window_start.store(now, Ordering::Relaxed);
+                self.logged.store(0, Ordering::Relaxed);
+            }
+        }
+        if self.logged.fetch_add(1, Ordering::Relaxed) >= self.burst {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let suppressed = self.suppressed.swap(0, Ordering::Relaxed);
+        if suppressed != 0 {
+            warn!("{suppressed} {what} were not logged (rate limited)");
+        }
+        true
+    }
 }
 
-extern "x86-interrupt" fn default_handler_with_error<const N: u8>(
-    frame: InterruptStackFrame64,
-    error_code: u64,
-) {
-    let cr2: u64;
-    unsafe {
-        core::arch:: asm!("mov {}, cr2", out(reg) cr2);
+static USER_FAULT_LOG_LIMIT: LogRateLimit = LogRateLimit::new(10, 5);
+static UNHANDLED_INTERRUPT_LOG_LIMIT: LogRateLimit = LogRateLimit::new(10, 5);
+
+pub(super) fn exception_name(vector: u8) -> Option<&'static str> {
+    let name = match vector {
+        0 => "divide error",
+        1 => "debug",
+        2 => "non-maskable interrupt",
+        3 => "breakpoint",
+        4 => "overflow",
+        5 => "bound range exceeded",
+        6 => "invalid opcode",
+        7 => "device not available",
+        8 => "double fault",
+        9 => "coprocessor segment overrun",
+        10 => "invalid TSS",
+        11 => "segment not present",
+        12 => "stack-segment fault",
+        13 => "general protection fault",
+        14 => "page fault",
+        16 => "x87 floating-point exception",
+        17 => "alignment check",
+        18 => "machine check",
+        19 => "SIMD floating-point exception",
+        20 => "virtualization exception",
+        21 => "control protection exception",
+        28 => "hypervisor injection exception",
+        29 => "VMM communication exception",
+        30 => "security exception",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The exit code of a process that caused the exception `vector`, `None` if it's not caused by the
+/// process (i.e. machine check), and can't be recovered from by terminating it
+fn user_exception_exit_code(vector: u8) -> Option<i32> {
+    match vector {
+        1 | 3 => Some(TRAP_EXIT_CODE),
+        0 | 7 | 16 | 19 => Some(ARITHMETIC_ERROR_EXIT_CODE),
+        6 => Some(ILLEGAL_INSTRUCTION_EXIT_CODE),
+        4 | 5 | 10 | 13 | 14 | 21 => Some(SEGMENTATION_FAULT_EXIT_CODE),
+        11 | 12 | 17 => Some(BUS_ERROR_EXIT_CODE),
+        _ => None,
     }
+}
+
+extern "cdecl" fn exception_handler(state: &mut InterruptAllSavedState) {
+    stats::record(state.number as u8);
+    handle_exception(state);
+}
+
+/// Faults from user processes terminate the process, the rest are kernel bugs (or hardware errors)
+fn handle_exception(state: &mut InterruptAllSavedState) {
+    let vector = state.number as u8;
+    if state.frame.cs & 0x3 == 3 {
+        if let Some(exit_code) = user_exception_exit_code(vector) {
+            terminate_faulting_process(state, exit_code);
+            return;
+        }
+    }
+    unhandled_exception_with_error(vector, &state.frame, state.error, state.rest.rbp);
+}
+
+/// Exit the current process with `exit_code`, `state` will be used to go back to the scheduler
+fn terminate_faulting_process(state: &mut InterruptAllSavedState, exit_code: i32) {
+    let vector = state.number as u8;
+    if USER_FAULT_LOG_LIMIT.check("user faults") {
+        let cr2 = unsafe { super::get_cr2() };
+        let process = ProcessName(Some(super::cpu().process_id));
+        error!(
+            "[{vector}] {process} {}: rip: {:X}, rsp: {:X}, error: {:016X}, cr2: {cr2:X}, terminating with exit code {exit_code}",
+            exception_name(vector).unwrap_or("unknown exception"),
+            state.frame.rip,
+            state.frame.rsp,
+            state.error,
+        );
+        crate::panic_handler::print_process_stack_trace(&state.frame, state.rest.rbp);
+    }
+    scheduler::exit_current_process(exit_code, state);
+}
+
+/// The vectors that no one handles, they are reported and acknowledged, so that a misconfigured
+/// device doesn't block the lower priority interrupts
+extern "cdecl" fn unhandled_interrupt_handler(state: &mut InterruptAllSavedState) {
+    let vector = state.number as u8;
+    if UNHANDLED_INTERRUPT_LOG_LIMIT.check("unhandled interrupts") {
+        let current_cpu = super::cpu();
+        let process = ProcessName(current_cpu.context.map(|_| current_cpu.process_id));
+        warn!(
+            "Unhandled interrupt {vector:#04x} in {process}, rip: {:X}",
+            state.frame.rip
+        );
+    }
+    apic::acknowledge_unhandled_interrupt(vector);
+}
+
+extern "cdecl" fn page_fault_handler(state: &mut InterruptAllSavedState) {
+    // expected fault from the user access probe
+    if super::user_access::handle_probe_fault(&mut state.frame) {
+        return;
+    }
+    tracepoints::record(
+        Event::PageFault,
+        unsafe { super::get_cr2() },
+        state.error as u32,
+    );
+    // writing to a present page, could be a copy-on-write page, either from the user
+    // or from the kernel writing to user memory
+    if state.error & (page_fault_error::PRESENT | page_fault_error::WRITE)
+        == page_fault_error::PRESENT | page_fault_error::WRITE
+    {
+        let addr = unsafe { super::get_cr2() } as usize;
+        if addr < virtual_memory_mapper::MAX_USER_VIRTUAL_ADDRESS
+            && virtual_memory_mapper::get_current_vm().handle_cow_fault(addr)
+        {
+            return;
+        }
+    }
+    stats::record(14);
+    // kernel stack overflow, we are running on a separate stack, so we can report it
+    if state.frame.cs & 0x3 == 0 {
+        let addr = unsafe { super::get_cr2() } as usize;
+        if let Some(stack) = memory_layout::stack_guard_containing(addr) {
+            kernel_stack_overflow(stack, &state.frame, state.rest.rbp);
+        }
+    }
+    handle_exception(state);
+}
+
+extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame64, error_code: u64) {
+    stats::record(8);
+    // a fault while pushing the frame of another exception, the stack could have overflowed
+    // (either the faulting address or the stack pointer is in a guard page)
+    if frame.cs & 0x3 == 0 {
+        let addr = unsafe { super::get_cr2() } as usize;
+        if let Some(stack) = memory_layout::stack_guard_containing(addr)
+            .or_else(|| memory_layout::stack_guard_containing(frame.rsp as usize))
+        {
+            kernel_stack_overflow(stack, &frame, super::rbp!());
+        }
+    }
+    unhandled_exception_with_error(8, &frame, error_code, super::rbp!());
+}
+
+fn kernel_stack_overflow(
+    stack: memory_layout::KernelStack,
+    frame: &InterruptStackFrame64,
+    rbp: u64,
+) -> ! {
+    let current_cpu = super::cpu();
+    let process = ProcessName(current_cpu.context.map(|_| current_cpu.process_id));
+    error!("Kernel stack overflow: {stack:?} stack, in {process}\n frame: {frame:x?}");
+
+    crate::panic_handler::print_kernel_stack_trace(frame.rip, frame.rsp, rbp);
+    panic!("Kernel stack overflow");
+}
+
+fn unhandled_exception_with_error(
+    n: u8,
+    frame: &InterruptStackFrame64,
+    error_code: u64,
+    rbp: u64,
+) -> ! {
+    let cr2 = unsafe { super::get_cr2() };
     let current_cpu = super::cpu();
-    let proc_id = current_cpu.context.map(|_| current_cpu.process_id);
+    let process = ProcessName(current_cpu.context.map(|_| current_cpu.process_id));
+    let name = exception_name(n).unwrap_or("unknown exception");
     error!(
-        "[{N}] {proc_id:?} Got exception: \n frame: {frame:x?}\n error: {error_code:016X}\n cr2: {cr2:X}",
+        "[{n}] {process} Got exception ({name}): \n frame: {frame:x?}\n error: {error_code:016X}\n cr2: {cr2:X}",
     );
 
-    crate::panic_handler::print_originating_stack_trace(&frame, super::rbp!());
+    crate::panic_handler::print_originating_stack_trace(frame, rbp);
     panic!("Unhandled exception");
 }
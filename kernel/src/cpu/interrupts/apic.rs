@@ -1,9 +1,16 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
 use alloc::vec::Vec;
 use tracing::{error, warn};
 
 use crate::{
     acpi::tables::{self, BiosTables, InterruptControllerStruct, InterruptSourceOverride},
-    cpu::{self, idt::InterruptStackFrame64, Cpu, CPUS, MAX_CPUS},
+    cpu::{
+        self,
+        idt::{InterruptStackFrame64, LogRateLimit},
+        Cpu, CPUS, MAX_CPUS,
+    },
+    init_stage::{self, InitStage},
     memory_management::virtual_space::VirtualSpace,
     sync::{once::OnceLock, spin::mutex::Mutex},
     utils::vcell::RW,
@@ -11,15 +18,18 @@ use crate::{
 
 use super::{
     allocate_basic_user_interrupt, allocate_user_interrupt, allocate_user_interrupt_all_saved,
-    InterruptHandler,
+    stats, InterruptHandler,
 };
 
 const APIC_BAR_ENABLED: u64 = 1 << 11;
 const APIC_BASE_MASK: u64 = 0xFFFF_FFFF_FFFF_F000;
 
 static APIC: OnceLock<Mutex<Apic>> = OnceLock::new();
+static SPURIOUS_VECTOR: AtomicU8 = AtomicU8::new(0);
+static SPURIOUS_LOG_LIMIT: LogRateLimit = LogRateLimit::new(10, 5);
 
 pub fn init(bios_tables: &BiosTables) {
+    init_stage::debug_assert_reached(InitStage::Interrupts);
     if APIC.try_get().is_some() {
         panic!("APIC already initialized");
     }
@@ -39,12 +49,35 @@ pub fn return_from_interrupt() {
     APIC.get().lock().return_from_interrupt();
 }
 
+/// Acknowledge `vector` that has no handler, only if it came from the `APIC` (it's in service),
+/// otherwise it's only counted in the [interrupt statistics](stats)
+pub fn acknowledge_unhandled_interrupt(vector: u8) {
+    match APIC.try_get() {
+        Some(apic) => apic.lock().acknowledge_unhandled_interrupt(vector),
+        None => stats::record(vector),
+    }
+}
+
 pub fn is_irq_assigned(irq_num: u8) -> bool {
     APIC.get().lock().is_irq_assigned(irq_num)
 }
 
-pub fn assign_io_irq<H: InterruptHandler>(handler: H, interrupt_num: u8, cpu: &Cpu) {
-    APIC.get().lock().assign_io_irq(handler, interrupt_num, cpu)
+/// Route the `IO APIC` interrupt `interrupt_num` to `handler` on `cpu`, `name` is shown in the
+/// [interrupt statistics](stats)
+pub fn assign_io_irq<H: InterruptHandler>(
+    handler: H,
+    interrupt_num: u8,
+    cpu: &Cpu,
+    name: &'static str,
+) {
+    APIC.get()
+        .lock()
+        .assign_io_irq(handler, interrupt_num, cpu, name)
+}
+
+/// Send an inter-processor interrupt with `vector` to the CPU with `apic_id`
+pub fn send_ipi(apic_id: u8, vector: u8) {
+    APIC.get().lock().send_ipi(apic_id, vector)
 }
 
 #[allow(dead_code)]
@@ -52,13 +85,14 @@ pub fn assign_io_irq_custom<H: InterruptHandler, F>(
     handler: H,
     interrupt_num: u8,
     cpu: &Cpu,
+    name: &'static str,
     modify_entry: F,
 ) where
     F: FnOnce(IoApicRedirectionBuilder) -> IoApicRedirectionBuilder,
 {
     APIC.get()
         .lock()
-        .assign_io_irq_custom(handler, interrupt_num, cpu, modify_entry)
+        .assign_io_irq_custom(handler, interrupt_num, cpu, name, modify_entry)
 }
 
 #[repr(C, align(4))]
@@ -103,6 +137,10 @@ const LVT_TIMER_MODE_MASK: u32 = 1 << 17;
 
 const SPURIOUS_ENABLE: u32 = 1 << 8;
 
+const ICR_DELIVERY_STATUS_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_DESTINATION_SHIFT: u32 = 24;
+
 #[derive(Default, Clone, Copy)]
 struct LocalVectorRegisterBuilder {
     reg: u32,
@@ -459,12 +497,48 @@ impl Apic {
         s
     }
 
+    /// The highest priority vector being serviced, the one that the next `EOI` completes
+    fn highest_in_service(&self) -> Option<u8> {
+        (0..8).rev().find_map(|i| {
+            let bits = self.mmio.in_service[i].read();
+            (bits != 0).then(|| (i * 32 + 31 - bits.leading_zeros() as usize) as u8)
+        })
+    }
+
     fn return_from_interrupt(&mut self) {
+        if let Some(vector) = self.highest_in_service() {
+            stats::record(vector);
+        }
         self.mmio.end_of_interrupt.write(0);
     }
 
+    fn acknowledge_unhandled_interrupt(&mut self, vector: u8) {
+        // if it was sent with `int`, the `EOI` would complete another interrupt
+        if self.highest_in_service() == Some(vector) {
+            self.return_from_interrupt();
+        } else {
+            stats::record(vector);
+        }
+    }
+
+    fn send_ipi(&mut self, apic_id: u8, vector: u8) {
+        self.mmio
+            .interrupt_command_high
+            .write((apic_id as u32) << ICR_DESTINATION_SHIFT);
+        // fixed delivery mode, physical destination, edge triggered
+        // writing the low part sends the interrupt
+        self.mmio
+            .interrupt_command_low
+            .write(ICR_LEVEL_ASSERT | vector as u32);
+        while self.mmio.interrupt_command_low.read() & ICR_DELIVERY_STATUS_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
     fn initialize_spurious_interrupt(&mut self) {
         let interrupt_num = allocate_basic_user_interrupt(spurious_handler);
+        SPURIOUS_VECTOR.store(interrupt_num, Ordering::Relaxed);
+        stats::register(interrupt_num, "spurious", None);
         // 1 << 8, to enable spurious interrupts
         self.mmio
             .spurious_interrupt_vector
@@ -480,6 +554,7 @@ impl Apic {
 
     fn initialize_timer(&mut self) {
         let interrupt_num = allocate_user_interrupt_all_saved(super::handlers::apic_timer_handler);
+        stats::register(interrupt_num, "APIC timer", None);
 
         // divide by 1
         self.mmio.timer_divide_configuration.write(0b1011);
@@ -502,6 +577,7 @@ impl Apic {
         self.mmio.error_status.write(0);
 
         let interrupt_num = allocate_basic_user_interrupt(error_interrupt_handler);
+        stats::register(interrupt_num, "APIC error", None);
         // not masked, and with the allocated vector number
         let vector_table = LocalVectorRegisterBuilder::default()
             .with_mask(false)
@@ -541,8 +617,14 @@ impl Apic {
         io_apic.is_entry_taken(entry_in_ioapic)
     }
 
-    fn assign_io_irq<H: InterruptHandler>(&mut self, handler: H, irq_num: u8, cpu: &Cpu) {
-        self.assign_io_irq_custom(handler, irq_num, cpu, |b| b)
+    fn assign_io_irq<H: InterruptHandler>(
+        &mut self,
+        handler: H,
+        irq_num: u8,
+        cpu: &Cpu,
+        name: &'static str,
+    ) {
+        self.assign_io_irq_custom(handler, irq_num, cpu, name, |b| b)
     }
 
     fn assign_io_irq_custom<H: InterruptHandler, F>(
@@ -550,6 +632,7 @@ impl Apic {
         handler: H,
         irq_num: u8,
         cpu: &Cpu,
+        name: &'static str,
         modify_entry: F,
     ) where
         F: FnOnce(IoApicRedirectionBuilder) -> IoApicRedirectionBuilder,
@@ -617,6 +700,7 @@ impl Apic {
         };
 
         let vector_num = allocate_user_interrupt(handler);
+        stats::register(vector_num, name, Some(irq_num));
         let b = IoApicRedirectionBuilder::default()
             .with_vector(vector_num)
             .with_delivery_mode(0) // fixed
@@ -632,8 +716,12 @@ impl Apic {
 }
 
 extern "x86-interrupt" fn spurious_handler(_frame: InterruptStackFrame64) {
-    warn!("Spurious interrupt");
-    return_from_interrupt();
+    if SPURIOUS_LOG_LIMIT.check("spurious interrupts") {
+        warn!("Spurious interrupt");
+    }
+    // spurious interrupts are not in service, so they must not be acknowledged,
+    // otherwise we would complete another interrupt
+    stats::record(SPURIOUS_VECTOR.load(Ordering::Relaxed));
 }
 
 extern "x86-interrupt" fn error_interrupt_handler(_frame: InterruptStackFrame64) {
@@ -0,0 +1,133 @@
+//! Exception handlers used from the start of `kernel_main` until [`init_interrupts`] is called.
+//!
+//! Without them, any fault before the console is ready would end up as a triple fault and a silent
+//! reboot. They don't depend on anything being initialized, and dump the faulting state directly to
+//! `COM1`, then halt.
+//!
+//! [`init_interrupts`]: super::init_interrupts
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    cpu::{
+        self,
+        idt::{self, InterruptAllSavedState, InterruptDescriptorTable},
+    },
+    io::uart::{Uart, UartPort},
+    sync::once::OnceLock,
+};
+
+/// The number of `u64`s dumped from the faulting stack
+const STACK_DUMP_LEN: usize = 16;
+
+static mut EARLY_IDT: InterruptDescriptorTable = InterruptDescriptorTable::empty();
+static SERIAL: OnceLock<Uart> = OnceLock::new();
+/// Set when we start reporting an exception, so that a fault while reporting (i.e. from a bad
+/// `rsp`) doesn't loop
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+struct SerialWriter<'a>(&'a Uart);
+
+impl Write for SerialWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // SAFETY: the uart is initialized before the handlers are installed
+            unsafe { self.0.write_byte(byte) };
+        }
+        Ok(())
+    }
+}
+
+/// Setup `COM1` and install the early exception handlers, must be called before anything else in
+/// `kernel_main`
+pub fn init_early_exceptions() {
+    SERIAL.get_or_init(|| {
+        let mut uart = Uart::new(UartPort::COM1);
+        uart.init_early();
+        uart
+    });
+
+    // SAFETY: this is only called at the start of boot, when nothing else is running
+    let idt = unsafe { &mut EARLY_IDT };
+    idt.init_early_handlers(early_exception_handler);
+    idt.apply_idt();
+}
+
+extern "cdecl" fn early_exception_handler(state: &mut InterruptAllSavedState) {
+    let mut out = SerialWriter(SERIAL.get());
+    if REPORTING.swap(true, Ordering::AcqRel) {
+        writeln!(
+            out,
+            "\nEarly exception {} while reporting another one",
+            state.number
+        )
+        .ok();
+    } else {
+        report(&mut out, state).ok();
+    }
+
+    loop {
+        unsafe {
+            cpu::clear_interrupts();
+            cpu::halt();
+        }
+    }
+}
+
+fn report(out: &mut SerialWriter, state: &InterruptAllSavedState) -> fmt::Result {
+    let vector = state.number as u8;
+    let frame = &state.frame;
+    let regs = &state.rest;
+
+    writeln!(
+        out,
+        "\nEarly exception {vector} ({}), error: {:#x}",
+        idt::exception_name(vector).unwrap_or("reserved"),
+        state.error
+    )?;
+    writeln!(
+        out,
+        "rip: {:016x}  cs: {:#04x}  rflags: {:016x}",
+        frame.rip, frame.cs, frame.rflags
+    )?;
+    writeln!(out, "rsp: {:016x}  ss: {:#04x}", frame.rsp, frame.ss)?;
+    writeln!(
+        out,
+        "rax: {:016x}  rbx: {:016x}  rcx: {:016x}",
+        regs.rax, regs.rbx, regs.rcx
+    )?;
+    writeln!(
+        out,
+        "rdx: {:016x}  rsi: {:016x}  rdi: {:016x}",
+        regs.rdx, regs.rsi, regs.rdi
+    )?;
+    writeln!(
+        out,
+        "rbp: {:016x}  r8:  {:016x}  r9:  {:016x}",
+        regs.rbp, regs.r8, regs.r9
+    )?;
+    writeln!(
+        out,
+        "r10: {:016x}  r11: {:016x}  r12: {:016x}",
+        regs.r10, regs.r11, regs.r12
+    )?;
+    writeln!(
+        out,
+        "r13: {:016x}  r14: {:016x}  r15: {:016x}",
+        regs.r13, regs.r14, regs.r15
+    )?;
+    let (cr2, cr3) = unsafe { (cpu::get_cr2(), cpu::get_cr3()) };
+    writeln!(out, "cr2: {cr2:016x}  cr3: {cr3:016x}")?;
+
+    writeln!(out, "stack:")?;
+    let stack = frame.rsp as *const u64;
+    for i in 0..STACK_DUMP_LEN {
+        // SAFETY: not safe if `rsp` is bad, but then the fault is caught by `REPORTING`
+        let (addr, value) = unsafe { (stack.add(i), stack.add(i).read_volatile()) };
+        writeln!(out, "  {:016x}: {value:016x}", addr as u64)?;
+    }
+    Ok(())
+}
@@ -2,7 +2,8 @@
 
 use crate::{
     cpu::idt::InterruptAllSavedState,
-    devices::{clock, keyboard_mouse},
+    devices::{clock, keyboard_mouse, watchdog},
+    gdb_stub,
     io::console,
     process::scheduler,
 };
@@ -10,12 +11,22 @@ use crate::{
 use super::apic;
 
 pub extern "cdecl" fn apic_timer_handler(all_state: &mut InterruptAllSavedState) {
-    // make sure its initialized
-    clock::clocks().tick_system_time();
+    // the timer may fire before the clocks are initialized
+    if let Some(clocks) = clock::try_clocks() {
+        clocks.tick_system_time();
+    }
     // flush log file if needed
     console::tracing::flush_log_file();
     // trigger poll if there is any events
     keyboard_mouse::poll_events();
+    // switch virtual terminals if requested by the keyboard
+    console::handle_terminal_requests();
+    // give the input to the terminals, and wake their readers
+    console::receive_input();
+    // reboot if the watchdog is not petted in time
+    watchdog::check();
+    // enter the debugger if GDB requested it
+    gdb_stub::poll(all_state);
 
     scheduler::yield_current_if_any(all_state);
     apic::return_from_interrupt();
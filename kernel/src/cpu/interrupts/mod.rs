@@ -1,5 +1,9 @@
 pub mod apic;
+mod early;
 mod handlers;
+pub mod stats;
+
+pub use early::init_early_exceptions;
 
 use crate::sync::{once::OnceLock, spin::mutex::Mutex};
 
@@ -127,6 +131,18 @@ pub fn create_scheduler_interrupt(handler: InterruptHandlerWithAllState) {
         .set_disable_interrupts(true);
 }
 
+/// Replace the handlers of the debug and breakpoint exceptions, used by the GDB stub
+pub fn create_debug_interrupts(handler: InterruptHandlerWithAllState) {
+    let mut interrupts = INTERRUPTS.get().lock();
+    interrupts.idt.debug.set_handler_with_number(handler, 1);
+    // allow `int3` from user processes
+    interrupts
+        .idt
+        .breakpoint
+        .set_handler_with_number(handler, 3)
+        .set_privilege_level(USER_RING);
+}
+
 pub fn create_syscall_interrupt(handler: InterruptHandlerWithAllState) {
     let mut interrupts = INTERRUPTS.get().lock();
     interrupts.idt.user_defined[SPECIAL_SYSCALL_INTERRUPT as usize]
@@ -0,0 +1,78 @@
+//! Interrupt statistics, the number of times each vector was received, reported in `/proc/interrupts`
+//!
+//! The interrupts from the `APIC` are counted when they are acknowledged (see [`apic::return_from_interrupt`]),
+//! using the highest in-service vector, which is the one the `EOI` completes.
+//!
+//! [`apic::return_from_interrupt`]: super::apic::return_from_interrupt
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::string::String;
+
+use crate::{sync::spin::mutex::Mutex, testing};
+
+#[derive(Debug, Clone, Copy)]
+struct VectorInfo {
+    name: &'static str,
+    /// The `IO APIC` interrupt it's routed from, if any
+    irq: Option<u8>,
+}
+
+// only used to initialize `COUNTS`
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_COUNT: AtomicU64 = AtomicU64::new(0);
+
+static COUNTS: [AtomicU64; 256] = [ZERO_COUNT; 256];
+static VECTORS: Mutex<[Option<VectorInfo>; 256]> = Mutex::new([None; 256]);
+
+/// Name the `vector`, so it shows in the statistics even before it's received
+pub fn register(vector: u8, name: &'static str, irq: Option<u8>) {
+    VECTORS.lock()[vector as usize] = Some(VectorInfo { name, irq });
+}
+
+/// Count one interrupt of `vector`, this is called from interrupt handlers, so it must not wait
+pub fn record(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of times `vector` was received
+pub fn count(vector: u8) -> u64 {
+    COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// A line for each registered vector, and each vector that was received without being registered
+pub fn report() -> String {
+    let vectors = *VECTORS.lock();
+    let mut content = String::from("Vector\tCount\tIRQ\tName\n");
+    for (vector, info) in vectors.iter().enumerate() {
+        let count = count(vector as u8);
+        if info.is_none() && count == 0 {
+            continue;
+        }
+        let (name, irq) = info.map_or(("unknown", None), |info| (info.name, info.irq));
+        let _ = write!(content, "{vector:#04x}\t{count}\t");
+        match irq {
+            Some(irq) => {
+                let _ = write!(content, "{irq}");
+            }
+            None => content.push('-'),
+        }
+        let _ = writeln!(content, "\t{name}");
+    }
+    content
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_report() {
+    register(0xF0, "test", Some(3));
+    record(0xF0);
+    record(0xF0);
+    record(0xF1);
+
+    let report = report();
+    assert!(report.contains("0xf0\t2\t3\ttest\n"), "{report}");
+    assert!(report.contains("0xf1\t1\t-\tunknown\n"), "{report}");
+}
@@ -1,4 +1,4 @@
-use crate::process::ProcessContext;
+use crate::{devices::clock::ClockTime, process::ProcessContext};
 
 use self::{
     gdt::{GlobalDescriptorTablePointer, SegmentSelector},
@@ -8,13 +8,25 @@ use self::{
 pub mod gdt;
 pub mod idt;
 pub mod interrupts;
+pub mod tlb;
+pub mod user_access;
 
-const MAX_CPUS: usize = 8;
+pub(crate) const MAX_CPUS: usize = 8;
 
 pub mod flags {
     pub const IF: u64 = 1 << 9;
 }
 
+pub mod cr0 {
+    /// Write protect, when set, the kernel can't write to read-only pages
+    pub const WP: u64 = 1 << 16;
+}
+
+pub mod cr4 {
+    pub const SMEP: u64 = 1 << 20;
+    pub const SMAP: u64 = 1 << 21;
+}
+
 #[allow(dead_code)]
 pub mod msr {
     pub const APIC_BASE: u32 = 0x1b;
@@ -35,11 +47,18 @@ pub mod msr {
 
 #[allow(dead_code)]
 pub mod cpuid {
+    pub const FN_MAX: u32 = 0;
     pub const FN_FEAT: u32 = 1;
+    pub const FN_EXT_FEAT: u32 = 7;
 
     pub const FEAT_EDX_TSC: u32 = 1 << 4;
     pub const FEAT_EDX_APIC: u32 = 1 << 9;
 
+    pub const FEAT_ECX_RDRAND: u32 = 1 << 30;
+
+    pub const EXT_FEAT_EBX_SMEP: u32 = 1 << 7;
+    pub const EXT_FEAT_EBX_SMAP: u32 = 1 << 20;
+
     #[macro_export]
     macro_rules! cpuid {
         ($rax:expr) => {
@@ -73,6 +92,10 @@ pub struct Cpu {
     // the process id of the current process
     pub process_id: u64,
     pub scheduling: bool,
+    // number of nested `UserAccessGuard`s, the kernel can access user memory if this is not 0
+    user_access_depth: usize,
+    // time spent in the idle task of the scheduler, waiting for interrupts
+    pub idle_time: ClockTime,
 }
 
 impl Cpu {
@@ -85,6 +108,11 @@ impl Cpu {
             context: None,
             process_id: 0,
             scheduling: false,
+            user_access_depth: 0,
+            idle_time: ClockTime {
+                nanoseconds: 0,
+                seconds: 0,
+            },
         }
     }
 
@@ -132,6 +160,15 @@ pub fn cpu() -> &'static mut Cpu {
     unsafe { &mut CPUS[0] }
 }
 
+/// The idle time of each online CPU, by its id
+pub fn idle_times() -> impl Iterator<Item = (usize, ClockTime)> {
+    let online = tlb::online_cpus();
+    (0..MAX_CPUS)
+        .filter(move |i| online & (1 << i) != 0)
+        // SAFETY: only read, and it doesn't matter if we get a slightly old value
+        .map(|i| (i, unsafe { CPUS[i].idle_time }))
+}
+
 pub unsafe fn rflags() -> u64 {
     let rflags: u64;
     core::arch::asm!("pushfq; pop {0:r}", out(reg) rflags, options(nostack, preserves_flags));
@@ -216,18 +253,22 @@ pub unsafe fn set_interrupts() {
     core::arch::asm!("sti", options(nomem, nostack, preserves_flags));
 }
 
-#[allow(dead_code)]
 pub unsafe fn get_cr0() -> u64 {
     let cr0: u64;
     core::arch::asm!("mov {0:r}, cr0", out(reg) cr0, options(readonly, nostack, preserves_flags));
     cr0
 }
 
-#[allow(dead_code)]
 pub unsafe fn set_cr0(cr0: u64) {
     core::arch::asm!("mov cr0, rax", in("rax") cr0, options(nomem, nostack, preserves_flags));
 }
 
+pub unsafe fn get_cr2() -> u64 {
+    let cr2: u64;
+    core::arch::asm!("mov {0:r}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+    cr2
+}
+
 pub unsafe fn set_cr3(cr3: u64) {
     core::arch::asm!("mov cr3, rax", in("rax") cr3, options(nomem, nostack, preserves_flags));
 }
@@ -238,14 +279,12 @@ pub unsafe fn get_cr3() -> u64 {
     cr3
 }
 
-#[allow(dead_code)]
 pub unsafe fn get_cr4() -> u64 {
     let cr4: u64;
     core::arch::asm!("mov {0:r}, cr4", out(reg) cr4, options(readonly, nostack, preserves_flags));
     cr4
 }
 
-#[allow(dead_code)]
 pub unsafe fn set_cr4(cr4: u64) {
     core::arch::asm!("mov cr4, rax", in("rax") cr4, options(nomem, nostack, preserves_flags));
 }
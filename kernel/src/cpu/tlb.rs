@@ -0,0 +1,215 @@
+//! TLB (Translation Lookaside Buffer) invalidation
+//!
+//! After modifying (or removing) a present page table entry, the old translation must be removed
+//! from the TLB of every CPU that could have cached it. All invalidations must go through here
+//! instead of using `invlpg` directly.
+//!
+//! The protocol is ready for SMP (even though we only run on one CPU for now):
+//! - Each CPU has a queue of pending invalidations.
+//! - The initiator flushes locally, pushes the invalidations to the queue of every other online CPU,
+//!   sends them an IPI, and waits until all of them acknowledge.
+//! - The IPI handler (or a CPU waiting for its own shootdown) drains its queue.
+//!
+//! Since we don't track which CPUs are using which address space, we send to all online CPUs.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use crate::sync::spin::mutex::Mutex;
+
+use super::{
+    idt::InterruptStackFrame64,
+    interrupts::{self, apic},
+    CPUS, MAX_CPUS,
+};
+
+/// Maximum number of pages to invalidate one by one, after this we flush the whole TLB
+const QUEUE_CAPACITY: usize = 32;
+
+static QUEUES: Mutex<[InvalidationQueue; MAX_CPUS]> =
+    Mutex::new([InvalidationQueue::new(); MAX_CPUS]);
+/// Bitmask of CPUs that are running, and thus could have TLB entries cached
+static ONLINE_CPUS: AtomicU8 = AtomicU8::new(0);
+/// Number of CPUs that haven't acknowledged the current shootdown yet
+static PENDING_ACKS: AtomicUsize = AtomicUsize::new(0);
+/// `0` until [`init`] is called, before that, we only flush locally
+static SHOOTDOWN_VECTOR: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct InvalidationQueue {
+    pages: [usize; QUEUE_CAPACITY],
+    len: usize,
+    flush_all: bool,
+    /// Number of shootdowns merged into this queue, each of them must be acknowledged
+    requests: usize,
+}
+
+impl InvalidationQueue {
+    const fn new() -> Self {
+        Self {
+            pages: [0; QUEUE_CAPACITY],
+            len: 0,
+            flush_all: false,
+            requests: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0 && !self.flush_all
+    }
+
+    fn push_page(&mut self, addr: usize) {
+        if self.flush_all {
+            return;
+        }
+        if self.len == QUEUE_CAPACITY {
+            self.flush_all = true;
+            self.len = 0;
+            return;
+        }
+        self.pages[self.len] = addr;
+        self.len += 1;
+    }
+
+    fn merge(&mut self, other: &InvalidationQueue) {
+        self.requests += 1;
+        if other.flush_all {
+            self.flush_all = true;
+            self.len = 0;
+            return;
+        }
+        for &addr in &other.pages[..other.len] {
+            self.push_page(addr);
+        }
+    }
+
+    fn apply_local(&self) {
+        if self.flush_all {
+            // we don't use global pages, so reloading `cr3` flushes everything
+            // SAFETY: we are setting the same value back
+            unsafe { super::set_cr3(super::get_cr3()) };
+        } else {
+            for &addr in &self.pages[..self.len] {
+                // SAFETY: invalidating is always safe
+                unsafe { super::invalidate_tlp(addr as _) };
+            }
+        }
+    }
+}
+
+/// A batch of TLB invalidations, applied (and shot down to other CPUs) when dropped
+#[derive(Default)]
+pub struct TlbFlush {
+    queue: InvalidationQueue,
+}
+
+impl TlbFlush {
+    pub const fn new() -> Self {
+        Self {
+            queue: InvalidationQueue::new(),
+        }
+    }
+
+    /// Invalidate the page containing `addr`, for 2MB pages any address inside the page works
+    pub fn add_page(&mut self, addr: usize) {
+        self.queue.push_page(addr);
+    }
+
+    /// Flush the whole TLB instead
+    #[allow(dead_code)]
+    pub fn add_all(&mut self) {
+        self.queue.flush_all = true;
+        self.queue.len = 0;
+    }
+}
+
+impl Drop for TlbFlush {
+    fn drop(&mut self) {
+        if !self.queue.is_empty() {
+            shootdown(&self.queue);
+        }
+    }
+}
+
+/// Invalidate the page containing `addr` in all CPUs
+pub fn flush_page(addr: usize) {
+    let mut flush = TlbFlush::new();
+    flush.add_page(addr);
+}
+
+/// Drain the queue of the current CPU, and acknowledge the shootdowns we got
+fn process_pending() {
+    let me = super::cpu().id;
+    let queue = {
+        let mut queues = QUEUES.lock();
+        core::mem::replace(&mut queues[me], InvalidationQueue::new())
+    };
+    if queue.requests == 0 {
+        return;
+    }
+    queue.apply_local();
+    PENDING_ACKS.fetch_sub(queue.requests, Ordering::AcqRel);
+}
+
+fn shootdown(queue: &InvalidationQueue) {
+    queue.apply_local();
+
+    let vector = SHOOTDOWN_VECTOR.load(Ordering::Acquire);
+    if vector == 0 {
+        return;
+    }
+    let me = super::cpu().id;
+    let targets = ONLINE_CPUS.load(Ordering::Acquire) & !(1 << me);
+    if targets == 0 {
+        return;
+    }
+
+    {
+        let mut queues = QUEUES.lock();
+        for (i, target_queue) in queues.iter_mut().enumerate() {
+            if targets & (1 << i) != 0 {
+                target_queue.merge(queue);
+            }
+        }
+    }
+    PENDING_ACKS.fetch_add(targets.count_ones() as usize, Ordering::AcqRel);
+    for i in (0..MAX_CPUS).filter(|i| targets & (1 << i) != 0) {
+        // SAFETY: `apic_id` is only written on initialization
+        let apic_id = unsafe { CPUS[i].apic_id };
+        apic::send_ipi(apic_id, vector);
+    }
+
+    // another CPU could be waiting for us to process its shootdown, while we are waiting for it
+    // (with interrupts disabled), so keep draining our queue while waiting
+    while PENDING_ACKS.load(Ordering::Acquire) != 0 {
+        process_pending();
+        core::hint::spin_loop();
+    }
+}
+
+extern "x86-interrupt" fn shootdown_handler(_frame: InterruptStackFrame64) {
+    process_pending();
+    apic::return_from_interrupt();
+}
+
+/// Mark the current CPU as online, i.e. it will receive shootdowns from other CPUs
+pub fn mark_cpu_online() {
+    ONLINE_CPUS.fetch_or(1 << super::cpu().id, Ordering::AcqRel);
+}
+
+/// Bitmask of the online CPUs
+pub fn online_cpus() -> u64 {
+    ONLINE_CPUS.load(Ordering::Acquire) as u64
+}
+
+/// Must be called after the APIC is initialized
+pub fn init() {
+    assert_eq!(
+        SHOOTDOWN_VECTOR.load(Ordering::Acquire),
+        0,
+        "TLB shootdown already initialized"
+    );
+    let vector = interrupts::allocate_basic_user_interrupt(shootdown_handler);
+    interrupts::stats::register(vector, "TLB shootdown", None);
+    SHOOTDOWN_VECTOR.store(vector, Ordering::Release);
+    mark_cpu_online();
+}
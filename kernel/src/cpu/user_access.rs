@@ -0,0 +1,229 @@
+//! Supervisor Mode Execution/Access Prevention (`SMEP`/`SMAP`)
+//!
+//! With `SMEP`, the kernel can't execute code from user pages, and with `SMAP` it can't
+//! access user pages at all, unless the access is explicitly allowed by setting `RFLAGS.AC`
+//! with `stac` (and disallowed again with `clac`).
+//!
+//! Any code that needs to access user memory must hold a [`UserAccessGuard`] (from [`allow_user_access`]),
+//! or use [`copy_from_user`]/[`copy_to_user`].
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::{info, warn};
+
+use crate::memory_management::{
+    memory_layout::PAGE_4K,
+    virtual_memory_mapper::{self, VirtualMemoryMapEntry},
+};
+
+use super::{cpuid, idt::InterruptStackFrame64};
+
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// returns `0` if the byte at `rdi` was read successfully, `1` if it faulted.
+// The page fault handler will move execution to `user_access_probe_fixup` if the fault
+// happened at `user_access_probe_instruction`.
+core::arch::global_asm!(
+    ".global user_access_probe_read",
+    "user_access_probe_read:",
+    "    xor eax, eax",
+    ".global user_access_probe_instruction",
+    "user_access_probe_instruction:",
+    "    mov cl, byte ptr [rdi]",
+    "    ret",
+    ".global user_access_probe_fixup",
+    "user_access_probe_fixup:",
+    "    mov eax, 1",
+    "    ret",
+);
+
+extern "C" {
+    fn user_access_probe_read(ptr: *const u8) -> u64;
+    fn user_access_probe_instruction();
+    fn user_access_probe_fixup();
+}
+
+#[inline(always)]
+unsafe fn stac() {
+    core::arch::asm!("stac", options(nomem, nostack));
+}
+
+#[inline(always)]
+unsafe fn clac() {
+    core::arch::asm!("clac", options(nomem, nostack));
+}
+
+pub fn is_smap_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Allows the kernel to access user memory while this is alive.
+///
+/// Can be nested, and user access will be disallowed when the outermost guard is dropped.
+/// Interrupts must be disabled while holding this, so that we don't switch to another context
+/// while user access is allowed.
+pub struct UserAccessGuard {
+    // not `Send`, as it relates to the current cpu
+    _not_send: core::marker::PhantomData<*const ()>,
+}
+
+/// Allow the kernel to access user memory until the returned guard is dropped
+#[must_use]
+pub fn allow_user_access() -> UserAccessGuard {
+    let cpu = super::cpu();
+    if cpu.user_access_depth == 0 && is_smap_enabled() {
+        // SAFETY: SMAP is enabled, so `stac` is supported
+        unsafe { stac() };
+    }
+    cpu.user_access_depth += 1;
+
+    UserAccessGuard {
+        _not_send: core::marker::PhantomData,
+    }
+}
+
+impl Drop for UserAccessGuard {
+    fn drop(&mut self) {
+        let cpu = super::cpu();
+        assert!(cpu.user_access_depth > 0);
+        cpu.user_access_depth -= 1;
+        if cpu.user_access_depth == 0 && is_smap_enabled() {
+            // SAFETY: SMAP is enabled, so `clac` is supported
+            unsafe { clac() };
+        }
+    }
+}
+
+/// Disallow user access when entering the kernel from user mode.
+///
+/// The CPU doesn't clear `RFLAGS.AC` on interrupts, and user mode can set it with `popf`,
+/// which would leave `SMAP` off in the kernel until the first [`UserAccessGuard`] is dropped.
+pub(super) fn on_entry_from_user() {
+    if is_smap_enabled() {
+        debug_assert_eq!(super::cpu().user_access_depth, 0);
+        // SAFETY: SMAP is enabled, so `clac` is supported
+        unsafe { clac() };
+    }
+}
+
+/// Copy `dst.len()` bytes from user memory at `src`
+///
+/// # Safety
+/// `src` must be a valid user pointer for `dst.len()` bytes in the current vm
+pub unsafe fn copy_from_user(dst: &mut [u8], src: *const u8) {
+    let _guard = allow_user_access();
+    core::ptr::copy_nonoverlapping(src, dst.as_mut_ptr(), dst.len());
+}
+
+/// Copy `src` into user memory at `dst`
+///
+/// # Safety
+/// `dst` must be a valid user pointer for `src.len()` bytes in the current vm
+pub unsafe fn copy_to_user(dst: *mut u8, src: &[u8]) {
+    let _guard = allow_user_access();
+    core::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+}
+
+/// Called by the page fault handler, if the fault is expected (from the probe),
+/// it will modify the `rip` to continue from the fixup location and return `true`
+pub(super) fn handle_probe_fault(frame: &mut InterruptStackFrame64) -> bool {
+    if frame.rip != user_access_probe_instruction as usize as u64 {
+        return false;
+    }
+    // SAFETY: this is the frame pushed by the CPU, and will be used by `iretq`,
+    //         use volatile so that its not optimized away
+    unsafe { core::ptr::write_volatile(&mut frame.rip, user_access_probe_fixup as usize as u64) };
+    true
+}
+
+pub fn init() {
+    let max_fn = unsafe { cpuid::cpuid!(cpuid::FN_MAX).eax };
+    if max_fn < cpuid::FN_EXT_FEAT {
+        warn!("SMEP/SMAP not supported");
+        return;
+    }
+    let ext_features = unsafe { cpuid::cpuid!(cpuid::FN_EXT_FEAT, 0).ebx };
+
+    let mut cr4 = unsafe { super::get_cr4() };
+    if ext_features & cpuid::EXT_FEAT_EBX_SMEP != 0 {
+        cr4 |= super::cr4::SMEP;
+        info!("Enabling SMEP");
+    } else {
+        warn!("SMEP not supported");
+    }
+    let smap = ext_features & cpuid::EXT_FEAT_EBX_SMAP != 0;
+    if smap {
+        cr4 |= super::cr4::SMAP;
+        info!("Enabling SMAP");
+    } else {
+        warn!("SMAP not supported");
+    }
+    // SAFETY: we only set bits that are supported, and we don't access user memory yet
+    unsafe {
+        // make sure we start with user access disabled
+        if smap {
+            clac();
+        }
+        super::set_cr4(cr4);
+    }
+    SMAP_ENABLED.store(smap, Ordering::Relaxed);
+
+    self_test();
+}
+
+/// Make sure that accessing user memory without [`UserAccessGuard`] faults, and that it
+/// works with it and with [`copy_from_user`]/[`copy_to_user`].
+fn self_test() {
+    if !is_smap_enabled() {
+        return;
+    }
+    // any user address would work
+    const TEST_ADDRESS: usize = 0x10000;
+
+    let mut vm = virtual_memory_mapper::clone_current_vm_as_user();
+    vm.map(&VirtualMemoryMapEntry {
+        virtual_address: TEST_ADDRESS,
+        physical_address: None,
+        size: PAGE_4K,
+        flags: virtual_memory_mapper::flags::PTE_USER | virtual_memory_mapper::flags::PTE_WRITABLE,
+    });
+    // SAFETY: we are in boot, the process specific kernel regions are not used
+    unsafe { vm.add_process_specific_mappings() };
+
+    super::cpu().push_cli();
+    let old_vm = virtual_memory_mapper::get_current_vm();
+    // SAFETY: this vm is a clone of the current kernel vm, and we are not using
+    //         any process specific kernel regions
+    unsafe { vm.switch_to_this() };
+
+    let faulted_without_guard = unsafe { user_access_probe_read(TEST_ADDRESS as _) } != 0;
+    let faulted_with_guard = {
+        let _guard = allow_user_access();
+        unsafe { user_access_probe_read(TEST_ADDRESS as _) != 0 }
+    };
+    let written = *b"SMAP self-test";
+    let mut read = [0; 14];
+    // SAFETY: the page is mapped and writable, and the copies don't fault with `SMAP`
+    unsafe {
+        copy_to_user(TEST_ADDRESS as _, &written);
+        copy_from_user(&mut read, TEST_ADDRESS as _);
+    }
+
+    unsafe { old_vm.switch_to_this() };
+    super::cpu().pop_cli();
+    vm.unmap_process_memory();
+
+    assert!(
+        faulted_without_guard,
+        "SMAP self-test: accessing user memory without a guard didn't fault"
+    );
+    assert!(
+        !faulted_with_guard,
+        "SMAP self-test: accessing user memory with a guard faulted"
+    );
+    assert_eq!(
+        read, written,
+        "SMAP self-test: reading back user memory didn't match what was written"
+    );
+    info!("SMAP self-test passed");
+}
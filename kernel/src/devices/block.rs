@@ -0,0 +1,176 @@
+//! Raw block devices
+//!
+//! The ATA disks are available as `/devices/disk<N>`, and their MBR partitions as `/devices/disk<N>p<M>`
+//! (`M` starting from `1`). They can be read and written at any offset and with any size, partial sectors
+//! are read first and written back (read-modify-write).
+//!
+//! The writes go directly to the disk, the cache of a filesystem mounted from the same disk is not updated.
+//! The shared file pages are dropped on every write, so new mappings read the new content.
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use tracing::warn;
+
+use crate::{
+    fs::{mbr::Mbr, FileSystemError},
+    memory_management::shared_pages,
+};
+
+use super::{
+    ide::{self, IdeDevice, IdeDeviceIndex, IdeDeviceType},
+    register_device, Device,
+};
+
+#[derive(Debug)]
+pub struct BlockDevice {
+    name: String,
+    disk: Arc<IdeDevice>,
+    /// The first sector of this device in the disk
+    start_sector: u64,
+    number_of_sectors: u64,
+}
+
+impl BlockDevice {
+    fn sector_size(&self) -> u64 {
+        self.disk.sector_size() as u64
+    }
+
+    /// Get the sectors covering `offset..offset + len` (must be inside the device),
+    /// as `(first_sector, buffer)` where the buffer is large enough to hold them
+    fn sectors_buffer(&self, offset: u64, len: u64) -> (u64, Vec<u8>) {
+        let sector_size = self.sector_size();
+        let first_sector = offset / sector_size;
+        let end_sector = (offset + len).div_ceil(sector_size);
+        let buffer = vec![0; ((end_sector - first_sector) * sector_size) as usize];
+        (first_sector, buffer)
+    }
+
+    fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<(), FileSystemError> {
+        let sector = self.start_sector + sector;
+        self.disk
+            .read_sync(sector, buf)
+            .map_err(|error| FileSystemError::DiskReadError { sector, error })
+    }
+
+    fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<(), FileSystemError> {
+        let sector = self.start_sector + sector;
+        self.disk
+            .write_sync(sector, buf)
+            .map_err(|error| FileSystemError::DiskReadError { sector, error })
+    }
+}
+
+impl Device for BlockDevice {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn size(&self) -> u64 {
+        self.number_of_sectors * self.sector_size()
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        if offset >= self.size() {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(self.size() - offset);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let (first_sector, mut sectors) = self.sectors_buffer(offset, len);
+        self.read_sectors(first_sector, &mut sectors)?;
+
+        let start = (offset - first_sector * self.sector_size()) as usize;
+        buf[..len as usize].copy_from_slice(&sectors[start..start + len as usize]);
+        Ok(len)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
+        if offset >= self.size() {
+            return Err(FileSystemError::EndOfFile);
+        }
+        let len = (buf.len() as u64).min(self.size() - offset);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let (first_sector, mut sectors) = self.sectors_buffer(offset, len);
+        let start = (offset - first_sector * self.sector_size()) as usize;
+        let end = start + len as usize;
+
+        // read the first and last sectors if they are not fully overwritten
+        let sector_size = self.sector_size() as usize;
+        if start != 0 {
+            self.read_sectors(first_sector, &mut sectors[..sector_size])?;
+        }
+        if end % sector_size != 0 {
+            let last = sectors.len() - sector_size;
+            let last_sector = first_sector + (last / sector_size) as u64;
+            self.read_sectors(last_sector, &mut sectors[last..])?;
+        }
+
+        sectors[start..end].copy_from_slice(&buf[..len as usize]);
+        self.write_sectors(first_sector, &sectors)?;
+        // the content of any file on this disk could have changed (i.e. `fsck` repairs)
+        shared_pages::invalidate_all();
+        Ok(len)
+    }
+
+    // needed for `echo`, as it truncates the file before writing, same as `power`
+    fn set_size(&self, size: u64) -> Result<(), FileSystemError> {
+        if size != 0 && size != self.size() {
+            return Err(FileSystemError::CouldNotSetFileLength);
+        }
+        Ok(())
+    }
+}
+
+/// Register all the ATA disks and their partitions as block devices
+pub fn register_block_devices() {
+    for index in 0.. {
+        let Some(disk) = ide::get_ide_device(IdeDeviceIndex {
+            ty: IdeDeviceType::Ata,
+            index,
+        }) else {
+            break;
+        };
+
+        let disk_name = format!("disk{index}");
+        let partitions = match Mbr::try_create_from_disk(&disk) {
+            Ok(mbr) => Some(mbr.partition_table),
+            Err(FileSystemError::PartitionTableNotFound) => None,
+            Err(e) => {
+                warn!("Could not read the partition table of {disk_name}: {e:?}");
+                None
+            }
+        };
+
+        for (i, partition) in partitions.iter().flatten().enumerate() {
+            let (start_lba, size_in_sectors) = (partition.start_lba, partition.size_in_sectors);
+            if partition.partition_type == 0 || size_in_sectors == 0 {
+                continue;
+            }
+            if start_lba as u64 + size_in_sectors as u64 > disk.number_of_sectors() {
+                warn!("Partition {} of {disk_name} is outside the disk", i + 1);
+                continue;
+            }
+            register_device(Arc::new(BlockDevice {
+                name: format!("{disk_name}p{}", i + 1),
+                disk: disk.clone(),
+                start_sector: start_lba as u64,
+                number_of_sectors: size_in_sectors as u64,
+            }));
+        }
+
+        register_device(Arc::new(BlockDevice {
+            name: disk_name,
+            number_of_sectors: disk.number_of_sectors(),
+            disk,
+            start_sector: 0,
+        }));
+    }
+}
@@ -238,6 +238,7 @@ impl Hpet {
             timer0_handler as InterruptHandlerWithAllState,
             chosen_route,
             cpu::cpu(),
+            "HPET timer0",
         );
 
         s.set_enabled(true);
@@ -333,15 +334,18 @@ impl ClockDevice for Mutex<Hpet> {
 }
 
 extern "cdecl" fn timer0_handler(_all_state: &mut InterruptAllSavedState) {
-    let mut clock = HPET_CLOCK.get().as_ref().lock();
-
-    // if we are level triggered, we must clear the interrupt bit
-    if clock.mmio.timers[0].config().is_interrupt_level_triggered {
-        if let Some(interrupt) = clock.status_interrupts_iter().next() {
-            // clear the interrupt (must for level triggered interrupts)
-            clock.ack_interrupt(interrupt);
-        } else {
-            warn!("Looks like we are getting PIT interrupt instead of HPET");
+    // the interrupt may fire before we finish initialization
+    if let Some(clock) = HPET_CLOCK.try_get() {
+        let mut clock = clock.as_ref().lock();
+
+        // if we are level triggered, we must clear the interrupt bit
+        if clock.mmio.timers[0].config().is_interrupt_level_triggered {
+            if let Some(interrupt) = clock.status_interrupts_iter().next() {
+                // clear the interrupt (must for level triggered interrupts)
+                clock.ack_interrupt(interrupt);
+            } else {
+                warn!("Looks like we are getting PIT interrupt instead of HPET");
+            }
         }
     }
 
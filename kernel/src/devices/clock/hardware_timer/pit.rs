@@ -132,6 +132,7 @@ impl Pit {
             pit_interrupt as BasicInterruptHandler,
             pit_io::DEFAULT_INTERRUPT,
             cpu::cpu(),
+            "PIT",
         );
 
         Pit {
@@ -212,7 +213,10 @@ impl ClockDevice for Pit {
 }
 
 extern "x86-interrupt" fn pit_interrupt(_stack_frame: InterruptStackFrame64) {
-    PIT_CLOCK.get().tick_total_counter();
+    // the interrupt may fire before we finish initialization
+    if let Some(pit) = PIT_CLOCK.try_get() {
+        pit.tick_total_counter();
+    }
 
     // nothing to do here really
     apic::return_from_interrupt();
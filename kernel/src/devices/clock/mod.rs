@@ -9,8 +9,13 @@ use tracing::info;
 
 use crate::{
     acpi::tables::{self, BiosTables, Facp},
-    cpu,
-    sync::{once::OnceLock, spin::rwlock::RwLock},
+    cmdline, cpu, devices,
+    init_stage::{self, InitStage},
+    sync::{
+        once::OnceLock,
+        spin::{self, rwlock::RwLock},
+    },
+    testing,
 };
 
 use self::rtc::Rtc;
@@ -18,6 +23,8 @@ use self::rtc::Rtc;
 pub const NANOS_PER_SEC: u64 = 1_000_000_000;
 pub const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
 pub const NANOS_PER_FEMTO: u64 = 1_000_000;
+/// The maximum rate the unix time is slewed at, in parts per million, same as `adjtime` in Linux
+const MAX_SLEW_PPM: u64 = 500;
 
 static CLOCKS: OnceLock<Clock> = OnceLock::new();
 
@@ -25,6 +32,12 @@ pub fn clocks() -> &'static Clock {
     CLOCKS.get()
 }
 
+/// Same as [`clocks`], but returns `None` if the clocks are not initialized yet,
+/// must be used from interrupt handlers that may run before that
+pub fn try_clocks() -> Option<&'static Clock> {
+    CLOCKS.try_get()
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct ClockTime {
     /// nanoseconds added to `seconds`
@@ -38,6 +51,43 @@ impl ClockTime {
     pub fn as_nanos(&self) -> u64 {
         self.seconds * NANOS_PER_SEC + self.nanoseconds
     }
+
+    pub fn from_nanos(nanos: u64) -> Self {
+        Self {
+            nanoseconds: nanos % NANOS_PER_SEC,
+            seconds: nanos / NANOS_PER_SEC,
+        }
+    }
+}
+
+/// A `TSC` value and the time since startup at the same moment, two of them are used to convert
+/// `TSC` timestamps, which are taken without locking, or before the clocks are initialized
+#[derive(Debug, Clone, Copy)]
+pub struct TscSyncPoint {
+    pub cycles: u64,
+    pub nanos: u64,
+}
+
+impl TscSyncPoint {
+    /// `None` if the clocks are not initialized, or are used by us (i.e. we panicked while holding them)
+    pub fn now() -> Option<Self> {
+        let time = try_clocks()?.try_time_since_startup()?;
+        Some(Self {
+            cycles: unsafe { cpu::read_tsc() },
+            nanos: time.as_nanos(),
+        })
+    }
+
+    /// Convert a number of `TSC` cycles to nanoseconds, using the rate between `self` and a later point `end`,
+    /// `None` if they are too close
+    pub fn cycles_to_nanos(&self, end: &TscSyncPoint, cycles: u64) -> Option<u64> {
+        if end.cycles <= self.cycles || end.nanos <= self.nanos {
+            return None;
+        }
+        let nanos =
+            cycles as u128 * (end.nanos - self.nanos) as u128 / (end.cycles - self.cycles) as u128;
+        Some(nanos as u64)
+    }
 }
 
 impl Ord for ClockTime {
@@ -96,6 +146,52 @@ impl core::ops::AddAssign for ClockTime {
     }
 }
 
+/// The time zone of the system, the local time is `utc + offset_seconds`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Offset of the local time from UTC in seconds
+    pub offset_seconds: i64,
+    /// The RTC holds the local time instead of UTC (as Windows sets it)
+    pub rtc_localtime: bool,
+}
+
+impl TimeZone {
+    fn from_cmdline() -> Self {
+        let cmdline = cmdline::cmdline();
+        Self {
+            offset_seconds: cmdline.timezone_offset as i64 * 60,
+            rtc_localtime: cmdline.rtc_localtime,
+        }
+    }
+
+    /// The seconds the RTC is ahead of UTC
+    fn rtc_bias(&self) -> i64 {
+        if self.rtc_localtime {
+            self.offset_seconds
+        } else {
+            0
+        }
+    }
+
+    /// Convert a timestamp read from the RTC to UTC
+    pub fn rtc_to_utc(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_add_signed(-self.rtc_bias())
+    }
+
+    /// Convert a UTC timestamp to the time the RTC uses
+    pub fn utc_to_rtc(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_add_signed(self.rtc_bias())
+    }
+
+    fn rtc_name(&self) -> &'static str {
+        if self.rtc_localtime {
+            "local time"
+        } else {
+            "UTC"
+        }
+    }
+}
+
 trait ClockDevice: Send + Sync {
     /// Returns the name of the device
     fn name(&self) -> &'static str;
@@ -126,15 +222,21 @@ struct SystemTime {
     startup_offset: ClockTime,
     /// device used to get the time
     device: Option<Arc<dyn ClockDevice>>,
+    /// Used to convert the RTC time to UTC
+    timezone: TimeZone,
+    /// Nanoseconds still to be added to `start_unix`, applied gradually in [`Self::tick`]
+    slew_remaining: i64,
 }
 
 impl SystemTime {
     fn new(rtc: &Rtc) -> Self {
         let time = rtc.get_time();
         // let device_time = device.get_time();
+        let timezone = TimeZone::from_cmdline();
 
-        let timestamp = time.seconds_since_unix_epoch().expect("Must be after 1970");
-        info!("Time now: {time} - UTC");
+        let timestamp =
+            timezone.rtc_to_utc(time.seconds_since_unix_epoch().expect("Must be after 1970"));
+        info!("Time now: {time} - {}", timezone.rtc_name());
         info!("System start timestamp: {}", timestamp);
 
         let start_unix = ClockTime {
@@ -153,6 +255,8 @@ impl SystemTime {
                 seconds: 0,
             },
             device: None,
+            timezone,
+            slew_remaining: 0,
         }
     }
 
@@ -162,9 +266,29 @@ impl SystemTime {
             let diff = time - self.last_tick;
             self.startup_offset += diff;
             self.last_tick = time;
+            self.apply_slew(diff);
         }
     }
 
+    /// Move `start_unix` by at most [`MAX_SLEW_PPM`] of the `elapsed` time towards the slew target,
+    /// so the unix time speeds up or slows down slightly, but never jumps or goes backwards
+    fn apply_slew(&mut self, elapsed: ClockTime) {
+        if self.slew_remaining == 0 {
+            return;
+        }
+        let max_step = (elapsed.as_nanos() * MAX_SLEW_PPM / 1_000_000) as i64;
+        let step = self.slew_remaining.clamp(-max_step, max_step);
+        self.slew_remaining -= step;
+        self.start_unix =
+            ClockTime::from_nanos(self.start_unix.as_nanos().saturating_add_signed(step));
+    }
+
+    /// Replace the slew target with `offset_nanos`, returns the part of the previous one that wasn't applied
+    fn slew(&mut self, offset_nanos: i64) -> i64 {
+        self.tick();
+        core::mem::replace(&mut self.slew_remaining, offset_nanos)
+    }
+
     /// Will update the device if this one is different
     fn update_device(&mut self, device: Arc<dyn ClockDevice>, rtc: &Rtc) {
         if let Some(current_device) = &self.device {
@@ -196,10 +320,15 @@ impl SystemTime {
             }
             let device_time = device.get_time();
 
-            let timestamp = rtc_time
-                .seconds_since_unix_epoch()
-                .expect("Must be after 1970");
-            info!("Adjusted Time now: {rtc_time} - UTC");
+            let timestamp = self.timezone.rtc_to_utc(
+                rtc_time
+                    .seconds_since_unix_epoch()
+                    .expect("Must be after 1970"),
+            );
+            info!(
+                "Adjusted Time now: {rtc_time} - {}",
+                self.timezone.rtc_name()
+            );
             info!("Adjusted System start timestamp: {}", timestamp);
 
             self.last_tick = device_time;
@@ -210,15 +339,21 @@ impl SystemTime {
             };
             self.start_unix = ClockTime {
                 nanoseconds: 0,
-                seconds: rtc_time
-                    .seconds_since_unix_epoch()
-                    .expect("Must be after 1970"),
+                seconds: timestamp,
             };
 
             cpu::cpu().pop_cli();
         }
     }
 
+    /// Changing the time zone only moves the unix time if the RTC holds the local time,
+    /// as the start time was read from it
+    fn set_timezone(&mut self, timezone: TimeZone) {
+        let rtc_start = self.timezone.utc_to_rtc(self.start_unix.seconds);
+        self.start_unix.seconds = timezone.rtc_to_utc(rtc_start);
+        self.timezone = timezone;
+    }
+
     fn time_since_startup(&self) -> ClockTime {
         self.startup_offset
     }
@@ -267,6 +402,33 @@ impl Clock {
             .update_device(devs[0].clone(), &self.rtc);
     }
 
+    pub fn timezone(&self) -> TimeZone {
+        self.system_time.read().timezone
+    }
+
+    /// Gradually correct the unix time by `offset_nanos`, replacing any correction still in progress,
+    /// returns the part of the previous correction that wasn't applied.
+    ///
+    /// This is meant for an NTP client, so that the time is disciplined without stepping it.
+    #[allow(dead_code)]
+    pub fn slew_time(&self, offset_nanos: i64) -> i64 {
+        self.system_time.write().slew(offset_nanos)
+    }
+
+    pub fn set_timezone(&self, timezone: TimeZone) {
+        info!(
+            "Time zone set to UTC{:+}s, RTC in {}",
+            timezone.offset_seconds,
+            timezone.rtc_name()
+        );
+        self.system_time.write().set_timezone(timezone);
+    }
+
+    /// Handle the RTC ACPI event, returns `true` if it was the alarm
+    pub fn handle_rtc_interrupt(&self) -> bool {
+        self.rtc.handle_interrupt()
+    }
+
     #[allow(dead_code)]
     fn get_best_clock(&self) -> Option<Arc<dyn ClockDevice>> {
         self.devices.read().first().map(Arc::clone)
@@ -282,17 +444,29 @@ impl Clock {
 
     #[allow(dead_code)]
     pub fn tick_system_time(&self) {
-        self.system_time.write().tick();
+        // from the timer interrupt
+        self.system_time
+            .write_timeout(spin::LOCK_TIMEOUT_SPINS)
+            .tick();
     }
 
     #[allow(dead_code)]
     pub fn time_since_startup(&self) -> ClockTime {
         // TODO: find a better way to do this
-        let mut time = self.system_time.write();
+        // used by the scheduler and the timer interrupt
+        let mut time = self.system_time.write_timeout(spin::LOCK_TIMEOUT_SPINS);
         time.tick();
         time.time_since_startup()
     }
 
+    /// Same as [`Self::time_since_startup`], but returns `None` instead of waiting if the time
+    /// is being updated, i.e. from the panic handler
+    pub fn try_time_since_startup(&self) -> Option<ClockTime> {
+        let mut time = self.system_time.try_write()?;
+        time.tick();
+        Some(time.time_since_startup())
+    }
+
     #[allow(dead_code)]
     pub fn time_since_unix_epoch(&self) -> ClockTime {
         // TODO: find a better way to do this
@@ -303,13 +477,20 @@ impl Clock {
 }
 
 pub fn init(bios_tables: &BiosTables) {
+    // the hardware timers use the APIC for their interrupts
+    init_stage::debug_assert_reached(InitStage::Apic);
     let facp = bios_tables.rsdt.get_table::<Facp>();
-    let century_reg = facp.map(|facp| facp.century);
+    let rtc = Rtc::new(
+        facp.map(|facp| facp.century),
+        facp.map(|facp| facp.day_alarm),
+        facp.map(|facp| facp.month_alarm),
+    );
 
     // create the clock
     CLOCKS
-        .set(Clock::new(Rtc::new(century_reg)))
+        .set(Clock::new(rtc))
         .expect("Clock is already initialized");
+    devices::register_device(Arc::new(rtc::RtcDevice));
 
     // init HPET
     let hpet_table = bios_tables.rsdt.get_table::<tables::Hpet>();
@@ -327,3 +508,48 @@ pub fn init(bios_tables: &BiosTables) {
         clocks().add_device(Arc::new(tsc));
     }
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_slew_time() {
+    const TICK: ClockTime = ClockTime {
+        nanoseconds: 1_000_000,
+        seconds: 0,
+    };
+    let max_step = TICK.as_nanos() * MAX_SLEW_PPM / 1_000_000;
+
+    let mut time = SystemTime {
+        start_unix: ClockTime {
+            nanoseconds: 0,
+            seconds: 1704110625,
+        },
+        last_tick: ClockTime::default(),
+        startup_offset: ClockTime::default(),
+        device: None,
+        timezone: TimeZone::default(),
+        slew_remaining: 0,
+    };
+
+    for offset in [-5_000_000, 5_000_000] {
+        let start = time.start_unix.as_nanos();
+        assert_eq!(time.slew(offset), 0);
+
+        let mut last_unix = time.time_since_unix_epoch();
+        // enough ticks to apply all of it at the max rate
+        for _ in 0..(offset.unsigned_abs() / max_step) {
+            let before = time.start_unix.as_nanos();
+            // same as `tick`, but without a device
+            time.startup_offset += TICK;
+            time.apply_slew(TICK);
+
+            assert!(time.start_unix.as_nanos().abs_diff(before) <= max_step);
+            let unix = time.time_since_unix_epoch();
+            assert!(unix > last_unix);
+            last_unix = unix;
+        }
+        assert_eq!(time.slew_remaining, 0);
+        assert_eq!(
+            time.start_unix.as_nanos(),
+            start.saturating_add_signed(offset)
+        );
+    }
+}
@@ -1,6 +1,11 @@
 use core::fmt;
 
-use crate::{cpu, testing};
+use alloc::format;
+use tracing::{info, warn};
+
+use crate::{cpu, devices::Device, fs::FileSystemError, sync::spin::mutex::Mutex, testing};
+
+use super::clocks;
 
 pub const CURRENT_CENTURY: u16 = 2000 / 100;
 
@@ -8,14 +13,29 @@ pub const RTC_ADDRESS: u16 = 0x70;
 pub const RTC_DATA: u16 = 0x71;
 
 pub const RTC_SECONDS: u8 = 0x00;
+pub const RTC_SECONDS_ALARM: u8 = 0x01;
 pub const RTC_MINUTES: u8 = 0x02;
+pub const RTC_MINUTES_ALARM: u8 = 0x03;
 pub const RTC_HOURS: u8 = 0x04;
+pub const RTC_HOURS_ALARM: u8 = 0x05;
 pub const RTC_DAY_OF_MONTH: u8 = 0x07;
 pub const RTC_MONTH: u8 = 0x08;
 pub const RTC_YEAR: u8 = 0x09;
 
 pub const RTC_STATUS_A: u8 = 0x0A;
 pub const RTC_STATUS_B: u8 = 0x0B;
+pub const RTC_STATUS_C: u8 = 0x0C;
+
+/// Status B: the hours are in 24 hours format
+const RTC_STATUS_B_24_HOURS: u8 = 1 << 1;
+/// Status B: values are binary and not BCD
+const RTC_STATUS_B_BINARY: u8 = 1 << 2;
+/// Status B: Alarm interrupt enable
+const RTC_STATUS_B_AIE: u8 = 1 << 5;
+/// Status C: Alarm interrupt flag, cleared on read
+const RTC_STATUS_C_AF: u8 = 1 << 5;
+/// Writing this value to an alarm register, makes it match any value
+const RTC_ALARM_DONT_CARE: u8 = 0xC0;
 
 pub const SECONDS_PER_MINUTE: u64 = 60;
 pub const SECONDS_PER_HOUR: u64 = 60 * SECONDS_PER_MINUTE;
@@ -61,10 +81,57 @@ impl RtcTime {
 
         Some(timestamp_since_unix - UNIX_EPOCH_IN_SECONDS)
     }
+
+    /// The reverse of [`Self::seconds_since_unix_epoch`]
+    pub fn from_seconds_since_unix_epoch(timestamp: u64) -> Self {
+        let days = timestamp / SECONDS_PER_DAY;
+        let seconds_of_day = timestamp % SECONDS_PER_DAY;
+
+        // days to civil date, from <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+        // shift the epoch to 0000-03-01, so that the leap day is at the end of the year
+        let days = days + 719468;
+        let era = days / 146097;
+        let day_of_era = days % 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day_of_month = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+        Self {
+            seconds: (seconds_of_day % SECONDS_PER_MINUTE) as u8,
+            minutes: ((seconds_of_day % SECONDS_PER_HOUR) / SECONDS_PER_MINUTE) as u8,
+            hours: (seconds_of_day / SECONDS_PER_HOUR) as u8,
+            day_of_month: day_of_month as u8,
+            month: month as u8,
+            year: year as u16,
+        }
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + ((value / 16) * 10)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
 }
 
 pub struct Rtc {
     century_reg: Option<u8>,
+    /// Registers of the day and month alarm, from the `FACP`, if not available the alarm matches every day/month
+    day_alarm_reg: Option<u8>,
+    month_alarm_reg: Option<u8>,
+    /// The address and data ports must be accessed together
+    lock: Mutex<()>,
+    /// The alarm currently set, in seconds since unix epoch
+    alarm: Mutex<Option<u64>>,
 }
 
 impl fmt::Display for RtcTime {
@@ -83,32 +150,53 @@ impl fmt::Display for RtcTime {
 }
 
 impl Rtc {
-    pub const fn new(century_reg: Option<u8>) -> Self {
-        let century_reg = if let Some(century_reg) = century_reg {
-            if century_reg == 0 {
-                None
-            } else {
-                Some(century_reg)
+    pub const fn new(
+        century_reg: Option<u8>,
+        day_alarm_reg: Option<u8>,
+        month_alarm_reg: Option<u8>,
+    ) -> Self {
+        // `0` means the register is not available
+        const fn non_zero(reg: Option<u8>) -> Option<u8> {
+            match reg {
+                Some(0) | None => None,
+                reg => reg,
             }
-        } else {
-            None
-        };
-        Self { century_reg }
+        }
+        Self {
+            century_reg: non_zero(century_reg),
+            day_alarm_reg: non_zero(day_alarm_reg),
+            month_alarm_reg: non_zero(month_alarm_reg),
+            lock: Mutex::new(()),
+            alarm: Mutex::new(None),
+        }
     }
 
     fn read_register(&self, reg: u8) -> u8 {
+        let _lock = self.lock.lock();
         unsafe {
             cpu::io_out(RTC_ADDRESS, reg);
             cpu::io_in(RTC_DATA)
         }
     }
 
+    fn write_register(&self, reg: u8, value: u8) {
+        let _lock = self.lock.lock();
+        unsafe {
+            cpu::io_out(RTC_ADDRESS, reg);
+            cpu::io_out(RTC_DATA, value);
+        }
+    }
+
     fn is_updating(&self) -> bool {
         self.read_register(RTC_STATUS_A) & 0x80 != 0
     }
 
     fn is_bcd(&self) -> bool {
-        self.read_register(RTC_STATUS_B) & 0x04 == 0
+        self.read_register(RTC_STATUS_B) & RTC_STATUS_B_BINARY == 0
+    }
+
+    fn is_24_hours(&self) -> bool {
+        self.read_register(RTC_STATUS_B) & RTC_STATUS_B_24_HOURS != 0
     }
 
     fn get_time_sync(&self) -> (RtcTime, u8) {
@@ -146,14 +234,14 @@ impl Rtc {
         let (mut t, mut century) = self.get_time_sync();
 
         if self.is_bcd() {
-            t.seconds = (t.seconds & 0x0F) + ((t.seconds / 16) * 10);
-            t.minutes = (t.minutes & 0x0F) + ((t.minutes / 16) * 10);
-            t.hours = ((t.hours & 0x0F) + (((t.hours & 0x70) / 16) * 10)) | (t.hours & 0x80);
-            t.day_of_month = (t.day_of_month & 0x0F) + ((t.day_of_month / 16) * 10);
-            t.month = (t.month & 0x0F) + ((t.month / 16) * 10);
-            t.year = (t.year & 0x0F) + ((t.year / 16) * 10);
+            t.seconds = from_bcd(t.seconds);
+            t.minutes = from_bcd(t.minutes);
+            t.hours = from_bcd(t.hours & 0x7F) | (t.hours & 0x80);
+            t.day_of_month = from_bcd(t.day_of_month);
+            t.month = from_bcd(t.month);
+            t.year = from_bcd(t.year as u8) as u16;
             if self.century_reg.is_some() {
-                century = (century & 0x0F) + ((century / 16) * 10);
+                century = from_bcd(century);
             }
         }
         if t.hours & 0x80 != 0 {
@@ -168,6 +256,169 @@ impl Rtc {
 
         t
     }
+
+    /// Returns the alarm currently set, in seconds since unix epoch
+    pub fn alarm(&self) -> Option<u64> {
+        *self.alarm.lock()
+    }
+
+    /// Program the alarm to fire at `timestamp` (seconds since unix epoch), which raises the
+    /// RTC ACPI event, and can wake the system from sleep.
+    ///
+    /// Without the day/month alarm registers, only the time of the day is matched, so the alarm
+    /// must be within the next 24 hours to fire at the correct time.
+    pub fn set_alarm(&self, timestamp: u64) {
+        let t = RtcTime::from_seconds_since_unix_epoch(timestamp);
+        let is_bcd = self.is_bcd();
+        let encode = |value: u8| if is_bcd { to_bcd(value) } else { value };
+
+        let hours = if self.is_24_hours() {
+            encode(t.hours)
+        } else {
+            // 12 hours format, `12` is used for the first hour, and the top bit is `PM`
+            let pm = if t.hours >= 12 { 0x80 } else { 0 };
+            let hours = match t.hours % 12 {
+                0 => 12,
+                h => h,
+            };
+            encode(hours) | pm
+        };
+
+        // disable the alarm while changing it
+        let status_b = self.read_register(RTC_STATUS_B);
+        self.write_register(RTC_STATUS_B, status_b & !RTC_STATUS_B_AIE);
+
+        self.write_register(RTC_SECONDS_ALARM, encode(t.seconds));
+        self.write_register(RTC_MINUTES_ALARM, encode(t.minutes));
+        self.write_register(RTC_HOURS_ALARM, hours);
+        if let Some(day_alarm_reg) = self.day_alarm_reg {
+            self.write_register(day_alarm_reg, encode(t.day_of_month));
+        }
+        if let Some(month_alarm_reg) = self.month_alarm_reg {
+            self.write_register(month_alarm_reg, encode(t.month));
+        }
+        if self.day_alarm_reg.is_none() {
+            warn!("RTC: no day alarm register, the alarm {t} will fire on the first matching time of day");
+        }
+
+        // clear any pending alarm flag
+        self.read_register(RTC_STATUS_C);
+        self.write_register(RTC_STATUS_B, status_b | RTC_STATUS_B_AIE);
+        *self.alarm.lock() = Some(timestamp);
+        info!("RTC: alarm set to {t} - RTC time");
+    }
+
+    pub fn clear_alarm(&self) {
+        let status_b = self.read_register(RTC_STATUS_B);
+        self.write_register(RTC_STATUS_B, status_b & !RTC_STATUS_B_AIE);
+        if let Some(day_alarm_reg) = self.day_alarm_reg {
+            self.write_register(day_alarm_reg, RTC_ALARM_DONT_CARE);
+        }
+        if let Some(month_alarm_reg) = self.month_alarm_reg {
+            self.write_register(month_alarm_reg, RTC_ALARM_DONT_CARE);
+        }
+        self.read_register(RTC_STATUS_C);
+        *self.alarm.lock() = None;
+    }
+
+    /// Handle the RTC ACPI event, returns `true` if it was the alarm
+    pub fn handle_interrupt(&self) -> bool {
+        // reading status C acknowledges the interrupt
+        if self.read_register(RTC_STATUS_C) & RTC_STATUS_C_AF == 0 {
+            return false;
+        }
+        let Some(alarm) = self.alarm.lock().take() else {
+            return false;
+        };
+        // the alarm only fires once
+        let status_b = self.read_register(RTC_STATUS_B);
+        self.write_register(RTC_STATUS_B, status_b & !RTC_STATUS_B_AIE);
+
+        info!(
+            "RTC: alarm {} fired",
+            RtcTime::from_seconds_since_unix_epoch(alarm)
+        );
+        true
+    }
+}
+
+/// RTC device
+///
+/// Reading it returns the current time and the alarm as seconds since unix epoch (`0` if there is no alarm):
+/// `<time> <alarm>`, both in UTC, even if the RTC holds the local time.
+///
+/// Writing to it sets the alarm:
+/// - `echo <timestamp> > /devices/rtc` to set the alarm to `timestamp` (seconds since unix epoch).
+/// - `echo clear > /devices/rtc` to remove the alarm.
+#[derive(Debug)]
+pub struct RtcDevice;
+
+impl Device for RtcDevice {
+    fn name(&self) -> &str {
+        "rtc"
+    }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        let rtc = &clocks().rtc;
+        let timezone = clocks().timezone();
+        let now = timezone.rtc_to_utc(
+            rtc.get_time()
+                .seconds_since_unix_epoch()
+                .ok_or(FileSystemError::DeviceError)?,
+        );
+        let alarm = rtc.alarm().map_or(0, |alarm| timezone.rtc_to_utc(alarm));
+        let content = format!("{now} {alarm}\n");
+        let content = content.as_bytes();
+
+        if offset >= content.len() as u64 {
+            return Ok(0);
+        }
+        let remaining = &content[offset as usize..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        Ok(count as u64)
+    }
+
+    // needed for `echo`, as it truncates the file before writing, same as `power`
+    fn set_size(&self, size: u64) -> Result<(), FileSystemError> {
+        if size != 0 {
+            return Err(FileSystemError::EndOfFile);
+        }
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
+        if offset != 0 {
+            return Err(FileSystemError::EndOfFile);
+        }
+        let cmd = core::str::from_utf8(buf)
+            .map_err(|_| FileSystemError::InvalidInput)?
+            .trim();
+        let rtc = &clocks().rtc;
+
+        if cmd == "clear" {
+            rtc.clear_alarm();
+        } else {
+            let timestamp = cmd
+                .parse::<u64>()
+                .map_err(|_| FileSystemError::InvalidInput)?;
+            // the alarm registers use the same time as the RTC
+            let timestamp = clocks().timezone().utc_to_rtc(timestamp);
+            let now = rtc
+                .get_time()
+                .seconds_since_unix_epoch()
+                .ok_or(FileSystemError::DeviceError)?;
+            if timestamp <= now {
+                return Err(FileSystemError::InvalidInput);
+            }
+            rtc.set_alarm(timestamp);
+        }
+        Ok(buf.len() as u64)
+    }
 }
 
 #[macro_rules_attribute::apply(testing::test)]
@@ -194,3 +445,27 @@ fn test_seconds_since_unix_epoch() {
         assert_eq!(t.seconds_since_unix_epoch(), Some(expected));
     }
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_from_seconds_since_unix_epoch() {
+    // the alarm is converted back from a timestamp
+    for timestamp in [
+        0,
+        565056000,
+        951782400,
+        1704110625,
+        13700828025,
+        99883100025,
+    ] {
+        let t = RtcTime::from_seconds_since_unix_epoch(timestamp);
+        assert_eq!(t.seconds_since_unix_epoch(), Some(timestamp));
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_bcd() {
+    for value in 0..100 {
+        assert_eq!(from_bcd(to_bcd(value)), value);
+    }
+    assert_eq!(to_bcd(59), 0x59);
+}
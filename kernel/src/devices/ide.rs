@@ -15,6 +15,7 @@ use crate::{
     },
     memory_management::memory_layout::MemSize,
     sync::spin::mutex::Mutex,
+    tracepoints::{self, Event},
 };
 
 use super::pci::{self, PciDevice, PciDeviceConfig, PciDeviceType, ProbeExtra};
@@ -167,6 +168,7 @@ mod ata {
     pub const COMMAND_READ_SECTORS: u8 = 0x20;
     pub const COMMAND_WRITE_SECTORS: u8 = 0x30;
     pub const COMMAND_DEVICE_RESET: u8 = 0x08;
+    pub const COMMAND_FLUSH_CACHE: u8 = 0xE7;
     pub const COMMAND_PACKET: u8 = 0xA0;
 
     pub const PACKET_FEAT_DMA: u8 = 1 << 0;
@@ -381,6 +383,18 @@ impl AtaCommand {
 
         io_port.write_data_block(data)
     }
+
+    /// Execute a command that doesn't transfer data, and wait for it to finish
+    pub fn execute_no_data(&self, io_port: &IdeIo) -> Result<(), u8> {
+        io_port.wait_until_can_command()?;
+        self.write(io_port);
+
+        io_port.wait_until_free();
+        if io_port.read_status() & ata::STATUS_ERR != 0 {
+            return Err(io_port.read_error());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -714,7 +728,6 @@ impl IdeDevice {
         self.second_device_select
     }
 
-    #[allow(dead_code)]
     pub fn number_of_sectors(&self) -> u64 {
         self.number_of_sectors
     }
@@ -735,7 +748,7 @@ impl IdeDevice {
         if start_sector
             .checked_add(number_of_sectors)
             .ok_or(IdeError::BoundsExceeded)?
-            >= self.number_of_sectors
+            > self.number_of_sectors
         {
             return Err(IdeError::BoundsExceeded);
         }
@@ -779,7 +792,7 @@ impl IdeDevice {
         if start_sector
             .checked_add(number_of_sectors)
             .ok_or(IdeError::BoundsExceeded)?
-            >= self.number_of_sectors
+            > self.number_of_sectors
         {
             return Err(IdeError::BoundsExceeded);
         }
@@ -807,6 +820,19 @@ impl IdeDevice {
             todo!("write_sync for ATAPI");
         }
     }
+
+    /// Wait until all the written data is in the disk, i.e. flush the write cache of the device,
+    /// writes before this are never reordered after the writes after it
+    pub fn flush_sync(&self) -> Result<(), IdeError> {
+        if self.device_type != IdeDeviceType::Ata {
+            // read only
+            return Ok(());
+        }
+        self.device_impl
+            .lock()
+            .flush_cache_ata()
+            .map_err(IdeError::DeviceError)
+    }
 }
 
 #[allow(dead_code)]
@@ -955,7 +981,10 @@ impl IdeDeviceImpl {
             .with_sector_count(len_sectors as u8)
             .with_second_drive(self.second_device_select);
 
-        command.execute_read(&self.io, data)
+        tracepoints::record(Event::DiskReadStart, start_sector, len_sectors as u32);
+        let result = command.execute_read(&self.io, data);
+        tracepoints::record(Event::DiskReadEnd, start_sector, len_sectors as u32);
+        result
     }
 
     fn read_sync_atapi(
@@ -975,7 +1004,10 @@ impl IdeDeviceImpl {
             .push_param_u16(len_sectors as u16) // transfer length
             .push_param(0); // control
 
-        command.execute(&self.io, data)
+        tracepoints::record(Event::DiskReadStart, start_sector, len_sectors as u32);
+        let result = command.execute(&self.io, data);
+        tracepoints::record(Event::DiskReadEnd, start_sector, len_sectors as u32);
+        result
     }
 
     fn write_sync_ata(
@@ -991,7 +1023,16 @@ impl IdeDeviceImpl {
             .with_sector_count(len_sectors as u8)
             .with_second_drive(self.second_device_select);
 
-        command.execute_write(&self.io, data)
+        tracepoints::record(Event::DiskWriteStart, start_sector, len_sectors as u32);
+        let result = command.execute_write(&self.io, data);
+        tracepoints::record(Event::DiskWriteEnd, start_sector, len_sectors as u32);
+        result
+    }
+
+    fn flush_cache_ata(&mut self) -> Result<(), u8> {
+        AtaCommand::new(ata::COMMAND_FLUSH_CACHE)
+            .with_second_drive(self.second_device_select)
+            .execute_no_data(&self.io)
     }
 
     fn interrupt(&mut self) {
@@ -1086,11 +1127,13 @@ impl PciDevice for IdeDevice {
                     ide_interrupt_primary as BasicInterruptHandler,
                     pci_cfg::DEFAULT_PRIMARY_INTERRUPT,
                     cpu::cpu(),
+                    "IDE primary",
                 );
                 apic::assign_io_irq(
                     ide_interrupt_secondary as BasicInterruptHandler,
                     pci_cfg::DEFAULT_SECONDARY_INTERRUPT,
                     cpu::cpu(),
+                    "IDE secondary",
                 );
             }
 
@@ -0,0 +1,182 @@
+//! Distribution of input events to the readers
+//!
+//! Each reader gets its own bounded queue, so readers never take events from each other.
+//! When a queue is full, the oldest event is dropped, and the reader gets an overflow marker
+//! (see [`InputEvent::overflow_marker`]) before the events that came after the drop.
+
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use kernel_user_link::{keyboard::Key, mouse::MouseEvent};
+
+use crate::{sync::spin::mutex::Mutex, testing};
+
+/// An event that can be sent through [`EventSender`]
+pub trait InputEvent: Copy {
+    /// The event given to a reader that missed `dropped` events
+    fn overflow_marker(timestamp: u64, dropped: u32) -> Self;
+    fn timestamp(&self) -> u64;
+}
+
+impl InputEvent for Key {
+    fn overflow_marker(timestamp: u64, dropped: u32) -> Self {
+        Key::overflow_marker(timestamp, dropped)
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl InputEvent for MouseEvent {
+    fn overflow_marker(timestamp: u64, dropped: u32) -> Self {
+        MouseEvent::overflow_marker(timestamp, dropped)
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+struct EventQueue<T> {
+    events: VecDeque<T>,
+    capacity: usize,
+    /// Events dropped since the last marker was read
+    dropped: u32,
+    /// Timestamp of the last dropped event
+    dropped_timestamp: u64,
+}
+
+impl<T: InputEvent> EventQueue<T> {
+    fn push(&mut self, event: T) {
+        if self.events.len() == self.capacity {
+            let dropped = self.events.pop_front().expect("queue is full");
+            self.dropped = self.dropped.saturating_add(1);
+            self.dropped_timestamp = dropped.timestamp();
+        }
+        self.events.push_back(event);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.dropped != 0 {
+            let marker = T::overflow_marker(self.dropped_timestamp, self.dropped);
+            self.dropped = 0;
+            return Some(marker);
+        }
+        self.events.pop_front()
+    }
+}
+
+/// Sends every event to all the [`EventReader`]s alive
+pub struct EventSender<T> {
+    readers: Mutex<Vec<Weak<Mutex<EventQueue<T>>>>>,
+    capacity: usize,
+}
+
+impl<T: InputEvent> EventSender<T> {
+    /// `capacity` is the size of each reader's queue
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            readers: Mutex::new(Vec::new()),
+            capacity,
+        }
+    }
+
+    /// Create a reader that receives the events sent from now on
+    pub fn new_receiver(&self) -> EventReader<T> {
+        let queue = Arc::new(Mutex::new(EventQueue {
+            events: VecDeque::with_capacity(self.capacity),
+            capacity: self.capacity,
+            dropped: 0,
+            dropped_timestamp: 0,
+        }));
+        self.readers.lock().push(Arc::downgrade(&queue));
+        EventReader { queue }
+    }
+
+    pub fn send(&self, event: T) {
+        // also remove the readers that are dropped
+        self.readers.lock().retain(|reader| {
+            let Some(queue) = reader.upgrade() else {
+                return false;
+            };
+            queue.lock().push(event);
+            true
+        });
+    }
+}
+
+/// A reader with its own queue of events, see [`EventSender`]
+pub struct EventReader<T> {
+    queue: Arc<Mutex<EventQueue<T>>>,
+}
+
+impl<T: InputEvent> EventReader<T> {
+    /// Get the next event if any, never blocks
+    pub fn recv(&mut self) -> Option<T> {
+        self.queue.lock().pop()
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_event_queue_independent_readers() {
+    use kernel_user_link::mouse::ScrollType;
+
+    let sender = EventSender::<MouseEvent>::new(4);
+    let mut first = sender.new_receiver();
+    let event = |sequence| MouseEvent {
+        x: 1,
+        y: -1,
+        scroll_type: ScrollType::None,
+        buttons: 0,
+        timestamp: 0,
+        sequence,
+    };
+    sender.send(event(0));
+    let mut second = sender.new_receiver();
+    sender.send(event(1));
+
+    assert_eq!(first.recv().map(|e| e.sequence), Some(0));
+    assert_eq!(first.recv().map(|e| e.sequence), Some(1));
+    assert!(first.recv().is_none());
+    // only gets the events after it was created, and not affected by `first`
+    assert_eq!(second.recv().map(|e| e.sequence), Some(1));
+    assert!(second.recv().is_none());
+
+    drop(second);
+    sender.send(event(2));
+    assert_eq!(sender.readers.lock().len(), 1);
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_event_queue_overflow() {
+    use kernel_user_link::keyboard::KeyType;
+
+    let sender = EventSender::<Key>::new(4);
+    let mut reader = sender.new_receiver();
+    let key = |sequence| Key {
+        pressed: true,
+        modifiers: 0,
+        key_type: KeyType::A,
+        timestamp: sequence as u64,
+        sequence,
+    };
+    for sequence in 0..10 {
+        sender.send(key(sequence));
+    }
+
+    // 6 oldest are dropped, the marker comes first, with the time of the last dropped event
+    let marker = reader.recv().unwrap();
+    assert!(marker.is_overflow_marker());
+    assert_eq!(marker.sequence, 6);
+    assert_eq!(marker.timestamp, 5);
+    for sequence in 6..10 {
+        let key = reader.recv().unwrap();
+        assert!(!key.is_overflow_marker());
+        assert_eq!(key.sequence, sequence);
+    }
+    assert!(reader.recv().is_none());
+}
@@ -1,11 +1,15 @@
-use core::sync::atomic::{AtomicU8, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 
-use blinkcast::alloc::{Receiver as BlinkcastReceiver, Sender as BlinkcastSender};
 use kernel_user_link::keyboard::{modifier, Key, KeyType};
 
-use super::ps2::Ps2;
+use crate::{devices::clock, io::console};
 
-/// Number of key events that can be buffered before being overwritten
+use super::{
+    event_queue::{EventReader, EventSender},
+    ps2::Ps2,
+};
+
+/// Number of key events that can be queued for each reader before dropping the oldest
 /// We are expecting interested readers to be fast, so we don't need a very large buffer
 const KEYBOARD_BUFFER_SIZE: usize = 256;
 
@@ -14,22 +18,25 @@ const KEY_PRESSED: u8 = 1 << 7;
 // PS/2 keyboard interrupt
 pub const KEYBOARD_INT_NUM: u8 = 1;
 
-pub type KeyboardReader = BlinkcastReceiver<Key>;
+pub type KeyboardReader = EventReader<Key>;
 
 pub struct Keyboard {
     active_modifiers: AtomicU8,
     active_toggles: AtomicU8,
+    /// The sequence number of the next key sent to the readers
+    next_sequence: AtomicU32,
     ps2: Ps2,
 
-    sender: BlinkcastSender<Key>,
+    sender: EventSender<Key>,
 }
 
 impl Keyboard {
     pub fn new(ps2: Ps2) -> Keyboard {
-        let sender = BlinkcastSender::new(KEYBOARD_BUFFER_SIZE);
+        let sender = EventSender::new(KEYBOARD_BUFFER_SIZE);
         Keyboard {
             active_modifiers: AtomicU8::new(0),
             active_toggles: AtomicU8::new(0),
+            next_sequence: AtomicU32::new(0),
             ps2,
             sender,
         }
@@ -53,6 +60,7 @@ impl Keyboard {
         }
 
         let data = self.ps2.read_data();
+        let timestamp = clock::clocks().time_since_startup().as_nanos();
 
         if data == 0xE0 {
             // this is an extended key
@@ -63,10 +71,12 @@ impl Keyboard {
                 return;
             };
 
-            self.sender.send(Key {
+            self.send_key(Key {
                 pressed,
                 modifiers: self.modifiers(),
                 key_type: key,
+                timestamp,
+                sequence: 0, // set by `send_key`
             });
         }
 
@@ -103,12 +113,54 @@ impl Keyboard {
             return;
         };
 
-        self.sender.send(Key {
+        self.send_key(Key {
             pressed,
             modifiers: self.modifiers(),
             key_type,
+            timestamp,
+            sequence: 0, // set by `send_key`
         })
     }
+
+    fn send_key(&self, mut key: Key) {
+        if !handle_console_hotkey(&key) {
+            // only count the keys the readers can see, so gaps are only from overflows
+            key.sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+            self.sender.send(key);
+        }
+    }
+}
+
+/// Handle the virtual terminals hotkeys, returns `true` if the key is consumed
+///
+/// - `Alt+F1..F4`: switch to terminal 1..4
+/// - `Shift+PageUp/PageDown`: scroll the active terminal
+fn handle_console_hotkey(key: &Key) -> bool {
+    const SCROLL_LINES: isize = 10;
+
+    // `F1..F10` are sequential
+    let f_key = (key.key_type as u8).wrapping_sub(KeyType::F1 as u8) as usize;
+    if key.modifiers & modifier::ALT != 0 && f_key < console::NUM_TERMINALS {
+        // consume the release as well
+        if key.pressed {
+            console::request_switch_terminal(f_key);
+        }
+        return true;
+    }
+
+    if key.modifiers & modifier::SHIFT != 0 {
+        let lines = match key.key_type {
+            KeyType::PageUp => SCROLL_LINES,
+            KeyType::PageDown => -SCROLL_LINES,
+            _ => return false,
+        };
+        if key.pressed {
+            console::request_scroll(lines);
+        }
+        return true;
+    }
+
+    false
 }
 
 // 0x80 means extended key
@@ -1,3 +1,4 @@
+mod event_queue;
 mod keyboard;
 mod mouse;
 mod ps2;
@@ -40,11 +41,13 @@ pub fn init_device() {
         ps2_interrupt_handler as BasicInterruptHandler,
         KEYBOARD_INT_NUM,
         cpu::cpu(),
+        "keyboard",
     );
     apic::assign_io_irq(
         ps2_interrupt_handler as BasicInterruptHandler,
         MOUSE_INT_NUM,
         cpu::cpu(),
+        "mouse",
     );
 }
 
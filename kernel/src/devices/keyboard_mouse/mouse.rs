@@ -1,9 +1,15 @@
-use super::ps2::Ps2;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::{
+    event_queue::{EventReader, EventSender},
+    ps2::Ps2,
+};
 
-use blinkcast::alloc::{Receiver as BlinkcastReceiver, Sender as BlinkcastSender};
 use kernel_user_link::mouse::{MouseEvent, ScrollType};
 use tracing::warn;
 
+use crate::devices::clock;
+
 #[allow(dead_code)]
 pub mod scaling {
     pub const PER_SEC_10: u8 = 10;
@@ -46,18 +52,20 @@ mod packet {
     pub const X_SIGN: u8 = 1 << 4;
 }
 
-/// Number of events that can be buffered before being overwritten
+/// Number of events that can be queued for each reader before dropping the oldest
 const MOUSE_BUFFER_SIZE: usize = 1024;
 
 // PS/2 mouse interrupt
 pub const MOUSE_INT_NUM: u8 = 12;
 
-pub type MouseReader = BlinkcastReceiver<MouseEvent>;
+pub type MouseReader = EventReader<MouseEvent>;
 
 pub struct Mouse {
     ps2: Ps2,
     has_extra_byte: bool,
-    sender: BlinkcastSender<MouseEvent>,
+    /// The sequence number of the next event sent to the readers
+    next_sequence: AtomicU32,
+    sender: EventSender<MouseEvent>,
 }
 
 #[allow(dead_code)]
@@ -66,7 +74,8 @@ impl Mouse {
         let mut device = Mouse {
             ps2,
             has_extra_byte: false,
-            sender: BlinkcastSender::new(MOUSE_BUFFER_SIZE),
+            next_sequence: AtomicU32::new(0),
+            sender: EventSender::new(MOUSE_BUFFER_SIZE),
         };
 
         // enable the mouse
@@ -108,6 +117,7 @@ impl Mouse {
         for d in data.iter_mut().take(read_len) {
             *d = self.ps2.read_data();
         }
+        let timestamp = clock::clocks().time_since_startup().as_nanos();
 
         if data[0] & packet::X_OVERFLOW != 0 || data[0] & packet::Y_OVERFLOW != 0 {
             // overflow, ignore the data
@@ -142,6 +152,8 @@ impl Mouse {
             y,
             buttons,
             scroll_type,
+            timestamp,
+            sequence: self.next_sequence.fetch_add(1, Ordering::Relaxed),
         };
 
         self.sender.send(event);
@@ -1,15 +1,22 @@
 use core::fmt;
 
-use alloc::{collections::BTreeMap, string::String, sync::Arc};
-use tracing::info;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use tracing::{info, warn};
 
 use crate::{
     fs::{
         self, DirTreverse, DirectoryNode, FileAttributes, FileNode, FileSystem, FileSystemError,
         Node,
     },
+    init_stage::{self, InitStage},
+    memory_management::kernel_stack,
     power,
-    sync::{once::OnceLock, spin::rwlock::RwLock},
+    process::scheduler::WaitQueue,
+    random,
+    sync::{
+        epoch::{self, EpochCell},
+        once::OnceLock,
+    },
 };
 
 use self::{
@@ -17,24 +24,33 @@ use self::{
     pci::{PciDeviceConfig, PciDeviceProbeIterator},
 };
 
+pub mod block;
 pub mod clock;
 pub mod ide;
 pub mod keyboard_mouse;
+pub mod null;
 pub mod pci;
 pub mod pipe;
+pub mod watchdog;
 
-static DEVICES: OnceLock<Arc<RwLock<Devices>>> = OnceLock::new();
+/// Looked up on each `open`, and only changed when a device is registered
+static DEVICES: OnceLock<Arc<EpochCell<Devices>>> = OnceLock::new();
 
 pub(crate) const DEVICES_FILESYSTEM_CLUSTER_MAGIC: u64 = 0xdef1ce5;
 pub(crate) const DEVICES_FILESYSTEM_ROOT_INODE_MAGIC: u64 = 0xdef1ce55007;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Devices {
     devices: BTreeMap<String, Arc<dyn Device>>,
 }
 
 pub trait Device: Sync + Send + fmt::Debug {
     fn name(&self) -> &str;
+    /// Does the device use the `offset` of `read` and `write`, otherwise its a stream (i.e. a pipe),
+    /// the offset is ignored, and seeking the file is rejected
+    fn is_seekable(&self) -> bool {
+        false
+    }
     fn read(&self, _offset: u64, _buf: &mut [u8]) -> Result<u64, FileSystemError> {
         Err(FileSystemError::ReadNotSupported)
     }
@@ -44,6 +60,27 @@ pub trait Device: Sync + Send + fmt::Debug {
     fn set_size(&self, _size: u64) -> Result<(), FileSystemError> {
         Err(FileSystemError::WriteNotSupported)
     }
+    /// The size of the device content, reported as the size of its file (i.e. disks)
+    fn size(&self) -> u64 {
+        0
+    }
+    /// The queue woken when the device gets data to read (or space to write), blocking reads wait
+    /// on it instead of polling the device
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        None
+    }
+    /// Whether the device edits the input a line at a time, `None` if not supported,
+    /// see [`FileMeta::LineEditing`](kernel_user_link::file::FileMeta::LineEditing)
+    fn line_editing(&self) -> Option<bool> {
+        None
+    }
+    fn set_line_editing(&self, _line_editing: bool) -> Result<(), FileSystemError> {
+        Err(FileSystemError::OperationNotSupported)
+    }
+    /// Used by [`pipe::splice`] to find the pipe side of the files
+    fn as_pipe(&self) -> Option<&pipe::PipeSide> {
+        None
+    }
     /// Informs the device that it is closed.
     fn close(&self) -> Result<(), FileSystemError> {
         Ok(())
@@ -59,9 +96,21 @@ pub trait Device: Sync + Send + fmt::Debug {
     fn try_create(&self) -> Option<Result<Arc<dyn Device>, FileSystemError>> {
         None
     }
+    /// Stop the device's activity before the system powers off, see [`suspend_all`].
+    /// The disks must keep working, as the filesystems are unmounted after this.
+    fn suspend(&self) -> Result<(), FileSystemError> {
+        Ok(())
+    }
+    /// Called periodically by the [`housekeeping`](crate::housekeeping), the device can enter a
+    /// low-power state if it's not used, and leave it on the next use
+    fn idle(&self) {}
 }
 
-impl FileSystem for RwLock<Devices> {
+impl FileSystem for EpochCell<Devices> {
+    fn type_name(&self) -> &'static str {
+        "devices"
+    }
+
     fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
         Ok(DirectoryNode::without_parent(
             String::from("/"),
@@ -78,7 +127,8 @@ impl FileSystem for RwLock<Devices> {
         assert_eq!(inode.start_cluster(), DEVICES_FILESYSTEM_ROOT_INODE_MAGIC);
 
         if inode.name().is_empty() || inode.name() == "/" {
-            for node in self.read().devices.iter().map(|(name, device)| {
+            let guard = epoch::pin();
+            for node in self.read(&guard).devices.iter().map(|(name, device)| {
                 FileNode::new_device(name.clone(), FileAttributes::EMPTY, device.clone()).into()
             }) {
                 if let DirTreverse::Stop = handler(node) {
@@ -92,38 +142,70 @@ impl FileSystem for RwLock<Devices> {
     }
 
     fn number_global_refs(&self) -> usize {
-        // we have `DEVICES` mutex globally stored
+        // we have `DEVICES` globally stored
         1
     }
 
     fn unmount(self: Arc<Self>) {
         // clean the devices
-        self.write().devices.clear();
+        self.update(|devices| devices.devices.clear());
     }
 }
 
 pub fn init_devices_mapping() {
     DEVICES
-        .set(Arc::new(RwLock::new(Devices {
+        .set(Arc::new(EpochCell::new(Devices {
             devices: BTreeMap::new(),
         })))
         .expect("Devices already initialized");
 
     // initialize builtin devices
+    register_device(Arc::new(null::NullDevice));
     register_device(Arc::new(power::PowerDevice));
+    register_device(Arc::new(watchdog::WatchdogDevice));
+    register_device(Arc::new(kernel_stack::KernelStackDevice::new()));
 
     fs::mapping::mount("/devices", DEVICES.get().clone()).expect("Mapping failed");
 }
 
 pub fn register_device(device: Arc<dyn Device>) {
-    let mut devices = DEVICES.get().write();
-    assert!(
-        !devices.devices.contains_key(device.name()),
-        "Device {} already registered",
-        device.name()
-    );
-    info!("Registered {} device", device.name());
-    devices.devices.insert(String::from(device.name()), device);
+    DEVICES.get().update(|devices| {
+        assert!(
+            !devices.devices.contains_key(device.name()),
+            "Device {} already registered",
+            device.name()
+        );
+        info!("Registered {} device", device.name());
+        devices.devices.insert(String::from(device.name()), device);
+    });
+}
+
+/// The registered devices, so they can be used without staying pinned
+fn registered_devices() -> Vec<Arc<dyn Device>> {
+    DEVICES
+        .get()
+        .read(&epoch::pin())
+        .devices
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// Suspend all the registered devices, in the reverse order of their registration,
+/// the errors are logged and the rest are still suspended
+pub fn suspend_all() {
+    for device in registered_devices().iter().rev() {
+        if let Err(e) = device.suspend() {
+            warn!("Failed to suspend device {}: {e:?}", device.name());
+        }
+    }
+}
+
+/// Let the registered devices enter a low-power state if they are not used, see [`Device::idle`]
+pub fn idle_all() {
+    for device in registered_devices() {
+        device.idle();
+    }
 }
 
 pub fn probe_pci_devices() {
@@ -151,16 +233,22 @@ pub fn probe_pci_devices() {
             );
         }
     }
+
+    block::register_block_devices();
 }
 
 pub fn probe_pci_driver(pci_device: &PciDeviceConfig) -> bool {
-    ide::try_register_ide_device(pci_device)
+    // drivers of disabled features are not probed, and get removed as dead code
+    cfg!(feature = "ide") && ide::try_register_ide_device(pci_device)
     // add more devices here
 }
 
 /// Devices such as PS/2 keyboard, mouse, serial ports, etc.
 pub fn init_legacy_devices() {
+    // the PS/2 controller needs the APIC for its interrupts
+    init_stage::debug_assert_reached(InitStage::Apic);
     keyboard_mouse::init_device();
     register_device(Arc::new(KeyboardDeviceCreator));
     register_device(Arc::new(MouseDeviceCreator));
+    register_device(Arc::new(random::RandomDevice));
 }
@@ -0,0 +1,28 @@
+//! `/devices/null`, reading from it returns end of file, and writes are discarded.
+//!
+//! Mainly used to give processes an empty `stdin` or to drop their output.
+
+use crate::{devices::Device, fs::FileSystemError};
+
+#[derive(Debug)]
+pub struct NullDevice;
+
+impl Device for NullDevice {
+    fn name(&self) -> &str {
+        "null"
+    }
+
+    fn read(&self, _offset: u64, _buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        // same as a pipe with no writers
+        Err(FileSystemError::EndOfFile)
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
+        Ok(buf.len() as u64)
+    }
+
+    // allow `cmd > /devices/null`, which truncates the file
+    fn set_size(&self, _size: u64) -> Result<(), FileSystemError> {
+        Ok(())
+    }
+}
@@ -1,32 +1,41 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use alloc::{collections::VecDeque, string::String, sync::Arc};
+use alloc::{collections::VecDeque, string::String, sync::Arc, vec};
 use kernel_user_link::file::BlockingMode;
 
 use crate::{
     fs::{self, FileAccess, FileAttributes, FileNode, FileSystemError},
+    process::scheduler::WaitQueue,
     sync::spin::mutex::Mutex,
+    testing,
 };
 
 use super::Device;
 
-/// Create a connected pipe pair.
+/// Create a connected pipe pair, that holds at most `capacity` bytes.
 /// The first returned file is the read side of the pipe.
 /// The second returned file is the write side of the pipe.
-pub fn create_pipe_pair() -> (fs::File, fs::File) {
+///
+/// Reading an empty pipe waits for data, unless the read side is made non blocking,
+/// and writing a full pipe waits for space, see [`PipeSide`].
+pub fn create_pipe_pair(capacity: usize) -> (fs::File, fs::File) {
     let pipe = Arc::new(Mutex::new(InnerPipe {
         buffer: VecDeque::new(),
+        capacity,
         read_side_available: true,
         write_side_available: true,
     }));
+    let queue = Arc::new(WaitQueue::new());
 
     let read_device = Arc::new(PipeSide {
         inner: pipe.clone(),
+        queue: queue.clone(),
         is_read_side: true,
         clones: AtomicUsize::new(1),
     });
     let write_device = Arc::new(PipeSide {
         inner: pipe.clone(),
+        queue,
         is_read_side: false,
         clones: AtomicUsize::new(1),
     });
@@ -50,7 +59,7 @@ pub fn create_pipe_pair() -> (fs::File, fs::File) {
         FileAccess::READ,
     )
     .expect("This is a file, shouldn't fail");
-    // no blocking for write
+    // the blocking mode is only used for reading, writing a full pipe always waits
     let write_file = fs::File::from_inode(
         write_inode,
         String::from("write_pipe"),
@@ -67,21 +76,46 @@ pub fn create_pipe_pair() -> (fs::File, fs::File) {
 /// Pipe is a device that allows two processes to communicate with each other.
 #[derive(Debug)]
 struct InnerPipe {
-    /// The buffer of the pipe.
+    /// The buffer of the pipe, written at the back and read from the front.
     buffer: VecDeque<u8>,
+    /// The most bytes `buffer` holds
+    capacity: usize,
     read_side_available: bool,
     write_side_available: bool,
 }
 
+impl InnerPipe {
+    fn space(&self) -> usize {
+        self.capacity.saturating_sub(self.buffer.len())
+    }
+}
+
 /// Represent one side of a pipe.
 /// Check [`create_pipe_pair`] for more details.
+///
+/// Both sides share the same [`WaitQueue`], it's woken when data is written, when space is freed
+/// by reading, and when a side is closed.
+/// A read of an empty pipe, or a write to a full one, returns [`FileSystemError::WouldBlock`],
+/// and the syscalls wait on the queue.
 #[derive(Debug)]
 pub struct PipeSide {
     inner: Arc<Mutex<InnerPipe>>,
+    queue: Arc<WaitQueue>,
     clones: AtomicUsize,
     is_read_side: bool,
 }
 
+impl PipeSide {
+    /// Put back `data` at the front of the pipe, so that it's read next,
+    /// `data` must have been read from this pipe in the same syscall
+    fn unread(&self, data: &[u8]) {
+        let mut pipe = self.inner.lock();
+        for &byte in data.iter().rev() {
+            pipe.buffer.push_front(byte);
+        }
+    }
+}
+
 impl Device for PipeSide {
     fn name(&self) -> &str {
         "pipe"
@@ -95,16 +129,15 @@ impl Device for PipeSide {
         if !pipe.write_side_available && pipe.buffer.is_empty() {
             return Err(FileSystemError::EndOfFile);
         }
-        let mut bytes_read = 0;
-        for byte in buf.iter_mut() {
-            if let Some(b) = pipe.buffer.pop_back() {
-                *byte = b;
-                bytes_read += 1;
-            } else {
-                break;
-            }
+        let bytes_read = buf.len().min(pipe.buffer.len());
+        for (byte, b) in buf.iter_mut().zip(pipe.buffer.drain(..bytes_read)) {
+            *byte = b;
+        }
+        if bytes_read != 0 {
+            // wake the writers waiting for space
+            self.queue.wake_all();
         }
-        Ok(bytes_read)
+        Ok(bytes_read as u64)
     }
 
     fn write(&self, _offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
@@ -115,10 +148,25 @@ impl Device for PipeSide {
         if !pipe.read_side_available {
             return Err(FileSystemError::EndOfFile);
         }
-        for byte in buf.iter() {
-            pipe.buffer.push_front(*byte);
+        // write what fits, and only wait if nothing does
+        let bytes_written = buf.len().min(pipe.space());
+        if bytes_written == 0 && !buf.is_empty() {
+            return Err(FileSystemError::WouldBlock);
         }
-        Ok(buf.len() as u64)
+        pipe.buffer.extend(&buf[..bytes_written]);
+        if bytes_written != 0 {
+            // wake the readers waiting for data
+            self.queue.wake_all();
+        }
+        Ok(bytes_written as u64)
+    }
+
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        Some(self.queue.clone())
+    }
+
+    fn as_pipe(&self) -> Option<&PipeSide> {
+        Some(self)
     }
 
     fn close(&self) -> Result<(), FileSystemError> {
@@ -133,6 +181,8 @@ impl Device for PipeSide {
         } else {
             pipe.write_side_available = false;
         }
+        // the other side gets `EndOfFile` instead of waiting
+        self.queue.wake_all();
         Ok(())
     }
 
@@ -141,3 +191,112 @@ impl Device for PipeSide {
         Ok(())
     }
 }
+
+/// Why [`splice`] didn't move anything, the first two mean that the syscall should wait on the
+/// wait queue of the side that is not ready and try again
+#[derive(Debug)]
+pub enum SpliceError {
+    /// The source has no data yet
+    SourceEmpty,
+    /// The destination is a full pipe
+    DestinationFull,
+    FileSystem(FileSystemError),
+}
+
+impl From<FileSystemError> for SpliceError {
+    fn from(e: FileSystemError) -> Self {
+        match e {
+            FileSystemError::WouldBlock => SpliceError::SourceEmpty,
+            e => SpliceError::FileSystem(e),
+        }
+    }
+}
+
+/// Move up to `len` bytes from `from` to `to` without copying them to userspace, one of them must
+/// be a pipe, i.e. the read side as `from`, or the write side as `to`.
+///
+/// The source is read once, with the same blocking rules as `read`. Returns the number of bytes
+/// moved, `0` at the end of the source.
+pub fn splice(from: &mut fs::File, to: &mut fs::File, len: usize) -> Result<u64, SpliceError> {
+    if len == 0 {
+        return Ok(0);
+    }
+
+    match (from.as_pipe().is_some(), to.as_pipe()) {
+        (false, Some(pipe)) => {
+            if pipe.is_read_side {
+                return Err(FileSystemError::WriteNotSupported.into());
+            }
+            // only take from the source what fits in the pipe
+            let space = {
+                let pipe = pipe.inner.lock();
+                if !pipe.read_side_available {
+                    return Err(FileSystemError::EndOfFile.into());
+                }
+                pipe.space()
+            };
+            if space == 0 {
+                return Err(SpliceError::DestinationFull);
+            }
+
+            let mut buf = vec![0; len.min(space)];
+            let bytes_read = from.read(&mut buf)? as usize;
+            // nothing else runs during the syscall, so the space is still there
+            let written = pipe.write(0, &buf[..bytes_read])?;
+            Ok(written)
+        }
+        (true, None) => {
+            let capacity = from.as_pipe().unwrap().inner.lock().capacity;
+            let mut buf = vec![0; len.min(capacity)];
+            let bytes_read = from.read(&mut buf)? as usize;
+            let written = match to.write(&buf[..bytes_read]) {
+                Ok(written) => written as usize,
+                Err(e) => {
+                    from.as_pipe().unwrap().unread(&buf[..bytes_read]);
+                    return Err(SpliceError::FileSystem(e));
+                }
+            };
+            // keep what was not written for the next read
+            from.as_pipe().unwrap().unread(&buf[written..bytes_read]);
+            Ok(written as u64)
+        }
+        // at least one side must be a pipe, and moving between two pipes is not supported
+        _ => Err(FileSystemError::OperationNotSupported.into()),
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_pipe_capacity() {
+    use kernel_user_link::file::PIPE_MIN_CAPACITY;
+
+    let (mut read_file, mut write_file) = create_pipe_pair(PIPE_MIN_CAPACITY);
+    let data = (0..PIPE_MIN_CAPACITY + 10)
+        .map(|i| i as u8)
+        .collect::<alloc::vec::Vec<_>>();
+
+    // only what fits is written, then it would wait
+    assert_eq!(write_file.write(&data).unwrap(), PIPE_MIN_CAPACITY as u64);
+    assert!(matches!(
+        write_file.write(&data),
+        Err(FileSystemError::WouldBlock)
+    ));
+
+    // read in the same order it was written
+    let mut buf = [0; 16];
+    assert_eq!(read_file.read(&mut buf).unwrap(), 16);
+    assert_eq!(buf, data[..16]);
+    assert_eq!(write_file.write(&data[..20]).unwrap(), 16);
+
+    let mut rest = vec![0; PIPE_MIN_CAPACITY];
+    assert_eq!(read_file.read(&mut rest).unwrap(), PIPE_MIN_CAPACITY as u64);
+    assert_eq!(rest[..PIPE_MIN_CAPACITY - 16], data[16..PIPE_MIN_CAPACITY]);
+    assert_eq!(rest[PIPE_MIN_CAPACITY - 16..], data[..16]);
+
+    // an empty pipe would wait for the writer, until it's closed
+    assert!(matches!(
+        read_file.read(&mut buf),
+        Err(FileSystemError::WouldBlock)
+    ));
+    drop(write_file);
+    assert_eq!(read_file.read(&mut buf).unwrap(), 0);
+}
@@ -0,0 +1,116 @@
+//! Software watchdog
+//!
+//! Once armed by the first write to `/devices/watchdog` (generally from `init`), it must be written to
+//! (petted) at least once every `watchdog_timeout` seconds (see [`cmdline`]), otherwise we assume the
+//! system is stuck (i.e. a deadlock in the kernel or the scheduler), log what we can, and reboot.
+//!
+//! The timeout is checked from the APIC timer interrupt, so it only uses atomics and never waits for locks.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use tracing::{error, info};
+
+use crate::{
+    cmdline,
+    devices::{clock, keyboard_mouse, Device},
+    fs::FileSystemError,
+    process::scheduler,
+};
+
+use super::clock::NANOS_PER_SEC;
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+/// Set when the watchdog fires, so that only one CPU handles it
+static FIRED: AtomicBool = AtomicBool::new(false);
+/// Time since startup of the last pet, in nanoseconds
+static LAST_PET: AtomicU64 = AtomicU64::new(0);
+
+fn timeout_nanos() -> u64 {
+    cmdline::cmdline().watchdog_timeout as u64 * NANOS_PER_SEC
+}
+
+fn pet() {
+    let Some(clocks) = clock::try_clocks() else {
+        return;
+    };
+    LAST_PET.store(clocks.time_since_startup().as_nanos(), Ordering::Release);
+    if !ARMED.swap(true, Ordering::AcqRel) {
+        info!(
+            "Watchdog armed, timeout: {}s",
+            cmdline::cmdline().watchdog_timeout
+        );
+    }
+}
+
+fn disarm() {
+    if ARMED.swap(false, Ordering::AcqRel) {
+        info!("Watchdog disarmed");
+    }
+}
+
+/// Check if the watchdog expired, and reboot if it did, called from the timer interrupt
+pub fn check() {
+    if !ARMED.load(Ordering::Acquire) {
+        return;
+    }
+    let Some(clocks) = clock::try_clocks() else {
+        return;
+    };
+    let elapsed = clocks
+        .time_since_startup()
+        .as_nanos()
+        .saturating_sub(LAST_PET.load(Ordering::Acquire));
+    if elapsed < timeout_nanos() || FIRED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    error!(
+        "Watchdog: not petted for {}ms, the system is stuck, rebooting",
+        elapsed / (NANOS_PER_SEC / 1000)
+    );
+    scheduler::log_processes();
+    // don't go through the power sequence, the scheduler may be the one stuck
+    keyboard_mouse::reset_system();
+}
+
+/// Watchdog device
+///
+/// Any write to it pets the watchdog (and arms it on the first write), writing `disable` disarms it,
+/// i.e. before a clean shutdown. Does nothing if `watchdog_timeout` is `0`.
+///
+/// Usage: `echo > /devices/watchdog` periodically.
+#[derive(Debug)]
+pub struct WatchdogDevice;
+
+impl Device for WatchdogDevice {
+    fn name(&self) -> &str {
+        "watchdog"
+    }
+
+    // needed for `echo`, as it truncates the file before writing, same as `power`
+    fn set_size(&self, size: u64) -> Result<(), FileSystemError> {
+        if size != 0 {
+            return Err(FileSystemError::EndOfFile);
+        }
+        Ok(())
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
+        if cmdline::cmdline().watchdog_timeout == 0 {
+            return Ok(buf.len() as u64);
+        }
+
+        if buf.trim_ascii() == b"disable" {
+            disarm();
+        } else {
+            pet();
+        }
+        Ok(buf.len() as u64)
+    }
+
+    /// Nothing pets the watchdog while the processes are stopped
+    fn suspend(&self) -> Result<(), FileSystemError> {
+        disarm();
+        Ok(())
+    }
+}
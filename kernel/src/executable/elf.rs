@@ -2,7 +2,22 @@ use core::{ffi::CStr, fmt, mem, ops::Deref};
 
 use alloc::{string::String, vec, vec::Vec};
 
-use crate::{fs, memory_management::virtual_memory_mapper};
+use crate::{
+    fs,
+    memory_management::{
+        memory_layout::{align_down, align_up, GB, PAGE_4K},
+        virtual_memory_mapper,
+    },
+};
+
+use super::integrity::{self, IntegrityError};
+
+/// The lowest address a segment can be loaded at, the first page is never mapped so that null
+/// pointers fault
+const USER_IMAGE_START: u64 = PAGE_4K as u64;
+/// The end of the lower half, with room left after the image for the heap (see `process`).
+/// The stack and the process metadata are in the upper half, so can't be overlapped
+const USER_IMAGE_END: u64 = 0x0000_8000_0000_0000 - 4 * GB as u64;
 
 #[derive(Debug)]
 pub enum ElfLoadError {
@@ -10,6 +25,21 @@ pub enum ElfLoadError {
     FileSystemError(fs::FileSystemError),
     InvalidElfOrNotSupported,
     UnexpectedEndOfFile,
+    IntegrityCheckFailed(IntegrityError),
+    /// A table or segment points outside the file
+    OutOfFileBounds,
+    InvalidSectionName,
+    /// `file_size > mem_size`, or the segment wraps around
+    InvalidSegmentSize,
+    InvalidAlignment,
+    /// The segment is outside the user image range, or its physical address doesn't match
+    InvalidAddress,
+    /// Two segments share a page
+    OverlappingSegments,
+    InvalidEntryPoint,
+    NoLoadSegments,
+    /// `PT_PHDR` is missing or not inside a `Load` segment
+    InvalidProgramHeaderAddress,
 }
 
 impl From<fs::FileSystemError> for ElfLoadError {
@@ -18,6 +48,14 @@ impl From<fs::FileSystemError> for ElfLoadError {
     }
 }
 
+/// Check that `size` bytes at `offset` are inside a file of `file_size` bytes
+fn check_in_file(offset: u64, size: u64, file_size: u64) -> Result<(), ElfLoadError> {
+    match offset.checked_add(size) {
+        Some(end) if end <= file_size => Ok(()),
+        _ => Err(ElfLoadError::OutOfFileBounds),
+    }
+}
+
 #[allow(dead_code)]
 mod consts {
     pub const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
@@ -647,15 +685,25 @@ pub struct ElfSection {
 }
 
 impl ElfSection {
-    pub fn new(inner: ElfSectionInner, string_table: &[u8]) -> Self {
-        let name_index = inner.name_index();
-        let name = String::from(
-            CStr::from_bytes_until_nul(&string_table[name_index as usize..])
-                .unwrap()
-                .to_str()
-                .unwrap(),
-        );
-        Self { name, inner }
+    /// Create a section with its name from `string_table`, if the table is empty (the file has
+    /// no section names), the name is empty
+    pub fn new(inner: ElfSectionInner, string_table: &[u8]) -> Result<Self, ElfLoadError> {
+        if string_table.is_empty() {
+            return Ok(Self {
+                name: String::new(),
+                inner,
+            });
+        }
+
+        let name = string_table
+            .get(inner.name_index() as usize..)
+            .and_then(|bytes| CStr::from_bytes_until_nul(bytes).ok())
+            .and_then(|name| name.to_str().ok())
+            .ok_or(ElfLoadError::InvalidSectionName)?;
+        Ok(Self {
+            name: String::from(name),
+            inner,
+        })
     }
 
     pub fn name(&self) -> &str {
@@ -697,6 +745,9 @@ pub struct Elf {
 
 impl Elf {
     pub fn load(file: &mut fs::File) -> Result<Self, ElfLoadError> {
+        // this will only do the check if enabled in the cmdline
+        integrity::verify_binary(file).map_err(ElfLoadError::IntegrityCheckFailed)?;
+
         // take the largest
         let mut header = [0u8; mem::size_of::<ElfHeader>()];
         if file.read(&mut header)? != header.len() as u64 {
@@ -710,6 +761,13 @@ impl Elf {
         if !header.is_valid_and_supported() {
             return Err(ElfLoadError::InvalidElfOrNotSupported);
         }
+        let file_size = file.size();
+
+        check_in_file(
+            header.program_header_offset(),
+            header.program_header_entry_size() * header.program_header_entry_count(),
+            file_size,
+        )?;
         file.seek(header.program_header_offset())?;
         let mut program_headers = Vec::with_capacity(header.program_header_entry_count() as usize);
 
@@ -719,25 +777,9 @@ impl Elf {
             program_headers.push(program);
         }
 
-        let string_table_index = header.section_header_string_table_index() as usize;
-        assert!(string_table_index < header.section_header_entry_count() as usize);
-        let string_table_position = header.section_header_offset()
-            + header.section_header_entry_size() * string_table_index as u64;
-        file.seek(string_table_position)?;
-        let string_table_section =
-            ElfSectionInner::load(file, header.is_elf64(), header.section_header_entry_size())?;
-        let mut string_table = vec![0u8; string_table_section.size() as usize];
-        file.seek(string_table_section.offset())?;
-        file.read(&mut string_table)?;
+        Self::validate_segments(&header, &program_headers, file_size)?;
 
-        file.seek(header.section_header_offset())?;
-        let mut sections = Vec::with_capacity(header.section_header_entry_count() as usize);
-        for _ in 0..header.section_header_entry_count() {
-            let section_inner =
-                ElfSectionInner::load(file, header.is_elf64(), header.section_header_entry_size())?;
-            let section = ElfSection::new(section_inner, &string_table);
-            sections.push(section);
-        }
+        let sections = Self::load_sections(file, &header, file_size)?;
 
         Ok(Self {
             header,
@@ -746,6 +788,144 @@ impl Elf {
         })
     }
 
+    /// Load the sections with their names, the sections are optional for executables, so a file
+    /// without them is fine
+    fn load_sections(
+        file: &mut fs::File,
+        header: &ElfHeader,
+        file_size: u64,
+    ) -> Result<Vec<ElfSection>, ElfLoadError> {
+        let count = header.section_header_entry_count();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        check_in_file(
+            header.section_header_offset(),
+            header.section_header_entry_size() * count,
+            file_size,
+        )?;
+
+        // index `0` (`SHN_UNDEF`) means there are no section names
+        let string_table_index = header.section_header_string_table_index();
+        let string_table = if string_table_index == 0 {
+            Vec::new()
+        } else {
+            if string_table_index >= count {
+                return Err(ElfLoadError::InvalidElfOrNotSupported);
+            }
+            file.seek(
+                header.section_header_offset()
+                    + header.section_header_entry_size() * string_table_index,
+            )?;
+            let string_table_section =
+                ElfSectionInner::load(file, header.is_elf64(), header.section_header_entry_size())?;
+            check_in_file(
+                string_table_section.offset(),
+                string_table_section.size(),
+                file_size,
+            )?;
+            let mut string_table = vec![0u8; string_table_section.size() as usize];
+            if file.read_at(string_table_section.offset(), &mut string_table)?
+                != string_table.len() as u64
+            {
+                return Err(ElfLoadError::UnexpectedEndOfFile);
+            }
+            string_table
+        };
+
+        file.seek(header.section_header_offset())?;
+        let mut sections = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let section_inner =
+                ElfSectionInner::load(file, header.is_elf64(), header.section_header_entry_size())?;
+            sections.push(ElfSection::new(section_inner, &string_table)?);
+        }
+        Ok(sections)
+    }
+
+    /// Make sure the `Load` segments can be mapped as is into a user process, and that the
+    /// entry point and the program headers are inside them
+    fn validate_segments(
+        header: &ElfHeader,
+        program_headers: &[ElfProgram],
+        file_size: u64,
+    ) -> Result<(), ElfLoadError> {
+        // (start, end) of each segment in memory
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+
+        for segment in program_headers
+            .iter()
+            .filter(|s| matches!(s.ty(), ElfProgramType::Load))
+        {
+            check_in_file(segment.offset(), segment.file_size(), file_size)?;
+            if segment.file_size() > segment.mem_size() {
+                return Err(ElfLoadError::InvalidSegmentSize);
+            }
+            let start = segment.virtual_address();
+            if start != segment.physical_address() {
+                return Err(ElfLoadError::InvalidAddress);
+            }
+            // `0` and `1` mean no alignment
+            let alignment = segment.alignment();
+            if alignment > 1
+                && (!alignment.is_power_of_two()
+                    || start % alignment != segment.offset() % alignment)
+            {
+                return Err(ElfLoadError::InvalidAlignment);
+            }
+            if segment.mem_size() == 0 {
+                continue;
+            }
+
+            let end = start
+                .checked_add(segment.mem_size())
+                .ok_or(ElfLoadError::InvalidSegmentSize)?;
+            if start < USER_IMAGE_START || end > USER_IMAGE_END {
+                return Err(ElfLoadError::InvalidAddress);
+            }
+            // each segment is mapped with its own flags, so they can't share a page
+            let page_start = align_down(start as usize, PAGE_4K) as u64;
+            let page_end = align_up(end as usize, PAGE_4K) as u64;
+            if ranges.iter().any(|&(other_start, other_end)| {
+                page_start < align_up(other_end as usize, PAGE_4K) as u64
+                    && (align_down(other_start as usize, PAGE_4K) as u64) < page_end
+            }) {
+                return Err(ElfLoadError::OverlappingSegments);
+            }
+            ranges.push((start, end));
+        }
+
+        if ranges.is_empty() {
+            return Err(ElfLoadError::NoLoadSegments);
+        }
+
+        let entry = header.entry();
+        if !ranges
+            .iter()
+            .any(|&(start, end)| (start..end).contains(&entry))
+        {
+            return Err(ElfLoadError::InvalidEntryPoint);
+        }
+
+        // needed to fill the process metadata, i.e. for thread local storage
+        let program_headers_segment = program_headers
+            .iter()
+            .find(|s| matches!(s.ty(), ElfProgramType::ProgramHeader))
+            .ok_or(ElfLoadError::InvalidProgramHeaderAddress)?;
+        let phdr_start = program_headers_segment.virtual_address();
+        let phdr_end = phdr_start
+            .checked_add(program_headers_segment.mem_size())
+            .ok_or(ElfLoadError::InvalidProgramHeaderAddress)?;
+        if !ranges
+            .iter()
+            .any(|&(start, end)| start <= phdr_start && phdr_end <= end)
+        {
+            return Err(ElfLoadError::InvalidProgramHeaderAddress);
+        }
+
+        Ok(())
+    }
+
     pub fn entry_point(&self) -> u64 {
         self.header.entry()
     }
@@ -0,0 +1,124 @@
+//! Verification of userspace binaries against the manifest generated at build time
+//!
+//! The manifest is at [`MANIFEST_PATH`] in the same format as `sha256sum`, i.e.
+//! `<hex hash>  <absolute path>` per line.
+//! The manifest is loaded once on first use, so modifying it afterwards has no effect.
+
+use alloc::{collections::BTreeMap, string::String};
+use emerald_crypto::sha256::{self, Sha256};
+use tracing::{error, info};
+
+use crate::{cmdline, fs, sync::once::OnceLock};
+
+const MANIFEST_PATH: &str = "/binaries.manifest";
+
+/// `None` if the manifest couldn't be loaded, in that case all binaries are refused
+static MANIFEST: OnceLock<Option<BTreeMap<String, [u8; sha256::DIGEST_SIZE]>>> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum IntegrityError {
+    ManifestNotAvailable,
+    NotInManifest,
+    HashMismatch,
+    FileSystemError(fs::FileSystemError),
+}
+
+impl From<fs::FileSystemError> for IntegrityError {
+    fn from(e: fs::FileSystemError) -> Self {
+        Self::FileSystemError(e)
+    }
+}
+
+fn parse_hex_digest(hex: &str) -> Option<[u8; sha256::DIGEST_SIZE]> {
+    if hex.len() != sha256::DIGEST_SIZE * 2 {
+        return None;
+    }
+    let mut digest = [0; sha256::DIGEST_SIZE];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+fn load_manifest() -> Option<BTreeMap<String, [u8; sha256::DIGEST_SIZE]>> {
+    let content = match fs::File::open(MANIFEST_PATH).and_then(|mut f| f.read_to_end()) {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Could not load binaries manifest {MANIFEST_PATH}: {e:?}");
+            return None;
+        }
+    };
+    let Ok(content) = core::str::from_utf8(&content) else {
+        error!("Binaries manifest {MANIFEST_PATH} is not valid UTF-8");
+        return None;
+    };
+
+    let mut manifest = BTreeMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((hash, path)) = line.split_once(char::is_whitespace) else {
+            error!("Binaries manifest: invalid line {}: {line:?}", i + 1);
+            return None;
+        };
+        let Some(hash) = parse_hex_digest(hash) else {
+            error!(
+                "Binaries manifest: invalid hash at line {}: {line:?}",
+                i + 1
+            );
+            return None;
+        };
+        manifest.insert(String::from(path.trim()), hash);
+    }
+    info!("Loaded binaries manifest with {} entries", manifest.len());
+
+    Some(manifest)
+}
+
+fn hash_file(file: &mut fs::File) -> Result<[u8; sha256::DIGEST_SIZE], fs::FileSystemError> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 512];
+
+    file.seek(0)?;
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read as usize]);
+    }
+    file.seek(0)?;
+
+    Ok(hasher.finalize())
+}
+
+/// Verify `file` against the manifest, if `verify_binaries` is enabled in the cmdline
+///
+/// The file position will be reset to the start.
+pub fn verify_binary(file: &mut fs::File) -> Result<(), IntegrityError> {
+    if !cmdline::cmdline().verify_binaries {
+        return Ok(());
+    }
+
+    let manifest = MANIFEST
+        .get_or_init(load_manifest)
+        .as_ref()
+        .ok_or(IntegrityError::ManifestNotAvailable)?;
+
+    // owned, since `file` is borrowed mutably to hash it
+    let path = String::from(file.path().as_str());
+    let Some(expected) = manifest.get(&path) else {
+        error!("Refusing to execute {path:?}: not in binaries manifest");
+        return Err(IntegrityError::NotInManifest);
+    };
+
+    let hash = hash_file(file)?;
+    if !emerald_crypto::constant_time_eq(&hash, expected) {
+        error!("Refusing to execute {path:?}: hash mismatch");
+        return Err(IntegrityError::HashMismatch);
+    }
+
+    Ok(())
+}
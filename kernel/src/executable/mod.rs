@@ -1,28 +1,146 @@
 use kernel_user_link::process::ProcessMetadata;
 use tracing::trace;
 
-use crate::{cpu, fs, memory_management::virtual_memory_mapper};
+use crate::{
+    fs,
+    memory_management::{
+        memory_layout::{align_down, align_up, physical2virtual, PAGE_4K},
+        shared_pages, virtual_memory_mapper,
+    },
+};
 
 pub mod elf;
+pub mod integrity;
 
-/// # Safety
-/// The `vm` passed must be an exact kernel clone to the current vm
-/// without loading new process specific mappings
-pub unsafe fn load_elf_to_vm(
+/// Copy `len` bytes from `file` at `file_offset` into `vm` at `virtual_address`.
+///
+/// This is done through the kernel mapping of the physical pages, so it works for read-only pages
+/// and without switching to the `vm`.
+fn read_file_to_vm(
+    vm: &mut virtual_memory_mapper::VirtualMemoryMapper,
+    file: &mut fs::File,
+    mut file_offset: u64,
+    mut virtual_address: usize,
+    mut len: usize,
+) -> Result<(), fs::FileSystemError> {
+    while len > 0 {
+        let chunk_len = (PAGE_4K - virtual_address % PAGE_4K).min(len);
+        let physical_address = vm
+            .virtual_to_physical(virtual_address)
+            .expect("segment must be mapped");
+        let ptr = physical2virtual(physical_address) as *mut u8;
+        // SAFETY: the page is mapped and allocated for this vm, and we don't cross the page boundary
+        let slice = unsafe { core::slice::from_raw_parts_mut(ptr, chunk_len) };
+        assert_eq!(file.read_at(file_offset, slice)?, chunk_len as u64);
+
+        file_offset += chunk_len as u64;
+        virtual_address += chunk_len;
+        len -= chunk_len;
+    }
+    Ok(())
+}
+
+/// Load a `Load` segment into the `vm`.
+///
+/// Pages that are fully backed by the file are shared with other processes loading the same file
+/// (see [`shared_pages`]), writable pages are mapped as copy-on-write.
+fn load_segment(
+    segment: &elf::ElfProgram,
+    file: &mut fs::File,
+    vm: &mut virtual_memory_mapper::VirtualMemoryMapper,
+    flags: u64,
+) -> Result<(), fs::FileSystemError> {
+    let segment_virtual = segment.virtual_address() as usize;
+    let file_size = segment.file_size() as usize;
+    let file_end = segment_virtual + file_size;
+    let mem_end = segment_virtual + segment.mem_size() as usize;
+    let file_offset_of =
+        |virtual_address: usize| segment.offset() + (virtual_address - segment_virtual) as u64;
+
+    let map = |vm: &mut virtual_memory_mapper::VirtualMemoryMapper, start: usize, end: usize| {
+        let entry = virtual_memory_mapper::VirtualMemoryMapEntry {
+            virtual_address: start,
+            physical_address: None,
+            size: end - start,
+            flags,
+        };
+        trace!("Mapping segment: {:x?}", entry);
+        vm.map(&entry);
+    };
+
+    let shared_start = align_up(segment_virtual, PAGE_4K);
+    let shared_end = align_down(file_end, PAGE_4K);
+    if shared_end <= shared_start {
+        // no full pages from the file, load it all privately
+        map(vm, segment_virtual, mem_end);
+        return read_file_to_vm(vm, file, segment.offset(), segment_virtual, file_size);
+    }
+
+    // the start, not aligned to a page
+    if segment_virtual < shared_start {
+        map(vm, segment_virtual, shared_start);
+        read_file_to_vm(
+            vm,
+            file,
+            segment.offset(),
+            segment_virtual,
+            shared_start - segment_virtual,
+        )?;
+    }
+
+    let mut shared_flags = (flags & !virtual_memory_mapper::flags::PTE_WRITABLE)
+        | virtual_memory_mapper::flags::PTE_SHARED;
+    if flags & virtual_memory_mapper::flags::PTE_WRITABLE != 0 {
+        shared_flags |= virtual_memory_mapper::flags::PTE_COW;
+    }
+    let (filesystem, inode) = file.content_id();
+    for page in (shared_start..shared_end).step_by(PAGE_4K) {
+        let offset = file_offset_of(page);
+        let key = shared_pages::SharedPageKey {
+            filesystem,
+            inode,
+            offset,
+        };
+        let physical_address = shared_pages::get_or_load(key, |content| {
+            assert_eq!(file.read_at(offset, content)?, PAGE_4K as u64);
+            Ok::<_, fs::FileSystemError>(())
+        })?;
+        vm.map(&virtual_memory_mapper::VirtualMemoryMapEntry {
+            virtual_address: page,
+            physical_address: Some(physical_address),
+            size: PAGE_4K,
+            flags: shared_flags,
+        });
+    }
+
+    // the end, the rest of the file and the zeroed memory
+    if shared_end < mem_end {
+        map(vm, shared_end, mem_end);
+        if shared_end < file_end {
+            read_file_to_vm(
+                vm,
+                file,
+                file_offset_of(shared_end),
+                shared_end,
+                file_end - shared_end,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Load the `elf` segments into `vm`, and fill the `process_meta` with the image information
+///
+/// The `vm` is not switched to, the memory is written through the kernel mapping.
+/// The segments are already validated by [`elf::Elf::load`], so they fit in the user range and
+/// don't overlap.
+pub fn load_elf_to_vm(
     elf: &elf::Elf,
     file: &mut fs::File,
     process_meta: &mut ProcessMetadata,
     vm: &mut virtual_memory_mapper::VirtualMemoryMapper,
 ) -> Result<(usize, usize), fs::FileSystemError> {
-    // we can't be interrupted and load another process vm in the middle of this work
-    cpu::cpu().push_cli();
-    let old_vm = virtual_memory_mapper::get_current_vm();
-
-    // switch temporarily so we can map the elf
-    // SAFETY: this must be called while the current vm and this new vm must share the same
-    //         kernel regions
-    vm.switch_to_this();
-
     let mut min_address = usize::MAX;
     let mut max_address = 0;
     let mut phdr_address = 0;
@@ -30,31 +148,14 @@ pub unsafe fn load_elf_to_vm(
     for segment in elf.program_headers() {
         match segment.ty() {
             elf::ElfProgramType::Load => {
-                let segment_virtual = segment.virtual_address();
-                assert_eq!(segment_virtual, segment.physical_address());
+                let segment_virtual = segment.virtual_address() as usize;
 
                 let mut flags = elf::to_virtual_memory_flags(segment.flags());
                 flags |= virtual_memory_mapper::flags::PTE_USER;
-                let entry = virtual_memory_mapper::VirtualMemoryMapEntry {
-                    virtual_address: segment_virtual as usize,
-                    physical_address: None,
-                    size: segment.mem_size() as usize,
-                    flags,
-                };
-                min_address = min_address.min(entry.virtual_address);
-                max_address = max_address.max(entry.virtual_address + entry.size);
-                trace!("Mapping segment: {:x?}", entry);
-                vm.map(&entry);
-
-                // read the file into the memory
-                file.seek(segment.offset())?;
-
-                let ptr = segment_virtual as *mut u8;
-                let slice =
-                    unsafe { core::slice::from_raw_parts_mut(ptr, segment.file_size() as usize) };
-
-                // read the whole segment
-                assert_eq!(file.read(slice)?, segment.file_size());
+                min_address = min_address.min(segment_virtual);
+                max_address = max_address.max(segment_virtual + segment.mem_size() as usize);
+
+                load_segment(segment, file, vm, flags)?;
             }
             elf::ElfProgramType::ProgramHeader => {
                 phdr_address = segment.virtual_address() as usize;
@@ -75,7 +176,8 @@ pub unsafe fn load_elf_to_vm(
 
     process_meta.image_base = min_address;
     process_meta.image_size = max_address - min_address;
-    assert!(phdr_address >= min_address && phdr_address < max_address);
+    // validated by `Elf::load`
+    debug_assert!(phdr_address >= min_address && phdr_address < max_address);
     process_meta.program_headers_offset = phdr_address - min_address;
 
     // reset if we got an invalid eh_frame, its optional
@@ -84,10 +186,5 @@ pub unsafe fn load_elf_to_vm(
         process_meta.eh_frame_size = 0;
     }
 
-    // switch back to the old vm
-    old_vm.switch_to_this();
-    // we can be interrupted again
-    cpu::cpu().pop_cli();
-
     Ok((min_address, max_address))
 }
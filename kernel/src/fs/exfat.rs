@@ -0,0 +1,623 @@
+//! exFAT filesystem (read only)
+//!
+//! Files and directories are read following the FAT, or directly if their clusters are contiguous (`NoFatChain`).
+//! The up-case table is not used, names are matched with ASCII case folding like FAT.
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+use kernel_user_link::file::FileSystemStat;
+
+use crate::{devices::ide::IdeDevice, io::NoDebug, sync::adaptive_mutex::AdaptiveMutex};
+
+use super::{
+    path::CaseSensitivity, AccessHelper, DirTreverse, DirectoryNode, FileAttributes, FileNode,
+    FileSystem, FileSystemError, Node,
+};
+
+const DIRECTORY_ENTRY_SIZE: usize = 32;
+const FILE_NAME_CHARS_PER_ENTRY: usize = 15;
+
+const FAT_END_OF_CHAIN: u32 = 0xFFFF_FFFF;
+
+mod entry_type {
+    pub const END_OF_DIRECTORY: u8 = 0x00;
+    pub const ALLOCATION_BITMAP: u8 = 0x81;
+    pub const VOLUME_LABEL: u8 = 0x83;
+    pub const FILE: u8 = 0x85;
+    pub const STREAM_EXTENSION: u8 = 0xC0;
+    pub const FILE_NAME: u8 = 0xC1;
+}
+
+/// `GeneralSecondaryFlags` of the stream extension, the clusters are contiguous and the FAT is not used
+const FLAG_NO_FAT_CHAIN: u8 = 1 << 1;
+
+/// The same bits as [`FileAttributes`], without the volume label bit which is reserved in exFAT
+const ATTRIBUTES_MASK: u16 = 0x37;
+
+// The `start_cluster` of the nodes holds the first cluster in the low 32 bits, and these flags
+/// The clusters of the node are contiguous
+const NODE_CONTIGUOUS: u64 = 1 << 32;
+/// The `ValidDataLength` of the file is less than its size, the rest reads as zeros
+const NODE_PARTIALLY_VALID: u64 = 1 << 33;
+/// For contiguous directories, the number of clusters is stored from this bit
+const NODE_CLUSTERS_SHIFT: u64 = 34;
+const NODE_CLUSTER_MASK: u64 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExFatError {
+    InvalidBootSector,
+    UnexpectedFatEntry,
+}
+
+impl From<ExFatError> for FileSystemError {
+    fn from(e: ExFatError) -> Self {
+        FileSystemError::ExFatError(e)
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_device(
+    device: &IdeDevice,
+    start_lba: u32,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<(), FileSystemError> {
+    let sector = start_lba as u64 + offset / device.sector_size() as u64;
+    device
+        .read_sync(sector, buf)
+        .map_err(|error| FileSystemError::DiskReadError { sector, error })
+}
+
+/// Check the boot signature of the partition starting at `start_lba`, the partition type
+/// (`0x07`) is shared with NTFS, so it's not enough
+pub fn is_exfat(device: &IdeDevice, start_lba: u32) -> Result<bool, FileSystemError> {
+    let mut sector = vec![0; device.sector_size() as usize];
+    read_device(device, start_lba, 0, &mut sector)?;
+    Ok(&sector[3..11] == b"EXFAT   ")
+}
+
+pub fn load_exfat_filesystem(
+    device: Arc<IdeDevice>,
+    start_lba: u32,
+    size_in_sectors: u32,
+) -> Result<ExFatFilesystem, FileSystemError> {
+    let mut sector = vec![0; (device.sector_size() as usize).max(512)];
+    read_device(&device, start_lba, 0, &mut sector)?;
+    let boot_sector = BootSector::parse(&sector)?;
+
+    let volume_size = size_in_sectors as u64 * device.sector_size() as u64;
+    let heap_end = boot_sector.cluster_offset(2)
+        + boot_sector.cluster_count as u64 * boot_sector.bytes_per_cluster() as u64;
+    if heap_end > volume_size {
+        return Err(ExFatError::InvalidBootSector.into());
+    }
+
+    ExFatFilesystem::new(start_lba, boot_sector, device)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BootSector {
+    fat_offset: u32,
+    cluster_heap_offset: u32,
+    cluster_count: u32,
+    root_cluster: u32,
+    bytes_per_sector_shift: u8,
+    sectors_per_cluster_shift: u8,
+}
+
+impl BootSector {
+    fn parse(sector: &[u8]) -> Result<Self, ExFatError> {
+        if &sector[3..11] != b"EXFAT   "
+            || u16_at(sector, 510) != 0xAA55
+            // `MustBeZero`, where the BPB of FAT would be
+            || sector[11..64].iter().any(|&b| b != 0)
+        {
+            return Err(ExFatError::InvalidBootSector);
+        }
+
+        let boot_sector = Self {
+            fat_offset: u32_at(sector, 80),
+            cluster_heap_offset: u32_at(sector, 88),
+            cluster_count: u32_at(sector, 92),
+            root_cluster: u32_at(sector, 96),
+            bytes_per_sector_shift: sector[108],
+            sectors_per_cluster_shift: sector[109],
+        };
+
+        // clusters are at most 32MB
+        if !(9..=12).contains(&boot_sector.bytes_per_sector_shift)
+            || boot_sector.bytes_per_sector_shift as u32
+                + boot_sector.sectors_per_cluster_shift as u32
+                > 25
+            || boot_sector.cluster_count == 0
+            || !boot_sector.is_valid_cluster(boot_sector.root_cluster)
+        {
+            return Err(ExFatError::InvalidBootSector);
+        }
+
+        Ok(boot_sector)
+    }
+
+    fn bytes_per_sector(&self) -> u32 {
+        1 << self.bytes_per_sector_shift
+    }
+
+    fn bytes_per_cluster(&self) -> u32 {
+        1 << (self.bytes_per_sector_shift + self.sectors_per_cluster_shift)
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count as u64 + 2).contains(&(cluster as u64))
+    }
+
+    /// Offset in bytes of `cluster` from the start of the volume
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        ((self.cluster_heap_offset as u64) << self.bytes_per_sector_shift)
+            + ((cluster as u64 - 2)
+                << (self.bytes_per_sector_shift + self.sectors_per_cluster_shift))
+    }
+}
+
+/// Compute the checksum of a directory entry set, skipping the checksum field in the first entry
+fn entry_set_checksum(entries: &[u8]) -> u16 {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 2 && *i != 3)
+        .fold(0u16, |checksum, (_, &b)| {
+            checksum.rotate_right(1).wrapping_add(b as u16)
+        })
+}
+
+#[derive(Debug)]
+pub struct ExFatFilesystem {
+    start_lba: u32,
+    boot_sector: BootSector,
+    device: NoDebug<Arc<IdeDevice>>,
+    volume_label: String,
+    /// Counted from the allocation bitmap when mounting, it doesn't change as we don't write
+    free_clusters: u32,
+    /// The last read sector of the FAT, following a chain reads the same sector many times
+    fat_sector_cache: Option<(u32, NoDebug<Vec<u8>>)>,
+}
+
+impl ExFatFilesystem {
+    fn new(
+        start_lba: u32,
+        boot_sector: BootSector,
+        device: Arc<IdeDevice>,
+    ) -> Result<Self, FileSystemError> {
+        let mut s = Self {
+            start_lba,
+            boot_sector,
+            device: NoDebug(device),
+            volume_label: String::new(),
+            free_clusters: 0,
+            fat_sector_cache: None,
+        };
+
+        // the allocation bitmap and the label are in the root directory
+        let root = s.read_directory(s.open_root_dir_inode().start_cluster())?;
+        let mut bitmap = None;
+        for entry in root.data.chunks_exact(DIRECTORY_ENTRY_SIZE) {
+            match entry[0] {
+                entry_type::END_OF_DIRECTORY => break,
+                // the second bitmap is only used with 2 FATs (TexFAT)
+                entry_type::ALLOCATION_BITMAP if bitmap.is_none() => {
+                    bitmap = Some((u32_at(entry, 20), u64_at(entry, 24)));
+                }
+                entry_type::VOLUME_LABEL => {
+                    let len = (entry[1] as usize).min(11);
+                    let chars = (0..len).map(|i| u16_at(entry, 2 + i * 2));
+                    s.volume_label = char::decode_utf16(chars)
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+
+        let (bitmap_cluster, bitmap_length) = bitmap.ok_or(ExFatError::InvalidBootSector)?;
+        if bitmap_length < (s.boot_sector.cluster_count as u64).div_ceil(8) {
+            return Err(ExFatError::InvalidBootSector.into());
+        }
+        let bitmap = s.read_chain(bitmap_cluster, false, bitmap_length)?;
+        let used = (0..s.boot_sector.cluster_count as usize)
+            .filter(|&i| bitmap[i / 8] & (1 << (i % 8)) != 0)
+            .count() as u32;
+        s.free_clusters = s.boot_sector.cluster_count - used;
+
+        Ok(s)
+    }
+
+    pub fn volume_label(&self) -> &str {
+        &self.volume_label
+    }
+
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<(), FileSystemError> {
+        read_device(&self.device, self.start_lba, offset, buf)
+    }
+
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FileSystemError> {
+        if !self.boot_sector.is_valid_cluster(cluster) {
+            return Err(ExFatError::UnexpectedFatEntry.into());
+        }
+        let bytes_per_sector = self.boot_sector.bytes_per_sector();
+        let entries_per_sector = bytes_per_sector / 4;
+        let sector = self.boot_sector.fat_offset + cluster / entries_per_sector;
+
+        if self.fat_sector_cache.as_ref().map(|(s, _)| *s) != Some(sector) {
+            let mut data = vec![0; bytes_per_sector as usize];
+            self.read_bytes(
+                (sector as u64) << self.boot_sector.bytes_per_sector_shift,
+                &mut data,
+            )?;
+            self.fat_sector_cache = Some((sector, NoDebug(data)));
+        }
+        let data = &self.fat_sector_cache.as_ref().unwrap().1 .0;
+
+        match u32_at(data, (cluster % entries_per_sector * 4) as usize) {
+            FAT_END_OF_CHAIN => Ok(None),
+            next if self.boot_sector.is_valid_cluster(next) => Ok(Some(next)),
+            _ => Err(ExFatError::UnexpectedFatEntry.into()),
+        }
+    }
+
+    /// Read `length` bytes of the clusters starting at `first`, which are contiguous or follow the FAT
+    fn read_chain(
+        &mut self,
+        first: u32,
+        contiguous: bool,
+        length: u64,
+    ) -> Result<Vec<u8>, FileSystemError> {
+        let bytes_per_cluster = self.boot_sector.bytes_per_cluster() as usize;
+        let mut data = vec![0; (length as usize).div_ceil(bytes_per_cluster) * bytes_per_cluster];
+
+        let mut cluster = first;
+        for (i, chunk) in data.chunks_exact_mut(bytes_per_cluster).enumerate() {
+            if i != 0 {
+                cluster = if contiguous {
+                    cluster + 1
+                } else {
+                    self.next_cluster(cluster)?
+                        .ok_or(ExFatError::UnexpectedFatEntry)?
+                };
+            }
+            if !self.boot_sector.is_valid_cluster(cluster) {
+                return Err(ExFatError::UnexpectedFatEntry.into());
+            }
+            self.read_bytes(self.boot_sector.cluster_offset(cluster), chunk)?;
+        }
+        data.truncate(length as usize);
+        Ok(data)
+    }
+
+    /// Read all the entries of the directory, from the `start_cluster` of its node
+    fn read_directory(&mut self, start_cluster: u64) -> Result<DirectoryData, FileSystemError> {
+        let bytes_per_cluster = self.boot_sector.bytes_per_cluster() as usize;
+        let first = (start_cluster & NODE_CLUSTER_MASK) as u32;
+        if !self.boot_sector.is_valid_cluster(first) {
+            return Err(ExFatError::UnexpectedFatEntry.into());
+        }
+
+        let mut clusters = vec![first];
+        if start_cluster & NODE_CONTIGUOUS != 0 {
+            let count = (start_cluster >> NODE_CLUSTERS_SHIFT) as u32;
+            clusters.extend((1..count).map(|i| first + i));
+        } else {
+            while let Some(next) = self.next_cluster(*clusters.last().unwrap())? {
+                // directories are at most 256MB, don't loop forever on a corrupted FAT
+                if clusters.len() * bytes_per_cluster >= 256 * 1024 * 1024 {
+                    return Err(ExFatError::UnexpectedFatEntry.into());
+                }
+                clusters.push(next);
+            }
+        }
+
+        let mut data = vec![0; clusters.len() * bytes_per_cluster];
+        for (&cluster, chunk) in clusters
+            .iter()
+            .zip(data.chunks_exact_mut(bytes_per_cluster))
+        {
+            if !self.boot_sector.is_valid_cluster(cluster) {
+                return Err(ExFatError::UnexpectedFatEntry.into());
+            }
+            self.read_bytes(self.boot_sector.cluster_offset(cluster), chunk)?;
+        }
+
+        Ok(DirectoryData { clusters, data })
+    }
+
+    fn open_root_dir_inode(&self) -> DirectoryNode {
+        DirectoryNode::without_parent(
+            String::from("/"),
+            FileAttributes::DIRECTORY,
+            self.boot_sector.root_cluster as u64,
+        )
+    }
+
+    /// Parse the file entry sets in the directory
+    fn read_dir_nodes(
+        &mut self,
+        inode: &DirectoryNode,
+        handler: &mut dyn FnMut(Node) -> DirTreverse,
+    ) -> Result<(), FileSystemError> {
+        let dir = self.read_directory(inode.start_cluster())?;
+        let entries = dir
+            .data
+            .chunks_exact(DIRECTORY_ENTRY_SIZE)
+            .collect::<Vec<_>>();
+
+        let mut i = 0;
+        while i < entries.len() {
+            let entry = entries[i];
+            if entry[0] == entry_type::END_OF_DIRECTORY {
+                break;
+            }
+            if entry[0] != entry_type::FILE {
+                // unused entries, other primary entries, and secondary entries without a primary
+                i += 1;
+                continue;
+            }
+
+            let secondary_count = entry[1] as usize;
+            let Some(set) = entries.get(i..=i + secondary_count) else {
+                break;
+            };
+            let node = self.parse_entry_set(&dir, i, set);
+            i += 1;
+            // corrupted sets are skipped
+            let Some(node) = node else {
+                continue;
+            };
+            i += secondary_count;
+
+            if let DirTreverse::Stop = handler(node) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse the file entry set at `index` in the directory
+    fn parse_entry_set(&self, dir: &DirectoryData, index: usize, set: &[&[u8]]) -> Option<Node> {
+        let file = set[0];
+        let stream = *set.get(1)?;
+        if stream[0] != entry_type::STREAM_EXTENSION {
+            return None;
+        }
+        let raw = set.concat();
+        if entry_set_checksum(&raw) != u16_at(file, 2) {
+            return None;
+        }
+
+        let name_length = stream[3] as usize;
+        let name_entries = set[2..]
+            .iter()
+            .take_while(|entry| entry[0] == entry_type::FILE_NAME);
+        let chars = name_entries
+            .flat_map(|entry| (0..FILE_NAME_CHARS_PER_ENTRY).map(move |i| u16_at(entry, 2 + i * 2)))
+            .take(name_length);
+        let name = char::decode_utf16(chars)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect::<String>();
+        if name.is_empty() {
+            return None;
+        }
+
+        let attributes = FileAttributes((u16_at(file, 4) & ATTRIBUTES_MASK) as u8);
+        let flags = stream[1];
+        let valid_data_length = u64_at(stream, 8);
+        let first_cluster = u32_at(stream, 20);
+        let data_length = u64_at(stream, 24);
+
+        let mut start_cluster = first_cluster as u64;
+        if flags & FLAG_NO_FAT_CHAIN != 0 {
+            start_cluster |= NODE_CONTIGUOUS;
+            if attributes.directory() {
+                let clusters = data_length.div_ceil(self.boot_sector.bytes_per_cluster() as u64);
+                start_cluster |= clusters << NODE_CLUSTERS_SHIFT;
+            }
+        }
+        if valid_data_length < data_length {
+            start_cluster |= NODE_PARTIALLY_VALID;
+        }
+
+        // point to the stream extension, which has the lengths
+        let (sector, sector_index) = dir.entry_position(&self.boot_sector, index + 1);
+
+        Some(Node::new(
+            name,
+            attributes,
+            start_cluster,
+            data_length,
+            sector,
+            sector_index,
+        ))
+    }
+
+    /// Get the cluster at `index` in the file
+    fn file_cluster_at(
+        &mut self,
+        inode: &FileNode,
+        index: u64,
+        access_helper: &mut AccessHelper,
+    ) -> Result<u32, FileSystemError> {
+        let first = (inode.start_cluster() & NODE_CLUSTER_MASK) as u32;
+        if inode.start_cluster() & NODE_CONTIGUOUS != 0 {
+            return Ok(first + index as u32);
+        }
+
+        // the chains never change, so the extents are always valid
+        if let Some(cluster) = access_helper.cached_cluster(index) {
+            return Ok(cluster as u32);
+        }
+        let (mut current_index, mut cluster) = match access_helper.last_cached_cluster() {
+            Some((i, cluster)) => (i, cluster as u32),
+            None => {
+                access_helper.cache_cluster(0, first as u64);
+                (0, first)
+            }
+        };
+        while current_index < index {
+            cluster = self
+                .next_cluster(cluster)?
+                .ok_or(ExFatError::UnexpectedFatEntry)?;
+            current_index += 1;
+            access_helper.cache_cluster(current_index, cluster as u64);
+        }
+        Ok(cluster)
+    }
+
+    /// The `ValidDataLength` of the file, from its stream extension entry
+    fn valid_data_length(&self, inode: &FileNode) -> Result<u64, FileSystemError> {
+        if inode.start_cluster() & NODE_PARTIALLY_VALID == 0 {
+            return Ok(inode.size());
+        }
+        let mut sector = vec![0; self.boot_sector.bytes_per_sector() as usize];
+        self.read_bytes(
+            inode.parent_dir_sector() << self.boot_sector.bytes_per_sector_shift,
+            &mut sector,
+        )?;
+        let entry = inode.parent_dir_index() as usize * DIRECTORY_ENTRY_SIZE;
+        Ok(u64_at(&sector, entry + 8).min(inode.size()))
+    }
+
+    fn read_file(
+        &mut self,
+        inode: &FileNode,
+        position: u64,
+        buf: &mut [u8],
+        access_helper: &mut AccessHelper,
+    ) -> Result<u64, FileSystemError> {
+        if position >= inode.size() {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(inode.size() - position) as usize;
+        let buf = &mut buf[..len];
+
+        let valid_len = self.valid_data_length(inode)?;
+        let (data, zeros) =
+            buf.split_at_mut(valid_len.saturating_sub(position).min(len as u64) as usize);
+        zeros.fill(0);
+
+        let bytes_per_sector = self.boot_sector.bytes_per_sector() as u64;
+        let bytes_per_cluster = self.boot_sector.bytes_per_cluster() as u64;
+        let mut position = position;
+        let mut done = 0;
+        while done < data.len() {
+            let cluster =
+                self.file_cluster_at(inode, position / bytes_per_cluster, access_helper)?;
+            if !self.boot_sector.is_valid_cluster(cluster) {
+                return Err(ExFatError::UnexpectedFatEntry.into());
+            }
+
+            // read the sectors containing the part we need in this cluster
+            let in_cluster = position % bytes_per_cluster;
+            let now = (bytes_per_cluster - in_cluster).min((data.len() - done) as u64);
+            let start = in_cluster - in_cluster % bytes_per_sector;
+            let end = (in_cluster + now).div_ceil(bytes_per_sector) * bytes_per_sector;
+            let mut sectors = vec![0; (end - start) as usize];
+            self.read_bytes(
+                self.boot_sector.cluster_offset(cluster) + start,
+                &mut sectors,
+            )?;
+
+            let offset = (in_cluster - start) as usize;
+            data[done..done + now as usize]
+                .copy_from_slice(&sectors[offset..offset + now as usize]);
+            done += now as usize;
+            position += now;
+        }
+
+        Ok(len as u64)
+    }
+
+    fn fs_stat(&self) -> FileSystemStat {
+        FileSystemStat {
+            block_size: self.boot_sector.bytes_per_cluster() as u64,
+            total_blocks: self.boot_sector.cluster_count as u64,
+            free_blocks: self.free_clusters as u64,
+        }
+    }
+}
+
+/// The entries of a directory, and the clusters they were read from
+struct DirectoryData {
+    clusters: Vec<u32>,
+    data: Vec<u8>,
+}
+
+impl DirectoryData {
+    /// The sector (from the start of the volume) of the entry at `index`, and its index in that sector
+    fn entry_position(&self, boot_sector: &BootSector, index: usize) -> (u64, u16) {
+        let offset = index * DIRECTORY_ENTRY_SIZE;
+        let bytes_per_cluster = boot_sector.bytes_per_cluster() as usize;
+        let cluster = self.clusters[offset / bytes_per_cluster];
+        let in_cluster = (offset % bytes_per_cluster) as u64;
+
+        let cluster_offset = boot_sector.cluster_offset(cluster) + in_cluster;
+        let sector = cluster_offset >> boot_sector.bytes_per_sector_shift;
+        let sector_index =
+            (in_cluster % boot_sector.bytes_per_sector() as u64) as usize / DIRECTORY_ENTRY_SIZE;
+        (sector, sector_index as u16)
+    }
+}
+
+impl FileSystem for AdaptiveMutex<ExFatFilesystem> {
+    fn type_name(&self) -> &'static str {
+        "exfat"
+    }
+
+    fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
+        Ok(self.lock_or_block()?.open_root_dir_inode())
+    }
+
+    fn read_dir(
+        &self,
+        inode: &DirectoryNode,
+        handler: &mut dyn FnMut(Node) -> DirTreverse,
+    ) -> Result<(), FileSystemError> {
+        self.lock_or_block()?.read_dir_nodes(inode, handler)
+    }
+
+    fn case_sensitivity(&self) -> CaseSensitivity {
+        CaseSensitivity::Insensitive
+    }
+
+    fn read_file(
+        &self,
+        inode: &FileNode,
+        position: u64,
+        buf: &mut [u8],
+        access_helper: &mut AccessHelper,
+    ) -> Result<u64, FileSystemError> {
+        self.lock_or_block()?
+            .read_file(inode, position, buf, access_helper)
+    }
+
+    fn flush_file(
+        &self,
+        _inode: &mut FileNode,
+        _access_helper: &mut AccessHelper,
+    ) -> Result<(), FileSystemError> {
+        // nothing is written
+        Ok(())
+    }
+
+    fn fs_stat(&self) -> Result<FileSystemStat, FileSystemError> {
+        Ok(self.lock_or_block()?.fs_stat())
+    }
+}
@@ -10,20 +10,30 @@ use alloc::{
     vec::Vec,
 };
 
+use kernel_user_link::file::FileSystemStat;
+use tracing::{info, warn};
+
 use crate::{
     devices::ide::IdeDevice,
     io::NoDebug,
     memory_management::memory_layout::{align_down, align_up},
-    sync::spin::mutex::Mutex,
+    sync::adaptive_mutex::AdaptiveMutex,
 };
 
 use super::{
+    path::{self, CaseSensitivity},
     AccessHelper, BaseNode, DirTreverse, DirectoryNode, FileAttributes, FileNode, FileSystem,
     FileSystemError, Node,
 };
 
 const DIRECTORY_ENTRY_SIZE: u32 = 32;
 
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+/// Value of `free_count` and `next_free` in `FSInfo` when they are not known
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
 fn file_attribute_from_fat(attributes: u8) -> FileAttributes {
     let mut file_attributes = FileAttributes::EMPTY;
     if attributes & attrs::READ_ONLY == attrs::READ_ONLY {
@@ -83,28 +93,33 @@ fn create_dir_entries(
     // create short name entry
     let mut short_name = [0; 11];
 
-    let (mut filename, extension) = match name.find('.') {
+    let (filename, extension) = match name.find('.') {
         Some(i) => {
             let (filename, extension) = name.split_at(i);
             (filename, &extension[1..])
         }
         None => (name, ""),
     };
+    // short names are ASCII only, the full name is in the long entries
+    let to_short = |s: &str| {
+        s.chars()
+            .map(|c| if c.is_ascii() { c as u8 } else { b'_' })
+            .collect::<Vec<_>>()
+    };
+    let mut filename = to_short(filename);
+    let extension = to_short(extension);
 
     let mut more_than_8 = false;
 
     if filename.len() > 8 {
-        filename = &filename[..6];
+        filename.truncate(6);
         more_than_8 = true;
-    } else {
-        let len = filename.len().min(8);
-        filename = &filename[..len];
     }
     assert!(filename.len() <= 8);
 
     for (i, c) in short_name.iter_mut().enumerate().take(8) {
         *c = if i < filename.len() {
-            filename.as_bytes()[i].to_ascii_uppercase()
+            filename[i].to_ascii_uppercase()
         } else {
             b' '
         };
@@ -116,7 +131,7 @@ fn create_dir_entries(
 
     for i in 0..3 {
         short_name[8 + i] = if i < extension.len() {
-            extension.as_bytes()[i].to_ascii_uppercase()
+            extension[i].to_ascii_uppercase()
         } else {
             b' '
         };
@@ -140,32 +155,26 @@ fn create_dir_entries(
 
     let short_name_checksum = normal_entry.name_checksum();
 
-    // create long name entries
+    // create long name entries, the long name is in UTF-16, 13 code units per entry
+    let long_name = name.encode_utf16().collect::<Vec<_>>();
     let mut long_name_entries = Vec::new();
     let mut sequence_number = 1;
-    let mut long_name = name;
-    loop {
-        if long_name.is_empty() {
-            break;
-        }
-
-        let len = long_name.len().min(13);
-        let mut name_part = long_name[..len].chars();
-
-        long_name = &long_name[len..];
+    let mut long_name_parts = long_name.chunks(13).peekable();
+    while let Some(part) = long_name_parts.next() {
+        let mut name_part = part.iter().copied();
 
         let mut name1 = [0; 5];
         let mut name2 = [0; 6];
         let mut name3 = [0; 2];
 
         for c in &mut name1 {
-            *c = name_part.next().unwrap_or('\0') as u16;
+            *c = name_part.next().unwrap_or(0);
         }
         for c in &mut name2 {
-            *c = name_part.next().unwrap_or('\0') as u16;
+            *c = name_part.next().unwrap_or(0);
         }
         for c in &mut name3 {
-            *c = name_part.next().unwrap_or('\0') as u16;
+            *c = name_part.next().unwrap_or(0);
         }
 
         let mut entry = DirectoryEntryLong {
@@ -182,7 +191,7 @@ fn create_dir_entries(
         sequence_number += 1;
 
         // mark the last entry
-        if long_name.is_empty() {
+        if long_name_parts.peek().is_none() {
             entry.sequence_number |= 0x40;
         }
 
@@ -292,6 +301,28 @@ struct Fat32ExtendedBootSector {
     boot_signature_2: u16,
 }
 
+/// The `FSInfo` sector of FAT32, hints about the free clusters, so we don't have to scan the FAT
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+#[allow(dead_code)]
+struct FsInfo {
+    lead_signature: u32,
+    reserved: NoDebug<[u8; 480]>,
+    struct_signature: u32,
+    free_count: u32,
+    next_free: u32,
+    reserved_2: [u8; 12],
+    trail_signature: u32,
+}
+
+impl FsInfo {
+    fn is_valid(&self) -> bool {
+        self.lead_signature == FS_INFO_LEAD_SIGNATURE
+            && self.struct_signature == FS_INFO_STRUCT_SIGNATURE
+            && self.trail_signature == FS_INFO_TRAIL_SIGNATURE
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 union FatExtendedBootSector {
@@ -533,6 +564,22 @@ impl FatBootSector {
         self.total_sectors() - self.data_start_sector()
     }
 
+    pub fn clusters_count(&self) -> u32 {
+        self.data_sectors() / self.sectors_per_cluster() as u32
+    }
+
+    /// The sector of the [`FsInfo`] structure, only in FAT32
+    pub fn fs_info_sector(&self) -> Option<u32> {
+        match self.ty {
+            FatType::Fat32 => {
+                let sector = unsafe { self.boot_sector.extended.fat32.fs_info };
+                // 0 and 0xFFFF mean there is no `FSInfo`
+                (sector != 0 && sector != 0xFFFF).then_some(sector as u32)
+            }
+            FatType::Fat12 | FatType::Fat16 => None,
+        }
+    }
+
     pub fn volume_label(&self) -> &[u8; 11] {
         match self.ty {
             FatType::Fat12 | FatType::Fat16 => unsafe {
@@ -773,14 +820,14 @@ impl FatNode {
     pub fn matches(&self, matcher: &str) -> bool {
         // First, check if we have a long name and if it matches
         if let Some(long_name) = &self.long_name {
-            if long_name.eq_ignore_ascii_case(matcher) {
+            if path::eq_ignore_case(long_name, matcher) {
                 return true;
             }
         }
 
         // If no long name match, check the short name
         let short_name = self.normal_entry.name();
-        short_name.eq_ignore_ascii_case(matcher)
+        path::eq_ignore_case(&short_name, matcher)
     }
 }
 
@@ -996,7 +1043,7 @@ impl DirectoryIterator<'_> {
             } else {
                 let long_name = long_entries_name_merge(current_long_entries_name.drain(..));
 
-                if long_name.eq_ignore_ascii_case(&new_entry_long_name) {
+                if path::eq_ignore_case(&long_name, &new_entry_long_name) {
                     return true;
                 }
                 // long name doesn't match, but short one matches, meaning the short name got clipped
@@ -1242,6 +1289,10 @@ struct Fat {
     dirty: bool,
     /// One bit for each sector in the FAT
     dirty_bitmap: Vec<u64>,
+    /// Number of clusters in the data region, entries `2..clusters_count + 2`
+    clusters_count: u32,
+    /// Number of free clusters, `None` if not known yet, see [`Fat::free_clusters`]
+    free_clusters: Option<u32>,
 }
 
 impl Fat {
@@ -1253,6 +1304,8 @@ impl Fat {
             fat_type: FatType::Fat12,
             dirty: false,
             dirty_bitmap: Vec::new(),
+            clusters_count: 0,
+            free_clusters: None,
         }
     }
 
@@ -1270,6 +1323,8 @@ impl Fat {
             fat_type,
             dirty: false,
             dirty_bitmap: vec![0; (fats_size_in_sectors as usize + 63) / 64],
+            clusters_count: filesystem.boot_sector.clusters_count(),
+            free_clusters: None,
         })
     }
 
@@ -1319,7 +1374,8 @@ impl Fat {
         }
     }
 
-    fn read_fat_entry(&self, entry: u32) -> FatEntry {
+    /// Read the value of the FAT entry without interpreting it
+    fn read_raw_entry(&self, entry: u32) -> u32 {
         let fat_offset = match self.fat_type {
             FatType::Fat12 => entry * 3 / 2,
             FatType::Fat16 => entry * 2,
@@ -1328,7 +1384,7 @@ impl Fat {
         assert!(fat_offset < self.buffer.0.len(), "FAT entry out of bounds");
         let ptr = unsafe { self.buffer.0.as_ptr().add(fat_offset) };
 
-        let entry = match self.fat_type {
+        match self.fat_type {
             FatType::Fat12 => {
                 let byte1 = self.buffer.0[fat_offset];
                 let byte2 = self.buffer.0[fat_offset + 1];
@@ -1340,9 +1396,37 @@ impl Fat {
             }
             FatType::Fat16 => unsafe { (*(ptr as *const u16)) as u32 },
             FatType::Fat32 => unsafe { (*(ptr as *const u32)) & 0x0FFF_FFFF },
-        };
+        }
+    }
+
+    fn read_fat_entry(&self, entry: u32) -> FatEntry {
+        FatEntry::from_u32(self.fat_type, self.read_raw_entry(entry))
+    }
+
+    /// The bit of the clean shutdown flag in the second FAT entry, FAT12 doesn't have it
+    fn clean_shutdown_bit(&self) -> Option<u32> {
+        match self.fat_type {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some(emerald_fat_check::FAT16_CLEAN_SHUTDOWN),
+            FatType::Fat32 => Some(emerald_fat_check::FAT32_CLEAN_SHUTDOWN),
+        }
+    }
 
-        FatEntry::from_u32(self.fat_type, entry)
+    /// Whether the filesystem was unmounted cleanly, always `true` for FAT12
+    fn is_clean(&self) -> bool {
+        self.clean_shutdown_bit()
+            .map_or(true, |bit| self.read_raw_entry(1) & bit != 0)
+    }
+
+    fn set_clean(&mut self, clean: bool) {
+        let Some(bit) = self.clean_shutdown_bit() else {
+            return;
+        };
+        let current = self.read_raw_entry(1);
+        let new = if clean { current | bit } else { current & !bit };
+        if new != current {
+            self.write_raw_entry(1, new);
+        }
     }
 
     fn mark_sector_dirty(&mut self, sector: usize) {
@@ -1352,6 +1436,22 @@ impl Fat {
     }
 
     fn write_fat_entry(&mut self, entry: u32, fat_entry: FatEntry) {
+        let new_entry = fat_entry.to_u32(self.fat_type).expect("invalid FAT entry");
+
+        let was_free = self.free_clusters.is_some() && self.read_fat_entry(entry) == FatEntry::Free;
+        if let Some(free_clusters) = self.free_clusters.as_mut() {
+            match (was_free, fat_entry == FatEntry::Free) {
+                // the value from `FSInfo` could be wrong, don't panic on it
+                (true, false) => *free_clusters = free_clusters.saturating_sub(1),
+                (false, true) => *free_clusters += 1,
+                _ => {}
+            }
+        }
+
+        self.write_raw_entry(entry, new_entry);
+    }
+
+    fn write_raw_entry(&mut self, entry: u32, new_entry: u32) {
         let fat_offset = match self.fat_type {
             FatType::Fat12 => entry * 3 / 2,
             FatType::Fat16 => entry * 2,
@@ -1360,8 +1460,6 @@ impl Fat {
         assert!(fat_offset < self.buffer.0.len(), "FAT entry out of bounds");
         let ptr = unsafe { self.buffer.0.as_mut_ptr().add(fat_offset) };
 
-        let new_entry = fat_entry.to_u32(self.fat_type).expect("invalid FAT entry");
-
         match self.fat_type {
             FatType::Fat12 => {
                 if entry & 1 == 1 {
@@ -1388,7 +1486,8 @@ impl Fat {
         self.dirty = true;
     }
 
-    fn find_free_cluster(&self) -> Option<u32> {
+    /// The entries of the clusters in the data region
+    fn clusters_range(&self) -> Range<u32> {
         let fat_size = self.buffer.0.len();
 
         let number_of_fat_entries = match self.fat_type {
@@ -1397,7 +1496,24 @@ impl Fat {
             FatType::Fat32 => fat_size / 4,
         } as u32;
 
-        (2..number_of_fat_entries).find(|&i| self.read_fat_entry(i) == FatEntry::Free)
+        2..number_of_fat_entries.min(self.clusters_count + 2)
+    }
+
+    fn find_free_cluster(&self) -> Option<u32> {
+        self.clusters_range()
+            .find(|&i| self.read_fat_entry(i) == FatEntry::Free)
+    }
+
+    /// Get the number of free clusters, scans the whole FAT the first time if we don't know it yet
+    fn free_clusters(&mut self) -> u32 {
+        if self.free_clusters.is_none() {
+            let free = self
+                .clusters_range()
+                .filter(|&i| self.read_fat_entry(i) == FatEntry::Free)
+                .count();
+            self.free_clusters = Some(free as u32);
+        }
+        self.free_clusters.unwrap()
     }
 
     fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FileSystemError> {
@@ -1411,6 +1527,35 @@ impl Fat {
     }
 }
 
+/// The partition of a [`FatFilesystem`], for [`emerald_fat_check`]
+struct PartitionVolume<'a>(&'a FatFilesystem);
+
+impl PartitionVolume<'_> {
+    fn lba(&self, offset: u64) -> u64 {
+        self.0.start_lba as u64 + offset / self.0.device.sector_size() as u64
+    }
+}
+
+impl emerald_fat_check::Volume for PartitionVolume<'_> {
+    type Error = FileSystemError;
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), FileSystemError> {
+        let sector = self.lba(offset);
+        self.0
+            .device
+            .read_sync(sector, buf)
+            .map_err(|error| FileSystemError::DiskReadError { sector, error })
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> Result<(), FileSystemError> {
+        let sector = self.lba(offset);
+        self.0
+            .device
+            .write_sync(sector, buf)
+            .map_err(|error| FileSystemError::DiskReadError { sector, error })
+    }
+}
+
 #[derive(Debug)]
 pub struct FatFilesystem {
     start_lba: u32,
@@ -1420,6 +1565,10 @@ pub struct FatFilesystem {
     fat: Fat,
     device: NoDebug<Arc<IdeDevice>>,
     cluster_cache: ClusterCache,
+    /// The sector number and data of the `FSInfo` sector, if valid
+    fs_info: Option<(u32, NoDebug<Vec<u8>>)>,
+    /// Incremented when the cluster chain of any file changes, see [`AccessHelper`]
+    chains_generation: u64,
 }
 
 impl FatFilesystem {
@@ -1436,14 +1585,153 @@ impl FatFilesystem {
             fat: Fat::new(),
             device: NoDebug(device),
             cluster_cache: ClusterCache::default(),
+            fs_info: None,
+            chains_generation: 0,
         };
 
         // TODO: replace by lazily reading FAT when needed
         s.fat = Fat::load(&s)?;
+        if !s.fat.is_clean() {
+            warn!(
+                "FAT filesystem {:?} was not unmounted cleanly, checking it",
+                s.volume_label()
+            );
+            s.check_and_repair()?;
+            // the free map may have been repaired
+            s.fat = Fat::load(&s)?;
+        }
+        s.load_fs_info()?;
+        // cleared until we unmount, so that if we don't, the filesystem is checked on the next mount
+        s.set_clean(false)?;
 
         Ok(s)
     }
 
+    /// Wait until everything written before is on the disk, so the writes after it are never done before them
+    fn barrier(&self) -> Result<(), FileSystemError> {
+        self.device
+            .flush_sync()
+            .map_err(|error| FileSystemError::DiskReadError {
+                sector: self.start_lba as u64,
+                error,
+            })
+    }
+
+    /// Set or clear the clean shutdown flag on the disk, after all the FAT changes before it
+    fn set_clean(&mut self, clean: bool) -> Result<(), FileSystemError> {
+        // the flag is in the first sector of the FAT, which is written first
+        self.flush_fat()?;
+        self.barrier()?;
+        self.fat.set_clean(clean);
+        self.flush_fat()?;
+        self.barrier()
+    }
+
+    /// Write the FAT and the directory entry of `inode`, must be called after the data of the file is written
+    ///
+    /// The order keeps the filesystem consistent if we stop at any point, only leaving orphaned clusters:
+    /// when the file grows, the new clusters are linked in the FAT before the directory entry points to them,
+    /// and when it shrinks, the directory entry is updated before the clusters are freed.
+    fn commit_metadata(&mut self, inode: &FileNode) -> Result<(), FileSystemError> {
+        let size = inode.size() as u32;
+        let mut shrinking = false;
+        self.update_directory_entry(inode, |entry| shrinking = entry.file_size > size)?;
+
+        // data before metadata
+        self.barrier()?;
+        if shrinking {
+            self.update_directory_entry(inode, |entry| entry.file_size = size)?;
+            self.barrier()?;
+            self.flush_fat()
+        } else {
+            self.flush_fat()?;
+            self.barrier()?;
+            self.update_directory_entry(inode, |entry| entry.file_size = size)
+        }
+    }
+
+    /// Check the filesystem with [`emerald_fat_check`] and repair the free map,
+    /// must be called before anything is cached, as it operates on the disk directly
+    fn check_and_repair(&self) -> Result<(), FileSystemError> {
+        let report = match emerald_fat_check::check(&mut PartitionVolume(self), true) {
+            Ok(report) => report,
+            Err(emerald_fat_check::Error::Volume(e)) => return Err(e),
+            Err(emerald_fat_check::Error::InvalidBootSector) => {
+                return Err(FatError::InvalidBootSector.into())
+            }
+        };
+
+        for problem in &report.problems {
+            warn!("FAT check: {problem}");
+        }
+        info!(
+            "FAT check: {} files, {} directories, {}/{} clusters free, {} problems{}",
+            report.files,
+            report.directories,
+            report.free_clusters,
+            report.clusters,
+            report.problems.len(),
+            if report.repaired { ", repaired" } else { "" }
+        );
+        Ok(())
+    }
+
+    /// Load the `FSInfo` sector if present, and take the number of free clusters from it,
+    /// otherwise, it will be computed from the FAT when needed
+    fn load_fs_info(&mut self) -> Result<(), FileSystemError> {
+        let Some(sector) = self.boot_sector.fs_info_sector() else {
+            return Ok(());
+        };
+        let data = self.read_sectors_no_cache(sector, 1)?;
+        if data.len() < mem::size_of::<FsInfo>() {
+            return Ok(());
+        }
+        // SAFETY: the buffer is large enough, and `FsInfo` is packed
+        let fs_info = unsafe { data.as_ptr().cast::<FsInfo>().read() };
+        if !fs_info.is_valid() {
+            return Ok(());
+        }
+
+        // the value may be unknown or wrong, in that case we scan the FAT
+        let free_count = fs_info.free_count;
+        if free_count != FS_INFO_UNKNOWN && free_count <= self.fat.clusters_count {
+            self.fat.free_clusters = Some(free_count);
+        }
+        self.fs_info = Some((sector, NoDebug(data)));
+
+        Ok(())
+    }
+
+    /// Write the number of free clusters to the `FSInfo` sector, if it changed
+    fn flush_fs_info(&mut self) -> Result<(), FileSystemError> {
+        let Some(free_clusters) = self.fat.free_clusters else {
+            return Ok(());
+        };
+        let Some((sector, data)) = self.fs_info.as_mut() else {
+            return Ok(());
+        };
+        // SAFETY: the buffer is large enough (checked on load), and `FsInfo` is packed
+        let fs_info = unsafe { &mut *data.as_mut_ptr().cast::<FsInfo>() };
+        if fs_info.free_count == free_clusters {
+            return Ok(());
+        }
+        fs_info.free_count = free_clusters;
+        // we don't track it, it's only a hint
+        fs_info.next_free = FS_INFO_UNKNOWN;
+
+        let sector = *sector;
+        let data = self.fs_info.as_ref().map(|(_, data)| data).unwrap();
+        self.write_sectors(sector, data)
+    }
+
+    fn fs_stat(&mut self) -> FileSystemStat {
+        FileSystemStat {
+            block_size: self.boot_sector.bytes_per_cluster() as u64,
+            total_blocks: self.fat.clusters_count as u64,
+            free_blocks: self.fat.free_clusters() as u64,
+        }
+    }
+
     pub fn volume_label(&self) -> String {
         let label = self.boot_sector.volume_label();
         let mut label = String::from_utf8_lossy(label).to_string();
@@ -1524,12 +1812,8 @@ impl FatFilesystem {
         cluster_num: u32,
         dirty_range: Range<usize>,
     ) -> Result<(), FileSystemError> {
-        self.flush_fat()?;
-        self.update_directory_entry(inode, |entry| {
-            entry.file_size = inode.size() as u32;
-        })?;
-
-        self.flush_cluster_dirty_range(cluster_data, cluster_num, dirty_range)
+        self.flush_cluster_dirty_range(cluster_data, cluster_num, dirty_range)?;
+        self.commit_metadata(inode)
     }
 
     fn flush_cluster_dirty_range(
@@ -1586,6 +1870,52 @@ impl FatFilesystem {
         Ok(())
     }
 
+    /// Write back the dirty part of `cluster` if its cached, the cluster stays in the cache
+    fn flush_cached_cluster(&mut self, cluster: u32) -> Result<(), FileSystemError> {
+        let Some(entry) = self.cluster_cache.try_get_cluster_mut(cluster) else {
+            return Ok(());
+        };
+        let Some(dirty_range) = entry.dirty_range.take() else {
+            return Ok(());
+        };
+        // take the data out, so that we can use `self` while writing
+        let data = mem::take(&mut entry.data.0);
+        let result = self.flush_cluster_dirty_range(&data, cluster, dirty_range);
+        if let Some(entry) = self.cluster_cache.try_get_cluster_mut(cluster) {
+            entry.data.0 = data;
+        }
+        result
+    }
+
+    /// Write back all the cached clusters of the file, and its metadata
+    fn sync_file(&mut self, inode: &FileNode) -> Result<(), FileSystemError> {
+        let mut cluster = Some(inode.start_cluster() as u32);
+        while let Some(current) = cluster {
+            self.flush_cached_cluster(current)?;
+            cluster = self.fat.next_cluster(current)?;
+        }
+        self.commit_metadata(inode)?;
+        self.barrier()
+    }
+
+    /// Write back all dirty cached clusters and the FAT
+    fn sync_all(&mut self) -> Result<(), FileSystemError> {
+        let dirty_clusters = self
+            .cluster_cache
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty_range.is_some())
+            .map(|(&cluster, _)| cluster)
+            .collect::<Vec<_>>();
+        for cluster in dirty_clusters {
+            self.flush_cached_cluster(cluster)?;
+        }
+        // data before metadata
+        self.barrier()?;
+        self.flush_fat()?;
+        self.barrier()
+    }
+
     /// Same as `release_cluster`, but doesn't release it, i.e. the cluster will
     /// still be used, but the `dirty` flag is removed
     fn flush_cluster(&mut self, inode: &FileNode, cluster: u32) -> Result<(), FileSystemError> {
@@ -1625,7 +1955,7 @@ impl FatFilesystem {
         }
         self.fat.clear_dirty();
 
-        Ok(())
+        self.flush_fs_info()
     }
 
     fn open_root_dir(&self) -> Result<Directory, FileSystemError> {
@@ -1696,6 +2026,65 @@ impl FatFilesystem {
         DirectoryIterator::new(self, dir)
     }
 
+    /// Fill `start..end` of the file with zeros, the file must be at least `end` bytes
+    fn zero_file_range(
+        &mut self,
+        inode: &FileNode,
+        start: u64,
+        end: u64,
+        access_helper: &mut AccessHelper,
+    ) -> Result<(), FileSystemError> {
+        let zeros = vec![0; self.boot_sector.bytes_per_sector() as usize];
+
+        let mut position = start;
+        while position < end {
+            let to_write = zeros.len().min((end - position) as usize);
+            self.read_write_file(
+                inode,
+                position as u32,
+                FileAccessBuffer::Write(&zeros[..to_write]),
+                access_helper,
+            )?;
+            position += to_write as u64;
+        }
+        Ok(())
+    }
+
+    /// Get the cluster at `cluster_index` in the file, using and filling the extents in `access_helper`
+    fn file_cluster_at(
+        &self,
+        inode: &FileNode,
+        cluster_index: u32,
+        access_helper: &mut AccessHelper,
+    ) -> Result<u32, FileSystemError> {
+        access_helper.check_extents_generation(self.chains_generation);
+        if let Some(cluster) = access_helper.cached_cluster(cluster_index as u64) {
+            return Ok(cluster as u32);
+        }
+
+        // continue from the last resolved cluster
+        let (mut index, mut cluster) = match access_helper.last_cached_cluster() {
+            Some((index, cluster)) => (index as u32, cluster as u32),
+            None => {
+                let cluster = inode.start_cluster() as u32;
+                // cannot be empty, or be the root
+                assert_ne!(cluster, 0);
+                access_helper.cache_cluster(0, cluster as u64);
+                (0, cluster)
+            }
+        };
+        while index < cluster_index {
+            cluster = self
+                .fat
+                .next_cluster(cluster)?
+                .ok_or(FatError::UnexpectedFatEntry)?;
+            index += 1;
+            access_helper.cache_cluster(index as u64, cluster as u64);
+        }
+
+        Ok(cluster)
+    }
+
     fn read_write_file(
         &mut self,
         inode: &FileNode,
@@ -1722,17 +2111,7 @@ impl FatFilesystem {
 
         // starting out
         let mut cluster_entry = if access_helper.current_cluster == 0 {
-            let mut cluster = inode.start_cluster() as u32;
-
-            // cannot be empty, or be the root
-            assert_ne!(cluster, 0);
-
-            for _ in 0..cluster_index {
-                cluster = self
-                    .fat
-                    .next_cluster(cluster)?
-                    .ok_or(FatError::UnexpectedFatEntry)?;
-            }
+            let cluster = self.file_cluster_at(inode, cluster_index, access_helper)?;
 
             access_helper.current_cluster = cluster as u64;
             access_helper.cluster_index = cluster_index as u64;
@@ -1784,6 +2163,7 @@ impl FatFilesystem {
                         cluster_entry = self.lock_cluster(cluster)?;
                         access_helper.current_cluster = cluster as u64;
                         access_helper.cluster_index += 1;
+                        access_helper.cache_cluster(access_helper.cluster_index, cluster as u64);
                     }
                     None => {
                         break;
@@ -1860,6 +2240,9 @@ impl FatFilesystem {
         normal_entry.first_cluster_lo = (cluster & 0xFFFF) as u16;
         normal_entry.first_cluster_hi = (cluster >> 16) as u16;
 
+        // the cluster must be allocated and cleared before the entry points to it
+        self.barrier()?;
+
         let node = self
             .open_dir_inode(parent_inode)
             .and_then(|mut dir| dir.add_entry(normal_entry.clone(), long_name_entries));
@@ -1934,6 +2317,9 @@ impl FatFilesystem {
         let new_size_in_clusters = new_size_in_clusters.max(1);
 
         if new_size_in_clusters != current_size_in_clusters {
+            // the extents cached by open files are no longer valid
+            self.chains_generation += 1;
+
             // update fat references
             let to_keep = new_size_in_clusters.min(current_size_in_clusters);
 
@@ -1967,6 +2353,11 @@ impl FatFilesystem {
                 // move backwards
                 for cluster in clusters.into_iter().rev() {
                     self.fat.write_fat_entry(cluster, FatEntry::Free);
+                    // the cluster may be cached by an open handle, don't write its data back
+                    // as it may be allocated for another file
+                    if let Some(entry) = self.cluster_cache.try_get_cluster_mut(cluster) {
+                        entry.dirty_range = None;
+                    }
                 }
 
                 // mark the current cluster as last
@@ -1998,9 +2389,13 @@ impl FatFilesystem {
     }
 }
 
-impl FileSystem for Mutex<FatFilesystem> {
+impl FileSystem for AdaptiveMutex<FatFilesystem> {
+    fn type_name(&self) -> &'static str {
+        "fat"
+    }
+
     fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
-        self.lock().open_root_dir_inode()
+        self.lock_or_block()?.open_root_dir_inode()
     }
 
     fn read_dir(
@@ -2008,7 +2403,7 @@ impl FileSystem for Mutex<FatFilesystem> {
         inode: &DirectoryNode,
         handler: &mut dyn FnMut(Node) -> DirTreverse,
     ) -> Result<(), FileSystemError> {
-        for node in self.lock().open_dir_inode(inode)? {
+        for node in self.lock_or_block()?.open_dir_inode(inode)? {
             if let DirTreverse::Stop = handler(node.into()) {
                 break;
             }
@@ -2017,8 +2412,12 @@ impl FileSystem for Mutex<FatFilesystem> {
         Ok(())
     }
 
+    fn case_sensitivity(&self) -> CaseSensitivity {
+        CaseSensitivity::Insensitive
+    }
+
     fn treverse_dir(&self, inode: &DirectoryNode, matcher: &str) -> Result<Node, FileSystemError> {
-        for node in self.lock().open_dir_inode(inode)? {
+        for node in self.lock_or_block()?.open_dir_inode(inode)? {
             if node.matches(matcher) {
                 return Ok(node.into());
             }
@@ -2033,7 +2432,8 @@ impl FileSystem for Mutex<FatFilesystem> {
         name: &str,
         attributes: FileAttributes,
     ) -> Result<Node, FileSystemError> {
-        self.lock().add_directory_entry(parent, name, attributes)
+        self.lock_or_block()?
+            .add_directory_entry(parent, name, attributes)
     }
 
     fn read_file(
@@ -2043,8 +2443,11 @@ impl FileSystem for Mutex<FatFilesystem> {
         buf: &mut [u8],
         access_helper: &mut AccessHelper,
     ) -> Result<u64, FileSystemError> {
-        assert!(position <= u32::MAX as u64);
-        self.lock().read_write_file(
+        // FAT files can't be larger than 4GB, so there is nothing to read there
+        if position > u32::MAX as u64 {
+            return Ok(0);
+        }
+        self.lock_or_block()?.read_write_file(
             inode,
             position as u32,
             FileAccessBuffer::Read(buf),
@@ -2059,12 +2462,14 @@ impl FileSystem for Mutex<FatFilesystem> {
         buf: &[u8],
         access_helper: &mut AccessHelper,
     ) -> Result<u64, FileSystemError> {
-        assert!(position <= u32::MAX as u64);
+        let new_size = position
+            .checked_add(buf.len() as u64)
+            .filter(|&size| size <= u32::MAX as u64)
+            .ok_or(FileSystemError::CouldNotSetFileLength)?;
 
-        let mut s = self.lock();
+        let mut s = self.lock_or_block()?;
 
         let current_size = inode.size();
-        let new_size = position + buf.len() as u64;
 
         if new_size > current_size {
             s.set_file_size(inode, new_size)
@@ -2074,21 +2479,7 @@ impl FileSystem for Mutex<FatFilesystem> {
         // we seeked past the end of the file
         // extend to the position with zeros
         if position > current_size {
-            let extend_size = position - current_size;
-
-            let zeros = vec![0; s.boot_sector.bytes_per_sector() as usize];
-
-            let mut written = 0;
-            while written < extend_size {
-                let to_write = zeros.len().min((extend_size - written) as usize);
-                s.read_write_file(
-                    inode,
-                    (current_size + written) as u32,
-                    FileAccessBuffer::Write(&zeros[..to_write]),
-                    access_helper,
-                )?;
-                written += to_write as u64;
-            }
+            s.zero_file_range(inode, current_size, position, access_helper)?;
         }
 
         s.read_write_file(
@@ -2117,27 +2508,51 @@ impl FileSystem for Mutex<FatFilesystem> {
             .release_cluster(inode, access_helper.current_cluster as u32)
     }
 
+    fn sync_file(
+        &self,
+        inode: &mut FileNode,
+        _access_helper: &mut AccessHelper,
+    ) -> Result<(), FileSystemError> {
+        self.lock_or_block()?.sync_file(inode)
+    }
+
+    fn sync(&self) -> Result<(), FileSystemError> {
+        self.lock_or_block()?.sync_all()
+    }
+
+    fn fs_stat(&self) -> Result<FileSystemStat, FileSystemError> {
+        Ok(self.lock_or_block()?.fs_stat())
+    }
+
     fn set_file_size(&self, inode: &mut FileNode, size: u64) -> Result<(), FileSystemError> {
-        let mut s = self.lock();
+        if size > u32::MAX as u64 {
+            return Err(FileSystemError::CouldNotSetFileLength);
+        }
+        let mut s = self.lock_or_block()?;
 
+        let current_size = inode.size();
         s.set_file_size(inode, size)?;
-        s.flush_fat()?;
-        s.update_directory_entry(inode, |entry| {
-            entry.file_size = inode.size() as u32;
-        })?;
-
-        Ok(())
+        if size > current_size {
+            // the new clusters (or the rest of the last one) may contain old data
+            let mut access_helper = AccessHelper::default();
+            let result = s.zero_file_range(inode, current_size, size, &mut access_helper);
+            s.release_cluster(inode, access_helper.current_cluster as u32)?;
+            result?;
+        }
+        s.commit_metadata(inode)
     }
 
     fn unmount(self: Arc<Self>) {
         let mut s = self.lock();
-        s.flush_fat().expect("flush fat");
 
+        // data before metadata
         for cluster in s.cluster_cache.release_all().into_values() {
             if let Some(dirty_range) = cluster.dirty_range {
                 s.flush_cluster_dirty_range(&cluster.data, cluster.cluster, dirty_range)
                     .expect("flush cluster dirty range");
             }
         }
+        s.barrier().expect("flush disk");
+        s.set_clean(true).expect("mark filesystem clean");
     }
 }
@@ -0,0 +1,306 @@
+//! Initial ramdisk, a read-only filesystem loaded by the bootloader as a module
+//!
+//! The module must be a `cpio` archive in the `newc` format (same as Linux's `initramfs`), i.e. created with
+//! `find . | cpio -o -H newc`. Only regular files and directories are supported, other entries are skipped.
+//!
+//! It is mounted at `/boot/initrd`, and can be mounted at `/` if there is no disk (see [`mount_as_root`]).
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use tracing::{info, warn};
+
+use crate::{
+    memory_management::virtual_space::VirtualSpace, multiboot2::MultiBoot2Info,
+    sync::once::OnceLock,
+};
+
+use super::{
+    mapping, AccessHelper, DirTreverse, DirectoryNode, FileAttributes, FileNode, FileSystem,
+    FileSystemError, Node, NO_PARENT_DIR_SECTOR,
+};
+
+static INITRD: OnceLock<Arc<Archive>> = OnceLock::new();
+
+const CPIO_NEWC_MAGIC: &[u8] = b"070701";
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const CPIO_MODE_TYPE_MASK: u32 = 0o170000;
+const CPIO_MODE_DIRECTORY: u32 = 0o040000;
+const CPIO_MODE_REGULAR: u32 = 0o100000;
+
+/// The inode of the root directory, the rest are indices into [`Archive::entries`]
+const ROOT_INODE: u64 = u64::MAX;
+
+#[derive(Debug)]
+enum EntryKind {
+    Directory,
+    /// Range of the content in the archive
+    File {
+        start: usize,
+        end: usize,
+    },
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Full path without the leading `/`
+    path: String,
+    kind: EntryKind,
+}
+
+impl Entry {
+    fn parent_and_name(&self) -> (&str, &str) {
+        self.path.rsplit_once('/').unwrap_or(("", &self.path))
+    }
+
+    fn to_node(&self, inode: usize) -> Node {
+        let name = self.parent_and_name().1.to_string();
+        match self.kind {
+            EntryKind::Directory => DirectoryNode::without_parent(
+                name,
+                FileAttributes::DIRECTORY | FileAttributes::READ_ONLY,
+                inode as u64,
+            )
+            .into(),
+            EntryKind::File { start, end } => FileNode::new_file(
+                name,
+                FileAttributes::READ_ONLY,
+                inode as u64,
+                (end - start) as u64,
+                NO_PARENT_DIR_SECTOR,
+                0,
+            )
+            .into(),
+        }
+    }
+}
+
+struct Archive {
+    data: VirtualSpace<[u8]>,
+    /// Sorted by path
+    entries: Vec<Entry>,
+}
+
+impl Archive {
+    fn parse(data: VirtualSpace<[u8]>) -> Option<Self> {
+        let mut entries = BTreeMap::new();
+
+        let mut offset = 0;
+        loop {
+            let header = data.get(offset..offset + CPIO_HEADER_SIZE)?;
+            if &header[..6] != CPIO_NEWC_MAGIC {
+                return None;
+            }
+            // fields are 8 hex digits each, after the magic
+            let field = |i: usize| {
+                let start = 6 + i * 8;
+                core::str::from_utf8(&header[start..start + 8])
+                    .ok()
+                    .and_then(|s| u32::from_str_radix(s, 16).ok())
+            };
+            let mode = field(1)?;
+            let file_size = field(6)? as usize;
+            let name_size = field(11)? as usize;
+
+            let name_start = offset + CPIO_HEADER_SIZE;
+            // the name includes the null terminator
+            let name = data.get(name_start..name_start + name_size.checked_sub(1)?)?;
+            let name = core::str::from_utf8(name).ok()?;
+            let data_start = (name_start + name_size).next_multiple_of(4);
+            let data_end = data_start + file_size;
+            if data_end > data.len() {
+                return None;
+            }
+            offset = data_end.next_multiple_of(4);
+
+            if name == CPIO_TRAILER {
+                break;
+            }
+
+            let path = name.trim_start_matches("./").trim_matches('/');
+            if path.is_empty() || path == "." {
+                continue;
+            }
+            if path.split('/').any(|c| c == ".." || c == ".") {
+                warn!("initrd: skipping entry with invalid path {name:?}");
+                continue;
+            }
+
+            let kind = match mode & CPIO_MODE_TYPE_MASK {
+                CPIO_MODE_DIRECTORY => EntryKind::Directory,
+                CPIO_MODE_REGULAR => EntryKind::File {
+                    start: data_start,
+                    end: data_end,
+                },
+                _ => {
+                    warn!("initrd: skipping unsupported entry {name:?} (mode {mode:o})");
+                    continue;
+                }
+            };
+
+            // archives don't always contain the parent directories
+            let mut parent = path;
+            while let Some((p, _)) = parent.rsplit_once('/') {
+                parent = p;
+                entries
+                    .entry(String::from(parent))
+                    .or_insert(EntryKind::Directory);
+            }
+            entries.insert(String::from(path), kind);
+        }
+
+        Some(Self {
+            data,
+            entries: entries
+                .into_iter()
+                .map(|(path, kind)| Entry { path, kind })
+                .collect(),
+        })
+    }
+
+    fn entry(&self, inode: u64) -> Result<&Entry, FileSystemError> {
+        self.entries
+            .get(inode as usize)
+            .ok_or(FileSystemError::FileNotFound)
+    }
+}
+
+/// Load the initrd from the first bootloader module if present, and mount it at `/boot/initrd`
+pub fn init(multiboot_info: &MultiBoot2Info) {
+    let Some(module) = multiboot_info.modules().next() else {
+        return;
+    };
+
+    let len = (module.end - module.start) as usize;
+    // SAFETY: the module memory is reserved by the physical allocator, and never freed
+    let Ok(data) = (unsafe { VirtualSpace::<u8>::new_slice(module.start, len) }) else {
+        warn!("initrd: could not map module {:?}", module.cmdline);
+        return;
+    };
+    let Some(archive) = Archive::parse(data) else {
+        warn!(
+            "initrd: module {:?} is not a valid newc cpio archive",
+            module.cmdline
+        );
+        return;
+    };
+    info!(
+        "initrd: loaded {} entries from module {:?}",
+        archive.entries.len(),
+        module.cmdline
+    );
+    let archive = INITRD.get_or_init(|| Arc::new(archive));
+
+    // `/boot` holds the files provided by the bootloader
+    mapping::mount("/boot", Arc::new(BootFileSystem)).expect("Mapping failed");
+    mapping::mount(
+        "/boot/initrd",
+        Arc::new(InitrdFileSystem::new(archive.clone())),
+    )
+    .expect("Mapping failed");
+}
+
+/// Mount the initrd at `/`, used when there is no disk, returns `false` if there is no initrd
+pub fn mount_as_root() -> bool {
+    let Some(archive) = INITRD.try_get() else {
+        return false;
+    };
+    info!("Mapping / to the initrd");
+    mapping::mount("/", Arc::new(InitrdFileSystem::new(archive.clone()))).expect("Mapping failed");
+    true
+}
+
+pub struct InitrdFileSystem {
+    archive: Arc<Archive>,
+}
+
+impl InitrdFileSystem {
+    fn new(archive: Arc<Archive>) -> Self {
+        Self { archive }
+    }
+}
+
+impl FileSystem for InitrdFileSystem {
+    fn type_name(&self) -> &'static str {
+        "initrd"
+    }
+
+    fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
+        Ok(DirectoryNode::without_parent(
+            String::from("/"),
+            FileAttributes::DIRECTORY | FileAttributes::READ_ONLY,
+            ROOT_INODE,
+        ))
+    }
+
+    fn read_dir(
+        &self,
+        inode: &DirectoryNode,
+        handler: &mut dyn FnMut(Node) -> DirTreverse,
+    ) -> Result<(), FileSystemError> {
+        let dir_path = match inode.start_cluster() {
+            ROOT_INODE => "",
+            inode => self.archive.entry(inode)?.path.as_str(),
+        };
+
+        for (i, entry) in self.archive.entries.iter().enumerate() {
+            if entry.parent_and_name().0 != dir_path {
+                continue;
+            }
+            if let DirTreverse::Stop = handler(entry.to_node(i)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_file(
+        &self,
+        inode: &FileNode,
+        position: u64,
+        buf: &mut [u8],
+        _access_helper: &mut AccessHelper,
+    ) -> Result<u64, FileSystemError> {
+        let EntryKind::File { start, end } = self.archive.entry(inode.start_cluster())?.kind else {
+            return Err(FileSystemError::IsDirectory);
+        };
+        let content = &self.archive.data[start..end];
+        if position >= content.len() as u64 {
+            return Ok(0);
+        }
+        let remaining = &content[position as usize..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        Ok(count as u64)
+    }
+}
+
+/// Empty directory mounted at `/boot`, so that we can mount the initrd inside it
+struct BootFileSystem;
+
+impl FileSystem for BootFileSystem {
+    fn type_name(&self) -> &'static str {
+        "boot"
+    }
+
+    fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
+        Ok(DirectoryNode::without_parent(
+            String::from("/"),
+            FileAttributes::DIRECTORY | FileAttributes::READ_ONLY,
+            ROOT_INODE,
+        ))
+    }
+
+    fn read_dir(
+        &self,
+        _inode: &DirectoryNode,
+        _handler: &mut dyn FnMut(Node) -> DirTreverse,
+    ) -> Result<(), FileSystemError> {
+        // the mounted filesystems are added by the directory listing
+        Ok(())
+    }
+}
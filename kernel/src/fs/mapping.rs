@@ -1,16 +1,29 @@
+//! Mapping of paths to the filesystems mounted on them
+//!
+//! A filesystem can only be mounted inside the root of another mapping (i.e. `/boot/initrd` inside `/boot`),
+//! and it hides (shadows) the entry with the same name in the directory it's mounted on, if any.
+
 use alloc::{
     boxed::Box,
     collections::{btree_map, BTreeMap},
     sync::{Arc, Weak},
+    vec::Vec,
 };
 use tracing::info;
 
 use crate::{
     io::NoDebug,
-    sync::{once::OnceLock, spin::rwlock::RwLock},
+    memory_management::shared_pages,
+    sync::{
+        epoch::{self, EpochCell},
+        once::OnceLock,
+        spin::{mutex::Mutex, rwlock::RwLock},
+    },
+    testing,
 };
 
 use super::{
+    filesystem_id,
     path::{Component, Path, PathBuf},
     EmptyFileSystem, FileSystem, FileSystemError,
 };
@@ -68,12 +81,39 @@ pub fn mount(arg: &str, filesystem: Arc<dyn FileSystem>) -> Result<(), MappingEr
     }
 }
 
+/// Unmounts the filesystem mounted at `arg`, the root can't be unmounted.
+///
+/// With [`UnmountMode::Normal`], it fails with `MappingError::Busy` if the filesystem is in use,
+/// i.e. it has open files (including the current directory of processes), or has other filesystems mounted inside it.
+///
+/// With [`UnmountMode::Lazy`], the mapping and the mappings inside it are removed immediately, so new paths
+/// don't reach them, but the filesystems are only unmounted when the last file using them is closed
+/// (see [`release_detached`]).
+pub fn unmount(arg: &str, mode: UnmountMode) -> Result<(), MappingError> {
+    FILESYSTEM_MAPPING.get().unmount(Path::new(arg), mode)
+}
+
+/// Unmount the filesystems detached by a lazy [`unmount`] that are not used anymore,
+/// this is called when files and directories are closed
+pub fn release_detached() {
+    if let Some(mapping) = FILESYSTEM_MAPPING.try_get() {
+        mapping.release_detached();
+    }
+}
+
 /// Unmounts all filesystems from the virtual filesystem.
 /// This function removes all mounted filesystems from the virtual filesystem, effectively clearing
 /// the filesystem mapping tree.
 pub fn unmount_all() {
-    // The `Drop` will call `unmount` for each filesystem
-    FILESYSTEM_MAPPING.get().root.unmount_all(Path::new("/"));
+    FILESYSTEM_MAPPING.get().unmount_all();
+}
+
+/// Applies `handler` to all mounted filesystems, including the root
+pub fn on_all_mappings(mut handler: impl FnMut(&Path, Arc<dyn FileSystem>)) {
+    FILESYSTEM_MAPPING
+        .get()
+        .root
+        .treverse(PathBuf::from("/"), &mut handler);
 }
 
 /// Traverses the filesystem mapping tree and applies a handler function to all matching mappings.
@@ -113,6 +153,18 @@ pub enum MappingError {
     InvalidPath,
     PartOfParentNotMounted,
     AlreadyMounted,
+    /// The path is not a mount point
+    NotMounted,
+    /// The filesystem is in use, or has other filesystems mounted inside it
+    Busy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmountMode {
+    /// Fail if the filesystem is in use
+    Normal,
+    /// Detach the filesystem immediately, and unmount it when it's not used anymore
+    Lazy,
 }
 
 impl From<MappingError> for FileSystemError {
@@ -126,7 +178,8 @@ impl From<MappingError> for FileSystemError {
 pub struct MappingNode {
     filesystem: NoDebug<RwLock<Arc<dyn FileSystem>>>,
     parent: Weak<MappingNode>,
-    children: RwLock<BTreeMap<Box<str>, Arc<MappingNode>>>,
+    /// Looked up on each path resolution, and only changed on mount and unmount
+    children: EpochCell<BTreeMap<Box<str>, Arc<MappingNode>>>,
 }
 
 impl MappingNode {
@@ -144,11 +197,11 @@ impl MappingNode {
         }
 
         if components.peek().is_none() {
-            for (name, node) in self.children.read().iter() {
-                node.treverse(name.into(), handler);
+            for (name, node) in self.children() {
+                node.treverse(name.as_ref().into(), handler);
             }
         } else {
-            for (name, node) in self.children.read().iter() {
+            for (name, node) in self.children() {
                 node.check_and_treverse(
                     components.as_path(),
                     Component::Normal(name.as_ref()),
@@ -163,13 +216,26 @@ impl MappingNode {
     fn treverse(&self, current_path: PathBuf, handler: &mut dyn FnMut(&Path, Arc<dyn FileSystem>)) {
         handler(&current_path, self.filesystem());
 
-        for (name, node) in self.children.read().iter() {
+        for (name, node) in self.children() {
             node.treverse(current_path.join(name.as_ref()), handler);
         }
     }
 
+    /// The children, so the handlers of `treverse` run without staying pinned, as they may use
+    /// the disks (i.e. `sync`)
+    fn children(&self) -> Vec<(Box<str>, Arc<MappingNode>)> {
+        self.children
+            .read(&epoch::pin())
+            .iter()
+            .map(|(name, node)| (name.clone(), node.clone()))
+            .collect()
+    }
+
     pub fn try_find_child(&self, component_name: &str) -> Option<Arc<MappingNode>> {
-        self.children.read().get(component_name).cloned()
+        self.children
+            .read(&epoch::pin())
+            .get(component_name)
+            .cloned()
     }
 
     pub fn filesystem(&self) -> Arc<dyn FileSystem> {
@@ -180,26 +246,54 @@ impl MappingNode {
         self.parent.upgrade()
     }
 
-    fn unmount_all(&self, this_name: &Path) {
-        let mut children = self.children.write();
-        while let Some((name, node)) = children.pop_first() {
-            node.unmount_all(&this_name.join(name.as_ref()));
+    /// Whether the filesystem is used by anything other than the mapping
+    fn is_in_use(&self) -> bool {
+        let fs = self.filesystem();
+        // number of global refs + the mapping + this one
+        Arc::strong_count(&fs) > fs.number_global_refs() + 2
+    }
+
+    /// Remove the filesystems of this node and all the nodes inside it, and add them to `detached`,
+    /// the children are added first
+    fn detach_all(&self, this_name: &Path, detached: &mut Vec<DetachedFileSystem>) {
+        let children = self.children.update(core::mem::take);
+        for (name, node) in children {
+            node.detach_all(&this_name.join(name.as_ref()), detached);
         }
 
-        info!("Unmounting {}", this_name.display());
         let fs = core::mem::replace(&mut *self.filesystem.0.write(), Arc::new(EmptyFileSystem));
-        assert_eq!(
-            Arc::strong_count(&fs),
-            fs.number_global_refs() + 1, // number of global refs + this one
-            "Filesystem still in use"
-        );
-        fs.unmount();
+        detached.push(DetachedFileSystem {
+            path: this_name.to_path_buf(),
+            filesystem: NoDebug(fs),
+        });
+    }
+}
+
+/// A filesystem removed from the mapping tree, waiting to be unmounted
+#[derive(Debug)]
+struct DetachedFileSystem {
+    path: PathBuf,
+    filesystem: NoDebug<Arc<dyn FileSystem>>,
+}
+
+impl DetachedFileSystem {
+    fn is_in_use(&self) -> bool {
+        // number of global refs + this one
+        Arc::strong_count(&self.filesystem.0) > self.filesystem.number_global_refs() + 1
+    }
+
+    fn unmount(self) {
+        info!("Unmounting {}", self.path.display());
+        shared_pages::invalidate_filesystem(filesystem_id(&self.filesystem.0));
+        self.filesystem.0.unmount();
     }
 }
 
 #[derive(Debug)]
 struct FileSystemMapping {
     root: Arc<MappingNode>,
+    /// Filesystems detached by a lazy unmount that are still in use
+    detached: Mutex<Vec<DetachedFileSystem>>,
 }
 
 impl FileSystemMapping {
@@ -208,8 +302,81 @@ impl FileSystemMapping {
             root: Arc::new(MappingNode {
                 filesystem: NoDebug(RwLock::new(Arc::new(EmptyFileSystem))),
                 parent: Weak::new(),
-                children: RwLock::new(BTreeMap::new()),
+                children: EpochCell::new(BTreeMap::new()),
             }),
+            detached: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn unmount(&self, path: &Path, mode: UnmountMode) -> Result<(), MappingError> {
+        if !path.is_absolute() {
+            return Err(MappingError::MustBeAbsolute);
+        }
+
+        let mut names = path.names();
+        let mut parent = None;
+        let mut node = self.root.clone();
+        let mut node_name = "";
+        let mut node_path = PathBuf::from("/");
+        for name in names.by_ref() {
+            let child = node.try_find_child(name).ok_or(MappingError::NotMounted)?;
+            parent = Some(core::mem::replace(&mut node, child));
+            node_name = name;
+            node_path.push(name);
+        }
+        // no `..` or `.` in the path
+        if !names.as_path().is_empty() {
+            return Err(MappingError::InvalidPath);
+        }
+        // the root is always in use
+        let parent = parent.ok_or(MappingError::Busy)?;
+
+        let mut detached = Vec::new();
+        // detach the node while updating the parent, so no one can mount next to it in between
+        parent.children.update(|parent_children| {
+            if mode == UnmountMode::Normal
+                && (!node.children.read(&epoch::pin()).is_empty() || node.is_in_use())
+            {
+                return Err(MappingError::Busy);
+            }
+            parent_children.remove(node_name);
+            node.detach_all(&node_path, &mut detached);
+            Ok(())
+        })?;
+
+        // it could still be used if it was reached before we detached it
+        self.detached.lock().extend(detached);
+        self.release_detached();
+        Ok(())
+    }
+
+    fn release_detached(&self) {
+        let mut released = Vec::new();
+        {
+            let mut detached = self.detached.lock();
+            let mut i = 0;
+            while i < detached.len() {
+                if detached[i].is_in_use() {
+                    i += 1;
+                } else {
+                    released.push(detached.swap_remove(i));
+                }
+            }
+        }
+
+        // unmount outside the lock, as it might write to the disk
+        for fs in released {
+            fs.unmount();
+        }
+    }
+
+    fn unmount_all(&self) {
+        let mut detached = core::mem::take(&mut *self.detached.lock());
+        self.root.detach_all(Path::new("/"), &mut detached);
+
+        for fs in detached {
+            assert!(!fs.is_in_use(), "Filesystem still in use");
+            fs.unmount();
         }
     }
 
@@ -233,32 +400,24 @@ impl FileSystemMapping {
         // must start with `/`
         let mut mapping_path = PathBuf::from("/");
 
-        let mut components = path.components();
-
-        if components.next() != Some(Component::RootDir) {
+        if !path.is_absolute() {
             return Err(FileSystemError::MustBeAbsolute);
         }
 
-        while let Some(component) = components.peek() {
-            match component {
-                Component::Normal(name) => {
-                    if let Some(child) = current.try_find_child(name) {
-                        mapping_path.push(name);
-                        current = child;
-                    } else {
-                        break;
-                    }
-                }
-                _ => {
-                    break;
-                }
-            }
+        // mappings can't contain `..` or `.`, so we stop at them
+        let mut names = path.names();
+        while let Some(name) = names.peek() {
+            let Some(child) = current.try_find_child(name) else {
+                break;
+            };
+            mapping_path.push(name);
+            current = child;
 
             // consume
-            components.next();
+            names.next();
         }
 
-        Ok((mapping_path, components.as_path(), current))
+        Ok((mapping_path, names.as_path(), current))
     }
 
     fn on_all_matching_mappings(
@@ -299,36 +458,111 @@ impl FileSystemMapping {
             let Component::Normal(component_path) = component else {
                 unreachable!("Already chacked all the components")
             };
-            let is_last = i == size - 1;
 
-            match current_element
-                .clone()
-                .children
-                .write()
-                .entry(component_path.into())
-            {
-                btree_map::Entry::Vacant(entry) => {
-                    if is_last {
+            if i != size - 1 {
+                current_element = current_element
+                    .try_find_child(component_path)
+                    .ok_or(MappingError::PartOfParentNotMounted)?;
+                continue;
+            }
+
+            return current_element.children.update(|children| {
+                match children.entry(component_path.into()) {
+                    btree_map::Entry::Vacant(entry) => {
                         entry.insert(Arc::new(MappingNode {
                             filesystem: NoDebug(RwLock::new(filesystem)),
                             parent: Arc::downgrade(&current_element),
-                            children: RwLock::new(BTreeMap::new()),
+                            children: EpochCell::new(BTreeMap::new()),
                         }));
-                        return Ok(());
-                    } else {
-                        return Err(MappingError::PartOfParentNotMounted);
-                    }
-                }
-                btree_map::Entry::Occupied(entry) => {
-                    if is_last {
-                        return Err(MappingError::AlreadyMounted);
-                    } else {
-                        current_element = entry.get().clone();
+                        Ok(())
                     }
+                    btree_map::Entry::Occupied(_) => Err(MappingError::AlreadyMounted),
                 }
-            }
+            });
         }
 
         unreachable!("For some reason, it wasn't mounted")
     }
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_mapping_unmount() {
+    use alloc::string::String;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::{DirTreverse, DirectoryNode, FileAttributes, Node};
+
+    static UNMOUNTED: AtomicUsize = AtomicUsize::new(0);
+
+    struct TestFileSystem;
+
+    impl FileSystem for TestFileSystem {
+        fn type_name(&self) -> &'static str {
+            "test"
+        }
+
+        fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
+            Ok(DirectoryNode::without_parent(
+                String::from("/"),
+                FileAttributes::DIRECTORY,
+                0,
+            ))
+        }
+
+        fn read_dir(
+            &self,
+            _inode: &DirectoryNode,
+            _handler: &mut dyn FnMut(Node) -> DirTreverse,
+        ) -> Result<(), FileSystemError> {
+            Ok(())
+        }
+
+        fn unmount(self: Arc<Self>) {
+            UNMOUNTED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let mapping = FileSystemMapping::empty_root();
+    mapping.set_root(Arc::new(TestFileSystem)).unwrap();
+    mapping.mount("/a", Arc::new(TestFileSystem)).unwrap();
+    mapping.mount("/a/b", Arc::new(TestFileSystem)).unwrap();
+
+    let unmount = |path, mode| mapping.unmount(Path::new(path), mode);
+    assert!(matches!(
+        unmount("/", UnmountMode::Normal),
+        Err(MappingError::Busy)
+    ));
+    assert!(matches!(
+        unmount("/c", UnmountMode::Normal),
+        Err(MappingError::NotMounted)
+    ));
+    // has `/a/b` inside it
+    assert!(matches!(
+        unmount("/a", UnmountMode::Normal),
+        Err(MappingError::Busy)
+    ));
+
+    // like an open file
+    let (_, _, node) = mapping.get_mapping(Path::new("/a/b/file")).unwrap();
+    let open_file = node.filesystem();
+    drop(node);
+    assert!(matches!(
+        unmount("/a/b", UnmountMode::Normal),
+        Err(MappingError::Busy)
+    ));
+
+    unmount("/a/b", UnmountMode::Lazy).unwrap();
+    // can't be reached, but still not unmounted
+    let (mapping_path, remaining, _) = mapping.get_mapping(Path::new("/a/b/file")).unwrap();
+    assert_eq!(mapping_path.as_path(), Path::new("/a"));
+    assert_eq!(remaining, Path::new("b/file"));
+    assert_eq!(UNMOUNTED.load(Ordering::Relaxed), 0);
+
+    drop(open_file);
+    mapping.release_detached();
+    assert_eq!(UNMOUNTED.load(Ordering::Relaxed), 1);
+
+    // not in use, unmounted immediately
+    unmount("/a", UnmountMode::Normal).unwrap();
+    assert_eq!(UNMOUNTED.load(Ordering::Relaxed), 2);
+}
@@ -1,39 +1,63 @@
+mod exfat;
 mod fat;
+pub mod initrd;
 pub mod mapping;
-mod mbr;
+pub mod mbr;
+pub mod notify;
 pub mod path;
 
+pub use fat::FatError;
+
 use core::ops;
 
-use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
-use kernel_user_link::file::{BlockingMode, DirEntry, FileStat, FileType, OpenOptions};
+use alloc::{
+    boxed::Box,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use kernel_user_link::file::{
+    watch_events, BlockingMode, DirEntry, FileStat, FileSystemStat, FileType, OpenOptions,
+};
 use mapping::MappingError;
 use path::PathBuf;
 use tracing::info;
 
 use crate::{
     devices::{
+        clock::{self, ClockTime},
         ide::{self, IdeDeviceIndex, IdeDeviceType},
+        pipe::PipeSide,
         Device, DEVICES_FILESYSTEM_CLUSTER_MAGIC,
     },
-    sync::{once::OnceLock, spin::mutex::Mutex},
+    init_stage::{self, InitStage},
+    memory_management::shared_pages,
+    process::scheduler::WaitQueue,
+    sync::{
+        adaptive_mutex::{self, AdaptiveMutex},
+        once::Lazy,
+        spin::mutex::Mutex,
+    },
 };
 
 use self::{
     mbr::Mbr,
-    path::{Component, Path},
+    path::{CaseSensitivity, Component, Path},
 };
 
 /// This is not used at all, just an indicator in [`Directory::fetch_entries`]
 pub(crate) const ANOTHER_FILESYSTEM_MAPPING_INODE_MAGIC: u64 = 0xf11356573e;
 pub(crate) const NO_PARENT_DIR_SECTOR: u64 = 0xFFFF_FFFF_FFFF_FFFF;
 
-static EMPTY_FILESYSTEM: OnceLock<Arc<EmptyFileSystem>> = OnceLock::new();
+static EMPTY_FILESYSTEM: Lazy<Arc<EmptyFileSystem>> = Lazy::new(|| Arc::new(EmptyFileSystem));
+static FILESYSTEM_IDS: Mutex<FileSystemIds> = Mutex::new(FileSystemIds {
+    ids: Vec::new(),
+    next_id: 0,
+});
 
 pub fn empty_filesystem() -> Arc<EmptyFileSystem> {
-    EMPTY_FILESYSTEM
-        .get_or_init(|| Arc::new(EmptyFileSystem))
-        .clone()
+    EMPTY_FILESYSTEM.clone()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -177,7 +201,7 @@ impl FileNode {
                 parent_dir_sector: NO_PARENT_DIR_SECTOR,
                 parent_dir_index: 0,
             },
-            size: 0,
+            size: device.size(),
             device: Some(device),
         }
     }
@@ -392,12 +416,68 @@ impl ops::DerefMut for Node {
     }
 }
 
+/// A run of contiguous clusters in a file
+#[derive(Debug, Clone, Copy)]
+struct Extent {
+    /// Index of the first cluster of the run in the file
+    index: u64,
+    /// The first cluster of the run on disk
+    cluster: u64,
+    len: u64,
+}
+
 // This is some sort of cache or extra metadata the filesystem
 // use to help implement the filesystem and improve performance
 #[derive(Debug, Default)]
 pub struct AccessHelper {
     current_cluster: u64,
     cluster_index: u64,
+    /// The clusters of the file resolved so far (from the start of the file), so that seeking
+    /// doesn't have to follow the chain from the start every time
+    extents: Vec<Extent>,
+    /// The generation of the filesystem's cluster chains when the `extents` were resolved,
+    /// if it changes (file truncated or extended), they are discarded
+    extents_generation: u64,
+}
+
+impl AccessHelper {
+    /// Drop the cached extents if they were resolved in another `generation`
+    fn check_extents_generation(&mut self, generation: u64) {
+        if self.extents_generation != generation {
+            self.extents.clear();
+            self.extents_generation = generation;
+        }
+    }
+
+    /// The cluster at `index` in the file, if it's resolved
+    fn cached_cluster(&self, index: u64) -> Option<u64> {
+        let i = self.extents.partition_point(|e| e.index + e.len <= index);
+        self.extents
+            .get(i)
+            .filter(|e| e.index <= index)
+            .map(|e| e.cluster + (index - e.index))
+    }
+
+    /// The last resolved cluster, as `(index, cluster)`
+    fn last_cached_cluster(&self) -> Option<(u64, u64)> {
+        self.extents
+            .last()
+            .map(|e| (e.index + e.len - 1, e.cluster + e.len - 1))
+    }
+
+    /// Add the cluster at `index` to the extents, ignored if it's not the one right after the last resolved cluster
+    fn cache_cluster(&mut self, index: u64, cluster: u64) {
+        match self.extents.last_mut() {
+            Some(last) if last.index + last.len != index => {}
+            Some(last) if last.cluster + last.len == cluster => last.len += 1,
+            None if index != 0 => {}
+            _ => self.extents.push(Extent {
+                index,
+                cluster,
+                len: 1,
+            }),
+        }
+    }
 }
 
 pub enum DirTreverse {
@@ -408,6 +488,9 @@ pub enum DirTreverse {
 /// A filesystem trait, this is the main interface to interact with the filesystem
 /// it is used to open files, directories, read and write files, etc.
 pub trait FileSystem: Send + Sync {
+    /// The type of the filesystem, shown in `/proc/mounts`
+    fn type_name(&self) -> &'static str;
+
     /// Open the root directory of the filesystem
     fn open_root(&self) -> Result<DirectoryNode, FileSystemError>;
 
@@ -419,13 +502,20 @@ pub trait FileSystem: Send + Sync {
         handler: &mut dyn FnMut(Node) -> DirTreverse,
     ) -> Result<(), FileSystemError>;
 
+    /// How names are compared when looking up entries, case sensitive by default.
+    /// Case insensitive filesystems should still keep the case of the names as created.
+    fn case_sensitivity(&self) -> CaseSensitivity {
+        CaseSensitivity::Sensitive
+    }
+
     /// Traverse the directory in the `inode` and return the entry with the name `matcher`
     /// Most of the time, no need to implement this, as it is already implemented in the default
-    /// using [`FileSystem::read_dir`]
+    /// using [`FileSystem::read_dir`] and [`FileSystem::case_sensitivity`]
     fn treverse_dir(&self, inode: &DirectoryNode, matcher: &str) -> Result<Node, FileSystemError> {
+        let case_sensitivity = self.case_sensitivity();
         let mut entry = None;
         self.read_dir(inode, &mut |inode| {
-            if inode.name() == matcher {
+            if case_sensitivity.names_eq(inode.name(), matcher) {
                 entry = Some(inode);
                 DirTreverse::Stop
             } else {
@@ -492,6 +582,27 @@ pub trait FileSystem: Send + Sync {
         Err(FileSystemError::WriteNotSupported)
     }
 
+    /// Write all the cached data of the file in `inode` and its metadata to disk,
+    /// unlike [`FileSystem::flush_file`], this is not limited to the data around the current position.
+    fn sync_file(
+        &self,
+        _inode: &mut FileNode,
+        _access_helper: &mut AccessHelper,
+    ) -> Result<(), FileSystemError> {
+        // nothing is cached by default
+        Ok(())
+    }
+
+    /// Write all the cached data and metadata of the filesystem to disk
+    fn sync(&self) -> Result<(), FileSystemError> {
+        Ok(())
+    }
+
+    /// Get the size and free space of the filesystem
+    fn fs_stat(&self) -> Result<FileSystemStat, FileSystemError> {
+        Err(FileSystemError::OperationNotSupported)
+    }
+
     /// Close the file in the `inode`, this is called when the file is dropped
     /// The `access_helper` is used to store some extra metadata to help the filesystem
     /// manage the caches or any extra data it needs.
@@ -532,6 +643,10 @@ pub trait FileSystem: Send + Sync {
 pub struct EmptyFileSystem;
 
 impl FileSystem for EmptyFileSystem {
+    fn type_name(&self) -> &'static str {
+        "none"
+    }
+
     fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
         Err(FileSystemError::FileNotFound)
     }
@@ -549,8 +664,12 @@ impl FileSystem for EmptyFileSystem {
 pub enum FileSystemError {
     PartitionTableNotFound,
     DeviceNotFound,
-    DiskReadError { sector: u64, error: ide::IdeError },
+    DiskReadError {
+        sector: u64,
+        error: ide::IdeError,
+    },
     FatError(fat::FatError),
+    ExFatError(exfat::ExFatError),
     FileNotFound,
     InvalidPath,
     MustBeAbsolute,
@@ -564,6 +683,26 @@ pub enum FileSystemError {
     BufferNotLargeEnough(usize),
     AlreadyExists,
     MappingError(MappingError),
+    /// A blocking read didn't get data within the file's read timeout
+    TimedOut,
+    /// Seeking, or accessing at an offset, a stream device
+    NotSeekable,
+    /// A blocking read has no data yet, and the device has a wait queue, the caller should wait
+    /// on [`File::wait_queue`] and read again
+    WouldBlock,
+    /// The filesystem is locked by someone else, the syscall is run again when it's unlocked,
+    /// see [`adaptive_mutex`](crate::sync::adaptive_mutex)
+    Blocked,
+    /// The data written to a device is not valid for it, i.e. a malformed command
+    InvalidInput,
+    /// The device is not in a usable state, i.e. the RTC holds a time before 1970
+    DeviceError,
+}
+
+impl From<adaptive_mutex::WouldBlock> for FileSystemError {
+    fn from(_: adaptive_mutex::WouldBlock) -> Self {
+        FileSystemError::Blocked
+    }
 }
 
 /// Loads the hard disk specified in the argument
@@ -572,6 +711,8 @@ pub enum FileSystemError {
 ///
 /// Creates a new filesystem mapping for `/` and the filesystem found
 pub fn create_disk_mapping(hard_disk_index: usize) -> Result<(), FileSystemError> {
+    // the disks are found when probing PCI devices
+    init_stage::debug_assert_reached(InitStage::Devices);
     let ide_index = IdeDeviceIndex {
         ty: IdeDeviceType::Ata,
         index: hard_disk_index,
@@ -583,18 +724,33 @@ pub fn create_disk_mapping(hard_disk_index: usize) -> Result<(), FileSystemError
 
     // load the first partition for now
     let first_partition = &mbr.partition_table[0];
-    let filesystem = fat::load_fat_filesystem(
-        device,
-        first_partition.start_lba,
-        first_partition.size_in_sectors,
-    )?;
-    info!(
-        "Mapping / to FAT filesystem {:?} ({:?}), partition_type: 0x{:02X}",
-        filesystem.volume_label(),
-        filesystem.fat_type(),
-        first_partition.partition_type
-    );
-    mapping::mount("/", Arc::new(Mutex::new(filesystem)))?;
+    let filesystem: Arc<dyn FileSystem> = if exfat::is_exfat(&device, first_partition.start_lba)? {
+        let filesystem = exfat::load_exfat_filesystem(
+            device,
+            first_partition.start_lba,
+            first_partition.size_in_sectors,
+        )?;
+        info!(
+            "Mapping / to exFAT filesystem {:?} (read only), partition_type: 0x{:02X}",
+            filesystem.volume_label(),
+            first_partition.partition_type
+        );
+        Arc::new(AdaptiveMutex::new(filesystem))
+    } else {
+        let filesystem = fat::load_fat_filesystem(
+            device,
+            first_partition.start_lba,
+            first_partition.size_in_sectors,
+        )?;
+        info!(
+            "Mapping / to FAT filesystem {:?} ({:?}), partition_type: 0x{:02X}",
+            filesystem.volume_label(),
+            filesystem.fat_type(),
+            first_partition.partition_type
+        );
+        Arc::new(AdaptiveMutex::new(filesystem))
+    };
+    mapping::mount("/", filesystem)?;
 
     Ok(())
 }
@@ -664,9 +820,11 @@ pub(crate) fn open_inode<P: AsRef<Path>>(
         }
 
         children_after_mapping += 1;
-        canonical_path.push(name);
 
         let mut entry = filesystem.treverse_dir(&dir, name)?;
+        // use the name as stored, for case insensitive filesystems, it could be different
+        // from `name`, so that all the ways to write the path give the same canonical path
+        canonical_path.push(entry.name());
 
         if remaining_components.peek().is_some() {
             if let Node::Directory(dir_node) = entry {
@@ -714,11 +872,11 @@ impl FileAccess {
         Self { read, write }
     }
 
-    fn is_read(&self) -> bool {
+    pub fn is_read(&self) -> bool {
         self.read
     }
 
-    fn is_write(&self) -> bool {
+    pub fn is_write(&self) -> bool {
         self.write
     }
 }
@@ -734,6 +892,48 @@ impl ops::BitOr for FileAccess {
     }
 }
 
+/// The ids given by [`filesystem_id`]
+struct FileSystemIds {
+    /// The `Weak` keeps the allocation, so the address can't be reused by another filesystem
+    /// while it's here
+    ids: Vec<(Weak<dyn FileSystem>, u64)>,
+    next_id: u64,
+}
+
+/// An id for `filesystem`, unlike its address, it's never reused by another filesystem,
+/// i.e. after unmounting and mounting again
+fn filesystem_id(filesystem: &Arc<dyn FileSystem>) -> u64 {
+    let mut ids = FILESYSTEM_IDS.lock();
+    // the dropped ones will never be looked up again
+    ids.ids.retain(|(fs, _)| fs.strong_count() > 0);
+
+    let ptr = Arc::as_ptr(filesystem) as *const ();
+    if let Some((_, id)) = ids
+        .ids
+        .iter()
+        .find(|(fs, _)| fs.as_ptr() as *const () == ptr)
+    {
+        return *id;
+    }
+    let id = ids.next_id;
+    ids.next_id += 1;
+    ids.ids.push((Arc::downgrade(filesystem), id));
+    id
+}
+
+fn filesystem_content_id(filesystem: &Arc<dyn FileSystem>, inode: &FileNode) -> (u64, u64) {
+    (filesystem_id(filesystem), inode.start_cluster())
+}
+
+/// Pages loaded from this file shouldn't be reused after the file is modified
+fn invalidate_shared_pages(filesystem: &Arc<dyn FileSystem>, inode: &FileNode) {
+    // devices don't have content to be shared
+    if inode.device.is_none() {
+        let (filesystem, inode) = filesystem_content_id(filesystem, inode);
+        shared_pages::invalidate_file(filesystem, inode);
+    }
+}
+
 /// A handle to a file, it has the inode which controls the properties of the node in the filesystem
 pub struct File {
     filesystem: Arc<dyn FileSystem>,
@@ -741,7 +941,12 @@ pub struct File {
     inode: FileNode,
     position: u64,
     is_terminal: bool,
+    /// Don't inherit this file when spawning processes, see [`FileMeta::CloseOnSpawn`](kernel_user_link::file::FileMeta::CloseOnSpawn)
+    close_on_spawn: bool,
     blocking_mode: BlockingMode,
+    /// Nanoseconds a blocking read waits for data, `0` waits forever,
+    /// see [`FileMeta::ReadTimeout`](kernel_user_link::file::FileMeta::ReadTimeout)
+    read_timeout: u64,
     access_helper: AccessHelper,
     file_access: FileAccess,
 }
@@ -787,7 +992,7 @@ impl File {
                 if open_options.is_create() || open_options.is_create_new() =>
             {
                 let path = path.as_ref();
-                let (canonical_path, filesystem, parent_inode) =
+                let (mut canonical_path, filesystem, parent_inode) =
                     open_inode(path.parent().unwrap())?;
                 let filename = path.file_name().unwrap();
                 if filename == "." || filename == ".." || filename == "/" {
@@ -798,6 +1003,8 @@ impl File {
                     filename,
                     FileAttributes::EMPTY,
                 )?;
+                canonical_path.push(filename);
+                notify::notify(&canonical_path, watch_events::CREATED);
                 (
                     canonical_path,
                     node.into_file()
@@ -811,6 +1018,8 @@ impl File {
         if open_options.is_truncate() {
             if open_options.is_write() {
                 filesystem.set_file_size(&mut node, 0)?;
+                invalidate_shared_pages(&filesystem, &node);
+                notify::notify(&canonical_path, watch_events::MODIFIED);
             } else {
                 return Err(FileSystemError::WriteNotSupported);
             }
@@ -824,7 +1033,10 @@ impl File {
 
         let access = FileAccess::new(open_options.is_read(), open_options.is_write());
 
-        Self::from_inode(node, canonical_path, filesystem, pos, blocking_mode, access)
+        let mut file =
+            Self::from_inode(node, canonical_path, filesystem, pos, blocking_mode, access)?;
+        file.close_on_spawn = open_options.is_close_on_spawn();
+        Ok(file)
     }
 
     pub fn from_inode<P: AsRef<Path>>(
@@ -841,7 +1053,9 @@ impl File {
             inode,
             position,
             is_terminal: false,
+            close_on_spawn: false,
             blocking_mode,
+            read_timeout: 0,
             access_helper: AccessHelper::default(),
             file_access,
         })
@@ -860,6 +1074,8 @@ impl File {
                 &mut self.access_helper,
             )?,
             BlockingMode::Line => {
+                let waitable = self.is_waitable();
+                let deadline = self.read_deadline();
                 // read until \n or \0
                 let mut i = 0;
                 loop {
@@ -890,7 +1106,21 @@ impl File {
                             break;
                         }
                     } else {
-                        // TODO: add IO waiting
+                        if waitable {
+                            // the rest of the line may take a while, return what we have
+                            if i == 0 {
+                                return Err(FileSystemError::WouldBlock);
+                            }
+                            break;
+                        }
+                        if Self::passed(deadline) {
+                            // return what we have of the line, if any
+                            if i == 0 {
+                                return Err(FileSystemError::TimedOut);
+                            }
+                            break;
+                        }
+                        // TODO: add IO waiting for the devices without a wait queue
                         for _ in 0..100 {
                             core::hint::spin_loop();
                         }
@@ -901,6 +1131,8 @@ impl File {
             BlockingMode::Block(size) => {
                 // TODO: support block size > 1
                 assert_eq!(size, 1, "Only block size 1 is supported");
+                let waitable = self.is_waitable();
+                let deadline = self.read_deadline();
 
                 // try to read until we have something
                 loop {
@@ -924,8 +1156,14 @@ impl File {
                     if read_byte != 0 {
                         break read_byte;
                     }
+                    if waitable {
+                        return Err(FileSystemError::WouldBlock);
+                    }
+                    if Self::passed(deadline) {
+                        return Err(FileSystemError::TimedOut);
+                    }
                     // otherwise we wait
-                    // TODO: add IO waiting
+                    // TODO: add IO waiting for the devices without a wait queue
                     for _ in 0..100 {
                         core::hint::spin_loop();
                     }
@@ -942,6 +1180,7 @@ impl File {
             return Err(FileSystemError::WriteNotSupported);
         }
 
+        invalidate_shared_pages(&self.filesystem, &self.inode);
         let written = self.filesystem.write_file(
             &mut self.inode,
             self.position,
@@ -949,9 +1188,123 @@ impl File {
             &mut self.access_helper,
         )?;
         self.position += written;
+        if written != 0 {
+            self.notify_modified();
+        }
+        Ok(written)
+    }
+
+    /// Inform watchers of this file, devices are not watched
+    fn notify_modified(&self) {
+        if self.inode.device.is_none() {
+            notify::notify(&self.path, watch_events::MODIFIED);
+        }
+    }
+
+    /// Read at `offset` without using or changing the current position.
+    /// This doesn't wait for data, regardless of the blocking mode.
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        if !self.file_access.is_read() {
+            return Err(FileSystemError::ReadNotSupported);
+        }
+        if !self.is_seekable() {
+            return Err(FileSystemError::NotSeekable);
+        }
+
+        self.filesystem
+            .read_file(&self.inode, offset, buf, &mut self.access_helper)
+    }
+
+    /// Write at `offset` without using or changing the current position
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
+        if !self.file_access.is_write() {
+            return Err(FileSystemError::WriteNotSupported);
+        }
+        if !self.is_seekable() {
+            return Err(FileSystemError::NotSeekable);
+        }
+
+        invalidate_shared_pages(&self.filesystem, &self.inode);
+        let written =
+            self.filesystem
+                .write_file(&mut self.inode, offset, buf, &mut self.access_helper)?;
+        if written != 0 {
+            self.notify_modified();
+        }
         Ok(written)
     }
 
+    /// Read into `bufs` in order, the first buffer follows the blocking mode, the rest are only
+    /// filled with what is available without waiting.
+    /// Stops at the first short read.
+    pub fn read_vectored(&mut self, bufs: &mut [&mut [u8]]) -> Result<u64, FileSystemError> {
+        if !self.file_access.is_read() {
+            return Err(FileSystemError::ReadNotSupported);
+        }
+
+        let mut bufs = bufs.iter_mut().filter(|buf| !buf.is_empty());
+        let Some(first) = bufs.next() else {
+            return Ok(0);
+        };
+
+        let first_read = self.read(first)?;
+        if first_read < first.len() as u64 {
+            return Ok(first_read);
+        }
+
+        let mut total = 0;
+        for buf in bufs {
+            let read = match self.filesystem.read_file(
+                &self.inode,
+                self.position + total,
+                buf,
+                &mut self.access_helper,
+            ) {
+                Ok(read) => read,
+                // we already have some data, report the error on the next read
+                Err(_) => break,
+            };
+            total += read;
+            if read < buf.len() as u64 {
+                break;
+            }
+        }
+        self.position += total;
+        Ok(first_read + total)
+    }
+
+    /// Write `bufs` in order, stops at the first short write
+    pub fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<u64, FileSystemError> {
+        if !self.file_access.is_write() {
+            return Err(FileSystemError::WriteNotSupported);
+        }
+
+        invalidate_shared_pages(&self.filesystem, &self.inode);
+        let mut total = 0;
+        for buf in bufs.iter().filter(|buf| !buf.is_empty()) {
+            let written = match self.filesystem.write_file(
+                &mut self.inode,
+                self.position + total,
+                buf,
+                &mut self.access_helper,
+            ) {
+                Ok(written) => written,
+                Err(e) if total == 0 => return Err(e),
+                // we already wrote some data, report the error on the next write
+                Err(_) => break,
+            };
+            total += written;
+            if written < buf.len() as u64 {
+                break;
+            }
+        }
+        self.position += total;
+        if total != 0 {
+            self.notify_modified();
+        }
+        Ok(total)
+    }
+
     pub fn flush(&mut self) -> Result<(), FileSystemError> {
         if !self.file_access.is_write() {
             return Err(FileSystemError::WriteNotSupported);
@@ -962,10 +1315,21 @@ impl File {
     }
 
     pub fn seek(&mut self, position: u64) -> Result<(), FileSystemError> {
+        if !self.is_seekable() {
+            return Err(FileSystemError::NotSeekable);
+        }
         self.position = position;
         Ok(())
     }
 
+    /// Regular files and seekable devices, stream devices (i.e. pipes) ignore the position
+    pub fn is_seekable(&self) -> bool {
+        self.inode
+            .device
+            .as_ref()
+            .map_or(true, |device| device.is_seekable())
+    }
+
     pub fn filesize(&self) -> u64 {
         self.inode.size()
     }
@@ -1007,6 +1371,63 @@ impl File {
         self.is_terminal = is_terminal;
     }
 
+    pub fn read_timeout(&self) -> u64 {
+        self.read_timeout
+    }
+
+    pub fn set_read_timeout(&mut self, nanos: u64) {
+        self.read_timeout = nanos;
+    }
+
+    /// The time a blocking read started now should give up, if there is a read timeout
+    fn read_deadline(&self) -> Option<ClockTime> {
+        (self.read_timeout != 0).then(|| {
+            clock::clocks().time_since_startup() + ClockTime::from_nanos(self.read_timeout)
+        })
+    }
+
+    /// The queue to wait on when a read or write returns [`FileSystemError::WouldBlock`]
+    pub fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        self.inode.device.as_ref()?.wait_queue()
+    }
+
+    /// Blocking reads return [`FileSystemError::WouldBlock`] instead of polling the device,
+    /// reads with a timeout still poll, as nothing wakes the queue when the time passes
+    fn is_waitable(&self) -> bool {
+        self.read_timeout == 0 && self.wait_queue().is_some()
+    }
+
+    pub fn line_editing(&self) -> Option<bool> {
+        self.inode.device.as_ref()?.line_editing()
+    }
+
+    pub fn as_pipe(&self) -> Option<&PipeSide> {
+        self.inode.device.as_ref()?.as_pipe()
+    }
+
+    pub fn set_line_editing(&self, line_editing: bool) -> Result<(), FileSystemError> {
+        match &self.inode.device {
+            Some(device) => device.set_line_editing(line_editing),
+            None => Err(FileSystemError::OperationNotSupported),
+        }
+    }
+
+    fn passed(deadline: Option<ClockTime>) -> bool {
+        deadline.is_some_and(|deadline| clock::clocks().time_since_startup() >= deadline)
+    }
+
+    pub fn access(&self) -> FileAccess {
+        self.file_access
+    }
+
+    pub fn is_close_on_spawn(&self) -> bool {
+        self.close_on_spawn
+    }
+
+    pub fn set_close_on_spawn(&mut self, close_on_spawn: bool) {
+        self.close_on_spawn = close_on_spawn;
+    }
+
     pub fn size(&self) -> u64 {
         self.inode.size()
     }
@@ -1020,7 +1441,31 @@ impl File {
             return Err(FileSystemError::WriteNotSupported);
         }
 
-        self.filesystem.set_file_size(&mut self.inode, size)
+        invalidate_shared_pages(&self.filesystem, &self.inode);
+        self.filesystem.set_file_size(&mut self.inode, size)?;
+        self.notify_modified();
+        Ok(())
+    }
+
+    /// Write all the cached data of this file to disk
+    pub fn sync(&mut self) -> Result<(), FileSystemError> {
+        self.filesystem
+            .sync_file(&mut self.inode, &mut self.access_helper)
+    }
+
+    /// Make sure the file is at least `size` bytes, the new space is zeroed.
+    /// Doesn't shrink the file.
+    pub fn allocate(&mut self, size: u64) -> Result<(), FileSystemError> {
+        if size <= self.filesize() {
+            return Ok(());
+        }
+        self.set_size(size)
+    }
+
+    /// An identifier of the content of this file, `(filesystem, inode)`, used for
+    /// [`shared_pages`](crate::memory_management::shared_pages)
+    pub fn content_id(&self) -> (u64, u64) {
+        filesystem_content_id(&self.filesystem, &self.inode)
     }
 
     /// This is a move verbose method than `Clone::clone`, as I want it to be
@@ -1032,7 +1477,10 @@ impl File {
             inode: self.inode.clone(),
             position: 0,
             is_terminal: self.is_terminal,
+            // the flag belongs to the fd, not the file it points to
+            close_on_spawn: false,
             blocking_mode: self.blocking_mode,
+            read_timeout: self.read_timeout,
             access_helper: AccessHelper::default(),
             file_access: self.file_access,
         };
@@ -1047,6 +1495,14 @@ impl File {
 
         s
     }
+
+    /// Same as [`File::clone_inherit`], but keeps the current position, used for duplicating
+    /// file descriptors in the same process
+    pub fn clone_duplicate(&self) -> Self {
+        let mut s = self.clone_inherit();
+        s.position = self.position;
+        s
+    }
 }
 
 impl Drop for File {
@@ -1054,6 +1510,9 @@ impl Drop for File {
         self.filesystem
             .close_file(&self.inode, core::mem::take(&mut self.access_helper))
             .expect("Failed to close file");
+        // drop our reference, so the filesystem can be unmounted if it was lazily unmounted
+        self.filesystem = empty_filesystem();
+        mapping::release_detached();
     }
 }
 
@@ -1082,26 +1541,29 @@ impl Directory {
 
     fn fetch_entries(&mut self) -> Result<(), FileSystemError> {
         if self.dir_entries.is_none() {
-            let mut dir_entries = Vec::new();
-            self.filesystem.read_dir(&self.inode, &mut |entry| {
-                dir_entries.push(entry);
-                DirTreverse::Continue
-            })?;
-            // add entries from the root mappings
+            // entries from the mappings inside this directory
+            let mut mounts = Vec::new();
             mapping::on_all_matching_mappings(&self.path, |path, _fs| {
                 // only add path with one component
                 if path.components().count() == 1 {
-                    dir_entries.push(
-                        DirectoryNode::without_parent(
-                            path.components().next().unwrap().as_str().into(),
-                            FileAttributes::DIRECTORY,
-                            ANOTHER_FILESYSTEM_MAPPING_INODE_MAGIC,
-                        )
-                        .into(),
-                    );
+                    mounts.push(Node::from(DirectoryNode::without_parent(
+                        path.components().next().unwrap().as_str().into(),
+                        FileAttributes::DIRECTORY,
+                        ANOTHER_FILESYSTEM_MAPPING_INODE_MAGIC,
+                    )));
                 }
             })?;
 
+            let mut dir_entries = Vec::new();
+            self.filesystem.read_dir(&self.inode, &mut |entry| {
+                // the mappings hide the entries they are mounted on
+                if !mounts.iter().any(|mount| mount.name() == entry.name()) {
+                    dir_entries.push(entry);
+                }
+                DirTreverse::Continue
+            })?;
+            dir_entries.extend(mounts);
+
             self.dir_entries = Some(dir_entries);
         }
 
@@ -1120,6 +1582,7 @@ impl Directory {
         let node = self.filesystem.create_node(&self.inode, name, attributes)?;
 
         let path = self.path.join(name);
+        notify::notify(&path, watch_events::CREATED);
 
         match node {
             Node::File(file) => Ok(File::from_inode(
@@ -1177,6 +1640,14 @@ impl Clone for Directory {
     }
 }
 
+impl Drop for Directory {
+    fn drop(&mut self) {
+        // drop our reference, so the filesystem can be unmounted if it was lazily unmounted
+        self.filesystem = empty_filesystem();
+        mapping::release_detached();
+    }
+}
+
 #[allow(dead_code)]
 impl FilesystemNode {
     pub fn as_file(&self) -> Result<&File, FileSystemError> {
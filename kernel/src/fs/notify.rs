@@ -0,0 +1,153 @@
+//! Change notifications for files and directories
+//!
+//! A process can watch a path with [`watch`], and gets back a file to read [`WatchEvent`]s from.
+//! Events are generated by the operations in [`File`](super::File) and [`Directory`](super::Directory),
+//! watching a directory gives events for its direct children, and watching a file gives events for the file itself.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{
+    collections::VecDeque,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use kernel_user_link::file::{BlockingMode, DirFilename, WatchEvent, MAX_FILENAME_LEN};
+
+use crate::{devices::Device, sync::spin::mutex::Mutex};
+
+use super::{
+    open_inode,
+    path::{Path, PathBuf},
+    FileAccess, FileAttributes, FileNode, FileSystemError,
+};
+
+/// Events after this are dropped until the watcher reads some
+const MAX_QUEUED_EVENTS: usize = 256;
+
+static WATCHERS: Mutex<Vec<Weak<Watcher>>> = Mutex::new(Vec::new());
+/// Number of live watchers, so that we don't take the lock on every write when no one is watching
+static NUM_WATCHERS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+struct Watcher {
+    path: PathBuf,
+    mask: u32,
+    events: Mutex<VecDeque<WatchEvent>>,
+}
+
+impl Watcher {
+    fn push(&self, event: WatchEvent) {
+        let mut events = self.events.lock();
+        if events.len() < MAX_QUEUED_EVENTS {
+            events.push_back(event);
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        NUM_WATCHERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+struct WatchDevice {
+    watcher: Arc<Watcher>,
+}
+
+impl Device for WatchDevice {
+    fn name(&self) -> &str {
+        "watch"
+    }
+
+    /// Only reads whole events
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        let event_size = core::mem::size_of::<WatchEvent>();
+        if buf.len() < event_size {
+            return Err(FileSystemError::BufferNotLargeEnough(event_size));
+        }
+
+        let mut events = self.watcher.events.lock();
+        let mut read = 0;
+        for chunk in buf.chunks_exact_mut(event_size) {
+            let Some(event) = events.pop_front() else {
+                break;
+            };
+            // SAFETY: `WatchEvent` is `repr(C)` and only contains integers and bytes
+            let bytes = unsafe {
+                core::slice::from_raw_parts(&event as *const WatchEvent as *const u8, event_size)
+            };
+            chunk.copy_from_slice(bytes);
+            read += event_size;
+        }
+        Ok(read as u64)
+    }
+}
+
+/// Watch `path` (must be absolute) for the events in `mask` (see [`watch_events`](kernel_user_link::file::watch_events)),
+/// the returned file is read only and gives [`WatchEvent`]s
+pub fn watch(path: &Path, mask: u32) -> Result<super::File, FileSystemError> {
+    // make sure it exists, and use the canonical path, as that's what the events use
+    let (canonical_path, _, _) = open_inode(path)?;
+
+    let watcher = Arc::new(Watcher {
+        path: canonical_path.clone(),
+        mask,
+        events: Mutex::new(VecDeque::new()),
+    });
+    NUM_WATCHERS.fetch_add(1, Ordering::Relaxed);
+    WATCHERS.lock().push(Arc::downgrade(&watcher));
+
+    let inode = FileNode::new_device(
+        String::from("watch"),
+        FileAttributes::READ_ONLY,
+        Arc::new(WatchDevice { watcher }),
+    );
+    super::File::from_inode(
+        inode,
+        canonical_path,
+        super::empty_filesystem(),
+        0,
+        BlockingMode::None,
+        FileAccess::READ,
+    )
+}
+
+/// Inform the watchers of `path` and its parent directory of an event
+pub(super) fn notify(path: &Path, kind: u32) {
+    if NUM_WATCHERS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let parent = path.parent();
+    let name = path.file_name().unwrap_or("");
+    let child_event = WatchEvent {
+        kind,
+        // we can't report longer names, but these shouldn't exist
+        name: if name.len() < MAX_FILENAME_LEN {
+            DirFilename::from(name)
+        } else {
+            DirFilename::default()
+        },
+    };
+    let self_event = WatchEvent {
+        kind,
+        name: DirFilename::default(),
+    };
+
+    WATCHERS.lock().retain(|watcher| {
+        let Some(watcher) = watcher.upgrade() else {
+            // closed
+            return false;
+        };
+        if watcher.mask & kind != 0 {
+            if watcher.path.as_path() == path {
+                watcher.push(self_event);
+            } else if parent == Some(watcher.path.as_path()) {
+                watcher.push(child_event);
+            }
+        }
+        true
+    });
+}
@@ -49,6 +49,8 @@ use alloc::{
     sync::Arc,
 };
 
+use crate::testing;
+
 /// use unix paths separator
 pub const SEPARATOR: char = '/';
 
@@ -60,6 +62,38 @@ fn is_separator_byte(c: u8) -> bool {
     c == SEPARATOR as u8
 }
 
+/// How the names of path components are compared, this depends on the filesystem
+/// (see `FileSystem::case_sensitivity`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseSensitivity {
+    #[default]
+    Sensitive,
+    /// Names that differ only in case are the same file, the filesystem still keeps
+    /// the case the name was created with
+    Insensitive,
+}
+
+impl CaseSensitivity {
+    /// Check if the names `a` and `b` refer to the same entry
+    pub fn names_eq(self, a: &str, b: &str) -> bool {
+        match self {
+            CaseSensitivity::Sensitive => a == b,
+            CaseSensitivity::Insensitive => eq_ignore_case(a, b),
+        }
+    }
+}
+
+/// Compare `a` and `b` ignoring case, unlike [`str::eq_ignore_ascii_case`], this handles
+/// non-ASCII letters as well, by comparing the lowercase forms of the characters
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    if a.is_ascii() && b.is_ascii() {
+        return a.eq_ignore_ascii_case(b);
+    }
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
 // Iterate through `iter` while it matches `prefix`; return `None` if `prefix`
 // is not a prefix of `iter`, otherwise return `Some(iter_after_prefix)` giving
 // `iter` after having exhausted `prefix`.
@@ -86,6 +120,20 @@ fn has_root(path: &str) -> bool {
     !path.is_empty() && path.as_bytes()[0] == b'/'
 }
 
+/// Split the file name into the stem and the extension, at the last `.`
+///
+/// Names starting with `.` with no other `.` (i.e. `.bashrc`) have no extension,
+/// and `..` is not split.
+fn split_file_at_dot(file_name: &str) -> (&str, Option<&str>) {
+    if file_name == ".." {
+        return (file_name, None);
+    }
+    match file_name.rfind('.') {
+        None | Some(0) => (file_name, None),
+        Some(i) => (&file_name[..i], Some(&file_name[i + 1..])),
+    }
+}
+
 /// Component parsing works by a double-ended state machine; the cursors at the
 /// front and back of the path each keep track of what parts of the path have
 /// been consumed so far.
@@ -304,7 +352,8 @@ impl<'a> Components<'a> {
     // remove the component
     fn parse_next_component(&self) -> (usize, Option<Component<'a>>) {
         debug_assert!(self.front == State::Body);
-        let (extra, comp) = match self.path.chars().position(is_separator) {
+        // the separator is ASCII, so we can search the bytes, and get a byte index
+        let (extra, comp) = match self.path.bytes().position(is_separator_byte) {
             None => (0, self.path),
             Some(i) => (1, &self.path[..i]),
         };
@@ -553,6 +602,58 @@ impl<'a> Iterator for Ancestors<'a> {
 
 impl FusedIterator for Ancestors<'_> {}
 
+/// An iterator over the names of the leading [`Component::Normal`] components of a [`Path`].
+///
+/// This `struct` is created by the [`Path::names`] method on [`Path`].
+/// See its documentation for more.
+#[derive(Clone)]
+pub struct Names<'a> {
+    inner: Components<'a>,
+}
+
+impl<'a> Names<'a> {
+    /// The rest of the path, starting from the first component not yet returned,
+    /// including the component that stopped the iteration (if any).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut names = Path::new("/a/b/../c").names();
+    /// assert_eq!(names.next(), Some("a"));
+    /// assert_eq!(names.as_path(), Path::new("b/../c"));
+    /// ```
+    pub fn as_path(&self) -> &'a Path {
+        self.inner.as_path()
+    }
+
+    pub fn peek(&self) -> Option<&'a str> {
+        self.clone().next()
+    }
+}
+
+impl fmt::Debug for Names<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Names").field(&self.as_path()).finish()
+    }
+}
+
+impl<'a> Iterator for Names<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        // don't consume the component that stops us, so that it stays in `as_path`
+        match self.inner.peek()? {
+            Component::Normal(name) => {
+                self.inner.next();
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FusedIterator for Names<'_> {}
+
 /// An owned, mutable path (akin to `String`).
 ///
 /// This type provides methods like [`PathBuf::push`] that mutate
@@ -749,6 +850,50 @@ impl PathBuf {
         self.push(file_name);
     }
 
+    /// Updates [`self.extension`] to `extension`.
+    ///
+    /// Returns `false` and does nothing if [`self.file_name`] is [`None`],
+    /// returns `true` and updates the extension otherwise.
+    ///
+    /// If [`self.extension`] is [`None`], the extension is added; otherwise
+    /// it is replaced. An empty `extension` removes the current one.
+    ///
+    /// [`self.file_name`]: struct.PathBuf.html#method.file_name
+    /// [`self.extension`]: struct.PathBuf.html#method.extension
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut p = PathBuf::from("/feel/the");
+    ///
+    /// p.set_extension("force");
+    /// assert_eq!(Path::new("/feel/the.force"), p.as_path());
+    ///
+    /// p.set_extension("dark_side");
+    /// assert_eq!(Path::new("/feel/the.dark_side"), p.as_path());
+    ///
+    /// p.set_extension("");
+    /// assert_eq!(Path::new("/feel/the"), p.as_path());
+    /// ```
+    pub fn set_extension<S: AsRef<str>>(&mut self, extension: S) -> bool {
+        self._set_extension(extension.as_ref())
+    }
+
+    fn _set_extension(&mut self, extension: &str) -> bool {
+        let Some(stem) = self.file_stem() else {
+            return false;
+        };
+
+        let mut file_name = String::with_capacity(stem.len() + 1 + extension.len());
+        file_name.push_str(stem);
+        if !extension.is_empty() {
+            file_name.push('.');
+            file_name.push_str(extension);
+        }
+        self.set_file_name(file_name);
+        true
+    }
+
     /// Consumes the `PathBuf`, yielding its internal `String` storage.
     ///
     /// # Examples
@@ -1326,6 +1471,50 @@ impl Path {
         })
     }
 
+    /// Extracts the stem (non-extension) portion of [`self.file_name`].
+    ///
+    /// [`self.file_name`]: struct.Path.html#method.file_name
+    ///
+    /// The stem is:
+    ///
+    /// * [`None`], if there is no file name;
+    /// * The entire file name if there is no embedded `.`;
+    /// * The entire file name if the file name begins with `.` and has no other `.`s within;
+    /// * Otherwise, the portion of the file name before the final `.`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Some("foo"), Path::new("foo.rs").file_stem());
+    /// assert_eq!(Some("foo.tar"), Path::new("foo.tar.gz").file_stem());
+    /// assert_eq!(Some(".bashrc"), Path::new("/home/.bashrc").file_stem());
+    /// ```
+    pub fn file_stem(&self) -> Option<&str> {
+        self.file_name().map(|name| split_file_at_dot(name).0)
+    }
+
+    /// Extracts the extension of [`self.file_name`], if possible.
+    ///
+    /// The extension is:
+    ///
+    /// * [`None`], if there is no file name;
+    /// * [`None`], if there is no embedded `.`;
+    /// * [`None`], if the file name begins with `.` and has no other `.`s within;
+    /// * Otherwise, the portion of the file name after the final `.`
+    ///
+    /// [`self.file_name`]: struct.Path.html#method.file_name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Some("rs"), Path::new("foo.rs").extension());
+    /// assert_eq!(Some("gz"), Path::new("foo.tar.gz").extension());
+    /// assert_eq!(None, Path::new("/home/.bashrc").extension());
+    /// ```
+    pub fn extension(&self) -> Option<&str> {
+        self.file_name().and_then(|name| split_file_at_dot(name).1)
+    }
+
     /// Returns a path that, when joined onto `base`, yields `self`.
     ///
     /// # Errors
@@ -1430,6 +1619,50 @@ impl Path {
         buf
     }
 
+    /// Resolves `.` and `..` components and collapses repeated separators, this is done
+    /// lexically, without accessing the filesystem.
+    ///
+    /// `..` at the root stays at the root, and leading `..` of relative paths are kept.
+    /// A trailing separator is kept, as it indicates that the path must be a directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Path::new("/a//b/./../c").normalize(), PathBuf::from("/a/c"));
+    /// assert_eq!(Path::new("/../a/").normalize(), PathBuf::from("/a/"));
+    /// assert_eq!(Path::new("../a/..").normalize(), PathBuf::from(".."));
+    /// ```
+    #[must_use]
+    pub fn normalize(&self) -> PathBuf {
+        let mut buf = PathBuf::new();
+        // number of normal components in `buf` that can be removed by `..`
+        let mut depth = 0;
+
+        for component in self.components() {
+            match component {
+                Component::RootDir => buf.push("/"),
+                Component::CurDir | Component::Normal("") => {}
+                Component::ParentDir => {
+                    if depth > 0 {
+                        buf.pop();
+                        depth -= 1;
+                    } else if !buf.has_root() {
+                        buf.push("..");
+                    }
+                }
+                Component::Normal(name) => {
+                    buf.push(name);
+                    depth += 1;
+                }
+            }
+        }
+
+        if self.has_last_separator() && !buf.is_empty() && !buf.has_last_separator() {
+            buf.push("");
+        }
+        buf
+    }
+
     /// Creates an owned [`PathBuf`] like `self` but with the given file name.
     ///
     /// See [`PathBuf::set_file_name`] for more details.
@@ -1456,6 +1689,32 @@ impl Path {
         buf
     }
 
+    /// Creates an owned [`PathBuf`] like `self` but with the given extension.
+    ///
+    /// See [`PathBuf::set_extension`] for more details.
+    ///
+    /// [`PathBuf`]: struct.PathBuf.html
+    /// [`PathBuf::set_extension`]: struct.PathBuf.html#method.set_extension
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let path = Path::new("/tmp/foo.txt");
+    /// assert_eq!(path.with_extension("rs"), PathBuf::from("/tmp/foo.rs"));
+    ///
+    /// let path = Path::new("/tmp/foo.tar.gz");
+    /// assert_eq!(path.with_extension(""), PathBuf::from("/tmp/foo.tar"));
+    /// ```
+    pub fn with_extension<S: AsRef<str>>(&self, extension: S) -> PathBuf {
+        self._with_extension(extension.as_ref())
+    }
+
+    fn _with_extension(&self, extension: &str) -> PathBuf {
+        let mut buf = self.to_path_buf();
+        buf.set_extension(extension);
+        buf
+    }
+
     /// Produces an iterator over the [`Component`]s of the path.
     ///
     /// When parsing the path, there is a small amount of normalization:
@@ -1516,6 +1775,35 @@ impl Path {
         }
     }
 
+    /// Produces an iterator over the names of the path, i.e. the [`Component::Normal`]
+    /// components after the root (if any).
+    ///
+    /// The iteration stops at the first `.` or `..` component, what is left
+    /// can be retrieved with [`Names::as_path`], a path that is [`normalize`]d
+    /// and absolute only has names.
+    ///
+    /// [`normalize`]: #method.normalize
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut names = Path::new("/tmp//foo.txt").names();
+    /// assert_eq!(names.next(), Some("tmp"));
+    /// assert_eq!(names.next(), Some("foo.txt"));
+    /// assert_eq!(names.next(), None);
+    ///
+    /// let mut names = Path::new("./foo.txt").names();
+    /// assert_eq!(names.next(), None);
+    /// assert_eq!(names.as_path(), Path::new("./foo.txt"));
+    /// ```
+    pub fn names(&self) -> Names<'_> {
+        let mut inner = self.components();
+        if inner.peek() == Some(Component::RootDir) {
+            inner.next();
+        }
+        Names { inner }
+    }
+
     /// Returns a newtype that implements Display for safely printing paths
     /// that may contain non-Unicode data.
     pub fn display(&self) -> Display<'_> {
@@ -1665,3 +1953,177 @@ impl fmt::Display for Display<'_> {
         fmt::Display::fmt(&self.path.as_str(), formatter)
     }
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_path_utf8_components() {
+    use alloc::vec::Vec;
+
+    let path = Path::new("/héllo/wörld/文件.txt");
+    let components = path.components().collect::<Vec<_>>();
+    assert_eq!(
+        components,
+        [
+            Component::RootDir,
+            Component::Normal("héllo"),
+            Component::Normal("wörld"),
+            Component::Normal("文件.txt"),
+        ]
+    );
+    assert_eq!(path.file_name(), Some("文件.txt"));
+    assert_eq!(path.parent(), Some(Path::new("/héllo/wörld")));
+    assert_eq!(Path::new("/héllo").join("wörld/文件.txt").as_path(), path);
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_path_case_insensitive_names() {
+    let insensitive = CaseSensitivity::Insensitive;
+    let sensitive = CaseSensitivity::Sensitive;
+
+    assert!(insensitive.names_eq("ReadMe.TXT", "readme.txt"));
+    assert!(insensitive.names_eq("ÉCOLE", "école"));
+    assert!(insensitive.names_eq("Straße", "STRAßE"));
+    assert!(insensitive.names_eq("ΣΊΣΥΦΟΣ", "σίσυφοσ"));
+    // the Kelvin sign is longer in bytes than `k`
+    assert!(insensitive.names_eq("\u{212A}elvin", "kelvin"));
+    assert!(!insensitive.names_eq("readme.txt", "readme.txt "));
+    assert!(!insensitive.names_eq("readme", "readme.txt"));
+    assert!(!insensitive.names_eq("e", "é"));
+
+    assert!(sensitive.names_eq("readme.txt", "readme.txt"));
+    assert!(!sensitive.names_eq("ReadMe.TXT", "readme.txt"));
+    assert!(!sensitive.names_eq("ÉCOLE", "école"));
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_path_backslash_is_not_separator() {
+    use alloc::vec::Vec;
+
+    // `\` is a normal character in names, paths from other systems are not converted
+    let path = Path::new("/dir\\sub\\file.txt");
+    let components = path.components().collect::<Vec<_>>();
+    assert_eq!(
+        components,
+        [Component::RootDir, Component::Normal("dir\\sub\\file.txt")]
+    );
+    assert_eq!(path.file_name(), Some("dir\\sub\\file.txt"));
+    assert_eq!(path.parent(), Some(Path::new("/")));
+
+    let path = Path::new("C:\\Windows/System32\\");
+    assert!(path.is_relative());
+    assert!(!path.has_last_separator());
+    assert_eq!(path.file_name(), Some("System32\\"));
+    assert_eq!(path.parent(), Some(Path::new("C:\\Windows")));
+    assert!(!is_separator('\\'));
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_path_normalize() {
+    let cases = [
+        ("/a/b/c", "/a/b/c"),
+        ("/a//b/./../c", "/a/c"),
+        ("/a/b/../../..", "/"),
+        ("/../a/", "/a/"),
+        ("/a/./", "/a/"),
+        ("a/../..", ".."),
+        ("../a/..", ".."),
+        ("./a/./b", "a/b"),
+        ("a/b/../../c/", "c/"),
+        (".", ""),
+        ("", ""),
+    ];
+    for (path, normalized) in cases {
+        assert_eq!(Path::new(path).normalize().as_str(), normalized, "{path:?}");
+    }
+
+    // already normalized paths stay the same
+    for (_, normalized) in cases {
+        assert_eq!(Path::new(normalized).normalize().as_str(), normalized);
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_path_extension() {
+    let cases = [
+        ("/tmp/foo.txt", Some("foo"), Some("txt")),
+        ("/tmp/foo.tar.gz", Some("foo.tar"), Some("gz")),
+        ("foo", Some("foo"), None),
+        ("foo.", Some("foo"), Some("")),
+        ("/home/.bashrc", Some(".bashrc"), None),
+        ("/home/.config.old", Some(".config"), Some("old")),
+        ("/dir.d/file", Some("file"), None),
+        ("/dir.d/", Some("dir"), Some("d")),
+        ("/", None, None),
+        ("/a/..", None, None),
+        ("", None, None),
+    ];
+    for (path, stem, extension) in cases {
+        let path = Path::new(path);
+        assert_eq!(path.file_stem(), stem, "{path:?}");
+        assert_eq!(path.extension(), extension, "{path:?}");
+    }
+
+    assert_eq!(
+        Path::new("/tmp/foo.txt").with_extension("rs").as_str(),
+        "/tmp/foo.rs"
+    );
+    assert_eq!(
+        Path::new("/tmp/foo").with_extension("rs").as_str(),
+        "/tmp/foo.rs"
+    );
+    assert_eq!(
+        Path::new("/tmp/foo.tar.gz").with_extension("").as_str(),
+        "/tmp/foo.tar"
+    );
+    assert_eq!(
+        Path::new("/home/.bashrc").with_extension("bak").as_str(),
+        "/home/.bashrc.bak"
+    );
+    assert_eq!(
+        Path::new("/tmp/dir.d/").with_extension("old").as_str(),
+        "/tmp/dir.old"
+    );
+
+    let mut path = PathBuf::from("/");
+    assert!(!path.set_extension("txt"));
+    assert_eq!(path.as_str(), "/");
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_path_strip_prefix() {
+    let path = Path::new("/devices/disk/part1");
+
+    assert_eq!(path.strip_prefix("/"), Ok(Path::new("devices/disk/part1")));
+    assert_eq!(path.strip_prefix("/devices"), Ok(Path::new("disk/part1")));
+    assert_eq!(path.strip_prefix("/devices/"), Ok(Path::new("disk/part1")));
+    assert_eq!(path.strip_prefix("//devices//disk"), Ok(Path::new("part1")));
+    assert_eq!(path.strip_prefix("/devices/disk/part1"), Ok(Path::new("")));
+    // only whole components
+    assert!(path.strip_prefix("/dev").is_err());
+    assert!(path.strip_prefix("devices").is_err());
+    assert!(path.strip_prefix("/devices/disk/part1/more").is_err());
+    // lexical, `..` is not resolved
+    assert!(path.strip_prefix("/proc/../devices").is_err());
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_path_names() {
+    use alloc::vec::Vec;
+
+    let path = Path::new("/a//b/./c/");
+    assert_eq!(path.names().collect::<Vec<_>>(), ["a", "b", "c"]);
+    assert_eq!(Path::new("a/b").names().collect::<Vec<_>>(), ["a", "b"]);
+    assert_eq!(Path::new("/").names().next(), None);
+
+    // stops at the first `..`, and keeps it in the rest of the path
+    let mut names = Path::new("/a/b/../c").names();
+    assert_eq!(names.peek(), Some("a"));
+    assert_eq!(names.next(), Some("a"));
+    assert_eq!(names.next(), Some("b"));
+    assert_eq!(names.next(), None);
+    assert_eq!(names.next(), None);
+    assert_eq!(names.as_path(), Path::new("../c"));
+
+    let names = Path::new("./a").names();
+    assert_eq!(names.peek(), None);
+    assert_eq!(names.as_path(), Path::new("./a"));
+}
@@ -0,0 +1,706 @@
+//! GDB remote stub
+//!
+//! Enabled with `gdb_stub=true` in the [`cmdline`], it speaks the GDB remote serial protocol over the second
+//! serial port (`COM2`), and stops the kernel early on boot waiting for GDB to connect.
+//!
+//! It supports:
+//! - reading and writing registers and memory (only mapped memory is accessed).
+//! - hardware breakpoints and watchpoints using the debug registers (4 at most).
+//! - single stepping.
+//! - listing the processes as threads, and reading their saved registers.
+//! - interrupting with `Ctrl-C`, which is polled from the APIC timer.
+//!
+//! The debug registers are saved per context (see [`ProcessContext`]), so breakpoints set while stopped in a
+//! process only apply to that process, and the ones set while stopped in the kernel apply to the kernel context.
+//! Only the CPU that stopped waits for GDB, the others keep running.
+//!
+//! The packets are handled in the exception handlers, so we use fixed buffers and don't allocate.
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    cmdline,
+    cpu::{self, idt::InterruptAllSavedState, interrupts, user_access},
+    io::uart::{Uart, UartPort},
+    memory_management::virtual_memory_mapper::{self, MAX_USER_VIRTUAL_ADDRESS},
+    process::{scheduler, ProcessContext},
+    sync::spin::mutex::Mutex,
+};
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const CTRL_C: u8 = 0x03;
+const MAX_PACKET_SIZE: usize = 0x1000;
+/// Thread of the kernel when no process is running, processes are `pid + FIRST_PROCESS_THREAD`,
+/// as GDB doesn't allow thread `0`
+const KERNEL_THREAD: u64 = 1;
+const FIRST_PROCESS_THREAD: u64 = 2;
+const NUM_HW_BREAKPOINTS: usize = 4;
+
+mod rflags {
+    /// Trap flag, single step
+    pub const TF: u64 = 1 << 8;
+    /// Resume flag, don't trigger instruction breakpoints on the next instruction
+    pub const RF: u64 = 1 << 16;
+}
+
+mod dr6 {
+    /// Which of the breakpoints was hit
+    pub const HIT_MASK: u64 = 0xF;
+}
+
+mod dr7 {
+    pub const fn local_enable(i: usize) -> u64 {
+        1 << (i * 2)
+    }
+    pub const fn condition_shift(i: usize) -> usize {
+        16 + i * 4
+    }
+    pub const fn len_shift(i: usize) -> usize {
+        18 + i * 4
+    }
+    pub const CONDITION_EXECUTE: u64 = 0b00;
+    pub const CONDITION_WRITE: u64 = 0b01;
+    pub const CONDITION_READ_WRITE: u64 = 0b11;
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STUB: Mutex<GdbStub> = Mutex::new(GdbStub::new());
+
+/// Response buffer, packets that don't fit are truncated
+struct Response {
+    buf: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Response {
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn write_hex_le(&mut self, value: u64, bytes: usize) {
+        for b in value.to_le_bytes().iter().take(bytes) {
+            let _ = write!(self, "{b:02x}");
+        }
+    }
+}
+
+impl Write for Response {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+enum Action {
+    Reply,
+    Continue,
+    Step,
+}
+
+/// Registers in the order of GDB's `x86_64` target, `g` packet
+struct Registers {
+    /// rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8-r15
+    gpr: [u64; 16],
+    rip: u64,
+    rflags: u64,
+    /// cs, ss, ds, es, fs, gs
+    segments: [u64; 6],
+}
+
+impl Registers {
+    fn from_state(state: &InterruptAllSavedState) -> Self {
+        let r = &state.rest;
+        Self {
+            gpr: [
+                r.rax,
+                r.rbx,
+                r.rcx,
+                r.rdx,
+                r.rsi,
+                r.rdi,
+                r.rbp,
+                state.frame.rsp,
+                r.r8,
+                r.r9,
+                r.r10,
+                r.r11,
+                r.r12,
+                r.r13,
+                r.r14,
+                r.r15,
+            ],
+            rip: state.frame.rip,
+            rflags: state.frame.rflags,
+            segments: [
+                state.frame.cs as u64,
+                state.frame.ss as u64,
+                r.ds,
+                r.es,
+                r.fs,
+                r.gs,
+            ],
+        }
+    }
+
+    fn from_context(c: &ProcessContext) -> Self {
+        Self {
+            gpr: [
+                c.rax, c.rbx, c.rcx, c.rdx, c.rsi, c.rdi, c.rbp, c.rsp, c.r8, c.r9, c.r10, c.r11,
+                c.r12, c.r13, c.r14, c.r15,
+            ],
+            rip: c.rip,
+            rflags: c.rflags,
+            segments: [c.cs, c.ss, c.ds, c.es, c.fs, c.gs],
+        }
+    }
+
+    /// Segments are not written back, changing them is not supported
+    fn write_to_state(&self, state: &mut InterruptAllSavedState) {
+        let r = &mut state.rest;
+        [
+            r.rax,
+            r.rbx,
+            r.rcx,
+            r.rdx,
+            r.rsi,
+            r.rdi,
+            r.rbp,
+            state.frame.rsp,
+            r.r8,
+            r.r9,
+            r.r10,
+            r.r11,
+            r.r12,
+            r.r13,
+            r.r14,
+            r.r15,
+        ] = self.gpr;
+        state.frame.rip = self.rip;
+        state.frame.rflags = self.rflags;
+    }
+
+    fn write_hex(&self, response: &mut Response) {
+        for reg in self.gpr {
+            response.write_hex_le(reg, 8);
+        }
+        response.write_hex_le(self.rip, 8);
+        response.write_hex_le(self.rflags, 4);
+        for seg in self.segments {
+            response.write_hex_le(seg, 4);
+        }
+    }
+
+    fn parse_hex(data: &[u8]) -> Option<Self> {
+        let mut fields = [0u64; 24];
+        let mut offset = 0;
+        for (i, field) in fields.iter_mut().enumerate() {
+            // rip and the ones before it are 8 bytes, the rest are 4
+            let bytes = if i <= 16 { 8 } else { 4 };
+            *field = parse_hex_le(data.get(offset..offset + bytes * 2)?)?;
+            offset += bytes * 2;
+        }
+        let mut gpr = [0; 16];
+        gpr.copy_from_slice(&fields[..16]);
+        let mut segments = [0; 6];
+        segments.copy_from_slice(&fields[18..]);
+        Some(Self {
+            gpr,
+            rip: fields[16],
+            rflags: fields[17],
+            segments,
+        })
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+fn parse_hex(data: &[u8]) -> Option<u64> {
+    if data.is_empty() || data.len() > 16 {
+        return None;
+    }
+    data.iter()
+        .try_fold(0u64, |acc, &c| Some((acc << 4) | hex_digit(c)? as u64))
+}
+
+/// Little-endian hex bytes, as used for registers
+fn parse_hex_le(data: &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, pair) in data.chunks(2).enumerate() {
+        value |= parse_hex(pair)? << (i * 8);
+    }
+    Some(value)
+}
+
+/// Parse `<addr>,<len>`
+fn parse_addr_len(data: &[u8]) -> Option<(u64, usize)> {
+    let comma = data.iter().position(|&c| c == b',')?;
+    Some((
+        parse_hex(&data[..comma])?,
+        parse_hex(&data[comma + 1..])? as usize,
+    ))
+}
+
+fn current_thread() -> u64 {
+    let cpu = cpu::cpu();
+    if cpu.context.is_some() {
+        cpu.process_id + FIRST_PROCESS_THREAD
+    } else {
+        KERNEL_THREAD
+    }
+}
+
+/// Read the memory of the current context, stops at the first unmapped byte
+fn read_memory(addr: u64, len: usize, response: &mut Response) -> bool {
+    let vm = virtual_memory_mapper::get_current_vm();
+    let _user_access = user_access::allow_user_access();
+    for i in 0..len as u64 {
+        let Some(addr) = addr.checked_add(i) else {
+            return i != 0;
+        };
+        if !vm.is_address_mapped(addr as usize) {
+            return i != 0;
+        }
+        let b = unsafe { (addr as *const u8).read_volatile() };
+        response.write_hex_le(b as u64, 1);
+    }
+    true
+}
+
+fn write_memory(addr: u64, data: &[u8]) -> bool {
+    let vm = virtual_memory_mapper::get_current_vm();
+    let len = data.len() as u64 / 2;
+    if addr.checked_add(len).is_none()
+        || !(addr..addr + len).all(|addr| vm.is_address_mapped(addr as usize))
+    {
+        return false;
+    }
+    let _user_access = user_access::allow_user_access();
+    // allow writing to the read-only kernel code, i.e. to patch it,
+    // user memory is kept protected, so that copy-on-write pages are copied first
+    let is_kernel = addr as usize >= MAX_USER_VIRTUAL_ADDRESS;
+    let cr0 = unsafe { cpu::get_cr0() };
+    if is_kernel {
+        unsafe { cpu::set_cr0(cr0 & !cpu::cr0::WP) };
+    }
+    for (i, pair) in data.chunks(2).enumerate() {
+        let Some(b) = parse_hex(pair) else {
+            break;
+        };
+        unsafe { ((addr + i as u64) as *mut u8).write_volatile(b as u8) };
+    }
+    if is_kernel {
+        unsafe { cpu::set_cr0(cr0) };
+    }
+    true
+}
+
+/// `Z`/`z` packets, `<type>,<addr>,<kind>`, returns `None` if not supported
+fn set_breakpoint(
+    state: &mut InterruptAllSavedState,
+    data: &[u8],
+    insert: bool,
+) -> Option<Result<(), ()>> {
+    let mut parts = data.split(|&c| c == b',');
+    let ty = parse_hex(parts.next()?)?;
+    let addr = parse_hex(parts.next()?)?;
+    let kind = parse_hex(parts.next()?)?;
+
+    let (condition, len_bits): (u64, u64) = match ty {
+        // software breakpoints are implemented as hardware ones as well,
+        // as the kernel code is read-only
+        0 | 1 => (dr7::CONDITION_EXECUTE, 0b00),
+        2 | 4 => {
+            let condition = if ty == 2 {
+                dr7::CONDITION_WRITE
+            } else {
+                dr7::CONDITION_READ_WRITE
+            };
+            let len_bits = match kind {
+                1 => 0b00,
+                2 => 0b01,
+                4 => 0b11,
+                8 => 0b10,
+                _ => return Some(Err(())),
+            };
+            (condition, len_bits)
+        }
+        // x86 doesn't have read only watchpoints
+        _ => return None,
+    };
+
+    let r = &mut state.rest;
+    let addresses = [&mut r.dr0, &mut r.dr1, &mut r.dr2, &mut r.dr3];
+    let config_mask = |i| 0b1111u64 << dr7::condition_shift(i);
+    let config = |i| (condition << dr7::condition_shift(i)) | (len_bits << dr7::len_shift(i));
+    let matching = (0..NUM_HW_BREAKPOINTS).find(|&i| {
+        r.dr7 & dr7::local_enable(i) != 0
+            && *addresses[i] == addr
+            && r.dr7 & config_mask(i) == config(i)
+    });
+
+    if insert {
+        if matching.is_some() {
+            return Some(Ok(()));
+        }
+        let Some(i) = (0..NUM_HW_BREAKPOINTS).find(|&i| r.dr7 & dr7::local_enable(i) == 0) else {
+            return Some(Err(()));
+        };
+        *addresses[i] = addr;
+        r.dr7 = (r.dr7 & !config_mask(i)) | config(i) | dr7::local_enable(i);
+    } else if let Some(i) = matching {
+        *addresses[i] = 0;
+        r.dr7 &= !(config_mask(i) | dr7::local_enable(i));
+    }
+    Some(Ok(()))
+}
+
+fn write_stop_reply(response: &mut Response, state: &InterruptAllSavedState, signal: u8, dr6: u64) {
+    let _ = write!(response, "T{signal:02x}");
+    let r = &state.rest;
+    let addresses = [r.dr0, r.dr1, r.dr2, r.dr3];
+    if let Some(i) = (0..NUM_HW_BREAKPOINTS).find(|&i| dr6 & dr6::HIT_MASK & (1 << i) != 0) {
+        match (r.dr7 >> dr7::condition_shift(i)) & 0b11 {
+            dr7::CONDITION_WRITE => {
+                let _ = write!(response, "watch:{:x};", addresses[i]);
+            }
+            dr7::CONDITION_READ_WRITE => {
+                let _ = write!(response, "awatch:{:x};", addresses[i]);
+            }
+            _ => {
+                let _ = write!(response, "hwbreak:;");
+            }
+        }
+    }
+    let _ = write!(response, "thread:{:x};", current_thread());
+}
+
+/// `qfThreadInfo`: all the threads in one reply
+fn write_thread_list(response: &mut Response) {
+    let _ = write!(response, "m{KERNEL_THREAD:x}");
+    scheduler::try_for_each_process(|process, _| {
+        if let Some(process) = process {
+            let _ = write!(response, ",{:x}", process.id() + FIRST_PROCESS_THREAD);
+        }
+    });
+}
+
+/// `qThreadExtraInfo`, the text is hex encoded
+fn write_thread_info(response: &mut Response, thread: u64) {
+    struct HexWriter<'a>(&'a mut Response);
+    impl Write for HexWriter<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for b in s.bytes() {
+                self.0.write_hex_le(b as u64, 1);
+            }
+            Ok(())
+        }
+    }
+
+    if thread == KERNEL_THREAD {
+        let _ = write!(HexWriter(response), "kernel");
+        return;
+    }
+    let pid = thread - FIRST_PROCESS_THREAD;
+    scheduler::try_for_each_process(|process, state| {
+        if let Some(process) = process.filter(|p| p.id() == pid) {
+            let _ = write!(
+                HexWriter(response),
+                "pid {pid} {:?} {state:?}",
+                process.file_path()
+            );
+        }
+    });
+}
+
+/// Registers of another thread, from its saved context
+fn thread_registers(thread: u64) -> Option<Registers> {
+    let pid = thread.checked_sub(FIRST_PROCESS_THREAD)?;
+    let mut registers = None;
+    scheduler::try_for_each_process(|process, _| {
+        if let Some(process) = process.filter(|p| p.id() == pid) {
+            registers = Some(Registers::from_context(process.context()));
+        }
+    });
+    registers
+}
+
+fn handle_packet(
+    packet: &[u8],
+    response: &mut Response,
+    selected_thread: &mut Option<u64>,
+    state: &mut InterruptAllSavedState,
+    stop: (u8, u64),
+) -> Action {
+    let Some((&command, args)) = packet.split_first() else {
+        return Action::Reply;
+    };
+    let ok_or_error = |response: &mut Response, ok: bool| {
+        let _ = response.write_str(if ok { "OK" } else { "E01" });
+    };
+
+    match command {
+        b'?' => write_stop_reply(response, state, stop.0, stop.1),
+        b'g' => match *selected_thread {
+            Some(thread) if thread != current_thread() => match thread_registers(thread) {
+                Some(registers) => registers.write_hex(response),
+                None => ok_or_error(response, false),
+            },
+            _ => Registers::from_state(state).write_hex(response),
+        },
+        b'G' => {
+            let is_current = selected_thread.map_or(true, |t| t == current_thread());
+            match Registers::parse_hex(args).filter(|_| is_current) {
+                Some(registers) => {
+                    registers.write_to_state(state);
+                    ok_or_error(response, true);
+                }
+                None => ok_or_error(response, false),
+            }
+        }
+        b'm' => match parse_addr_len(args) {
+            Some((addr, len)) if len * 2 <= MAX_PACKET_SIZE => {
+                if !read_memory(addr, len, response) {
+                    response.clear();
+                    let _ = response.write_str("E14");
+                }
+            }
+            _ => ok_or_error(response, false),
+        },
+        b'M' => {
+            let colon = args.iter().position(|&c| c == b':');
+            let ok = colon
+                .and_then(|colon| Some((parse_addr_len(&args[..colon])?, &args[colon + 1..])))
+                .filter(|((_, len), data)| data.len() == len * 2)
+                .is_some_and(|((addr, _), data)| write_memory(addr, data));
+            ok_or_error(response, ok);
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                state.frame.rip = addr;
+            }
+            return if command == b'c' {
+                Action::Continue
+            } else {
+                Action::Step
+            };
+        }
+        b'H' => {
+            if args.first() == Some(&b'g') {
+                // `0` and `-1` mean any/all threads
+                *selected_thread = match &args[1..] {
+                    b"0" | b"-1" => None,
+                    thread => parse_hex(thread),
+                };
+            }
+            ok_or_error(response, true);
+        }
+        b'T' => {
+            let thread = parse_hex(args).unwrap_or(0);
+            let alive = thread == KERNEL_THREAD
+                || thread == current_thread()
+                || thread_registers(thread).is_some();
+            ok_or_error(response, alive);
+        }
+        b'Z' | b'z' => {
+            if let Some(result) = set_breakpoint(state, args, command == b'Z') {
+                ok_or_error(response, result.is_ok());
+            }
+        }
+        b'q' => {
+            if args.starts_with(b"Supported") {
+                let _ = write!(response, "PacketSize={MAX_PACKET_SIZE:x};hwbreak+");
+            } else if args == b"C" {
+                let _ = write!(response, "QC{:x}", current_thread());
+            } else if args == b"fThreadInfo" {
+                write_thread_list(response);
+            } else if args == b"sThreadInfo" {
+                let _ = response.write_str("l");
+            } else if let Some(thread) = args.strip_prefix(b"ThreadExtraInfo,") {
+                write_thread_info(response, parse_hex(thread).unwrap_or(0));
+            } else if args == b"Attached" {
+                let _ = response.write_str("1");
+            }
+        }
+        // detach and kill, we just continue running
+        b'D' => {
+            ok_or_error(response, true);
+            return Action::Continue;
+        }
+        b'k' => return Action::Continue,
+        // empty response for unsupported commands
+        _ => {}
+    }
+    Action::Reply
+}
+
+struct GdbStub {
+    uart: Uart,
+    packet: [u8; MAX_PACKET_SIZE],
+    response: Response,
+    /// Thread selected with `Hg`, for reading the registers
+    selected_thread: Option<u64>,
+}
+
+impl GdbStub {
+    const fn new() -> Self {
+        Self {
+            uart: Uart::new(UartPort::COM2),
+            packet: [0; MAX_PACKET_SIZE],
+            response: Response {
+                buf: [0; MAX_PACKET_SIZE],
+                len: 0,
+            },
+            selected_thread: None,
+        }
+    }
+
+    fn read_byte(&self) -> u8 {
+        loop {
+            if let Some(b) = unsafe { self.uart.try_read_byte() } {
+                return b;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn write_bytes(&self, data: &[u8]) {
+        for &b in data {
+            unsafe { self.uart.write_byte(b) };
+        }
+    }
+
+    /// Read a packet `$<data>#<checksum>` into `self.packet` and acknowledge it, returns its length
+    fn read_packet(&mut self) -> usize {
+        loop {
+            // skip anything outside a packet, i.e. acks
+            while self.read_byte() != b'$' {}
+
+            let mut len = 0;
+            let mut checksum = 0u8;
+            loop {
+                let b = self.read_byte();
+                if b == b'#' {
+                    break;
+                }
+                checksum = checksum.wrapping_add(b);
+                if len < MAX_PACKET_SIZE {
+                    self.packet[len] = b;
+                    len += 1;
+                }
+            }
+            let expected = [self.read_byte(), self.read_byte()];
+            if parse_hex(&expected) == Some(checksum as u64) {
+                self.write_bytes(b"+");
+                return len;
+            }
+            self.write_bytes(b"-");
+        }
+    }
+
+    /// Send `self.response`, and wait for it to be acknowledged
+    fn send_response(&self) {
+        let data = self.response.as_bytes();
+        let checksum = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        let hex = b"0123456789abcdef";
+        loop {
+            self.write_bytes(b"$");
+            self.write_bytes(data);
+            self.write_bytes(&[
+                b'#',
+                hex[(checksum >> 4) as usize],
+                hex[(checksum & 0xF) as usize],
+            ]);
+            if self.read_byte() == b'+' {
+                return;
+            }
+        }
+    }
+
+    /// Report the stop to GDB, and handle its commands until it continues
+    fn handle_stop(&mut self, state: &mut InterruptAllSavedState, signal: u8) {
+        // disable the breakpoints while we are here, so that reading watched memory doesn't trap,
+        // they are restored from `state` when we return
+        unsafe { core::arch::asm!("mov dr7, {}", in(reg) 0u64, options(nomem, nostack)) };
+        let dr6 = state.rest.dr6;
+        // the CPU doesn't clear it
+        state.rest.dr6 = 0;
+        // single step is only for one instruction
+        state.frame.rflags &= !rflags::TF;
+        self.selected_thread = None;
+
+        self.response.clear();
+        write_stop_reply(&mut self.response, state, signal, dr6);
+        self.send_response();
+
+        loop {
+            let len = self.read_packet();
+            self.response.clear();
+            let action = handle_packet(
+                &self.packet[..len],
+                &mut self.response,
+                &mut self.selected_thread,
+                state,
+                (signal, dr6),
+            );
+            match action {
+                Action::Reply => self.send_response(),
+                Action::Continue => break,
+                Action::Step => {
+                    state.frame.rflags |= rflags::TF;
+                    break;
+                }
+            }
+        }
+        // don't hit the same instruction breakpoint again when resuming
+        state.frame.rflags |= rflags::RF;
+    }
+}
+
+extern "cdecl" fn debug_exception_handler(state: &mut InterruptAllSavedState) {
+    STUB.lock().handle_stop(state, SIGTRAP);
+}
+
+/// Enter the debugger if GDB sent `Ctrl-C`, called from the APIC timer interrupt
+pub fn poll(state: &mut InterruptAllSavedState) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    // the stub may be in use by another CPU
+    let Some(mut stub) = STUB.try_lock() else {
+        return;
+    };
+    if unsafe { stub.uart.try_read_byte() } == Some(CTRL_C) {
+        stub.handle_stop(state, SIGINT);
+    }
+}
+
+/// Setup the stub if enabled in the cmdline, and wait for GDB to connect
+pub fn init() {
+    if !cmdline::cmdline().gdb_stub {
+        return;
+    }
+    if !cmdline::cmdline().uart {
+        warn!("GDB stub: uart is disabled in the cmdline, the stub will not be enabled");
+        return;
+    }
+    STUB.lock().uart.init();
+    interrupts::create_debug_interrupts(debug_exception_handler);
+    ENABLED.store(true, Ordering::Relaxed);
+
+    info!("GDB stub: waiting for GDB on COM2");
+    unsafe { core::arch::asm!("int3") };
+}
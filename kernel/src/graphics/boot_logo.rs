@@ -0,0 +1,171 @@
+//! Boot logo, the image provided by the firmware in the ACPI `BGRT` table
+//!
+//! The logo is drawn once the VGA display is initialized, and kept on the screen until the boot finishes,
+//! the console doesn't render to the screen during that time, and redraws everything
+//! written to it when the logo is removed (see [`finish`]).
+//!
+//! Can be disabled with `boot_logo=false` in the cmdline.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use byteorder::{ByteOrder, LittleEndian};
+use tracing::{info, warn};
+
+use crate::{
+    acpi::tables::{self, BiosTables},
+    cmdline,
+    io::console,
+    memory_management::virtual_space::VirtualSpace,
+};
+
+use super::{vga, Pixel};
+
+/// `BITMAPFILEHEADER` + `BITMAPINFOHEADER`
+const BMP_HEADERS_SIZE: usize = 54;
+/// The only image type defined for `BGRT`
+const BGRT_IMAGE_TYPE_BMP: u8 = 0;
+
+static SHOWING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+struct BmpInfo {
+    file_size: usize,
+    data_offset: usize,
+    width: usize,
+    height: usize,
+    /// rows are stored from the top, otherwise from the bottom
+    top_down: bool,
+    bytes_per_pixel: usize,
+}
+
+impl BmpInfo {
+    fn parse(headers: &[u8]) -> Option<Self> {
+        if &headers[0..2] != b"BM" {
+            return None;
+        }
+        let file_size = LittleEndian::read_u32(&headers[2..]) as usize;
+        let data_offset = LittleEndian::read_u32(&headers[10..]) as usize;
+        let width = LittleEndian::read_i32(&headers[18..]);
+        let height = LittleEndian::read_i32(&headers[22..]);
+        let bpp = LittleEndian::read_u16(&headers[28..]);
+        let compression = LittleEndian::read_u32(&headers[30..]);
+
+        // only uncompressed 24/32 bit images, which is what firmwares provide
+        if compression != 0 || (bpp != 24 && bpp != 32) || width <= 0 || height == 0 {
+            return None;
+        }
+
+        let info = Self {
+            file_size,
+            data_offset,
+            width: width as usize,
+            height: height.unsigned_abs() as usize,
+            top_down: height < 0,
+            bytes_per_pixel: bpp as usize / 8,
+        };
+
+        if info.data_offset + info.row_stride() * info.height > info.file_size {
+            return None;
+        }
+        Some(info)
+    }
+
+    /// rows are padded to 4 bytes
+    fn row_stride(&self) -> usize {
+        (self.width * self.bytes_per_pixel).next_multiple_of(4)
+    }
+
+    fn pixel(&self, file: &[u8], x: usize, y: usize) -> Pixel {
+        let row = if self.top_down {
+            y
+        } else {
+            self.height - 1 - y
+        };
+        let i = self.data_offset + row * self.row_stride() + x * self.bytes_per_pixel;
+        Pixel {
+            r: file[i + 2],
+            g: file[i + 1],
+            b: file[i],
+        }
+    }
+}
+
+/// Draw the boot logo if the firmware provided one
+pub fn init(bios_tables: &BiosTables) {
+    if !cmdline::cmdline().boot_logo {
+        return;
+    }
+    let Some(vga) = vga::controller() else {
+        return;
+    };
+    let Some(bgrt) = bios_tables.rsdt.get_table::<tables::Bgrt>() else {
+        return;
+    };
+    // copy out of the packed struct
+    let image_address = bgrt.image_address;
+    let image_type = bgrt.image_type;
+    if image_address == 0 {
+        return;
+    }
+    if image_type != BGRT_IMAGE_TYPE_BMP {
+        warn!("Unsupported BGRT image type {image_type}");
+        return;
+    }
+
+    // SAFETY: the address is provided by the firmware, and we only read from it
+    let Ok(headers) = (unsafe { VirtualSpace::<u8>::new_slice(image_address, BMP_HEADERS_SIZE) })
+    else {
+        warn!("Could not map the BGRT image");
+        return;
+    };
+    let Some(bmp) = BmpInfo::parse(&headers) else {
+        warn!("Invalid BGRT image");
+        return;
+    };
+    drop(headers);
+
+    // SAFETY: same as above, the size is taken from the image header
+    let Ok(file) = (unsafe { VirtualSpace::<u8>::new_slice(image_address, bmp.file_size) }) else {
+        warn!("Could not map the BGRT image");
+        return;
+    };
+
+    let fb_info = vga.framebuffer_info();
+    if bmp.width > fb_info.width || bmp.height > fb_info.height {
+        warn!("BGRT image {}x{} is larger than the screen", bmp.width, bmp.height);
+        return;
+    }
+    // the offset is for the screen mode of the firmware, which may not be ours, center it in that case
+    let mut x_offset = bgrt.image_offset_x as usize;
+    let mut y_offset = bgrt.image_offset_y as usize;
+    if x_offset + bmp.width > fb_info.width || y_offset + bmp.height > fb_info.height {
+        x_offset = (fb_info.width - bmp.width) / 2;
+        y_offset = (fb_info.height - bmp.height) / 2;
+    }
+
+    let Some(mut display) = vga.lock_kernel() else {
+        return;
+    };
+    display.clear();
+    for y in 0..bmp.height {
+        for x in 0..bmp.width {
+            display.put_pixel(x_offset + x, y_offset + y, bmp.pixel(&file, x, y));
+        }
+    }
+    drop(display);
+
+    SHOWING.store(true, Ordering::Release);
+    info!("Displaying boot logo {}x{}", bmp.width, bmp.height);
+}
+
+/// Is the boot logo on the screen, the console must not render anything while this is `true`
+pub fn is_showing() -> bool {
+    SHOWING.load(Ordering::Acquire)
+}
+
+/// Remove the boot logo, and let the console take over the screen
+pub fn finish() {
+    if SHOWING.swap(false, Ordering::AcqRel) {
+        console::redraw();
+    }
+}
@@ -1,5 +1,6 @@
 use embedded_graphics::pixelcolor::RgbColor;
 
+pub mod boot_logo;
 pub mod vga;
 
 #[repr(C)]
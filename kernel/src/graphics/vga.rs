@@ -6,8 +6,10 @@ use embedded_graphics::{
     pixelcolor::Rgb888,
 };
 pub use kernel_user_link::graphics::FrameBufferInfo;
+use kernel_user_link::graphics::CAPTURE_BYTES_PER_PIXEL;
 
 use crate::{
+    io::console,
     memory_management::virtual_space::VirtualSpace,
     multiboot2::{self, FramebufferColorInfo},
     sync::{
@@ -25,16 +27,18 @@ pub fn init(framebuffer: Option<multiboot2::Framebuffer>) {
         panic!("VGA display controller already initialized");
     }
 
-    match framebuffer {
-        Some(framebuffer) => match framebuffer.color_info {
-            FramebufferColorInfo::Indexed { .. } => {}
-            FramebufferColorInfo::Rgb { .. } => {
-                // only initialize if the framebuffer is RGB
-                VGA_DISPLAY_CONTROLLER.get_or_init(|| VgaDisplayController::new(framebuffer));
-            }
-            FramebufferColorInfo::EgaText => {}
-        },
-        None => panic!("No framebuffer provided"),
+    let Some(framebuffer) = framebuffer else {
+        // no graphics, the console will use the VGA text mode
+        return;
+    };
+
+    match framebuffer.color_info {
+        FramebufferColorInfo::Indexed { .. } => {}
+        FramebufferColorInfo::Rgb { .. } => {
+            // only initialize if the framebuffer is RGB
+            VGA_DISPLAY_CONTROLLER.get_or_init(|| VgaDisplayController::new(framebuffer));
+        }
+        FramebufferColorInfo::EgaText => {}
     }
 }
 
@@ -42,6 +46,11 @@ pub fn controller() -> Option<&'static VgaDisplayController> {
     VGA_DISPLAY_CONTROLLER.try_get()
 }
 
+/// Is the display owned by a process, then the kernel can't draw on it
+pub fn is_owned() -> bool {
+    controller().is_some_and(|controller| controller.owner().is_some())
+}
+
 pub struct VgaDisplayController {
     display: Mutex<VgaDisplay>,
     framebuffer_info: FrameBufferInfo,
@@ -88,21 +97,46 @@ impl VgaDisplayController {
             .is_ok()
     }
 
+    /// Release the ownership if `pid` is the owner, the console is redrawn since the process
+    /// may have drawn over it
     pub fn release(&self, pid: u64) -> bool {
         assert!(pid < i64::MAX as u64);
-        self.owner_process
+        let released = self
+            .owner_process
             .compare_exchange(
                 pid as i64,
                 -1,
                 core::sync::atomic::Ordering::Relaxed,
                 Ordering::Relaxed,
             )
-            .is_ok()
+            .is_ok();
+        if released {
+            console::redraw();
+        }
+        released
+    }
+
+    /// Take the display from the owning process without asking, used when the kernel panics, as
+    /// the process will never run again, and the panic message must be visible
+    pub fn force_kernel_ownership(&self) {
+        self.owner_process.store(-1, Ordering::Relaxed);
+    }
+
+    /// The process owning the display if any
+    pub fn owner(&self) -> Option<u64> {
+        let owner = self.owner_process.load(Ordering::Relaxed);
+        (owner != -1).then_some(owner as u64)
     }
 
     pub fn framebuffer_info(&self) -> &FrameBufferInfo {
         &self.framebuffer_info
     }
+
+    /// Copy the displayed frame into `buffer` as packed `RGB`, regardless of the owner,
+    /// `buffer` must be at least [`FrameBufferInfo::capture_size`] long
+    pub fn capture(&self, buffer: &mut [u8]) {
+        self.display.lock().capture(buffer);
+    }
 }
 
 // extra custom functionality
@@ -194,6 +228,20 @@ impl VgaDisplay {
         self.memory.fill(0);
     }
 
+    pub fn capture(&self, buffer: &mut [u8]) {
+        assert!(buffer.len() >= self.fb_info.capture_size());
+
+        let mut out = buffer.chunks_exact_mut(CAPTURE_BYTES_PER_PIXEL);
+        for y in 0..self.fb_info.height {
+            for x in 0..self.fb_info.width {
+                let pixel = self.fb_info.read_pixel(&self.memory, (x, y)).unwrap();
+                out.next()
+                    .unwrap()
+                    .copy_from_slice(&[pixel.r, pixel.g, pixel.b]);
+            }
+        }
+    }
+
     pub fn blit_inner_ranges(
         &mut self,
         src: (usize, usize),
@@ -353,6 +401,10 @@ impl VgaDisplay {
             self.fb_info.write_pixel(first_line, (i, 0), color);
         }
 
+        if height == 1 {
+            return;
+        }
+
         // take from the end of the first line, i.e. `before` will have the first line
         // and `after` will have the rest of the memory
         let second_line_start = self.fb_info.get_arr_pos((0, dest_y + 1)).unwrap();
@@ -365,6 +417,42 @@ impl VgaDisplay {
             dest_line.copy_from_slice(first_line);
         }
     }
+
+    /// Copy a region of the framebuffer into another position, unlike [`Self::blit_inner_ranges`]
+    /// the regions can overlap
+    pub fn copy_rect(
+        &mut self,
+        src: (usize, usize),
+        dest: (usize, usize),
+        width: usize,
+        height: usize,
+    ) {
+        let (src_x, src_y) = src;
+        let (dest_x, dest_y) = dest;
+        assert!(src_x + width <= self.fb_info.width);
+        assert!(src_y + height <= self.fb_info.height);
+        assert!(dest_x + width <= self.fb_info.width);
+        assert!(dest_y + height <= self.fb_info.height);
+
+        if height == 0 || width == 0 {
+            return;
+        }
+
+        let chunk_size = width * self.fb_info.byte_per_pixel as usize;
+        let copy_line = |y: usize| {
+            let src_i = self.fb_info.get_arr_pos((src_x, src_y + y)).unwrap();
+            let dest_i = self.fb_info.get_arr_pos((dest_x, dest_y + y)).unwrap();
+            // handles overlap within the same line
+            self.memory.copy_within(src_i..src_i + chunk_size, dest_i);
+        };
+
+        // when moving down, start from the bottom so we don't overwrite lines before copying them
+        if dest_y > src_y {
+            (0..height).rev().for_each(copy_line);
+        } else {
+            (0..height).for_each(copy_line);
+        }
+    }
 }
 
 impl DrawTarget for VgaDisplay {
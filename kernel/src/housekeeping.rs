@@ -0,0 +1,74 @@
+//! Periodic kernel work that no event drives
+//!
+//! [`run_if_due`] is called from the scheduler loop between processes, outside of syscalls and
+//! interrupts, so the work can use the disks. Every [`INTERVAL`], it writes the dirty data of all
+//! the mounted filesystems (i.e. the cached FAT sectors) to the disks, same as the `sync` syscall,
+//! so nothing stays only in memory for longer than that. Then, it lets the devices enter a
+//! low-power state if they are not used, see [`Device::idle`](crate::devices::Device::idle), and
+//! frees the old values of the read-mostly tables, see [`epoch::collect`].
+//!
+//! The system time doesn't need it, it's ticked by the timer interrupt.
+//!
+//! No process runs while the work is done, so the duration of each run is kept, the last and the
+//! longest are in `/proc/stat`, and a run longer than [`SLOW_RUN`] is logged.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::warn;
+
+use crate::{
+    devices::{
+        self,
+        clock::{self, ClockTime, NANOS_PER_SEC},
+    },
+    fs,
+    sync::epoch,
+};
+
+const INTERVAL: u64 = 5 * NANOS_PER_SEC;
+const SLOW_RUN: u64 = 50_000_000;
+
+/// Nanoseconds since startup when the next run is due
+static NEXT_RUN: AtomicU64 = AtomicU64::new(INTERVAL);
+static LAST_DURATION: AtomicU64 = AtomicU64::new(0);
+static LONGEST_DURATION: AtomicU64 = AtomicU64::new(0);
+
+/// Run the housekeeping work if [`INTERVAL`] passed since the last run
+pub fn run_if_due() {
+    let start = clock::clocks().time_since_startup();
+    if start.as_nanos() < NEXT_RUN.load(Ordering::Relaxed) {
+        return;
+    }
+
+    fs::mapping::on_all_mappings(|path, filesystem| {
+        if let Err(e) = filesystem.sync() {
+            warn!(
+                "Housekeeping: failed to sync filesystem at {}: {e:?}",
+                path.display()
+            );
+        }
+    });
+    devices::idle_all();
+    epoch::collect();
+
+    let end = clock::clocks().time_since_startup();
+    let duration = (end - start).as_nanos();
+    LAST_DURATION.store(duration, Ordering::Relaxed);
+    LONGEST_DURATION.fetch_max(duration, Ordering::Relaxed);
+    if duration > SLOW_RUN {
+        warn!(
+            "Housekeeping took {}ms, processes were stalled",
+            duration / 1_000_000
+        );
+    }
+    // count from the end, so a slow run doesn't make the next one due right away
+    NEXT_RUN.store(end.as_nanos() + INTERVAL, Ordering::Relaxed);
+}
+
+/// The duration of the last run, and of the longest one
+pub fn durations() -> (ClockTime, ClockTime) {
+    (
+        ClockTime::from_nanos(LAST_DURATION.load(Ordering::Relaxed)),
+        ClockTime::from_nanos(LONGEST_DURATION.load(Ordering::Relaxed)),
+    )
+}
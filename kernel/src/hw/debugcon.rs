@@ -0,0 +1,149 @@
+//! Structured telemetry channel over QEMU's `isa-debugcon` device
+//!
+//! Each record is a flat JSON object, prefixed by its length in bytes as a little-endian `u32`.
+//! `xtask` connects the device to a file, and parses and prints the records after QEMU exits.
+//!
+//! All records have a `type` field, which is one of:
+//! - `test`: `name`, `result` (`ok`/`failed`/`ignored`) and `output` (the captured output of failed tests).
+//! - `test_summary`: `passed`, `failed` and `ignored`.
+//! - `panic`: `message`.
+//! - `profile`: `name` and `nanos`, i.e. the time each [`InitStage`](crate::init_stage::InitStage) is reached.
+//! - `boot_step`: `name`, `nanos` (since entry) and `duration`, one for each [`BootStep`](crate::boot_time::BootStep).
+//! - `boot_total`: `nanos`, the time from entering `kernel_main` until `init` is loaded.
+//!
+//! The records are formatted twice, once to get the length and once to write them, so it doesn't allocate,
+//! and can be used from the panic handler.
+//!
+//! A second device, on [`TRACE_PORT`], gets the binary trace buffers of the [tracepoints](crate::tracepoints),
+//! `xtask` only connects it when tracing is requested.
+
+use core::fmt::{self, Write};
+
+use crate::cpu;
+
+const DEBUGCON_PORT: u16 = 0xE9;
+const TRACE_PORT: u16 = 0xEA;
+
+pub enum Value<'a> {
+    Str(&'a str),
+    Display(&'a dyn fmt::Display),
+    U64(u64),
+}
+
+/// Writes the string escaped for JSON
+struct JsonEscape<'a, W: Write>(&'a mut W);
+
+impl<W: Write> Write for JsonEscape<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(self.0, "\\u{:04x}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Record<'a> {
+    kind: &'a str,
+    fields: &'a [(&'a str, Value<'a>)],
+}
+
+impl Record<'_> {
+    fn write_string(w: &mut impl Write, value: impl fmt::Display) -> fmt::Result {
+        w.write_char('"')?;
+        write!(JsonEscape(&mut *w), "{value}")?;
+        w.write_char('"')
+    }
+
+    fn write_to(&self, w: &mut impl Write) -> fmt::Result {
+        w.write_str("{\"type\":")?;
+        Self::write_string(w, self.kind)?;
+        for (name, value) in self.fields {
+            w.write_char(',')?;
+            Self::write_string(w, name)?;
+            w.write_char(':')?;
+            match value {
+                Value::Str(s) => Self::write_string(w, s)?,
+                Value::Display(d) => Self::write_string(w, d)?,
+                Value::U64(n) => write!(w, "{n}")?,
+            }
+        }
+        w.write_char('}')
+    }
+}
+
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+struct Port;
+
+impl Write for Port {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            unsafe { cpu::io_out(DEBUGCON_PORT, b) };
+        }
+        Ok(())
+    }
+}
+
+/// Reading the port returns `0xE9` if the device is present (even for other ports)
+fn is_present_at(port: u16) -> bool {
+    unsafe { cpu::io_in::<u8>(port) == DEBUGCON_PORT as u8 }
+}
+
+fn is_present() -> bool {
+    is_present_at(DEBUGCON_PORT)
+}
+
+pub fn is_trace_port_present() -> bool {
+    is_present_at(TRACE_PORT)
+}
+
+/// Write raw bytes to the trace port, the caller must check that it's present
+pub fn write_trace(data: &[u8]) {
+    for &b in data {
+        unsafe { cpu::io_out(TRACE_PORT, b) };
+    }
+}
+
+/// Send a record with type `kind`, dropped if the device is not present
+pub fn send(kind: &str, fields: &[(&str, Value)]) {
+    if !is_present() {
+        return;
+    }
+    let record = Record { kind, fields };
+
+    let mut counter = Counter(0);
+    record.write_to(&mut counter).expect("counting can't fail");
+
+    cpu::cpu().push_cli();
+    for b in (counter.0 as u32).to_le_bytes() {
+        unsafe { cpu::io_out(DEBUGCON_PORT, b) };
+    }
+    record.write_to(&mut Port).expect("port write can't fail");
+    cpu::cpu().pop_cli();
+}
+
+pub fn panic(message: &dyn fmt::Display) {
+    send("panic", &[("message", Value::Display(message))]);
+}
+
+pub fn profile_sample(name: &dyn fmt::Display, nanos: u64) {
+    send(
+        "profile",
+        &[("name", Value::Display(name)), ("nanos", Value::U64(nanos))],
+    );
+}
@@ -1 +1,2 @@
+pub mod debugcon;
 pub mod qemu;
@@ -0,0 +1,76 @@
+//! Kernel initialization order
+//!
+//! `kernel_main` initializes the subsystems in a fixed order, and some of them depend on others
+//! being initialized first. Each [`InitStage`] is marked with [`reached`] once done, and init functions
+//! that depend on another subsystem check it with [`debug_assert_reached`].
+//!
+//! This way, reordering `kernel_main` wrongly fails early in debug builds with a clear message, instead
+//! of panicking in some `OnceLock::get` (or worse, inside an interrupt handler).
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{devices::clock, hw::debugcon};
+
+/// The stages in the order they are reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum InitStage {
+    /// Nothing is initialized yet
+    Start,
+    /// Physical and virtual memory are initialized, heap allocations are possible
+    Memory,
+    /// GDT and IDT are initialized, exceptions can be handled
+    Interrupts,
+    /// ACPI tables and APIC are initialized, device interrupts can be routed
+    Apic,
+    /// Clocks are initialized, so timing and the APIC timer can be used
+    Clocks,
+    /// Interrupts are enabled and devices are probed
+    Devices,
+    /// The root filesystem is mounted, and boot is finished
+    Filesystem,
+}
+
+impl InitStage {
+    const ALL: [InitStage; 7] = [
+        InitStage::Start,
+        InitStage::Memory,
+        InitStage::Interrupts,
+        InitStage::Apic,
+        InitStage::Clocks,
+        InitStage::Devices,
+        InitStage::Filesystem,
+    ];
+}
+
+static CURRENT_STAGE: AtomicU8 = AtomicU8::new(InitStage::Start as u8);
+
+pub fn current() -> InitStage {
+    InitStage::ALL[CURRENT_STAGE.load(Ordering::Acquire) as usize]
+}
+
+/// Mark `stage` as reached, stages must be reached in order
+pub fn reached(stage: InitStage) {
+    let previous = InitStage::ALL[CURRENT_STAGE.swap(stage as u8, Ordering::Release) as usize];
+    debug_assert_eq!(
+        previous as u8 + 1,
+        stage as u8,
+        "init stage {stage:?} reached out of order, previous stage is {previous:?}"
+    );
+    if let Some(clocks) = clock::try_clocks() {
+        debugcon::profile_sample(
+            &format_args!("init_stage::{stage:?}"),
+            clocks.time_since_startup().as_nanos(),
+        );
+    }
+}
+
+/// Make sure that `stage` is reached, only checked in debug builds
+#[track_caller]
+pub fn debug_assert_reached(stage: InitStage) {
+    debug_assert!(
+        current() >= stage,
+        "requires init stage {stage:?}, but only reached {:?}",
+        current()
+    );
+}
@@ -1,32 +1,49 @@
+mod keymap;
 pub mod tracing;
 mod vga_graphics;
 mod vga_text;
+mod virtual_terminal;
 
 use core::{
     cell::RefCell,
     fmt::{self, Write},
+    sync::atomic::{AtomicIsize, AtomicUsize, Ordering},
 };
 
-use alloc::{boxed::Box, string::String, sync::Arc};
+use alloc::{boxed::Box, format, string::String, sync::Arc};
 
 use crate::{
+    cmdline,
     devices::{
         self,
         keyboard_mouse::{self, KeyboardReader},
         Device,
     },
     fs::FileSystemError,
+    graphics,
     multiboot2::{self, FramebufferColorInfo},
-    sync::spin::remutex::ReMutex,
+    process::scheduler::WaitQueue,
+    sync::spin::{self, remutex::ReMutex},
 };
 
-use self::{vga_graphics::VgaGraphics, vga_text::VgaText};
+use self::{
+    keymap::Keymap, vga_graphics::VgaGraphics, vga_text::VgaText, virtual_terminal::VirtualTerminal,
+};
 
 use super::uart::{Uart, UartPort};
 
 // SAFETY: the console is only used inside a lock or mutex
 static mut CONSOLE: ConsoleController = ConsoleController::empty_early();
 
+/// Number of virtual terminals, available as `/devices/console1..N`
+pub const NUM_TERMINALS: usize = 4;
+/// The terminal where the kernel prints, and that is mirrored to the uart
+const KERNEL_TERMINAL: usize = 0;
+
+const NO_TERMINAL_REQUEST: usize = usize::MAX;
+static REQUESTED_TERMINAL: AtomicUsize = AtomicUsize::new(NO_TERMINAL_REQUEST);
+static REQUESTED_SCROLL: AtomicIsize = AtomicIsize::new(0);
+
 /// # SAFETY
 /// the caller must assure that this is not called while not being initialized
 /// at the same time
@@ -54,13 +71,126 @@ pub fn init_late_device(framebuffer: Option<multiboot2::Framebuffer>) {
     //  without printing anything at the same time since we are only
     //  running 1 CPU at the  time
     //  We are also sure that no one is printing at this time
-    let device = unsafe {
+    let console = unsafe {
         CONSOLE.init_late(framebuffer);
         // Must have a device
         CONSOLE.late_device().unwrap()
     };
 
-    devices::register_device(device);
+    let device = |terminal: usize, name: String| {
+        let input_queue = console.lock().borrow().input_queues[terminal].clone();
+        Arc::new(TerminalDevice {
+            console: console.clone(),
+            terminal,
+            input_queue,
+            name,
+        })
+    };
+
+    // `console` is the kernel terminal, same as `console1`
+    devices::register_device(device(KERNEL_TERMINAL, String::from("console")));
+    for terminal in 0..NUM_TERMINALS {
+        devices::register_device(device(terminal, format!("console{}", terminal + 1)));
+    }
+}
+
+/// Switch the screen and keyboard to another virtual terminal (0-based), called from the keyboard driver.
+///
+/// The switch is done later in [`handle_terminal_requests`], since we may be interrupting
+/// someone using the console.
+pub fn request_switch_terminal(terminal: usize) {
+    REQUESTED_TERMINAL.store(terminal, Ordering::Relaxed);
+}
+
+/// Scroll the active virtual terminal by `lines` (positive is up), called from the keyboard driver.
+pub fn request_scroll(lines: isize) {
+    REQUESTED_SCROLL.fetch_add(lines, Ordering::Relaxed);
+}
+
+/// Perform the switch/scroll requested by the keyboard driver, called periodically from the timer interrupt
+pub fn handle_terminal_requests() {
+    if REQUESTED_TERMINAL.load(Ordering::Relaxed) == NO_TERMINAL_REQUEST
+        && REQUESTED_SCROLL.load(Ordering::Relaxed) == 0
+    {
+        return;
+    }
+    // keep the requests until the boot logo is removed
+    if graphics::boot_logo::is_showing() {
+        return;
+    }
+
+    // SAFETY: we are only reading the console, and the late console is never replaced after init
+    let Some(console) = (unsafe { CONSOLE.late_device() }) else {
+        return;
+    };
+    let console = console.lock_timeout(spin::LOCK_TIMEOUT_SPINS);
+    // the console may be used by the code we interrupted, try again next time
+    let Ok(mut console) = console.try_borrow_mut() else {
+        return;
+    };
+
+    let terminal = REQUESTED_TERMINAL.swap(NO_TERMINAL_REQUEST, Ordering::Relaxed);
+    if terminal != NO_TERMINAL_REQUEST {
+        console.switch_terminal(terminal);
+    }
+    let scroll = REQUESTED_SCROLL.swap(0, Ordering::Relaxed);
+    if scroll != 0 {
+        console.scroll_active_terminal(scroll);
+    }
+}
+
+/// Move the keyboard and uart input to the terminals and wake their readers, called periodically
+/// from the timer interrupt
+pub fn receive_input() {
+    // SAFETY: we are only reading the console, and the late console is never replaced after init
+    let Some(console) = (unsafe { CONSOLE.late_device() }) else {
+        return;
+    };
+    let console = console.lock_timeout(spin::LOCK_TIMEOUT_SPINS);
+    // the console may be used by the code we interrupted, try again next time
+    let Ok(mut console) = console.try_borrow_mut() else {
+        return;
+    };
+    console.receive_input();
+}
+
+/// Redraw the active terminal on the screen, used when the screen was used by something else
+/// (i.e. the boot logo)
+pub fn redraw() {
+    // SAFETY: we are only reading the console, and the late console is never replaced after init
+    let Some(console) = (unsafe { CONSOLE.late_device() }) else {
+        return;
+    };
+    let console = console.lock();
+    // if the console is in use (i.e. we are panicking while printing), we can't redraw
+    let Ok(mut console) = console.try_borrow_mut() else {
+        return;
+    };
+    let console = &mut *console;
+    console.terminals[console.active_terminal].redraw(console.video_console.as_mut());
+}
+
+/// Prepare the screen for the panic message, take the display back from any process using it,
+/// and show the bottom of the kernel terminal, so whatever is printed after is visible
+pub fn show_panic_screen() {
+    if let Some(controller) = graphics::vga::controller() {
+        controller.force_kernel_ownership();
+    }
+    // SAFETY: we are only reading the console, and the late console is never replaced after init
+    let Some(console) = (unsafe { CONSOLE.late_device() }) else {
+        return;
+    };
+    let console = console.lock();
+    // if the console is in use (i.e. we are panicking while printing), we can't redraw
+    let Ok(mut console) = console.try_borrow_mut() else {
+        return;
+    };
+    let console = &mut *console;
+    console.active_terminal = KERNEL_TERMINAL;
+    let terminal = &mut console.terminals[KERNEL_TERMINAL];
+    terminal.reset_scroll(console.video_console.as_mut());
+    // `reset_scroll` only redraws if we were scrolled up
+    terminal.redraw(console.video_console.as_mut());
 }
 
 #[allow(dead_code)]
@@ -85,11 +215,11 @@ fn create_video_console(framebuffer: Option<multiboot2::Framebuffer>) -> Box<dyn
             }
             FramebufferColorInfo::EgaText => Box::new(VgaText::new(framebuffer)),
         },
-        None => panic!("No framebuffer provided"),
+        None => Box::new(VgaText::legacy()),
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 enum AnsiColor {
     Black = 0,
@@ -134,7 +264,7 @@ impl AnsiColor {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct VideoConsoleAttribute {
     foreground: AnsiColor,
     background: AnsiColor,
@@ -155,8 +285,12 @@ impl Default for VideoConsoleAttribute {
 
 trait VideoConsole: Send + Sync {
     fn init(&mut self);
+    /// Clear the screen and move the cursor to the start
+    fn clear(&mut self);
+    /// Number of text lines that fit on the screen
+    fn rows(&self) -> usize;
     fn set_attrib(&mut self, attrib: VideoConsoleAttribute);
-    fn write_byte(&mut self, c: u8);
+    fn write_char(&mut self, c: char);
     fn backspace(&mut self);
 }
 
@@ -229,7 +363,7 @@ impl ConsoleController {
     {
         let ret = match self {
             ConsoleController::Early(console) => {
-                let console = console.lock();
+                let console = console.lock_timeout(spin::LOCK_TIMEOUT_SPINS);
                 let x = if let Ok(mut c) = console.try_borrow_mut() {
                     Some(f(&mut *c))
                 } else {
@@ -240,7 +374,7 @@ impl ConsoleController {
             // we have to use another branch because the types are different
             // even though we use same function calls
             ConsoleController::Late(console) => {
-                let console = console.lock();
+                let console = console.lock_timeout(spin::LOCK_TIMEOUT_SPINS);
                 let x = if let Ok(mut c) = console.try_borrow_mut() {
                     Some(f(&mut *c))
                 } else {
@@ -295,7 +429,7 @@ impl Write for EarlyConsole {
 impl Console for EarlyConsole {
     fn write(&mut self, src: &[u8]) -> usize {
         if let Some(capture) = &mut self.capture {
-            capture.push_str(core::str::from_utf8(src).expect("Non-UTF8"));
+            capture.push_str(&String::from_utf8_lossy(src));
         } else {
             for &c in src {
                 self.write_byte(c);
@@ -322,8 +456,12 @@ pub(super) struct LateConsole {
     uart: Uart,
     video_console: Box<dyn VideoConsole>,
     keyboard: KeyboardReader,
-    console_cmd_buffer: Option<String>,
-    current_attrib: VideoConsoleAttribute,
+    keymap: Keymap,
+    terminals: [VirtualTerminal; NUM_TERMINALS],
+    /// Woken when the terminal at the same index has input to read
+    input_queues: [Arc<WaitQueue>; NUM_TERMINALS],
+    /// The terminal shown on the screen and receiving keyboard input
+    active_terminal: usize,
     capture: Option<String>,
 }
 
@@ -334,168 +472,135 @@ impl LateConsole {
             uart,
             video_console,
             keyboard: keyboard_mouse::get_keyboard_reader(),
-            console_cmd_buffer: None,
-            current_attrib: Default::default(),
+            keymap: Keymap::default(),
+            terminals: core::array::from_fn(|_| VirtualTerminal::new()),
+            input_queues: core::array::from_fn(|_| Arc::new(WaitQueue::new())),
+            active_terminal: 0,
             capture: None,
         }
     }
 
-    fn write_byte(&mut self, byte: u8) {
-        let mut write_byte_inner = |byte: u8| {
-            // backspace
-            if byte == 8 {
-                self.video_console.backspace();
-                // Safety: we are sure that the uart is initialized
-                unsafe {
-                    // write backspace
+    fn write_byte(&mut self, terminal: usize, byte: u8) {
+        let graphics_owned = graphics::vga::is_owned();
+        // the kernel terminal is mirrored to the uart, and the rest if requested while
+        // we can't show them on the screen
+        if terminal == KERNEL_TERMINAL
+            || (graphics_owned && cmdline::cmdline().graphics_serial_mirror)
+        {
+            // Safety: we are sure that the uart is initialized
+            unsafe {
+                if byte == 8 {
+                    // write backspace, then space to clear the character, and backspace again
                     self.uart.write_byte(byte);
-                    // write space to clear the character
                     self.uart.write_byte(b' ');
-                    // write backspace again
                     self.uart.write_byte(byte);
-                };
-            } else {
-                self.video_console.write_byte(byte);
-                // Safety: we are sure that the uart is initialized
-                unsafe { self.uart.write_byte(byte) };
-            }
-        };
-
-        if let Some(buf) = &mut self.console_cmd_buffer {
-            // is this the end of the command
-            match byte {
-                b'0'..=b'9' | b';' | b'[' => {
-                    // part of the command
-                    buf.push(byte as char);
-                }
-                b'm' => {
-                    // end of the color command
-                    if let Some(inner_cmd) = buf.strip_prefix('[') {
-                        inner_cmd.split(';').for_each(|cmd| {
-                            if let Ok(cmd) = cmd.parse::<u8>() {
-                                match cmd {
-                                    0 => {
-                                        self.current_attrib = Default::default();
-                                    }
-                                    1 => {
-                                        self.current_attrib.bold = true;
-                                        self.current_attrib.faint = false;
-                                    }
-                                    2 => {
-                                        self.current_attrib.bold = false;
-                                        self.current_attrib.faint = true;
-                                    }
-                                    30..=37 => {
-                                        let color = cmd - 30;
-                                        self.current_attrib.foreground = AnsiColor::from_u8(color);
-                                    }
-                                    90..=97 => {
-                                        let color = (cmd - 90) + 8;
-                                        self.current_attrib.foreground = AnsiColor::from_u8(color);
-                                    }
-                                    40..=47 => {
-                                        let color = cmd - 40;
-                                        self.current_attrib.background = AnsiColor::from_u8(color);
-                                    }
-                                    100..=107 => {
-                                        let color = (cmd - 100) + 8;
-                                        self.current_attrib.background = AnsiColor::from_u8(color);
-                                    }
-                                    _ => {}
-                                }
-                                self.video_console.set_attrib(self.current_attrib);
-                            }
-                        });
-
-                        // output all saved into the uart as well
-                        // Safety: we are sure that the uart is initialized
-                        unsafe {
-                            self.uart.write_byte(0x1b);
-                            self.uart.write_byte(b'[');
-                            for &c in inner_cmd.as_bytes() {
-                                self.uart.write_byte(c);
-                            }
-                            self.uart.write_byte(b'm');
-                        }
-                        self.console_cmd_buffer = None;
-                    } else {
-                        // not a valid command
-                        // abort and write the char
-                        self.console_cmd_buffer = None;
-                        write_byte_inner(byte);
-                    }
-                }
-                _ => {
-                    // unsupported command or character of a command
-                    // abort and write char, probably we lost some characters
-                    // if this was not intended to be a command
-                    self.console_cmd_buffer = None;
-                    write_byte_inner(byte);
+                } else {
+                    self.uart.write_byte(byte);
                 }
             }
-        } else {
-            // start of a new command
-            // 0x1b = ESC
-            if byte == 0x1b {
-                self.console_cmd_buffer = Some(String::new());
-                return;
-            }
-            // otherwise, just write to the screen
-            write_byte_inner(byte);
         }
-    }
-}
 
-impl Write for LateConsole {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write(s.as_bytes());
-        Ok(())
+        // the screen is not ours until the boot logo is removed or the process owning it releases it,
+        // we will redraw then
+        if terminal == self.active_terminal && !graphics::boot_logo::is_showing() && !graphics_owned
+        {
+            let terminal = &mut self.terminals[terminal];
+            // new output, go back to the bottom
+            terminal.reset_scroll(self.video_console.as_mut());
+            terminal.write_byte(byte, Some(self.video_console.as_mut()));
+        } else {
+            self.terminals[terminal].write_byte(byte, None);
+        }
     }
-}
 
-impl Console for LateConsole {
-    fn write(&mut self, src: &[u8]) -> usize {
+    fn write_terminal(&mut self, terminal: usize, src: &[u8]) -> usize {
         if let Some(capture) = &mut self.capture {
-            capture.push_str(core::str::from_utf8(src).expect("Non-UTF8"));
+            capture.push_str(&String::from_utf8_lossy(src));
         } else {
             for &c in src {
-                self.write_byte(c);
+                self.write_byte(terminal, c);
             }
         }
         src.len()
     }
 
-    fn read(&mut self, dst: &mut [u8]) -> usize {
-        let mut i = 0;
+    /// Move pending input to the terminals' buffers, keyboard input goes to the active terminal
+    /// and uart input to the kernel terminal, then wake the readers of the terminals that have input
+    fn receive_input(&mut self) {
+        while let Some(key) = self.keyboard.recv() {
+            // at most 2 characters, see `Keymap::process`
+            let mut chars = ['\0'; 2];
+            let mut len = 0;
+            self.keymap.process(&key, |c| {
+                chars[len] = c;
+                len += 1;
+            });
+            for c in &chars[..len] {
+                // the input is UTF-8
+                let mut buf = [0; 4];
+                for &byte in c.encode_utf8(&mut buf).as_bytes() {
+                    self.push_input(self.active_terminal, byte);
+                }
+            }
+        }
 
         // for some reason, uart returns \r instead of \n when pressing <enter>
         // so we have to convert it to \n
         // Safety: we are sure that the uart is initialized
-        let read_uart = || unsafe {
-            self.uart.try_read_byte().map(|c| match c {
+        while let Some(c) = unsafe { self.uart.try_read_byte() } {
+            let c = match c {
                 b'\r' => b'\n',
                 b'\x7f' => b'\x08', // delete -> backspace
                 _ => c,
-            })
-        };
+            };
+            self.push_input(KERNEL_TERMINAL, c);
+        }
 
-        while i < dst.len() {
-            // try to read from keyboard
-            // if we can't read from keyboard, try to read from uart
-            if let Some(c) = self
-                .keyboard
-                .recv()
-                .and_then(|c| if c.pressed { c.virtual_char() } else { None })
-                .or_else(read_uart)
-            {
-                dst[i] = c;
-                i += 1;
-                // ignore if it's not a valid char
-            } else {
-                break;
+        for (terminal, queue) in self.terminals.iter().zip(&self.input_queues) {
+            if terminal.has_input() {
+                queue.wake_all();
             }
         }
-        i
+    }
+
+    /// Give an input byte to `terminal`, and echo it if the terminal is editing the line
+    fn push_input(&mut self, terminal: usize, byte: u8) {
+        if let Some(echo) = self.terminals[terminal].push_input(byte) {
+            self.write_byte(terminal, echo);
+        }
+    }
+
+    fn read_terminal(&mut self, terminal: usize, dst: &mut [u8]) -> usize {
+        self.terminals[terminal].read_input(dst)
+    }
+
+    fn switch_terminal(&mut self, terminal: usize) {
+        if terminal >= NUM_TERMINALS || terminal == self.active_terminal {
+            return;
+        }
+        self.active_terminal = terminal;
+        self.terminals[terminal].redraw(self.video_console.as_mut());
+    }
+
+    fn scroll_active_terminal(&mut self, lines: isize) {
+        self.terminals[self.active_terminal].scroll(lines, self.video_console.as_mut());
+    }
+}
+
+impl Write for LateConsole {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Console for LateConsole {
+    fn write(&mut self, src: &[u8]) -> usize {
+        self.write_terminal(KERNEL_TERMINAL, src)
+    }
+
+    fn read(&mut self, dst: &mut [u8]) -> usize {
+        self.read_terminal(KERNEL_TERMINAL, dst)
     }
 
     fn start_capture(&mut self) -> Option<String> {
@@ -513,15 +618,32 @@ impl fmt::Debug for LateConsole {
     }
 }
 
-impl Device for ReMutex<RefCell<LateConsole>> {
+/// A device for one of the virtual terminals of the [`LateConsole`]
+pub(super) struct TerminalDevice {
+    console: Arc<ReMutex<RefCell<LateConsole>>>,
+    terminal: usize,
+    input_queue: Arc<WaitQueue>,
+    name: String,
+}
+
+impl fmt::Debug for TerminalDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TerminalDevice")
+            .field("terminal", &self.terminal)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl Device for TerminalDevice {
     fn name(&self) -> &str {
-        "console"
+        &self.name
     }
 
     fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
-        let console = self.lock();
+        let console = self.console.lock();
         let x = if let Ok(mut c) = console.try_borrow_mut() {
-            c.read(buf)
+            c.read_terminal(self.terminal, buf)
         } else {
             // cannot read from console if its taken
             0
@@ -529,10 +651,31 @@ impl Device for ReMutex<RefCell<LateConsole>> {
         Ok(x as u64)
     }
 
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        Some(self.input_queue.clone())
+    }
+
+    fn line_editing(&self) -> Option<bool> {
+        let console = self.console.lock();
+        let line_editing = console.borrow().terminals[self.terminal].line_editing();
+        Some(line_editing)
+    }
+
+    fn set_line_editing(&self, line_editing: bool) -> Result<(), FileSystemError> {
+        let console = self.console.lock();
+        let terminal = &mut console.borrow_mut().terminals[self.terminal];
+        terminal.set_line_editing(line_editing);
+        // disabling line editing gives the line typed so far to the readers
+        if terminal.has_input() {
+            self.input_queue.wake_all();
+        }
+        Ok(())
+    }
+
     fn write(&self, _offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
-        let console = self.lock();
+        let console = self.console.lock();
         let x = if let Ok(mut c) = console.try_borrow_mut() {
-            c.write(buf)
+            c.write_terminal(self.terminal, buf)
         } else {
             // this should not be reached at all, but just in case
             //
@@ -0,0 +1,212 @@
+//! Translation of the keyboard keys to the text input of the terminals
+//!
+//! On top of the US layout of [`Key::virtual_char`], `AltGr` (the right `Alt`) gives access
+//! to some `Latin-1` characters, and to dead keys that add an accent to the next letter,
+//! similar to the `US International` layout.
+
+use kernel_user_link::keyboard::{modifier, Key, KeyType};
+
+use crate::testing;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeadKey {
+    Grave,
+    Acute,
+    Circumflex,
+    Tilde,
+    Diaeresis,
+}
+
+impl DeadKey {
+    /// The character of the accent alone, when it can't be combined with the next key
+    fn spacing_char(self) -> char {
+        match self {
+            DeadKey::Grave => '`',
+            DeadKey::Acute => '\u{B4}',
+            DeadKey::Circumflex => '^',
+            DeadKey::Tilde => '~',
+            DeadKey::Diaeresis => '\u{A8}',
+        }
+    }
+
+    /// Combine with a lowercase letter, uppercase is handled by the caller
+    fn combine(self, c: char) -> Option<char> {
+        let (bases, combined) = match self {
+            DeadKey::Grave => ("aeiou", "àèìòù"),
+            DeadKey::Acute => ("aeiouy", "áéíóúý"),
+            DeadKey::Circumflex => ("aeiou", "âêîôû"),
+            DeadKey::Tilde => ("ano", "ãñõ"),
+            DeadKey::Diaeresis => ("aeiouy", "äëïöüÿ"),
+        };
+        let i = bases.chars().position(|base| base == c)?;
+        combined.chars().nth(i)
+    }
+}
+
+/// `AltGr` + key, with the dead keys
+fn alt_gr_action(key_type: KeyType, shifted: bool) -> Option<Result<char, DeadKey>> {
+    let c = match (key_type, shifted) {
+        (KeyType::Backtick, false) => return Some(Err(DeadKey::Grave)),
+        (KeyType::Backtick, true) => return Some(Err(DeadKey::Tilde)),
+        (KeyType::SingleQuote, false) => return Some(Err(DeadKey::Acute)),
+        (KeyType::SingleQuote, true) => return Some(Err(DeadKey::Diaeresis)),
+        (KeyType::Num6, true) => return Some(Err(DeadKey::Circumflex)),
+        (KeyType::Num1, false) => '¡',
+        (KeyType::Num2, false) => '²',
+        (KeyType::Num3, false) => '³',
+        (KeyType::Num4, false) => '¤',
+        (KeyType::Num5, false) => '€',
+        (KeyType::Num6, false) => '¼',
+        (KeyType::Num7, false) => '½',
+        (KeyType::Num8, false) => '¾',
+        (KeyType::Minus, false) => '¥',
+        (KeyType::Equals, false) => '×',
+        (KeyType::Equals, true) => '÷',
+        (KeyType::LeftBracket, false) => '«',
+        (KeyType::RightBracket, false) => '»',
+        (KeyType::Semicolon, false) => '¶',
+        (KeyType::Semicolon, true) => '°',
+        (KeyType::Slash, false) => '¿',
+        (KeyType::Q, _) => 'ä',
+        (KeyType::W, _) => 'å',
+        (KeyType::E, _) => 'é',
+        (KeyType::R, _) => '®',
+        (KeyType::T, _) => 'þ',
+        (KeyType::Y, _) => 'ü',
+        (KeyType::U, _) => 'ú',
+        (KeyType::I, _) => 'í',
+        (KeyType::O, _) => 'ó',
+        (KeyType::P, _) => 'ö',
+        (KeyType::A, _) => 'á',
+        (KeyType::S, false) => 'ß',
+        (KeyType::S, true) => '§',
+        (KeyType::D, _) => 'ð',
+        (KeyType::L, _) => 'ø',
+        (KeyType::Z, _) => 'æ',
+        (KeyType::C, _) => '©',
+        (KeyType::N, _) => 'ñ',
+        (KeyType::M, _) => 'µ',
+        (KeyType::Comma, _) => 'ç',
+        _ => return None,
+    };
+
+    Some(Ok(if shifted { to_upper(c) } else { c }))
+}
+
+/// Uppercase if it's a single character
+fn to_upper(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+/// Keeps the state of `AltGr` and the pending dead key
+#[derive(Default)]
+pub(super) struct Keymap {
+    alt_gr: bool,
+    dead_key: Option<DeadKey>,
+}
+
+impl Keymap {
+    /// Process a key event, and call `out` with the resulting characters, which can be 2 when
+    /// a dead key can't be combined with the key after it
+    pub fn process(&mut self, key: &Key, mut out: impl FnMut(char)) {
+        if key.key_type == KeyType::RightAlt {
+            self.alt_gr = key.pressed;
+            return;
+        }
+        if !key.pressed {
+            return;
+        }
+        let shifted = key.modifiers & modifier::SHIFT != 0;
+
+        let c = if self.alt_gr {
+            match alt_gr_action(key.key_type, shifted) {
+                Some(Ok(c)) => c,
+                Some(Err(dead_key)) => {
+                    // pressing a dead key twice gives the accent
+                    if let Some(pending) = self.dead_key.replace(dead_key) {
+                        out(pending.spacing_char());
+                        if pending == dead_key {
+                            self.dead_key = None;
+                        }
+                    }
+                    return;
+                }
+                None => return,
+            }
+        } else {
+            let Some(c) = key.virtual_char() else {
+                // modifiers and other keys don't affect the dead key
+                return;
+            };
+            c as char
+        };
+
+        let Some(dead_key) = self.dead_key.take() else {
+            return out(c);
+        };
+        let lower = c.to_ascii_lowercase();
+        match dead_key.combine(lower) {
+            Some(combined) if lower != c => out(to_upper(combined)),
+            Some(combined) => out(combined),
+            // space gives the accent alone
+            None if c == ' ' => out(dead_key.spacing_char()),
+            None => {
+                out(dead_key.spacing_char());
+                out(c);
+            }
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_keymap_alt_gr_and_dead_keys() {
+    use alloc::string::String;
+
+    let key = |key_type, pressed, modifiers| Key {
+        pressed,
+        modifiers,
+        key_type,
+        timestamp: 0,
+        sequence: 0,
+    };
+    let alt_gr = |pressed| key(KeyType::RightAlt, pressed, 0);
+    let press = |key_type| key(key_type, true, 0);
+    let press_shifted = |key_type| key(key_type, true, modifier::SHIFT);
+
+    let mut keymap = Keymap::default();
+    let mut out = String::new();
+    let keys = [
+        press(KeyType::A),
+        alt_gr(true),
+        press(KeyType::E),
+        press_shifted(KeyType::N),
+        press(KeyType::SingleQuote),
+        alt_gr(false),
+        // dead acute + e
+        press(KeyType::E),
+        alt_gr(true),
+        press_shifted(KeyType::SingleQuote),
+        alt_gr(false),
+        // dead diaeresis + shift + u
+        press_shifted(KeyType::U),
+        alt_gr(true),
+        press(KeyType::Backtick),
+        alt_gr(false),
+        // dead grave + x can't be combined
+        press(KeyType::X),
+        alt_gr(true),
+        press_shifted(KeyType::Num6),
+        alt_gr(false),
+        // dead circumflex + space
+        press(KeyType::Space),
+    ];
+    for key in &keys {
+        keymap.process(key, |c| out.push(c));
+    }
+
+    assert_eq!(out, "aéÑéÜ`x^");
+}
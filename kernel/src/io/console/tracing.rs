@@ -176,6 +176,9 @@ impl LogFile {
                 Err(crate::fs::FileSystemError::FileNotFound) => return,
                 Err(e) => {
                     println!("Failed to open log file: {:?}", e);
+                    // don't keep trying, i.e. the root filesystem is read-only (initrd)
+                    self.shutdown = true;
+                    self.buffer.clear();
                     return;
                 }
                 Ok(f) => {
@@ -213,7 +216,10 @@ impl Default for LogFile {
 
 impl Write for LogFile {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.buffer.push_str(s);
+        // nothing will flush it
+        if !self.shutdown {
+            self.buffer.push_str(s);
+        }
         Ok(())
     }
 }
@@ -1,7 +1,7 @@
 use embedded_graphics::{
     geometry::Point,
     mono_font::{
-        ascii::{FONT_9X15, FONT_9X15_BOLD},
+        iso_8859_1::{FONT_9X15, FONT_9X15_BOLD},
         MonoTextStyle,
     },
     pixelcolor::{Rgb888, RgbColor},
@@ -15,6 +15,14 @@ use crate::graphics::{self, vga, Pixel};
 
 use super::{VideoConsole, VideoConsoleAttribute};
 
+/// Shown for characters that are not in the font
+const REPLACEMENT_GLYPH: char = '?';
+
+/// The font only has the printable characters of `ISO 8859-1` (Latin-1)
+fn has_glyph(c: char) -> bool {
+    matches!(c, ' '..='~' | '\u{A0}'..='\u{FF}')
+}
+
 pub(super) struct VgaGraphics {
     pos: Point,
     text_style: MonoTextStyle<'static, Rgb888>,
@@ -82,6 +90,17 @@ impl VideoConsole for VgaGraphics {
         }
     }
 
+    fn clear(&mut self) {
+        if let Some(mut vga) = self.vga.lock_kernel() {
+            vga.clear();
+        }
+        self.pos = Point::new(0, 0);
+    }
+
+    fn rows(&self) -> usize {
+        self.vga.framebuffer_info().height / self.text_style.line_height() as usize
+    }
+
     fn set_attrib(&mut self, attrib: VideoConsoleAttribute) {
         // These colors are used in PowerShell 6 in Windows 10
         // except for black, changed to all zeros
@@ -117,19 +136,20 @@ impl VideoConsole for VgaGraphics {
         }
     }
 
-    fn write_byte(&mut self, c: u8) {
+    fn write_char(&mut self, c: char) {
         let Some(mut vga) = self.vga.lock_kernel() else {
             // don't change anything if we can't lock the VGA
             return;
         };
 
-        if c == b'\n' {
+        if c == '\n' {
             self.pos = Point::new(0, self.pos.y + self.text_style.line_height() as i32);
-        } else if c == b'\r' {
+        } else if c == '\r' {
             self.pos.x = 0;
         } else {
+            let c = if has_glyph(c) { c } else { REPLACEMENT_GLYPH };
             let mut dst = [0; 4];
-            let str = (c as char).encode_utf8(&mut dst);
+            let str = c.encode_utf8(&mut dst);
 
             let style = self.text_style;
 
@@ -1,13 +1,54 @@
 //! A temporary tool to allow for easy printing to the screen.
 //! We are using the VGA text mode buffer to print to the screen.
+//!
+//! Used when the bootloader sets up a text mode instead of a linear framebuffer,
+//! or when it doesn't tell us anything about the display, in which case we assume the
+//! text mode the BIOS leaves us in.
 
-use crate::{memory_management::virtual_space::VirtualSpace, multiboot2};
+use crate::{cpu, memory_management::virtual_space::VirtualSpace, multiboot2};
 
 use super::{VideoConsole, VideoConsoleAttribute};
 
 /// White on black text
 const DEFAULT_ATTRIB: u8 = 0x0f;
 
+/// The default `80x25` text mode buffer
+const LEGACY_TEXT_ADDR: u64 = 0xB8000;
+const LEGACY_TEXT_WIDTH: u32 = 80;
+const LEGACY_TEXT_HEIGHT: u32 = 25;
+
+/// The CRT controller registers, to disable the hardware cursor, we don't move it
+const CRTC_ADDR_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_DISABLE: u8 = 1 << 5;
+
+/// The characters of code page 437 from `0x80` to `0xFF`, the character set of the text mode
+const CP437_HIGH: [&str; 8] = [
+    "ÇüéâäàåçêëèïîìÄÅ",
+    "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧",
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩",
+    "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}",
+];
+
+/// Shown for characters that are not in the code page
+const REPLACEMENT_GLYPH: u8 = b'?';
+
+fn to_cp437(c: char) -> u8 {
+    if matches!(c, ' '..='~') {
+        return c as u8;
+    }
+    CP437_HIGH
+        .iter()
+        .flat_map(|row| row.chars())
+        .position(|high| high == c)
+        .map_or(REPLACEMENT_GLYPH, |i| 0x80 + i as u8)
+}
+
 pub(super) struct VgaText {
     pos: (usize, usize),
     attrib: u8,
@@ -38,6 +79,18 @@ impl VgaText {
         }
     }
 
+    /// The text mode set up by the BIOS, when we don't have a framebuffer from the bootloader
+    pub fn legacy() -> Self {
+        Self::new(multiboot2::Framebuffer {
+            addr: LEGACY_TEXT_ADDR,
+            pitch: LEGACY_TEXT_WIDTH * 2,
+            width: LEGACY_TEXT_WIDTH,
+            height: LEGACY_TEXT_HEIGHT,
+            bpp: 16,
+            color_info: multiboot2::FramebufferColorInfo::EgaText,
+        })
+    }
+
     fn get_arr_pos(&self, pos: (usize, usize)) -> usize {
         pos.0 * 2 + pos.1 * self.pitch
     }
@@ -65,13 +118,6 @@ impl VgaText {
         }
     }
 
-    fn clear(&mut self) {
-        for i in 0..self.height {
-            self.clear_line(i);
-        }
-        self.pos = (0, 0);
-    }
-
     fn clear_line(&mut self, line: usize) {
         for i in 0..self.width {
             let pos = self.get_arr_pos((i, line));
@@ -84,9 +130,25 @@ impl VgaText {
 
 impl VideoConsole for VgaText {
     fn init(&mut self) {
+        // SAFETY: these are the standard VGA ports, and we are the only user of the display
+        unsafe {
+            cpu::io_out(CRTC_ADDR_PORT, CRTC_CURSOR_START);
+            cpu::io_out(CRTC_DATA_PORT, CRTC_CURSOR_DISABLE);
+        }
         self.clear();
     }
 
+    fn clear(&mut self) {
+        for i in 0..self.height {
+            self.clear_line(i);
+        }
+        self.pos = (0, 0);
+    }
+
+    fn rows(&self) -> usize {
+        self.height
+    }
+
     fn set_attrib(&mut self, attrib: VideoConsoleAttribute) {
         let to_vga_color = |color: u8| {
             let mappings = &[
@@ -123,15 +185,15 @@ impl VideoConsole for VgaText {
         self.attrib = (bg << 4) | fg;
     }
 
-    fn write_byte(&mut self, c: u8) {
-        if c == b'\n' {
+    fn write_char(&mut self, c: char) {
+        if c == '\n' {
             self.pos.0 = 0;
             self.pos.1 += 1;
             self.fix_after_advance();
             return;
         }
         let i = self.get_arr_pos(self.pos);
-        self.memory[i] = c;
+        self.memory[i] = to_cp437(c);
         self.memory[i + 1] = self.attrib;
         self.pos.0 += 1;
         self.fix_after_advance();
@@ -0,0 +1,392 @@
+//! Virtual terminals, independent text consoles sharing the same screen
+//!
+//! Each terminal keeps the text written to it (with the attributes) as lines, so that it can be
+//! redrawn when switching to it, or when scrolling back.
+//!
+//! The output is decoded as UTF-8, invalid sequences are shown as [`char::REPLACEMENT_CHARACTER`].
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+
+use crate::testing;
+
+use super::{AnsiColor, VideoConsole, VideoConsoleAttribute};
+
+/// Number of lines kept for each terminal, including the visible ones
+const SCROLLBACK_LINES: usize = 500;
+/// Number of input bytes buffered for a terminal until they are read, more input is dropped
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    c: char,
+    attrib: VideoConsoleAttribute,
+}
+
+/// Decodes UTF-8 one byte at a time, since a character can be split between writes
+#[derive(Default)]
+struct Utf8Decoder {
+    buf: [u8; 4],
+    len: usize,
+    needed: usize,
+}
+
+impl Utf8Decoder {
+    /// Add a byte, and call `out` with the characters completed by it, which can be 2 when
+    /// it interrupts an incomplete sequence
+    fn push(&mut self, byte: u8, mut out: impl FnMut(char)) {
+        if self.len != 0 {
+            if byte & 0xC0 == 0x80 {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                if self.len == self.needed {
+                    // this rejects overlong encodings and surrogates
+                    let c = core::str::from_utf8(&self.buf[..self.len])
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.len = 0;
+                    out(c);
+                }
+                return;
+            }
+            // the sequence ended early
+            self.len = 0;
+            out(char::REPLACEMENT_CHARACTER);
+        }
+
+        self.needed = match byte {
+            0x00..=0x7F => return out(byte as char),
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return out(char::REPLACEMENT_CHARACTER),
+        };
+        self.buf[0] = byte;
+        self.len = 1;
+    }
+}
+
+pub(super) struct VirtualTerminal {
+    /// Never empty, the last line is the one being written to
+    lines: VecDeque<Vec<Cell>>,
+    console_cmd_buffer: Option<String>,
+    current_attrib: VideoConsoleAttribute,
+    /// Number of lines we are scrolled up from the bottom
+    scroll: usize,
+    /// Input received while this terminal is active, until it is read
+    input: VecDeque<u8>,
+    /// Edit the input a line at a time, see [`VirtualTerminal::push_input`]
+    line_editing: bool,
+    /// The line being edited, moved to `input` on `\n`
+    line: Vec<u8>,
+    decoder: Utf8Decoder,
+}
+
+impl VirtualTerminal {
+    pub fn new() -> Self {
+        let mut lines = VecDeque::new();
+        lines.push_back(Vec::new());
+        Self {
+            lines,
+            console_cmd_buffer: None,
+            current_attrib: Default::default(),
+            scroll: 0,
+            input: VecDeque::with_capacity(INPUT_BUFFER_SIZE),
+            line_editing: false,
+            line: Vec::new(),
+            decoder: Utf8Decoder::default(),
+        }
+    }
+
+    /// Add an input byte, and return the byte to echo to the terminal, if any.
+    ///
+    /// With line editing, the bytes are kept in the line until `\n`, backspace removes the last
+    /// character, and the changes are echoed. Otherwise, they can be read right away.
+    pub fn push_input(&mut self, byte: u8) -> Option<u8> {
+        if !self.line_editing {
+            if self.input.len() < INPUT_BUFFER_SIZE {
+                self.input.push_back(byte);
+            }
+            return None;
+        }
+
+        match byte {
+            b'\x08' => {
+                if self.line.is_empty() {
+                    return None;
+                }
+                // remove a whole character, the continuation bytes, then the first one
+                while self.line.pop().is_some_and(|byte| byte & 0xC0 == 0x80) {}
+            }
+            b'\n' => {
+                self.line.push(byte);
+                self.flush_line();
+            }
+            _ => {
+                // leave space for the `\n`
+                if self.line.len() + 1 >= INPUT_BUFFER_SIZE {
+                    return None;
+                }
+                self.line.push(byte);
+            }
+        }
+        Some(byte)
+    }
+
+    /// Move the edited line to the input, what doesn't fit is dropped
+    fn flush_line(&mut self) {
+        let space = INPUT_BUFFER_SIZE - self.input.len();
+        self.input.extend(self.line.drain(..).take(space));
+    }
+
+    /// Is there input ready to be read
+    pub fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    pub fn line_editing(&self) -> bool {
+        self.line_editing
+    }
+
+    pub fn set_line_editing(&mut self, line_editing: bool) {
+        self.line_editing = line_editing;
+        if !line_editing {
+            // give the line typed so far as is
+            self.flush_line();
+        }
+    }
+
+    pub fn read_input(&mut self, dst: &mut [u8]) -> usize {
+        let mut i = 0;
+        while i < dst.len() {
+            let Some(byte) = self.input.pop_front() else {
+                break;
+            };
+            dst[i] = byte;
+            i += 1;
+        }
+        i
+    }
+
+    fn store_char(&mut self, c: char) {
+        match c {
+            '\n' => {
+                self.lines.push_back(Vec::new());
+                if self.lines.len() > SCROLLBACK_LINES {
+                    self.lines.pop_front();
+                }
+            }
+            // backspace
+            '\x08' => {
+                self.lines.back_mut().unwrap().pop();
+            }
+            _ => {
+                self.lines.back_mut().unwrap().push(Cell {
+                    c,
+                    attrib: self.current_attrib,
+                });
+            }
+        }
+    }
+
+    /// Write a byte to the terminal, and to `video_console` if this terminal is the one on the screen
+    pub fn write_byte(&mut self, byte: u8, video_console: Option<&mut dyn VideoConsole>) {
+        if let Some(buf) = &mut self.console_cmd_buffer {
+            // is this the end of the command
+            match byte {
+                b'0'..=b'9' | b';' | b'[' => {
+                    // part of the command
+                    buf.push(byte as char);
+                }
+                b'm' => {
+                    // end of the color command
+                    let buf = self.console_cmd_buffer.take().unwrap();
+                    if let Some(inner_cmd) = buf.strip_prefix('[') {
+                        inner_cmd.split(';').for_each(|cmd| {
+                            if let Ok(cmd) = cmd.parse::<u8>() {
+                                self.apply_attrib_cmd(cmd);
+                            }
+                        });
+                        if let Some(video_console) = video_console {
+                            video_console.set_attrib(self.current_attrib);
+                        }
+                    } else {
+                        // not a valid command
+                        // abort and write the char
+                        self.put_byte(byte, video_console);
+                    }
+                }
+                _ => {
+                    // unsupported command or character of a command
+                    // abort and write char, probably we lost some characters
+                    // if this was not intended to be a command
+                    self.console_cmd_buffer = None;
+                    self.put_byte(byte, video_console);
+                }
+            }
+        } else {
+            // start of a new command
+            // 0x1b = ESC
+            if byte == 0x1b {
+                self.console_cmd_buffer = Some(String::new());
+                return;
+            }
+            // otherwise, just write to the screen
+            self.put_byte(byte, video_console);
+        }
+    }
+
+    fn put_byte(&mut self, byte: u8, mut video_console: Option<&mut dyn VideoConsole>) {
+        let mut decoder = core::mem::take(&mut self.decoder);
+        decoder.push(byte, |c| {
+            // reborrow for each character
+            let video_console = video_console
+                .as_mut()
+                .map(|video_console| &mut **video_console as &mut dyn VideoConsole);
+            self.put_char(c, video_console)
+        });
+        self.decoder = decoder;
+    }
+
+    fn put_char(&mut self, c: char, video_console: Option<&mut dyn VideoConsole>) {
+        self.store_char(c);
+        if let Some(video_console) = video_console {
+            // backspace
+            if c == '\x08' {
+                video_console.backspace();
+            } else {
+                video_console.write_char(c);
+            }
+        }
+    }
+
+    fn apply_attrib_cmd(&mut self, cmd: u8) {
+        match cmd {
+            0 => {
+                self.current_attrib = Default::default();
+            }
+            1 => {
+                self.current_attrib.bold = true;
+                self.current_attrib.faint = false;
+            }
+            2 => {
+                self.current_attrib.bold = false;
+                self.current_attrib.faint = true;
+            }
+            30..=37 => {
+                let color = cmd - 30;
+                self.current_attrib.foreground = AnsiColor::from_u8(color);
+            }
+            90..=97 => {
+                let color = (cmd - 90) + 8;
+                self.current_attrib.foreground = AnsiColor::from_u8(color);
+            }
+            40..=47 => {
+                let color = cmd - 40;
+                self.current_attrib.background = AnsiColor::from_u8(color);
+            }
+            100..=107 => {
+                let color = (cmd - 100) + 8;
+                self.current_attrib.background = AnsiColor::from_u8(color);
+            }
+            _ => {}
+        }
+    }
+
+    /// Scroll the view by `lines` (positive is up, into the scrollback) and redraw
+    pub fn scroll(&mut self, lines: isize, video_console: &mut dyn VideoConsole) {
+        let max_scroll = self.lines.len().saturating_sub(video_console.rows());
+        let scroll = self.scroll.saturating_add_signed(lines).min(max_scroll);
+        if scroll != self.scroll {
+            self.scroll = scroll;
+            self.redraw(video_console);
+        }
+    }
+
+    /// Go back to the bottom of the terminal if we are scrolled up
+    pub fn reset_scroll(&mut self, video_console: &mut dyn VideoConsole) {
+        if self.scroll != 0 {
+            self.scroll = 0;
+            self.redraw(video_console);
+        }
+    }
+
+    /// Clear the screen and draw the visible lines of this terminal
+    pub fn redraw(&self, video_console: &mut dyn VideoConsole) {
+        video_console.clear();
+
+        let end = self.lines.len() - self.scroll;
+        let start = end.saturating_sub(video_console.rows());
+
+        let mut attrib = VideoConsoleAttribute::default();
+        video_console.set_attrib(attrib);
+        for (i, line) in self.lines.range(start..end).enumerate() {
+            if i != 0 {
+                video_console.write_char('\n');
+            }
+            for cell in line {
+                if cell.attrib != attrib {
+                    attrib = cell.attrib;
+                    video_console.set_attrib(attrib);
+                }
+                video_console.write_char(cell.c);
+            }
+        }
+        video_console.set_attrib(self.current_attrib);
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_utf8_decoder() {
+    let decode = |bytes: &[u8]| {
+        let mut decoder = Utf8Decoder::default();
+        let mut out = String::new();
+        for &byte in bytes {
+            decoder.push(byte, |c| out.push(c));
+        }
+        out
+    };
+
+    assert_eq!(decode("aé€😀".as_bytes()), "aé€😀");
+    // interrupted sequence, the next character is still decoded
+    assert_eq!(decode(b"\xC3a"), "\u{FFFD}a");
+    // invalid start byte, overlong encoding and surrogate
+    assert_eq!(decode(b"\xFFb\xC0\x80"), "\u{FFFD}b\u{FFFD}\u{FFFD}");
+    assert_eq!(decode(b"\xED\xA0\x80"), "\u{FFFD}");
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_line_editing() {
+    let mut terminal = VirtualTerminal::new();
+    let mut buf = [0; 16];
+
+    terminal.set_line_editing(true);
+    let echo = "ab\x08é\x08c"
+        .bytes()
+        .filter_map(|byte| terminal.push_input(byte))
+        .collect::<Vec<_>>();
+    assert_eq!(echo, "ab\x08é\x08c".as_bytes());
+    // nothing to read until the line is done
+    assert!(!terminal.has_input());
+    assert_eq!(terminal.push_input(b'\n'), Some(b'\n'));
+    let len = terminal.read_input(&mut buf);
+    assert_eq!(&buf[..len], b"ac\n");
+    // backspace on an empty line is not echoed
+    assert_eq!(terminal.push_input(b'\x08'), None);
+
+    // the partial line is given when disabling it
+    terminal.push_input(b'x');
+    terminal.set_line_editing(false);
+    assert_eq!(terminal.push_input(b'\x08'), None);
+    let len = terminal.read_input(&mut buf);
+    assert_eq!(&buf[..len], b"x\x08");
+
+    // new input is dropped when the buffer is full
+    for _ in 0..INPUT_BUFFER_SIZE + 1 {
+        terminal.push_input(b'1');
+    }
+    terminal.push_input(b'2');
+    let mut buf = [0; INPUT_BUFFER_SIZE + 1];
+    assert_eq!(terminal.read_input(&mut buf), INPUT_BUFFER_SIZE);
+    assert!(buf[..INPUT_BUFFER_SIZE].iter().all(|&byte| byte == b'1'));
+}
@@ -1,7 +1,7 @@
 use core::{fmt, sync::atomic::AtomicBool};
 
 pub mod console;
-mod uart;
+pub mod uart;
 
 static PRINT_ERR: AtomicBool = AtomicBool::new(false);
 
@@ -6,6 +6,7 @@ use crate::{cmdline, cpu};
 #[derive(Clone, Copy)]
 pub enum UartPort {
     COM1 = 0x3F8,
+    COM2 = 0x2F8,
 }
 
 #[repr(u8)]
@@ -53,18 +54,13 @@ fn read_reg(port_addr: UartPort, reg: UartReg) -> u8 {
 }
 
 /// Will return `true` if the test pass, otherwise, the serial port is disabled
-fn init_port(port_addr: UartPort) -> bool {
+fn init_port(port_addr: UartPort, rate: u32) -> bool {
     // disable interrupts
     write_reg(port_addr, UartReg::InterruptEnable, 0);
     // disable FIFO
     write_reg(port_addr, UartReg::InterruptAndFifoControl, 0);
 
     // compute divisor
-    let rate = if cmdline::cmdline().uart_baud == 0 {
-        cmdline::cmdline().uart_baud
-    } else {
-        115200
-    };
     let mut divisor = 115200 / rate;
     if divisor == 0 {
         divisor = 1;
@@ -132,7 +128,18 @@ impl Uart {
     }
 
     pub fn init(&mut self) {
-        self.is_enabled = cmdline::cmdline().uart && init_port(self.port_addr);
+        let rate = if cmdline::cmdline().uart_baud == 0 {
+            cmdline::cmdline().uart_baud
+        } else {
+            115200
+        };
+        self.is_enabled = cmdline::cmdline().uart && init_port(self.port_addr, rate);
+    }
+
+    /// Initialize the port at `115200` baud without reading the `cmdline`, used to report
+    /// exceptions that happen before the `cmdline` is parsed
+    pub fn init_early(&mut self) {
+        self.is_enabled = init_port(self.port_addr, 115200);
     }
 
     /// SAFETY: `init` must be called before calling this function
@@ -167,6 +174,7 @@ impl Uart {
     pub fn interrupt_num(&self) -> u8 {
         match self.port_addr {
             UartPort::COM1 => 4,
+            UartPort::COM2 => 3,
         }
     }
 }
@@ -23,31 +23,39 @@ core::arch::global_asm!(include_str!("boot.S"));
 mod macros;
 
 mod acpi;
+mod boot_time;
 mod cmdline;
 mod collections;
 mod cpu;
 mod devices;
 mod executable;
 mod fs;
+mod gdb_stub;
 mod graphics;
+mod housekeeping;
 mod hw;
+mod init_stage;
 mod io;
 mod memory_management;
 mod multiboot2;
 mod panic_handler;
 mod power;
 mod process;
+mod random;
 mod sync;
 mod testing;
+mod tracepoints;
 mod utils;
 
 use alloc::vec::Vec;
+use boot_time::BootStep;
 use cpu::{
     gdt,
     interrupts::{self, apic},
 };
 use executable::elf::Elf;
 use increasing_heap_allocator::HeapStats;
+use init_stage::InitStage;
 use io::console;
 use kernel_user_link::{
     file::{BlockingMode, OpenOptions},
@@ -56,12 +64,13 @@ use kernel_user_link::{
 use memory_management::virtual_memory_mapper;
 use multiboot2::MultiBoot2Info;
 use process::scheduler;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
     devices::clock,
     memory_management::{
         kernel_heap_allocator::ALLOCATOR,
+        kernel_stack, memmap,
         memory_layout::{self, MemSize, KERNEL_HEAP_SIZE, PAGE_4K},
         physical_page_allocator, virtual_space,
     },
@@ -99,7 +108,10 @@ fn finish_boot() {
         allocated as f64 / KERNEL_HEAP_SIZE as f64 * 100.
     );
     virtual_space::debug_blocks();
+    boot_time::report();
     info!("");
+    // give the screen back to the console
+    graphics::boot_logo::finish();
 }
 
 fn load_init_process() {
@@ -110,6 +122,7 @@ fn load_init_process() {
         &elf,
         &mut init_file,
         Vec::new(),
+        Vec::new(),
         fs::Directory::open("/").expect("No root"),
     )
     .expect("Could not allocate process for `init`");
@@ -125,9 +138,9 @@ fn load_init_process() {
     .expect("Could not find `/devices/console`");
     // mark it as `terminal`
     console.set_terminal(true);
-    process.attach_fs_node_to_fd(FD_STDIN, console.clone_inherit());
-    process.attach_fs_node_to_fd(FD_STDOUT, console.clone_inherit());
-    process.attach_fs_node_to_fd(FD_STDERR, console);
+    process.attach_handle_to_fd(FD_STDIN, console.clone_inherit());
+    process.attach_handle_to_fd(FD_STDOUT, console.clone_inherit());
+    process.attach_handle_to_fd(FD_STDERR, console);
 
     info!("Added `init` process pid={}", process.id());
     scheduler::push_process(process);
@@ -139,6 +152,11 @@ fn load_init_process() {
 /// `multiboot_info` is essentially `'static`, since it won't ever be removed from the memory
 /// since we don't exit `main` at all.
 pub extern "C" fn kernel_main(multiboot_info: &'static MultiBoot2Info) -> ! {
+    boot_time::mark(BootStep::Entry);
+    // report faults to the serial port until the console and the real handlers are ready
+    interrupts::init_early_exceptions();
+    // before we go deeper into the boot stack
+    kernel_stack::init_boot_stack();
     // uart setup require `cmdline`
     cmdline::init(multiboot_info);
     // init console first, so if we panicked, we can still see the output
@@ -146,37 +164,76 @@ pub extern "C" fn kernel_main(multiboot_info: &'static MultiBoot2Info) -> ! {
     console::tracing::init();
     cmdline::print_cmdline_parse(multiboot_info);
     info!("{}", multiboot_info);
+    boot_time::mark(BootStep::EarlyConsole);
+    memmap::init(multiboot_info);
     // must be called before any pages can be allocated
-    physical_page_allocator::init(multiboot_info);
+    physical_page_allocator::init();
+    boot_time::mark(BootStep::PhysicalAllocator);
     // must be called next, before GDT, and this must be called before any heap allocations
     virtual_memory_mapper::init_kernel_vm();
+    init_stage::reached(InitStage::Memory);
+    boot_time::mark(BootStep::VirtualMemory);
     // require heap allocation
     console::tracing::move_to_dynamic_buffer();
     // must be called before interrupts
     gdt::init_kernel_gdt();
     interrupts::init_interrupts();
+    // must be after interrupts, as the self-test relies on the page fault handler
+    cpu::user_access::init();
+    // wait for GDB as early as possible, if enabled
+    gdb_stub::init();
+    init_stage::reached(InitStage::Interrupts);
+    boot_time::mark(BootStep::Interrupts);
     // mount devices map before initializing them
     devices::init_devices_mapping();
     let bios_tables = acpi::init_acpi_tables(multiboot_info);
     info!("BIOS tables: {}", bios_tables);
+    boot_time::mark(BootStep::AcpiTables);
     apic::init(bios_tables);
     // must be done after APIC is initialized
+    cpu::tlb::init();
     acpi::init();
+    init_stage::reached(InitStage::Apic);
+    boot_time::mark(BootStep::Apic);
     clock::init(bios_tables);
+    init_stage::reached(InitStage::Clocks);
+    boot_time::mark(BootStep::Clocks);
+    // require the clocks for seeding
+    random::init();
+    // require the clocks to convert the timestamps
+    tracepoints::init();
 
     // APIC timer interrupt rely on the clock, so it must be initialized after the clock
     // and interrupts should be disabled until
     unsafe { cpu::set_interrupts() };
     devices::init_legacy_devices();
     graphics::vga::init(multiboot_info.framebuffer());
+    if cfg!(feature = "graphics") {
+        graphics::boot_logo::init(bios_tables);
+    }
+    // the ACPI tables are copied, and the `BGRT` image is drawn, we don't need them anymore
+    memmap::reclaim_acpi_tables();
     console::init_late_device(multiboot_info.framebuffer());
+    boot_time::mark(BootStep::LegacyDevices);
     devices::probe_pci_devices();
-    fs::create_disk_mapping(0).expect("Could not load filesystem");
+    boot_time::mark(BootStep::PciDevices);
+    init_stage::reached(InitStage::Devices);
+    fs::initrd::init(multiboot_info);
+    if let Err(err) = fs::create_disk_mapping(0) {
+        // without a disk, we can still boot from the initrd
+        if !fs::initrd::mount_as_root() {
+            panic!("Could not load filesystem: {err:?}");
+        }
+        warn!("Could not load disk filesystem: {err:?}, using the initrd as root");
+    }
+    process::procfs::init_procfs_mapping();
+    init_stage::reached(InitStage::Filesystem);
+    boot_time::mark(BootStep::DiskMount);
+    load_init_process();
+    boot_time::mark(BootStep::InitSpawn);
     finish_boot();
     // -- BOOT FINISHED --
 
-    load_init_process();
-
     // this will return on shutdown, the sequence is initiated by `power::start_shutdown`
     scheduler::schedule();
     // continue the shutdown process
@@ -188,9 +245,12 @@ pub extern "C" fn kernel_main(multiboot_info: &'static MultiBoot2Info) -> ! {
 #[cfg(test)]
 pub extern "C" fn kernel_main(multiboot_info: &MultiBoot2Info) -> ! {
     // perform necessary initialization, then call the test
+    interrupts::init_early_exceptions();
     console::early_init();
-    physical_page_allocator::init(multiboot_info);
+    memmap::init(multiboot_info);
+    physical_page_allocator::init();
     virtual_memory_mapper::init_kernel_vm();
+    init_stage::reached(InitStage::Memory);
 
     test_main();
 
@@ -80,6 +80,7 @@ impl LockedKernelHeapAllocator {
     }
 }
 
+// Not using `lock_timeout` here, the timeout panic formats a message, which may allocate
 unsafe impl GlobalAlloc for LockedKernelHeapAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         self.inner
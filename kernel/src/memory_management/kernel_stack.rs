@@ -0,0 +1,215 @@
+//! Kernel stack canaries and usage watermarks
+//!
+//! The lowest word of each kernel stack (right above its guard page) holds a canary, which is checked on every
+//! context switch and syscall return. A function with a large stack frame can skip over the guard page
+//! without touching it, the canary catches these before the corrupted memory below the stack is used.
+//!
+//! The rest of the stack is filled with a pattern when it's created, the deepest word that doesn't hold the
+//! pattern anymore is the most the stack was ever used, this is reported in `/devices/kstack`, so deep paths
+//! (i.e. AML parsing or filesystem traversal) can be seen getting close to the end of the stack.
+
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use alloc::{format, string::String, sync::Arc};
+use tracing::warn;
+
+use crate::{cpu, devices::Device, fs::FileSystemError};
+
+use super::{
+    memory_layout::{physical2virtual, KernelStack, PAGE_4K},
+    virtual_memory_mapper::VirtualMemoryMapper,
+};
+
+const STACK_CANARY: u64 = 0x5AFE_57AC_C0DE_CA11;
+const STACK_FILL: u64 = 0x5757_5757_5757_5757;
+const WORD_SIZE: usize = core::mem::size_of::<u64>();
+
+/// The boot stack is filled after we are already running on it
+static BOOT_STACK_READY: AtomicBool = AtomicBool::new(false);
+/// The interrupt stacks are filled when they are mapped in the `GDT` setup
+static INTERRUPT_STACKS_READY: AtomicBool = AtomicBool::new(false);
+/// The most any process kernel stack was used, in bytes, recorded when processes exit
+static PROCESS_KERNEL_STACK_MAX_USAGE: AtomicUsize = AtomicUsize::new(0);
+
+/// Fill `range` with the pattern, and put the canary at the bottom
+///
+/// # Safety
+/// `range` must be mapped, writable and not used by anything
+unsafe fn fill(range: Range<usize>) {
+    let words = core::slice::from_raw_parts_mut(range.start as *mut u64, range.len() / WORD_SIZE);
+    words.fill(STACK_FILL);
+    words[0] = STACK_CANARY;
+}
+
+/// Fill the part of the boot stack below us, must be called early, as we are running on it
+pub fn init_boot_stack() {
+    let range = KernelStack::Boot.range();
+    let rsp: usize;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp) };
+    // leave a page for the calls we make from here (the `fill` itself), so we don't override them
+    let end = rsp - PAGE_4K;
+    assert!(range.contains(&end));
+
+    // SAFETY: this is below our stack pointer (and the page for our calls), so its not used
+    unsafe { fill(range.start..end) };
+    BOOT_STACK_READY.store(true, Ordering::Release);
+}
+
+/// Fill the interrupt stacks, must be called right after they are mapped and before they are used
+pub fn init_interrupt_stacks() {
+    for stack in KernelStack::all().filter(|s| matches!(s, KernelStack::Interrupt(_))) {
+        // SAFETY: the stacks are mapped and not used yet
+        unsafe { fill(stack.range()) };
+    }
+    INTERRUPT_STACKS_READY.store(true, Ordering::Release);
+}
+
+/// Fill the kernel stack of a new process in `vm`, which is not the current vm, so its filled
+/// through the kernel mapping of the physical pages
+pub fn init_process_kernel_stack(vm: &mut VirtualMemoryMapper) {
+    for page in KernelStack::ProcessKernel.range().step_by(PAGE_4K) {
+        let physical = vm
+            .virtual_to_physical(page)
+            .expect("process kernel stack must be mapped");
+        let page = physical2virtual(physical);
+        // SAFETY: the page is allocated for this stack, and the process is not running yet
+        unsafe { fill(page..page + PAGE_4K) };
+    }
+    let bottom = vm
+        .virtual_to_physical(KernelStack::ProcessKernel.range().start)
+        .unwrap();
+    // SAFETY: same as above, the stack must only have one canary, at the bottom
+    unsafe { (physical2virtual(bottom) as *mut u64).write(STACK_CANARY) };
+}
+
+/// The stacks that can be accessed now, the process kernel stack only when a process is running
+fn ready_stacks() -> impl Iterator<Item = KernelStack> {
+    let boot = BOOT_STACK_READY.load(Ordering::Acquire);
+    let interrupts = INTERRUPT_STACKS_READY.load(Ordering::Acquire);
+    let process = cpu::cpu().context.is_some();
+
+    KernelStack::all().filter(move |stack| match stack {
+        KernelStack::Boot => boot,
+        KernelStack::ProcessKernel => process,
+        KernelStack::Interrupt(_) => interrupts,
+    })
+}
+
+/// Check the canary of the kernel stacks, panics if any was overwritten, as the memory below
+/// it (another stack or kernel data) may be corrupted as well
+pub fn check_canaries() {
+    for stack in ready_stacks() {
+        // SAFETY: the stack is mapped
+        let canary = unsafe { (stack.range().start as *const u64).read_volatile() };
+        if canary != STACK_CANARY {
+            panic!("Kernel stack overflow: {stack} stack canary overwritten ({canary:#x})");
+        }
+    }
+}
+
+/// The number of bytes of `stack` that were used at some point, the stack must be mapped
+fn usage(stack: KernelStack) -> usize {
+    let range = stack.range();
+    // SAFETY: the stack is mapped, and we stop at the first word that was used
+    let read = |addr: usize| unsafe { (addr as *const u64).read_volatile() };
+
+    // the canary is the first word, if its overwritten, the whole stack was used
+    if read(range.start) != STACK_CANARY {
+        return range.len();
+    }
+    let unused_words = 1 + range
+        .clone()
+        .step_by(WORD_SIZE)
+        .skip(1)
+        .take_while(|&addr| read(addr) == STACK_FILL)
+        .count();
+    range.len() - unused_words * WORD_SIZE
+}
+
+/// Record the usage of the current process kernel stack, called before the process exits
+pub fn record_process_kernel_stack_usage() {
+    let stack = KernelStack::ProcessKernel;
+    let used = usage(stack);
+    let previous = PROCESS_KERNEL_STACK_MAX_USAGE.fetch_max(used, Ordering::Relaxed);
+
+    // only warn on new records, otherwise every process exit will warn
+    let size = stack.range().len();
+    if used > previous && used > size / 4 * 3 {
+        warn!("{stack} stack usage is getting close to its size: {used}/{size} bytes");
+    }
+}
+
+/// The usage of all the ready stacks, and the most used by any process kernel stack
+fn report() -> String {
+    let mut content = String::new();
+    for stack in ready_stacks().filter(|&s| s != KernelStack::ProcessKernel) {
+        content += &format!(
+            "{stack}:\t{} / {} bytes\n",
+            usage(stack),
+            stack.range().len()
+        );
+    }
+
+    if cpu::cpu().context.is_some() {
+        record_process_kernel_stack_usage();
+    }
+    content += &format!(
+        "{}:\t{} / {} bytes\n",
+        KernelStack::ProcessKernel,
+        PROCESS_KERNEL_STACK_MAX_USAGE.load(Ordering::Relaxed),
+        KernelStack::ProcessKernel.range().len()
+    );
+    content
+}
+
+/// Kernel stacks usage device
+///
+/// Reading it gives the most used bytes of each kernel stack, for the process kernel stacks, its the most used
+/// by any process that exited (or by the reader).
+///
+/// Usage: `cat /devices/kstack`.
+#[derive(Debug)]
+pub struct KernelStackDevice {
+    content: Option<String>,
+}
+
+impl KernelStackDevice {
+    pub const fn new() -> Self {
+        Self { content: None }
+    }
+}
+
+impl Device for KernelStackDevice {
+    fn name(&self) -> &str {
+        "kstack"
+    }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        let content = self
+            .content
+            .as_ref()
+            .ok_or(FileSystemError::ReadNotSupported)?
+            .as_bytes();
+        if offset >= content.len() as u64 {
+            return Ok(0);
+        }
+        let remaining = &content[offset as usize..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        Ok(count as u64)
+    }
+
+    // generate the content on open, so reading it in parts is consistent
+    fn try_create(&self) -> Option<Result<Arc<dyn Device>, FileSystemError>> {
+        Some(Ok(Arc::new(KernelStackDevice {
+            content: Some(report()),
+        })))
+    }
+}
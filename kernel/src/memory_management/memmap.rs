@@ -0,0 +1,305 @@
+//! Physical memory map
+//!
+//! Built once on boot from the multiboot2 memory map, then the ranges we know about (kernel, multiboot info,
+//! bootloader modules, framebuffer) are marked on top of it, so that the rest of the kernel
+//! (i.e. [`physical_page_allocator`](super::physical_page_allocator)) only needs to look at [`RegionKind::Usable`]
+//! regions to know which memory is free.
+//!
+//! This is created before the heap is available, so the regions are stored in a fixed size array.
+
+use core::fmt;
+
+use tracing::{info, warn};
+
+use crate::{
+    multiboot2::{MemoryMapType, MultiBoot2Info},
+    sync::{once::OnceLock, spin::mutex::Mutex},
+    testing,
+};
+
+use super::{
+    memory_layout::{
+        align_up, kernel_elf_end, virtual2physical, MemSize, EXTENDED_OFFSET, KERNEL_LINK, PAGE_4K,
+    },
+    physical_page_allocator,
+};
+
+/// Maximum number of regions, firmwares generally provide less than 20
+const MAX_REGIONS: usize = 64;
+
+static MEMORY_MAP: OnceLock<Mutex<MemoryMap>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Free memory, can be used by the physical page allocator
+    Usable,
+    /// Used by the BIOS and the bootloader (i.e. the low 1MB)
+    Reserved,
+    /// The kernel elf and the multiboot info
+    Kernel,
+    /// Modules loaded by the bootloader (i.e. initrd)
+    Module,
+    /// ACPI tables, can be used after we finish parsing them (see [`reclaim_acpi_tables`])
+    AcpiReclaimable,
+    /// ACPI non-volatile storage, must be kept as is
+    AcpiNvs,
+    /// Memory mapped devices (i.e. framebuffer)
+    Mmio,
+    BadMemory,
+}
+
+impl From<MemoryMapType> for RegionKind {
+    fn from(ty: MemoryMapType) -> Self {
+        match ty {
+            MemoryMapType::Available => RegionKind::Usable,
+            MemoryMapType::ACPIReclaimable => RegionKind::AcpiReclaimable,
+            MemoryMapType::ACPINonVolatile => RegionKind::AcpiNvs,
+            MemoryMapType::BadMemory => RegionKind::BadMemory,
+            MemoryMapType::Reserved | MemoryMapType::Undefined(_) => RegionKind::Reserved,
+        }
+    }
+}
+
+/// A physical memory range `[start, end)`
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub kind: RegionKind,
+}
+
+impl Region {
+    const EMPTY: Self = Self {
+        start: 0,
+        end: 0,
+        kind: RegionKind::Reserved,
+    };
+
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "range={:016x}..{:016x}, len={:4}  {:?}",
+            self.start,
+            self.end,
+            MemSize(self.size()),
+            self.kind
+        )
+    }
+}
+
+pub struct MemoryMap {
+    /// Sorted by `start`, and never overlapping
+    regions: [Region; MAX_REGIONS],
+    len: usize,
+}
+
+impl MemoryMap {
+    const fn empty() -> Self {
+        Self {
+            regions: [Region::EMPTY; MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    fn from_multiboot(multiboot_info: &MultiBoot2Info) -> Self {
+        let mut map = Self::empty();
+
+        let memory_maps = multiboot_info
+            .memory_maps()
+            .expect("No memory map provided by the bootloader");
+        for memory in memory_maps {
+            if memory.length == 0 {
+                continue;
+            }
+            map.mark(
+                memory.base_addr,
+                memory.base_addr + memory.length,
+                memory.mem_type.into(),
+            );
+        }
+
+        // skip all the memory before the kernel, it could be used by the bootloader
+        // its generally not a lot, just 1 MB, so its fine to skip it
+        map.mark_existing(0, EXTENDED_OFFSET as u64, RegionKind::Reserved);
+
+        let kernel_start = virtual2physical(KERNEL_LINK);
+        let kernel_end = virtual2physical(align_up(kernel_elf_end(), PAGE_4K));
+        map.mark_existing(kernel_start, kernel_end, RegionKind::Kernel);
+
+        // `multiboot_info` is `'static`, grub generally puts it after the kernel
+        let multiboot_start = virtual2physical(multiboot_info as *const _ as usize);
+        let multiboot_end = align_up(
+            virtual2physical(multiboot_info.end_address() as usize),
+            PAGE_4K,
+        );
+        map.mark_existing(multiboot_start, multiboot_end, RegionKind::Kernel);
+
+        for module in multiboot_info.modules() {
+            map.mark_existing(
+                module.start,
+                align_up(module.end, PAGE_4K),
+                RegionKind::Module,
+            );
+        }
+
+        // the framebuffer is generally outside the memory map, so add it as well
+        if let Some(framebuffer) = multiboot_info.framebuffer() {
+            let size = framebuffer.pitch as u64 * framebuffer.height as u64;
+            map.mark(
+                framebuffer.addr,
+                align_up(framebuffer.addr + size, PAGE_4K),
+                RegionKind::Mmio,
+            );
+        }
+
+        map
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions[..self.len]
+    }
+
+    fn push(&mut self, region: Region) {
+        if self.len == MAX_REGIONS {
+            warn!("memmap: too many regions, dropping {region}");
+            return;
+        }
+        self.regions[self.len] = region;
+        self.len += 1;
+    }
+
+    /// Change the kind of the parts of `[start, end)` that are already in the map, splitting regions as needed
+    fn mark_existing(&mut self, start: u64, end: u64, kind: RegionKind) {
+        let old = core::mem::replace(self, Self::empty());
+
+        for region in old.regions() {
+            if region.end <= start || region.start >= end {
+                self.push(*region);
+                continue;
+            }
+            if region.start < start {
+                self.push(Region {
+                    end: start,
+                    ..*region
+                });
+            }
+            self.push(Region {
+                start: region.start.max(start),
+                end: region.end.min(end),
+                kind,
+            });
+            if region.end > end {
+                self.push(Region {
+                    start: end,
+                    ..*region
+                });
+            }
+        }
+    }
+
+    /// Same as [`Self::mark_existing`], but also adds the parts of `[start, end)` that are not in the map
+    fn mark(&mut self, start: u64, end: u64, kind: RegionKind) {
+        self.mark_existing(start, end, kind);
+
+        // fill the holes, the regions are sorted, and the new ones are pushed after `len`
+        let existing = self.regions;
+        let mut current = start;
+        for region in &existing[..self.len] {
+            if region.end <= current {
+                continue;
+            }
+            if region.start >= end {
+                break;
+            }
+            if region.start > current {
+                self.push(Region {
+                    start: current,
+                    end: region.start,
+                    kind,
+                });
+            }
+            current = region.end;
+        }
+        if current < end {
+            self.push(Region {
+                start: current,
+                end,
+                kind,
+            });
+        }
+
+        self.regions[..self.len].sort_unstable_by_key(|region| region.start);
+    }
+}
+
+/// Build the memory map, must be called before the physical page allocator is initialized
+pub fn init(multiboot_info: &MultiBoot2Info) {
+    MEMORY_MAP
+        .set(Mutex::new(MemoryMap::from_multiboot(multiboot_info)))
+        .unwrap_or_else(|_| panic!("Memory map already initialized"));
+}
+
+/// Call `f` with the memory map
+pub fn with_memory_map<R>(f: impl FnOnce(&MemoryMap) -> R) -> R {
+    f(&MEMORY_MAP.get().lock())
+}
+
+/// Give the memory of the ACPI tables to the physical page allocator,
+/// must be called after all the tables are parsed and copied out
+pub fn reclaim_acpi_tables() {
+    let mut map = MEMORY_MAP.get().lock();
+    let len = map.len;
+    let mut reclaimed = 0;
+    for region in map.regions[..len]
+        .iter_mut()
+        .filter(|region| region.kind == RegionKind::AcpiReclaimable)
+    {
+        region.kind = RegionKind::Usable;
+        reclaimed += physical_page_allocator::add_range(region.start, region.end);
+    }
+    info!(
+        "Reclaimed {} from ACPI tables",
+        MemSize(reclaimed * PAGE_4K)
+    );
+}
+
+/// Print the memory map
+pub fn display() {
+    info!("Physical memory map:");
+    with_memory_map(|map| {
+        for region in map.regions() {
+            info!("  {region}");
+        }
+    });
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_mark_regions() {
+    let mut map = MemoryMap::empty();
+    map.mark(0x1000, 0x9000, RegionKind::Usable);
+    map.mark(0xA000, 0xC000, RegionKind::Reserved);
+    // split the usable region
+    map.mark_existing(0x2000, 0x3000, RegionKind::Kernel);
+    // covers the end of the usable region, the hole, and part of the reserved region
+    map.mark(0x8000, 0xB000, RegionKind::Mmio);
+
+    let expected = [
+        (0x1000, 0x2000, RegionKind::Usable),
+        (0x2000, 0x3000, RegionKind::Kernel),
+        (0x3000, 0x8000, RegionKind::Usable),
+        (0x8000, 0x9000, RegionKind::Mmio),
+        (0x9000, 0xA000, RegionKind::Mmio),
+        (0xA000, 0xB000, RegionKind::Mmio),
+        (0xB000, 0xC000, RegionKind::Reserved),
+    ];
+    assert_eq!(map.regions().len(), expected.len());
+    for (region, (start, end, kind)) in map.regions().iter().zip(expected) {
+        assert_eq!((region.start, region.end, region.kind), (start, end, kind));
+    }
+}
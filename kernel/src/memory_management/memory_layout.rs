@@ -1,8 +1,8 @@
-use core::fmt;
+use core::{fmt, ops::Range};
 
 use tracing::info;
 
-use super::virtual_memory_mapper;
+use super::{memmap, virtual_memory_mapper};
 
 extern "C" {
     static begin: usize;
@@ -11,6 +11,7 @@ extern "C" {
     static rodata_end: usize;
     static data_end: usize;
     static stack_guard_page: usize;
+    static stack_end: usize;
     static __eh_frame: usize;
 }
 
@@ -51,6 +52,7 @@ pub const KERNEL_EXTRA_MEMORY_SIZE: usize = KERNEL_LAST_POSSIBLE_ADDR - KERNEL_E
 // Kernel Data specific to each process (will be mapped differently for each process)
 pub const KERNEL_PROCESS_VIRTUAL_ADDRESS_START: usize =
     virtual_memory_mapper::KERNEL_PROCESS_VIRTUAL_ADDRESS_START;
+// unmapped page below the process kernel stack, accessing it means that the stack overflowed
 pub const PROCESS_KERNEL_STACK_GUARD: usize = PAGE_4K;
 // process specific kernel stack, this will be where the process is running while in the kernel
 // the process can be interrupted while in the kernel, so we want to save it into a specific stack
@@ -92,6 +94,70 @@ pub fn stack_guard_page_ptr() -> usize {
     (unsafe { &stack_guard_page } as *const usize as usize)
 }
 
+pub fn boot_stack_end() -> usize {
+    (unsafe { &stack_end } as *const usize as usize)
+}
+
+/// A kernel stack that overflowed into its guard page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelStack {
+    /// The stack used on boot, and by the kernel when not running a process
+    Boot,
+    /// The per-process kernel stack, used on transitions from user to kernel
+    ProcessKernel,
+    /// One of the interrupt stacks, with its index in the `TSS`
+    Interrupt(usize),
+}
+
+impl KernelStack {
+    /// All the kernel stacks
+    pub fn all() -> impl Iterator<Item = Self> {
+        [Self::Boot, Self::ProcessKernel]
+            .into_iter()
+            .chain((0..INTR_STACK_COUNT).map(Self::Interrupt))
+    }
+
+    /// The usable range of the stack, right above its guard page
+    pub fn range(&self) -> Range<usize> {
+        match self {
+            Self::Boot => stack_guard_page_ptr() + PAGE_4K..boot_stack_end(),
+            Self::ProcessKernel => PROCESS_KERNEL_STACK_BASE..PROCESS_KERNEL_STACK_END,
+            Self::Interrupt(index) => {
+                let start = INTR_STACK_BASE + index * INTR_STACK_ENTRY_SIZE + INTR_STACK_EMPTY_SIZE;
+                start..start + INTR_STACK_SIZE
+            }
+        }
+    }
+}
+
+impl fmt::Display for KernelStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Boot => write!(f, "Boot"),
+            Self::ProcessKernel => write!(f, "ProcessKernel"),
+            Self::Interrupt(index) => write!(f, "Interrupt{index}"),
+        }
+    }
+}
+
+/// Returns the stack that `addr` is a guard page of, if any
+pub fn stack_guard_containing(addr: usize) -> Option<KernelStack> {
+    let boot_guard = stack_guard_page_ptr();
+    if (boot_guard..boot_guard + PAGE_4K).contains(&addr) {
+        return Some(KernelStack::Boot);
+    }
+    if (KERNEL_PROCESS_VIRTUAL_ADDRESS_START..PROCESS_KERNEL_STACK_BASE).contains(&addr) {
+        return Some(KernelStack::ProcessKernel);
+    }
+    if (INTR_STACK_BASE..INTR_STACK_BASE + INTR_STACK_TOTAL_SIZE).contains(&addr) {
+        let offset = addr - INTR_STACK_BASE;
+        if offset % INTR_STACK_ENTRY_SIZE < INTR_STACK_EMPTY_SIZE {
+            return Some(KernelStack::Interrupt(offset / INTR_STACK_ENTRY_SIZE));
+        }
+    }
+    None
+}
+
 pub fn eh_frame_start() -> usize {
     (unsafe { &__eh_frame } as *const usize as usize)
 }
@@ -259,6 +325,8 @@ pub fn display_kernel_map() {
         "whole kernel size: {}",
         MemSize(usize::MAX - KERNEL_BASE + 1)
     );
+
+    memmap::display();
 }
 
 #[repr(transparent)]
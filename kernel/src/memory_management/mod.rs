@@ -1,5 +1,8 @@
 pub mod kernel_heap_allocator;
+pub mod kernel_stack;
+pub mod memmap;
 pub mod memory_layout;
 pub mod physical_page_allocator;
+pub mod shared_pages;
 pub mod virtual_memory_mapper;
 pub mod virtual_space;
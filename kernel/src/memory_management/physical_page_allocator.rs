@@ -2,13 +2,12 @@ use core::ptr::NonNull;
 
 use tracing::info;
 
-use super::memory_layout::{align_down, align_up, is_aligned, PAGE_4K};
+use super::{
+    memmap::{self, RegionKind},
+    memory_layout::{align_down, align_up, is_aligned, PAGE_2M, PAGE_4K},
+};
 use crate::{
-    memory_management::memory_layout::{
-        kernel_elf_end, physical2virtual, virtual2physical, EXTENDED_OFFSET, KERNEL_END,
-        KERNEL_LINK,
-    },
-    multiboot2::{MemoryMapType, MultiBoot2Info},
+    memory_management::memory_layout::{physical2virtual, virtual2physical, KERNEL_END},
     sync::{once::OnceLock, spin::mutex::Mutex},
     testing,
 };
@@ -19,12 +18,24 @@ struct FreePage {
 
 static ALLOCATOR: OnceLock<Mutex<PhysicalPageAllocator>> = OnceLock::new();
 
-pub fn init(multiboot_info: &MultiBoot2Info) {
+/// Number of 4K pages in a 2MB page
+const PAGES_PER_2M: usize = PAGE_2M / PAGE_4K;
+
+/// Initialize the allocator with the usable regions of the [`memmap`], which must be initialized before this
+pub fn init() {
     if ALLOCATOR.try_get().is_some() {
         panic!("PhysicalPageAllocator already initialized");
     }
 
-    ALLOCATOR.get_or_init(|| Mutex::new(PhysicalPageAllocator::new(multiboot_info)));
+    ALLOCATOR.get_or_init(|| Mutex::new(PhysicalPageAllocator::new()));
+}
+
+/// Add the physical range `[start, end)` to the allocator, used to give back memory we don't need
+/// anymore (i.e. ACPI tables), returns the number of 4K pages added
+///
+/// The range must not be used by anything else
+pub fn add_range(start: u64, end: u64) -> usize {
+    ALLOCATOR.get().lock().add_range(start, end)
 }
 
 /// SAFETY: this must be called after `init`
@@ -58,6 +69,40 @@ pub unsafe fn free(page: *mut u8) {
     r.unwrap_or_else(|| panic!("Page {page:p} not valid"))
 }
 
+/// SAFETY: this must be called after `init`
+///
+/// Allocates a 2MB page of memory, the returned address is guaranteed to be aligned to 2MB, and is mapped into virtual space.
+/// Returns `None` if there are no free 2MB pages, in that case the caller should fallback to 4K pages
+pub unsafe fn alloc_huge() -> Option<*mut u8> {
+    ALLOCATOR.get().lock().alloc_huge()
+}
+
+/// SAFETY: this must be called after `init`
+///
+/// Same as [`alloc_huge`], but the page is zeroed
+pub unsafe fn alloc_huge_zeroed() -> Option<*mut u8> {
+    let page = alloc_huge()?;
+    page.write_bytes(0, PAGE_2M);
+    Some(page)
+}
+
+/// SAFETY:
+/// this must be called after `init`
+/// this must never be called with same page twice, the allocator doesn't check itself
+///
+/// A 2MB page can also be freed as 4K pages with [`free`], i.e. after splitting it,
+/// but it won't be available as a 2MB page anymore.
+///
+/// panics if:
+/// - `page` is not a valid page
+/// - `page` is not in the range of the allocator
+/// - `page` is not aligned to 2MB
+pub unsafe fn free_huge(page: *mut u8) {
+    let r = { ALLOCATOR.get().lock().free_huge(page) };
+    r.unwrap_or_else(|| panic!("Huge page {page:p} not valid"))
+}
+
+/// Returns `(free, used)` in 4K pages
 pub fn stats() -> (usize, usize) {
     let allocator = ALLOCATOR.get().lock();
     (allocator.free_count, allocator.used_count)
@@ -65,9 +110,12 @@ pub fn stats() -> (usize, usize) {
 
 struct PhysicalPageAllocator {
     low_mem_free_list_head: Option<NonNull<FreePage>>,
+    /// Free 2MB aligned pages, split into 4K pages when we run out of 4K pages
+    huge_free_list_head: Option<NonNull<FreePage>>,
     #[allow(dead_code)]
     // TODO: handle more memory
     high_mem_start: usize,
+    /// The lowest and highest addresses we got, there could be holes inside
     start: usize,
     end: usize,
     free_count: usize,
@@ -77,35 +125,10 @@ struct PhysicalPageAllocator {
 unsafe impl Send for PhysicalPageAllocator {}
 
 impl PhysicalPageAllocator {
-    fn new(multiboot_info: &MultiBoot2Info) -> Self {
-        const PHYSICAL_KERNEL_START: u64 = virtual2physical(KERNEL_LINK);
-        // get the end of the kernel, align, and add 5 PAGES of alignment as well
-        // because the multiboot info might be stored there by grub
-        let mut physical_kernel_end = virtual2physical(align_up(kernel_elf_end(), PAGE_4K));
-        let multiboot_end = align_up(
-            virtual2physical(multiboot_info.end_address() as usize),
-            PAGE_4K,
-        );
-        info!("multiboot end: {multiboot_end:x}",);
-        info!(
-            "physical_kernel_start: {:p}",
-            PHYSICAL_KERNEL_START as *mut u8
-        );
-        info!("physical_kernel_end: {:p}", physical_kernel_end as *mut u8);
-
-        // if the multiboot info is after the kernel, make sure we are not allocating it
-        if multiboot_end > physical_kernel_end {
-            // if its after the kernel by a lot, then panic, so we can handle it, we don't want
-            // to make this more complex if we don't need to
-            assert!(
-                multiboot_end - physical_kernel_end < PAGE_4K as u64 * 5,
-                "Multiboot is after the kernel by a lot",
-            );
-            physical_kernel_end = multiboot_end;
-        }
-
+    fn new() -> Self {
         let mut s = Self {
             low_mem_free_list_head: None,
+            huge_free_list_head: None,
             high_mem_start: 0,
             start: 0,
             end: 0,
@@ -113,51 +136,45 @@ impl PhysicalPageAllocator {
             used_count: 0,
         };
 
-        for memory in multiboot_info.memory_maps().unwrap() {
-            // skip all the memory before the kernel, it could be used by the bootloader
-            // its generally not a lot, just 1 MB, so its fine to skip it
-            if (memory.base_addr + memory.length) < EXTENDED_OFFSET as u64 {
-                continue;
-            }
-            if memory.mem_type != MemoryMapType::Available {
-                continue;
+        memmap::with_memory_map(|map| {
+            for region in map.regions() {
+                if region.kind != RegionKind::Usable {
+                    continue;
+                }
+                s.add_range(region.start, region.end);
+                if s.high_mem_start != 0 {
+                    break;
+                }
             }
-            // if this is the range where the kernel is mapped, skip the pages the kernel use
-            // and start after that
-            let start_physical;
-            let end_physical;
-            if memory.base_addr <= PHYSICAL_KERNEL_START
-                && (memory.base_addr + memory.length) >= physical_kernel_end
-            {
-                start_physical = physical_kernel_end;
-                end_physical = align_down(memory.base_addr + memory.length, PAGE_4K);
-                s.start = physical2virtual(physical_kernel_end);
-            } else {
-                assert!(memory.base_addr >= physical_kernel_end);
+        });
+        s
+    }
 
-                start_physical = align_up(memory.base_addr, PAGE_4K);
-                end_physical = align_down(memory.base_addr + memory.length, PAGE_4K);
-            }
-            let mut high_mem_start = 0;
-            let end_virtual = if end_physical >= virtual2physical(KERNEL_END) {
-                high_mem_start = KERNEL_END;
-                KERNEL_END
-            } else {
-                physical2virtual(end_physical)
-            };
-            let start_virtual = physical2virtual(start_physical);
+    /// Add the physical range `[start, end)` to the free pages, only the part that is mapped
+    /// in the kernel (below `KERNEL_END`) is used, returns the number of 4K pages added
+    fn add_range(&mut self, start: u64, end: u64) -> usize {
+        const KERNEL_END_PHYSICAL: u64 = virtual2physical(KERNEL_END);
 
-            if start_virtual < end_virtual {
-                s.end = end_virtual;
+        let start_physical = align_up(start, PAGE_4K);
+        let end_physical = align_down(end, PAGE_4K);
+        if start_physical >= end_physical || start_physical >= KERNEL_END_PHYSICAL {
+            return 0;
+        }
+        let start_virtual = physical2virtual(start_physical);
+        let end_virtual = if end_physical >= KERNEL_END_PHYSICAL {
+            // TODO: handle more memory
+            self.high_mem_start = KERNEL_END;
+            KERNEL_END
+        } else {
+            physical2virtual(end_physical)
+        };
 
-                s.init_range(start_virtual as _, end_virtual as _);
-                if high_mem_start != 0 {
-                    s.high_mem_start = high_mem_start;
-                    break;
-                }
-            }
+        if self.start == 0 || start_virtual < self.start {
+            self.start = start_virtual;
         }
-        s
+        self.end = self.end.max(end_virtual);
+        self.init_range(start_virtual as _, end_virtual as _);
+        (end_virtual - start_virtual) / PAGE_4K
     }
 
     fn init_range(&mut self, start: *mut u8, end: *mut u8) {
@@ -167,18 +184,44 @@ impl PhysicalPageAllocator {
         assert!(start < end);
         let mut page = start;
         while page < end {
-            unsafe { self.free(page).expect("valid page") };
-            page = unsafe { page.add(PAGE_4K) };
+            if is_aligned(page as usize, PAGE_2M) && (end as usize - page as usize) >= PAGE_2M {
+                unsafe { self.free_huge(page).expect("valid page") };
+                page = unsafe { page.add(PAGE_2M) };
+            } else {
+                unsafe { self.free(page).expect("valid page") };
+                page = unsafe { page.add(PAGE_4K) };
+            }
         }
     }
 
+    fn is_valid_page(&self, page: *mut u8, alignment: usize) -> bool {
+        !page.is_null()
+            && is_aligned(page as usize, alignment)
+            && page < self.end as _
+            && page >= self.start as _
+    }
+
+    /// Move one 2MB page into the 4K free list, returns `false` if there are no 2MB pages
+    unsafe fn split_huge(&mut self) -> bool {
+        let Some(huge_page) = self.alloc_huge() else {
+            return false;
+        };
+        // `alloc_huge` counted it as used, and `free` moves it back page by page
+        // free in order, so that the pages are allocated from the end (same as `init_range`)
+        for i in 0..PAGES_PER_2M {
+            self.free(huge_page.add(i * PAGE_4K)).expect("valid page");
+        }
+        true
+    }
+
     /// SAFETY: this must be called after `init`
     ///
     /// Allocates a 4K page of memory
     unsafe fn alloc(&mut self) -> *mut u8 {
-        let Some(low_mem_free_list_head) = self.low_mem_free_list_head else {
+        if self.low_mem_free_list_head.is_none() && !self.split_huge() {
             panic!("out of memory");
-        };
+        }
+        let low_mem_free_list_head = self.low_mem_free_list_head.expect("free list not empty");
 
         let page = low_mem_free_list_head;
         self.low_mem_free_list_head = page.as_ref().next;
@@ -187,9 +230,25 @@ impl PhysicalPageAllocator {
         // fill with random data to catch dangling pointer bugs
         page.write_bytes(1, PAGE_4K);
         self.used_count += 1;
+        self.free_count -= 1;
         page
     }
 
+    /// SAFETY: this must be called after `init`
+    ///
+    /// Allocates a 2MB page of memory
+    unsafe fn alloc_huge(&mut self) -> Option<*mut u8> {
+        let page = self.huge_free_list_head?;
+        self.huge_free_list_head = page.as_ref().next;
+
+        let page = page.as_ptr() as *mut u8;
+        // only fill the header, filling 2MB is expensive, and most users will zero it anyway
+        page.write_bytes(1, core::mem::size_of::<FreePage>());
+        self.used_count += PAGES_PER_2M;
+        self.free_count -= PAGES_PER_2M;
+        Some(page)
+    }
+
     /// SAFETY:
     /// this must be called after `init`
     /// this must never be called with same page twice, the allocator doesn't check itself
@@ -201,15 +260,10 @@ impl PhysicalPageAllocator {
     /// with `None`, otherwise, `Some(())`
     #[must_use]
     unsafe fn free(&mut self, page: *mut u8) -> Option<()> {
-        let page = page.cast::<FreePage>();
-
-        if page.is_null()
-            || !is_aligned(page as usize, PAGE_4K)
-            || page >= self.end as _
-            || page < self.start as _
-        {
+        if !self.is_valid_page(page, PAGE_4K) {
             return None;
         }
+        let page = page.cast::<FreePage>();
 
         // fill with random data to catch dangling pointer bugs
         page.cast::<u8>().write_bytes(2, PAGE_4K);
@@ -220,6 +274,33 @@ impl PhysicalPageAllocator {
         page.as_mut().next = self.low_mem_free_list_head;
         self.low_mem_free_list_head = Some(page);
         self.free_count += 1;
+        self.used_count = self.used_count.saturating_sub(1);
+        Some(())
+    }
+
+    /// SAFETY:
+    /// this must be called after `init`
+    /// this must never be called with same page twice, the allocator doesn't check itself
+    ///
+    /// fails if:
+    /// - `page` is null
+    /// - `page` (the whole 2MB) is not in the range of the allocator
+    /// - `page` is not aligned to 2MB
+    /// with `None`, otherwise, `Some(())`
+    #[must_use]
+    unsafe fn free_huge(&mut self, page: *mut u8) -> Option<()> {
+        if !self.is_valid_page(page, PAGE_2M) || page as usize + PAGE_2M > self.end {
+            return None;
+        }
+        // only fill the header, see `alloc_huge`
+        page.write_bytes(2, core::mem::size_of::<FreePage>());
+        assert!(self.high_mem_start == 0 || page < self.high_mem_start as _);
+        let mut page = NonNull::new_unchecked(page.cast::<FreePage>());
+
+        page.as_mut().next = self.huge_free_list_head;
+        self.huge_free_list_head = Some(page);
+        self.free_count += PAGES_PER_2M;
+        self.used_count = self.used_count.saturating_sub(PAGES_PER_2M);
         Some(())
     }
 }
@@ -286,3 +367,32 @@ fn test_unaligned_free() {
 
     unsafe { free(addr_inside_page) };
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_huge_alloc() {
+    let (free_before, _) = stats();
+    let Some(page) = (unsafe { alloc_huge_zeroed() }) else {
+        // no 2MB pages available, nothing to test
+        return;
+    };
+
+    assert_eq!(page as usize % PAGE_2M, 0);
+    assert_eq!(stats().0, free_before - PAGES_PER_2M);
+    assert!(unsafe { core::slice::from_raw_parts(page, PAGE_2M) }
+        .iter()
+        .all(|&x| x == 0),);
+
+    unsafe { free_huge(page) };
+    assert_eq!(stats().0, free_before);
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_split_huge_stats() {
+    let stats_before = stats();
+    // splitting only moves pages between the free lists, so the counts must stay the same
+    if !unsafe { ALLOCATOR.get().lock().split_huge() } {
+        // no 2MB pages available, nothing to test
+        return;
+    }
+    assert_eq!(stats(), stats_before);
+}
@@ -0,0 +1,252 @@
+//! Physical pages shared between processes
+//!
+//! Pages loaded from the same file content (same filesystem, inode and offset) can be shared
+//! between processes, for example when running multiple shells, the executable pages are loaded once.
+//!
+//! Shared pages are mapped read-only with [`flags::PTE_SHARED`], and if the original mapping
+//! was writable, they are marked with [`flags::PTE_COW`] as well, on a write, the page fault
+//! handler will create a private copy of the page for the process
+//! (see [`VirtualMemoryMapper::handle_cow_fault`]).
+//!
+//! [`flags::PTE_SHARED`]: super::virtual_memory_mapper::flags::PTE_SHARED
+//! [`flags::PTE_COW`]: super::virtual_memory_mapper::flags::PTE_COW
+//! [`VirtualMemoryMapper::handle_cow_fault`]: super::virtual_memory_mapper::VirtualMemoryMapper::handle_cow_fault
+
+use core::ops::RangeBounds;
+
+use alloc::{
+    collections::{btree_map, BTreeMap},
+    vec::Vec,
+};
+
+use crate::{sync::spin::mutex::Mutex, testing};
+
+use super::{
+    memory_layout::{physical2virtual, virtual2physical, PAGE_4K},
+    physical_page_allocator,
+};
+
+static SHARED_PAGES: Mutex<SharedPages> = Mutex::new(SharedPages::new());
+
+/// Identifies the content of a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SharedPageKey {
+    /// An identifier for the filesystem, never reused, see [`File::content_id`](crate::fs::File::content_id)
+    pub filesystem: u64,
+    pub inode: u64,
+    pub offset: u64,
+}
+
+struct SharedPage {
+    refs: usize,
+    /// `None` if the page is not in the cache anymore, i.e. the file was modified
+    key: Option<SharedPageKey>,
+}
+
+struct SharedPages {
+    /// key -> physical address
+    cache: BTreeMap<SharedPageKey, u64>,
+    /// physical address -> page
+    pages: BTreeMap<u64, SharedPage>,
+}
+
+impl SharedPages {
+    const fn new() -> Self {
+        Self {
+            cache: BTreeMap::new(),
+            pages: BTreeMap::new(),
+        }
+    }
+
+    fn remove_page(&mut self, physical_address: u64) {
+        let page = self
+            .pages
+            .remove(&physical_address)
+            .expect("shared page not found");
+        if let Some(key) = page.key {
+            assert_eq!(self.cache.remove(&key), Some(physical_address));
+        }
+    }
+}
+
+/// Get a physical page with the content of `key`, the page will be reused if its already loaded,
+/// otherwise a new page will be allocated and filled with `fill`.
+///
+/// The returned page is referenced and must be released with [`release`] (done on unmap).
+pub fn get_or_load<E>(
+    key: SharedPageKey,
+    fill: impl FnOnce(&mut [u8]) -> Result<(), E>,
+) -> Result<u64, E> {
+    {
+        let mut shared_pages = SHARED_PAGES.lock();
+        if let Some(&physical_address) = shared_pages.cache.get(&key) {
+            shared_pages
+                .pages
+                .get_mut(&physical_address)
+                .expect("shared page not found")
+                .refs += 1;
+            return Ok(physical_address);
+        }
+    }
+
+    // SAFETY: panics if it can't allocate
+    let page = unsafe { physical_page_allocator::alloc_zeroed() };
+    // SAFETY: we have just allocated this page, and its not used anywhere else
+    let content = unsafe { core::slice::from_raw_parts_mut(page, PAGE_4K) };
+    if let Err(e) = fill(content) {
+        // SAFETY: allocated above and not shared yet
+        unsafe { physical_page_allocator::free(page) };
+        return Err(e);
+    }
+    let physical_address = virtual2physical(page as usize);
+
+    let mut shared_pages = SHARED_PAGES.lock();
+    // if someone else loaded the same page while we were filling it, we just won't cache ours
+    let key = match shared_pages.cache.entry(key) {
+        btree_map::Entry::Occupied(_) => None,
+        btree_map::Entry::Vacant(entry) => {
+            entry.insert(physical_address);
+            Some(key)
+        }
+    };
+    shared_pages
+        .pages
+        .insert(physical_address, SharedPage { refs: 1, key });
+
+    Ok(physical_address)
+}
+
+/// Release a reference to a shared page, the page is freed when the last reference is released
+pub fn release(physical_address: u64) {
+    let mut shared_pages = SHARED_PAGES.lock();
+    let page = shared_pages
+        .pages
+        .get_mut(&physical_address)
+        .expect("shared page not found");
+    page.refs -= 1;
+    if page.refs == 0 {
+        shared_pages.remove_page(physical_address);
+        // SAFETY: this page is not referenced anymore
+        unsafe { physical_page_allocator::free(physical2virtual(physical_address) as _) };
+    }
+}
+
+/// If the caller holds the only reference to this page, the page will stop being shared,
+/// and the caller will own it as a normal allocated page, returns `true` in that case.
+pub fn try_take_exclusive(physical_address: u64) -> bool {
+    let mut shared_pages = SHARED_PAGES.lock();
+    let page = shared_pages
+        .pages
+        .get(&physical_address)
+        .expect("shared page not found");
+    if page.refs != 1 {
+        return false;
+    }
+    shared_pages.remove_page(physical_address);
+    true
+}
+
+/// Returns `(pages, references)`, the number of shared pages and the number of mappings to them
+pub fn stats() -> (usize, usize) {
+    let shared_pages = SHARED_PAGES.lock();
+    let refs = shared_pages.pages.values().map(|page| page.refs).sum();
+    (shared_pages.pages.len(), refs)
+}
+
+/// Stop reusing pages loaded from this file, must be called when the file content changes.
+///
+/// Pages still mapped by processes are kept until they are released.
+pub fn invalidate_file(filesystem: u64, inode: u64) {
+    let start = SharedPageKey {
+        filesystem,
+        inode,
+        offset: 0,
+    };
+    invalidate_range(
+        start..=SharedPageKey {
+            offset: u64::MAX,
+            ..start
+        },
+    );
+}
+
+/// Stop reusing pages loaded from any file in `filesystem`, i.e. when it's unmounted
+pub fn invalidate_filesystem(filesystem: u64) {
+    let start = SharedPageKey {
+        filesystem,
+        inode: 0,
+        offset: 0,
+    };
+    invalidate_range(
+        start..=SharedPageKey {
+            inode: u64::MAX,
+            offset: u64::MAX,
+            ..start
+        },
+    );
+}
+
+/// Stop reusing all the loaded pages, when a disk is written to directly (not through a filesystem),
+/// as we don't know which files were changed
+pub fn invalidate_all() {
+    invalidate_range(..);
+}
+
+fn invalidate_range(range: impl RangeBounds<SharedPageKey>) {
+    let mut shared_pages = SHARED_PAGES.lock();
+    let to_remove = shared_pages
+        .cache
+        .range(range)
+        .map(|(_, &physical_address)| physical_address)
+        .collect::<Vec<_>>();
+    for physical_address in to_remove {
+        if let Some(page) = shared_pages.pages.get_mut(&physical_address) {
+            let key = page.key.take().expect("cached page must have a key");
+            shared_pages.cache.remove(&key);
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_shared_pages_invalidate() {
+    // not a real filesystem, so it doesn't collide with the pages of the test executables
+    const FILESYSTEM: u64 = u64::MAX;
+
+    let key = |inode| SharedPageKey {
+        filesystem: FILESYSTEM,
+        inode,
+        offset: 0,
+    };
+    let load = |inode, value| {
+        get_or_load(key(inode), |content| {
+            content.fill(value);
+            Ok::<_, ()>(())
+        })
+        .unwrap()
+    };
+    let first_byte =
+        |physical_address| unsafe { *(physical2virtual(physical_address) as *const u8) };
+
+    let a = load(1, 1);
+    let b = load(2, 2);
+    // cached, not filled again
+    let a2 = load(1, 0xFF);
+    assert_eq!(a, a2);
+    assert_eq!(first_byte(a2), 1);
+
+    invalidate_file(FILESYSTEM, 1);
+    let a3 = load(1, 3);
+    assert_ne!(a3, a);
+    assert_eq!(first_byte(a3), 3);
+    // the other file is still cached
+    assert_eq!(load(2, 0xFF), b);
+
+    invalidate_filesystem(FILESYSTEM);
+    let b2 = load(2, 4);
+    assert_ne!(b2, b);
+    assert_eq!(first_byte(b2), 4);
+
+    for page in [a, a2, b, b, a3, b2] {
+        release(page);
+    }
+}
@@ -6,20 +6,22 @@ use core::{ops::RangeBounds, slice::IterMut};
 use tracing::trace;
 
 use crate::{
-    cpu,
+    cpu::{self, tlb},
     memory_management::{
+        kernel_stack,
         memory_layout::{
-            align_range, align_up, is_aligned, kernel_elf_rodata_end, physical2virtual,
+            align_down, align_range, align_up, is_aligned, kernel_elf_rodata_end, physical2virtual,
             virtual2physical, MemSize, EXTENDED_OFFSET, KERNEL_BASE, KERNEL_END, KERNEL_LINK,
             KERNEL_MAPPED_SIZE, PAGE_2M, PAGE_4K,
         },
-        physical_page_allocator,
+        physical_page_allocator, shared_pages,
     },
     sync::{once::OnceLock, spin::mutex::Mutex},
 };
 
 use super::memory_layout::{
-    stack_guard_page_ptr, PROCESS_KERNEL_STACK_BASE, PROCESS_KERNEL_STACK_SIZE,
+    stack_guard_page_ptr, PROCESS_KERNEL_STACK_BASE, PROCESS_KERNEL_STACK_GUARD,
+    PROCESS_KERNEL_STACK_SIZE,
 };
 
 // TODO: replace by some sort of bitfield
@@ -35,6 +37,13 @@ pub mod flags {
     pub(super) const PTE_HUGE_PAGE: u64 = 1 << 7;
     pub(super) const PTE_GLOBAL: u64 = 1 << 8;
     pub(super) const PTE_NO_EXECUTE: u64 = 1 << 63;
+
+    // available bits, ignored by the CPU
+    /// The page is copy-on-write, it will be copied on the first write to it
+    pub const PTE_COW: u64 = 1 << 9;
+    /// The page is managed by [`shared_pages`](crate::memory_management::shared_pages),
+    /// and must be released instead of freed
+    pub const PTE_SHARED: u64 = 1 << 10;
 }
 
 const ADDR_MASK: u64 = 0x0000_0000_FFFF_F000;
@@ -102,6 +111,14 @@ pub struct VirtualMemoryMapEntry {
     pub flags: u64,
 }
 
+/// The direction of [`VirtualMemoryMapper::copy_user_memory`]
+pub enum UserMemoryAccess<'a> {
+    /// Copy from the user memory into the buffer
+    Read(&'a mut [u8]),
+    /// Copy the buffer into the user memory
+    Write(&'a [u8]),
+}
+
 // This is a general structure for all levels
 #[repr(C, align(4096))]
 struct PageDirectoryTable {
@@ -162,6 +179,75 @@ impl PageDirectoryTablePtr {
     }
 }
 
+/// Number of pages to unmap before flushing the TLB and freeing them
+const UNMAP_BATCH_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+enum PageToFree {
+    Normal(u64),
+    Huge(u64),
+    Shared(u64),
+}
+
+impl PageToFree {
+    fn free(self) {
+        match self {
+            // SAFETY: these pages were removed from the page tables and flushed from the TLB
+            PageToFree::Normal(physical_address) => unsafe {
+                physical_page_allocator::free(physical2virtual(physical_address) as _)
+            },
+            PageToFree::Huge(physical_address) => unsafe {
+                physical_page_allocator::free_huge(physical2virtual(physical_address) as _)
+            },
+            PageToFree::Shared(physical_address) => shared_pages::release(physical_address),
+        }
+    }
+}
+
+/// Pages removed from the page tables, they are only freed after the TLB is flushed in all CPUs,
+/// so that they are not accessed through stale TLB entries after being reused
+struct UnmapBatch {
+    tlb_flush: tlb::TlbFlush,
+    to_free: [Option<PageToFree>; UNMAP_BATCH_SIZE],
+    len: usize,
+}
+
+impl UnmapBatch {
+    const fn new() -> Self {
+        Self {
+            tlb_flush: tlb::TlbFlush::new(),
+            to_free: [None; UNMAP_BATCH_SIZE],
+            len: 0,
+        }
+    }
+
+    fn add(&mut self, virtual_address: usize, to_free: Option<PageToFree>) {
+        if self.len == UNMAP_BATCH_SIZE {
+            self.finish();
+        }
+        self.tlb_flush.add_page(virtual_address);
+        self.to_free[self.len] = to_free;
+        self.len += 1;
+    }
+
+    fn finish(&mut self) {
+        // flush first, then free
+        drop(core::mem::take(&mut self.tlb_flush));
+        for to_free in self.to_free[..self.len].iter_mut() {
+            if let Some(page) = to_free.take() {
+                page.free();
+            }
+        }
+        self.len = 0;
+    }
+}
+
+impl Drop for UnmapBatch {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
 static KERNEL_VIRTUAL_MEMORY_MANAGER: OnceLock<Mutex<VirtualMemoryMapper>> = OnceLock::new();
 
 pub fn init_kernel_vm() {
@@ -227,6 +313,8 @@ pub fn get_current_vm() -> VirtualMemoryMapper {
 pub struct VirtualMemoryMapper {
     page_map_l4: PageDirectoryTablePtr,
     is_user: bool,
+    /// Number of 4K user pages mapped through this mapper (only counted for user vms)
+    mapped_user_pages: usize,
 }
 
 impl VirtualMemoryMapper {
@@ -234,6 +322,7 @@ impl VirtualMemoryMapper {
         Self {
             page_map_l4: PageDirectoryTablePtr::alloc_new(),
             is_user: false,
+            mapped_user_pages: 0,
         }
     }
 
@@ -273,6 +362,8 @@ impl VirtualMemoryMapper {
         // set it temporarily so we can map kernel range
         // TODO: fix this hack
         self.is_user = false;
+        // the page below the stack must stay unmapped, so overflows fault instead of corrupting memory
+        assert!(!self.is_address_mapped(PROCESS_KERNEL_STACK_BASE - PROCESS_KERNEL_STACK_GUARD));
         // load new kernel stack for this process
         self.map(&VirtualMemoryMapEntry {
             virtual_address: PROCESS_KERNEL_STACK_BASE,
@@ -280,6 +371,7 @@ impl VirtualMemoryMapper {
             size: PROCESS_KERNEL_STACK_SIZE,
             flags: flags::PTE_WRITABLE,
         });
+        kernel_stack::init_process_kernel_stack(self);
         self.is_user = true;
     }
 
@@ -302,9 +394,16 @@ impl VirtualMemoryMapper {
         Self {
             page_map_l4: PageDirectoryTablePtr::from_entry(cr3),
             is_user,
+            // we don't know, and this is only a view of the vm, so the counters are not used
+            mapped_user_pages: 0,
         }
     }
 
+    /// Number of user pages (4K) mapped through this vm, this doesn't include the page tables
+    pub fn mapped_user_pages(&self) -> usize {
+        self.mapped_user_pages
+    }
+
     /// Return `true` if the current VM is used by the current cpu
     pub fn is_used_by_me(&self) -> bool {
         let cr3 = unsafe { cpu::get_cr3() };
@@ -414,14 +513,6 @@ impl VirtualMemoryMapper {
         );
 
         while size > 0 {
-            let current_physical_address = physical_address.unwrap_or_else(|| {
-                virtual2physical(unsafe { physical_page_allocator::alloc_zeroed() as _ })
-            });
-            trace!(
-                "[!] Mapping {:p} to {:p}",
-                virtual_address as *const u8,
-                current_physical_address as *const u8
-            );
             let page_map_l4_index = get_l4(virtual_address);
             let page_directory_pointer_index = get_l3(virtual_address);
             let page_directory_index = get_l2(virtual_address);
@@ -472,43 +563,46 @@ impl VirtualMemoryMapper {
             let page_directory_entry =
                 &mut page_directory_table.as_mut().entries[page_directory_index];
 
-            // here we have an intersection, if we can map a 2MB page, we will, otherwise we will map a 4K page
-            // if we are providing the pages (the user didn't provide), then we can't use 2MB pages
-            // let can_map_2mb_page = physical_address
-            //     .map(|phy_addr| {
-            //         is_aligned(phy_addr as _, PAGE_2M)
-            //             && is_aligned(virtual_address as _, PAGE_2M)
-            //             && size >= PAGE_2M as u64
-            //     })
-            //     .unwrap_or(false);
-            // TODO: we have disabled 2MB as its not easy to unmap in the middle, all pages must be the sames
-
-            let can_map_2mb_page = false;
-            if can_map_2mb_page {
-                // we already have an entry here
-                if *page_directory_entry & flags::PTE_PRESENT != 0 {
-                    // did we have a mapping here that lead to 4k pages?
-                    // if so, we should free the physical page allocation for them
-                    if *page_directory_entry & flags::PTE_HUGE_PAGE == 0 {
-                        let page_table_ptr =
-                            PageDirectoryTablePtr::from_entry(*page_directory_entry);
+            // here we have an intersection, if we can map a 2MB page, we will, otherwise we will map a 4K page.
+            // If we are providing the pages (the user didn't provide), we will try to get a 2MB physical page
+            // and fallback to 4K pages if we can't.
+            // Mapping inside an existing 2MB page will split it first.
+            let huge_physical_address = if is_aligned(virtual_address, PAGE_2M)
+                && size >= PAGE_2M
+                && Self::is_free_for_huge_page(page_directory_entry)
+            {
+                match physical_address {
+                    Some(phy_addr) if is_aligned(phy_addr, PAGE_2M) => Some(phy_addr),
+                    Some(_) => None,
+                    // SAFETY: the allocator is initialized before any mapping
+                    None => unsafe { physical_page_allocator::alloc_huge_zeroed() }
+                        .map(|page| virtual2physical(page as _)),
+                }
+            } else {
+                None
+            };
 
-                        unsafe { page_table_ptr.free() };
-                    }
+            if let Some(current_physical_address) = huge_physical_address {
+                // we already have an entry here, but its an empty page table
+                if *page_directory_entry & flags::PTE_PRESENT != 0 {
+                    assert_eq!(*page_directory_entry & flags::PTE_HUGE_PAGE, 0);
+                    let page_table_ptr = PageDirectoryTablePtr::from_entry(*page_directory_entry);
+                    unsafe { page_table_ptr.free() };
                 }
 
-                // Level 1
                 *page_directory_entry = (current_physical_address & ADDR_MASK)
                     | flags
                     | flags::PTE_PRESENT
                     | flags::PTE_HUGE_PAGE;
-
                 trace!(
                     "L2[{}] huge: {:p} = {:x}",
                     page_directory_index,
                     page_directory_entry,
                     *page_directory_entry
                 );
+                if self.is_user {
+                    self.mapped_user_pages += PAGE_2M / PAGE_4K;
+                }
 
                 size -= PAGE_2M;
                 // do not overflow the address
@@ -520,11 +614,22 @@ impl VirtualMemoryMapper {
                     *physical_address += PAGE_2M as u64;
                 }
             } else {
+                let current_physical_address = physical_address.unwrap_or_else(|| {
+                    virtual2physical(unsafe { physical_page_allocator::alloc_zeroed() as _ })
+                });
+                trace!(
+                    "[!] Mapping {:p} to {:p}",
+                    virtual_address as *const u8,
+                    current_physical_address as *const u8
+                );
+
                 // continue mapping 4K pages
                 if *page_directory_entry & flags::PTE_PRESENT == 0 {
                     let page_table = PageDirectoryTablePtr::alloc_new();
                     *page_directory_entry =
                         (page_table.as_physical() & ADDR_MASK) | flags::PTE_PRESENT;
+                } else if *page_directory_entry & flags::PTE_HUGE_PAGE != 0 {
+                    Self::split_huge_page(page_directory_entry, virtual_address);
                 }
                 // add new flags
                 *page_directory_entry |= flags;
@@ -546,6 +651,9 @@ impl VirtualMemoryMapper {
                     page_table_entry,
                     *page_table_entry
                 );
+                if self.is_user {
+                    self.mapped_user_pages += 1;
+                }
 
                 size -= PAGE_4K;
                 // do not overflow the address
@@ -562,6 +670,47 @@ impl VirtualMemoryMapper {
         }
     }
 
+    /// A 2MB page can be mapped in this level 2 entry, if its empty, or points to an empty page table
+    fn is_free_for_huge_page(page_directory_entry: &mut u64) -> bool {
+        if *page_directory_entry & flags::PTE_PRESENT == 0 {
+            return true;
+        }
+        if *page_directory_entry & flags::PTE_HUGE_PAGE != 0 {
+            return false;
+        }
+        PageDirectoryTablePtr::entries_from_mut_entry(page_directory_entry)
+            .entries
+            .iter()
+            .all(|entry| *entry & flags::PTE_PRESENT == 0)
+    }
+
+    /// Convert a 2MB page entry into a page table of 4K pages mapping the same memory with the same flags.
+    ///
+    /// If the 2MB page was allocated (i.e. from `map` without physical address), the 4K pages
+    /// can be freed individually after this.
+    fn split_huge_page(page_directory_entry: &mut u64, virtual_address: usize) {
+        assert_ne!(*page_directory_entry & flags::PTE_HUGE_PAGE, 0);
+
+        // bit 12 is `PAT` for 2MB pages, we don't use it, so its part of the address mask
+        let huge_physical_address = *page_directory_entry & ADDR_MASK;
+        assert!(is_aligned(huge_physical_address, PAGE_2M));
+        let entry_flags = *page_directory_entry & !(ADDR_MASK | flags::PTE_HUGE_PAGE);
+
+        let mut page_table = PageDirectoryTablePtr::alloc_new();
+        for (i, entry) in page_table.as_mut().entries.iter_mut().enumerate() {
+            *entry = (huge_physical_address + (i * PAGE_4K) as u64) | entry_flags;
+        }
+        // the upper levels don't care about no-execute and dirty/accessed bits
+        *page_directory_entry = page_table.as_physical()
+            | (entry_flags & !(flags::PTE_NO_EXECUTE | flags::PTE_DIRTY | flags::PTE_GLOBAL));
+        trace!(
+            "split 2MB page at {:p} into 4K pages",
+            align_down(virtual_address, PAGE_2M) as *const u8
+        );
+        // any address inside the page will invalidate the whole 2MB entry
+        tlb::flush_page(virtual_address);
+    }
+
     /// Removes mapping of a virtual entry, it will free it from physical memory if it was allocated
     pub fn unmap(&mut self, entry: &VirtualMemoryMapEntry, is_allocated: bool) {
         let VirtualMemoryMapEntry {
@@ -590,11 +739,9 @@ impl VirtualMemoryMapper {
             }
         );
 
-        while size > 0 {
-            unsafe {
-                cpu::invalidate_tlp(virtual_address as _);
-            }
+        let mut batch = UnmapBatch::new();
 
+        while size > 0 {
             let page_map_l4_index = get_l4(virtual_address);
             let page_directory_pointer_index = get_l3(virtual_address);
             let page_directory_index = get_l2(virtual_address);
@@ -643,6 +790,38 @@ impl VirtualMemoryMapper {
             if *page_directory_entry & flags::PTE_PRESENT == 0 {
                 panic!("Trying to unmap a non-mapped address");
             }
+
+            if *page_directory_entry & flags::PTE_HUGE_PAGE != 0 {
+                if is_aligned(virtual_address, PAGE_2M) && size >= PAGE_2M {
+                    // remove the whole 2MB page
+                    let physical_address = *page_directory_entry & ADDR_MASK;
+                    *page_directory_entry = 0;
+                    batch.add(
+                        virtual_address,
+                        is_allocated.then_some(PageToFree::Huge(physical_address)),
+                    );
+                    if self.is_user {
+                        self.mapped_user_pages =
+                            self.mapped_user_pages.saturating_sub(PAGE_2M / PAGE_4K);
+                    }
+                    trace!(
+                        "L2[{}] huge: {:p} = {:x}",
+                        page_directory_index,
+                        page_directory_entry,
+                        *page_directory_entry
+                    );
+
+                    size -= PAGE_2M;
+                    // do not overflow the address
+                    if size == 0 {
+                        break;
+                    }
+                    virtual_address += PAGE_2M;
+                    continue;
+                }
+                // unmapping part of it, split and continue with 4K pages
+                Self::split_huge_page(page_directory_entry, virtual_address);
+            }
             // remove flags
             *page_directory_entry &= !flags;
 
@@ -652,12 +831,18 @@ impl VirtualMemoryMapper {
             if *page_table_entry & flags::PTE_PRESENT == 0 {
                 panic!("Trying to unmap a non-mapped address");
             }
-            let physical_entry = PageDirectoryTablePtr::from_entry(*page_table_entry);
-            if is_allocated {
-                unsafe { physical_entry.free() };
-            }
+            let physical_address = *page_table_entry & ADDR_MASK;
+            let to_free = if *page_table_entry & flags::PTE_SHARED != 0 {
+                Some(PageToFree::Shared(physical_address))
+            } else {
+                is_allocated.then_some(PageToFree::Normal(physical_address))
+            };
             // remove whole entry
             *page_table_entry = 0;
+            batch.add(virtual_address, to_free);
+            if self.is_user {
+                self.mapped_user_pages = self.mapped_user_pages.saturating_sub(1);
+            }
             trace!(
                 "L1[{}]: {:p} = {:x}",
                 page_table_index,
@@ -740,6 +925,165 @@ impl VirtualMemoryMapper {
         true
     }
 
+    /// Get the level 1 entry of `addr`, `None` if its not mapped or mapped with a 2MB page
+    fn get_l1_entry_mut(&mut self, addr: usize) -> Option<&mut u64> {
+        let present = |entry: u64| entry & flags::PTE_PRESENT != 0;
+
+        let page_map_l4_entry = &mut self.page_map_l4.as_mut().entries[get_l4(addr)];
+        if !present(*page_map_l4_entry) {
+            return None;
+        }
+        let page_directory_pointer_entry =
+            &mut PageDirectoryTablePtr::entries_from_mut_entry(page_map_l4_entry).entries
+                [get_l3(addr)];
+        if !present(*page_directory_pointer_entry) {
+            return None;
+        }
+        let page_directory_entry =
+            &mut PageDirectoryTablePtr::entries_from_mut_entry(page_directory_pointer_entry)
+                .entries[get_l2(addr)];
+        if !present(*page_directory_entry) || *page_directory_entry & flags::PTE_HUGE_PAGE != 0 {
+            return None;
+        }
+        let page_table_entry =
+            &mut PageDirectoryTablePtr::entries_from_mut_entry(page_directory_entry).entries
+                [get_l1(addr)];
+        if !present(*page_table_entry) {
+            return None;
+        }
+
+        Some(page_table_entry)
+    }
+
+    /// Get the level 2 entry of `addr` if its mapped with a 2MB page
+    fn get_huge_entry(&self, addr: usize) -> Option<u64> {
+        let present = |entry: u64| entry & flags::PTE_PRESENT != 0;
+
+        let page_map_l4_entry = self.page_map_l4.as_ref().entries[get_l4(addr)];
+        if !present(page_map_l4_entry) {
+            return None;
+        }
+        let page_directory_pointer_entry = PageDirectoryTablePtr::from_entry(page_map_l4_entry)
+            .as_ref()
+            .entries[get_l3(addr)];
+        if !present(page_directory_pointer_entry) {
+            return None;
+        }
+        let page_directory_entry = PageDirectoryTablePtr::from_entry(page_directory_pointer_entry)
+            .as_ref()
+            .entries[get_l2(addr)];
+        (present(page_directory_entry) && page_directory_entry & flags::PTE_HUGE_PAGE != 0)
+            .then_some(page_directory_entry)
+    }
+
+    /// Get the physical address that `addr` is mapped to
+    pub fn virtual_to_physical(&mut self, addr: usize) -> Option<u64> {
+        if let Some(entry) = self.get_huge_entry(addr) {
+            return Some((entry & ADDR_MASK) + (addr % PAGE_2M) as u64);
+        }
+        self.get_l1_entry_mut(addr)
+            .map(|entry| (*entry & ADDR_MASK) + (addr % PAGE_4K) as u64)
+    }
+
+    /// Handle a write fault to a copy-on-write page, the page will be copied (if its still shared),
+    /// and mapped as writable.
+    ///
+    /// Returns `false` if the page is not a copy-on-write page, i.e. its a real fault.
+    pub fn handle_cow_fault(&mut self, addr: usize) -> bool {
+        let Some(entry) = self.get_l1_entry_mut(addr) else {
+            return false;
+        };
+        if *entry & flags::PTE_COW == 0 {
+            return false;
+        }
+        assert_ne!(*entry & flags::PTE_SHARED, 0, "COW page must be shared");
+
+        let old_physical = *entry & ADDR_MASK;
+        let new_physical = if shared_pages::try_take_exclusive(old_physical) {
+            // we are the only user, just take it
+            old_physical
+        } else {
+            // SAFETY: panics if it can't allocate
+            let new_page = unsafe { physical_page_allocator::alloc() };
+            // SAFETY: both are valid pages, and the new page is not used by anyone else
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    physical2virtual(old_physical) as *const u8,
+                    new_page,
+                    PAGE_4K,
+                );
+            }
+            shared_pages::release(old_physical);
+            virtual2physical(new_page as usize)
+        };
+
+        *entry = (*entry & !(ADDR_MASK | flags::PTE_COW | flags::PTE_SHARED))
+            | new_physical
+            | flags::PTE_WRITABLE;
+        tlb::flush_page(align_down(addr, PAGE_4K));
+
+        true
+    }
+
+    /// Copy between `addr` in the user memory of this vm and the buffer of `access`, through the
+    /// kernel mapping of the physical pages, so this vm doesn't have to be the current one.
+    ///
+    /// Stops at the first page that is not mapped for the user, or not writable when writing,
+    /// copy-on-write pages are copied first, as if the process wrote to them.
+    ///
+    /// Returns the number of bytes copied.
+    pub fn copy_user_memory(&mut self, addr: usize, mut access: UserMemoryAccess) -> usize {
+        let (len, writing) = match &access {
+            UserMemoryAccess::Read(buf) => (buf.len(), false),
+            UserMemoryAccess::Write(buf) => (buf.len(), true),
+        };
+
+        let mut copied = 0;
+        while copied < len {
+            let Some(current) = addr
+                .checked_add(copied)
+                .filter(|&current| current < MAX_USER_VIRTUAL_ADDRESS)
+            else {
+                break;
+            };
+            let (mut entry, page_size) = if let Some(entry) = self.get_huge_entry(current) {
+                (entry, PAGE_2M)
+            } else if let Some(entry) = self.get_l1_entry_mut(current) {
+                (*entry, PAGE_4K)
+            } else {
+                break;
+            };
+            if entry & flags::PTE_USER == 0 {
+                break;
+            }
+            if writing && entry & flags::PTE_WRITABLE == 0 {
+                if !self.handle_cow_fault(current) {
+                    break;
+                }
+                entry = *self
+                    .get_l1_entry_mut(current)
+                    .expect("copy-on-write page must still be mapped");
+            }
+
+            let offset = current % page_size;
+            let chunk_len = (page_size - offset).min(len - copied);
+            let page_ptr = physical2virtual((entry & ADDR_MASK) + offset as u64) as *mut u8;
+            // SAFETY: the page is mapped for the user, and the chunk doesn't cross its end
+            unsafe {
+                match &mut access {
+                    UserMemoryAccess::Read(buf) => {
+                        page_ptr.copy_to_nonoverlapping(buf[copied..].as_mut_ptr(), chunk_len)
+                    }
+                    UserMemoryAccess::Write(buf) => {
+                        page_ptr.copy_from_nonoverlapping(buf[copied..].as_ptr(), chunk_len)
+                    }
+                }
+            }
+            copied += chunk_len;
+        }
+        copied
+    }
+
     // TODO: add tests for this
     fn do_for_ranges_entries<R1, R2, F>(&mut self, l4_ranges: R1, l3_ranges: R2, mut f: F)
     where
@@ -826,17 +1170,19 @@ impl VirtualMemoryMapper {
     // also unmap any process specific kernel memory
     pub fn unmap_process_memory(&mut self) {
         let free_page = |entry: &mut u64| {
-            assert_eq!(
-                *entry & flags::PTE_HUGE_PAGE,
-                0,
-                "We haven't implemented 2MB physical pages for user allocation"
-            );
             let page_table_ptr = PageDirectoryTablePtr::from_entry(*entry);
-            unsafe { page_table_ptr.free() };
+            if *entry & flags::PTE_HUGE_PAGE != 0 {
+                unsafe { physical_page_allocator::free_huge(page_table_ptr.as_virtual() as _) };
+            } else if *entry & flags::PTE_SHARED != 0 {
+                shared_pages::release(page_table_ptr.as_physical());
+            } else {
+                unsafe { page_table_ptr.free() };
+            }
             *entry = 0;
         };
 
         self.do_for_every_user_entry(free_page);
         self.do_for_kernel_process_entry(free_page);
+        self.mapped_user_pages = 0;
     }
 }
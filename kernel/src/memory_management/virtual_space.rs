@@ -6,9 +6,10 @@ use tracing::info;
 use crate::{
     memory_management::memory_layout::{
         align_range, is_aligned, MemSize, KERNEL_EXTRA_MEMORY_BASE, KERNEL_EXTRA_MEMORY_SIZE,
-        PAGE_4K,
+        PAGE_2M, PAGE_4K,
     },
     sync::spin::mutex::Mutex,
+    testing,
 };
 
 use super::virtual_memory_mapper::{self, VirtualMemoryMapEntry};
@@ -39,6 +40,9 @@ type Result<T> = core::result::Result<T, VirtualSpaceError>;
 /// A wrapper over memory that is defined by its `physical address`.
 /// We map this memory in `virtual space`, and return a pointer to it.
 ///
+/// A physical page can only be in one `VirtualSpace` at a time, so that there are no two
+/// mutable references to the same memory. Creating one that overlaps an existing one fails with
+/// [`VirtualSpaceError::AlreadyMapped`], and panics in debug builds, since it's a bug in the caller.
 pub struct VirtualSpace<T: ?Sized> {
     size: usize,
     data: NonNull<T>,
@@ -131,7 +135,21 @@ fn allocate_and_map_virtual_space(physical_start: u64, size: usize) -> Result<us
     let (aligned_start, size, offset) = align_range(physical_start, size, PAGE_4K);
 
     let mut allocator = VIRTUAL_SPACE_ALLOCATOR.lock();
-    let virtual_addr = allocator.allocate(aligned_start, size)?;
+    let virtual_addr = match allocator.allocate(aligned_start, size) {
+        Err(VirtualSpaceError::AlreadyMapped) if cfg!(debug_assertions) => {
+            let entry = allocator
+                .get_overlapping_entry(aligned_start, size)
+                .unwrap();
+            panic!(
+                "Physical range {:016X}..{:016X} is already mapped in {:016X}..{:016X}",
+                aligned_start,
+                aligned_start + size as u64,
+                entry.physical_start.unwrap(),
+                entry.physical_start.unwrap() + entry.size as u64
+            );
+        }
+        result => result?,
+    };
 
     virtual_memory_mapper::map_kernel(&VirtualMemoryMapEntry {
         virtual_address: virtual_addr,
@@ -170,6 +188,21 @@ pub fn debug_blocks() {
     allocator.debug_blocks();
 }
 
+/// Returns `(used_blocks, used_size, free_size)`, the sizes are in bytes
+pub fn stats() -> (usize, usize, usize) {
+    let allocator = VIRTUAL_SPACE_ALLOCATOR.lock();
+    allocator
+        .entries
+        .iter()
+        .fold((0, 0, 0), |(blocks, used, free), entry| {
+            if entry.physical_start.is_some() {
+                (blocks + 1, used + entry.size, free)
+            } else {
+                (blocks, used, free + entry.size)
+            }
+        })
+}
+
 struct VirtualSpaceEntry {
     physical_start: Option<u64>,
     virtual_start: usize,
@@ -203,40 +236,23 @@ impl VirtualSpaceAllocator {
         }
     }
 
-    /// Returns `(virtual_start, is_fully_inside)`
-    fn get_entry_containing(
-        &mut self,
+    /// Returns the mapped entry that has any part of the physical range inside it
+    fn get_overlapping_entry(
+        &self,
         req_phy_start: u64,
         req_size: usize,
-    ) -> Option<(&VirtualSpaceEntry, bool)> {
+    ) -> Option<&VirtualSpaceEntry> {
         assert!(req_size > 0);
         assert!(is_aligned(req_phy_start, PAGE_4K));
         assert!(is_aligned(req_size, PAGE_4K));
 
-        let mut cursor = self.entries.cursor_front();
-        while let Some(entry) = cursor.current() {
-            if let Some(current_phy_start) = entry.physical_start {
-                // is inside?
-                if current_phy_start <= req_phy_start
+        let req_phy_end = req_phy_start + req_size as u64;
+        self.entries.iter().find(|entry| {
+            entry.physical_start.is_some_and(|current_phy_start| {
+                current_phy_start < req_phy_end
                     && current_phy_start + entry.size as u64 > req_phy_start
-                {
-                    // this has parts of it inside
-                    // is it fully inside?
-                    return if current_phy_start + entry.size as u64
-                        >= req_phy_start + req_size as u64
-                    {
-                        // yes, it is fully inside
-                        Some((entry, true))
-                    } else {
-                        // no, it is not fully inside, but there is an overlap
-                        // we can't allocate this and we can't relocate
-                        Some((entry, false))
-                    };
-                }
-            }
-            cursor.move_next();
-        }
-        None
+            })
+        })
     }
 
     fn allocate(&mut self, phy_start: u64, size: usize) -> Result<usize> {
@@ -244,15 +260,35 @@ impl VirtualSpaceAllocator {
         assert!(is_aligned(phy_start, PAGE_4K));
         assert!(is_aligned(size, PAGE_4K));
 
-        if self.get_entry_containing(phy_start, size).is_some() {
+        if self.get_overlapping_entry(phy_start, size).is_some() {
             return Err(VirtualSpaceError::AlreadyMapped);
         }
 
+        // for large mappings (i.e. framebuffers), keep the same offset inside a 2MB page as the physical
+        // address, so that the mapper can use 2MB pages
+        let padding_for = |virtual_start: usize| {
+            if size < PAGE_2M {
+                return 0;
+            }
+            let physical_offset = phy_start as usize % PAGE_2M;
+            (physical_offset + PAGE_2M - virtual_start % PAGE_2M) % PAGE_2M
+        };
+
         let mut cursor = self.entries.cursor_front_mut();
         // find largest fitting entry and allocate from it
         while let Some(entry) = cursor.current() {
-            if entry.physical_start.is_none() && entry.size >= size {
-                // found it, split into two, and add to the list
+            let padding = padding_for(entry.virtual_start);
+            if entry.physical_start.is_none() && entry.size >= size + padding {
+                // found it, split into two (or three if we need padding), and add to the list
+
+                // the padding (before this)
+                let padding_entry = VirtualSpaceEntry {
+                    physical_start: None,
+                    virtual_start: entry.virtual_start,
+                    size: padding,
+                };
+                entry.virtual_start += padding;
+                entry.size -= padding;
 
                 // the new entry (after this)
                 let new_entry = VirtualSpaceEntry {
@@ -265,8 +301,11 @@ impl VirtualSpaceAllocator {
                 entry.physical_start = Some(phy_start);
                 let virtual_address = entry.virtual_start;
 
-                // add the new entry
+                // add the new entries
                 cursor.insert_after(new_entry);
+                if padding != 0 {
+                    cursor.insert_before(padding_entry);
+                }
                 return Ok(virtual_address);
             }
             cursor.move_next();
@@ -352,3 +391,40 @@ impl VirtualSpaceAllocator {
         }
     }
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_virtual_space_no_aliasing() {
+    // only the bookkeeping, nothing is mapped
+    let mut allocator = VirtualSpaceAllocator::empty();
+    let base = 0x10_0000;
+
+    let virtual_start = allocator.allocate(base, PAGE_4K * 2).unwrap();
+    // inside
+    assert!(matches!(
+        allocator.allocate(base + PAGE_4K as u64, PAGE_4K),
+        Err(VirtualSpaceError::AlreadyMapped)
+    ));
+    // starts before, ends inside
+    assert!(matches!(
+        allocator.allocate(base - PAGE_4K as u64, PAGE_4K * 2),
+        Err(VirtualSpaceError::AlreadyMapped)
+    ));
+    // contains it
+    assert!(matches!(
+        allocator.allocate(base - PAGE_4K as u64, PAGE_4K * 4),
+        Err(VirtualSpaceError::AlreadyMapped)
+    ));
+    // next to it on both sides
+    let before = allocator.allocate(base - PAGE_4K as u64, PAGE_4K).unwrap();
+    let after = allocator
+        .allocate(base + PAGE_4K as u64 * 2, PAGE_4K)
+        .unwrap();
+
+    allocator.deallocate(virtual_start, PAGE_4K * 2).unwrap();
+    // can be mapped again after it's gone
+    let virtual_start = allocator.allocate(base, PAGE_4K).unwrap();
+
+    allocator.deallocate(virtual_start, PAGE_4K).unwrap();
+    allocator.deallocate(before, PAGE_4K).unwrap();
+    allocator.deallocate(after, PAGE_4K).unwrap();
+}
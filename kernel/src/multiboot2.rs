@@ -352,6 +352,7 @@ pub enum MultiBootTag<'a> {
     BootLoaderName {
         name: &'a str,
     },
+    Module(BootModule<'a>),
     BasicMemoryInfo(&'a BasicMemoryInfo),
     AdvancedPowerManagementTable(&'a AdvancedPowerManagementTable),
     ImageLoadBasePhysical {
@@ -378,6 +379,16 @@ pub enum MultiBootTag<'a> {
     VbeInfo(&'a VbeInfo),
 }
 
+/// A file loaded by the bootloader alongside the kernel (i.e. `module2` in grub)
+#[derive(Debug, Clone, Copy)]
+pub struct BootModule<'a> {
+    /// Physical address of the start of the module
+    pub start: u64,
+    /// Physical address of the end of the module (exclusive)
+    pub end: u64,
+    pub cmdline: &'a str,
+}
+
 pub struct MultiBootTagIter<'a> {
     current: *const MultiBootTagRaw,
     remaining: usize,
@@ -415,6 +426,18 @@ impl<'a> Iterator for MultiBootTagIter<'a> {
                 let name = unsafe { ffi::CStr::from_ptr(str_ptr).to_str().expect("invalid utf8") };
                 MultiBootTag::BootLoaderName { name }
             }
+            3 => {
+                let tag = unsafe { ptr.add(1) as *const u32 };
+                let range = unsafe { core::slice::from_raw_parts(tag, 2) };
+                let str_ptr = unsafe { tag.add(2) as *const i8 };
+                let cmdline =
+                    unsafe { ffi::CStr::from_ptr(str_ptr).to_str().expect("invalid utf8") };
+                MultiBootTag::Module(BootModule {
+                    start: range[0] as u64,
+                    end: range[1] as u64,
+                    cmdline,
+                })
+            }
             4 => {
                 let tag = unsafe { &*(ptr.add(1) as *const BasicMemoryInfo) };
                 MultiBootTag::BasicMemoryInfo(tag)
@@ -568,6 +591,13 @@ impl MultiBoot2Info {
         })
     }
 
+    pub fn modules(&self) -> impl Iterator<Item = BootModule<'_>> + '_ {
+        self.tags().filter_map(|tag| match tag {
+            MultiBootTag::Module(module) => Some(module),
+            _ => None,
+        })
+    }
+
     pub fn framebuffer(&self) -> Option<Framebuffer> {
         self.tags().find_map(|tag| match tag {
             MultiBootTag::FrameBufferInfo(fb) => Some(fb),
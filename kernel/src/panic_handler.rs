@@ -17,11 +17,17 @@ use kernel_user_link::process::process_metadata;
 
 use crate::{
     cpu::{self, idt::InterruptStackFrame64},
-    hw::qemu,
-    memory_management::memory_layout::{
-        eh_frame_end, eh_frame_start, kernel_elf_end, kernel_text_end, KERNEL_LINK,
+    graphics,
+    hw::{debugcon, qemu},
+    io::console,
+    memory_management::{
+        memory_layout::{
+            eh_frame_end, eh_frame_start, kernel_elf_end, kernel_text_end, KERNEL_LINK,
+        },
+        virtual_memory_mapper,
     },
     process::scheduler::with_current_process,
+    tracepoints,
 };
 
 // this should be 'core-local/thread-local', but that's okay, as we want to halt the whole kernel
@@ -90,6 +96,8 @@ pub fn print_process_stack_trace(frame: &InterruptStackFrame64, rbp: u64) {
     cpu::cpu().push_cli();
 
     assert_eq!(frame.cs & 0x3, 3, "We are in user mode");
+    // we will be reading the process stack and metadata
+    let _user_access = cpu::user_access::allow_user_access();
 
     let meta = process_metadata();
 
@@ -116,7 +124,18 @@ pub fn print_process_stack_trace(frame: &InterruptStackFrame64, rbp: u64) {
     let mut unwinder: UnwinderX86_64<&[u8]> = UnwinderX86_64::new();
     unwinder.add_module(module);
 
-    let mut read_stack = |addr| Ok(unsafe { (addr as *const u64).read_volatile() });
+    // the process could have faulted because of a bad stack, so don't fault again reading it
+    let vm = virtual_memory_mapper::get_current_vm();
+    let readable = |addr: usize| {
+        addr < virtual_memory_mapper::MAX_USER_VIRTUAL_ADDRESS && vm.is_address_mapped(addr)
+    };
+    let mut read_stack = |addr: u64| {
+        let addr = addr as usize;
+        if !readable(addr) || !readable(addr.saturating_add(7)) {
+            return Err(());
+        }
+        Ok(unsafe { (addr as *const u64).read_volatile() })
+    };
 
     let mut iter = unwinder.iter_frames(
         frame.rip as _,
@@ -213,7 +232,13 @@ fn panic_trace(msg: Box<dyn Any + Send>) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo<'_>) -> ! {
     unsafe { cpu::clear_interrupts() };
+    // make sure the panic message is visible
+    graphics::boot_logo::finish();
+    console::show_panic_screen();
+    println!("\x1b[41;97m KERNEL PANIC \x1b[0m");
     println!("{}", info);
+    debugcon::panic(info);
+    tracepoints::dump();
 
     struct NoPayload;
     panic_trace(Box::new(NoPayload))
@@ -3,11 +3,12 @@ use tracing::{error, info};
 use crate::{
     acpi,
     cpu::{self},
-    devices::{keyboard_mouse, Device},
+    devices::{self, keyboard_mouse, Device},
     fs,
     io::console,
     process::scheduler,
     sync::once::OnceLock,
+    tracepoints,
 };
 
 static CURRENT_CMD: OnceLock<PowerCommand> = OnceLock::new();
@@ -32,6 +33,10 @@ impl Device for PowerDevice {
         "power"
     }
 
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
     // This is needed to support the `echo shutdown > /dev/power`, as it will
     // open the file and truncate it to 0, then write to it.
     fn set_size(&self, size: u64) -> Result<(), fs::FileSystemError> {
@@ -95,6 +100,8 @@ pub fn finish_power_sequence() -> ! {
     let cmd = CURRENT_CMD.try_get().expect("No power command set");
 
     console::tracing::shutdown_log_file();
+    tracepoints::dump();
+    devices::suspend_all();
     // unmount all filesystems
     fs::unmount_all();
 
@@ -0,0 +1,214 @@
+//! Kernel objects that a process refers to by a file descriptor
+//!
+//! The file descriptor table of a [`Process`](super::Process) holds [`KernelHandle`]s, the
+//! operations common to all of them (read, write, poll, close and clone) go through the trait,
+//! and the ones specific to a kind are reached with the downcast helpers, such as
+//! [`as_file_mut`](dyn KernelHandle::as_file_mut).
+//!
+//! Pipes, watches and other devices are [`File`]s, so a new kind of handle is only needed for
+//! objects that don't live in the filesystem.
+
+use core::any::Any;
+
+use alloc::{boxed::Box, sync::Arc};
+
+use crate::{
+    fs::{Directory, File, FileSystemError},
+    process::scheduler::WaitQueue,
+};
+
+/// The state of a handle, see [`KernelHandle::poll`]
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HandleStatus {
+    pub readable: bool,
+    pub writable: bool,
+    /// Reading may wait for data, which needs interrupts, so it must not be done while holding
+    /// the process
+    pub may_block: bool,
+}
+
+pub trait KernelHandle: Any + Send {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn read(&mut self, _buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        Err(FileSystemError::ReadNotSupported)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [&mut [u8]]) -> Result<u64, FileSystemError> {
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let read = self.read(buf)?;
+            total += read;
+            if read < buf.len() as u64 {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> Result<u64, FileSystemError> {
+        Err(FileSystemError::WriteNotSupported)
+    }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<u64, FileSystemError> {
+        let mut total = 0;
+        for buf in bufs {
+            let written = self.write(buf)?;
+            total += written;
+            if written < buf.len() as u64 {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn poll(&self) -> HandleStatus;
+
+    /// The queue to wait on when a read or write returns [`FileSystemError::WouldBlock`]
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        None
+    }
+
+    /// Release the handle, the default is to drop it, which is enough for most handles.
+    ///
+    /// Called outside the process lock, as closing may need to notify devices
+    fn close(self: Box<Self>) {}
+
+    /// Duplicate the handle in the same process, see `dup`
+    fn clone_duplicate(&self) -> Box<dyn KernelHandle>;
+
+    /// Create the handle given to a child process, `None` if it shouldn't be inherited
+    fn clone_inherit(&self) -> Option<Box<dyn KernelHandle>>;
+}
+
+impl dyn KernelHandle {
+    pub fn is<T: KernelHandle>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    pub fn downcast_ref<T: KernelHandle>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: KernelHandle>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+
+    /// The error to return when this handle is not of the kind an operation expects
+    fn wrong_kind_error(&self) -> FileSystemError {
+        if self.is::<Directory>() {
+            FileSystemError::IsDirectory
+        } else if self.is::<File>() {
+            FileSystemError::IsNotDirectory
+        } else {
+            FileSystemError::OperationNotSupported
+        }
+    }
+
+    pub fn as_file(&self) -> Result<&File, FileSystemError> {
+        let err = self.wrong_kind_error();
+        self.downcast_ref().ok_or(err)
+    }
+
+    pub fn as_file_mut(&mut self) -> Result<&mut File, FileSystemError> {
+        let err = self.wrong_kind_error();
+        self.downcast_mut().ok_or(err)
+    }
+
+    pub fn as_dir_mut(&mut self) -> Result<&mut Directory, FileSystemError> {
+        let err = self.wrong_kind_error();
+        self.downcast_mut().ok_or(err)
+    }
+}
+
+impl<T: KernelHandle> From<T> for Box<dyn KernelHandle> {
+    fn from(handle: T) -> Self {
+        Box::new(handle)
+    }
+}
+
+impl KernelHandle for File {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        File::read(self, buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [&mut [u8]]) -> Result<u64, FileSystemError> {
+        File::read_vectored(self, bufs)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<u64, FileSystemError> {
+        File::write(self, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<u64, FileSystemError> {
+        File::write_vectored(self, bufs)
+    }
+
+    fn poll(&self) -> HandleStatus {
+        HandleStatus {
+            readable: self.access().is_read(),
+            writable: self.access().is_write(),
+            may_block: self.is_blocking(),
+        }
+    }
+
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        File::wait_queue(self)
+    }
+
+    fn clone_duplicate(&self) -> Box<dyn KernelHandle> {
+        Box::new(File::clone_duplicate(self))
+    }
+
+    fn clone_inherit(&self) -> Option<Box<dyn KernelHandle>> {
+        if self.is_close_on_spawn() {
+            return None;
+        }
+        Some(Box::new(File::clone_inherit(self)))
+    }
+}
+
+impl KernelHandle for Directory {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn read(&mut self, _buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        // entries are read with `read_dir`
+        Err(FileSystemError::IsDirectory)
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> Result<u64, FileSystemError> {
+        Err(FileSystemError::IsDirectory)
+    }
+
+    fn poll(&self) -> HandleStatus {
+        HandleStatus {
+            readable: true,
+            writable: false,
+            may_block: false,
+        }
+    }
+
+    fn clone_duplicate(&self) -> Box<dyn KernelHandle> {
+        Box::new(self.clone())
+    }
+
+    fn clone_inherit(&self) -> Option<Box<dyn KernelHandle>> {
+        Some(Box::new(self.clone()))
+    }
+}
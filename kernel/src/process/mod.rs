@@ -1,13 +1,20 @@
+pub mod handle;
+pub mod procfs;
 pub mod scheduler;
 mod syscalls;
 
 use core::sync::atomic::{AtomicU64, Ordering};
 
-use alloc::{collections::BTreeMap, string::String, vec::Vec};
-use kernel_user_link::process::{PriorityLevel, ProcessMetadata};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+use kernel_user_link::process::{
+    PriorityLevel, ProcessMemoryStats, ProcessMetadata, SchedulingClass, ALL_CPUS_AFFINITY,
+};
+
+use handle::KernelHandle;
 
 use crate::{
     cpu::{self, gdt},
+    devices::clock::ClockTime,
     executable::{elf, load_elf_to_vm},
     fs::{
         self,
@@ -15,11 +22,16 @@ use crate::{
     },
     graphics::vga,
     memory_management::{
-        memory_layout::{align_down, align_up, is_aligned, GB, KERNEL_BASE, MB, PAGE_2M, PAGE_4K},
+        memory_layout::{
+            align_down, align_up, is_aligned, physical2virtual, GB, KERNEL_BASE, MB, PAGE_2M,
+            PAGE_4K,
+        },
         virtual_memory_mapper::{
-            self, VirtualMemoryMapEntry, VirtualMemoryMapper, MAX_USER_VIRTUAL_ADDRESS,
+            self, UserMemoryAccess, VirtualMemoryMapEntry, VirtualMemoryMapper,
+            MAX_USER_VIRTUAL_ADDRESS,
         },
     },
+    testing,
 };
 
 static PROCESS_ID_ALLOCATOR: GoingUpAllocator = GoingUpAllocator::new();
@@ -58,6 +70,20 @@ impl GoingUpAllocator {
     }
 }
 
+/// When the exit hooks run, in this order, see [`Process::add_exit_hook`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExitHookStage {
+    /// Resources owned by the process that others may be waiting for, e.g. the graphics
+    Resources,
+    /// The open handles
+    Handles,
+    /// The memory of the process, last, as the other hooks may still use it
+    Memory,
+}
+
+/// Cleanup to run when the process is released
+pub type ExitHook = Box<dyn FnOnce(&mut Process) + Send>;
+
 #[repr(C, align(0x10))]
 #[derive(Debug, Clone, Copy, Default)]
 pub struct FxSave(pub [u128; 32]);
@@ -107,10 +133,12 @@ pub struct Process {
     parent_id: u64,
 
     // use BTreeMap to keep FDs even after closing some of them
-    open_filesystem_nodes: BTreeMap<usize, fs::FilesystemNode>,
+    handles: BTreeMap<usize, Box<dyn KernelHandle>>,
     file_index_allocator: GoingUpAllocator,
 
     argv: Vec<String>,
+    /// The initial environment, `KEY=VALUE` strings, the process may change its own copy in userspace
+    env: Vec<String>,
     file_path: PathBuf,
 
     current_dir: fs::Directory,
@@ -122,11 +150,21 @@ pub struct Process {
     heap_size: usize,
     heap_max: usize,
 
+    /// Pages mapped for the executable segments
+    file_mapped_pages: usize,
+
     priority: PriorityLevel,
+    scheduling_class: SchedulingClass,
+    /// Bitmask of the CPUs this process can run on, not used by the scheduler until we run on multiple CPUs
+    affinity: u64,
+    /// Time spent running, updated by the scheduler when the process stops running
+    cpu_time: ClockTime,
 
     // split from the state, so that we can keep it as a simple enum
     exit_code: i32,
     children_exits: BTreeMap<u64, i32>,
+    /// Sorted by the stage, see [`Process::add_exit_hook`]
+    exit_hooks: Vec<(ExitHookStage, ExitHook)>,
 }
 
 impl Process {
@@ -135,6 +173,7 @@ impl Process {
         elf: &elf::Elf,
         file: &mut fs::File,
         argv: Vec<String>,
+        env: Vec<String>,
         current_dir: fs::Directory,
     ) -> Result<Self, ProcessError> {
         let id = PROCESS_ID_ALLOCATOR.allocate();
@@ -164,20 +203,20 @@ impl Process {
         });
 
         let rsp = stack_end as u64 - 8;
-        let (new_rsp, argc, argv_ptr) =
-            Self::prepare_stack(&mut vm, &argv, rsp, stack_start as u64);
+        let (new_rsp, argc, argv_ptr, envp_ptr) =
+            Self::prepare_stack(&mut vm, &argv, &env, rsp, stack_start as u64);
+        process_meta.envp = envp_ptr as usize;
 
-        // SAFETY: we know that the vm passed is an exact kernel copy of this vm, so its safe to switch to it
-        // TODO: maybe it would be best to create the new vm inside this function?
-        let (_min_addr, max_addr) =
-            unsafe { load_elf_to_vm(elf, file, &mut process_meta, &mut vm)? };
+        let pages_before_elf = vm.mapped_user_pages();
+        let (_min_addr, max_addr) = load_elf_to_vm(elf, file, &mut process_meta, &mut vm)?;
+        let file_mapped_pages = vm.mapped_user_pages() - pages_before_elf;
 
         Self::write_process_meta(&mut vm, process_meta_addr, process_meta);
 
         // SAFETY: we know that the vm is never used after this point until scheduling
         unsafe { vm.add_process_specific_mappings() };
 
-        // set it quite a distance from the elf and align it to 2MB pages (we are not using 2MB virtual memory, so its not related)
+        // set it quite a distance from the elf and align it to 2MB pages, so that large heap increments can use 2MB pages
         let heap_start = align_up(max_addr + HEAP_OFFSET_FROM_ELF_END, PAGE_2M);
         let heap_size = 0; // start at 0, let user space programs control it
         let heap_max = DEFAULT_MAX_HEAP_SIZE;
@@ -197,15 +236,17 @@ impl Process {
         // NOTE: This is very specific to x86_64 SYSV abi
         context.rdi = argc;
         context.rsi = argv_ptr;
+        context.rdx = envp_ptr;
 
-        Ok(Self {
+        let mut process = Self {
             vm,
             context,
             id,
             parent_id,
-            open_filesystem_nodes: BTreeMap::new(),
+            handles: BTreeMap::new(),
             file_index_allocator: GoingUpAllocator::new(),
             argv,
+            env,
             file_path: file.path().to_path_buf(),
             current_dir,
             stack_ptr_end: stack_end - 8, // 8 bytes for padding
@@ -213,10 +254,19 @@ impl Process {
             heap_start,
             heap_size,
             heap_max,
+            file_mapped_pages,
             priority: PriorityLevel::Normal,
+            scheduling_class: SchedulingClass::Normal,
+            affinity: ALL_CPUS_AFFINITY,
+            cpu_time: ClockTime::default(),
             exit_code: 0,
             children_exits: BTreeMap::new(),
-        })
+            exit_hooks: Vec::new(),
+        };
+        process.add_default_exit_hooks();
+        procfs::register_process(id, parent_id, process.file_path(), process.memory_stats());
+
+        Ok(process)
     }
 
     /// # Safety
@@ -229,18 +279,27 @@ impl Process {
         self.id
     }
 
-    #[allow(dead_code)]
     pub fn parent_id(&self) -> u64 {
         self.parent_id
     }
 
+    /// The saved registers, only valid when the process is not running
+    pub fn context(&self) -> &ProcessContext {
+        &self.context
+    }
+
     pub fn is_user_address_mapped(&self, address: usize) -> bool {
         self.vm.is_address_mapped(address)
     }
 
+    /// Copy to or from the memory of this process, see [`VirtualMemoryMapper::copy_user_memory`]
+    pub fn copy_user_memory(&mut self, address: usize, access: UserMemoryAccess) -> usize {
+        self.vm.copy_user_memory(address, access)
+    }
+
     pub fn finish_stdio(&mut self) {
-        // make sure we have STDIN/STDOUT/STDERR, and the allocator is after them
-        assert!(self.open_filesystem_nodes.len() >= 3);
+        // make sure the allocator is after STDIN/STDOUT/STDERR, some of them may be missing
+        // if they were close-on-spawn in the parent, but their fds are still reserved
         if self.file_index_allocator.next_id.load(Ordering::Relaxed) < 3 {
             self.file_index_allocator
                 .next_id
@@ -248,54 +307,110 @@ impl Process {
         }
     }
 
-    pub fn push_fs_node<F: Into<fs::FilesystemNode>>(&mut self, file: F) -> usize {
+    pub fn push_handle<H: Into<Box<dyn KernelHandle>>>(&mut self, handle: H) -> usize {
         let fd = self.file_index_allocator.allocate() as usize;
         assert!(
-            self.open_filesystem_nodes.insert(fd, file.into()).is_none(),
+            self.handles.insert(fd, handle.into()).is_none(),
             "fd already exists"
         );
         fd
     }
 
-    pub fn attach_fs_node_to_fd<F: Into<fs::FilesystemNode>>(
+    pub fn attach_handle_to_fd<H: Into<Box<dyn KernelHandle>>>(
         &mut self,
         fd: usize,
-        file: F,
+        handle: H,
     ) -> bool {
         // fail first
-        if self.open_filesystem_nodes.contains_key(&fd) {
+        if self.handles.contains_key(&fd) {
             return false;
         }
-        // update allocator so that next push_file will not overwrite this fd
+        // update allocator so that next push_handle will not overwrite this fd
         self.file_index_allocator
             .next_id
-            .store(fd as u64 + 1, Ordering::SeqCst);
+            .fetch_max(fd as u64 + 1, Ordering::SeqCst);
         // must always return `true`
-        self.open_filesystem_nodes.insert(fd, file.into()).is_none()
+        self.handles.insert(fd, handle.into()).is_none()
     }
 
-    pub fn get_fs_node(&mut self, fd: usize) -> Option<&mut fs::FilesystemNode> {
-        self.open_filesystem_nodes.get_mut(&fd)
+    pub fn get_handle(&mut self, fd: usize) -> Option<&mut (dyn KernelHandle + 'static)> {
+        self.handles.get_mut(&fd).map(|handle| &mut **handle)
     }
 
-    pub fn take_fs_node(&mut self, fd: usize) -> Option<fs::FilesystemNode> {
-        self.open_filesystem_nodes.remove(&fd)
+    pub fn take_handle(&mut self, fd: usize) -> Option<Box<dyn KernelHandle>> {
+        self.handles.remove(&fd)
     }
 
-    pub fn put_fs_node(&mut self, fd: usize, file: fs::FilesystemNode) {
+    /// Put `handle` in `fd`, returning the handle that was there before if any
+    pub fn replace_handle<H: Into<Box<dyn KernelHandle>>>(
+        &mut self,
+        fd: usize,
+        handle: H,
+    ) -> Option<Box<dyn KernelHandle>> {
+        // make sure the allocator doesn't give this fd later
+        self.file_index_allocator
+            .next_id
+            .fetch_max(fd as u64 + 1, Ordering::SeqCst);
+        self.handles.insert(fd, handle.into())
+    }
+
+    pub fn put_handle(&mut self, fd: usize, handle: Box<dyn KernelHandle>) {
         assert!(
-            self.open_filesystem_nodes.insert(fd, file).is_none(),
+            self.handles.insert(fd, handle).is_none(),
             "fd already exists"
         )
     }
 
-    /// Sets the exit_code and prepare to release the resources held by this process
+    /// Sets the exit_code, the resources held by this process are released by the exit hooks
+    /// when the scheduler calls [`Process::release`].
     /// The scheduler will handle the `state` of the process
     pub fn exit(&mut self, exit_code: i32) {
         self.exit_code = exit_code;
-        // release the vga if we have it
-        if let Some(vga) = vga::controller() {
-            vga.release(self.id);
+    }
+
+    /// Add `hook` to run when the process is released, hooks run in the order of their `stage`,
+    /// and in the order they were added within the same stage.
+    ///
+    /// The scheduler releases a process with [`Process::release`] after it exits, when its memory
+    /// is not used anymore, so subsystems that give resources to a process can clean them up
+    /// without the scheduler knowing about them.
+    pub fn add_exit_hook(
+        &mut self,
+        stage: ExitHookStage,
+        hook: impl FnOnce(&mut Process) + Send + 'static,
+    ) {
+        let index = self.exit_hooks.partition_point(|(s, _)| *s <= stage);
+        self.exit_hooks.insert(index, (stage, Box::new(hook)));
+    }
+
+    /// The cleanup needed by all processes
+    fn add_default_exit_hooks(&mut self) {
+        self.add_exit_hook(ExitHookStage::Resources, |process| {
+            procfs::unregister_process(process.id);
+        });
+        self.add_exit_hook(ExitHookStage::Resources, |process| {
+            // release the vga if we have it
+            if let Some(vga) = vga::controller() {
+                vga.release(process.id);
+            }
+        });
+        self.add_exit_hook(ExitHookStage::Handles, |process| {
+            for handle in core::mem::take(&mut process.handles).into_values() {
+                handle.close();
+            }
+        });
+        self.add_exit_hook(ExitHookStage::Memory, |process| {
+            process.vm.unmap_process_memory();
+        });
+    }
+
+    /// Run the exit hooks and drop the process.
+    ///
+    /// Must be called outside the scheduler lock, as the hooks close handles and release devices,
+    /// which may wake up other processes.
+    pub fn release(mut self) {
+        for (_, hook) in core::mem::take(&mut self.exit_hooks) {
+            hook(&mut self);
         }
     }
 
@@ -321,7 +436,7 @@ impl Process {
 
         assert!(is_aligned(increment.unsigned_abs(), PAGE_4K));
 
-        let new_size = self.heap_size as isize + increment;
+        let new_size = (self.heap_size as isize).checked_add(increment)?;
         if new_size < 0 || new_size as usize > self.heap_max {
             return None;
         }
@@ -350,10 +465,20 @@ impl Process {
             // `true` because we allocated physical memory using `map`
             self.vm.unmap(&entry, true);
         }
+        procfs::update_memory_stats(self.id, self.memory_stats());
 
         Some(old_end)
     }
 
+    pub fn memory_stats(&self) -> ProcessMemoryStats {
+        ProcessMemoryStats {
+            resident_pages: self.vm.mapped_user_pages() as u64,
+            heap_size: self.heap_size as u64,
+            stack_size: self.stack_size as u64,
+            file_mapped_pages: self.file_mapped_pages as u64,
+        }
+    }
+
     pub fn get_current_dir(&self) -> &fs::Directory {
         &self.current_dir
     }
@@ -370,19 +495,88 @@ impl Process {
         self.priority = priority;
     }
 
+    pub fn scheduling_class(&self) -> SchedulingClass {
+        self.scheduling_class
+    }
+
+    pub fn set_scheduling_class(&mut self, scheduling_class: SchedulingClass) {
+        self.scheduling_class = scheduling_class;
+    }
+
+    pub fn affinity(&self) -> u64 {
+        self.affinity
+    }
+
+    pub fn set_affinity(&mut self, affinity: u64) {
+        self.affinity = affinity;
+    }
+
     pub fn file_path(&self) -> &Path {
         self.file_path.as_path()
     }
+
+    pub fn argv(&self) -> &[String] {
+        &self.argv
+    }
+
+    pub fn env(&self) -> &[String] {
+        &self.env
+    }
+
+    pub fn open_files_count(&self) -> usize {
+        self.handles.len()
+    }
 }
 
 impl Process {
+    /// A process without an executable for the kernel tests, it only has the kernel mappings and
+    /// never runs in user mode, the tests map the memory they need with `vm`
+    #[cfg(test)]
+    fn new_for_test(current_dir: fs::Directory, heap_start: usize, heap_max: usize) -> Self {
+        let id = PROCESS_ID_ALLOCATOR.allocate();
+        let mut vm = virtual_memory_mapper::clone_current_vm_as_user();
+        // SAFETY: the vm is only switched to by the tests, which run on the boot stack
+        unsafe { vm.add_process_specific_mappings() };
+
+        let mut process = Self {
+            vm,
+            context: ProcessContext::default(),
+            id,
+            parent_id: id,
+            handles: BTreeMap::new(),
+            file_index_allocator: GoingUpAllocator::new(),
+            argv: Vec::new(),
+            env: Vec::new(),
+            file_path: PathBuf::from("/test_process"),
+            current_dir,
+            stack_ptr_end: 0,
+            stack_size: 0,
+            heap_start,
+            heap_size: 0,
+            heap_max,
+            file_mapped_pages: 0,
+            priority: PriorityLevel::Normal,
+            scheduling_class: SchedulingClass::Normal,
+            affinity: ALL_CPUS_AFFINITY,
+            cpu_time: ClockTime::default(),
+            exit_code: 0,
+            children_exits: BTreeMap::new(),
+            exit_hooks: Vec::new(),
+        };
+        process.add_default_exit_hooks();
+        procfs::register_process(id, id, process.file_path(), process.memory_stats());
+
+        process
+    }
+
     // NOTE: this is very specific to 64bit x86
     fn prepare_stack(
         vm: &mut VirtualMemoryMapper,
         argv: &[String],
+        env: &[String],
         mut rsp: u64,
         stack_top: u64,
-    ) -> (u64, u64, u64) {
+    ) -> (u64, u64, u64, u64) {
         // dealing with vm, so we must disable interrupts
         cpu::cpu().push_cli();
         let old_vm = virtual_memory_mapper::get_current_vm();
@@ -391,6 +585,33 @@ impl Process {
         // SAFETY: this must be called while the current vm and this new vm must share the same
         //         kernel regions
         unsafe { vm.switch_to_this() };
+        // the stack is user memory
+        let user_access = cpu::user_access::allow_user_access();
+
+        // the environment is placed first, at the top of the stack
+        let mut envp = Vec::with_capacity(env.len() + 1);
+        for var in env.iter() {
+            rsp -= var.len() as u64 + 1;
+            let var_ptr = rsp;
+            // align to 8 bytes
+            rsp -= rsp % 8;
+            assert!(rsp >= stack_top);
+
+            let var_ptr_slice =
+                unsafe { core::slice::from_raw_parts_mut(var_ptr as *mut u8, var.len() + 1) };
+            var_ptr_slice[..var.len()].copy_from_slice(var.as_bytes());
+            var_ptr_slice[var.len()] = 0;
+
+            envp.push(var_ptr);
+        }
+        // unlike `argv`, there is no count, so its terminated by a null pointer
+        envp.push(0);
+        let envp_array_ptr = rsp - (envp.len() * 8) as u64;
+        rsp = envp_array_ptr;
+        assert!(rsp >= stack_top);
+        let envp_array_ptr_slice =
+            unsafe { core::slice::from_raw_parts_mut(envp_array_ptr as *mut u64, envp.len()) };
+        envp_array_ptr_slice.copy_from_slice(&envp);
 
         let argc = argv.len();
 
@@ -442,6 +663,7 @@ impl Process {
         assert!(rsp >= stack_top);
         unsafe { (rsp as *mut u64).write(argc as u64) };
 
+        drop(user_access);
         // switch back to the old vm
         unsafe { old_vm.switch_to_this() };
         // we can be interrupted again
@@ -457,7 +679,7 @@ impl Process {
         // second, subtract 8, the call instruction
         rsp -= 8;
 
-        (rsp, argc as u64, argv_array_ptr)
+        (rsp, argc as u64, argv_array_ptr, envp_array_ptr)
     }
 
     fn write_process_meta(
@@ -465,29 +687,58 @@ impl Process {
         process_meta_addr: usize,
         process_meta: ProcessMetadata,
     ) {
-        // dealing with vm, so we must disable interrupts
-        cpu::cpu().push_cli();
-        let old_vm = virtual_memory_mapper::get_current_vm();
-
-        // switch temporarily so we can map the elf
-        // SAFETY: this must be called while the current vm and this new vm must share the same
-        //         kernel regions
-        unsafe { vm.switch_to_this() };
-
-        // write the process meta
-        let process_meta_ptr = process_meta_addr as *mut ProcessMetadata;
+        // the page is read-only for the user, so write it through the kernel mapping
+        let physical_address = vm
+            .virtual_to_physical(process_meta_addr)
+            .expect("process meta must be mapped");
+        let process_meta_ptr = physical2virtual(physical_address) as *mut ProcessMetadata;
+        // SAFETY: the page is allocated for this process, and it fits `ProcessMetadata`
         unsafe { process_meta_ptr.write(process_meta) };
-
-        // switch back to the old vm
-        unsafe { old_vm.switch_to_this() };
-        // we can be interrupted again
-        cpu::cpu().pop_cli();
     }
 }
 
 impl Drop for Process {
     fn drop(&mut self) {
         assert!(!self.vm.is_used_by_me());
-        self.vm.unmap_process_memory();
+        assert!(
+            self.exit_hooks.is_empty(),
+            "process {} dropped without being released",
+            self.id
+        );
     }
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_exit_hooks_order() {
+    use alloc::sync::Arc;
+
+    use crate::{
+        fs::{DirectoryNode, FileAttributes},
+        sync::spin::mutex::Mutex,
+    };
+
+    let root_dir = fs::Directory::from_inode(
+        DirectoryNode::without_parent(String::from("/"), FileAttributes::DIRECTORY, 0),
+        "/",
+        fs::empty_filesystem(),
+        0,
+    )
+    .expect("This is a directory, shouldn't fail");
+    let mut process = Process::new_for_test(root_dir, GB, PAGE_4K);
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let hook = |name: &'static str| {
+        let order = order.clone();
+        move |_: &mut Process| order.lock().push(name)
+    };
+    process.add_exit_hook(ExitHookStage::Memory, hook("memory"));
+    process.add_exit_hook(ExitHookStage::Resources, hook("resources 1"));
+    process.add_exit_hook(ExitHookStage::Handles, hook("handles"));
+    process.add_exit_hook(ExitHookStage::Resources, hook("resources 2"));
+    process.release();
+
+    assert_eq!(
+        *order.lock(),
+        ["resources 1", "resources 2", "handles", "memory"]
+    );
+}
@@ -0,0 +1,373 @@
+//! Process information filesystem, mounted at `/proc`
+//!
+//! Contains a directory for each process (named by its `pid`) with a `status` file, and
+//! a `meminfo` file for the system wide memory usage (physical memory, kernel heap, virtual space, etc.),
+//! a `stat` file with the uptime and the idle time of each CPU, a `mounts` file with the mounted filesystems,
+//! and an `interrupts` file with the number of times each interrupt vector was received.
+//!
+//! The process information is stored in a separate registry and not read from the scheduler,
+//! since the scheduler lock may be held while reading from files.
+
+use core::fmt;
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use kernel_user_link::process::{MemInfo, ProcessMemoryStats};
+
+use crate::{
+    cpu::{self, interrupts},
+    devices::{clock, Device},
+    fs::{
+        self, DirTreverse, DirectoryNode, FileAttributes, FileNode, FileSystem, FileSystemError,
+        Node,
+    },
+    housekeeping,
+    memory_management::{
+        kernel_heap_allocator::ALLOCATOR,
+        memory_layout::{KERNEL_HEAP_SIZE, PAGE_4K},
+        physical_page_allocator, shared_pages, virtual_space,
+    },
+    sync::spin::mutex::Mutex,
+};
+
+static PROCESSES: Mutex<BTreeMap<u64, ProcessEntry>> = Mutex::new(BTreeMap::new());
+
+const PROC_FILESYSTEM_ROOT_INODE_MAGIC: u64 = 0x960cf50007;
+/// The rest of the bits are the `pid`
+const PROC_FILESYSTEM_PID_DIR_INODE_FLAG: u64 = 1 << 63;
+
+struct ProcessEntry {
+    parent_id: u64,
+    file_path: String,
+    memory: ProcessMemoryStats,
+}
+
+pub fn register_process(
+    pid: u64,
+    parent_id: u64,
+    file_path: &fs::path::Path,
+    memory: ProcessMemoryStats,
+) {
+    let old = PROCESSES.lock().insert(
+        pid,
+        ProcessEntry {
+            parent_id,
+            file_path: String::from(file_path.as_str()),
+            memory,
+        },
+    );
+    assert!(old.is_none(), "process {pid} already registered");
+}
+
+pub fn unregister_process(pid: u64) {
+    PROCESSES.lock().remove(&pid);
+}
+
+pub fn update_memory_stats(pid: u64, memory: ProcessMemoryStats) {
+    if let Some(entry) = PROCESSES.lock().get_mut(&pid) {
+        entry.memory = memory;
+    }
+}
+
+pub fn process_memory_stats(pid: u64) -> Option<ProcessMemoryStats> {
+    PROCESSES.lock().get(&pid).map(|entry| entry.memory)
+}
+
+/// Displays the process `pid` with its executable path, for error messages.
+///
+/// It doesn't wait for the lock, so it can be used from exception handlers,
+/// `None` is the kernel (no process running)
+pub struct ProcessName(pub Option<u64>);
+
+impl fmt::Display for ProcessName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(pid) = self.0 else {
+            return write!(f, "kernel");
+        };
+        write!(f, "pid {pid}")?;
+        if let Some(processes) = PROCESSES.try_lock() {
+            if let Some(entry) = processes.get(&pid) {
+                write!(f, " ({})", entry.file_path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// System wide memory information, `process` is left empty
+pub fn system_meminfo() -> MemInfo {
+    let (free, used) = physical_page_allocator::stats();
+    MemInfo {
+        page_size: PAGE_4K as u64,
+        total_pages: (free + used) as u64,
+        free_pages: free as u64,
+        process: ProcessMemoryStats::default(),
+    }
+}
+
+fn process_status(pid: u64) -> Option<String> {
+    let processes = PROCESSES.lock();
+    let entry = processes.get(&pid)?;
+    let kb = |pages: u64| pages * PAGE_4K as u64 / 1024;
+
+    Some(format!(
+        "Name:\t{}\nPid:\t{}\nPPid:\t{}\nVmRSS:\t{} kB\nVmHeap:\t{} kB\nVmStack:\t{} kB\nVmFile:\t{} kB\n",
+        entry.file_path,
+        pid,
+        entry.parent_id,
+        kb(entry.memory.resident_pages),
+        entry.memory.heap_size / 1024,
+        entry.memory.stack_size / 1024,
+        kb(entry.memory.file_mapped_pages),
+    ))
+}
+
+fn meminfo() -> String {
+    let info = system_meminfo();
+    let kb = |pages: u64| pages * info.page_size / 1024;
+    let heap = ALLOCATOR.stats();
+    let (virtual_blocks, virtual_used, virtual_free) = virtual_space::stats();
+    let (shared_pages, shared_refs) = shared_pages::stats();
+    let (processes, processes_resident) = {
+        let processes = PROCESSES.lock();
+        let resident = processes
+            .values()
+            .map(|entry| entry.memory.resident_pages)
+            .sum::<u64>();
+        (processes.len(), resident)
+    };
+
+    format!(
+        "MemTotal:\t{} kB\nMemFree:\t{} kB\nMemUsed:\t{} kB\n\
+         HeapMax:\t{} kB\nHeapSize:\t{} kB\nHeapUsed:\t{} kB\nHeapFree:\t{} kB\n\
+         VirtualBlocks:\t{}\nVirtualUsed:\t{} kB\nVirtualFree:\t{} kB\n\
+         SharedPages:\t{} kB\nSharedRefs:\t{}\n\
+         Processes:\t{}\nProcessesRSS:\t{} kB\n",
+        kb(info.total_pages),
+        kb(info.free_pages),
+        kb(info.used_pages()),
+        KERNEL_HEAP_SIZE / 1024,
+        heap.heap_size / 1024,
+        heap.allocated / 1024,
+        heap.free_size / 1024,
+        virtual_blocks,
+        virtual_used / 1024,
+        virtual_free / 1024,
+        kb(shared_pages as u64),
+        shared_refs,
+        processes,
+        kb(processes_resident),
+    )
+}
+
+/// The uptime, the idle time of each CPU, and the duration of the [`housekeeping`] runs,
+/// in nanoseconds
+fn stat() -> String {
+    let mut content = format!(
+        "Uptime:\t{} ns\n",
+        clock::clocks().time_since_startup().as_nanos()
+    );
+    for (id, idle_time) in cpu::idle_times() {
+        content += &format!("Cpu{id}Idle:\t{} ns\n", idle_time.as_nanos());
+    }
+    let (last, longest) = housekeeping::durations();
+    content += &format!(
+        "HousekeepingLast:\t{} ns\nHousekeepingLongest:\t{} ns\n",
+        last.as_nanos(),
+        longest.as_nanos()
+    );
+    content
+}
+
+/// The mount point and the type of each mounted filesystem
+fn mounts() -> String {
+    let mut content = String::new();
+    fs::mapping::on_all_mappings(|path, filesystem| {
+        content += &format!("{}\t{}\n", path.display(), filesystem.type_name());
+    });
+    content
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ProcFileKind {
+    Status(u64),
+    MemInfo,
+    Stat,
+    Mounts,
+    Interrupts,
+}
+
+/// A `/proc` file, the content is generated when opening the file, so reading it
+/// multiple times gives consistent results.
+///
+/// `meminfo`, `stat`, `mounts` and `interrupts` are generated again when reading from the start, so monitoring tools can
+/// seek back to `0` and read them again without reopening them.
+#[derive(Debug)]
+struct ProcFile {
+    kind: ProcFileKind,
+    content: Option<Mutex<String>>,
+}
+
+impl ProcFile {
+    fn new(kind: ProcFileKind) -> Self {
+        Self {
+            kind,
+            content: None,
+        }
+    }
+}
+
+impl Device for ProcFile {
+    fn name(&self) -> &str {
+        match self.kind {
+            ProcFileKind::Status(_) => "status",
+            ProcFileKind::MemInfo => "meminfo",
+            ProcFileKind::Stat => "stat",
+            ProcFileKind::Mounts => "mounts",
+            ProcFileKind::Interrupts => "interrupts",
+        }
+    }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        let mut content = self
+            .content
+            .as_ref()
+            .ok_or(FileSystemError::ReadNotSupported)?
+            .lock();
+        if offset == 0 {
+            match self.kind {
+                ProcFileKind::MemInfo => *content = meminfo(),
+                ProcFileKind::Stat => *content = stat(),
+                ProcFileKind::Mounts => *content = mounts(),
+                ProcFileKind::Interrupts => *content = interrupts::stats::report(),
+                ProcFileKind::Status(_) => {}
+            }
+        }
+        let content = content.as_bytes();
+        if offset >= content.len() as u64 {
+            return Ok(0);
+        }
+        let remaining = &content[offset as usize..];
+        let count = remaining.len().min(buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        Ok(count as u64)
+    }
+
+    fn try_create(&self) -> Option<Result<Arc<dyn Device>, FileSystemError>> {
+        let content = match self.kind {
+            ProcFileKind::Status(pid) => match process_status(pid) {
+                Some(content) => content,
+                None => return Some(Err(FileSystemError::FileNotFound)),
+            },
+            ProcFileKind::MemInfo => meminfo(),
+            ProcFileKind::Stat => stat(),
+            ProcFileKind::Mounts => mounts(),
+            ProcFileKind::Interrupts => interrupts::stats::report(),
+        };
+
+        Some(Ok(Arc::new(ProcFile {
+            kind: self.kind,
+            content: Some(Mutex::new(content)),
+        })))
+    }
+}
+
+pub struct ProcFileSystem;
+
+impl FileSystem for ProcFileSystem {
+    fn type_name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn open_root(&self) -> Result<DirectoryNode, FileSystemError> {
+        Ok(DirectoryNode::without_parent(
+            String::from("/"),
+            FileAttributes::DIRECTORY,
+            PROC_FILESYSTEM_ROOT_INODE_MAGIC,
+        ))
+    }
+
+    fn read_dir(
+        &self,
+        inode: &DirectoryNode,
+        handler: &mut dyn FnMut(Node) -> DirTreverse,
+    ) -> Result<(), FileSystemError> {
+        let cluster = inode.start_cluster();
+
+        if cluster == PROC_FILESYSTEM_ROOT_INODE_MAGIC {
+            let meminfo = FileNode::new_device(
+                String::from("meminfo"),
+                FileAttributes::READ_ONLY,
+                Arc::new(ProcFile::new(ProcFileKind::MemInfo)),
+            );
+            if let DirTreverse::Stop = handler(meminfo.into()) {
+                return Ok(());
+            }
+            let stat = FileNode::new_device(
+                String::from("stat"),
+                FileAttributes::READ_ONLY,
+                Arc::new(ProcFile::new(ProcFileKind::Stat)),
+            );
+            if let DirTreverse::Stop = handler(stat.into()) {
+                return Ok(());
+            }
+            let mounts = FileNode::new_device(
+                String::from("mounts"),
+                FileAttributes::READ_ONLY,
+                Arc::new(ProcFile::new(ProcFileKind::Mounts)),
+            );
+            if let DirTreverse::Stop = handler(mounts.into()) {
+                return Ok(());
+            }
+            let interrupts = FileNode::new_device(
+                String::from("interrupts"),
+                FileAttributes::READ_ONLY,
+                Arc::new(ProcFile::new(ProcFileKind::Interrupts)),
+            );
+            if let DirTreverse::Stop = handler(interrupts.into()) {
+                return Ok(());
+            }
+
+            // collect first, so we don't hold the lock while calling the handler
+            let pids = PROCESSES.lock().keys().copied().collect::<Vec<_>>();
+            for pid in pids {
+                let node = DirectoryNode::without_parent(
+                    pid.to_string(),
+                    FileAttributes::DIRECTORY | FileAttributes::READ_ONLY,
+                    PROC_FILESYSTEM_PID_DIR_INODE_FLAG | pid,
+                );
+                if let DirTreverse::Stop = handler(node.into()) {
+                    break;
+                }
+            }
+            Ok(())
+        } else if cluster & PROC_FILESYSTEM_PID_DIR_INODE_FLAG != 0 {
+            let pid = cluster & !PROC_FILESYSTEM_PID_DIR_INODE_FLAG;
+            if !PROCESSES.lock().contains_key(&pid) {
+                return Err(FileSystemError::FileNotFound);
+            }
+            let status = FileNode::new_device(
+                String::from("status"),
+                FileAttributes::READ_ONLY,
+                Arc::new(ProcFile::new(ProcFileKind::Status(pid))),
+            );
+            handler(status.into());
+            Ok(())
+        } else {
+            Err(FileSystemError::FileNotFound)
+        }
+    }
+}
+
+pub fn init_procfs_mapping() {
+    fs::mapping::mount("/proc", Arc::new(ProcFileSystem)).expect("Mapping failed");
+}
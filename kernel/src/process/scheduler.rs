@@ -4,29 +4,48 @@ use core::{
     sync::atomic::{AtomicBool, Ordering},
 };
 
-use alloc::{
-    boxed::Box,
-    collections::{BTreeMap, BinaryHeap},
-    vec::Vec,
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use kernel_user_link::{
+    file::{DirFilename, MAX_FILENAME_LEN},
+    process::{
+        PriorityLevel, ProcessInfo, ProcessState as UserProcessState, SchedulingClass,
+        KILLED_EXIT_CODE,
+    },
 };
-use tracing::{info, trace};
+use tracing::{error, info, trace};
 
 use crate::{
     cpu::{self, idt::InterruptAllSavedState, interrupts},
     devices::clock::{self, ClockTime},
-    memory_management::virtual_memory_mapper,
+    housekeeping,
+    memory_management::{kernel_stack, virtual_memory_mapper},
     process::{syscalls, FxSave},
-    sync::spin::mutex::Mutex,
+    sync::spin::{
+        self,
+        mutex::Mutex,
+        ticket::{TicketMutex, TicketMutexGuard},
+    },
+    tracepoints::{self, Event},
 };
 
+use self::policy::{Prioritized, RunQueue};
 use super::{Process, ProcessContext};
 
-static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+mod policy;
+#[cfg(test)]
+mod simulation;
+mod wait_queue;
+
+pub use wait_queue::WaitQueue;
+
+/// Fair, since all CPUs keep taking it, see [`lock_scheduler`]
+static SCHEDULER: TicketMutex<Scheduler> = TicketMutex::new(Scheduler::new());
 static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+/// Processes woken by a [`WaitQueue`], rescheduled the next time the scheduler runs
+static IO_WAKES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
 
-// an arbitrary value to reset the priority counters
-// we don't want to get to 0, as it will result in underflow on subtract
-const MIN_PRIORITY_VALUE: u64 = 100;
+/// The length of `int 0xFE`, used to run the syscall again after waiting on a [`WaitQueue`]
+const SYSCALL_INSTRUCTION_LEN: u64 = 2;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProcessState {
@@ -34,6 +53,8 @@ pub enum ProcessState {
     Scheduled,
     WaitingForPid(u64),
     WaitingForTime(ClockTime),
+    /// Parked on a [`WaitQueue`] until it's woken
+    WaitingForIo,
 }
 
 /// A wrapper around [`Process`] that has extra details the scheduler cares about
@@ -41,44 +62,71 @@ struct SchedulerProcess {
     // using box here so that moving this around won't be as expensive
     process: RefCell<Box<Process>>,
     state: ProcessState,
-    priority_counter: u64,
+    /// When did the process start running, valid when `state` is [`ProcessState::Running`]
+    running_since: ClockTime,
+    /// Killed while running on another CPU, it will exit when it stops running
+    killed: bool,
 }
 
-impl PartialEq for SchedulerProcess {
-    fn eq(&self, other: &Self) -> bool {
-        self.priority_counter == other.priority_counter
+impl SchedulerProcess {
+    fn info(&self) -> ProcessInfo {
+        let inner_proc = self.process.borrow();
+        let mut cpu_time = inner_proc.cpu_time;
+        let state = match self.state {
+            ProcessState::Running => {
+                cpu_time += clock::clocks().time_since_startup() - self.running_since;
+                UserProcessState::Running
+            }
+            ProcessState::Scheduled => UserProcessState::Scheduled,
+            ProcessState::WaitingForPid(_) => UserProcessState::WaitingForPid,
+            ProcessState::WaitingForTime(_) => UserProcessState::Sleeping,
+            ProcessState::WaitingForIo => UserProcessState::WaitingForIo,
+        };
+        let name = inner_proc.file_path().file_name().unwrap_or("");
+        // leave space for the null terminator
+        let mut name_len = name.len().min(MAX_FILENAME_LEN - 1);
+        while !name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+
+        ProcessInfo {
+            pid: inner_proc.id,
+            parent_id: inner_proc.parent_id,
+            state,
+            priority: inner_proc.priority,
+            cpu_time: cpu_time.as_nanos(),
+            heap_size: inner_proc.heap_size as u64,
+            open_files: inner_proc.open_files_count() as u64,
+            affinity: inner_proc.affinity(),
+            name: DirFilename::from(&name[..name_len]),
+        }
     }
 }
 
-impl PartialOrd for SchedulerProcess {
-    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        Some(self.cmp(other))
+impl Prioritized for SchedulerProcess {
+    fn priority(&self) -> PriorityLevel {
+        self.process.borrow().priority
     }
-}
 
-impl Eq for SchedulerProcess {}
-impl Ord for SchedulerProcess {
-    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        self.priority_counter.cmp(&other.priority_counter)
+    fn scheduling_class(&self) -> SchedulingClass {
+        self.process.borrow().scheduling_class
     }
 }
 
 struct Scheduler {
     interrupt_initialized: bool,
-    scheduled_processes: BinaryHeap<SchedulerProcess>,
+    scheduled_processes: RunQueue<SchedulerProcess>,
     running_waiting_procs: BTreeMap<u64, SchedulerProcess>,
     exited_processes: Vec<Process>,
-    max_priority: u64,
 }
 
 impl Scheduler {
     const fn new() -> Self {
         Self {
             interrupt_initialized: false,
-            scheduled_processes: BinaryHeap::new(),
+            scheduled_processes: RunQueue::new(),
             running_waiting_procs: BTreeMap::new(),
             exited_processes: Vec::new(),
-            max_priority: u64::MAX,
         }
     }
 
@@ -87,7 +135,8 @@ impl Scheduler {
         self.reschedule_process(SchedulerProcess {
             process: RefCell::new(Box::new(process)),
             state: ProcessState::Scheduled,
-            priority_counter: self.max_priority,
+            running_since: ClockTime::default(),
+            killed: false,
         })
     }
 
@@ -112,26 +161,23 @@ impl Scheduler {
             self.exited_processes.push(*inner_proc);
             return;
         }
-        process.priority_counter = self.max_priority;
+        if process.killed {
+            self.exit_killed_process(process);
+            return;
+        }
         process.state = ProcessState::Scheduled;
         self.scheduled_processes.push(process);
     }
 
-    fn reset_scheduled_processes_counters(&mut self) {
-        let max_priority = u64::MAX;
-        self.scheduled_processes = self
-            .scheduled_processes
-            .drain()
-            .map(|mut p| {
-                p.priority_counter = max_priority;
-                p
-            })
-            .collect::<BinaryHeap<_>>();
-    }
-
-    fn try_wake_waiting_processes(&mut self) {
+    /// Wake the processes that are done waiting, and take the exited processes out after
+    /// notifying their parents, the caller must [`Process::release`] them after dropping the lock
+    #[must_use]
+    fn try_wake_waiting_processes(&mut self) -> Vec<Process> {
         let time_now = clock::clocks().time_since_startup();
 
+        let io_wakes = mem::take(&mut *IO_WAKES.lock());
+        self.wake_io_waiters(&io_wakes);
+
         // First, check waiting processes
         let extracted = self
             .running_waiting_procs
@@ -139,7 +185,9 @@ impl Scheduler {
                 let mut remove = false;
                 let mut inner_proc = process.process.borrow_mut();
                 match process.state {
-                    ProcessState::WaitingForPid(_) | ProcessState::Running => {
+                    ProcessState::WaitingForPid(_)
+                    | ProcessState::WaitingForIo
+                    | ProcessState::Running => {
                         self.exited_processes.retain_mut(|exited_proc| {
                             let found_parent = exited_proc.parent_id == inner_proc.id;
 
@@ -183,8 +231,8 @@ impl Scheduler {
         }
 
         // here are processes with parent either in `scheduled_processes` or already gone
-        //let mut scheduled_list = self.scheduled_processes.drain().collect::<Vec<_>>();
-        for exited_proc in self.exited_processes.drain(..) {
+        let exited_processes = mem::take(&mut self.exited_processes);
+        for exited_proc in exited_processes.iter() {
             for process in self.scheduled_processes.iter() {
                 let mut inner_proc = process.process.borrow_mut();
                 if inner_proc.id == exited_proc.parent_id {
@@ -193,37 +241,110 @@ impl Scheduler {
             }
         }
 
-        // we can clear here, since we don't use the vm of the process anymore
-        self.exited_processes.clear();
+        // we don't use the vm of these processes anymore, they can be released
+        exited_processes
+    }
+
+    /// Reschedule the processes in `pids` that are still waiting for IO, the others were killed
+    /// or woken by another queue
+    fn wake_io_waiters(&mut self, pids: &[u64]) {
+        for pid in pids {
+            let waiting = self
+                .running_waiting_procs
+                .get(pid)
+                .is_some_and(|process| process.state == ProcessState::WaitingForIo);
+            if waiting {
+                trace!("Process {} is woken from IO", pid);
+                let process = self.running_waiting_procs.remove(pid).unwrap();
+                self.reschedule_process(process);
+            }
+        }
+    }
+
+    fn exit_killed_process(&mut self, process: SchedulerProcess) {
+        let mut inner_proc = process.process.into_inner();
+        info!("Process {} was killed", inner_proc.id);
+        inner_proc.exit(KILLED_EXIT_CODE);
+        self.exited_processes.push(*inner_proc);
     }
 
     /// Exits all non-running (waiting and scheduled) processes.
     /// The [`schedule`] function will return when all processes are done.
     fn exit_idle_processes(&mut self) {
         // TODO: implement graceful shutdown and wait for processes to exit
-        for process in self.scheduled_processes.drain() {
+        let scheduled = self.scheduled_processes.drain().collect::<Vec<_>>();
+        // shutdown the waiting processes
+        let waiting = self
+            .running_waiting_procs
+            .extract_if(|_, process| match process.state {
+                ProcessState::Running => false,
+                ProcessState::Scheduled
+                | ProcessState::WaitingForPid(_)
+                | ProcessState::WaitingForTime(_)
+                | ProcessState::WaitingForIo => true,
+            })
+            .map(|(_, process)| process)
+            .collect::<Vec<_>>();
+
+        for process in scheduled.into_iter().chain(waiting) {
             let mut inner_proc = process.process.into_inner();
             info!("Force stopping process {}", inner_proc.id);
             inner_proc.exit(0);
+            // released with the other exited processes
+            self.exited_processes.push(*inner_proc);
         }
-        // shutdown the waiting processes
-        self.running_waiting_procs
-            .retain(|_, process| match process.state {
-                ProcessState::Running => true,
-                ProcessState::Scheduled
-                | ProcessState::WaitingForPid(_)
-                | ProcessState::WaitingForTime(_) => {
-                    let mut inner_proc = process.process.borrow_mut();
-                    info!("Force stopping process {}", inner_proc.id);
-                    inner_proc.exit(0);
-                    false
-                }
-            });
     }
 }
 
+/// The scheduler lock is taken from the interrupts, so if it's never released, report it
+/// instead of hanging all the CPUs
+#[track_caller]
+fn lock_scheduler() -> TicketMutexGuard<'static, Scheduler> {
+    SCHEDULER.lock_timeout(spin::LOCK_TIMEOUT_SPINS)
+}
+
 pub fn push_process(process: Process) {
-    SCHEDULER.lock().push_process(process);
+    lock_scheduler().push_process(process);
+}
+
+/// Run `f` with `process` as the current process of this CPU and its memory active, then drop it.
+///
+/// For the kernel tests, which run without the scheduler, `f` must not leave the process,
+/// i.e. exit, sleep or wait.
+#[cfg(test)]
+pub fn run_as_current_process<U>(process: Process, f: impl FnOnce() -> U) -> U {
+    let current_cpu = cpu::cpu();
+    let old_pid = current_cpu.process_id;
+    let pid = process.id;
+
+    // SAFETY: the tests run on the boot stack, which is mapped in all the vms
+    unsafe { process.vm.switch_to_this() };
+    current_cpu.process_id = pid;
+    // as if the process is running, the context is never switched to
+    let old_context = current_cpu.context.replace(ProcessContext::default());
+    lock_scheduler().running_waiting_procs.insert(
+        pid,
+        SchedulerProcess {
+            process: RefCell::new(Box::new(process)),
+            state: ProcessState::Running,
+            running_since: ClockTime::default(),
+            killed: false,
+        },
+    );
+
+    let result = f();
+
+    let process = lock_scheduler()
+        .running_waiting_procs
+        .remove(&pid)
+        .expect("test process not found");
+    current_cpu.process_id = old_pid;
+    current_cpu.context = old_context;
+    // SAFETY: same as above, and the process is not used after this
+    unsafe { virtual_memory_mapper::switch_to_kernel() };
+    process.process.into_inner().release();
+
+    result
 }
 
 /// What this function does is that it tells the scheduler to stop scheduling any more processes.
@@ -232,14 +353,65 @@ pub fn stop_scheduler() {
     SHUTDOWN.store(true, Ordering::Relaxed);
 }
 
+/// Call `f` with all the processes and their state, the process is `None` if its in use.
+/// Used for diagnostics and debugging, so it gives up and returns `false` if the scheduler is locked
+pub fn try_for_each_process(mut f: impl FnMut(Option<&Process>, ProcessState)) -> bool {
+    let Some(scheduler) = SCHEDULER.try_lock() else {
+        return false;
+    };
+    let processes = scheduler
+        .scheduled_processes
+        .iter()
+        .chain(scheduler.running_waiting_procs.values());
+    for process in processes {
+        match process.process.try_borrow() {
+            Ok(inner_proc) => f(Some(&inner_proc), process.state),
+            Err(_) => f(None, process.state),
+        }
+    }
+    true
+}
+
+/// Log all the processes and their state, used for diagnostics when the system is stuck
+pub fn log_processes() {
+    let done = try_for_each_process(|process, state| match process {
+        Some(process) => error!(
+            "  process {} (parent {}) {:?}: {:?}",
+            process.id(),
+            process.parent_id(),
+            process.file_path(),
+            state
+        ),
+        None => error!("  process [in use]: {state:?}"),
+    });
+    if !done {
+        error!("Scheduler is locked, can't list the processes");
+    }
+}
+
+/// A snapshot of all the processes, sorted by `pid`
+pub fn process_list() -> Vec<ProcessInfo> {
+    let scheduler = lock_scheduler();
+    let mut list = scheduler
+        .scheduled_processes
+        .iter()
+        .chain(scheduler.running_waiting_procs.values())
+        .map(SchedulerProcess::info)
+        .collect::<Vec<_>>();
+    list.sort_unstable_by_key(|info| info.pid);
+    list
+}
+
 pub fn schedule() {
-    SCHEDULER.lock().init_interrupt();
+    lock_scheduler().init_interrupt();
 
     loop {
         let current_cpu = cpu::cpu();
         assert!(current_cpu.context.is_none());
 
-        let mut scheduler = SCHEDULER.lock();
+        housekeeping::run_if_due();
+
+        let mut scheduler = lock_scheduler();
         let shutdown = SHUTDOWN.load(Ordering::Acquire);
         if shutdown {
             scheduler.exit_idle_processes();
@@ -247,34 +419,21 @@ pub fn schedule() {
 
         current_cpu.push_cli();
 
-        scheduler.try_wake_waiting_processes();
-
-        // check if we need to reset the priority counters
-        if scheduler
-            .scheduled_processes
-            .peek()
-            .map(|p| p.priority_counter < MIN_PRIORITY_VALUE)
-            .unwrap_or(false)
-        {
-            scheduler.reset_scheduled_processes_counters();
-        }
+        let exited_processes = scheduler.try_wake_waiting_processes();
 
+        // TODO: skip processes that can't run on this CPU (`affinity`) when we run on multiple CPUs
         let top = scheduler.scheduled_processes.pop();
 
         if let Some(mut top) = top {
             assert_eq!(top.state, ProcessState::Scheduled);
             top.state = ProcessState::Running;
+            top.running_since = clock::clocks().time_since_startup();
             let pid;
             if !shutdown {
                 {
                     let mut inner_proc = top.process.borrow_mut();
                     pid = inner_proc.id;
 
-                    // the higher the value, the lower the priority
-                    let decrement = 6 - inner_proc.priority as u64;
-                    top.priority_counter -= decrement;
-
-                    scheduler.max_priority = top.priority_counter;
                     // SAFETY: we are the scheduler and running in kernel space, so it's safe to switch to this vm
                     // as it has clones of our kernel mappings
                     unsafe { inner_proc.switch_to_this_vm() };
@@ -288,16 +447,23 @@ pub fn schedule() {
             current_cpu.pop_cli();
         }
 
-        if shutdown
+        let done = shutdown
             && scheduler.scheduled_processes.is_empty()
             && scheduler.running_waiting_procs.is_empty()
-            && current_cpu.context.is_none()
-        {
-            break;
-        }
+            && current_cpu.context.is_none();
 
         drop(scheduler);
 
+        // outside the lock, closing the handles of a process may wake up others, which needs
+        // the scheduler
+        for process in exited_processes {
+            process.release();
+        }
+
+        if done {
+            break;
+        }
+
         if current_cpu.context.is_some() {
             // call scheduler_interrupt_handler
             // we are using interrupts to switch context since it allows us to save the registers of exit, which is
@@ -305,22 +471,32 @@ pub fn schedule() {
             // The `sys_exit` syscall changes the context from user to kernel,
             // and because of how we implemented syscalls, the result will be in `rax`, so we tell
             // the compiler to ignore `rax` as it may be clobbered after this call
+            let pid = current_cpu.process_id;
+            tracepoints::record(Event::SchedulerSwitchIn, pid, 0);
             unsafe { core::arch::asm!("int 0xff", out("rax") _) }
+            tracepoints::record(Event::SchedulerSwitchOut, pid, 0);
             // SAFETY: we are not running in any process context, so it's safe to go back to the kernel
             unsafe { virtual_memory_mapper::switch_to_kernel() };
         } else {
             // no process to run, just wait for interrupts
-            unsafe { cpu::halt() };
+            idle(current_cpu);
         }
     }
 }
 
+/// The idle task of the CPU, waits for an interrupt, and accounts the time as the CPU idle time
+fn idle(current_cpu: &mut cpu::Cpu) {
+    let start = clock::clocks().time_since_startup();
+    unsafe { cpu::halt() };
+    current_cpu.idle_time += clock::clocks().time_since_startup() - start;
+}
+
 fn with_current_process_and_state<F, U>(f: F) -> U
 where
     F: FnOnce(&mut SchedulerProcess) -> U,
 {
     let current_cpu = cpu::cpu();
-    let mut scheduler = SCHEDULER.lock();
+    let mut scheduler = lock_scheduler();
     let process = scheduler
         .running_waiting_procs
         .get_mut(&current_cpu.process_id)
@@ -334,8 +510,7 @@ where
 /// causes the `current_process` to be unavailable later on
 unsafe fn take_current_process() -> SchedulerProcess {
     let current_cpu = cpu::cpu();
-    let process = SCHEDULER
-        .lock()
+    let process = lock_scheduler()
         .running_waiting_procs
         .remove(&current_cpu.process_id)
         .expect("current process not found");
@@ -350,23 +525,21 @@ where
     with_current_process_and_state(|p| f(&mut p.process.borrow_mut()))
 }
 
-pub fn with_process<F, U>(pid: u64, f: F) -> U
+/// Runs `f` on the process with `pid`, returns `None` if the process is not found
+/// (for example, it has exited already)
+pub fn try_with_process<F, U>(pid: u64, f: F) -> Option<U>
 where
     F: FnOnce(&mut Process) -> U,
 {
-    let scheduler = SCHEDULER.lock();
-    let process = scheduler
-        .running_waiting_procs
-        .get(&pid)
-        .unwrap_or_else(|| {
-            scheduler
-                .scheduled_processes
-                .iter()
-                .find(|p| p.process.borrow().id == pid)
-                .expect("process not found")
-        });
+    let scheduler = lock_scheduler();
+    let process = scheduler.running_waiting_procs.get(&pid).or_else(|| {
+        scheduler
+            .scheduled_processes
+            .iter()
+            .find(|p| p.process.borrow().id == pid)
+    })?;
     let r = f(&mut process.process.borrow_mut());
-    r
+    Some(r)
 }
 
 /// Exit the current process, and move the `all_state` to the scheduler.
@@ -380,30 +553,37 @@ pub fn exit_current_process(exit_code: i32, all_state: &mut InterruptAllSavedSta
     // SAFETY: called within push_cli and pop_cli
     let process = unsafe { take_current_process() };
 
+    let running_since = process.running_since;
     let mut inner_proc = process.process.into_inner();
 
     trace!("Process {} exited with code {}", inner_proc.id, exit_code);
+    // we are still on its kernel stack
+    kernel_stack::record_process_kernel_stack_usage();
 
     swap_context(current_cpu.context.as_mut().unwrap(), all_state);
     // Even though this context won't run again
     // This may be useful if a process wants to read that context later on.
     // The virtual memory will be cleared once we drop the process
     // thus, we can't drop the process here
-    inner_proc.context = current_cpu.context.take().unwrap();
+    save_current_context(&mut inner_proc, running_since);
     inner_proc.exit(exit_code);
 
-    SCHEDULER.lock().exited_processes.push(*inner_proc);
+    lock_scheduler().exited_processes.push(*inner_proc);
 
     current_cpu.pop_cli();
     // go back to the kernel after the scheduler interrupt
 }
 
 pub fn sleep_current_process(time: ClockTime, all_state: &mut InterruptAllSavedState) {
+    let deadline = clock::clocks().time_since_startup() + time;
+    sleep_current_process_until(deadline, all_state);
+}
+
+/// Sleep until the system time (since startup) reaches `deadline`
+pub fn sleep_current_process_until(deadline: ClockTime, all_state: &mut InterruptAllSavedState) {
     let current_cpu = cpu::cpu();
     assert!(current_cpu.context.is_some());
 
-    let deadline = clock::clocks().time_since_startup() + time;
-
     with_current_process_and_state(|p| {
         current_cpu.push_cli();
         let mut inner_proc = p.process.borrow_mut();
@@ -415,13 +595,55 @@ pub fn sleep_current_process(time: ClockTime, all_state: &mut InterruptAllSavedS
         );
         swap_context(current_cpu.context.as_mut().unwrap(), all_state);
 
-        inner_proc.context = current_cpu.context.take().unwrap();
+        save_current_context(&mut inner_proc, p.running_since);
     });
 
     current_cpu.pop_cli();
     // go back to the kernel after the scheduler interrupt
 }
 
+/// Park the current process on `queue`, and move the `all_state` to the scheduler.
+///
+/// `generation` is the [`WaitQueue::generation`] taken before finding that the resource is not
+/// ready, if the queue was woken since, this returns `false` without parking, and the caller
+/// should check the resource again.
+///
+/// Must be called from a syscall, the process will run the syscall again when it's woken.
+pub fn wait_on_queue(
+    queue: &WaitQueue,
+    generation: u64,
+    all_state: &mut InterruptAllSavedState,
+) -> bool {
+    let current_cpu = cpu::cpu();
+    assert!(current_cpu.context.is_some());
+
+    // the scheduler is locked while registering, so `wake_all` can't run before we are parked
+    let parked = with_current_process_and_state(|p| {
+        current_cpu.push_cli();
+        let mut inner_proc = p.process.borrow_mut();
+        if !queue.register(inner_proc.id, generation) {
+            return false;
+        }
+        p.state = ProcessState::WaitingForIo;
+        trace!("Process {} is waiting for IO", inner_proc.id);
+
+        swap_context(current_cpu.context.as_mut().unwrap(), all_state);
+        save_current_context(&mut inner_proc, p.running_since);
+        // go back to the `int 0xFE` instruction, the registers still have the syscall arguments
+        inner_proc.context.rip -= SYSCALL_INSTRUCTION_LEN;
+        true
+    });
+    current_cpu.pop_cli();
+    // if parked, go back to the kernel after the scheduler interrupt
+    parked
+}
+
+/// Called from [`WaitQueue::wake_all`], takes the `pids` to be rescheduled later,
+/// see [`Scheduler::try_wake_waiting_processes`]
+fn wake_io_waiters(pids: &mut Vec<u64>) {
+    IO_WAKES.lock().append(pids);
+}
+
 pub fn yield_current_if_any(all_state: &mut InterruptAllSavedState) {
     let current_cpu = cpu::cpu();
     // do not yield if we don't have context, or we are in the middle of scheduling
@@ -432,16 +654,16 @@ pub fn yield_current_if_any(all_state: &mut InterruptAllSavedState) {
     // SAFETY: called within push_cli and pop_cli
     let process = unsafe { take_current_process() };
     swap_context(current_cpu.context.as_mut().unwrap(), all_state);
-    process.process.borrow_mut().context = current_cpu.context.take().unwrap();
+    save_current_context(&mut process.process.borrow_mut(), process.running_since);
 
-    SCHEDULER.lock().reschedule_process(process);
+    lock_scheduler().reschedule_process(process);
     current_cpu.pop_cli();
     // go back to the kernel after the scheduler interrupt
 }
 
 pub fn is_process_running(pid: u64) -> bool {
-    let scheduler = SCHEDULER.lock();
-    scheduler
+    let scheduler = lock_scheduler();
+    let is_running = scheduler
         .running_waiting_procs
         .keys()
         .cloned()
@@ -451,7 +673,47 @@ pub fn is_process_running(pid: u64) -> bool {
                 .iter()
                 .map(|p| p.process.borrow().id),
         )
-        .any(|id| id == pid)
+        .any(|id| id == pid);
+    is_running
+}
+
+/// Terminate the process `pid` with [`KILLED_EXIT_CODE`], it must not be the current process.
+/// Returns `false` if the process is not found.
+pub fn kill_process(pid: u64) -> bool {
+    let current_cpu = cpu::cpu();
+    assert_ne!(
+        current_cpu.process_id, pid,
+        "can't kill the current process"
+    );
+    current_cpu.push_cli();
+
+    let mut scheduler = lock_scheduler();
+    let process = match scheduler
+        .scheduled_processes
+        .remove_first(|p| p.process.borrow().id == pid)
+    {
+        Some(process) => Some(process),
+        None => match scheduler.running_waiting_procs.get_mut(&pid) {
+            // running on another CPU, will be handled when it's rescheduled
+            Some(process) if process.state == ProcessState::Running => {
+                process.killed = true;
+                None
+            }
+            Some(_) => scheduler.running_waiting_procs.remove(&pid),
+            None => {
+                drop(scheduler);
+                current_cpu.pop_cli();
+                return false;
+            }
+        },
+    };
+    if let Some(process) = process {
+        scheduler.exit_killed_process(process);
+    }
+    drop(scheduler);
+
+    current_cpu.pop_cli();
+    true
 }
 
 pub fn wait_for_pid(all_state: &mut InterruptAllSavedState, pid: u64) -> bool {
@@ -472,7 +734,7 @@ pub fn wait_for_pid(all_state: &mut InterruptAllSavedState, pid: u64) -> bool {
         trace!("Process {} is waiting for process {}", inner_proc.id, pid);
 
         swap_context(current_cpu.context.as_mut().unwrap(), all_state);
-        inner_proc.context = current_cpu.context.take().unwrap();
+        save_current_context(&mut inner_proc, p.running_since);
     });
 
     current_cpu.pop_cli();
@@ -480,7 +742,16 @@ pub fn wait_for_pid(all_state: &mut InterruptAllSavedState, pid: u64) -> bool {
     true
 }
 
+/// Move the context of the current process from the CPU back to the process (must be swapped first),
+/// and account the time it ran
+fn save_current_context(inner_proc: &mut Process, running_since: ClockTime) {
+    inner_proc.context = cpu::cpu().context.take().unwrap();
+    inner_proc.cpu_time += clock::clocks().time_since_startup() - running_since;
+}
+
 pub fn swap_context(context: &mut ProcessContext, all_state: &mut InterruptAllSavedState) {
+    kernel_stack::check_canaries();
+
     let mut fxsave = FxSave::default();
     unsafe { core::arch::x86_64::_fxsave64(&mut fxsave as *mut FxSave as _) };
     unsafe { core::arch::x86_64::_fxrstor64(context.fxsave.0.as_ptr() as _) };
@@ -537,5 +808,9 @@ extern "cdecl" fn syscall_interrupt_handler(all_state: &mut InterruptAllSavedSta
     let current_cpu = cpu::cpu();
     assert!(current_cpu.context.is_some());
 
+    let syscall_number = all_state.rest.rax;
+    tracepoints::record(Event::SyscallEnter, syscall_number, 0);
     syscalls::handle_syscall(all_state);
+    tracepoints::record(Event::SyscallExit, syscall_number, 0);
+    kernel_stack::check_canaries();
 }
@@ -0,0 +1,145 @@
+//! The scheduling policy, i.e. which process runs next
+//!
+//! This is separated from the processes and the CPU, so that it can be driven by
+//! the [`simulation`](super::simulation) in tests.
+
+use core::mem;
+
+use alloc::collections::BinaryHeap;
+use kernel_user_link::process::{PriorityLevel, SchedulingClass};
+
+// an arbitrary value to reset the priority counters
+// we don't want to get to 0, as it will result in underflow on subtract
+const MIN_PRIORITY_VALUE: u64 = 100;
+/// Batch processes decrement their counter by this factor more than normal processes
+/// with the same priority, so they get a smaller share of the CPU
+const BATCH_DECREMENT_FACTOR: u64 = 2;
+
+/// Anything that can be put in the [`RunQueue`]
+pub trait Prioritized {
+    fn priority(&self) -> PriorityLevel;
+    fn scheduling_class(&self) -> SchedulingClass;
+}
+
+/// How much the counter of `item` goes down each time its picked
+fn counter_decrement(item: &impl Prioritized) -> u64 {
+    // the higher the value, the lower the priority
+    let decrement = 6 - item.priority() as u64;
+    match item.scheduling_class() {
+        SchedulingClass::Normal => decrement,
+        SchedulingClass::Batch => decrement * BATCH_DECREMENT_FACTOR,
+    }
+}
+
+struct Entry<T> {
+    priority_counter: u64,
+    /// Order of insertion, entries with the same counter are picked in the order they were pushed
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority_counter == other.priority_counter && self.seq == other.seq
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.priority_counter
+            .cmp(&other.priority_counter)
+            // lower `seq` is older, and should be on top
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+/// The processes ready to run, ordered by a `priority_counter`
+///
+/// The counter starts at `u64::MAX`, each time an item is picked, its counter is decremented by a value
+/// based on its priority (higher priority decrements less), and items pushed again get the counter of the
+/// last picked item, so a process that was waiting for a long time doesn't hog the CPU when it's back.
+pub struct RunQueue<T> {
+    heap: BinaryHeap<Entry<T>>,
+    max_priority: u64,
+    next_seq: u64,
+}
+
+impl<T: Prioritized> RunQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            max_priority: u64::MAX,
+            next_seq: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(Entry {
+            priority_counter: self.max_priority,
+            seq,
+            item,
+        });
+    }
+
+    /// Pick the next item to run
+    pub fn pop(&mut self) -> Option<T> {
+        // check if we need to reset the priority counters
+        if self
+            .heap
+            .peek()
+            .is_some_and(|e| e.priority_counter < MIN_PRIORITY_VALUE)
+        {
+            self.reset_counters();
+        }
+
+        let mut top = self.heap.pop()?;
+        top.priority_counter -= counter_decrement(&top.item);
+        self.max_priority = top.priority_counter;
+        Some(top.item)
+    }
+
+    fn reset_counters(&mut self) {
+        self.heap = self
+            .heap
+            .drain()
+            .map(|mut e| {
+                e.priority_counter = u64::MAX;
+                e
+            })
+            .collect();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Iterate over the items in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.heap.iter().map(|e| &e.item)
+    }
+
+    /// Remove the first item matching `f` if any, keeping the order of the rest
+    pub fn remove_first(&mut self, mut f: impl FnMut(&T) -> bool) -> Option<T> {
+        let mut entries = mem::take(&mut self.heap).into_vec();
+        let removed = entries
+            .iter()
+            .position(|e| f(&e.item))
+            .map(|i| entries.swap_remove(i).item);
+        self.heap = BinaryHeap::from(entries);
+        removed
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.heap.drain().map(|e| e.item)
+    }
+}
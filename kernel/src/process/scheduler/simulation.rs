@@ -0,0 +1,265 @@
+//! Deterministic simulation of the scheduling [`policy`](super::policy)
+//!
+//! Synthetic processes are scheduled on a virtual clock, where each tick is one time slice, so we can check
+//! the fairness properties of the policy without real processes, timers or CPUs.
+
+use alloc::vec::Vec;
+use kernel_user_link::process::{PriorityLevel, SchedulingClass};
+
+use crate::testing;
+
+use super::policy::{Prioritized, RunQueue};
+
+struct SimProcess {
+    id: usize,
+    priority: PriorityLevel,
+    class: SchedulingClass,
+    /// `None` for a busy process that always uses its whole slice,
+    /// otherwise the number of ticks it sleeps after each run
+    sleep: Option<u64>,
+    runs: u64,
+    /// When it should wake up, if sleeping
+    deadline: Option<u64>,
+    /// Number of ticks between each wake up deadline and the run after it
+    latencies: Vec<u64>,
+}
+
+impl Prioritized for SimProcess {
+    fn priority(&self) -> PriorityLevel {
+        self.priority
+    }
+
+    fn scheduling_class(&self) -> SchedulingClass {
+        self.class
+    }
+}
+
+struct Simulation {
+    now: u64,
+    queue: RunQueue<SimProcess>,
+    sleeping: Vec<SimProcess>,
+    next_id: usize,
+    /// The process that ran in each tick, `None` if the CPU was idle
+    trace: Vec<Option<usize>>,
+}
+
+impl Simulation {
+    fn new() -> Self {
+        Self {
+            now: 0,
+            queue: RunQueue::new(),
+            sleeping: Vec::new(),
+            next_id: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    fn spawn(&mut self, priority: PriorityLevel, sleep: Option<u64>) -> usize {
+        self.spawn_with_class(priority, SchedulingClass::Normal, sleep)
+    }
+
+    fn spawn_with_class(
+        &mut self,
+        priority: PriorityLevel,
+        class: SchedulingClass,
+        sleep: Option<u64>,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.push(SimProcess {
+            id,
+            priority,
+            class,
+            sleep,
+            runs: 0,
+            deadline: None,
+            latencies: Vec::new(),
+        });
+        id
+    }
+
+    /// Run one time slice
+    fn step(&mut self) {
+        // wake in the order they went to sleep, similar to `try_wake_waiting_processes`
+        let mut i = 0;
+        while i < self.sleeping.len() {
+            if self.sleeping[i].deadline.is_some_and(|d| d <= self.now) {
+                let process = self.sleeping.remove(i);
+                self.queue.push(process);
+            } else {
+                i += 1;
+            }
+        }
+
+        let Some(mut process) = self.queue.pop() else {
+            self.trace.push(None);
+            self.now += 1;
+            return;
+        };
+
+        self.trace.push(Some(process.id));
+        process.runs += 1;
+        if let Some(deadline) = process.deadline.take() {
+            process.latencies.push(self.now - deadline);
+        }
+        self.now += 1;
+
+        match process.sleep {
+            Some(sleep) => {
+                process.deadline = Some(self.now + sleep);
+                self.sleeping.push(process);
+            }
+            None => self.queue.push(process),
+        }
+    }
+
+    fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    fn process(&self, id: usize) -> &SimProcess {
+        self.queue
+            .iter()
+            .chain(self.sleeping.iter())
+            .find(|p| p.id == id)
+            .expect("process not found")
+    }
+
+    /// The indices of the ticks where `id` ran
+    fn runs_of(&self, id: usize) -> Vec<usize> {
+        self.trace
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| **p == Some(id))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// The counter decrement of a process with `priority`, must match [`RunQueue::pop`]
+fn decrement(priority: PriorityLevel) -> usize {
+    6 - priority as usize
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_scheduler_priority_ordering() {
+    let mut sim = Simulation::new();
+    let high = sim.spawn(PriorityLevel::VeryHigh, None);
+    let normal = sim.spawn(PriorityLevel::Normal, None);
+    let low = sim.spawn(PriorityLevel::VeryLow, None);
+
+    sim.run(600);
+
+    let high = sim.process(high).runs;
+    let normal = sim.process(normal).runs;
+    let low = sim.process(low).runs;
+    assert!(high > normal, "high={high} normal={normal}");
+    assert!(normal > low, "normal={normal} low={low}");
+    assert!(low > 0);
+    assert_eq!(high + normal + low, 600);
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_scheduler_no_starvation() {
+    const HIGH_PROCESSES: usize = 4;
+    // every time the low process is passed, the high processes gain `decrement(VeryHigh)`
+    // on it, so it can't wait more than this
+    let bound = HIGH_PROCESSES * decrement(PriorityLevel::VeryLow) + 1;
+
+    let mut sim = Simulation::new();
+    let low = sim.spawn(PriorityLevel::VeryLow, None);
+    for _ in 0..HIGH_PROCESSES {
+        sim.spawn(PriorityLevel::VeryHigh, None);
+    }
+
+    sim.run(1000);
+
+    let runs = sim.runs_of(low);
+    assert!(!runs.is_empty());
+    assert!(runs[0] <= bound, "first run at {}", runs[0]);
+    for gap in runs.windows(2).map(|w| w[1] - w[0]) {
+        assert!(gap <= bound, "gap of {gap} ticks, bound is {bound}");
+    }
+    let last_gap = sim.trace.len() - runs.last().unwrap();
+    assert!(last_gap <= bound);
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_scheduler_wake_latency() {
+    const HIGH_PROCESSES: usize = 3;
+
+    let mut sim = Simulation::new();
+    for _ in 0..HIGH_PROCESSES {
+        sim.spawn(PriorityLevel::VeryHigh, None);
+    }
+    // a woken process gets the counter of the last picked process, so even with the lowest priority,
+    // it only waits for the processes already ahead of it
+    let sleeper = sim.spawn(PriorityLevel::VeryLow, Some(5));
+
+    sim.run(1000);
+
+    let latencies = &sim.process(sleeper).latencies;
+    assert!(latencies.len() > 100, "only {} wake ups", latencies.len());
+    let max_latency = latencies.iter().copied().max().unwrap();
+    assert!(
+        max_latency <= HIGH_PROCESSES as u64,
+        "max latency {max_latency}"
+    );
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_scheduler_batch_class() {
+    let mut sim = Simulation::new();
+    let normal = sim.spawn(PriorityLevel::Normal, None);
+    let batch = sim.spawn_with_class(PriorityLevel::Normal, SchedulingClass::Batch, None);
+
+    sim.run(600);
+
+    let normal = sim.process(normal).runs;
+    let batch = sim.process(batch).runs;
+    assert_eq!(normal + batch, 600);
+    // the batch process decrements twice as fast, so it runs about half as much
+    assert!(batch > 0);
+    assert!(
+        normal.abs_diff(batch * 2) <= 6,
+        "normal={normal} batch={batch}"
+    );
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_scheduler_deterministic() {
+    fn simulate() -> Vec<Option<usize>> {
+        let mut sim = Simulation::new();
+        sim.spawn(PriorityLevel::High, None);
+        sim.spawn(PriorityLevel::Normal, Some(2));
+        sim.spawn(PriorityLevel::Low, None);
+        sim.spawn(PriorityLevel::VeryLow, Some(7));
+        sim.run(500);
+        sim.trace
+    }
+
+    assert_eq!(simulate(), simulate());
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_scheduler_remove() {
+    let mut sim = Simulation::new();
+    let first = sim.spawn(PriorityLevel::Normal, None);
+    let killed = sim.spawn(PriorityLevel::Normal, None);
+    let last = sim.spawn(PriorityLevel::Normal, None);
+    sim.run(30);
+
+    let removed = sim.queue.remove_first(|p| p.id == killed);
+    assert!(removed.is_some_and(|p| p.id == killed));
+    assert!(sim.queue.remove_first(|p| p.id == killed).is_none());
+    sim.run(30);
+
+    assert!(sim.runs_of(killed).iter().all(|&tick| tick < 30));
+    // the rest keep taking turns
+    for ticks in sim.trace[30..].windows(2) {
+        assert_ne!(ticks[0], ticks[1]);
+        assert!(ticks.iter().all(|p| *p == Some(first) || *p == Some(last)));
+    }
+}
@@ -0,0 +1,66 @@
+//! Queues that processes wait on for a resource, i.e. input from a device
+//!
+//! A process that finds the resource not ready parks itself with [`wait_on_queue`], and is
+//! rescheduled when the owner of the resource calls [`WaitQueue::wake_all`]. The process restarts
+//! the syscall it blocked in, so it checks the resource again.
+//!
+//! Waking doesn't lock the scheduler, the processes are rescheduled the next time it runs, so
+//! [`WaitQueue::wake_all`] can be called from interrupts, and while holding any lock, including
+//! the current process (i.e. a pipe written by a syscall).
+//!
+//! To not miss a wake up that happens between checking the resource and parking, the
+//! [`generation`](WaitQueue::generation) is taken before checking, and [`wait_on_queue`] doesn't
+//! park if the queue was woken since.
+//!
+//! [`wait_on_queue`]: super::wait_on_queue
+
+use alloc::vec::Vec;
+
+use crate::sync::spin::mutex::Mutex;
+
+#[derive(Debug)]
+struct Waiters {
+    /// Incremented on each wake up
+    generation: u64,
+    pids: Vec<u64>,
+}
+
+#[derive(Debug)]
+pub struct WaitQueue {
+    waiters: Mutex<Waiters>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(Waiters {
+                generation: 0,
+                pids: Vec::new(),
+            }),
+        }
+    }
+
+    /// The current generation, must be taken before checking the resource, see the module docs
+    pub fn generation(&self) -> u64 {
+        self.waiters.lock().generation
+    }
+
+    /// Add `pid` to the waiters if the queue was not woken since `generation`
+    pub(super) fn register(&self, pid: u64, generation: u64) -> bool {
+        let mut waiters = self.waiters.lock();
+        if waiters.generation != generation {
+            return false;
+        }
+        waiters.pids.push(pid);
+        true
+    }
+
+    /// Wake all the processes waiting on this queue, see the module docs
+    pub fn wake_all(&self) {
+        let mut waiters = self.waiters.lock();
+        waiters.generation = waiters.generation.wrapping_add(1);
+        if !waiters.pids.is_empty() {
+            super::wake_io_waiters(&mut waiters.pids);
+        }
+    }
+}
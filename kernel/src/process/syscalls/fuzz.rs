@@ -0,0 +1,386 @@
+//! Fuzzing of the syscall arguments
+//!
+//! Every syscall is called with random and hostile arguments from a fake process, whose memory has
+//! a few mapped pages filled with random values and pointers. The kernel must reject the invalid
+//! arguments with errors, any panic (or fault) fails the test run.
+//!
+//! The tests run without the scheduler and the clocks, so the syscalls that leave the process
+//! or read the time are skipped, see [`SKIPPED_SYSCALLS`].
+
+use alloc::string::String;
+use kernel_user_link::{
+    file::{BlockingMode, PIPE_DEFAULT_CAPACITY},
+    syscalls::{
+        syscall_result_from_u64, SyscallArgError, SyscallError, SyscallResult, NUM_SYSCALLS,
+        SYS_DUP2, SYS_EXIT, SYS_GET_FILE_META, SYS_GET_TIME, SYS_KILL, SYS_OPEN, SYS_PRIORITY,
+        SYS_PROCESS_LIST, SYS_PROC_VM_READ, SYS_PROC_VM_WRITE, SYS_READ_DIR, SYS_SLEEP,
+        SYS_SLEEP_UNTIL, SYS_TIMEZONE, SYS_WAIT_PID, SYS_WRITE,
+    },
+};
+use tracing::trace;
+
+use crate::{
+    cpu::{idt::InterruptAllSavedState, user_access},
+    devices::pipe,
+    fs::{self, DirectoryNode, FileAttributes},
+    memory_management::{
+        memory_layout::{KERNEL_BASE, KERNEL_HEAP_BASE, PAGE_4K},
+        virtual_memory_mapper::{self, VirtualMemoryMapEntry, MAX_USER_VIRTUAL_ADDRESS},
+    },
+    process::{scheduler, GoingUpAllocator, Process},
+    testing,
+};
+
+const SEED: u64 = 0x2545_F491_4F6C_DD1D;
+const ITERATIONS_PER_SYSCALL: usize = 300;
+
+/// Where the memory of the fake process is, page `FUZZ_HOLE_PAGE` is left unmapped, so that
+/// ranges can start and end in mapped pages with a hole in the middle
+const FUZZ_MEMORY: usize = 0x1000_0000;
+const FUZZ_PAGES: usize = 4;
+const FUZZ_HOLE_PAGE: usize = 2;
+
+/// Keep the heap small, so `inc_heap` doesn't use a lot of memory
+const HEAP_START: usize = 0x4000_0000;
+const HEAP_MAX: usize = PAGE_4K * 16;
+
+/// - `exit` and `kill` (of the current process): leave the process, which is not running for real.
+/// - `sleep`, `sleep_until` and `get_time`, `timezone`, `process_list`: need the clocks.
+///
+/// `wait_pid` is kept, as there are no other processes to wait for.
+const SKIPPED_SYSCALLS: &[u64] = &[
+    SYS_EXIT,
+    SYS_KILL,
+    SYS_SLEEP,
+    SYS_SLEEP_UNTIL,
+    SYS_GET_TIME,
+    SYS_TIMEZONE,
+    SYS_PROCESS_LIST,
+];
+
+/// `xorshift64`, we only need the values to be spread and reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn choose(&mut self, values: &[u64]) -> u64 {
+        values[self.below(values.len() as u64) as usize]
+    }
+
+    fn pointer(&mut self) -> u64 {
+        let fuzz_size = (FUZZ_PAGES * PAGE_4K) as u64;
+        let hole_end = ((FUZZ_HOLE_PAGE + 1) * PAGE_4K) as u64;
+        let stack_local = 0u64;
+        match self.below(10) {
+            // anywhere in the fuzz memory, aligned or not
+            0..=2 => FUZZ_MEMORY as u64 + self.below(fuzz_size),
+            3 => FUZZ_MEMORY as u64 + (self.below(fuzz_size) & !7),
+            // just before the hole, or the end of the memory
+            4 => {
+                let end = self.choose(&[FUZZ_HOLE_PAGE as u64 * PAGE_4K as u64, fuzz_size]);
+                FUZZ_MEMORY as u64 + end - 1 - self.below(16)
+            }
+            5 => FUZZ_MEMORY as u64 + hole_end + self.below(PAGE_4K as u64),
+            6 => self.choose(&[
+                KERNEL_BASE as u64,
+                KERNEL_HEAP_BASE as u64,
+                &stack_local as *const u64 as u64,
+            ]),
+            // around the non-canonical hole and the end of user memory
+            7 => self.choose(&[
+                0x0000_7FFF_FFFF_FFF8,
+                0x0000_8000_0000_0000,
+                0xFFFF_7FFF_FFFF_FFF8,
+                MAX_USER_VIRTUAL_ADDRESS as u64 - 8,
+                MAX_USER_VIRTUAL_ADDRESS as u64,
+            ]),
+            8 => self.choose(&[0, 1, u64::MAX - 7, u64::MAX]),
+            _ => self.next(),
+        }
+    }
+
+    fn length(&mut self) -> u64 {
+        let fuzz_size = (FUZZ_PAGES * PAGE_4K) as u64;
+        match self.below(4) {
+            0 => self.below(64),
+            1 => self.choose(&[PAGE_4K as u64, PAGE_4K as u64 + 1, fuzz_size]),
+            // overflow the size multiplications and additions
+            2 => self.choose(&[
+                u64::MAX,
+                u64::MAX / 2 + 1,
+                u64::MAX / 8 + 1,
+                i64::MAX as u64,
+            ]),
+            _ => self.below(fuzz_size * 2),
+        }
+    }
+
+    fn arg(&mut self) -> u64 {
+        match self.below(5) {
+            // fds, flags, enum values
+            0 => self.below(16),
+            1 => self.pointer(),
+            2 => self.length(),
+            3 => self.choose(&[0, u64::MAX, 1 << 63, i64::MAX as u64, u32::MAX as u64]),
+            _ => self.next(),
+        }
+    }
+
+    /// Fill the mapped fuzz memory with the same kind of values as the arguments, so pointers to
+    /// arrays and structs point to more pointers and lengths
+    fn fill_memory(&mut self) {
+        let _user_access = user_access::allow_user_access();
+        for page in (0..FUZZ_PAGES).filter(|&page| page != FUZZ_HOLE_PAGE) {
+            let words = (FUZZ_MEMORY + page * PAGE_4K) as *mut u64;
+            for i in 0..PAGE_4K / 8 {
+                let value = if self.below(4) == 0 { 0 } else { self.arg() };
+                // Safety: the page is mapped and writable
+                unsafe { words.add(i).write(value) };
+            }
+        }
+
+        if self.below(4) == 0 {
+            let strings: [&[u8]; 4] = [b"/\0", b"/test_process\0", b"..\0", b"\xFF\xFE\0"];
+            let string = strings[self.below(strings.len() as u64) as usize];
+            let page = match self.below(FUZZ_PAGES as u64) as usize {
+                FUZZ_HOLE_PAGE => 0,
+                page => page,
+            };
+            let offset = self.below((PAGE_4K - string.len()) as u64) as usize;
+            let ptr = (FUZZ_MEMORY + page * PAGE_4K + offset) as *mut u8;
+            // Safety: the string fits in the mapped page
+            unsafe { ptr.copy_from_nonoverlapping(string.as_ptr(), string.len()) };
+        }
+    }
+}
+
+fn root_dir() -> fs::Directory {
+    fs::Directory::from_inode(
+        DirectoryNode::without_parent(String::from("/"), FileAttributes::DIRECTORY, 0),
+        "/",
+        fs::empty_filesystem(),
+        0,
+    )
+    .expect("This is a directory, shouldn't fail")
+}
+
+/// Create the fake process with the fuzz memory mapped, and run `f` as the current process
+fn with_fuzz_process<U>(f: impl FnOnce() -> U) -> U {
+    // the paths are resolved in the mappings, without a filesystem, so every lookup fails
+    let _ = fs::mapping::mount("/", fs::empty_filesystem());
+
+    let mut process = Process::new_for_test(root_dir(), HEAP_START, HEAP_MAX);
+    for page in (0..FUZZ_PAGES).filter(|&page| page != FUZZ_HOLE_PAGE) {
+        process.vm.map(&VirtualMemoryMapEntry {
+            virtual_address: FUZZ_MEMORY + page * PAGE_4K,
+            physical_address: None,
+            size: PAGE_4K,
+            flags: virtual_memory_mapper::flags::PTE_USER
+                | virtual_memory_mapper::flags::PTE_WRITABLE,
+        });
+    }
+
+    scheduler::run_as_current_process(process, f)
+}
+
+/// Replace all the handles with fresh ones, `0` is the read end of a pipe, `1` its write end,
+/// and `2` the root directory.
+///
+/// Reading blocking files needs interrupts and the clocks, so the pipe is non blocking, and the
+/// handles made blocking by a syscall are replaced before the next one
+fn reset_handles() {
+    let (mut read_file, write_file) = pipe::create_pipe_pair(PIPE_DEFAULT_CAPACITY);
+    read_file.set_blocking(BlockingMode::None);
+
+    let old_handles = scheduler::with_current_process(|process| {
+        let old_handles = core::mem::take(&mut process.handles);
+        process.file_index_allocator = GoingUpAllocator::new();
+        process.push_handle(read_file);
+        process.push_handle(write_file);
+        process.push_handle(root_dir());
+        process.set_current_dir(root_dir());
+        old_handles
+    });
+    // close outside the process lock, like `sys_close`
+    for handle in old_handles.into_values() {
+        handle.close();
+    }
+}
+
+fn syscall(number: u64, args: &[u64; 7]) -> SyscallResult {
+    let mut state = InterruptAllSavedState::default();
+    state.rest.rax = number;
+    state.rest.rcx = args[0];
+    state.rest.rdx = args[1];
+    state.rest.rsi = args[2];
+    state.rest.rdi = args[3];
+    state.rest.r8 = args[4];
+    state.rest.r9 = args[5];
+    state.rest.r10 = args[6];
+
+    super::handle_syscall(&mut state);
+    syscall_result_from_u64(state.rest.rax)
+}
+
+fn is_invalid_argument(result: SyscallResult) -> bool {
+    matches!(result, Err(SyscallError::InvalidArgument(..)))
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_syscalls_fuzz() {
+    let mut rng = Rng(SEED);
+
+    with_fuzz_process(|| {
+        // one past the end to check the syscall number as well
+        for number in 0..=NUM_SYSCALLS as u64 {
+            if SKIPPED_SYSCALLS.contains(&number) {
+                continue;
+            }
+            for _ in 0..ITERATIONS_PER_SYSCALL {
+                reset_handles();
+                rng.fill_memory();
+                let args = core::array::from_fn(|_| rng.arg());
+                trace!("fuzz: syscall {number} with {args:x?}");
+
+                let result = syscall(number, &args);
+                if number == NUM_SYSCALLS as u64 {
+                    assert!(matches!(result, Err(SyscallError::SyscallNotFound)));
+                }
+            }
+        }
+        reset_handles();
+    });
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_syscalls_reject_bad_user_memory() {
+    let page = |i: usize| (FUZZ_MEMORY + i * PAGE_4K) as u64;
+    let write = |ptr: u64, len: u64| syscall(SYS_WRITE, &[1, ptr, len, 0, 0, 0, 0]);
+
+    with_fuzz_process(|| {
+        reset_handles();
+
+        assert_eq!(write(page(0), 16).ok(), Some(16));
+        // kernel memory
+        assert!(is_invalid_argument(write(KERNEL_BASE as u64, 8)));
+        assert!(is_invalid_argument(write(KERNEL_HEAP_BASE as u64, 8)));
+        // crosses into the non-canonical hole
+        assert!(is_invalid_argument(write(0x0000_7FFF_FFFF_FFFC, 8)));
+        // the first and last pages are mapped, but not the one in the middle
+        assert!(is_invalid_argument(write(
+            page(0),
+            (PAGE_4K * FUZZ_PAGES) as u64
+        )));
+        assert!(is_invalid_argument(write(page(FUZZ_HOLE_PAGE) - 8, 16)));
+        // the end overflows
+        assert!(is_invalid_argument(write(page(0) + 8, u64::MAX)));
+        // the size of the slice overflows
+        let dir_entries = syscall(SYS_READ_DIR, &[2, page(0), u64::MAX / 8 + 1, 0, 0, 0, 0]);
+        assert!(is_invalid_argument(dir_entries));
+        // unaligned
+        let meta = syscall(SYS_GET_FILE_META, &[1, 0, page(0) + 1, 0, 0, 0, 0]);
+        assert!(is_invalid_argument(meta));
+
+        // a path without a null terminator before the hole
+        let path_start = page(FUZZ_HOLE_PAGE) - 4;
+        {
+            let _user_access = user_access::allow_user_access();
+            // Safety: the 4 bytes before the hole are mapped
+            unsafe { (path_start as *mut u32).write(u32::from_ne_bytes(*b"/abc")) };
+        }
+        let open = syscall(SYS_OPEN, &[path_start, 1, 0, 0, 0, 0, 0]);
+        assert!(matches!(
+            open,
+            Err(SyscallError::InvalidArgument(
+                Some(SyscallArgError::InvalidUserPointer),
+                ..
+            ))
+        ));
+
+        // fds that can't be used by the allocator
+        let dup = syscall(SYS_DUP2, &[1, u64::MAX, 0, 0, 0, 0, 0]);
+        assert!(is_invalid_argument(dup));
+
+        // processes that don't exist, and waiting for ourselves
+        let pid = scheduler::with_current_process(|process| process.id());
+        let priority = syscall(SYS_PRIORITY, &[u64::MAX, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(priority, Err(SyscallError::PidNotFound)));
+        assert!(is_invalid_argument(syscall(
+            SYS_WAIT_PID,
+            &[pid, 1, 0, 0, 0, 0, 0]
+        )));
+
+        reset_handles();
+    });
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_syscalls_proc_vm() {
+    let page = |i: usize| (FUZZ_MEMORY + i * PAGE_4K) as u64;
+    // mapped after the fuzz memory
+    let read_only_page = page(FUZZ_PAGES);
+
+    with_fuzz_process(|| {
+        let pid = scheduler::with_current_process(|process| {
+            process.vm.map(&VirtualMemoryMapEntry {
+                virtual_address: read_only_page as usize,
+                physical_address: None,
+                size: PAGE_4K,
+                flags: virtual_memory_mapper::flags::PTE_USER,
+            });
+            process.id()
+        });
+        let read = |addr: u64, buf: u64, len: u64| {
+            syscall(SYS_PROC_VM_READ, &[pid, addr, buf, len, 0, 0, 0])
+        };
+        let write = |addr: u64, buf: u64, len: u64| {
+            syscall(SYS_PROC_VM_WRITE, &[pid, addr, buf, len, 0, 0, 0])
+        };
+
+        let data = *b"emerald debugger";
+        {
+            let _user_access = user_access::allow_user_access();
+            // Safety: the pages are mapped and writable
+            unsafe {
+                (page(0) as *mut [u8; 16]).write(data);
+                (page(1) as *mut [u8; 16]).write([0; 16]);
+            }
+        }
+        assert_eq!(write(page(3) + 8, page(0), 16).ok(), Some(16));
+        assert_eq!(read(page(3) + 8, page(1), 16).ok(), Some(16));
+        {
+            let _user_access = user_access::allow_user_access();
+            // Safety: the page is mapped
+            assert_eq!(unsafe { (page(1) as *const [u8; 16]).read() }, data);
+        }
+
+        // stops at the hole
+        assert_eq!(read(page(FUZZ_HOLE_PAGE) - 8, page(1), 16).ok(), Some(8));
+        assert!(is_invalid_argument(read(page(FUZZ_HOLE_PAGE), page(1), 16)));
+        assert!(is_invalid_argument(read(KERNEL_BASE as u64, page(1), 16)));
+        assert!(is_invalid_argument(read(
+            MAX_USER_VIRTUAL_ADDRESS as u64,
+            page(1),
+            16
+        )));
+
+        // can be read, but not written
+        assert_eq!(read(read_only_page, page(1), 16).ok(), Some(16));
+        assert!(is_invalid_argument(write(read_only_page, page(0), 16)));
+
+        let missing = syscall(SYS_PROC_VM_READ, &[u64::MAX, page(0), page(1), 16, 0, 0, 0]);
+        assert!(matches!(missing, Err(SyscallError::PidNotFound)));
+    });
+}
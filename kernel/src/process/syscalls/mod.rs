@@ -1,35 +1,68 @@
-use core::{ffi::CStr, mem};
+use core::{mem, ops::Range};
 
-use alloc::{borrow::Cow, string::String, vec::Vec};
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
 use kernel_user_link::{
-    clock::ClockType,
-    file::{BlockingMode, DirEntry, FileMeta, OpenOptions, SeekFrom, SeekWhence},
-    graphics::{BlitCommand, FrameBufferInfo, GraphicsCommand},
-    process::{PriorityLevel, SpawnFileMapping},
+    clock::{ClockType, TimeZone},
+    file::{
+        unmount_flags, watch_events, BlockingMode, DirEntry, FileMeta, IoVec, OpenOptions,
+        SeekFrom, SeekWhence, DIR_FD_CWD, MAX_IO_VECS, PIPE_DEFAULT_CAPACITY, PIPE_MAX_CAPACITY,
+        PIPE_MIN_CAPACITY,
+    },
+    graphics::{
+        BlitCommand, CaptureCommand, CopyRectCommand, FillRectCommand, FrameBufferInfo,
+        GraphicsCommand, Rect,
+    },
+    process::{
+        spawn_redirect, MemInfo, PriorityLevel, ProcessInfo, SchedulingClass, SpawnFileMapping,
+        SpawnOptions, SpawnStdioRedirect, KILLED_EXIT_CODE, SPAWN_OPTIONS_SIZE_V1,
+    },
     sys_arg,
     syscalls::{
-        syscall_arg_to_u64, syscall_handler_wrapper, SyscallArgError, SyscallError, SyscallResult,
-        NUM_SYSCALLS,
+        syscall_arg_to_u64, syscall_handler_wrapper, ElfLoadErrorReason, SyscallArgError,
+        SyscallError, SyscallResult, NUM_SYSCALLS,
     },
-    to_arg_err, verify_args, FD_STDERR,
+    to_arg_err, verify_args, FD_STDERR, FD_STDOUT,
 };
+use tracing::{debug, info, warn};
 
 use crate::{
-    cpu::{self, idt::InterruptAllSavedState},
-    devices::{self, clock},
-    executable::elf::Elf,
-    fs::{self, path::Path, FileSystemError},
+    cpu::{
+        self,
+        idt::InterruptAllSavedState,
+        user_access::{self, copy_from_user, copy_to_user},
+    },
+    devices::{self, clock, pipe::SpliceError},
+    executable::elf::{Elf, ElfLoadError},
+    fs::{
+        self,
+        mapping::{MappingError, UnmountMode},
+        path::{Path, PathBuf},
+        FatError, FileSystemError,
+    },
     graphics,
-    memory_management::memory_layout::{is_aligned, PAGE_4K},
-    process::{scheduler, Process},
+    memory_management::{
+        memory_layout::{align_down, is_aligned, PAGE_4K},
+        virtual_memory_mapper::{UserMemoryAccess, MAX_USER_VIRTUAL_ADDRESS},
+    },
+    process::{handle::KernelHandle, procfs, scheduler, Process},
+    sync::adaptive_mutex,
 };
 
 use super::scheduler::{
-    exit_current_process, sleep_current_process, with_current_process, with_process,
+    exit_current_process, sleep_current_process, sleep_current_process_until, with_current_process,
 };
 
+#[cfg(test)]
+mod fuzz;
+
 type Syscall = fn(&mut InterruptAllSavedState) -> SyscallResult;
 
+/// The biggest fd userspace can choose, fds are `int` in C, so bigger ones can't be used anyway
+const MAX_FD: usize = i32::MAX as usize;
+
+/// The size of the kernel buffer the data of `read`/`write` (and friends) goes through
+const IO_CHUNK_SIZE: usize = PAGE_4K * 16;
+
 const SYSCALLS: [Syscall; NUM_SYSCALLS] = [
     sys_open,          // kernel_user_link::syscalls::SYS_OPEN
     sys_write,         // kernel_user_link::syscalls::SYS_WRITE
@@ -53,31 +86,113 @@ const SYSCALLS: [Syscall; NUM_SYSCALLS] = [
     sys_graphics,      // kernel_user_link::syscalls::SYS_GRAPHICS
     sys_seek,          // kernel_user_link::syscalls::SYS_SEEK
     sys_priority,      // kernel_user_link::syscalls::SYS_PRIORITY
+    sys_meminfo,       // kernel_user_link::syscalls::SYS_MEMINFO
+    sys_dup,           // kernel_user_link::syscalls::SYS_DUP
+    sys_dup2,          // kernel_user_link::syscalls::SYS_DUP2
+    sys_readv,         // kernel_user_link::syscalls::SYS_READV
+    sys_writev,        // kernel_user_link::syscalls::SYS_WRITEV
+    sys_pread,         // kernel_user_link::syscalls::SYS_PREAD
+    sys_pwrite,        // kernel_user_link::syscalls::SYS_PWRITE
+    sys_truncate,      // kernel_user_link::syscalls::SYS_TRUNCATE
+    sys_ftruncate,     // kernel_user_link::syscalls::SYS_FTRUNCATE
+    sys_fallocate,     // kernel_user_link::syscalls::SYS_FALLOCATE
+    sys_fsync,         // kernel_user_link::syscalls::SYS_FSYNC
+    sys_sync,          // kernel_user_link::syscalls::SYS_SYNC
+    sys_watch,         // kernel_user_link::syscalls::SYS_WATCH
+    sys_realpath,      // kernel_user_link::syscalls::SYS_REALPATH
+    sys_openat,        // kernel_user_link::syscalls::SYS_OPENAT
+    sys_statat,        // kernel_user_link::syscalls::SYS_STATAT
+    sys_open_dir_at,   // kernel_user_link::syscalls::SYS_OPEN_DIR_AT
+    sys_process_list,  // kernel_user_link::syscalls::SYS_PROCESS_LIST
+    sys_get_proc_info, // kernel_user_link::syscalls::SYS_GET_PROC_INFO
+    sys_timezone,      // kernel_user_link::syscalls::SYS_TIMEZONE
+    sys_sleep_until,   // kernel_user_link::syscalls::SYS_SLEEP_UNTIL
+    sys_set_affinity,  // kernel_user_link::syscalls::SYS_SET_AFFINITY
+    sys_fs_stat,       // kernel_user_link::syscalls::SYS_FS_STAT
+    sys_kill,          // kernel_user_link::syscalls::SYS_KILL
+    sys_unmount,       // kernel_user_link::syscalls::SYS_UNMOUNT
+    sys_splice,        // kernel_user_link::syscalls::SYS_SPLICE
+    sys_proc_vm_read,  // kernel_user_link::syscalls::SYS_PROC_VM_READ
+    sys_proc_vm_write, // kernel_user_link::syscalls::SYS_PROC_VM_WRITE
 ];
 
 impl From<FileSystemError> for SyscallError {
     fn from(e: FileSystemError) -> Self {
         match e {
-            FileSystemError::InvalidPath => SyscallError::CouldNotOpenFile,
+            FileSystemError::InvalidPath | FileSystemError::MustBeAbsolute => {
+                SyscallError::InvalidPath
+            }
             FileSystemError::FileNotFound => SyscallError::FileNotFound,
             FileSystemError::ReadNotSupported => SyscallError::CouldNotReadFromFile,
-            FileSystemError::WriteNotSupported | FileSystemError::CouldNotSetFileLength => SyscallError::CouldNotWriteToFile,
+            FileSystemError::WriteNotSupported | FileSystemError::CouldNotSetFileLength => {
+                SyscallError::CouldNotWriteToFile
+            }
             FileSystemError::EndOfFile => SyscallError::EndOfFile,
             FileSystemError::IsNotDirectory => SyscallError::IsNotDirectory,
             FileSystemError::IsDirectory => SyscallError::IsDirectory,
             FileSystemError::AlreadyExists => SyscallError::AlreadyExists,
-            FileSystemError::BufferNotLargeEnough(_) => SyscallError::BufferTooSmall,
+            FileSystemError::BufferNotLargeEnough(needed) => SyscallError::BufferTooSmall(needed),
             FileSystemError::OperationNotSupported => SyscallError::OperationNotSupported,
-            FileSystemError::DiskReadError { .. }
-            | FileSystemError::FatError(_)
-            | FileSystemError::MappingError(_)
-            | FileSystemError::DeviceNotFound
-            | FileSystemError::MustBeAbsolute   // should not happen from user mode
-            | FileSystemError::PartitionTableNotFound => panic!("should not happen?"),
+            FileSystemError::TimedOut => SyscallError::TimedOut,
+            FileSystemError::NotSeekable => SyscallError::NotSeekable,
+            // only reaches here if the caller doesn't wait on the device
+            FileSystemError::WouldBlock => SyscallError::CouldNotReadFromFile,
+            // the result is discarded, and the syscall is run again
+            FileSystemError::Blocked => SyscallError::CouldNotReadFromFile,
+            // from the block devices, i.e. out of the disk bounds
+            FileSystemError::DiskReadError { .. } | FileSystemError::DeviceError => {
+                SyscallError::IoError
+            }
+            // the content of the buffer given to `write`
+            FileSystemError::InvalidInput => to_arg_err!(1, SyscallArgError::GeneralInvalid),
+            FileSystemError::FatError(FatError::NotEnoughSpace) => SyscallError::NoSpace,
+            // corrupted filesystem
+            FileSystemError::FatError(
+                FatError::InvalidBootSector | FatError::UnexpectedFatEntry,
+            )
+            | FileSystemError::ExFatError(_) => SyscallError::IoError,
+            FileSystemError::MappingError(e) => match e {
+                MappingError::MustBeAbsolute | MappingError::InvalidPath => {
+                    SyscallError::InvalidPath
+                }
+                MappingError::PartOfParentNotMounted | MappingError::NotMounted => {
+                    SyscallError::FileNotFound
+                }
+                MappingError::AlreadyMounted => SyscallError::AlreadyExists,
+                MappingError::Busy => SyscallError::Busy,
+            },
+            FileSystemError::DeviceNotFound | FileSystemError::PartitionTableNotFound => {
+                SyscallError::NoDevice
+            }
         }
     }
 }
 
+impl From<ElfLoadError> for SyscallError {
+    fn from(e: ElfLoadError) -> Self {
+        let reason = match e {
+            // failing to read the file is not the executable's fault
+            ElfLoadError::FileSystemError(e) => return e.into(),
+            ElfLoadError::InvalidMagic => ElfLoadErrorReason::InvalidMagic,
+            ElfLoadError::InvalidElfOrNotSupported => ElfLoadErrorReason::NotSupported,
+            ElfLoadError::UnexpectedEndOfFile => ElfLoadErrorReason::UnexpectedEndOfFile,
+            ElfLoadError::IntegrityCheckFailed(_) => ElfLoadErrorReason::IntegrityCheckFailed,
+            ElfLoadError::OutOfFileBounds => ElfLoadErrorReason::OutOfFileBounds,
+            ElfLoadError::InvalidSectionName => ElfLoadErrorReason::InvalidSectionName,
+            ElfLoadError::InvalidSegmentSize => ElfLoadErrorReason::InvalidSegmentSize,
+            ElfLoadError::InvalidAlignment => ElfLoadErrorReason::InvalidAlignment,
+            ElfLoadError::InvalidAddress => ElfLoadErrorReason::InvalidAddress,
+            ElfLoadError::OverlappingSegments => ElfLoadErrorReason::OverlappingSegments,
+            ElfLoadError::InvalidEntryPoint => ElfLoadErrorReason::InvalidEntryPoint,
+            ElfLoadError::NoLoadSegments => ElfLoadErrorReason::NoLoadSegments,
+            ElfLoadError::InvalidProgramHeaderAddress => {
+                ElfLoadErrorReason::InvalidProgramHeaderAddress
+            }
+        };
+        SyscallError::CouldNotLoadElf(reason)
+    }
+}
+
 impl From<clock::ClockTime> for kernel_user_link::clock::ClockTime {
     fn from(time: clock::ClockTime) -> Self {
         assert!(time.nanoseconds < clock::NANOS_PER_SEC);
@@ -88,80 +203,249 @@ impl From<clock::ClockTime> for kernel_user_link::clock::ClockTime {
     }
 }
 
+impl From<clock::TimeZone> for TimeZone {
+    fn from(timezone: clock::TimeZone) -> Self {
+        Self {
+            offset_seconds: timezone.offset_seconds,
+            rtc_is_localtime: timezone.rtc_localtime as u32,
+        }
+    }
+}
+
+impl TryFrom<TimeZone> for clock::TimeZone {
+    type Error = SyscallArgError;
+
+    fn try_from(timezone: TimeZone) -> Result<Self, Self::Error> {
+        let rtc_localtime = match timezone.rtc_is_localtime {
+            0 => false,
+            1 => true,
+            _ => return Err(SyscallArgError::GeneralInvalid),
+        };
+        Ok(Self {
+            offset_seconds: timezone.offset_seconds,
+            rtc_localtime,
+        })
+    }
+}
+
+/// Canonical address below [`MAX_USER_VIRTUAL_ADDRESS`], the user can use both halves of the
+/// address space except the last `L4` entry, which is the kernel
 #[inline]
+fn is_user_address(addr: usize) -> bool {
+    let canonical = ((addr << 16) as isize >> 16) as usize == addr;
+    canonical && addr < MAX_USER_VIRTUAL_ADDRESS
+}
+
+/// Check that all the `len` bytes starting at `arg` are in user memory and mapped
 fn check_ptr(arg: *const u8, len: usize) -> Result<(), SyscallArgError> {
-    if arg.is_null() {
+    let start = arg as usize;
+    if arg.is_null() || len == 0 {
+        return Err(SyscallArgError::InvalidUserPointer);
+    }
+    let last = start
+        .checked_add(len - 1)
+        .ok_or(SyscallArgError::InvalidUserPointer)?;
+    // both in the same half, so the range doesn't cross the non-canonical hole
+    if !is_user_address(start) || !is_user_address(last) || (start ^ last) >> 63 != 0 {
         return Err(SyscallArgError::InvalidUserPointer);
     }
+
     if !with_current_process(|process| {
-        process.is_user_address_mapped(arg as _)
-        // very basic check, just check the last byte
-        // TODO: check all mapped pages
-            && process.is_user_address_mapped(arg as usize + len - 1 )
+        (align_down(start, PAGE_4K)..=last)
+            .step_by(PAGE_4K)
+            .all(|page| process.is_user_address_mapped(page))
     }) {
         return Err(SyscallArgError::InvalidUserPointer);
     }
     Ok(())
 }
 
+/// Creating references to unaligned `T` is undefined behavior
 #[inline]
-fn ptr_as_mut<T>(ptr: *mut u8) -> Result<*mut T, SyscallArgError> {
-    check_ptr(ptr as *const u8, mem::size_of::<T>())?;
-    Ok(ptr as *mut T)
+fn check_align<T>(arg: *const u8) -> Result<(), SyscallArgError> {
+    if !is_aligned(arg as usize, mem::align_of::<T>()) {
+        return Err(SyscallArgError::InvalidUserPointer);
+    }
+    Ok(())
 }
 
-#[inline]
-fn ptr_as_ref<T>(ptr: *const u8) -> Result<*const T, SyscallArgError> {
-    check_ptr(ptr, mem::size_of::<T>())?;
-    Ok(ptr as *const T)
+/// `len` `T`s in the memory of the current process, checked to be mapped and aligned.
+///
+/// The memory is only accessed with [`copy_from_user`]/[`copy_to_user`], `T` must be plain data,
+/// as userspace can put any bytes there.
+#[derive(Clone, Copy)]
+struct UserSlice<T> {
+    ptr: *mut T,
+    len: usize,
 }
 
-// expects null terminated string
-fn sys_arg_to_str<'a>(arg: *const u8) -> Result<&'a str, SyscallArgError> {
-    check_ptr(arg, 1)?;
+impl<T: Copy> UserSlice<T> {
+    fn new(ptr: *const u8, len: usize) -> Result<Self, SyscallArgError> {
+        if len != 0 {
+            check_align::<T>(ptr)?;
+            let size = len
+                .checked_mul(mem::size_of::<T>())
+                .ok_or(SyscallArgError::InvalidUserPointer)?;
+            check_ptr(ptr, size)?;
+        }
+        Ok(Self {
+            ptr: ptr as *mut T,
+            len,
+        })
+    }
 
-    let slice = unsafe { CStr::from_ptr(arg as _) };
-    let string = CStr::to_str(slice).map_err(|_| SyscallArgError::NotValidUtf8)?;
-    Ok(string)
-}
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Copy the elements starting at `index` into `dst`
+    fn read_into(&self, index: usize, dst: &mut [T]) {
+        if dst.is_empty() {
+            return;
+        }
+        assert!(index + dst.len() <= self.len);
+        // SAFETY: the range is inside the checked memory
+        unsafe {
+            copy_from_user(
+                core::slice::from_raw_parts_mut(dst.as_mut_ptr() as *mut u8, mem::size_of_val(dst)),
+                self.ptr.add(index) as *const u8,
+            )
+        };
+    }
+
+    /// Copy `src` to the elements starting at `index`
+    fn write_at(&self, index: usize, src: &[T]) {
+        if src.is_empty() {
+            return;
+        }
+        assert!(index + src.len() <= self.len);
+        // SAFETY: the range is inside the checked memory
+        unsafe {
+            copy_to_user(
+                self.ptr.add(index) as *mut u8,
+                core::slice::from_raw_parts(src.as_ptr() as *const u8, mem::size_of_val(src)),
+            )
+        };
+    }
+
+    fn to_vec(self) -> Vec<T> {
+        // SAFETY: `T` is plain data, so all zeros is a valid value
+        let mut vec = vec![unsafe { mem::zeroed::<T>() }; self.len];
+        self.read_into(0, &mut vec);
+        vec
+    }
 
-fn sys_arg_to_path<'a>(arg: *const u8) -> Result<&'a Path, SyscallArgError> {
-    sys_arg_to_str(arg).map(Path::new)
+    /// The memory in place, for buffers too big to copy, i.e. framebuffers.
+    ///
+    /// # Safety
+    /// User access must be allowed (see [`user_access::allow_user_access`]) while the slice is used
+    unsafe fn as_slice(&self) -> &[T] {
+        if self.len == 0 {
+            return &[];
+        }
+        core::slice::from_raw_parts(self.ptr, self.len)
+    }
+
+    /// Same as [`Self::as_slice`], but mutable
+    ///
+    /// # Safety
+    /// User access must be allowed (see [`user_access::allow_user_access`]) while the slice is used
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn as_mut_slice(&self) -> &mut [T] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        core::slice::from_raw_parts_mut(self.ptr, self.len)
+    }
 }
 
-fn sys_arg_to_slice<'a, T: Sized>(buf: *const u8, len: usize) -> Result<&'a [T], SyscallArgError> {
-    if len == 0 {
-        return Ok(&[]);
+/// A single `T` in the memory of the current process, see [`UserSlice`]
+#[derive(Clone, Copy)]
+struct UserPtr<T>(UserSlice<T>);
+
+impl<T: Copy> UserPtr<T> {
+    fn new(ptr: *const u8) -> Result<Self, SyscallArgError> {
+        UserSlice::new(ptr, 1).map(Self)
     }
 
-    check_ptr(buf, len * mem::size_of::<T>())?;
+    fn read(&self) -> T {
+        self.0.to_vec()[0]
+    }
 
-    let slice = unsafe { core::slice::from_raw_parts(buf as _, len) };
-    Ok(slice)
+    fn write(&self, value: T) {
+        self.0.write_at(0, core::slice::from_ref(&value));
+    }
 }
 
-fn sys_arg_to_mut_slice<'a, T: Sized>(
-    buf: *mut u8,
+/// The buffers of an [`IoVec`] array, used as if they were one contiguous buffer
+struct UserIoVecs {
+    bufs: Vec<UserSlice<u8>>,
     len: usize,
-) -> Result<&'a mut [T], SyscallArgError> {
-    if len == 0 {
-        return Ok(&mut []);
+}
+
+impl UserIoVecs {
+    /// Calls `f` with each buffer holding part of `offset..offset + len`, the offset in that
+    /// buffer, and the range of the part relative to `offset`
+    fn split(
+        &self,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(&UserSlice<u8>, usize, Range<usize>),
+    ) {
+        let mut buf_start = 0;
+        for buf in &self.bufs {
+            let buf_end = buf_start + buf.len();
+            let start = offset.max(buf_start);
+            let end = (offset + len).min(buf_end);
+            if start < end {
+                f(buf, start - buf_start, start - offset..end - offset);
+            }
+            buf_start = buf_end;
+        }
     }
 
-    check_ptr(buf, len * mem::size_of::<T>())?;
+    fn read_into(&self, offset: usize, dst: &mut [u8]) {
+        self.split(offset, dst.len(), |buf, buf_offset, range| {
+            buf.read_into(buf_offset, &mut dst[range])
+        });
+    }
+
+    fn write_at(&self, offset: usize, src: &[u8]) {
+        self.split(offset, src.len(), |buf, buf_offset, range| {
+            buf.write_at(buf_offset, &src[range])
+        });
+    }
+}
+
+// expects null terminated string
+fn sys_arg_to_str(arg: *const u8) -> Result<String, SyscallArgError> {
+    // look for the null terminator one page at a time, as the string may end at an unmapped page
+    let mut bytes = Vec::new();
+    loop {
+        let chunk_start = (arg as usize).wrapping_add(bytes.len());
+        let chunk_len = PAGE_4K - chunk_start % PAGE_4K;
+        let chunk = UserSlice::<u8>::new(chunk_start as _, chunk_len)?.to_vec();
+        if let Some(null_pos) = chunk.iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..null_pos]);
+            break;
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(bytes).map_err(|_| SyscallArgError::NotValidUtf8)
+}
 
-    let slice = unsafe { core::slice::from_raw_parts_mut(buf as _, len) };
-    Ok(slice)
+fn sys_arg_to_path(arg: *const u8) -> Result<PathBuf, SyscallArgError> {
+    sys_arg_to_str(arg).map(PathBuf::from)
 }
 
 /// Allocates space for the strings and copies them
 fn sys_arg_to_str_array(array_ptr: *const u8) -> Result<Vec<String>, SyscallArgError> {
-    let array_ptr = ptr_as_ref::<*const u8>(array_ptr)?;
-
     let mut array = Vec::new();
-    let mut i = 0;
+    let mut element_ptr = array_ptr;
     loop {
-        let ptr = unsafe { *array_ptr.offset(i) };
+        // the length is not known, so check each element before reading it
+        let ptr = UserPtr::<*const u8>::new(element_ptr)?.read();
         if ptr.is_null() {
             break;
         }
@@ -169,22 +453,25 @@ fn sys_arg_to_str_array(array_ptr: *const u8) -> Result<Vec<String>, SyscallArgE
         if str.is_empty() {
             break;
         }
-        array.push(String::from(str));
-        i += 1;
+        array.push(str);
+        element_ptr = element_ptr.wrapping_add(mem::size_of::<*const u8>());
     }
 
     Ok(array)
 }
 
 /// Allocates space fro the mapping and copies them
-fn sys_arg_to_file_mappings_array<'a>(
+fn sys_arg_to_file_mappings_array(
     array_ptr: *const u8,
     array_size: usize,
-) -> Result<&'a [SpawnFileMapping], SyscallArgError> {
-    let mappings_array = sys_arg_to_slice::<SpawnFileMapping>(array_ptr, array_size)?;
+) -> Result<Vec<SpawnFileMapping>, SyscallArgError> {
+    let mappings_array = UserSlice::<SpawnFileMapping>::new(array_ptr, array_size)?.to_vec();
 
     for i in 0..array_size {
         let mapping = mappings_array[i];
+        if mapping.dst_fd > MAX_FD {
+            return Err(SyscallArgError::GeneralInvalid);
+        }
 
         // before doing push check that we don't have duplicates
         for other_mapping in mappings_array.iter().take(i) {
@@ -197,21 +484,160 @@ fn sys_arg_to_file_mappings_array<'a>(
     Ok(mappings_array)
 }
 
+/// Copies the redirects, returns the user array as well, so that the pipe fds can be written back
+fn sys_arg_to_redirects_array(
+    array_ptr: *mut u8,
+    array_size: usize,
+    file_mappings: &[SpawnFileMapping],
+) -> Result<(UserSlice<SpawnStdioRedirect>, Vec<SpawnStdioRedirect>), SyscallArgError> {
+    let user_redirects = UserSlice::<SpawnStdioRedirect>::new(array_ptr, array_size)?;
+    let redirects = user_redirects.to_vec();
+
+    for i in 0..array_size {
+        let redirect = redirects[i];
+
+        if redirect.dst_fd != FD_STDOUT && redirect.dst_fd != FD_STDERR {
+            return Err(SyscallArgError::GeneralInvalid);
+        }
+        if !matches!(
+            redirect.kind,
+            spawn_redirect::FILE_TRUNCATE | spawn_redirect::FILE_APPEND | spawn_redirect::PIPE
+        ) {
+            return Err(SyscallArgError::GeneralInvalid);
+        }
+        // the fd can't be given by another redirect or a file mapping
+        if redirects[..i]
+            .iter()
+            .any(|other| other.dst_fd == redirect.dst_fd)
+            || file_mappings
+                .iter()
+                .any(|mapping| mapping.dst_fd == redirect.dst_fd)
+        {
+            return Err(SyscallArgError::DuplicateFileMappings);
+        }
+    }
+
+    Ok((user_redirects, redirects))
+}
+
+/// Copy the [`SpawnOptions`] from userspace, handling older (smaller) and newer (bigger) versions
+/// of the struct, the fields not given by userspace get their default values
+fn sys_arg_to_spawn_options(ptr: *const u8) -> Result<SpawnOptions, SyscallArgError> {
+    let size = UserPtr::<u64>::new(ptr)?.read() as usize;
+    if size < SPAWN_OPTIONS_SIZE_V1 {
+        return Err(SyscallArgError::InvalidStructSize);
+    }
+    let bytes = UserSlice::<u8>::new(ptr, size)?;
+    let known_size = mem::size_of::<SpawnOptions>();
+    // fields we don't know about can only be used if they are not set
+    let mut extra = [0; 64];
+    for offset in (known_size..size).step_by(extra.len()) {
+        let extra = &mut extra[..(size - offset).min(64)];
+        bytes.read_into(offset, extra);
+        if extra.iter().any(|&b| b != 0) {
+            return Err(SyscallArgError::InvalidStructSize);
+        }
+    }
+
+    let mut options = SpawnOptions::default();
+    let copy_size = size.min(known_size);
+    // SAFETY: `SpawnOptions` is plain data, and we only copy the part of it given by userspace
+    bytes.read_into(0, unsafe {
+        core::slice::from_raw_parts_mut(&mut options as *mut SpawnOptions as *mut u8, copy_size)
+    });
+    options.size = known_size as u64;
+    Ok(options)
+}
+
+/// Convert an array of [`IoVec`] into buffers, checking each of them
+fn sys_arg_to_io_vecs(
+    array_ptr: *const u8,
+    array_size: usize,
+) -> Result<UserIoVecs, SyscallArgError> {
+    if array_size > MAX_IO_VECS {
+        return Err(SyscallArgError::GeneralInvalid);
+    }
+    let io_vecs = UserSlice::<IoVec>::new(array_ptr, array_size)?.to_vec();
+
+    let bufs = io_vecs
+        .iter()
+        .map(|io_vec| UserSlice::new(io_vec.base, io_vec.len))
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = bufs.iter().map(UserSlice::len).sum();
+    Ok(UserIoVecs { bufs, len })
+}
+
+/// Open the files (or create the pipes) requested by the redirects, returns for each the file for
+/// the child and the read end of the pipe for the parent if any
+fn open_spawn_redirects(
+    redirects: &[SpawnStdioRedirect],
+) -> Result<Vec<(fs::File, Option<fs::File>)>, SyscallError> {
+    let mut files = Vec::with_capacity(redirects.len());
+    for redirect in redirects {
+        let entry = match redirect.kind {
+            spawn_redirect::FILE_TRUNCATE | spawn_redirect::FILE_APPEND => {
+                let path = sys_arg_to_path(redirect.path).map_err(|err| to_arg_err!(0, err))?;
+                let append = redirect.kind == spawn_redirect::FILE_APPEND;
+                let mut open_options = OpenOptions::new();
+                open_options
+                    .write(true)
+                    .create(true)
+                    .truncate(!append)
+                    .append(append);
+
+                let absolute_path = path_to_proc_absolute_path(&path);
+                let file =
+                    fs::File::open_blocking(absolute_path, BlockingMode::None, open_options)?;
+                (file, None)
+            }
+            spawn_redirect::PIPE => {
+                let (read_file, write_file) =
+                    devices::pipe::create_pipe_pair(PIPE_DEFAULT_CAPACITY);
+                (write_file, Some(read_file))
+            }
+            _ => unreachable!("redirect kind is validated"),
+        };
+        files.push(entry);
+    }
+    Ok(files)
+}
+
 /// Get the absolute path, if the `path` is relative, it will use the current process working directory to get the absolute path.
-/// If the `path` is absolute, it will return it as is.
-fn path_to_proc_absolute_path(path: &Path) -> Cow<'_, Path> {
+///
+/// All paths coming from userspace must go through this, the result is normalized
+/// (see [`Path::normalize`]), so `..` can't go above the root.
+fn path_to_proc_absolute_path(path: &Path) -> PathBuf {
     let absolute_path = if path.is_absolute() {
-        Cow::Borrowed(path)
+        path.normalize()
     } else {
         let current_dir =
             with_current_process(|process| process.get_current_dir().path().to_path_buf());
-        Cow::Owned(current_dir.join(path))
+        current_dir.join(path).normalize()
     };
     assert!(absolute_path.is_absolute());
 
     absolute_path
 }
 
+/// Same as [`path_to_proc_absolute_path`], but relative paths are resolved from the directory
+/// opened at `dir_fd` instead, or from the current directory if `dir_fd` is [`DIR_FD_CWD`].
+fn path_to_dir_absolute_path(dir_fd: usize, path: &Path) -> Result<PathBuf, SyscallError> {
+    if path.is_absolute() || dir_fd == DIR_FD_CWD {
+        return Ok(path_to_proc_absolute_path(path));
+    }
+
+    let dir_path = with_current_process(|process| -> Result<PathBuf, SyscallError> {
+        let dir = process
+            .get_handle(dir_fd)
+            .ok_or(SyscallError::InvalidFileIndex)?;
+        Ok(dir.as_dir_mut()?.path().to_path_buf())
+    })?;
+    let absolute_path = dir_path.join(path).normalize();
+    assert!(absolute_path.is_absolute());
+
+    Ok(absolute_path)
+}
+
 fn sys_open(all_state: &mut InterruptAllSavedState) -> SyscallResult {
     let (path, open_options, flags, ..) = verify_args! {
         sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
@@ -225,9 +651,30 @@ fn sys_open(all_state: &mut InterruptAllSavedState) -> SyscallResult {
     let blocking_mode = kernel_user_link::file::parse_flags(flags)
         .ok_or(to_arg_err!(2, SyscallArgError::GeneralInvalid))?;
 
-    let absolute_path = path_to_proc_absolute_path(path);
+    let absolute_path = path_to_proc_absolute_path(&path);
     let file = fs::File::open_blocking(absolute_path, blocking_mode, open_options)?;
-    let file_index = with_current_process(|process| process.push_fs_node(file));
+    let file_index = with_current_process(|process| process.push_handle(file));
+
+    SyscallResult::Ok(file_index as u64)
+}
+
+fn sys_openat(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (dir_fd, path, open_options, flags, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => sys_arg_to_path(*const u8)),
+        sys_arg!(2, all_state.rest => u64),
+        sys_arg!(3, all_state.rest => u64),
+    };
+
+    let open_options = OpenOptions::from_u64(open_options)
+        .ok_or(to_arg_err!(2, SyscallArgError::GeneralInvalid))?;
+
+    let blocking_mode = kernel_user_link::file::parse_flags(flags)
+        .ok_or(to_arg_err!(3, SyscallArgError::GeneralInvalid))?;
+
+    let absolute_path = path_to_dir_absolute_path(dir_fd, &path)?;
+    let file = fs::File::open_blocking(absolute_path, blocking_mode, open_options)?;
+    let file_index = with_current_process(|process| process.push_handle(file));
 
     SyscallResult::Ok(file_index as u64)
 }
@@ -238,15 +685,90 @@ fn sys_write(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(1, all_state.rest => *const u8),
         sys_arg!(2, all_state.rest => usize),
     };
-    let buf = sys_arg_to_slice(buf, size).map_err(|err| to_arg_err!(0, err))?;
-    let bytes_written = with_current_process(|process| -> Result<u64, SyscallError> {
-        let file = process
-            .get_fs_node(file_index)
-            .ok_or(SyscallError::InvalidFileIndex)?;
+    let buf = UserSlice::<u8>::new(buf, size).map_err(|err| to_arg_err!(0, err))?;
+    write_chunked(file_index, all_state, size, |offset, chunk| {
+        buf.read_into(offset, chunk)
+    })
+}
 
-        file.as_file_mut()?.write(buf).map_err(|e| e.into())
-    })?;
-    SyscallResult::Ok(bytes_written)
+/// Run `transfer` on each chunk of `len` bytes, through a kernel buffer of [`IO_CHUNK_SIZE`] bytes,
+/// with the offset of the chunk, until all of it is transferred or a chunk is short.
+///
+/// Errors after some of the data is transferred are dropped, and reported by the next call instead.
+fn transfer_chunked(
+    len: usize,
+    mut transfer: impl FnMut(usize, &mut [u8]) -> Result<u64, SyscallError>,
+) -> SyscallResult {
+    let mut chunk_buf = vec![0; len.min(IO_CHUNK_SIZE)];
+    let mut total = 0;
+    loop {
+        let chunk = &mut chunk_buf[..(len - total).min(IO_CHUNK_SIZE)];
+        let transferred = match transfer(total, chunk) {
+            Ok(transferred) => transferred as usize,
+            Err(e) if total == 0 => return Err(e),
+            Err(_) => break,
+        };
+        total += transferred;
+        if transferred < chunk.len() || total == len {
+            break;
+        }
+    }
+    SyscallResult::Ok(total as u64)
+}
+
+/// Write `len` bytes to the handle at `file_index`, `fill` copies the user data at an offset into
+/// the chunk to write, only the first chunk waits for space (see [`write_waiting`])
+fn write_chunked(
+    file_index: usize,
+    all_state: &mut InterruptAllSavedState,
+    len: usize,
+    mut fill: impl FnMut(usize, &mut [u8]),
+) -> SyscallResult {
+    transfer_chunked(len, |offset, chunk| {
+        fill(offset, chunk);
+        if offset == 0 {
+            write_waiting(file_index, all_state, |handle| handle.write(chunk))
+        } else {
+            with_current_process(|process| {
+                let handle = process
+                    .get_handle(file_index)
+                    .ok_or(SyscallError::InvalidFileIndex)?;
+                Ok(handle.write(chunk)?)
+            })
+        }
+    })
+}
+
+/// Write to the handle at `file_index`, if it's full (i.e. a pipe) the process waits on its wait
+/// queue, and the syscall runs again when woken, the result is discarded in that case.
+fn write_waiting(
+    file_index: usize,
+    all_state: &mut InterruptAllSavedState,
+    mut write: impl FnMut(&mut dyn KernelHandle) -> Result<u64, FileSystemError>,
+) -> Result<u64, SyscallError> {
+    loop {
+        let (result, wait) = with_current_process(|process| {
+            let handle = process
+                .get_handle(file_index)
+                .ok_or(SyscallError::InvalidFileIndex)?;
+            // taken before writing, so that space freed after the write wakes us
+            let wait = handle.wait_queue().map(|queue| {
+                let generation = queue.generation();
+                (queue, generation)
+            });
+            Ok::<_, SyscallError>((write(handle), wait))
+        })?;
+
+        match (result, wait) {
+            (Err(FileSystemError::WouldBlock), Some((queue, generation))) => {
+                if scheduler::wait_on_queue(&queue, generation, all_state) {
+                    return Ok(0);
+                }
+                // space was freed while writing, try again
+            }
+            (result, _) => return Ok(result?),
+        }
+    }
 }
 
 fn sys_read(all_state: &mut InterruptAllSavedState) -> SyscallResult {
@@ -255,62 +777,414 @@ fn sys_read(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(1, all_state.rest => *mut u8),
         sys_arg!(2, all_state.rest => usize),
     };
-    let buf = sys_arg_to_mut_slice(buf, size).map_err(|err| to_arg_err!(0, err))?;
-
-    // TODO: fix this hack
-    //
-    // So, that's this about?
-    // We want to read files in blocking mode, and some of these, for example the `/console` file
-    // relies on the keyboard interrupts, but while we are in `with_current_process` we don't get interrupts
-    // because we are inside a lock.
-    // So instead, we take the file out, read from it, and put it back
-    // this is only done for files that are blocking, otherwise we just read from it directly.
-    //
-    // This is a big issue because when threads come in view later, since reading from another thread will report that
-    // the file is not found which is not correct.
-    //
-    // A good solution would be to have waitable objects.
-    let (bytes_read, file) = with_current_process(|process| {
-        let file = process
-            .get_fs_node(file_index)
-            .ok_or(SyscallError::InvalidFileIndex)?
-            .as_file_mut()?;
-        if file.is_blocking() {
-            // take file now
-            let file = process
-                .take_fs_node(file_index)
+    let buf = UserSlice::<u8>::new(buf, size).map_err(|err| to_arg_err!(0, err))?;
+
+    read_chunked(file_index, all_state, size, |offset, data| {
+        buf.write_at(offset, data)
+    })
+}
+
+/// Read up to `len` bytes from the handle at `file_index`, `copy_out` copies each chunk read to
+/// the user memory at its offset.
+///
+/// Handles that may block are only read once, as the chunks after the first must not wait for data
+fn read_chunked(
+    file_index: usize,
+    all_state: &mut InterruptAllSavedState,
+    len: usize,
+    mut copy_out: impl FnMut(usize, &[u8]),
+) -> SyscallResult {
+    transfer_chunked(len, |offset, chunk| {
+        // TODO: fix this hack
+        //
+        // So, that's this about?
+        // We want to read files in blocking mode, and some of these are polled until they have data, but while we are in `with_current_process` we don't get interrupts
+        // because we are inside a lock.
+        // So instead, we take the handle out, read from it, and put it back
+        // this is only done for handles that may block, otherwise we just read from it directly.
+        // Devices with a wait queue (i.e. the console and pipes) don't poll, the process waits on the queue instead.
+        //
+        // This is a big issue because when threads come in view later, since reading from another thread will report that
+        // the file is not found which is not correct.
+        //
+        // A good solution would be to give every blocking device a wait queue.
+        let (bytes_read, handle) = with_current_process(|process| {
+            let handle = process
+                .get_handle(file_index)
                 .ok_or(SyscallError::InvalidFileIndex)?;
-            Ok((0, Some(file)))
+            if handle.poll().may_block {
+                if offset != 0 {
+                    return Ok((0, None));
+                }
+                // take handle now
+                let handle = process
+                    .take_handle(file_index)
+                    .ok_or(SyscallError::InvalidFileIndex)?;
+                Ok((0, Some(handle)))
+            } else {
+                let bytes_read = handle.read(chunk)?;
+                Ok::<_, SyscallError>((bytes_read, None))
+            }
+        })?;
+
+        let bytes_read = if let Some(handle) = handle {
+            read_blocking_handle(file_index, handle, all_state, |handle| handle.read(chunk))?
         } else {
-            let bytes_read = file.read(buf)?;
-            Ok::<_, SyscallError>((bytes_read, None))
+            bytes_read
+        };
+        copy_out(offset, &chunk[..bytes_read as usize]);
+        Ok(bytes_read)
+    })
+}
+
+/// Read from a `handle` taken out of the process (see `sys_read`), and put it back.
+///
+/// If the handle has no data yet and has a wait queue, the process waits on it, and the syscall
+/// runs again when woken, the result is discarded in that case.
+fn read_blocking_handle(
+    file_index: usize,
+    mut handle: Box<dyn KernelHandle>,
+    all_state: &mut InterruptAllSavedState,
+    mut read: impl FnMut(&mut dyn KernelHandle) -> Result<u64, FileSystemError>,
+) -> Result<u64, SyscallError> {
+    loop {
+        // taken before reading, so that data arriving after the read wakes us
+        let wait = handle.wait_queue().map(|queue| {
+            let generation = queue.generation();
+            (queue, generation)
+        });
+        let result = read(&mut *handle);
+        // put handle back, even on error
+        with_current_process(|process| process.put_handle(file_index, handle));
+
+        match (result, wait) {
+            (Err(FileSystemError::WouldBlock), Some((queue, generation))) => {
+                if scheduler::wait_on_queue(&queue, generation, all_state) {
+                    return Ok(0);
+                }
+                // woken while reading, try again
+                handle = with_current_process(|process| process.take_handle(file_index))
+                    .ok_or(SyscallError::InvalidFileIndex)?;
+            }
+            (result, _) => return Ok(result?),
         }
+    }
+}
+
+/// Same as `sys_read`, the buffers are filled in order as if they were one
+fn sys_readv(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, io_vecs, io_vecs_size, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => *const u8),
+        sys_arg!(2, all_state.rest => usize),
+    };
+    let bufs = sys_arg_to_io_vecs(io_vecs, io_vecs_size).map_err(|err| to_arg_err!(1, err))?;
+
+    read_chunked(file_index, all_state, bufs.len, |offset, data| {
+        bufs.write_at(offset, data)
+    })
+}
+
+/// Same as `sys_write`, the buffers are written in order as if they were one
+fn sys_writev(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, io_vecs, io_vecs_size, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => *const u8),
+        sys_arg!(2, all_state.rest => usize),
+    };
+    let bufs = sys_arg_to_io_vecs(io_vecs, io_vecs_size).map_err(|err| to_arg_err!(1, err))?;
+
+    write_chunked(file_index, all_state, bufs.len, |offset, chunk| {
+        bufs.read_into(offset, chunk)
+    })
+}
+
+fn sys_pread(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, buf, size, offset, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => *mut u8),
+        sys_arg!(2, all_state.rest => usize),
+        sys_arg!(3, all_state.rest => u64),
+    };
+    let buf = UserSlice::<u8>::new(buf, size).map_err(|err| to_arg_err!(1, err))?;
+
+    transfer_chunked(size, |chunk_offset, chunk| {
+        // doesn't block, so no need to take the file out like `sys_read`
+        let bytes_read = with_current_process(|process| -> Result<u64, SyscallError> {
+            let file = process
+                .get_handle(file_index)
+                .ok_or(SyscallError::InvalidFileIndex)?;
+
+            let offset = offset
+                .checked_add(chunk_offset as u64)
+                .ok_or(to_arg_err!(3, SyscallArgError::GeneralInvalid))?;
+            file.as_file_mut()?
+                .read_at(offset, chunk)
+                .map_err(|e| e.into())
+        })?;
+        buf.write_at(chunk_offset, &chunk[..bytes_read as usize]);
+        Ok(bytes_read)
+    })
+}
+
+fn sys_pwrite(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, buf, size, offset, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => *const u8),
+        sys_arg!(2, all_state.rest => usize),
+        sys_arg!(3, all_state.rest => u64),
+    };
+    let buf = UserSlice::<u8>::new(buf, size).map_err(|err| to_arg_err!(1, err))?;
+
+    transfer_chunked(size, |chunk_offset, chunk| {
+        buf.read_into(chunk_offset, chunk);
+        with_current_process(|process| -> Result<u64, SyscallError> {
+            let file = process
+                .get_handle(file_index)
+                .ok_or(SyscallError::InvalidFileIndex)?;
+
+            let offset = offset
+                .checked_add(chunk_offset as u64)
+                .ok_or(to_arg_err!(3, SyscallArgError::GeneralInvalid))?;
+            file.as_file_mut()?
+                .write_at(offset, chunk)
+                .map_err(|e| e.into())
+        })
+    })
+}
+
+fn sys_truncate(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (path, size, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
+        sys_arg!(1, all_state.rest => u64),
+    };
+
+    let absolute_path = path_to_proc_absolute_path(&path);
+    let mut file = fs::File::open_blocking(absolute_path, BlockingMode::None, OpenOptions::WRITE)?;
+    file.set_size(size)?;
+
+    SyscallResult::Ok(0)
+}
+
+fn sys_ftruncate(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, size, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => u64),
+    };
+
+    with_current_process(|process| -> Result<(), SyscallError> {
+        let file = process
+            .get_handle(file_index)
+            .ok_or(SyscallError::InvalidFileIndex)?;
+
+        file.as_file_mut()?.set_size(size).map_err(|e| e.into())
+    })?;
+    SyscallResult::Ok(0)
+}
+
+fn sys_fallocate(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, size, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => u64),
+    };
+
+    with_current_process(|process| -> Result<(), SyscallError> {
+        let file = process
+            .get_handle(file_index)
+            .ok_or(SyscallError::InvalidFileIndex)?;
+
+        file.as_file_mut()?.allocate(size).map_err(|e| e.into())
+    })?;
+    SyscallResult::Ok(0)
+}
+
+fn sys_fsync(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+    };
+
+    with_current_process(|process| -> Result<(), SyscallError> {
+        let file = process
+            .get_handle(file_index)
+            .ok_or(SyscallError::InvalidFileIndex)?;
+
+        file.as_file_mut()?.sync().map_err(|e| e.into())
+    })?;
+    SyscallResult::Ok(0)
+}
+
+fn sys_sync(_all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let mut result = Ok(());
+    fs::mapping::on_all_mappings(|path, filesystem| {
+        if let Err(e) = filesystem.sync() {
+            warn!("Failed to sync filesystem at {}: {e:?}", path.display());
+            // keep syncing the rest
+            result = Err(e);
+        }
+    });
+    result?;
+
+    SyscallResult::Ok(0)
+}
+
+fn sys_unmount(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (path, flags, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
+        sys_arg!(1, all_state.rest => u64),
+    };
+
+    if flags & !unmount_flags::ALL != 0 {
+        return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
+    }
+    let mode = if flags & unmount_flags::LAZY != 0 {
+        UnmountMode::Lazy
+    } else {
+        UnmountMode::Normal
+    };
+
+    let absolute_path = path_to_proc_absolute_path(&path);
+    fs::mapping::unmount(absolute_path.as_str(), mode).map_err(|e| match e {
+        MappingError::Busy => SyscallError::Busy,
+        _ => to_arg_err!(0, SyscallArgError::GeneralInvalid),
+    })?;
+
+    SyscallResult::Ok(0)
+}
+
+/// Move data between a file and a pipe without copying it to userspace, see
+/// [`devices::pipe::splice`]
+fn sys_splice(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (from_index, to_index, len, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => usize),
+        sys_arg!(2, all_state.rest => usize),
+    };
+
+    loop {
+        // both handles are taken out while moving the data, as the source may be polled,
+        // same as `sys_read`
+        let (mut from, mut to) = with_current_process(|process| {
+            let from = process
+                .take_handle(from_index)
+                .ok_or(SyscallError::InvalidFileIndex)?;
+            // the same handle can't be both a pipe and a file
+            let to = match process.take_handle(to_index) {
+                Some(to) => to,
+                None if from_index == to_index => {
+                    process.put_handle(from_index, from);
+                    return Err(SyscallError::OperationNotSupported);
+                }
+                None => {
+                    process.put_handle(from_index, from);
+                    return Err(SyscallError::InvalidFileIndex);
+                }
+            };
+            Ok((from, to))
+        })?;
+        // taken before moving, so that data or space arriving after it wakes us
+        let wait_of = |handle: &dyn KernelHandle| {
+            handle.wait_queue().map(|queue| {
+                let generation = queue.generation();
+                (queue, generation)
+            })
+        };
+        let (from_wait, to_wait) = (wait_of(&*from), wait_of(&*to));
+
+        let result = match (from.as_file_mut(), to.as_file_mut()) {
+            (Ok(from), Ok(to)) => devices::pipe::splice(from, to, len),
+            (Err(e), _) | (_, Err(e)) => Err(e.into()),
+        };
+        with_current_process(|process| {
+            process.put_handle(from_index, from);
+            process.put_handle(to_index, to);
+        });
+
+        let wait = match result {
+            Ok(moved) => return SyscallResult::Ok(moved),
+            Err(SpliceError::FileSystem(e)) => return Err(e.into()),
+            Err(SpliceError::SourceEmpty) => from_wait,
+            Err(SpliceError::DestinationFull) => to_wait,
+        };
+        let (queue, generation) = wait.expect("Only files with a wait queue block");
+        if scheduler::wait_on_queue(&queue, generation, all_state) {
+            return SyscallResult::Ok(0);
+        }
+        // woken while moving, try again
+    }
+}
+
+fn sys_watch(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (path, mask, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
+        sys_arg!(1, all_state.rest => u64),
+    };
+
+    if mask == 0 || mask & !(watch_events::ALL as u64) != 0 {
+        return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
+    }
+
+    let absolute_path = path_to_proc_absolute_path(&path);
+    let file = fs::notify::watch(&absolute_path, mask as u32)?;
+    let file_index = with_current_process(|process| process.push_handle(file));
+
+    SyscallResult::Ok(file_index as u64)
+}
+
+fn sys_close(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+    };
+
+    let handle = with_current_process(|process| process.take_handle(file_index))
+        .ok_or(SyscallError::InvalidFileIndex)?;
+    // close outside the process lock, as it may need to notify devices
+    handle.close();
+
+    SyscallResult::Ok(0)
+}
+
+fn sys_dup(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+    };
+
+    let new_index = with_current_process(|process| {
+        let handle = process
+            .get_handle(file_index)
+            .ok_or(SyscallError::InvalidFileIndex)?
+            .clone_duplicate();
+        Ok::<_, SyscallError>(process.push_handle(handle))
     })?;
 
-    let bytes_read = if let Some(mut file) = file {
-        let bytes_read = file.as_file_mut()?.read(buf)?;
-        // put file back
-        with_current_process(|process| process.put_fs_node(file_index, file));
-        bytes_read
-    } else {
-        bytes_read
-    };
-    SyscallResult::Ok(bytes_read)
+    SyscallResult::Ok(new_index as u64)
 }
 
-fn sys_close(all_state: &mut InterruptAllSavedState) -> SyscallResult {
-    let (file_index, ..) = verify_args! {
+fn sys_dup2(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (file_index, new_index, ..) = verify_args! {
         sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => usize),
     };
 
-    with_current_process(|process| {
-        process
-            .take_fs_node(file_index)
+    if new_index > MAX_FD {
+        return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
+    }
+
+    let old_handle = with_current_process(|process| {
+        let handle = process
+            .get_handle(file_index)
             .ok_or(SyscallError::InvalidFileIndex)?;
-        Ok::<_, SyscallError>(())
+        if file_index == new_index {
+            return Ok(None);
+        }
+        let handle = handle.clone_duplicate();
+        Ok::<_, SyscallError>(process.replace_handle(new_index, handle))
     })?;
+    // close the old handle outside the process lock, as it may need to notify devices
+    if let Some(old_handle) = old_handle {
+        old_handle.close();
+    }
 
-    SyscallResult::Ok(0)
+    SyscallResult::Ok(new_index as u64)
 }
 
 fn sys_blocking_mode(all_state: &mut InterruptAllSavedState) -> SyscallResult {
@@ -324,7 +1198,7 @@ fn sys_blocking_mode(all_state: &mut InterruptAllSavedState) -> SyscallResult {
 
     with_current_process(|process| {
         let file = process
-            .get_fs_node(file_index)
+            .get_handle(file_index)
             .ok_or(SyscallError::InvalidFileIndex)?;
         file.as_file_mut()?.set_blocking(blocking_mode);
         Ok::<_, SyscallError>(())
@@ -344,63 +1218,137 @@ fn sys_exit(all_state: &mut InterruptAllSavedState) -> SyscallResult {
 }
 
 fn sys_spawn(all_state: &mut InterruptAllSavedState) -> SyscallResult {
-    let (path, argv, file_mappings, file_mappings_size, ..) = verify_args! {
-        sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
-        sys_arg!(1, all_state.rest => *const u8),   // array of pointers
-        sys_arg!(2, all_state.rest => *const u8),   // array of mappings or null
-        sys_arg!(3, all_state.rest => usize),       // size of the array
+    let (options, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => sys_arg_to_spawn_options(*const u8)),
+    };
+    // all the errors are reported on the only argument, `options`
+    let options_err = |err| to_arg_err!(0, err);
+    let path = sys_arg_to_path(options.path).map_err(options_err)?;
+    let argv = sys_arg_to_str_array(options.argv as _).map_err(options_err)?;
+    let env = if options.env.is_null() {
+        None
+    } else {
+        Some(sys_arg_to_str_array(options.env as _).map_err(options_err)?)
     };
-    let argv = sys_arg_to_str_array(argv).map_err(|err| to_arg_err!(1, err))?;
-    let file_mappings = sys_arg_to_file_mappings_array(file_mappings, file_mappings_size)
-        .map_err(|err| to_arg_err!(2, err))?;
+    let current_dir = if options.current_dir.is_null() {
+        None
+    } else {
+        Some(sys_arg_to_path(options.current_dir).map_err(options_err)?)
+    };
+    let file_mappings =
+        sys_arg_to_file_mappings_array(options.file_mappings as _, options.file_mappings_len)
+            .map_err(options_err)?;
+    let (user_redirects, mut redirects) = sys_arg_to_redirects_array(
+        options.redirects as _,
+        options.redirects_len,
+        &file_mappings,
+    )
+    .map_err(options_err)?;
+    let priority = match options.priority {
+        0 => None,
+        value => Some(
+            PriorityLevel::from_u64(value)
+                .ok_or(to_arg_err!(0, SyscallArgError::GeneralInvalid))?,
+        ),
+    };
+    let scheduling_class = match options.scheduling_class {
+        0 => None,
+        value => Some(
+            SchedulingClass::from_u64(value)
+                .ok_or(to_arg_err!(0, SyscallArgError::GeneralInvalid))?,
+        ),
+    };
+
+    // the child can't be scheduled better than the parent
+    let (priority, scheduling_class) = with_current_process(|process| {
+        let parent_priority = process.get_priority();
+        let parent_class = process.scheduling_class();
+        let priority = priority.unwrap_or(parent_priority);
+        let scheduling_class = scheduling_class.unwrap_or(parent_class);
+        if priority.to_u64() > parent_priority.to_u64()
+            || (parent_class == SchedulingClass::Batch && scheduling_class != parent_class)
+        {
+            return Err(SyscallError::PermissionDenied);
+        }
+        Ok((priority, scheduling_class))
+    })?;
 
     // don't go into lock if no need to
     if !file_mappings.is_empty() {
         // a bit unoptimal, but check all files first before taking them and doing any action
         with_current_process(|process| {
-            for mapping in file_mappings {
+            for mapping in &file_mappings {
                 process
-                    .get_fs_node(mapping.src_fd)
+                    .get_handle(mapping.src_fd)
                     .ok_or(SyscallError::InvalidFileIndex)?;
             }
             Ok::<_, SyscallError>(())
         })?;
     }
 
-    let absolute_path = path_to_proc_absolute_path(path);
+    let absolute_path = path_to_proc_absolute_path(&path);
 
     let mut file = fs::File::open(absolute_path)?;
-    let elf = Elf::load(&mut file).map_err(|_| SyscallError::CouldNotLoadElf)?;
-    let (current_pid, current_dir) =
-        with_current_process(|process| (process.id, process.get_current_dir().clone()));
+    let elf = Elf::load(&mut file)?;
+    // open them before creating the process, so that if any fails, nothing is created
+    let redirect_files = open_spawn_redirects(&redirects)?;
+    let current_dir = match current_dir {
+        Some(current_dir) => fs::Directory::open(path_to_proc_absolute_path(&current_dir))?,
+        None => with_current_process(|process| process.get_current_dir().clone()),
+    };
+    let (current_pid, env) =
+        with_current_process(|process| (process.id, env.unwrap_or_else(|| process.env().to_vec())));
     let mut new_process =
-        Process::allocate_process(current_pid, &elf, &mut file, argv, current_dir)
+        Process::allocate_process(current_pid, &elf, &mut file, argv, env, current_dir)
             .map_err(|_| SyscallError::CouldNotAllocateProcess)?;
+    new_process.set_priority(priority);
+    new_process.set_scheduling_class(scheduling_class);
 
     let mut std_needed = [true; 3];
-    with_current_process(|process| {
+    let attached = with_current_process(|process| {
         // take the files if any
         for mapping in file_mappings.iter() {
-            let file = process
-                .take_fs_node(mapping.src_fd)
+            let mut handle = process
+                .take_handle(mapping.src_fd)
                 .ok_or(SyscallError::InvalidFileIndex)?;
-            new_process.attach_fs_node_to_fd(mapping.dst_fd, file);
+            // explicitly given to the child, so the flag doesn't apply, and the child gets a new fd
+            if let Ok(file) = handle.as_file_mut() {
+                file.set_close_on_spawn(false);
+            }
+            new_process.attach_handle_to_fd(mapping.dst_fd, handle);
             if mapping.dst_fd <= FD_STDERR {
                 std_needed[mapping.dst_fd] = false;
             }
         }
 
-        // inherit files STD files if not set
+        for (redirect, (file, pipe_read_file)) in redirects.iter_mut().zip(redirect_files) {
+            new_process.attach_handle_to_fd(redirect.dst_fd, file);
+            std_needed[redirect.dst_fd] = false;
+            if let Some(pipe_read_file) = pipe_read_file {
+                redirect.pipe_read_fd = process.push_handle(pipe_read_file);
+            }
+        }
+
+        // inherit files STD files if not set, unless marked as close-on-spawn, then the
+        // child won't have that fd
         for (i, _) in std_needed.iter().enumerate().filter(|(_, &b)| b) {
-            let file = process
-                .get_fs_node(i)
+            let handle = process
+                .get_handle(i)
                 .ok_or(SyscallError::InvalidFileIndex)?;
-            let inherited_file = file.as_file()?.clone_inherit();
-            new_process.attach_fs_node_to_fd(i, inherited_file);
+            if let Some(handle) = handle.clone_inherit() {
+                new_process.attach_handle_to_fd(i, handle);
+            }
         }
 
         Ok::<_, SyscallError>(())
-    })?;
+    });
+    if let Err(err) = attached {
+        // outside the process lock, closes the handles given to it so far
+        new_process.release();
+        return Err(err);
+    }
+    // give the read ends of the pipes to the caller
+    user_redirects.write_at(0, &redirects);
 
     let new_pid = new_process.id();
     // make sure fds are setup correctly
@@ -426,25 +1374,29 @@ fn sys_inc_heap(all_state: &mut InterruptAllSavedState) -> SyscallResult {
 }
 
 fn sys_create_pipe(all_state: &mut InterruptAllSavedState) -> SyscallResult {
-    let (read_fd_ptr, write_fd_ptr, ..) = verify_args! {
+    let (read_fd_ptr, write_fd_ptr, capacity, ..) = verify_args! {
         sys_arg!(0, all_state.rest => *mut usize),
         sys_arg!(1, all_state.rest => *mut usize),
+        sys_arg!(2, all_state.rest => usize),
+    };
+    let read_fd_ptr = UserPtr::new(read_fd_ptr as *mut u8).map_err(|err| to_arg_err!(0, err))?;
+    let write_fd_ptr = UserPtr::new(write_fd_ptr as *mut u8).map_err(|err| to_arg_err!(1, err))?;
+    let capacity = match capacity {
+        0 => PIPE_DEFAULT_CAPACITY,
+        1..=PIPE_MAX_CAPACITY => capacity.max(PIPE_MIN_CAPACITY),
+        _ => return Err(to_arg_err!(2, SyscallArgError::GeneralInvalid)),
     };
-    let read_fd_ptr = ptr_as_mut(read_fd_ptr as *mut u8).map_err(|err| to_arg_err!(0, err))?;
-    let write_fd_ptr = ptr_as_mut(write_fd_ptr as *mut u8).map_err(|err| to_arg_err!(1, err))?;
 
-    let (read_file, write_file) = devices::pipe::create_pipe_pair();
+    let (read_file, write_file) = devices::pipe::create_pipe_pair(capacity);
     let (read_fd, write_fd) = with_current_process(|process| {
         (
-            process.push_fs_node(read_file),
-            process.push_fs_node(write_file),
+            process.push_handle(read_file),
+            process.push_handle(write_file),
         )
     });
 
-    unsafe {
-        *read_fd_ptr = read_fd;
-        *write_fd_ptr = write_fd;
-    }
+    read_fd_ptr.write(read_fd);
+    write_fd_ptr.write(write_fd);
 
     SyscallResult::Ok(0)
 }
@@ -462,6 +1414,11 @@ fn sys_wait_pid(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         return SyscallResult::Ok(exit_code as u64);
     }
 
+    // waiting for ourselves would never finish
+    if pid == with_current_process(|process| process.id) {
+        return Err(to_arg_err!(0, SyscallArgError::GeneralInvalid));
+    }
+
     if !block {
         if scheduler::is_process_running(pid) {
             return Err(SyscallError::ProcessStillRunning);
@@ -484,14 +1441,44 @@ fn sys_stat(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
         sys_arg!(1, all_state.rest => *mut u8),
     };
-    let stat_ptr = ptr_as_mut(stat_ptr).map_err(|err| to_arg_err!(1, err))?;
+    let stat_ptr = UserPtr::new(stat_ptr).map_err(|err| to_arg_err!(1, err))?;
 
-    let absolute_path = path_to_proc_absolute_path(path);
+    let absolute_path = path_to_proc_absolute_path(&path);
     let (_, _, inode) = fs::open_inode(absolute_path)?;
 
-    unsafe {
-        *stat_ptr = inode.as_file_stat();
-    }
+    stat_ptr.write(inode.as_file_stat());
+
+    SyscallResult::Ok(0)
+}
+
+fn sys_statat(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (dir_fd, path, stat_ptr, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => sys_arg_to_path(*const u8)),
+        sys_arg!(2, all_state.rest => *mut u8),
+    };
+    let stat_ptr = UserPtr::new(stat_ptr).map_err(|err| to_arg_err!(2, err))?;
+
+    let absolute_path = path_to_dir_absolute_path(dir_fd, &path)?;
+    let (_, _, inode) = fs::open_inode(absolute_path)?;
+
+    stat_ptr.write(inode.as_file_stat());
+
+    SyscallResult::Ok(0)
+}
+
+fn sys_fs_stat(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (path, stat_ptr, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
+        sys_arg!(1, all_state.rest => *mut u8),
+    };
+    let stat_ptr = UserPtr::new(stat_ptr).map_err(|err| to_arg_err!(1, err))?;
+
+    let absolute_path = path_to_proc_absolute_path(&path);
+    let (_, filesystem, _) = fs::open_inode(absolute_path)?;
+    let stat = filesystem.fs_stat()?;
+
+    stat_ptr.write(stat);
 
     SyscallResult::Ok(0)
 }
@@ -501,9 +1488,22 @@ fn sys_open_dir(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
     };
 
-    let absolute_path = path_to_proc_absolute_path(path);
+    let absolute_path = path_to_proc_absolute_path(&path);
+    let dir = fs::Directory::open(absolute_path)?;
+    let dir_index = with_current_process(|process| process.push_handle(dir));
+
+    SyscallResult::Ok(dir_index as u64)
+}
+
+fn sys_open_dir_at(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (dir_fd, path, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => sys_arg_to_path(*const u8)),
+    };
+
+    let absolute_path = path_to_dir_absolute_path(dir_fd, &path)?;
     let dir = fs::Directory::open(absolute_path)?;
-    let dir_index = with_current_process(|process| process.push_fs_node(dir));
+    let dir_index = with_current_process(|process| process.push_handle(dir));
 
     SyscallResult::Ok(dir_index as u64)
 }
@@ -514,14 +1514,32 @@ fn sys_read_dir(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(1, all_state.rest => *mut u8),
         sys_arg!(2, all_state.rest => usize),
     };
-    let buf = sys_arg_to_mut_slice::<DirEntry>(buf, len).map_err(|err| to_arg_err!(1, err))?;
+    let buf = UserSlice::<DirEntry>::new(buf, len).map_err(|err| to_arg_err!(1, err))?;
 
-    let entries_read = with_current_process(|process| -> Result<usize, SyscallError> {
-        let file = process
-            .get_fs_node(dir_index)
-            .ok_or(SyscallError::InvalidFileIndex)?;
-        file.as_dir_mut()?.read(buf).map_err(|e| e.into())
-    })?;
+    // read through a kernel buffer, a chunk at a time
+    let chunk_len = (IO_CHUNK_SIZE / mem::size_of::<DirEntry>()).max(1);
+    let mut chunk_buf = vec![DirEntry::default(); len.min(chunk_len)];
+    let mut entries_read = 0;
+    loop {
+        let chunk = &mut chunk_buf[..(len - entries_read).min(chunk_len)];
+        let result = with_current_process(|process| -> Result<usize, SyscallError> {
+            let file = process
+                .get_handle(dir_index)
+                .ok_or(SyscallError::InvalidFileIndex)?;
+            file.as_dir_mut()?.read(chunk).map_err(|e| e.into())
+        });
+        let chunk_read = match result {
+            Ok(chunk_read) => chunk_read,
+            Err(e) if entries_read == 0 => return Err(e),
+            // report the error on the next call
+            Err(_) => break,
+        };
+        buf.write_at(entries_read, &chunk[..chunk_read]);
+        entries_read += chunk_read;
+        if chunk_read < chunk.len() || entries_read == len {
+            break;
+        }
+    }
 
     SyscallResult::Ok(entries_read as u64)
 }
@@ -531,17 +1549,36 @@ fn sys_get_cwd(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(0, all_state.rest => *mut u8),
         sys_arg!(1, all_state.rest => usize),
     };
-    let buf = sys_arg_to_mut_slice::<u8>(buf, len).map_err(|err| to_arg_err!(0, err))?;
+    let buf = UserSlice::<u8>::new(buf, len).map_err(|err| to_arg_err!(0, err))?;
 
-    let needed_bytes = with_current_process(|process| -> Result<usize, SyscallError> {
-        let cwd = process.get_current_dir().path();
-        let needed_bytes = cwd.as_str().as_bytes().len();
-        if needed_bytes > len {
-            return Err(SyscallError::BufferTooSmall);
-        }
-        buf[..needed_bytes].copy_from_slice(cwd.as_str().as_bytes());
-        Ok(needed_bytes)
-    })?;
+    // copied out of the process lock, as writing to user memory may fault
+    let cwd = with_current_process(|process| process.get_current_dir().path().to_path_buf());
+    let needed_bytes = cwd.as_str().as_bytes().len();
+    if needed_bytes > len {
+        return Err(SyscallError::BufferTooSmall(needed_bytes));
+    }
+    buf.write_at(0, cwd.as_str().as_bytes());
+
+    SyscallResult::Ok(needed_bytes as u64)
+}
+
+fn sys_realpath(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (path, buf, len, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
+        sys_arg!(1, all_state.rest => *mut u8),
+        sys_arg!(2, all_state.rest => usize),
+    };
+    let buf = UserSlice::<u8>::new(buf, len).map_err(|err| to_arg_err!(1, err))?;
+
+    let absolute_path = path_to_proc_absolute_path(&path);
+    // make sure it exists, and get the path as the filesystem sees it
+    let (canonical_path, _, _) = fs::open_inode(absolute_path)?;
+
+    let needed_bytes = canonical_path.as_str().len();
+    if needed_bytes > len {
+        return Err(SyscallError::BufferTooSmall(needed_bytes));
+    }
+    buf.write_at(0, canonical_path.as_str().as_bytes());
 
     SyscallResult::Ok(needed_bytes as u64)
 }
@@ -551,7 +1588,7 @@ fn sys_chdir(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(0, all_state.rest => sys_arg_to_path(*const u8)),
     };
 
-    let absolute_path = path_to_proc_absolute_path(path);
+    let absolute_path = path_to_proc_absolute_path(&path);
     let dir = fs::Directory::open(absolute_path)?;
     with_current_process(|process| process.set_current_dir(dir));
 
@@ -571,10 +1608,10 @@ fn sys_set_file_meta(all_state: &mut InterruptAllSavedState) -> SyscallResult {
 
     let op_on_file = |op: &dyn Fn(&mut fs::File)| {
         with_current_process(|process| {
-            let file = process
-                .get_fs_node(file_index)
+            let handle = process
+                .get_handle(file_index)
                 .ok_or(SyscallError::InvalidFileIndex)?;
-            op(file.as_file_mut()?);
+            op(handle.as_file_mut()?);
             Ok::<_, SyscallError>(())
         })
     };
@@ -586,6 +1623,21 @@ fn sys_set_file_meta(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         FileMeta::IsTerminal(is_terminal) => {
             op_on_file(&|file| file.set_terminal(is_terminal))?;
         }
+        FileMeta::CloseOnSpawn(close_on_spawn) => {
+            op_on_file(&|file| file.set_close_on_spawn(close_on_spawn))?;
+        }
+        FileMeta::ReadTimeout(nanos) => {
+            op_on_file(&|file| file.set_read_timeout(nanos))?;
+        }
+        FileMeta::LineEditing(line_editing) => {
+            with_current_process(|process| {
+                let handle = process
+                    .get_handle(file_index)
+                    .ok_or(SyscallError::InvalidFileIndex)?;
+                handle.as_file()?.set_line_editing(line_editing)?;
+                Ok::<_, SyscallError>(())
+            })?;
+        }
         _ => {
             return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
         }
@@ -600,7 +1652,8 @@ fn sys_get_file_meta(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         sys_arg!(1, all_state.rest => u64),
         sys_arg!(2, all_state.rest => *mut u64),
     };
-    let meta_data_ptr = ptr_as_mut(meta_data_ptr as *mut u8).map_err(|err| to_arg_err!(2, err))?;
+    let meta_data_ptr =
+        UserPtr::new(meta_data_ptr as *mut u8).map_err(|err| to_arg_err!(2, err))?;
 
     let meta_op = FileMeta::try_from((meta_id, 0))
         .ok()
@@ -608,12 +1661,19 @@ fn sys_get_file_meta(all_state: &mut InterruptAllSavedState) -> SyscallResult {
 
     let data = with_current_process(|process| {
         let file = process
-            .get_fs_node(file_index)
+            .get_handle(file_index)
             .ok_or(SyscallError::InvalidFileIndex)?;
 
         let meta_data = match meta_op {
             FileMeta::BlockingMode(..) => file.as_file()?.blocking_mode().to_u64(),
             FileMeta::IsTerminal(..) => file.as_file()?.is_terminal() as u64,
+            FileMeta::CloseOnSpawn(..) => file.as_file()?.is_close_on_spawn() as u64,
+            FileMeta::ReadTimeout(..) => file.as_file()?.read_timeout(),
+            FileMeta::LineEditing(..) => {
+                file.as_file()?
+                    .line_editing()
+                    .ok_or(SyscallError::OperationNotSupported)? as u64
+            }
             _ => {
                 return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
             }
@@ -622,8 +1682,7 @@ fn sys_get_file_meta(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         Ok::<_, SyscallError>(meta_data)
     })?;
 
-    // Safety: we checked that the pointer is valid
-    unsafe { *meta_data_ptr = data }
+    meta_data_ptr.write(data);
 
     SyscallResult::Ok(0)
 }
@@ -654,13 +1713,43 @@ fn sys_sleep(all_state: &mut InterruptAllSavedState) -> SyscallResult {
     SyscallResult::Ok(0)
 }
 
+/// Sleep until the system time (since startup, i.e. [`ClockType::SystemTime`]) reaches the deadline,
+/// returns immediately if it has already passed
+fn sys_sleep_until(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (seconds, nanoseconds, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => u64),
+        sys_arg!(1, all_state.rest => u64),
+    };
+
+    if nanoseconds >= clock::NANOS_PER_SEC {
+        return Err(to_arg_err!(1, SyscallArgError::InvalidNanoseconds));
+    }
+
+    let deadline = clock::ClockTime {
+        seconds,
+        nanoseconds,
+    };
+
+    if deadline <= clock::clocks().time_since_startup() {
+        return SyscallResult::Ok(0);
+    }
+
+    // put the result manually, as we will go back to the kernel after the call below
+    all_state.rest.rax = 0;
+
+    // modify the all_state to go back to the kernel, the current all_state will be dropped
+    sleep_current_process_until(deadline, all_state);
+
+    SyscallResult::Ok(0)
+}
+
 fn sys_get_time(all_state: &mut InterruptAllSavedState) -> SyscallResult {
     let (time_type, time_ptr, ..) = verify_args! {
         sys_arg!(0, all_state.rest => u64),
         sys_arg!(1, all_state.rest => *mut u8),
     };
-    let time_ptr: *mut kernel_user_link::clock::ClockTime =
-        ptr_as_mut(time_ptr).map_err(|err| to_arg_err!(1, err))?;
+    let time_ptr = UserPtr::<kernel_user_link::clock::ClockTime>::new(time_ptr)
+        .map_err(|err| to_arg_err!(1, err))?;
 
     let time_type = ClockType::try_from(time_type)
         .map_err(|_| to_arg_err!(0, SyscallArgError::GeneralInvalid))?;
@@ -669,15 +1758,16 @@ fn sys_get_time(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         ClockType::RealTime => clock::clocks().time_since_unix_epoch().into(),
         ClockType::SystemTime => clock::clocks().time_since_startup().into(),
     };
-    // Safety: we checked that the pointer is valid
-    unsafe {
-        *time_ptr = time;
-    }
+    time_ptr.write(time);
 
     SyscallResult::Ok(0)
 }
 
 fn sys_graphics(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    // without the `graphics` feature, the display is only used by the console
+    if !cfg!(feature = "graphics") {
+        return Err(SyscallError::GraphicsNotAvailable);
+    }
     let (command_id, extra, ..) = verify_args! {
         sys_arg!(0, all_state.rest => u64),
         sys_arg!(1, all_state.rest => *mut u8)
@@ -705,31 +1795,67 @@ fn sys_graphics(all_state: &mut InterruptAllSavedState) -> SyscallResult {
                 return Err(SyscallError::GraphicsNotOwned);
             }
         }
+        GraphicsCommand::ForceRelease => {
+            let controller =
+                graphics::vga::controller().ok_or(SyscallError::GraphicsNotAvailable)?;
+            let owner = controller.owner().ok_or(SyscallError::GraphicsNotOwned)?;
+            if owner != pid && pid != 0 {
+                // the owner may be exiting, if it's gone already, anyone can release it
+                let owner_parent =
+                    scheduler::try_with_process(owner, |process| process.parent_id());
+                if owner_parent.is_some_and(|owner_parent| owner_parent != pid) {
+                    return Err(SyscallError::PermissionDenied);
+                }
+            }
+            // if it was released in the meantime, that's fine
+            controller.release(owner);
+        }
         GraphicsCommand::GetFrameBufferInfo => {
             let info = *graphics::vga::controller()
                 .ok_or(SyscallError::GraphicsNotAvailable)?
                 .framebuffer_info();
-            let info_ptr =
-                ptr_as_mut::<FrameBufferInfo>(extra).map_err(|err| to_arg_err!(1, err))?;
-            // Safety: we checked that the pointer is valid
-            unsafe {
-                *info_ptr = info;
-            }
+            UserPtr::<FrameBufferInfo>::new(extra)
+                .map_err(|err| to_arg_err!(1, err))?
+                .write(info);
         }
         GraphicsCommand::Blit => {
-            let blit = ptr_as_ref::<BlitCommand>(extra).map_err(|err| to_arg_err!(1, err))?;
-            // Safety: we checked that the pointer is valid
-            let blit = unsafe { *blit };
-
-            let buffer_len = blit.src_framebuffer_info.memory_size();
-            let buffer = sys_arg_to_slice(blit.memory, buffer_len)
+            let blit = UserPtr::<BlitCommand>::new(extra)
+                .map_err(|err| to_arg_err!(1, err))?
+                .read();
+
+            let buffer_len = blit
+                .src_framebuffer_info
+                .pitch
+                .checked_mul(blit.src_framebuffer_info.height)
+                .ok_or(SyscallError::InvalidGraphicsBuffer)?;
+            let buffer = UserSlice::<u8>::new(blit.memory, buffer_len)
                 .map_err(|_| SyscallError::InvalidGraphicsBuffer)?;
+            let damage = UserSlice::<Rect>::new(blit.damage as _, blit.damage_len)
+                .map_err(|err| to_arg_err!(1, err))?
+                .to_vec();
+
+            let controller =
+                graphics::vga::controller().ok_or(SyscallError::GraphicsNotAvailable)?;
+            if !blit.src_framebuffer_info.contains_rect(blit.src, blit.size)
+                || !controller
+                    .framebuffer_info()
+                    .contains_rect(blit.dst, blit.size)
+                || !damage
+                    .iter()
+                    .all(|rect| rect.is_inside(blit.src, blit.size))
+            {
+                return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
+            }
 
-            graphics::vga::controller()
-                .ok_or(SyscallError::GraphicsNotAvailable)?
+            let mut display = controller
                 .lock_process(pid)
-                .ok_or(SyscallError::GraphicsNotOwned)?
-                .blit(
+                .ok_or(SyscallError::GraphicsNotOwned)?;
+            // the buffer is too big to copy, so it's read in place
+            let _user_access = user_access::allow_user_access();
+            // SAFETY: user access is allowed until the end of the blit
+            let buffer = unsafe { buffer.as_slice() };
+            if damage.is_empty() {
+                display.blit(
                     buffer,
                     &blit.src_framebuffer_info,
                     blit.src,
@@ -737,6 +1863,85 @@ fn sys_graphics(all_state: &mut InterruptAllSavedState) -> SyscallResult {
                     blit.size.0,
                     blit.size.1,
                 );
+            }
+            for rect in &damage {
+                // same offset from `dst` as from `src`
+                let dst = (
+                    blit.dst.0 + (rect.pos.0 - blit.src.0),
+                    blit.dst.1 + (rect.pos.1 - blit.src.1),
+                );
+                display.blit(
+                    buffer,
+                    &blit.src_framebuffer_info,
+                    rect.pos,
+                    dst,
+                    rect.size.0,
+                    rect.size.1,
+                );
+            }
+        }
+        GraphicsCommand::CaptureFrameBuffer => {
+            let capture = UserPtr::<CaptureCommand>::new(extra)
+                .map_err(|err| to_arg_err!(1, err))?
+                .read();
+
+            let controller =
+                graphics::vga::controller().ok_or(SyscallError::GraphicsNotAvailable)?;
+            let capture_size = controller.framebuffer_info().capture_size();
+            if capture.len < capture_size {
+                return Err(SyscallError::BufferTooSmall(capture_size));
+            }
+            let buffer = UserSlice::<u8>::new(capture.memory, capture.len)
+                .map_err(|_| SyscallError::InvalidGraphicsBuffer)?;
+
+            // the buffer is too big to copy, so it's written in place
+            let _user_access = user_access::allow_user_access();
+            // SAFETY: user access is allowed until the end of the capture
+            controller.capture(unsafe { buffer.as_mut_slice() });
+        }
+        GraphicsCommand::FillRect => {
+            let fill = UserPtr::<FillRectCommand>::new(extra)
+                .map_err(|err| to_arg_err!(1, err))?
+                .read();
+
+            let controller =
+                graphics::vga::controller().ok_or(SyscallError::GraphicsNotAvailable)?;
+            if !controller
+                .framebuffer_info()
+                .contains_rect(fill.dst, fill.size)
+            {
+                return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
+            }
+
+            let (r, g, b) = fill.color;
+            controller
+                .lock_process(pid)
+                .ok_or(SyscallError::GraphicsNotOwned)?
+                .clear_rect(
+                    fill.dst.0,
+                    fill.dst.1,
+                    fill.size.0,
+                    fill.size.1,
+                    graphics::Pixel { r, g, b },
+                );
+        }
+        GraphicsCommand::CopyRect => {
+            let copy = UserPtr::<CopyRectCommand>::new(extra)
+                .map_err(|err| to_arg_err!(1, err))?
+                .read();
+
+            let controller =
+                graphics::vga::controller().ok_or(SyscallError::GraphicsNotAvailable)?;
+            let info = controller.framebuffer_info();
+            if !info.contains_rect(copy.src, copy.size) || !info.contains_rect(copy.dst, copy.size)
+            {
+                return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
+            }
+
+            controller
+                .lock_process(pid)
+                .ok_or(SyscallError::GraphicsNotOwned)?
+                .copy_rect(copy.src, copy.dst, copy.size.0, copy.size.1);
         }
         c => panic!("invalid graphics command {c:?}"),
     }
@@ -759,7 +1964,7 @@ fn sys_seek(all_state: &mut InterruptAllSavedState) -> SyscallResult {
 
     let new_position = with_current_process(|process| {
         let file = process
-            .get_fs_node(file_index)
+            .get_handle(file_index)
             .ok_or(SyscallError::InvalidFileIndex)?;
         let file = file.as_file_mut()?;
 
@@ -817,26 +2022,281 @@ fn sys_priority(all_state: &mut InterruptAllSavedState) -> SyscallResult {
         )
     };
 
-    let current_priority = with_process(pid, |process| {
+    let current_priority = scheduler::try_with_process(pid, |process| {
         if let Some(priority_level) = priority_level {
             process.set_priority(priority_level);
         }
 
-        Ok::<_, SyscallError>(process.get_priority())
-    })?;
+        process.get_priority()
+    })
+    .ok_or(SyscallError::PidNotFound)?;
 
     SyscallResult::Ok(current_priority.to_u64())
 }
 
+/// Set the CPUs the process `pid` can run on if `affinity` is not `0`, returns the previous affinity.
+///
+/// The mask must include at least one online CPU, but it's not used by the scheduler until
+/// we run on multiple CPUs.
+fn sys_set_affinity(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (pid, affinity, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => u64),
+        sys_arg!(1, all_state.rest => u64),
+    };
+
+    // if its `None`, just get the value
+    let affinity = if affinity == 0 {
+        None
+    } else if affinity & cpu::tlb::online_cpus() == 0 {
+        return Err(to_arg_err!(1, SyscallArgError::GeneralInvalid));
+    } else {
+        Some(affinity)
+    };
+
+    let old_affinity = scheduler::try_with_process(pid, |process| {
+        let old_affinity = process.affinity();
+        if let Some(affinity) = affinity {
+            process.set_affinity(affinity);
+        }
+
+        old_affinity
+    })
+    .ok_or(SyscallError::PidNotFound)?;
+
+    SyscallResult::Ok(old_affinity)
+}
+
+/// Get the system memory information, and the memory usage of the process `pid`
+fn sys_meminfo(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (pid, info_ptr, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => u64),
+        sys_arg!(1, all_state.rest => *mut u8),
+    };
+    let info_ptr = UserPtr::<MemInfo>::new(info_ptr).map_err(|err| to_arg_err!(1, err))?;
+
+    let mut info = procfs::system_meminfo();
+    info.process = procfs::process_memory_stats(pid).ok_or(SyscallError::PidNotFound)?;
+
+    info_ptr.write(info);
+
+    SyscallResult::Ok(0)
+}
+
+/// Terminate the process `pid`, its exit code will be [`KILLED_EXIT_CODE`]
+fn sys_kill(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (pid, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => u64),
+    };
+
+    if pid == with_current_process(|process| process.id) {
+        exit_current_process(KILLED_EXIT_CODE, all_state);
+        return SyscallResult::Ok(0);
+    }
+
+    if !scheduler::kill_process(pid) {
+        return Err(SyscallError::PidNotFound);
+    }
+
+    SyscallResult::Ok(0)
+}
+
+/// Get a snapshot of the processes sorted by `pid`, starting from the `offset`th process,
+/// returns the number of entries written, less than `len` if there are no more processes
+fn sys_process_list(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (offset, buf, len, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => usize),
+        sys_arg!(1, all_state.rest => *mut u8),
+        sys_arg!(2, all_state.rest => usize),
+    };
+    let buf = UserSlice::<ProcessInfo>::new(buf, len).map_err(|err| to_arg_err!(1, err))?;
+
+    let list = scheduler::process_list();
+    let list = list.get(offset..).unwrap_or_default();
+    let count = list.len().min(len);
+    buf.write_at(0, &list[..count]);
+
+    SyscallResult::Ok(count as u64)
+}
+
+/// Get the executable path, current directory and arguments of the process `pid`, written as null terminated
+/// strings `path\0cwd\0argv[0]\0argv[1]\0...`, returns the number of bytes written
+fn sys_get_proc_info(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (pid, buf, len, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => u64),
+        sys_arg!(1, all_state.rest => *mut u8),
+        sys_arg!(2, all_state.rest => usize),
+    };
+    let buf = UserSlice::<u8>::new(buf, len).map_err(|err| to_arg_err!(1, err))?;
+
+    // build it first, so we don't write to user memory while holding the scheduler lock
+    let info = scheduler::try_with_process(pid, |process| {
+        let mut info = Vec::new();
+        let strings = [
+            process.file_path().as_str(),
+            process.get_current_dir().path().as_str(),
+        ]
+        .into_iter()
+        .chain(process.argv().iter().map(String::as_str));
+        for s in strings {
+            info.extend_from_slice(s.as_bytes());
+            info.push(0);
+        }
+        info
+    })
+    .ok_or(SyscallError::PidNotFound)?;
+
+    if info.len() > len {
+        return Err(SyscallError::BufferTooSmall(info.len()));
+    }
+    buf.write_at(0, &info);
+
+    SyscallResult::Ok(info.len() as u64)
+}
+
+/// Check that the current process can access the memory of `pid`, only the process itself, its
+/// parent, and `init` can
+fn check_proc_vm_permission(pid: u64) -> Result<(), SyscallError> {
+    let current_pid = cpu::cpu().process_id;
+    let parent_id = scheduler::try_with_process(pid, |process| process.parent_id())
+        .ok_or(SyscallError::PidNotFound)?;
+    if pid != current_pid && current_pid != 0 && parent_id != current_pid {
+        warn!("Process {current_pid} denied access to the memory of process {pid}");
+        return Err(SyscallError::PermissionDenied);
+    }
+    Ok(())
+}
+
+/// Copy between the memory of `pid` at `addr` and `local` (in the current process), one page at a
+/// time through a kernel buffer, as the memory of the current process can't be accessed while
+/// holding the scheduler lock (it may fault), returns the number of bytes copied.
+///
+/// If `write` is set, `local` is copied to `pid`, otherwise it's the other way around.
+fn copy_proc_vm(pid: u64, addr: usize, local: UserSlice<u8>, write: bool) -> SyscallResult {
+    let len = local.len();
+    let mut chunk_buf = vec![0; len.min(PAGE_4K)];
+
+    let mut copied = 0;
+    while copied < len {
+        let chunk = &mut chunk_buf[..(len - copied).min(PAGE_4K)];
+        if write {
+            local.read_into(copied, chunk);
+        }
+        let chunk_len = chunk.len();
+        let chunk_copied = scheduler::try_with_process(pid, |process| {
+            let access = if write {
+                UserMemoryAccess::Write(chunk)
+            } else {
+                UserMemoryAccess::Read(chunk)
+            };
+            process.copy_user_memory(addr + copied, access)
+        })
+        .ok_or(SyscallError::PidNotFound)?;
+        if !write {
+            local.write_at(copied, &chunk_buf[..chunk_copied]);
+        }
+        copied += chunk_copied;
+        if chunk_copied < chunk_len {
+            break;
+        }
+    }
+
+    // nothing at `addr` is accessible
+    if copied == 0 && len != 0 {
+        return Err(to_arg_err!(1, SyscallArgError::InvalidUserPointer));
+    }
+    SyscallResult::Ok(copied as u64)
+}
+
+/// Read `len` bytes from the memory of the process `pid` at `addr` into `buf`, returns the number of
+/// bytes read, less than `len` if it reached memory that is not mapped for the process
+fn sys_proc_vm_read(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (pid, addr, buf, len, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => u64),
+        sys_arg!(1, all_state.rest => usize),
+        sys_arg!(2, all_state.rest => *mut u8),
+        sys_arg!(3, all_state.rest => usize),
+    };
+    let buf = UserSlice::<u8>::new(buf, len).map_err(|err| to_arg_err!(2, err))?;
+
+    check_proc_vm_permission(pid)?;
+    debug!(
+        "Process {} reads {len} bytes from process {pid} at {addr:#x}",
+        cpu::cpu().process_id
+    );
+
+    copy_proc_vm(pid, addr, buf, false)
+}
+
+/// Write `len` bytes from `buf` to the memory of the process `pid` at `addr`, returns the number of
+/// bytes written, less than `len` if it reached memory that is not mapped as writable for the
+/// process (copy-on-write pages are copied first)
+fn sys_proc_vm_write(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (pid, addr, buf, len, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => u64),
+        sys_arg!(1, all_state.rest => usize),
+        sys_arg!(2, all_state.rest => *const u8),
+        sys_arg!(3, all_state.rest => usize),
+    };
+    let buf = UserSlice::<u8>::new(buf, len).map_err(|err| to_arg_err!(2, err))?;
+
+    check_proc_vm_permission(pid)?;
+    // changing the memory of another process is worth keeping a trace of
+    info!(
+        "Process {} writes {len} bytes to process {pid} at {addr:#x}",
+        cpu::cpu().process_id
+    );
+
+    copy_proc_vm(pid, addr, buf, true)
+}
+
 pub fn handle_syscall(all_state: &mut InterruptAllSavedState) {
     let syscall_number = all_state.rest.rax;
 
-    // `syscall_handler_wrapper` will check the syscall number and return error if it exceed the
-    // number of syscalls (NUM_SYSCALLS)
-    all_state.rest.rax = syscall_handler_wrapper(syscall_number, || {
-        let syscall_func = SYSCALLS[syscall_number as usize];
-        syscall_func(all_state)
-    });
+    loop {
+        // `syscall_handler_wrapper` will check the syscall number and return error if it exceed the
+        // number of syscalls (NUM_SYSCALLS)
+        let (result, blocked) = adaptive_mutex::run_blockable(|| {
+            syscall_handler_wrapper(syscall_number, || {
+                let syscall_func = SYSCALLS[syscall_number as usize];
+                syscall_func(all_state)
+            })
+        });
+        let Some((queue, generation)) = blocked else {
+            all_state.rest.rax = result;
+            break;
+        };
+        // blocked on a lock, the result is discarded, and the syscall is run again when woken
+        if scheduler::wait_on_queue(&queue, generation, all_state) {
+            return;
+        }
+        // unlocked since, try again
+    }
 
     crate::scheduler::yield_current_if_any(all_state);
 }
+
+/// Get the system time zone into `old`, and replace it with `new` if it's not null
+fn sys_timezone(all_state: &mut InterruptAllSavedState) -> SyscallResult {
+    let (new_ptr, old_ptr, ..) = verify_args! {
+        sys_arg!(0, all_state.rest => *const u8),
+        sys_arg!(1, all_state.rest => *mut u8),
+    };
+    let old_ptr = UserPtr::<TimeZone>::new(old_ptr).map_err(|err| to_arg_err!(1, err))?;
+    // if its null, just get the value
+    let new = if new_ptr.is_null() {
+        None
+    } else {
+        let new_ptr = UserPtr::<TimeZone>::new(new_ptr).map_err(|err| to_arg_err!(0, err))?;
+        let new = clock::TimeZone::try_from(new_ptr.read()).map_err(|err| to_arg_err!(0, err))?;
+        Some(new)
+    };
+
+    let clocks = clock::clocks();
+    let old = clocks.timezone();
+    if let Some(new) = new {
+        clocks.set_timezone(new);
+    }
+    old_ptr.write(old.into());
+
+    SyscallResult::Ok(0)
+}
@@ -0,0 +1,228 @@
+//! Kernel cryptographically secure random number generator
+//!
+//! A ChaCha20 based generator with fast key erasure, after each request the key is replaced
+//! by fresh output of the generator, so a compromised state can't be used to recover
+//! previous outputs.
+//!
+//! The initial key is derived (SHA-256) from `rdrand` (if available), the `TSC`
+//! and the current time.
+
+use emerald_crypto::{
+    chacha20::{self, ChaCha20},
+    sha256::Sha256,
+};
+use tracing::{info, warn};
+
+use crate::{
+    cpu,
+    devices::{clock, Device},
+    fs::FileSystemError,
+    init_stage::{self, InitStage},
+    sync::spin::mutex::Mutex,
+    testing,
+};
+
+static RNG: Mutex<Option<Csprng>> = Mutex::new(None);
+
+struct Csprng {
+    key: [u8; chacha20::KEY_SIZE],
+    /// used as the nonce, so that we never reuse the same (key, nonce) pair
+    counter: u64,
+}
+
+impl Csprng {
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        let mut nonce = [0; chacha20::NONCE_SIZE];
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter = self.counter.wrapping_add(1);
+
+        let mut stream = ChaCha20::new(&self.key, &nonce, 0);
+        let mut new_key = [0; chacha20::KEY_SIZE];
+        stream.apply_keystream(&mut new_key);
+        buf.fill(0);
+        stream.apply_keystream(buf);
+
+        self.key = new_key;
+    }
+
+    fn add_entropy(&mut self, data: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update(data);
+        self.key = hasher.finalize();
+    }
+}
+
+fn rdrand() -> Option<u64> {
+    let value: u64;
+    let success: u8;
+    // SAFETY: only called when `cpuid` reports support for `rdrand`
+    unsafe {
+        core::arch::asm!(
+            "rdrand {0}",
+            "setc {1}",
+            out(reg) value,
+            out(reg_byte) success,
+            options(nomem, nostack)
+        );
+    }
+    (success != 0).then_some(value)
+}
+
+fn has_rdrand() -> bool {
+    let cpuid = unsafe { cpu::cpuid::cpuid!(cpu::cpuid::FN_FEAT) };
+    cpuid.ecx & cpu::cpuid::FEAT_ECX_RDRAND != 0
+}
+
+/// Must be called after the clocks are initialized
+pub fn init() {
+    // require the clocks for seeding
+    init_stage::debug_assert_reached(InitStage::Clocks);
+    let mut hasher = Sha256::new();
+
+    if has_rdrand() {
+        // retry a couple of times as recommended by Intel, as it may fail spuriously
+        let mut collected = 0;
+        for _ in 0..32 {
+            if let Some(value) = rdrand() {
+                hasher.update(&value.to_le_bytes());
+                collected += 1;
+                if collected == 4 {
+                    break;
+                }
+            }
+        }
+        info!("CSPRNG: seeded with {} `rdrand` values", collected);
+    } else {
+        warn!("CSPRNG: `rdrand` is not available, seeding from time sources only");
+    }
+
+    hasher.update(&unsafe { cpu::read_tsc() }.to_le_bytes());
+    hasher.update(
+        &clock::clocks()
+            .time_since_unix_epoch()
+            .as_nanos()
+            .to_le_bytes(),
+    );
+    hasher.update(
+        &clock::clocks()
+            .time_since_startup()
+            .as_nanos()
+            .to_le_bytes(),
+    );
+
+    let mut rng = RNG.lock();
+    assert!(rng.is_none(), "CSPRNG already initialized");
+    *rng = Some(Csprng {
+        key: hasher.finalize(),
+        counter: 0,
+    });
+}
+
+/// Fill `buf` with random bytes
+///
+/// # Panics
+/// If the generator is not initialized yet
+pub fn fill_bytes(buf: &mut [u8]) {
+    RNG.lock()
+        .as_mut()
+        .expect("CSPRNG not initialized")
+        .fill_bytes(buf);
+}
+
+/// Mix `data` into the generator state, the timestamp of the call is mixed as well
+pub fn add_entropy(data: &[u8]) {
+    if let Some(rng) = RNG.lock().as_mut() {
+        rng.add_entropy(&unsafe { cpu::read_tsc() }.to_le_bytes());
+        rng.add_entropy(data);
+    }
+}
+
+/// Random device
+///
+/// Reading from it will provide bytes from the kernel CSPRNG, can be used by userspace
+/// with `cat /devices/random | xxd` for example.
+/// Writing to it will mix the written data into the generator state.
+#[derive(Debug)]
+pub struct RandomDevice;
+
+impl Device for RandomDevice {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<u64, FileSystemError> {
+        fill_bytes(buf);
+        Ok(buf.len() as u64)
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<u64, FileSystemError> {
+        add_entropy(buf);
+        Ok(buf.len() as u64)
+    }
+
+    // allow `echo data > /devices/random`
+    fn set_size(&self, _size: u64) -> Result<(), FileSystemError> {
+        Ok(())
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_sha256_hmac_vectors() {
+    use emerald_crypto::hmac::HmacSha256;
+
+    assert_eq!(
+        Sha256::digest(b"abc"),
+        [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad
+        ]
+    );
+    // RFC 4231, test case 2
+    assert_eq!(
+        HmacSha256::mac(b"Jefe", b"what do ya want for nothing?"),
+        [
+            0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+            0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+            0x64, 0xec, 0x38, 0x43
+        ]
+    );
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_chacha20_poly1305_roundtrip() {
+    use emerald_crypto::chacha20poly1305::{AeadError, ChaCha20Poly1305};
+
+    let aead = ChaCha20Poly1305::new(&[0x42; 32]);
+    let nonce = [7; 12];
+    let message = *b"the quick brown fox jumps over the lazy dog";
+    let mut buf = message;
+
+    let tag = aead.encrypt_in_place_detached(&nonce, b"header", &mut buf);
+    assert_ne!(buf, message);
+    aead.decrypt_in_place_detached(&nonce, b"header", &mut buf, &tag)
+        .unwrap();
+    assert_eq!(buf, message);
+
+    let tag = aead.encrypt_in_place_detached(&nonce, b"header", &mut buf);
+    buf[0] ^= 1;
+    assert_eq!(
+        aead.decrypt_in_place_detached(&nonce, b"header", &mut buf, &tag),
+        Err(AeadError::AuthenticationFailed)
+    );
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_x25519_shared_secret() {
+    use emerald_crypto::x25519;
+
+    let a = [0x11; 32];
+    let b = [0x77; 32];
+    let a_public = x25519::public_key(&a);
+    let b_public = x25519::public_key(&b);
+    assert_eq!(
+        x25519::shared_secret(&a, &b_public),
+        x25519::shared_secret(&b, &a_public)
+    );
+}
@@ -0,0 +1,206 @@
+//! A mutex that spins for a short time, then blocks the current process
+//!
+//! Used for locks that can be held for a long time, i.e. the filesystems, which are held while
+//! reading the disk. A process can only be parked between syscalls, as the syscall is run again
+//! when it's woken (see [`wait_on_queue`]), so blocking is done in two steps:
+//! - [`AdaptiveMutex::lock_or_block`] spins for [`SPIN_LIMIT`] tries, and if the mutex is still
+//!   locked, it records the queue of the mutex and returns [`WouldBlock`], which the caller
+//!   returns as an error.
+//! - The syscall handler runs the syscall inside [`run_blockable`], which returns the recorded queue,
+//!   and the process is parked on it, instead of returning the error.
+//!
+//! Since the syscall is run again from the start, it can only block when taking the first
+//! `AdaptiveMutex`, after that, it may have changed something that can't be done twice, so the
+//! later ones spin like a normal [`Mutex`]. This is also the case outside [`run_blockable`] (i.e. in
+//! the scheduler or interrupts), as there is no process to block.
+//!
+//! The holder always runs until it unlocks, as syscalls run with interrupts disabled, so a low
+//! priority holder can't delay a high priority waiter by being preempted, and there is no need for
+//! priority inheritance.
+//!
+//! [`wait_on_queue`]: crate::process::scheduler::wait_on_queue
+
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use alloc::sync::Arc;
+
+use crate::{
+    cpu::{self, MAX_CPUS},
+    process::scheduler::WaitQueue,
+    testing,
+};
+
+use super::spin::mutex::{Mutex, MutexGuard};
+
+/// The number of tries before blocking, the locks are mostly held for a short time
+const SPIN_LIMIT: usize = 1000;
+
+/// The mutex is locked, and the current process will block on it, see the module docs
+#[derive(Debug)]
+pub struct WouldBlock;
+
+struct CpuState {
+    /// Inside [`run_blockable`], and no `AdaptiveMutex` was taken yet
+    can_block: bool,
+    /// The queue to wait on, and its generation before the last try
+    blocked: Option<(Arc<WaitQueue>, u64)>,
+}
+
+// only used to initialize `CPU_STATES`
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_NOT_BLOCKABLE: CpuState = CpuState {
+    can_block: false,
+    blocked: None,
+};
+
+/// Accessed only by the owner CPU, with interrupts disabled
+static mut CPU_STATES: [CpuState; MAX_CPUS] = [CPU_NOT_BLOCKABLE; MAX_CPUS];
+
+fn with_cpu_state<U>(f: impl FnOnce(&mut CpuState) -> U) -> U {
+    let cpu = cpu::cpu();
+    cpu.push_cli();
+    // SAFETY: only this CPU accesses its state, and interrupts are disabled
+    let result = f(unsafe { &mut CPU_STATES[cpu.id] });
+    cpu.pop_cli();
+    result
+}
+
+/// Run `f` (a syscall), allowing it to block on the first `AdaptiveMutex` it takes.
+/// Returns the queue and generation to wait on if it did, in that case, the result of `f` should
+/// be discarded, and the syscall run again when the queue is woken.
+pub fn run_blockable<U>(f: impl FnOnce() -> U) -> (U, Option<(Arc<WaitQueue>, u64)>) {
+    with_cpu_state(|state| state.can_block = true);
+    let result = f();
+    let blocked = with_cpu_state(|state| {
+        state.can_block = false;
+        state.blocked.take()
+    });
+    (result, blocked)
+}
+
+pub struct AdaptiveMutex<T> {
+    inner: Mutex<T>,
+    queue: Arc<WaitQueue>,
+    /// Someone blocked on the mutex, so it must be woken on unlock
+    waiting: AtomicBool,
+}
+
+impl<T> AdaptiveMutex<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            inner: Mutex::new(data),
+            queue: Arc::new(WaitQueue::new()),
+            waiting: AtomicBool::new(false),
+        }
+    }
+
+    fn guard<'a>(&'a self, inner: MutexGuard<'a, T>) -> AdaptiveMutexGuard<'a, T> {
+        with_cpu_state(|state| state.can_block = false);
+        AdaptiveMutexGuard {
+            lock: self,
+            inner: ManuallyDrop::new(inner),
+        }
+    }
+
+    /// Lock the mutex, spinning until it's available
+    pub fn lock(&self) -> AdaptiveMutexGuard<T> {
+        self.guard(self.inner.lock())
+    }
+
+    /// Lock the mutex, or return [`WouldBlock`] if the current process should block on it,
+    /// see the module docs
+    pub fn lock_or_block(&self) -> Result<AdaptiveMutexGuard<T>, WouldBlock> {
+        for _ in 0..SPIN_LIMIT {
+            if let Some(inner) = self.inner.try_lock() {
+                return Ok(self.guard(inner));
+            }
+            core::hint::spin_loop();
+        }
+        if !with_cpu_state(|state| state.can_block) {
+            return Ok(self.lock());
+        }
+
+        // set before the last try, so the unlock after it wakes us
+        self.waiting.store(true, Ordering::SeqCst);
+        let generation = self.queue.generation();
+        if let Some(inner) = self.inner.try_lock() {
+            return Ok(self.guard(inner));
+        }
+        with_cpu_state(|state| {
+            state.can_block = false;
+            state.blocked = Some((self.queue.clone(), generation));
+        });
+        Err(WouldBlock)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for AdaptiveMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdaptiveMutex")
+            .field("inner", &self.inner)
+            .field("waiting", &self.waiting)
+            .finish()
+    }
+}
+
+#[must_use]
+pub struct AdaptiveMutexGuard<'a, T> {
+    lock: &'a AdaptiveMutex<T>,
+    inner: ManuallyDrop<MutexGuard<'a, T>>,
+}
+
+impl<T> Deref for AdaptiveMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for AdaptiveMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for AdaptiveMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: not used after this
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        if self.lock.waiting.swap(false, Ordering::SeqCst) {
+            self.lock.queue.wake_all();
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_adaptive_mutex_block() {
+    let mutex = AdaptiveMutex::new(0);
+
+    // not blockable, so it's just a normal lock
+    *mutex.lock_or_block().unwrap() += 1;
+
+    let (_, blocked) = run_blockable(|| {
+        *mutex.lock_or_block().unwrap() += 1;
+        // only the first one can block
+        assert!(!with_cpu_state(|state| state.can_block));
+    });
+    assert!(blocked.is_none());
+
+    // keep it locked, so the one inside `run_blockable` blocks
+    let guard = mutex.lock();
+    let (result, blocked) = run_blockable(|| mutex.lock_or_block().map(|_| ()));
+    assert!(result.is_err());
+    let (queue, generation) = blocked.unwrap();
+    assert_eq!(queue.generation(), generation);
+
+    // unlocking wakes the blocked one
+    drop(guard);
+    assert_ne!(queue.generation(), generation);
+    assert_eq!(*mutex.lock(), 2);
+}
@@ -0,0 +1,223 @@
+//! Epoch-based reclamation, for read-mostly tables that are looked up without locking
+//!
+//! Readers [`pin`] the current CPU, and can then read an [`EpochCell`] until the [`Guard`] is
+//! dropped. Writers don't modify the value in place, they replace it with an updated copy, and
+//! [`retire`] the old one, which is freed later when no CPU can be reading it anymore.
+//!
+//! Each pinned CPU records the global epoch it saw when it was pinned. The global epoch is advanced
+//! only when all the pinned CPUs have seen the current one, so a value retired in epoch `e` is not
+//! reachable by any CPU once the global epoch is `e + 2`.
+//!
+//! Pinning disables interrupts, so the CPU doesn't switch to another process while pinned, and
+//! reading is done on the same CPU that pinned. This is also why pinned sections must be short,
+//! and must not wait on anything.
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    mem,
+    sync::atomic::{self, AtomicPtr, AtomicU64, Ordering},
+};
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+    cpu::{self, MAX_CPUS},
+    sync::{cache_padded::CachePadded, spin::mutex::Mutex},
+    testing,
+};
+
+/// The value of a CPU's epoch when it's not pinned, the global epoch starts after it
+const NOT_PINNED: u64 = 0;
+
+static GLOBAL_EPOCH: AtomicU64 = AtomicU64::new(NOT_PINNED + 1);
+
+// only used to initialize `CPU_EPOCHS`
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_NOT_PINNED: CachePadded<AtomicU64> = CachePadded::new(AtomicU64::new(NOT_PINNED));
+
+/// The global epoch each CPU saw when it was pinned, written only by the owner CPU
+static CPU_EPOCHS: [CachePadded<AtomicU64>; MAX_CPUS] = [CPU_NOT_PINNED; MAX_CPUS];
+
+static GARBAGE: Mutex<Vec<Retired>> = Mutex::new(Vec::new());
+
+/// A value waiting to be freed
+struct Retired {
+    epoch: u64,
+    _value: Box<dyn Send>,
+}
+
+/// Keeps the current CPU pinned, see [`pin`]
+pub struct Guard {
+    /// Only the outermost guard unpins the CPU
+    outer: bool,
+    /// Must be dropped on the CPU that pinned
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let cpu = cpu::cpu();
+        if self.outer {
+            CPU_EPOCHS[cpu.id].store(NOT_PINNED, Ordering::Release);
+        }
+        cpu.pop_cli();
+    }
+}
+
+/// Pin the current CPU, values read from [`EpochCell`]s are not freed until the guard is dropped.
+/// Can be nested.
+pub fn pin() -> Guard {
+    let cpu = cpu::cpu();
+    cpu.push_cli();
+    let cpu_epoch = &CPU_EPOCHS[cpu.id];
+    let outer = cpu_epoch.load(Ordering::Relaxed) == NOT_PINNED;
+    if outer {
+        cpu_epoch.store(GLOBAL_EPOCH.load(Ordering::SeqCst), Ordering::SeqCst);
+        // the epoch must be visible to writers before we read anything
+        atomic::fence(Ordering::SeqCst);
+    }
+    Guard {
+        outer,
+        _not_send: PhantomData,
+    }
+}
+
+/// Free `value` when no CPU can be reading it anymore, it must not be reachable by new readers
+pub fn retire<T: Send + 'static>(value: T) {
+    let epoch = GLOBAL_EPOCH.load(Ordering::SeqCst);
+    GARBAGE.lock().push(Retired {
+        epoch,
+        _value: Box::new(value),
+    });
+    collect();
+}
+
+/// Advance the global epoch if all the pinned CPUs have seen it, returns the current one
+fn try_advance() -> u64 {
+    let epoch = GLOBAL_EPOCH.load(Ordering::SeqCst);
+    let all_seen = CPU_EPOCHS.iter().all(|cpu_epoch| {
+        let cpu_epoch = cpu_epoch.load(Ordering::SeqCst);
+        cpu_epoch == NOT_PINNED || cpu_epoch == epoch
+    });
+    if !all_seen {
+        return epoch;
+    }
+    match GLOBAL_EPOCH.compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => epoch + 1,
+        // someone else advanced it
+        Err(current) => current,
+    }
+}
+
+/// Free the retired values that no CPU can be reading anymore. Called on each [`retire`], and
+/// periodically by the [`housekeeping`](crate::housekeeping), so the values are freed even if
+/// there are no more writes.
+pub fn collect() {
+    let epoch = try_advance();
+    let freed: Vec<_> = {
+        let mut garbage = GARBAGE.lock();
+        let (freed, kept) = mem::take(&mut *garbage)
+            .into_iter()
+            .partition(|retired| retired.epoch + 2 <= epoch);
+        *garbage = kept;
+        freed
+    };
+    // dropped outside the lock, as dropping may retire more values
+    drop(freed);
+}
+
+/// A value that is read without locking while [`pin`]ned, and replaced as a whole by writers
+pub struct EpochCell<T> {
+    value: AtomicPtr<T>,
+    /// Only one writer at a time, so no update is lost
+    writer: Mutex<()>,
+}
+
+unsafe impl<T: Send> Send for EpochCell<T> {}
+unsafe impl<T: Send + Sync> Sync for EpochCell<T> {}
+
+impl<T: Send + Sync + 'static> EpochCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: Mutex::new(()),
+        }
+    }
+
+    pub fn read<'a>(&'a self, _guard: &'a Guard) -> &'a T {
+        // SAFETY: the value is not freed while we are pinned, and we are holding `&self`
+        unsafe { &*self.value.load(Ordering::Acquire) }
+    }
+
+    /// Replace the value with a copy modified by `f`, the old one is retired. Readers that are
+    /// pinned keep seeing the old value.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Clone,
+    {
+        let writer = self.writer.lock();
+        // SAFETY: the value is only replaced and retired while holding `writer`
+        let mut new = Box::new(unsafe { &*self.value.load(Ordering::Acquire) }.clone());
+        let result = f(&mut new);
+        let old = self.value.swap(Box::into_raw(new), Ordering::AcqRel);
+        drop(writer);
+
+        // SAFETY: `old` came from `Box::into_raw`, and it can't be reached by new readers
+        retire(unsafe { Box::from_raw(old) });
+        result
+    }
+}
+
+impl<T> Drop for EpochCell<T> {
+    fn drop(&mut self) {
+        // SAFETY: no one can be reading it, since we have `&mut self`
+        drop(unsafe { Box::from_raw(*self.value.get_mut()) });
+    }
+}
+
+impl<T: fmt::Debug + Send + Sync + 'static> fmt::Debug for EpochCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EpochCell").field(self.read(&pin())).finish()
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_epoch_cell_reclaim() {
+    use core::sync::atomic::AtomicUsize;
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
+    struct Tracked(u32);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let cell = EpochCell::new(Tracked(1));
+
+    let guard = pin();
+    let old = cell.read(&guard);
+    // the copy made by `update` is overwritten
+    cell.update(|value| *value = Tracked(2));
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+    assert_eq!(old.0, 1);
+    assert_eq!(cell.read(&guard).0, 2);
+
+    // can't advance twice while we are pinned
+    collect();
+    collect();
+    assert_eq!(old.0, 1);
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+    drop(guard);
+
+    collect();
+    collect();
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 2);
+
+    drop(cell);
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 3);
+}
@@ -1,3 +1,5 @@
+pub mod adaptive_mutex;
 mod cache_padded;
+pub mod epoch;
 pub mod once;
 pub mod spin;
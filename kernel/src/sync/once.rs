@@ -1,11 +1,14 @@
 use core::{
-    cell::UnsafeCell,
+    cell::{Cell, UnsafeCell},
     fmt,
     marker::PhantomData,
     mem::MaybeUninit,
+    ops::Deref,
     sync::atomic::{AtomicUsize, Ordering},
 };
 
+use crate::cpu;
+
 const ONCE_STATE_INIT: usize = 0;
 const ONCE_STATE_RUNNING: usize = 1;
 const ONCE_STATE_DONE: usize = 2;
@@ -139,11 +142,18 @@ impl<T> OnceLock<T> {
         self.init(|| Ok(value))
     }
 
+    /// Get the value, panics if its not initialized yet.
+    ///
+    /// Code that may run before initialization (e.g. interrupt handlers) should use [`OnceLock::try_get`] instead.
+    #[track_caller]
     pub fn get(&self) -> &T {
         if self.once.is_completed() {
             unsafe { self.get_unchecked() }
         } else {
-            panic!("OnceLock::get called before OnceLock::set");
+            panic!(
+                "OnceLock<{}>::get called before OnceLock::set",
+                core::any::type_name::<T>()
+            );
         }
     }
 
@@ -225,3 +235,74 @@ impl<T> Drop for OnceLock<T> {
         }
     }
 }
+
+/// A value that is initialized on first access with `F`.
+///
+/// Unlike [`OnceLock::get_or_init`], initialization is done with interrupts disabled, so an interrupt
+/// handler on the same CPU can never observe the value while its being initialized
+/// (which would panic in `Once::call`).
+/// Interrupt handlers should still prefer [`Lazy::get`], which never initializes and returns `None`
+/// if the value is not ready.
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceLock<T>,
+    init: Cell<Option<F>>,
+}
+
+// SAFETY: `init` is only accessed inside `Once::call`, which is exclusive
+unsafe impl<T: Sync + Send, F: Send> Sync for Lazy<T, F> {}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_tuple("Lazy");
+        match self.cell.try_get() {
+            Some(v) => d.field(v),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+#[allow(dead_code)]
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceLock::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+
+    /// Get the value, initializing it if needed.
+    ///
+    /// This is an associated function, so that it doesn't conflict with methods of `T`.
+    pub fn force(this: &Self) -> &T {
+        if let Some(value) = this.cell.try_get() {
+            return value;
+        }
+
+        let cpu = cpu::cpu();
+        cpu.push_cli();
+        let value = this.cell.get_or_init(|| match this.init.take() {
+            Some(f) => f(),
+            None => panic!("Lazy instance has previously been poisoned"),
+        });
+        cpu.pop_cli();
+        value
+    }
+
+    /// Get the value if its initialized, never initializes it.
+    pub fn get(this: &Self) -> Option<&T> {
+        this.cell.try_get()
+    }
+
+    pub fn is_initialized(this: &Self) -> bool {
+        this.cell.is_completed()
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
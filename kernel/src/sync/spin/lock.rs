@@ -9,11 +9,13 @@ use crate::sync::cache_padded::CachePadded;
 //   0: Unlocked
 //   1..=0x3FFF_FFFE: Locked by N readers
 //   0x3FFF_FFFF: Write locked
+// Bit 30: Writers waiting, only set by `write_lock_prefer_writers`
 const UNLOCKED: u64 = 0;
 const READ_LOCKED: u64 = 1;
 const MASK: u64 = (1 << 30) - 1;
 const WRITE_LOCKED: u64 = MASK;
 const MAX_READERS: u64 = MASK - 1;
+const WRITERS_WAITING: u64 = 1 << 30;
 
 #[inline]
 fn is_read_lockable(state: u64) -> bool {
@@ -23,7 +25,7 @@ fn is_read_lockable(state: u64) -> bool {
     // and there's no writers waiting. The only situation when this happens is after unlocking,
     // at which point the unlocking thread might be waking up writers, which have priority over readers.
     // The unlocking thread will clear the readers waiting bit and wake up readers, if necessary.
-    state & MASK < MAX_READERS
+    state & MASK < MAX_READERS && state & WRITERS_WAITING == 0
 }
 
 /// A raw spin lock, provides `read_lock`, `read_unlock`, `write_lock`, and `write_unlock`
@@ -75,7 +77,38 @@ impl Lock {
         // only try to lock once, then loop until we can, then try again
         // this reduces `cache exclusion` and improve performance
         while !self.try_write_lock() {
-            while self.state.load(Ordering::Relaxed) != UNLOCKED {
+            while self.state.load(Ordering::Relaxed) & MASK != UNLOCKED {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Same as `write_lock`, but calls `on_timeout` if we couldn't get the lock after `max_spins` iterations,
+    /// `on_timeout` must not return (i.e. it panics)
+    ///
+    /// The timeout is only checked in debug builds, in release this is the same as `write_lock`
+    pub fn write_lock_timeout(&self, max_spins: u64, on_timeout: impl FnOnce()) {
+        let mut spins = 0u64;
+        while !self.try_write_lock() {
+            while self.state.load(Ordering::Relaxed) & MASK != UNLOCKED {
+                if cfg!(debug_assertions) {
+                    spins += 1;
+                    if spins > max_spins {
+                        on_timeout();
+                        unreachable!("`on_timeout` returned");
+                    }
+                }
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Same as `write_lock`, but while we are waiting, new readers can't acquire the lock,
+    /// so that writers don't starve when there are a lot of readers
+    pub fn write_lock_prefer_writers(&self) {
+        while !self.try_write_lock() {
+            self.state.fetch_or(WRITERS_WAITING, Ordering::Relaxed);
+            while self.state.load(Ordering::Relaxed) & MASK != UNLOCKED {
                 core::hint::spin_loop();
             }
         }
@@ -85,15 +118,39 @@ impl Lock {
     #[inline(always)]
     /// Try to lock the lock, returns true if successful
     pub fn try_write_lock(&self) -> bool {
+        // this clears `WRITERS_WAITING`, other waiting writers will set it again
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |s| {
+                (s & MASK == UNLOCKED).then_some(WRITE_LOCKED)
+            })
+            .is_ok()
+    }
+
+    #[must_use]
+    /// Upgrade a read lock to a write lock, only succeeds if we are the only reader
+    pub fn try_upgrade(&self) -> bool {
         self.state
-            .compare_exchange_weak(UNLOCKED, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |s| {
+                (s & MASK == READ_LOCKED).then_some(WRITE_LOCKED)
+            })
             .is_ok()
     }
 
+    /// Convert a write lock into a read lock, without allowing other writers in between
+    ///
+    /// SAFETY: the caller must hold the write lock
+    pub unsafe fn downgrade(&self) {
+        let state = self
+            .state
+            .fetch_sub(WRITE_LOCKED - READ_LOCKED, Ordering::Release)
+            - (WRITE_LOCKED - READ_LOCKED);
+        assert_eq!(state & MASK, READ_LOCKED);
+    }
+
     /// SAFETY: the caller must assure that there is only one accessor for this lock
     ///         we don't want multiple unlocks, it doesn't make sense for this Lock (check `super::remutex::ReMutex`)
     pub unsafe fn write_unlock(&self) {
         let state = self.state.fetch_sub(WRITE_LOCKED, Ordering::Release) - WRITE_LOCKED;
-        assert_eq!(state, UNLOCKED);
+        assert_eq!(state & MASK, UNLOCKED);
     }
 }
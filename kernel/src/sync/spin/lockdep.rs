@@ -0,0 +1,260 @@
+//! Lock dependency tracking for spin locks, only enabled in debug builds with the `spin_lock_debug` feature
+//!
+//! Each CPU keeps a small stack of the locks it holds, along with where they were acquired.
+//! When acquiring a lock we check for:
+//! - Recursive acquisition of a (non-reentrant) lock this CPU already holds, which would spin forever.
+//! - Ordering inversions, i.e. acquiring `B` while holding `A`, where previously `A` was acquired while
+//!   holding `B`, two CPUs doing this at the same time will deadlock.
+//!
+//! In both cases, we panic with the sites of both acquisitions.
+//!
+//! Locks are identified by their address, and ordering is only recorded for locks inside the kernel image
+//! (i.e. `static`s), since locks on the heap can be moved or freed, and their address reused by another lock.
+//! Only locks that disable interrupts are tracked (i.e. not `RwLock::read`), since the others can be held
+//! across context switches.
+
+use core::panic::Location;
+
+#[cfg(all(debug_assertions, feature = "spin_lock_debug"))]
+pub(super) use enabled::{acquired, before_acquire, released};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum LockKind {
+    Exclusive,
+    Reentrant,
+}
+
+#[cfg(not(all(debug_assertions, feature = "spin_lock_debug")))]
+mod disabled {
+    use super::{Location, LockKind};
+
+    #[inline(always)]
+    pub fn before_acquire(_lock: usize, _kind: LockKind) {}
+    #[inline(always)]
+    pub fn acquired(_lock: usize, _kind: LockKind) {}
+    #[inline(always)]
+    pub fn released(_lock: usize) {}
+    pub fn holder_site(_lock: usize, _cpu: usize) -> Option<&'static Location<'static>> {
+        None
+    }
+}
+#[cfg(not(all(debug_assertions, feature = "spin_lock_debug")))]
+pub(super) use disabled::{acquired, before_acquire, released};
+
+/// Panic with diagnostics about a lock that was held for too long (used by `lock_timeout`),
+/// `site` is where we tried to acquire the lock
+#[cold]
+pub(super) fn report_timeout(lock: usize, owner_cpu: i64, site: &'static Location<'static>) -> ! {
+    #[cfg(not(all(debug_assertions, feature = "spin_lock_debug")))]
+    use disabled::holder_site;
+    #[cfg(all(debug_assertions, feature = "spin_lock_debug"))]
+    use enabled::holder_site;
+
+    let holder = usize::try_from(owner_cpu)
+        .ok()
+        .and_then(|cpu| holder_site(lock, cpu));
+
+    match holder {
+        Some(holder) => panic!(
+            "Timeout acquiring lock {lock:#x} at {site}, held by CPU {owner_cpu} (acquired at {holder})"
+        ),
+        None => panic!("Timeout acquiring lock {lock:#x} at {site}, held by CPU {owner_cpu}"),
+    }
+}
+
+#[cfg(all(debug_assertions, feature = "spin_lock_debug"))]
+mod enabled {
+    use core::{cell::UnsafeCell, panic::Location};
+
+    use crate::{
+        cpu::{self, MAX_CPUS},
+        memory_management::memory_layout::{kernel_elf_end, KERNEL_LINK},
+        sync::spin::lock::Lock,
+    };
+
+    use super::LockKind;
+
+    const MAX_HELD_LOCKS: usize = 16;
+    const MAX_ORDER_EDGES: usize = 512;
+
+    #[derive(Clone, Copy)]
+    struct HeldLock {
+        lock: usize,
+        kind: LockKind,
+        site: &'static Location<'static>,
+    }
+
+    #[derive(Clone, Copy)]
+    struct HeldLocks {
+        locks: [Option<HeldLock>; MAX_HELD_LOCKS],
+        len: usize,
+        /// locks that didn't fit in `locks`, we still need to count them to know when we are back
+        overflow: usize,
+    }
+
+    impl HeldLocks {
+        const fn new() -> Self {
+            Self {
+                locks: [None; MAX_HELD_LOCKS],
+                len: 0,
+                overflow: 0,
+            }
+        }
+
+        fn iter(&self) -> impl Iterator<Item = &HeldLock> {
+            self.locks[..self.len].iter().flatten()
+        }
+
+        fn find(&self, lock: usize) -> Option<&HeldLock> {
+            self.iter().find(|held| held.lock == lock)
+        }
+
+        fn push(&mut self, held: HeldLock) {
+            if self.len == MAX_HELD_LOCKS {
+                self.overflow += 1;
+                return;
+            }
+            self.locks[self.len] = Some(held);
+            self.len += 1;
+        }
+
+        fn remove(&mut self, lock: usize) {
+            // locks are not always released in reverse order
+            if let Some(i) = self.locks[..self.len]
+                .iter()
+                .rposition(|held| held.map(|h| h.lock) == Some(lock))
+            {
+                self.locks.copy_within(i + 1..self.len, i);
+                self.len -= 1;
+                self.locks[self.len] = None;
+            } else {
+                self.overflow = self.overflow.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Accessed only by the owner CPU, with interrupts disabled
+    static mut HELD_LOCKS: [HeldLocks; MAX_CPUS] = [HeldLocks::new(); MAX_CPUS];
+
+    #[derive(Clone, Copy)]
+    struct OrderEdge {
+        before: usize,
+        after: usize,
+        before_site: &'static Location<'static>,
+        after_site: &'static Location<'static>,
+    }
+
+    struct OrderGraph {
+        lock: Lock,
+        edges: UnsafeCell<([Option<OrderEdge>; MAX_ORDER_EDGES], usize)>,
+    }
+
+    // SAFETY: `edges` is only accessed while holding `lock`
+    unsafe impl Sync for OrderGraph {}
+
+    static ORDER_GRAPH: OrderGraph = OrderGraph {
+        lock: Lock::new(),
+        edges: UnsafeCell::new(([None; MAX_ORDER_EDGES], 0)),
+    };
+
+    fn held_locks() -> &'static mut HeldLocks {
+        // SAFETY: only the current CPU accesses its own entry, and interrupts are disabled
+        //         when locking/unlocking tracked locks
+        unsafe { &mut HELD_LOCKS[cpu::cpu().id] }
+    }
+
+    fn is_static_lock(lock: usize) -> bool {
+        (KERNEL_LINK..kernel_elf_end()).contains(&lock)
+    }
+
+    /// Check that acquiring `after` while holding `before` doesn't invert an order we have seen before,
+    /// and record it
+    fn check_and_record_order(
+        before: &HeldLock,
+        after: usize,
+        after_site: &'static Location<'static>,
+    ) {
+        ORDER_GRAPH.lock.write_lock();
+        // SAFETY: we hold the lock
+        let (edges, len) = unsafe { &mut *ORDER_GRAPH.edges.get() };
+
+        let mut inverted = None;
+        let mut already_recorded = false;
+        for edge in edges[..*len].iter().flatten() {
+            if edge.before == after && edge.after == before.lock {
+                inverted = Some(*edge);
+                break;
+            }
+            if edge.before == before.lock && edge.after == after {
+                already_recorded = true;
+            }
+        }
+        if inverted.is_none() && !already_recorded && *len < MAX_ORDER_EDGES {
+            edges[*len] = Some(OrderEdge {
+                before: before.lock,
+                after,
+                before_site: before.site,
+                after_site,
+            });
+            *len += 1;
+        }
+        // SAFETY: we hold the lock
+        unsafe { ORDER_GRAPH.lock.write_unlock() };
+
+        if let Some(edge) = inverted {
+            panic!(
+                "Lock order inversion: acquiring lock {after:#x} at {after_site} while holding lock {:#x} (acquired at {}), \
+                 but previously lock {:#x} was acquired at {} while holding lock {:#x} (acquired at {})",
+                before.lock, before.site, edge.after, edge.after_site, edge.before, edge.before_site,
+            );
+        }
+    }
+
+    /// Must be called before spinning on the lock
+    #[track_caller]
+    pub fn before_acquire(lock: usize, kind: LockKind) {
+        let site = Location::caller();
+        let held = held_locks();
+
+        if let Some(current) = held.find(lock) {
+            if kind == LockKind::Reentrant && current.kind == LockKind::Reentrant {
+                return;
+            }
+            panic!(
+                "Recursive acquisition of lock {lock:#x} at {site}, already acquired by this CPU at {}",
+                current.site
+            );
+        }
+
+        if !is_static_lock(lock) {
+            return;
+        }
+        // copy, so that we don't hold a reference to the stack while panicking
+        let held_copy = *held;
+        for before in held_copy.iter().filter(|h| is_static_lock(h.lock)) {
+            check_and_record_order(before, lock, site);
+        }
+    }
+
+    /// Must be called after the lock is acquired (only the first time for reentrant locks)
+    #[track_caller]
+    pub fn acquired(lock: usize, kind: LockKind) {
+        held_locks().push(HeldLock {
+            lock,
+            kind,
+            site: Location::caller(),
+        });
+    }
+
+    /// Must be called before releasing the lock (only the last time for reentrant locks)
+    pub fn released(lock: usize) {
+        held_locks().remove(lock);
+    }
+
+    /// Where `cpu` acquired `lock`, for diagnostics only, as we are reading another CPU's state
+    pub fn holder_site(lock: usize, cpu: usize) -> Option<&'static Location<'static>> {
+        // SAFETY: this is racy, but only used for diagnostics before panicking
+        let held = unsafe { core::ptr::addr_of!(HELD_LOCKS[cpu]).read_volatile() };
+        held.find(lock).map(|held| held.site)
+    }
+}
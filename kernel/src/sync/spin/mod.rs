@@ -1,4 +1,10 @@
 mod lock;
+mod lockdep;
 pub mod mutex;
 pub mod remutex;
 pub mod rwlock;
+pub mod ticket;
+
+/// How many times `lock_timeout` and co. spin before reporting a deadlock (in debug builds), for the
+/// global locks that are taken from interrupts, far longer than any of them should be held for
+pub const LOCK_TIMEOUT_SPINS: u64 = 1 << 28;
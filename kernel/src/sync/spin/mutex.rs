@@ -3,12 +3,13 @@ use core::{
     fmt,
     marker::PhantomData,
     ops::{Deref, DerefMut},
+    panic::Location,
     sync::atomic::{AtomicI64, Ordering},
 };
 
 use crate::cpu;
 
-use super::lock;
+use super::{lock, lockdep};
 
 pub struct Mutex<T: ?Sized> {
     lock: lock::Lock,
@@ -66,16 +67,19 @@ impl<T> Mutex<T> {
 }
 
 impl<T: ?Sized> Mutex<T> {
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
     pub fn lock(&self) -> MutexGuard<T> {
         let cpu = cpu::cpu();
         cpu.push_cli(); // disable interrupts to avoid deadlock
         let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Exclusive);
 
         if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
             panic!("Mutex already locked by this CPU");
         } else {
             self.lock.write_lock();
             self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
             MutexGuard {
                 lock: self,
                 marker: PhantomData,
@@ -83,6 +87,35 @@ impl<T: ?Sized> Mutex<T> {
         }
     }
 
+    /// Same as [`Mutex::lock`], but panics with diagnostics (including where the lock was acquired,
+    /// if known) if we couldn't get the lock after `max_spins` iterations.
+    /// The timeout is only checked in debug builds.
+    ///
+    /// Useful for locks used in interrupt context, where a deadlock would hang silently.
+    #[track_caller]
+    #[allow(dead_code)]
+    pub fn lock_timeout(&self, max_spins: u64) -> MutexGuard<T> {
+        let site = Location::caller();
+        let cpu = cpu::cpu();
+        cpu.push_cli(); // disable interrupts to avoid deadlock
+        let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Exclusive);
+
+        if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
+            panic!("Mutex already locked by this CPU");
+        }
+        self.lock.write_lock_timeout(max_spins, || {
+            lockdep::report_timeout(self.lock_id(), self.owner_cpu.load(Ordering::Relaxed), site)
+        });
+        self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+        lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
+        MutexGuard {
+            lock: self,
+            marker: PhantomData,
+        }
+    }
+
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
         let cpu = cpu::cpu();
         cpu.push_cli(); // disable interrupts to avoid deadlock
@@ -94,6 +127,7 @@ impl<T: ?Sized> Mutex<T> {
             None
         } else if self.lock.try_write_lock() {
             self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
             Some(MutexGuard {
                 lock: self,
                 marker: PhantomData,
@@ -104,6 +138,11 @@ impl<T: ?Sized> Mutex<T> {
         }
     }
 
+    /// Identifies the lock for [`lockdep`]
+    fn lock_id(&self) -> usize {
+        &self.lock as *const lock::Lock as usize
+    }
+
     /// A special method to allow accessing the variable inside
     /// the lock after locking it.
     ///
@@ -156,6 +195,7 @@ impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
 
 impl<T: ?Sized> Drop for MutexGuard<'_, T> {
     fn drop(&mut self) {
+        lockdep::released(self.lock.lock_id());
         self.lock.owner_cpu.store(-1, Ordering::Relaxed);
         // SAFETY: the mutex is locked, we are the only accessor
         unsafe { self.lock.lock.write_unlock() };
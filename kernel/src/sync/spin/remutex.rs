@@ -3,12 +3,13 @@ use core::{
     fmt,
     marker::PhantomData,
     ops::Deref,
+    panic::Location,
     sync::atomic::{AtomicI64, Ordering},
 };
 
 use crate::cpu;
 
-use super::lock;
+use super::{lock, lockdep};
 
 /// A mutex that can be entered more than once by the same CPU
 ///
@@ -67,10 +68,12 @@ impl<T> ReMutex<T> {
         }
     }
 
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
     pub fn lock(&self) -> ReMutexGuard<T> {
         let cpu = cpu::cpu();
         cpu.push_cli(); // disable interrupts to avoid deadlock
         let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Reentrant);
 
         if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
             assert!(self.lock_count.get() > 0);
@@ -89,6 +92,7 @@ impl<T> ReMutex<T> {
             self.lock.write_lock();
             self.owner_cpu.store(cpu_id, Ordering::Relaxed);
             self.lock_count.set(1);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Reentrant);
             ReMutexGuard {
                 lock: self,
                 marker: PhantomData,
@@ -96,6 +100,44 @@ impl<T> ReMutex<T> {
         }
     }
 
+    /// Same as [`ReMutex::lock`], but panics with diagnostics if another CPU holds the lock
+    /// for more than `max_spins` iterations, see [`Mutex::lock_timeout`](super::mutex::Mutex::lock_timeout)
+    #[track_caller]
+    pub fn lock_timeout(&self, max_spins: u64) -> ReMutexGuard<T> {
+        let site = Location::caller();
+        let cpu = cpu::cpu();
+        cpu.push_cli(); // disable interrupts to avoid deadlock
+        let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Reentrant);
+
+        if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
+            assert!(self.lock_count.get() > 0);
+            assert!(cpu.n_cli() > 0 && cpu.interrupts_disabled());
+            self.lock_count.set(
+                self.lock_count
+                    .get()
+                    .checked_add(1)
+                    .expect("ReMutex lock count overflow"),
+            );
+        } else {
+            self.lock.write_lock_timeout(max_spins, || {
+                lockdep::report_timeout(
+                    self.lock_id(),
+                    self.owner_cpu.load(Ordering::Relaxed),
+                    site,
+                )
+            });
+            self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+            self.lock_count.set(1);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Reentrant);
+        }
+        ReMutexGuard {
+            lock: self,
+            marker: PhantomData,
+        }
+    }
+
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
     pub fn try_lock(&self) -> Option<ReMutexGuard<T>> {
         let cpu = cpu::cpu();
         cpu.push_cli(); // disable interrupts to avoid deadlock
@@ -117,6 +159,7 @@ impl<T> ReMutex<T> {
             // already locked here
             self.owner_cpu.store(cpu_id, Ordering::Relaxed);
             self.lock_count.set(1);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Reentrant);
             Some(ReMutexGuard {
                 lock: self,
                 marker: PhantomData,
@@ -129,6 +172,11 @@ impl<T> ReMutex<T> {
     pub fn get_mut(&mut self) -> &mut T {
         &mut self.data
     }
+
+    /// Identifies the lock for [`lockdep`]
+    fn lock_id(&self) -> usize {
+        &self.lock as *const lock::Lock as usize
+    }
 }
 
 impl<T> core::ops::Deref for ReMutexGuard<'_, T> {
@@ -149,6 +197,7 @@ impl<T> Drop for ReMutexGuard<'_, T> {
                 .expect("ReMutex lock count underflow"),
         );
         if self.lock.lock_count.get() == 0 {
+            lockdep::released(self.lock.lock_id());
             self.lock.owner_cpu.store(-1, Ordering::Relaxed);
             // SAFETY: the mutex is locked, we are the only accessor
             unsafe { self.lock.lock.write_unlock() };
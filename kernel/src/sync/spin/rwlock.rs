@@ -2,18 +2,22 @@ use core::{
     cell::UnsafeCell,
     fmt,
     marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
+    panic::Location,
     ptr::NonNull,
     sync::atomic::{AtomicI64, Ordering},
 };
 
-use crate::cpu;
+use crate::{cpu, testing};
 
-use super::lock;
+use super::{lock, lockdep};
 
 pub struct RwLock<T: ?Sized> {
     lock: lock::Lock,
     owner_cpu: AtomicI64,
+    /// If `true`, waiting writers block new readers, see [`RwLock::new_writer_preferring`]
+    prefer_writers: bool,
     data: UnsafeCell<T>,
 }
 
@@ -42,7 +46,7 @@ pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
     // `NonNull` is also covariant over `T`, just like we would have with `&T`. `NonNull`
     // is preferable over `const* T` to allow for niche optimization.
     data: NonNull<T>,
-    inner_lock: &'a lock::Lock,
+    lock: &'a RwLock<T>,
     marker: PhantomData<*const ()>, // !Send
 }
 
@@ -85,6 +89,18 @@ impl<T> RwLock<T> {
         Self {
             lock: lock::Lock::new(),
             owner_cpu: AtomicI64::new(-1),
+            prefer_writers: false,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Create a lock where writers waiting for the lock prevent new readers from acquiring it,
+    /// so that writers don't starve when the lock is heavily read
+    pub const fn new_writer_preferring(data: T) -> Self {
+        Self {
+            lock: lock::Lock::new(),
+            owner_cpu: AtomicI64::new(-1),
+            prefer_writers: true,
             data: UnsafeCell::new(data),
         }
     }
@@ -98,7 +114,7 @@ impl<T: ?Sized> RwLock<T> {
         self.owner_cpu.store(-1, Ordering::Relaxed);
         RwLockReadGuard {
             data: unsafe { NonNull::new_unchecked(self.data.get()) },
-            inner_lock: &self.lock,
+            lock: self,
             marker: PhantomData,
         }
     }
@@ -109,7 +125,7 @@ impl<T: ?Sized> RwLock<T> {
             self.owner_cpu.store(-1, Ordering::Relaxed);
             Some(RwLockReadGuard {
                 data: unsafe { NonNull::new_unchecked(self.data.get()) },
-                inner_lock: &self.lock,
+                lock: self,
                 marker: PhantomData,
             })
         } else {
@@ -117,16 +133,23 @@ impl<T: ?Sized> RwLock<T> {
         }
     }
 
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
     pub fn write(&self) -> RwLockWriteGuard<T> {
         let cpu = cpu::cpu();
         cpu.push_cli(); // disable interrupts to avoid deadlock
         let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Exclusive);
 
         if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
             panic!("Mutex already locked by this CPU");
         } else {
-            self.lock.write_lock();
+            if self.prefer_writers {
+                self.lock.write_lock_prefer_writers();
+            } else {
+                self.lock.write_lock();
+            }
             self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
             RwLockWriteGuard {
                 lock: self,
                 marker: PhantomData,
@@ -134,6 +157,31 @@ impl<T: ?Sized> RwLock<T> {
         }
     }
 
+    /// Same as [`RwLock::write`], but panics with diagnostics if we couldn't get the lock
+    /// after `max_spins` iterations, see [`Mutex::lock_timeout`](super::mutex::Mutex::lock_timeout)
+    #[track_caller]
+    pub fn write_timeout(&self, max_spins: u64) -> RwLockWriteGuard<T> {
+        let site = Location::caller();
+        let cpu = cpu::cpu();
+        cpu.push_cli(); // disable interrupts to avoid deadlock
+        let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Exclusive);
+
+        if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
+            panic!("Mutex already locked by this CPU");
+        }
+        self.lock.write_lock_timeout(max_spins, || {
+            lockdep::report_timeout(self.lock_id(), self.owner_cpu.load(Ordering::Relaxed), site)
+        });
+        self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+        lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
+        RwLockWriteGuard {
+            lock: self,
+            marker: PhantomData,
+        }
+    }
+
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
     pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
         let cpu = cpu::cpu();
         cpu.push_cli(); // disable interrupts to avoid deadlock
@@ -145,6 +193,7 @@ impl<T: ?Sized> RwLock<T> {
             None
         } else if self.lock.try_write_lock() {
             self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
             Some(RwLockWriteGuard {
                 lock: self,
                 marker: PhantomData,
@@ -161,6 +210,63 @@ impl<T: ?Sized> RwLock<T> {
     pub fn get_mut(&mut self) -> &mut T {
         self.data.get_mut()
     }
+
+    /// Identifies the lock for [`lockdep`]
+    fn lock_id(&self) -> usize {
+        &self.lock as *const lock::Lock as usize
+    }
+}
+
+#[allow(dead_code)]
+impl<'a, T: ?Sized> RwLockReadGuard<'a, T> {
+    /// Upgrade to a write lock, only succeeds if this is the only reader,
+    /// otherwise the read guard is returned back.
+    ///
+    /// This is an associated function, so that it doesn't conflict with methods of `T`.
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
+    pub fn try_upgrade(this: Self) -> Result<RwLockWriteGuard<'a, T>, Self> {
+        let cpu = cpu::cpu();
+        cpu.push_cli(); // disable interrupts to avoid deadlock
+
+        if this.lock.lock.try_upgrade() {
+            let lock = this.lock;
+            // we have moved the lock into the write guard, don't unlock
+            mem::forget(this);
+            lock.owner_cpu.store(cpu.id as i64, Ordering::Relaxed);
+            lockdep::acquired(lock.lock_id(), lockdep::LockKind::Exclusive);
+            Ok(RwLockWriteGuard {
+                lock,
+                marker: PhantomData,
+            })
+        } else {
+            cpu.pop_cli();
+            Err(this)
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<'a, T: ?Sized> RwLockWriteGuard<'a, T> {
+    /// Convert to a read lock, without letting any writer acquire the lock in between.
+    ///
+    /// This is an associated function, so that it doesn't conflict with methods of `T`.
+    pub fn downgrade(this: Self) -> RwLockReadGuard<'a, T> {
+        let lock = this.lock;
+        // we will release the write lock manually
+        mem::forget(this);
+
+        lockdep::released(lock.lock_id());
+        lock.owner_cpu.store(-1, Ordering::Relaxed);
+        // SAFETY: we hold the write lock
+        unsafe { lock.lock.downgrade() };
+        cpu::cpu().pop_cli(); // re-enable interrupts
+
+        RwLockReadGuard {
+            data: unsafe { NonNull::new_unchecked(lock.data.get()) },
+            lock,
+            marker: PhantomData,
+        }
+    }
 }
 
 impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
@@ -194,16 +300,41 @@ impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
 impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
     fn drop(&mut self) {
         // SAFETY: the mutex is locked, we are the only accessor
-        unsafe { self.inner_lock.read_unlock() };
+        unsafe { self.lock.lock.read_unlock() };
     }
 }
 
 impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
     fn drop(&mut self) {
         assert_ne!(self.lock.owner_cpu.load(Ordering::Relaxed), -1);
+        lockdep::released(self.lock.lock_id());
         self.lock.owner_cpu.store(-1, Ordering::Relaxed);
         // SAFETY: the mutex is locked, we are the only accessor
         unsafe { self.lock.lock.write_unlock() };
         cpu::cpu().pop_cli(); // re-enable interrupts
     }
 }
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_rwlock_upgrade_downgrade() {
+    let lock = RwLock::new_writer_preferring(1);
+
+    let read = lock.read();
+    let read2 = lock.read();
+    // can't upgrade with another reader
+    let read = RwLockReadGuard::try_upgrade(read).unwrap_err();
+    drop(read2);
+
+    let mut write = RwLockReadGuard::try_upgrade(read).unwrap();
+    *write = 2;
+    assert!(lock.try_read().is_none());
+
+    let read = RwLockWriteGuard::downgrade(write);
+    assert_eq!(*read, 2);
+    assert!(lock.try_write().is_none());
+    assert_eq!(*lock.try_read().unwrap(), 2);
+    drop(read);
+
+    *lock.write() = 3;
+    assert_eq!(*lock.read(), 3);
+}
@@ -0,0 +1,244 @@
+//! A fair mutex, where CPUs get the lock in the order they requested it
+//!
+//! With [`Mutex`](super::mutex::Mutex), when the lock is released, any of the waiting CPUs can get it,
+//! so under heavy contention a CPU might keep losing and starve. Here, each CPU takes a ticket
+//! and waits until its ticket is being served.
+//!
+//! The downside is that a waiting CPU can't give up after taking a ticket, and a lock holder
+//! that is slow delays everyone waiting after it.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::atomic::{AtomicI64, AtomicU32, Ordering},
+};
+
+use crate::{cpu, sync::cache_padded::CachePadded};
+
+use super::lockdep;
+
+struct TicketLock {
+    next_ticket: CachePadded<AtomicU32>,
+    now_serving: CachePadded<AtomicU32>,
+}
+
+impl TicketLock {
+    const fn new() -> Self {
+        Self {
+            next_ticket: CachePadded::new(AtomicU32::new(0)),
+            now_serving: CachePadded::new(AtomicU32::new(0)),
+        }
+    }
+
+    fn lock(&self) {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Same as `lock`, but calls `on_timeout` if we didn't get the lock after `max_spins` iterations,
+    /// `on_timeout` must not return (i.e. it panics)
+    ///
+    /// The timeout is only checked in debug builds, in release this is the same as `lock`
+    fn lock_timeout(&self, max_spins: u64, on_timeout: impl FnOnce()) {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut spins = 0u64;
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            if cfg!(debug_assertions) {
+                spins += 1;
+                if spins > max_spins {
+                    on_timeout();
+                    unreachable!("`on_timeout` returned");
+                }
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    #[must_use]
+    fn try_lock(&self) -> bool {
+        let ticket = self.now_serving.load(Ordering::Relaxed);
+        // only take a ticket if it will be served immediately
+        self.next_ticket
+            .compare_exchange(
+                ticket,
+                ticket.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// SAFETY: the caller must hold the lock
+    unsafe fn unlock(&self) {
+        self.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+pub struct TicketMutex<T: ?Sized> {
+    lock: TicketLock,
+    owner_cpu: AtomicI64,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for TicketMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for TicketMutex<T> {}
+
+impl<T> fmt::Debug for TicketMutex<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("TicketMutex");
+        s.field("owner_cpu", &self.owner_cpu);
+        if let Some(data) = self.try_lock() {
+            s.field("data", &data);
+        } else {
+            s.field("data", &"[locked]");
+        }
+        s.finish()
+    }
+}
+
+#[must_use]
+pub struct TicketMutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a TicketMutex<T>,
+    marker: PhantomData<*const ()>, // !Send
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for TicketMutexGuard<'_, T> {}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for TicketMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for TicketMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[allow(dead_code)]
+impl<T> TicketMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            lock: TicketLock::new(),
+            owner_cpu: AtomicI64::new(-1),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<T: ?Sized> TicketMutex<T> {
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
+    pub fn lock(&self) -> TicketMutexGuard<T> {
+        let cpu = cpu::cpu();
+        cpu.push_cli(); // disable interrupts to avoid deadlock
+        let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Exclusive);
+
+        if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
+            panic!("Mutex already locked by this CPU");
+        }
+        self.lock.lock();
+        self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+        lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
+        TicketMutexGuard {
+            lock: self,
+            marker: PhantomData,
+        }
+    }
+
+    /// Same as [`TicketMutex::lock`], but panics with diagnostics if we couldn't get the lock
+    /// after `max_spins` iterations, see [`Mutex::lock_timeout`](super::mutex::Mutex::lock_timeout)
+    #[track_caller]
+    pub fn lock_timeout(&self, max_spins: u64) -> TicketMutexGuard<T> {
+        let site = Location::caller();
+        let cpu = cpu::cpu();
+        cpu.push_cli(); // disable interrupts to avoid deadlock
+        let cpu_id = cpu.id as i64;
+        lockdep::before_acquire(self.lock_id(), lockdep::LockKind::Exclusive);
+
+        if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
+            panic!("Mutex already locked by this CPU");
+        }
+        self.lock.lock_timeout(max_spins, || {
+            lockdep::report_timeout(self.lock_id(), self.owner_cpu.load(Ordering::Relaxed), site)
+        });
+        self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+        lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
+        TicketMutexGuard {
+            lock: self,
+            marker: PhantomData,
+        }
+    }
+
+    #[cfg_attr(all(debug_assertions, feature = "spin_lock_debug"), track_caller)]
+    pub fn try_lock(&self) -> Option<TicketMutexGuard<T>> {
+        let cpu = cpu::cpu();
+        cpu.push_cli(); // disable interrupts to avoid deadlock
+        let cpu_id = cpu.id as i64;
+
+        if self.owner_cpu.load(Ordering::Relaxed) == cpu_id {
+            // we will not throw here, since the CPU might want to try to lock it again, at least its not a deadlock
+            cpu.pop_cli();
+            None
+        } else if self.lock.try_lock() {
+            self.owner_cpu.store(cpu_id, Ordering::Relaxed);
+            lockdep::acquired(self.lock_id(), lockdep::LockKind::Exclusive);
+            Some(TicketMutexGuard {
+                lock: self,
+                marker: PhantomData,
+            })
+        } else {
+            cpu.pop_cli();
+            None
+        }
+    }
+
+    /// Identifies the lock for [`lockdep`]
+    fn lock_id(&self) -> usize {
+        &self.lock as *const TicketLock as usize
+    }
+
+    /// We know statically that no one else is accessing the lock, so we can
+    /// just return a reference to the data without acquiring the lock.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for TicketMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the mutex is locked, we are the only accessors,
+        //         and the pointer is valid, since it was generated for a valid T
+        unsafe { self.lock.data.get().as_ref().unwrap() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for TicketMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the mutex is locked, we are the only accessors,
+        //         and the pointer is valid, since it was generated for a valid T
+        unsafe { self.lock.data.get().as_mut().unwrap() }
+    }
+}
+
+impl<T: ?Sized> Drop for TicketMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::released(self.lock.lock_id());
+        self.lock.owner_cpu.store(-1, Ordering::Relaxed);
+        // SAFETY: the mutex is locked, we are the only accessor
+        unsafe { self.lock.lock.unlock() };
+        cpu::cpu().pop_cli(); // re-enable interrupts
+    }
+}
@@ -1,5 +1,8 @@
 #[cfg(test)]
-use crate::hw::qemu;
+use crate::hw::{
+    debugcon::{self, Value},
+    qemu,
+};
 
 #[cfg(test)]
 pub struct TestCase {
@@ -109,6 +112,13 @@ pub fn test_runner(tests: &[&TestCase]) {
         if test.ignore {
             println!("IGNORED");
             ignored += 1;
+            debugcon::send(
+                "test",
+                &[
+                    ("name", Value::Str(test.name)),
+                    ("result", Value::Str("ignored")),
+                ],
+            );
             continue;
         }
 
@@ -118,21 +128,43 @@ pub fn test_runner(tests: &[&TestCase]) {
 
         let buffer = console::stop_capture().unwrap();
 
-        if r.is_ok() {
+        let ok = if r.is_ok() {
             if test.should_panic {
                 failed += 1;
                 println!("FAILED (should_panic)");
+                false
             } else {
                 passed += 1;
                 println!("OK");
+                true
             }
         } else if test.should_panic {
             passed += 1;
             println!("OK");
+            true
         } else {
             failed += 1;
             println!("FAILED");
+            false
+        };
 
+        if ok {
+            debugcon::send(
+                "test",
+                &[
+                    ("name", Value::Str(test.name)),
+                    ("result", Value::Str("ok")),
+                ],
+            );
+        } else {
+            debugcon::send(
+                "test",
+                &[
+                    ("name", Value::Str(test.name)),
+                    ("result", Value::Str("failed")),
+                    ("output", Value::Str(&buffer)),
+                ],
+            );
             failed_buffers.push((test.name, buffer));
         }
     }
@@ -148,6 +180,14 @@ pub fn test_runner(tests: &[&TestCase]) {
     }
 
     println!("{} passed; {} failed; {} ignored", passed, failed, ignored);
+    debugcon::send(
+        "test_summary",
+        &[
+            ("passed", Value::U64(passed)),
+            ("failed", Value::U64(failed)),
+            ("ignored", Value::U64(ignored)),
+        ],
+    );
 
     if failed > 0 {
         qemu::exit(qemu::ExitStatus::Failure);
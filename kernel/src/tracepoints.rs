@@ -0,0 +1,235 @@
+//! Static tracepoints, recorded as fixed size binary records into a ring buffer for each CPU
+//!
+//! Recording is enabled only when the trace `isa-debugcon` port is present (`cargo xtask run --trace`),
+//! otherwise [`record`] only checks a flag. The buffers are sent through the port on shutdown and panic,
+//! and `cargo xtask trace` decodes them into the Chrome trace-event format.
+//!
+//! The port gets a dump for each CPU, a [`DumpHeader`] followed by `count` [`TraceRecord`]s (oldest first),
+//! all fields are little-endian. The timestamps are `TSC` cycles, as reading the clocks takes a lock, and
+//! the header has two `(cycles, nanoseconds)` points to convert them.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use alloc::{boxed::Box, vec::Vec};
+use tracing::{info, warn};
+
+use crate::{
+    cpu::{self, MAX_CPUS},
+    devices::clock::TscSyncPoint,
+    hw::debugcon,
+    init_stage::{self, InitStage},
+    sync::once::OnceLock,
+    testing,
+};
+
+/// 256KB for each CPU
+const RECORDS_PER_CPU: usize = 8192;
+const DUMP_MAGIC: [u8; 8] = *b"EMTRACE1";
+/// The pid of records that are not in a process
+const NO_PROCESS: u64 = u64::MAX;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUFFERS: OnceLock<Vec<CpuBuffer>> = OnceLock::new();
+static START: OnceLock<TscSyncPoint> = OnceLock::new();
+
+/// The meaning of `arg` and `arg2` depends on the event
+#[repr(u16)]
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    /// A process starts running, `arg` is its pid
+    SchedulerSwitchIn = 1,
+    /// A process stopped running (yield, sleep, exit, ...), `arg` is its pid
+    SchedulerSwitchOut = 2,
+    /// `arg` is the syscall number
+    SyscallEnter = 3,
+    /// `arg` is the syscall number
+    SyscallExit = 4,
+    /// `arg` is the faulting address, `arg2` is the error code
+    PageFault = 5,
+    /// `arg` is the start sector, `arg2` is the number of sectors, same for the rest
+    DiskReadStart = 6,
+    DiskReadEnd = 7,
+    DiskWriteStart = 8,
+    DiskWriteEnd = 9,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct TraceRecord {
+    /// `TSC` cycles
+    timestamp: u64,
+    /// The running process, [`NO_PROCESS`] if none
+    pid: u64,
+    arg: u64,
+    event: u16,
+    reserved: u16,
+    arg2: u32,
+}
+
+impl TraceRecord {
+    const SIZE: usize = 32;
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.pid.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.arg.to_le_bytes());
+        bytes[24..26].copy_from_slice(&self.event.to_le_bytes());
+        bytes[26..28].copy_from_slice(&self.reserved.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.arg2.to_le_bytes());
+        bytes
+    }
+}
+
+struct DumpHeader {
+    cpu: u32,
+    count: u32,
+    start: TscSyncPoint,
+    end: TscSyncPoint,
+}
+
+impl DumpHeader {
+    const SIZE: usize = 48;
+
+    fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&DUMP_MAGIC);
+        bytes[8..12].copy_from_slice(&self.cpu.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.count.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.start.cycles.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.start.nanos.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.end.cycles.to_le_bytes());
+        bytes[40..48].copy_from_slice(&self.end.nanos.to_le_bytes());
+        bytes
+    }
+}
+
+struct CpuBuffer {
+    records: Box<[UnsafeCell<TraceRecord>]>,
+    /// The number of records written, the next one goes to `next % len`
+    next: AtomicUsize,
+}
+
+// SAFETY: a slot is only written by the CPU that reserved it with `next`, and the records are only
+//         read after recording is stopped
+unsafe impl Sync for CpuBuffer {}
+
+impl CpuBuffer {
+    fn new(len: usize) -> Self {
+        Self {
+            records: (0..len)
+                .map(|_| UnsafeCell::new(TraceRecord::default()))
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, record: TraceRecord) {
+        if self.records.is_empty() {
+            return;
+        }
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.records.len();
+        // SAFETY: the slot is reserved for us, even if an interrupt records in the middle
+        unsafe { self.records[index].get().write(record) };
+    }
+
+    /// The records in the order they were written, the oldest are overwritten when the buffer is full
+    fn records(&self) -> impl Iterator<Item = TraceRecord> + '_ {
+        let next = self.next.load(Ordering::Relaxed);
+        let count = next.min(self.records.len());
+        // SAFETY: recording is stopped, no one is writing
+        (next - count..next).map(|i| unsafe { *self.records[i % self.records.len()].get() })
+    }
+}
+
+/// Enable the tracepoints if the trace port is present
+pub fn init() {
+    // the timestamps are converted using the clocks
+    init_stage::debug_assert_reached(InitStage::Clocks);
+    if !debugcon::is_trace_port_present() {
+        return;
+    }
+    let Some(start) = TscSyncPoint::now() else {
+        warn!("Tracepoints: could not read the clocks, tracepoints are disabled");
+        return;
+    };
+
+    let online = cpu::tlb::online_cpus();
+    BUFFERS.get_or_init(|| {
+        (0..MAX_CPUS)
+            .map(|i| {
+                let online = online & (1 << i) != 0;
+                CpuBuffer::new(if online { RECORDS_PER_CPU } else { 0 })
+            })
+            .collect()
+    });
+    START.set(start).expect("Tracepoints already initialized");
+    ENABLED.store(true, Ordering::Release);
+    info!("Tracepoints enabled, {RECORDS_PER_CPU} records for each CPU");
+}
+
+/// Record `event` in the buffer of the current CPU, does nothing if the tracepoints are disabled.
+///
+/// Must not take any locks, as it can be called from any interrupt handler.
+pub fn record(event: Event, arg: u64, arg2: u32) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    let current_cpu = cpu::cpu();
+    let pid = if current_cpu.context.is_some() {
+        current_cpu.process_id
+    } else {
+        NO_PROCESS
+    };
+    BUFFERS.get()[current_cpu.id].push(TraceRecord {
+        timestamp: unsafe { cpu::read_tsc() },
+        pid,
+        arg,
+        event: event as u16,
+        reserved: 0,
+        arg2,
+    });
+}
+
+/// Stop recording, and send the buffers through the trace port, called on shutdown and panic
+pub fn dump() {
+    if !ENABLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    let start = *START.get();
+    // the decoder uses the cycles as nanoseconds if we couldn't get the end point
+    let end = TscSyncPoint::now().unwrap_or(start);
+
+    for (cpu, buffer) in BUFFERS.get().iter().enumerate() {
+        if buffer.records.is_empty() {
+            continue;
+        }
+        let header = DumpHeader {
+            cpu: cpu as u32,
+            count: buffer.records().count() as u32,
+            start,
+            end,
+        };
+        debugcon::write_trace(&header.to_bytes());
+        for record in buffer.records() {
+            debugcon::write_trace(&record.to_bytes());
+        }
+    }
+}
+
+#[macro_rules_attribute::apply(testing::test)]
+fn test_buffer_keeps_latest_records() {
+    let buffer = CpuBuffer::new(4);
+    for arg in 0..6 {
+        buffer.push(TraceRecord {
+            arg,
+            ..Default::default()
+        });
+    }
+
+    let args = buffer.records().map(|r| r.arg).collect::<Vec<_>>();
+    assert_eq!(args, [2, 3, 4, 5]);
+}
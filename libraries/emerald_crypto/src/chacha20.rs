@@ -0,0 +1,107 @@
+//! ChaCha20 stream cipher (RFC 8439)
+
+use crate::read_u32_le;
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+pub const BLOCK_SIZE: usize = 64;
+
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+#[inline(always)]
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Compute one keystream block for the given `key`, `nonce` and block `counter`
+pub fn block(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], counter: u32) -> [u8; BLOCK_SIZE] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&CONSTANTS);
+    for i in 0..8 {
+        state[4 + i] = read_u32_le(&key[i * 4..]);
+    }
+    state[12] = counter;
+    for i in 0..3 {
+        state[13 + i] = read_u32_le(&nonce[i * 4..]);
+    }
+
+    let mut working = state;
+    for _ in 0..10 {
+        // column rounds
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        // diagonal rounds
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    let mut out = [0u8; BLOCK_SIZE];
+    for (i, chunk) in out.chunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&working[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    out
+}
+
+/// ChaCha20 keystream generator, can be used to encrypt/decrypt data incrementally
+pub struct ChaCha20 {
+    key: [u8; KEY_SIZE],
+    nonce: [u8; NONCE_SIZE],
+    counter: u32,
+    keystream: [u8; BLOCK_SIZE],
+    /// position inside `keystream`, `BLOCK_SIZE` means we need a new block
+    keystream_pos: usize,
+}
+
+impl ChaCha20 {
+    pub fn new(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], initial_counter: u32) -> Self {
+        Self {
+            key: *key,
+            nonce: *nonce,
+            counter: initial_counter,
+            keystream: [0; BLOCK_SIZE],
+            keystream_pos: BLOCK_SIZE,
+        }
+    }
+
+    /// XOR the keystream into `data`, encrypting or decrypting it
+    ///
+    /// # Panics
+    /// If the 32-bit block counter overflows (more than 256GB for a single nonce)
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            if self.keystream_pos == BLOCK_SIZE {
+                self.keystream = block(&self.key, &self.nonce, self.counter);
+                self.counter = self
+                    .counter
+                    .checked_add(1)
+                    .expect("ChaCha20 counter overflow");
+                self.keystream_pos = 0;
+            }
+            *byte ^= self.keystream[self.keystream_pos];
+            self.keystream_pos += 1;
+        }
+    }
+}
+
+impl Drop for ChaCha20 {
+    fn drop(&mut self) {
+        // don't leave key material lying around
+        // SAFETY: the pointers are valid and aligned, we use volatile writes so it
+        //         won't be optimized away
+        unsafe {
+            core::ptr::write_volatile(&mut self.key, [0; KEY_SIZE]);
+            core::ptr::write_volatile(&mut self.keystream, [0; BLOCK_SIZE]);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! ChaCha20-Poly1305 AEAD (RFC 8439)
+
+use crate::{
+    chacha20::{self, ChaCha20},
+    constant_time_eq,
+    poly1305::{self, Poly1305},
+};
+
+pub const KEY_SIZE: usize = chacha20::KEY_SIZE;
+pub const NONCE_SIZE: usize = chacha20::NONCE_SIZE;
+pub const TAG_SIZE: usize = poly1305::TAG_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadError {
+    /// The tag didn't match, the data or the additional data were modified
+    /// or the wrong key/nonce were used
+    AuthenticationFailed,
+}
+
+pub struct ChaCha20Poly1305 {
+    key: [u8; KEY_SIZE],
+}
+
+impl ChaCha20Poly1305 {
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        Self { key: *key }
+    }
+
+    fn compute_tag(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        ciphertext: &[u8],
+    ) -> [u8; TAG_SIZE] {
+        let poly_key_block = chacha20::block(&self.key, nonce, 0);
+        let mut poly = Poly1305::new(poly_key_block[..poly1305::KEY_SIZE].try_into().unwrap());
+
+        poly.update(associated_data);
+        poly.pad_to_block();
+        poly.update(ciphertext);
+        poly.pad_to_block();
+        poly.update(&(associated_data.len() as u64).to_le_bytes());
+        poly.update(&(ciphertext.len() as u64).to_le_bytes());
+        poly.finalize()
+    }
+
+    /// Encrypt `buffer` in place and return the authentication tag
+    ///
+    /// A `nonce` must never be reused with the same key.
+    pub fn encrypt_in_place_detached(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> [u8; TAG_SIZE] {
+        ChaCha20::new(&self.key, nonce, 1).apply_keystream(buffer);
+        self.compute_tag(nonce, associated_data, buffer)
+    }
+
+    /// Verify `tag` and decrypt `buffer` in place
+    ///
+    /// On failure, `buffer` is left untouched (still encrypted).
+    pub fn decrypt_in_place_detached(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &[u8; TAG_SIZE],
+    ) -> Result<(), AeadError> {
+        let expected_tag = self.compute_tag(nonce, associated_data, buffer);
+        if !constant_time_eq(&expected_tag, tag) {
+            return Err(AeadError::AuthenticationFailed);
+        }
+        ChaCha20::new(&self.key, nonce, 1).apply_keystream(buffer);
+        Ok(())
+    }
+}
+
+impl Drop for ChaCha20Poly1305 {
+    fn drop(&mut self) {
+        // SAFETY: the pointer is valid and aligned
+        unsafe { core::ptr::write_volatile(&mut self.key, [0; KEY_SIZE]) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_hex;
+
+    // RFC 8439 section 2.8.2
+    const KEY: [u8; KEY_SIZE] = [
+        0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x8b, 0x8c, 0x8d, 0x8e,
+        0x8f, 0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0x9b, 0x9c, 0x9d,
+        0x9e, 0x9f,
+    ];
+    const NONCE: [u8; NONCE_SIZE] = [
+        0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+    ];
+    const AAD: [u8; 12] = [
+        0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+    ];
+    const PLAINTEXT: &[u8; 114] = b"Ladies and Gentlemen of the class of '99: \
+        If I could offer you only one tip for the future, sunscreen would be it.";
+    const CIPHERTEXT: &str = "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+        3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+        92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+        3ff4def08e4b7a9de576d26586cec64b6116";
+    const TAG: &str = "1ae10b594f09e26a7e902ecbd0600691";
+
+    #[test]
+    fn rfc8439_encrypt() {
+        let mut buffer = *PLAINTEXT;
+        let tag = ChaCha20Poly1305::new(&KEY).encrypt_in_place_detached(&NONCE, &AAD, &mut buffer);
+        assert_eq!(buffer, from_hex::<114>(CIPHERTEXT));
+        assert_eq!(tag, from_hex::<TAG_SIZE>(TAG));
+    }
+
+    #[test]
+    fn rfc8439_decrypt() {
+        let mut buffer = from_hex::<114>(CIPHERTEXT);
+        let tag = from_hex::<TAG_SIZE>(TAG);
+        ChaCha20Poly1305::new(&KEY)
+            .decrypt_in_place_detached(&NONCE, &AAD, &mut buffer, &tag)
+            .unwrap();
+        assert_eq!(&buffer, PLAINTEXT);
+    }
+
+    #[test]
+    fn rfc8439_tampered() {
+        let cipher = ChaCha20Poly1305::new(&KEY);
+        let ciphertext = from_hex::<114>(CIPHERTEXT);
+        let tag = from_hex::<TAG_SIZE>(TAG);
+
+        let mut buffer = ciphertext;
+        buffer[0] ^= 1;
+        assert_eq!(
+            cipher.decrypt_in_place_detached(&NONCE, &AAD, &mut buffer, &tag),
+            Err(AeadError::AuthenticationFailed)
+        );
+        // left untouched
+        assert_eq!(buffer[1..], ciphertext[1..]);
+
+        let mut buffer = ciphertext;
+        assert_eq!(
+            cipher.decrypt_in_place_detached(&NONCE, &AAD[1..], &mut buffer, &tag),
+            Err(AeadError::AuthenticationFailed)
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! HMAC-SHA256 (RFC 2104)
+
+use crate::{
+    constant_time_eq,
+    sha256::{self, Sha256},
+};
+
+pub const TAG_SIZE: usize = sha256::DIGEST_SIZE;
+
+/// Incremental HMAC-SHA256
+#[derive(Clone)]
+pub struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    pub fn new(key: &[u8]) -> Self {
+        let mut block_key = [0u8; sha256::BLOCK_SIZE];
+        if key.len() > sha256::BLOCK_SIZE {
+            block_key[..sha256::DIGEST_SIZE].copy_from_slice(&Sha256::digest(key));
+        } else {
+            block_key[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner_pad = [0x36u8; sha256::BLOCK_SIZE];
+        let mut outer_pad = [0x5cu8; sha256::BLOCK_SIZE];
+        for i in 0..sha256::BLOCK_SIZE {
+            inner_pad[i] ^= block_key[i];
+            outer_pad[i] ^= block_key[i];
+        }
+
+        let mut inner = Sha256::new();
+        inner.update(&inner_pad);
+        let mut outer = Sha256::new();
+        outer.update(&outer_pad);
+
+        Self { inner, outer }
+    }
+
+    /// Compute the tag of `data` in one go
+    pub fn mac(key: &[u8], data: &[u8]) -> [u8; TAG_SIZE] {
+        let mut hmac = Self::new(key);
+        hmac.update(data);
+        hmac.finalize()
+    }
+
+    /// Verify `tag` against the computed tag of `data`, in constant time
+    pub fn verify(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+        constant_time_eq(&Self::mac(key, data), tag)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(self) -> [u8; TAG_SIZE] {
+        let inner_hash = self.inner.finalize();
+        let mut outer = self.outer;
+        outer.update(&inner_hash);
+        outer.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_hex;
+
+    // RFC 4231, test cases 1, 2 and 6
+    #[test]
+    fn rfc4231_vectors() {
+        let vectors: [(&[u8], &[u8], &str); 3] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+        ];
+
+        for (key, data, expected) in vectors {
+            let expected = from_hex::<TAG_SIZE>(expected);
+            assert_eq!(HmacSha256::mac(key, data), expected);
+            assert!(HmacSha256::verify(key, data, &expected));
+            assert!(!HmacSha256::verify(key, data, &expected[1..]));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+pub mod chacha20;
+pub mod chacha20poly1305;
+pub mod hmac;
+pub mod poly1305;
+pub mod sha256;
+pub mod x25519;
+
+/// Compare two byte slices in constant time (with respect to the content).
+///
+/// Returns `false` if the lengths are different, the length is not considered secret.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    // prevent the compiler from short-circuiting the loop above
+    core::hint::black_box(diff) == 0
+}
+
+#[inline]
+pub(crate) fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[inline]
+pub(crate) fn read_u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+pub(crate) fn from_hex<const N: usize>(hex: &str) -> [u8; N] {
+    assert_eq!(hex.len(), N * 2);
+    let mut out = [0; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    out
+}
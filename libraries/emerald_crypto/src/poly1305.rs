@@ -0,0 +1,219 @@
+//! Poly1305 one-time authenticator (RFC 8439)
+//!
+//! Uses 26-bit limbs, based on `poly1305-donna`.
+
+use crate::read_u32_le;
+
+pub const KEY_SIZE: usize = 32;
+pub const TAG_SIZE: usize = 16;
+const BLOCK_SIZE: usize = 16;
+
+const LIMB_MASK: u32 = 0x3ffffff;
+
+pub struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+    buffer: [u8; BLOCK_SIZE],
+    buffer_len: usize,
+}
+
+impl Poly1305 {
+    /// Create a new authenticator, `key` must only be used for a single message
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        // clamp `r`
+        let r = [
+            read_u32_le(&key[0..]) & 0x3ffffff,
+            (read_u32_le(&key[3..]) >> 2) & 0x3ffff03,
+            (read_u32_le(&key[6..]) >> 4) & 0x3ffc0ff,
+            (read_u32_le(&key[9..]) >> 6) & 0x3f03fff,
+            (read_u32_le(&key[12..]) >> 8) & 0x00fffff,
+        ];
+        let pad = [
+            read_u32_le(&key[16..]),
+            read_u32_le(&key[20..]),
+            read_u32_le(&key[24..]),
+            read_u32_le(&key[28..]),
+        ];
+
+        Self {
+            r,
+            h: [0; 5],
+            pad,
+            buffer: [0; BLOCK_SIZE],
+            buffer_len: 0,
+        }
+    }
+
+    /// Compute the tag of `data` in one go
+    pub fn mac(key: &[u8; KEY_SIZE], data: &[u8]) -> [u8; TAG_SIZE] {
+        let mut poly = Self::new(key);
+        poly.update(data);
+        poly.finalize()
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.buffer_len > 0 {
+            let to_copy = (BLOCK_SIZE - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len + to_copy]
+                .copy_from_slice(&data[..to_copy]);
+            self.buffer_len += to_copy;
+            data = &data[to_copy..];
+
+            if self.buffer_len < BLOCK_SIZE {
+                return;
+            }
+            let block = self.buffer;
+            self.process_block(&block, 1 << 24);
+            self.buffer_len = 0;
+        }
+
+        let mut chunks = data.chunks_exact(BLOCK_SIZE);
+        for block in &mut chunks {
+            self.process_block(block.try_into().unwrap(), 1 << 24);
+        }
+        let rest = chunks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffer_len = rest.len();
+    }
+
+    /// Pad the current message with zeros up to a multiple of 16 bytes,
+    /// used by the AEAD construction
+    pub(crate) fn pad_to_block(&mut self) {
+        if self.buffer_len > 0 {
+            self.buffer[self.buffer_len..].fill(0);
+            let block = self.buffer;
+            self.process_block(&block, 1 << 24);
+            self.buffer_len = 0;
+        }
+    }
+
+    fn process_block(&mut self, block: &[u8; BLOCK_SIZE], hibit: u32) {
+        let [r0, r1, r2, r3, r4] = self.r.map(|r| r as u64);
+        let s1 = r1 * 5;
+        let s2 = r2 * 5;
+        let s3 = r3 * 5;
+        let s4 = r4 * 5;
+
+        let [mut h0, mut h1, mut h2, mut h3, mut h4] = self.h;
+
+        // h += m[i]
+        h0 += read_u32_le(&block[0..]) & LIMB_MASK;
+        h1 += (read_u32_le(&block[3..]) >> 2) & LIMB_MASK;
+        h2 += (read_u32_le(&block[6..]) >> 4) & LIMB_MASK;
+        h3 += (read_u32_le(&block[9..]) >> 6) & LIMB_MASK;
+        h4 += (read_u32_le(&block[12..]) >> 8) | hibit;
+
+        let [h0w, h1w, h2w, h3w, h4w] = [h0, h1, h2, h3, h4].map(|h| h as u64);
+
+        // h *= r
+        let d0 = h0w * r0 + h1w * s4 + h2w * s3 + h3w * s2 + h4w * s1;
+        let mut d1 = h0w * r1 + h1w * r0 + h2w * s4 + h3w * s3 + h4w * s2;
+        let mut d2 = h0w * r2 + h1w * r1 + h2w * r0 + h3w * s4 + h4w * s3;
+        let mut d3 = h0w * r3 + h1w * r2 + h2w * r1 + h3w * r0 + h4w * s4;
+        let mut d4 = h0w * r4 + h1w * r3 + h2w * r2 + h3w * r1 + h4w * r0;
+
+        // (partial) h %= p
+        let mut c = d0 >> 26;
+        h0 = d0 as u32 & LIMB_MASK;
+        d1 += c;
+        c = d1 >> 26;
+        h1 = d1 as u32 & LIMB_MASK;
+        d2 += c;
+        c = d2 >> 26;
+        h2 = d2 as u32 & LIMB_MASK;
+        d3 += c;
+        c = d3 >> 26;
+        h3 = d3 as u32 & LIMB_MASK;
+        d4 += c;
+        c = d4 >> 26;
+        h4 = d4 as u32 & LIMB_MASK;
+        h0 += c as u32 * 5;
+        let c = h0 >> 26;
+        h0 &= LIMB_MASK;
+        h1 += c;
+
+        self.h = [h0, h1, h2, h3, h4];
+    }
+
+    pub fn finalize(mut self) -> [u8; TAG_SIZE] {
+        if self.buffer_len > 0 {
+            // last partial block, padded with a single `1` bit then zeros
+            self.buffer[self.buffer_len] = 1;
+            self.buffer[self.buffer_len + 1..].fill(0);
+            let block = self.buffer;
+            self.process_block(&block, 0);
+        }
+
+        let [mut h0, mut h1, mut h2, mut h3, mut h4] = self.h;
+
+        // fully carry h
+        let mut c = h1 >> 26;
+        h1 &= LIMB_MASK;
+        h2 += c;
+        c = h2 >> 26;
+        h2 &= LIMB_MASK;
+        h3 += c;
+        c = h3 >> 26;
+        h3 &= LIMB_MASK;
+        h4 += c;
+        c = h4 >> 26;
+        h4 &= LIMB_MASK;
+        h0 += c * 5;
+        c = h0 >> 26;
+        h0 &= LIMB_MASK;
+        h1 += c;
+
+        // compute h + -p
+        let mut g0 = h0 + 5;
+        c = g0 >> 26;
+        g0 &= LIMB_MASK;
+        let mut g1 = h1 + c;
+        c = g1 >> 26;
+        g1 &= LIMB_MASK;
+        let mut g2 = h2 + c;
+        c = g2 >> 26;
+        g2 &= LIMB_MASK;
+        let mut g3 = h3 + c;
+        c = g3 >> 26;
+        g3 &= LIMB_MASK;
+        let g4 = (h4 + c).wrapping_sub(1 << 26);
+
+        // select h if h < p, or h + -p if h >= p (without branching)
+        let mask = (g4 >> 31).wrapping_sub(1);
+        g0 &= mask;
+        g1 &= mask;
+        g2 &= mask;
+        g3 &= mask;
+        let g4 = g4 & mask;
+        let mask = !mask;
+        h0 = (h0 & mask) | g0;
+        h1 = (h1 & mask) | g1;
+        h2 = (h2 & mask) | g2;
+        h3 = (h3 & mask) | g3;
+        h4 = (h4 & mask) | g4;
+
+        // h %= 2^128
+        let h0 = h0 | (h1 << 26);
+        let h1 = (h1 >> 6) | (h2 << 20);
+        let h2 = (h2 >> 12) | (h3 << 14);
+        let h3 = (h3 >> 18) | (h4 << 8);
+
+        // tag = (h + pad) % 2^128
+        let mut f = h0 as u64 + self.pad[0] as u64;
+        let t0 = f as u32;
+        f = h1 as u64 + self.pad[1] as u64 + (f >> 32);
+        let t1 = f as u32;
+        f = h2 as u64 + self.pad[2] as u64 + (f >> 32);
+        let t2 = f as u32;
+        f = h3 as u64 + self.pad[3] as u64 + (f >> 32);
+        let t3 = f as u32;
+
+        let mut tag = [0u8; TAG_SIZE];
+        tag[0..4].copy_from_slice(&t0.to_le_bytes());
+        tag[4..8].copy_from_slice(&t1.to_le_bytes());
+        tag[8..12].copy_from_slice(&t2.to_le_bytes());
+        tag[12..16].copy_from_slice(&t3.to_le_bytes());
+        tag
+    }
+}
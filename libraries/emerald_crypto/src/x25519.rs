@@ -0,0 +1,272 @@
+//! X25519 Diffie-Hellman key exchange (RFC 7748)
+//!
+//! Field arithmetic uses 16 limbs of 16 bits each (stored in `i64`), based on `TweetNaCl`,
+//! all operations are constant time with respect to the secret scalar.
+
+pub const KEY_SIZE: usize = 32;
+
+/// The u-coordinate of the base point
+pub const BASE_POINT: [u8; KEY_SIZE] = {
+    let mut p = [0; KEY_SIZE];
+    p[0] = 9;
+    p
+};
+
+type FieldElement = [i64; 16];
+
+const A24: FieldElement = [0xdb41, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+fn carry(o: &mut FieldElement) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            // 2^256 = 38 (mod p)
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Swap `p` and `q` if `b == 1`, without branching
+fn conditional_swap(p: &mut FieldElement, q: &mut FieldElement, b: i64) {
+    let mask = !(b - 1);
+    for i in 0..16 {
+        let t = mask & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack(n: &FieldElement) -> [u8; KEY_SIZE] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+
+    let mut m = [0i64; 16];
+    for _ in 0..2 {
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        conditional_swap(&mut t, &mut m, 1 - b);
+    }
+
+    let mut out = [0u8; KEY_SIZE];
+    for i in 0..16 {
+        out[2 * i] = t[i] as u8;
+        out[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    out
+}
+
+fn unpack(n: &[u8; KEY_SIZE]) -> FieldElement {
+    let mut o = [0i64; 16];
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn add(a: &FieldElement, b: &FieldElement) -> FieldElement {
+    let mut o = [0i64; 16];
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    o
+}
+
+fn sub(a: &FieldElement, b: &FieldElement) -> FieldElement {
+    let mut o = [0i64; 16];
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    o
+}
+
+fn mul(a: &FieldElement, b: &FieldElement) -> FieldElement {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = [0i64; 16];
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+fn square(a: &FieldElement) -> FieldElement {
+    mul(a, a)
+}
+
+/// Compute `a^(p-2)`, which is the inverse of `a` (mod p)
+fn invert(a: &FieldElement) -> FieldElement {
+    let mut c = *a;
+    for i in (0..=253).rev() {
+        c = square(&c);
+        if i != 2 && i != 4 {
+            c = mul(&c, a);
+        }
+    }
+    c
+}
+
+/// Multiply the point `point` (u-coordinate) by `scalar`
+pub fn x25519(scalar: &[u8; KEY_SIZE], point: &[u8; KEY_SIZE]) -> [u8; KEY_SIZE] {
+    // clamp the scalar
+    let mut z = *scalar;
+    z[31] = (z[31] & 127) | 64;
+    z[0] &= 248;
+
+    let x = unpack(point);
+    let mut a = [0i64; 16];
+    let mut b = x;
+    let mut c = [0i64; 16];
+    let mut d = [0i64; 16];
+    a[0] = 1;
+    d[0] = 1;
+
+    // montgomery ladder
+    for i in (0..=254).rev() {
+        let bit = ((z[i >> 3] >> (i & 7)) & 1) as i64;
+        conditional_swap(&mut a, &mut b, bit);
+        conditional_swap(&mut c, &mut d, bit);
+
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        c = add(&b, &d);
+        b = sub(&b, &d);
+        d = square(&e);
+        let f = square(&a);
+        a = mul(&c, &a);
+        c = mul(&b, &e);
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        b = square(&a);
+        c = sub(&d, &f);
+        a = mul(&c, &A24);
+        a = add(&a, &d);
+        c = mul(&c, &a);
+        a = mul(&d, &f);
+        d = mul(&b, &x);
+        b = square(&e);
+
+        conditional_swap(&mut a, &mut b, bit);
+        conditional_swap(&mut c, &mut d, bit);
+    }
+
+    let c = invert(&c);
+    pack(&mul(&a, &c))
+}
+
+/// Compute the public key for `secret`
+pub fn public_key(secret: &[u8; KEY_SIZE]) -> [u8; KEY_SIZE] {
+    x25519(secret, &BASE_POINT)
+}
+
+/// Compute the shared secret between our `secret` and their `public`
+pub fn shared_secret(secret: &[u8; KEY_SIZE], their_public: &[u8; KEY_SIZE]) -> [u8; KEY_SIZE] {
+    x25519(secret, their_public)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_hex;
+
+    // RFC 7748 section 5.2
+    #[test]
+    fn rfc7748_vectors() {
+        let vectors = [
+            (
+                "a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4",
+                "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c",
+                "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552",
+            ),
+            (
+                "4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d",
+                "e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493",
+                "95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957",
+            ),
+        ];
+
+        for (scalar, point, expected) in vectors {
+            assert_eq!(
+                x25519(&from_hex(scalar), &from_hex(point)),
+                from_hex::<KEY_SIZE>(expected)
+            );
+        }
+    }
+
+    // RFC 7748 section 5.2, iterating `k = x25519(k, u), u = old k`
+    #[test]
+    fn rfc7748_iterated() {
+        let mut k = BASE_POINT;
+        let mut u = BASE_POINT;
+
+        for i in 1..=1000 {
+            let result = x25519(&k, &u);
+            u = k;
+            k = result;
+
+            if i == 1 {
+                assert_eq!(
+                    k,
+                    from_hex::<KEY_SIZE>(
+                        "422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079"
+                    )
+                );
+            }
+        }
+        assert_eq!(
+            k,
+            from_hex::<KEY_SIZE>(
+                "684cf59ba83309552800ef566f2f4d3c1c3887c49360e3875f2eb94d99532c51"
+            )
+        );
+    }
+
+    // RFC 7748 section 6.1
+    #[test]
+    fn rfc7748_diffie_hellman() {
+        let alice_secret =
+            from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob_secret =
+            from_hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+
+        let alice_public = public_key(&alice_secret);
+        let bob_public = public_key(&bob_secret);
+        assert_eq!(
+            alice_public,
+            from_hex::<KEY_SIZE>(
+                "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+            )
+        );
+        assert_eq!(
+            bob_public,
+            from_hex::<KEY_SIZE>(
+                "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"
+            )
+        );
+
+        let shared = from_hex::<KEY_SIZE>(
+            "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742",
+        );
+        assert_eq!(shared_secret(&alice_secret, &bob_public), shared);
+        assert_eq!(shared_secret(&bob_secret, &alice_public), shared);
+    }
+}
@@ -0,0 +1,614 @@
+//! FAT filesystem consistency checker
+//!
+//! The checker follows the cluster chains of all the files and directories from the root directory,
+//! and compares them with the FAT, see [`Problem`] for what is detected.
+//!
+//! Repairing only fixes the free map, i.e. the orphaned clusters are freed (in all the FAT copies)
+//! and the free clusters count in the FAT32 `FSInfo` sector is corrected.
+//! Other problems are only reported.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+/// Bit of the second FAT entry of FAT16, set if the filesystem was unmounted cleanly
+pub const FAT16_CLEAN_SHUTDOWN: u32 = 0x8000;
+/// Bit of the second FAT entry of FAT32, set if the filesystem was unmounted cleanly
+pub const FAT32_CLEAN_SHUTDOWN: u32 = 0x0800_0000;
+
+const DIRECTORY_ENTRY_SIZE: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+/// Directories deeper than this are not checked, so a directory loop doesn't recurse forever
+const MAX_DEPTH: usize = 64;
+
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+const FS_INFO_FREE_COUNT_OFFSET: usize = 488;
+const FS_INFO_NEXT_FREE_OFFSET: usize = 492;
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The storage containing the filesystem, i.e. a partition
+pub trait Volume {
+    type Error: fmt::Debug;
+
+    /// Read `buf.len()` bytes at `offset` from the start of the volume,
+    /// the offset and length are always multiples of `512`
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Self::Error>;
+    /// Write `buf` at `offset` from the start of the volume,
+    /// the offset and length are always multiples of `512`
+    fn write(&mut self, offset: u64, buf: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Volume(E),
+    InvalidBootSector,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// The mask of the clean shutdown bit in the second FAT entry, `None` for FAT12 as it doesn't have it
+    pub fn clean_shutdown_bit(self) -> Option<u32> {
+        match self {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some(FAT16_CLEAN_SHUTDOWN),
+            FatType::Fat32 => Some(FAT32_CLEAN_SHUTDOWN),
+        }
+    }
+
+    fn bad_cluster(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF7,
+            FatType::Fat16 => 0xFFF7,
+            FatType::Fat32 => 0x0FFF_FFF7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The chain of `path` points to a free, bad or out of range cluster, after `cluster`
+    InvalidChain { path: String, cluster: u32 },
+    /// `cluster` is used by `path` and another file before it
+    CrossLinked { path: String, cluster: u32 },
+    /// The size of `path` needs `expected` clusters, but its chain has `actual`
+    SizeMismatch {
+        path: String,
+        expected: u32,
+        actual: u32,
+    },
+    /// `count` clusters are allocated in the FAT, but not used by any file
+    Orphaned { count: u32 },
+    /// The `FSInfo` sector has `recorded` free clusters, but there are `actual`
+    FreeCountMismatch { recorded: u32, actual: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::InvalidChain { path, cluster } => {
+                write!(f, "{path}: invalid cluster chain after cluster {cluster}")
+            }
+            Problem::CrossLinked { path, cluster } => {
+                write!(f, "{path}: cluster {cluster} is used by another file")
+            }
+            Problem::SizeMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{path}: size needs {expected} clusters, but the chain has {actual}"
+            ),
+            Problem::Orphaned { count } => {
+                write!(f, "{count} clusters are allocated but not used")
+            }
+            Problem::FreeCountMismatch { recorded, actual } => write!(
+                f,
+                "FSInfo free clusters count is {recorded}, should be {actual}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub fat_type: FatType,
+    pub bytes_per_cluster: u32,
+    pub clusters: u32,
+    /// Free clusters, after the repair if it was done
+    pub free_clusters: u32,
+    pub files: u32,
+    pub directories: u32,
+    pub problems: Vec<Problem>,
+    /// Whether the free map was written back to the volume
+    pub repaired: bool,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// The values we need from the boot sector
+#[derive(Debug, Clone, Copy)]
+struct BootSector {
+    fat_type: FatType,
+    bytes_per_sector: u32,
+    sectors_per_cluster: u32,
+    reserved_sectors: u32,
+    number_of_fats: u32,
+    root_entry_count: u32,
+    fat_size: u32,
+    root_cluster: u32,
+    fs_info_sector: u32,
+    clusters_count: u32,
+}
+
+impl BootSector {
+    fn parse(sector: &[u8]) -> Option<Self> {
+        if sector.len() < 512 || u16_at(sector, 510) != 0xAA55 {
+            return None;
+        }
+
+        let bytes_per_sector = u16_at(sector, 11) as u32;
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = u16_at(sector, 14) as u32;
+        let number_of_fats = sector[16] as u32;
+        let root_entry_count = u16_at(sector, 17) as u32;
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32),
+            n => n as u32,
+        };
+        let fat_size_16 = u16_at(sector, 22) as u32;
+        let fat_size = match fat_size_16 {
+            0 => u32_at(sector, 36),
+            n => n,
+        };
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || number_of_fats == 0
+            || fat_size == 0
+        {
+            return None;
+        }
+
+        let root_dir_sectors =
+            (root_entry_count * DIRECTORY_ENTRY_SIZE as u32).div_ceil(bytes_per_sector);
+        let data_start = reserved_sectors + number_of_fats * fat_size + root_dir_sectors;
+        let clusters_count = total_sectors.checked_sub(data_start)? / sectors_per_cluster;
+
+        // same as the kernel driver
+        let fat_type = match total_sectors / sectors_per_cluster {
+            _ if fat_size_16 == 0 => FatType::Fat32,
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+
+        let (root_cluster, fs_info_sector) = if fat_type == FatType::Fat32 {
+            (u32_at(sector, 44), u16_at(sector, 48) as u32)
+        } else {
+            (0, 0)
+        };
+
+        Some(Self {
+            fat_type,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            number_of_fats,
+            root_entry_count,
+            fat_size,
+            root_cluster,
+            fs_info_sector,
+            clusters_count,
+        })
+    }
+
+    fn bytes_per_cluster(&self) -> u32 {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    fn fat_offset(&self, copy: u32) -> u64 {
+        (self.reserved_sectors + copy * self.fat_size) as u64 * self.bytes_per_sector as u64
+    }
+
+    fn root_dir_offset(&self) -> u64 {
+        self.fat_offset(self.number_of_fats)
+    }
+
+    fn root_dir_size(&self) -> usize {
+        let sectors =
+            (self.root_entry_count * DIRECTORY_ENTRY_SIZE as u32).div_ceil(self.bytes_per_sector);
+        (sectors * self.bytes_per_sector) as usize
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.root_dir_offset()
+            + self.root_dir_size() as u64
+            + (cluster - 2) as u64 * self.bytes_per_cluster() as u64
+    }
+}
+
+/// Get the raw value of the FAT entry of `cluster`
+fn fat_entry(fat_type: FatType, fat: &[u8], cluster: u32) -> u32 {
+    match fat_type {
+        FatType::Fat12 => {
+            let offset = (cluster * 3 / 2) as usize;
+            let value = u16_at(fat, offset) as u32;
+            if cluster & 1 == 1 {
+                value >> 4
+            } else {
+                value & 0xFFF
+            }
+        }
+        FatType::Fat16 => u16_at(fat, cluster as usize * 2) as u32,
+        FatType::Fat32 => u32_at(fat, cluster as usize * 4) & 0x0FFF_FFFF,
+    }
+}
+
+fn set_fat_entry(fat_type: FatType, fat: &mut [u8], cluster: u32, value: u32) {
+    match fat_type {
+        FatType::Fat12 => {
+            let offset = (cluster * 3 / 2) as usize;
+            let old = u16_at(fat, offset);
+            let new = if cluster & 1 == 1 {
+                (old & 0x000F) | ((value as u16) << 4)
+            } else {
+                (old & 0xF000) | (value as u16 & 0x0FFF)
+            };
+            fat[offset..offset + 2].copy_from_slice(&new.to_le_bytes());
+        }
+        FatType::Fat16 => {
+            let offset = cluster as usize * 2;
+            fat[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+        }
+        FatType::Fat32 => {
+            // the top 4 bits are reserved, keep them
+            let offset = cluster as usize * 4;
+            let value = (u32_at(fat, offset) & 0xF000_0000) | (value & 0x0FFF_FFFF);
+            fat[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Get the name of a directory entry, from the long name entries before it if there are any
+fn entry_name(entry: &[u8], long_name: &mut Vec<(u8, [u16; 13])>) -> String {
+    let mut name = String::new();
+    if !long_name.is_empty() {
+        long_name.sort_by_key(|(seq, _)| *seq);
+        let chars = long_name
+            .iter()
+            .flat_map(|(_, chars)| chars.iter().copied())
+            .take_while(|&c| c != 0 && c != 0xFFFF);
+        name.extend(char::decode_utf16(chars).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+        long_name.clear();
+        return name;
+    }
+
+    let base = &entry[0..8];
+    let ext = &entry[8..11];
+    let trim = |s: &[u8]| {
+        let len = s.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        String::from_utf8_lossy(&s[..len]).into_owned()
+    };
+    name.push_str(&trim(base));
+    let ext = trim(ext);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+fn long_name_part(entry: &[u8]) -> (u8, [u16; 13]) {
+    let mut chars = [0; 13];
+    let offsets = (1..11)
+        .step_by(2)
+        .chain((14..26).step_by(2))
+        .chain((28..32).step_by(2));
+    for (c, offset) in chars.iter_mut().zip(offsets) {
+        *c = u16_at(entry, offset);
+    }
+    (entry[0] & 0x1F, chars)
+}
+
+struct Checker<'a, V: Volume> {
+    volume: &'a mut V,
+    boot: BootSector,
+    /// The first FAT copy
+    fat: Vec<u8>,
+    /// One bit per cluster, set if it's used by a file or directory
+    used: Vec<u64>,
+    report: Report,
+}
+
+impl<V: Volume> Checker<'_, V> {
+    fn read(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, Error<V::Error>> {
+        let mut buf = vec![0; len];
+        self.volume.read(offset, &mut buf).map_err(Error::Volume)?;
+        Ok(buf)
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.boot.clusters_count + 2).contains(&cluster)
+    }
+
+    /// Mark `cluster` as used, returns `false` if it was already used
+    fn mark_used(&mut self, cluster: u32) -> bool {
+        let (index, bit) = (cluster as usize / 64, cluster % 64);
+        let was_used = self.used[index] & (1 << bit) != 0;
+        self.used[index] |= 1 << bit;
+        !was_used
+    }
+
+    fn is_used(&self, cluster: u32) -> bool {
+        self.used[cluster as usize / 64] & (1 << (cluster % 64)) != 0
+    }
+
+    /// Follow the chain starting at `first`, marking the clusters as used,
+    /// stops at the first invalid or cross-linked cluster (after reporting it)
+    ///
+    /// Returns the valid part of the chain, and whether the whole chain is valid
+    fn follow_chain(&mut self, path: &str, first: u32) -> (Vec<u32>, bool) {
+        let mut chain = Vec::new();
+        let mut valid = true;
+        let mut cluster = first;
+        let mut previous = 0;
+        loop {
+            if !self.is_valid_cluster(cluster) {
+                self.report.problems.push(Problem::InvalidChain {
+                    path: path.into(),
+                    cluster: previous,
+                });
+                valid = false;
+                break;
+            }
+            if !self.mark_used(cluster) {
+                self.report.problems.push(Problem::CrossLinked {
+                    path: path.into(),
+                    cluster,
+                });
+                valid = false;
+                break;
+            }
+            chain.push(cluster);
+
+            let next = fat_entry(self.boot.fat_type, &self.fat, cluster);
+            if next > self.boot.fat_type.bad_cluster() {
+                // end of chain
+                break;
+            }
+            previous = cluster;
+            cluster = next;
+        }
+        (chain, valid)
+    }
+
+    fn check_directory(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        depth: usize,
+    ) -> Result<(), Error<V::Error>> {
+        let mut long_name = Vec::new();
+        for entry in data.chunks_exact(DIRECTORY_ENTRY_SIZE) {
+            match entry[0] {
+                0x00 => break,
+                0xE5 => {
+                    long_name.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            let attributes = entry[11];
+            if attributes & ATTR_LONG_NAME == ATTR_LONG_NAME {
+                long_name.push(long_name_part(entry));
+                continue;
+            }
+            if attributes & ATTR_VOLUME_ID != 0 || entry[0] == b'.' {
+                long_name.clear();
+                continue;
+            }
+
+            let name = entry_name(entry, &mut long_name);
+            let mut entry_path = String::from(path);
+            if !entry_path.ends_with('/') {
+                entry_path.push('/');
+            }
+            entry_path.push_str(&name);
+
+            let first_cluster = ((u16_at(entry, 20) as u32) << 16) | u16_at(entry, 26) as u32;
+            let size = u32_at(entry, 28);
+
+            if attributes & ATTR_DIRECTORY != 0 {
+                self.report.directories += 1;
+                if first_cluster == 0 {
+                    self.report.problems.push(Problem::InvalidChain {
+                        path: entry_path,
+                        cluster: 0,
+                    });
+                    continue;
+                }
+                let (chain, _) = self.follow_chain(&entry_path, first_cluster);
+                if depth < MAX_DEPTH {
+                    let data = self.read_chain(&chain)?;
+                    self.check_directory(&entry_path, &data, depth + 1)?;
+                }
+            } else {
+                self.report.files += 1;
+                let expected = size.div_ceil(self.boot.bytes_per_cluster());
+                let (actual, valid) = if first_cluster == 0 {
+                    (0, true)
+                } else {
+                    let (chain, valid) = self.follow_chain(&entry_path, first_cluster);
+                    (chain.len() as u32, valid)
+                };
+                // empty files may keep one cluster
+                if valid && actual != expected && !(expected == 0 && actual == 1) {
+                    self.report.problems.push(Problem::SizeMismatch {
+                        path: entry_path,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_chain(&mut self, chain: &[u32]) -> Result<Vec<u8>, Error<V::Error>> {
+        let cluster_size = self.boot.bytes_per_cluster() as usize;
+        let mut data = vec![0; chain.len() * cluster_size];
+        for (&cluster, buf) in chain.iter().zip(data.chunks_exact_mut(cluster_size)) {
+            let offset = self.boot.cluster_offset(cluster);
+            self.volume.read(offset, buf).map_err(Error::Volume)?;
+        }
+        Ok(data)
+    }
+
+    fn check_fs_info(&mut self, repair: bool) -> Result<(), Error<V::Error>> {
+        if self.boot.fat_type != FatType::Fat32 || self.boot.fs_info_sector == 0 {
+            return Ok(());
+        }
+        let offset = self.boot.fs_info_sector as u64 * self.boot.bytes_per_sector as u64;
+        let mut sector = self.read(offset, self.boot.bytes_per_sector as usize)?;
+        if u32_at(&sector, 0) != FS_INFO_LEAD_SIGNATURE
+            || u32_at(&sector, 484) != FS_INFO_STRUCT_SIGNATURE
+            || u32_at(&sector, 508) != FS_INFO_TRAIL_SIGNATURE
+        {
+            return Ok(());
+        }
+
+        let recorded = u32_at(&sector, FS_INFO_FREE_COUNT_OFFSET);
+        let actual = self.report.free_clusters;
+        if recorded == FS_INFO_UNKNOWN || recorded == actual {
+            return Ok(());
+        }
+        self.report
+            .problems
+            .push(Problem::FreeCountMismatch { recorded, actual });
+
+        if repair {
+            sector[FS_INFO_FREE_COUNT_OFFSET..][..4].copy_from_slice(&actual.to_le_bytes());
+            sector[FS_INFO_NEXT_FREE_OFFSET..][..4].copy_from_slice(&FS_INFO_UNKNOWN.to_le_bytes());
+            self.volume.write(offset, &sector).map_err(Error::Volume)?;
+            self.report.repaired = true;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, repair: bool) -> Result<(), Error<V::Error>> {
+        match self.boot.fat_type {
+            FatType::Fat32 => {
+                self.report.directories += 1;
+                let (chain, _) = self.follow_chain("/", self.boot.root_cluster);
+                let data = self.read_chain(&chain)?;
+                self.check_directory("/", &data, 0)?;
+            }
+            FatType::Fat12 | FatType::Fat16 => {
+                self.report.directories += 1;
+                let data = self.read(self.boot.root_dir_offset(), self.boot.root_dir_size())?;
+                self.check_directory("/", &data, 0)?;
+            }
+        }
+
+        let fat_type = self.boot.fat_type;
+        let mut orphaned = 0;
+        let mut free = 0;
+        for cluster in 2..self.boot.clusters_count + 2 {
+            let entry = fat_entry(fat_type, &self.fat, cluster);
+            if entry == 0 {
+                free += 1;
+            } else if entry != fat_type.bad_cluster() && !self.is_used(cluster) {
+                orphaned += 1;
+                if repair {
+                    set_fat_entry(fat_type, &mut self.fat, cluster, 0);
+                    free += 1;
+                }
+            }
+        }
+        self.report.free_clusters = free;
+
+        if orphaned != 0 {
+            self.report
+                .problems
+                .push(Problem::Orphaned { count: orphaned });
+            if repair {
+                let fat = core::mem::take(&mut self.fat);
+                for copy in 0..self.boot.number_of_fats {
+                    let offset = self.boot.fat_offset(copy);
+                    self.volume.write(offset, &fat).map_err(Error::Volume)?;
+                }
+                self.fat = fat;
+                self.report.repaired = true;
+            }
+        }
+
+        self.check_fs_info(repair)
+    }
+}
+
+/// Check the filesystem in `volume`, and repair the free map if `repair` is set
+pub fn check<V: Volume>(volume: &mut V, repair: bool) -> Result<Report, Error<V::Error>> {
+    let mut sector = [0; 512];
+    volume.read(0, &mut sector).map_err(Error::Volume)?;
+    let boot = BootSector::parse(&sector).ok_or(Error::InvalidBootSector)?;
+
+    let mut fat = vec![0; (boot.fat_size * boot.bytes_per_sector) as usize];
+    volume
+        .read(boot.fat_offset(0), &mut fat)
+        .map_err(Error::Volume)?;
+
+    // the FAT must cover all the clusters
+    let fat_entries = match boot.fat_type {
+        FatType::Fat12 => fat.len() * 2 / 3,
+        FatType::Fat16 => fat.len() / 2,
+        FatType::Fat32 => fat.len() / 4,
+    };
+    if fat_entries < boot.clusters_count as usize + 2 {
+        return Err(Error::InvalidBootSector);
+    }
+
+    let mut checker = Checker {
+        volume,
+        boot,
+        fat,
+        used: vec![0; (boot.clusters_count as usize + 2).div_ceil(64)],
+        report: Report {
+            fat_type: boot.fat_type,
+            bytes_per_cluster: boot.bytes_per_cluster(),
+            clusters: boot.clusters_count,
+            free_clusters: 0,
+            files: 0,
+            directories: 0,
+            problems: Vec::new(),
+            repaired: false,
+        },
+    };
+    checker.run(repair)?;
+    Ok(checker.report)
+}
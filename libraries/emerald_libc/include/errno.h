@@ -0,0 +1,24 @@
+#ifndef _EMERALD_ERRNO_H
+#define _EMERALD_ERRNO_H
+
+extern int errno;
+
+#define ENOENT 2
+#define EIO 5
+#define ENOEXEC 8
+#define EBADF 9
+#define ECHILD 10
+#define EAGAIN 11
+#define ENOMEM 12
+#define EBUSY 16
+#define EEXIST 17
+#define ENOTDIR 20
+#define EISDIR 21
+#define EINVAL 22
+#define ESPIPE 29
+#define ERANGE 34
+#define ENOSYS 38
+#define ENOTSUP 95
+#define ETIMEDOUT 110
+
+#endif
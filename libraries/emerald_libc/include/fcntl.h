@@ -0,0 +1,16 @@
+#ifndef _EMERALD_FCNTL_H
+#define _EMERALD_FCNTL_H
+
+#define O_RDONLY 0
+#define O_WRONLY 01
+#define O_RDWR 02
+#define O_ACCMODE 03
+#define O_CREAT 0100
+#define O_EXCL 0200
+#define O_TRUNC 01000
+#define O_APPEND 02000
+#define O_CLOEXEC 02000000
+
+int open(const char *path, int flags, ...);
+
+#endif
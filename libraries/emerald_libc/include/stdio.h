@@ -0,0 +1,20 @@
+#ifndef _EMERALD_STDIO_H
+#define _EMERALD_STDIO_H
+
+#include <stdarg.h>
+#include <stddef.h>
+
+#define EOF (-1)
+
+int printf(const char *fmt, ...);
+int vprintf(const char *fmt, va_list args);
+int dprintf(int fd, const char *fmt, ...);
+int vdprintf(int fd, const char *fmt, va_list args);
+int sprintf(char *buf, const char *fmt, ...);
+int vsprintf(char *buf, const char *fmt, va_list args);
+int snprintf(char *buf, size_t size, const char *fmt, ...);
+int vsnprintf(char *buf, size_t size, const char *fmt, va_list args);
+int putchar(int c);
+int puts(const char *s);
+
+#endif
@@ -0,0 +1,22 @@
+#ifndef _EMERALD_STDLIB_H
+#define _EMERALD_STDLIB_H
+
+#include <stddef.h>
+
+#define EXIT_SUCCESS 0
+#define EXIT_FAILURE 1
+
+void *malloc(size_t size);
+void *calloc(size_t count, size_t size);
+void *realloc(void *ptr, size_t size);
+void free(void *ptr);
+
+_Noreturn void exit(int status);
+_Noreturn void abort(void);
+char *getenv(const char *name);
+
+int abs(int value);
+int atoi(const char *s);
+long strtol(const char *s, char **end, int base);
+
+#endif
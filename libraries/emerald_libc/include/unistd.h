@@ -0,0 +1,23 @@
+#ifndef _EMERALD_UNISTD_H
+#define _EMERALD_UNISTD_H
+
+#include <stddef.h>
+
+typedef long ssize_t;
+typedef long off_t;
+
+#define STDIN_FILENO 0
+#define STDOUT_FILENO 1
+#define STDERR_FILENO 2
+
+#define SEEK_SET 0
+#define SEEK_CUR 1
+#define SEEK_END 2
+
+ssize_t read(int fd, void *buf, size_t count);
+ssize_t write(int fd, const void *buf, size_t count);
+off_t lseek(int fd, off_t offset, int whence);
+int close(int fd);
+_Noreturn void _exit(int status);
+
+#endif
@@ -0,0 +1,16 @@
+use core::ffi::{c_char, c_int};
+
+use crate::stdlib::exit;
+
+extern "C" {
+    fn main(argc: c_int, argv: *mut *mut c_char) -> c_int;
+}
+
+/// The entry point of the program.
+///
+/// The kernel passes `argc` and `argv` in registers as if `_start` was called normally with the
+/// `SYSV` calling convention, and aligns the stack accordingly, so no assembly is needed here.
+#[no_mangle]
+pub unsafe extern "C" fn _start(argc: c_int, argv: *mut *mut c_char) -> ! {
+    exit(main(argc, argv))
+}
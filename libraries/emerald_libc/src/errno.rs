@@ -0,0 +1,77 @@
+use core::ffi::c_int;
+
+use emerald_std::SyscallError;
+
+pub const EPERM: c_int = 1;
+pub const ENOENT: c_int = 2;
+pub const EIO: c_int = 5;
+pub const ENOEXEC: c_int = 8;
+pub const EBADF: c_int = 9;
+pub const ECHILD: c_int = 10;
+pub const EAGAIN: c_int = 11;
+pub const ENOMEM: c_int = 12;
+pub const EBUSY: c_int = 16;
+pub const EEXIST: c_int = 17;
+pub const ENODEV: c_int = 19;
+pub const ENOTDIR: c_int = 20;
+pub const EISDIR: c_int = 21;
+pub const EINVAL: c_int = 22;
+pub const ENOSPC: c_int = 28;
+pub const ESPIPE: c_int = 29;
+pub const ERANGE: c_int = 34;
+pub const ENOSYS: c_int = 38;
+pub const ENOTSUP: c_int = 95;
+pub const ETIMEDOUT: c_int = 110;
+
+#[no_mangle]
+pub static mut errno: c_int = 0;
+
+pub fn set_errno(value: c_int) {
+    unsafe { errno = value };
+}
+
+pub fn from_syscall_error(error: SyscallError) -> c_int {
+    match error {
+        SyscallError::SyscallNotFound => ENOSYS,
+        SyscallError::CouldNotOpenFile
+        | SyscallError::CouldNotWriteToFile
+        | SyscallError::CouldNotReadFromFile
+        | SyscallError::EndOfFile
+        | SyscallError::IoError => EIO,
+        SyscallError::InvalidFileIndex => EBADF,
+        SyscallError::CouldNotLoadElf(_) => ENOEXEC,
+        SyscallError::CouldNotAllocateProcess | SyscallError::HeapRangesExceeded => ENOMEM,
+        SyscallError::FileNotFound | SyscallError::InvalidPath => ENOENT,
+        SyscallError::PidNotFound => ECHILD,
+        SyscallError::ProcessStillRunning => EAGAIN,
+        SyscallError::IsNotDirectory => ENOTDIR,
+        SyscallError::IsDirectory => EISDIR,
+        SyscallError::BufferTooSmall(_) => ERANGE,
+        SyscallError::NoSpace => ENOSPC,
+        SyscallError::NoDevice => ENODEV,
+        SyscallError::GraphicsNotAvailable
+        | SyscallError::GraphicsAlreadyTaken
+        | SyscallError::GraphicsNotOwned
+        | SyscallError::Busy => EBUSY,
+        SyscallError::AlreadyExists => EEXIST,
+        SyscallError::OperationNotSupported => ENOTSUP,
+        SyscallError::TimedOut => ETIMEDOUT,
+        SyscallError::NotSeekable => ESPIPE,
+        SyscallError::PermissionDenied => EPERM,
+        _ => EINVAL,
+    }
+}
+
+/// Convert a syscall result into the C convention, `-1` and setting `errno` on error
+pub fn syscall_result<T: TryInto<R>, R: From<i8>>(result: Result<T, SyscallError>) -> R {
+    match result {
+        Ok(value) => value.try_into().unwrap_or_else(|_| {
+            set_errno(ERANGE);
+            R::from(-1)
+        }),
+        Err(error) => {
+            set_errno(from_syscall_error(error));
+            R::from(-1)
+        }
+    }
+}
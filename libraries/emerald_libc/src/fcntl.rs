@@ -0,0 +1,39 @@
+use core::ffi::{c_char, c_int, CStr};
+
+use emerald_std::io::{syscall_open, OpenOptions};
+
+use crate::errno::{self, syscall_result, EINVAL};
+
+// same values as Linux, so that ported programs using hardcoded values still work
+pub const O_RDONLY: c_int = 0;
+pub const O_WRONLY: c_int = 1;
+pub const O_RDWR: c_int = 2;
+pub const O_ACCMODE: c_int = 3;
+pub const O_CREAT: c_int = 0o100;
+pub const O_EXCL: c_int = 0o200;
+pub const O_TRUNC: c_int = 0o1000;
+pub const O_APPEND: c_int = 0o2000;
+pub const O_CLOEXEC: c_int = 0o2000000;
+
+/// The `mode` argument is accepted but ignored, as we don't have permissions
+#[no_mangle]
+pub unsafe extern "C" fn open(path: *const c_char, flags: c_int, _mode: ...) -> c_int {
+    let mut options = OpenOptions::new();
+    match flags & O_ACCMODE {
+        O_RDONLY => options.read(true),
+        O_WRONLY => options.write(true),
+        O_RDWR => options.read(true).write(true),
+        _ => {
+            errno::set_errno(EINVAL);
+            return -1;
+        }
+    };
+    options
+        .create(flags & O_CREAT != 0)
+        .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0)
+        .truncate(flags & O_TRUNC != 0)
+        .append(flags & O_APPEND != 0)
+        .close_on_spawn(flags & O_CLOEXEC != 0);
+
+    syscall_result(syscall_open(CStr::from_ptr(path), options, 0))
+}
@@ -0,0 +1,44 @@
+//! A small C library for porting C programs to Emerald.
+//!
+//! This is built as a static library, and C programs are linked against it together with the
+//! headers in `include`. `memcpy`, `memmove`, `memset` and `memcmp` are not implemented here,
+//! they come from `compiler_builtins` which is bundled into the static library.
+#![no_std]
+#![feature(c_variadic)]
+#![feature(lang_items)]
+#![allow(internal_features)]
+// all the exported functions are C functions, their safety requirements are the same as in C
+#![allow(clippy::missing_safety_doc)]
+
+mod crt0;
+mod errno;
+mod fcntl;
+mod stdio;
+mod stdlib;
+mod string;
+mod unistd;
+
+use core::fmt::Write;
+
+use emerald_std::io::{syscall_write, FD_STDERR};
+
+struct StderrWriter;
+
+impl Write for StderrWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        unsafe { syscall_write(FD_STDERR, s.as_bytes()) }
+            .map(|_| ())
+            .map_err(|_| core::fmt::Error)
+    }
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let _ = writeln!(StderrWriter, "emerald_libc: {info}");
+    unsafe { emerald_std::process::exit(101) }
+}
+
+// we never unwind, this is only needed when not building with `panic=abort`, as the static
+// library is the final artifact from the Rust side
+#[lang = "eh_personality"]
+extern "C" fn eh_personality() {}
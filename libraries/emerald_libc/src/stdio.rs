@@ -0,0 +1,525 @@
+use core::{
+    ffi::{c_char, c_int, c_void, CStr, VaList, VaListImpl},
+    fmt::Write,
+};
+
+use emerald_std::io::{syscall_write, FD_STDOUT};
+
+use crate::errno::{self, from_syscall_error};
+
+/// A destination of formatted output
+trait Sink {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+/// Buffered writes to a file descriptor, flushed when full and at the end of formatting
+struct FdSink {
+    fd: usize,
+    buf: [u8; 256],
+    len: usize,
+    total: usize,
+    failed: bool,
+}
+
+impl FdSink {
+    fn new(fd: usize) -> Self {
+        Self {
+            fd,
+            buf: [0; 256],
+            len: 0,
+            total: 0,
+            failed: false,
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut data = &self.buf[..self.len];
+        while !data.is_empty() && !self.failed {
+            match unsafe { syscall_write(self.fd, data) } {
+                Ok(written) => data = &data[written as usize..],
+                Err(error) => {
+                    errno::set_errno(from_syscall_error(error));
+                    self.failed = true;
+                }
+            }
+        }
+        self.len = 0;
+    }
+}
+
+impl Sink for FdSink {
+    fn put(&mut self, mut bytes: &[u8]) {
+        self.total += bytes.len();
+        while !bytes.is_empty() {
+            if self.len == self.buf.len() {
+                self.flush();
+            }
+            let n = bytes.len().min(self.buf.len() - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+    }
+}
+
+/// Writes into a C buffer of `cap` bytes, keeping the last byte for the null terminator.
+/// `len` counts all the output, even the part that didn't fit
+struct BufSink {
+    buf: *mut u8,
+    cap: usize,
+    len: usize,
+}
+
+impl BufSink {
+    unsafe fn finish(self) -> c_int {
+        if self.cap > 0 {
+            *self.buf.add(self.len.min(self.cap - 1)) = 0;
+        }
+        self.len as c_int
+    }
+}
+
+impl Sink for BufSink {
+    fn put(&mut self, bytes: &[u8]) {
+        let room = self.cap.saturating_sub(1).saturating_sub(self.len);
+        let n = bytes.len().min(room);
+        if n > 0 {
+            unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.buf.add(self.len), n) };
+        }
+        self.len += bytes.len();
+    }
+}
+
+/// Fixed buffer for formatting numbers with [`core::fmt`], output that doesn't fit is dropped
+struct FmtBuf {
+    buf: [u8; 512],
+    len: usize,
+}
+
+impl FmtBuf {
+    fn new() -> Self {
+        Self {
+            buf: [0; 512],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for FmtBuf {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Write `prefix`, `zeros` zeros and then `body`, padded to the width
+    fn emit(&self, out: &mut impl Sink, prefix: &[u8], zeros: usize, body: &[u8]) {
+        let len = prefix.len() + zeros + body.len();
+        let pad = self.width.saturating_sub(len);
+        // zero padding is ignored with `-`, and for integers with a precision
+        let zero_pad = self.zero && !self.left;
+
+        if !self.left && !zero_pad {
+            pad_with(out, b' ', pad);
+        }
+        out.put(prefix);
+        pad_with(out, b'0', zeros + if zero_pad { pad } else { 0 });
+        out.put(body);
+        if self.left {
+            pad_with(out, b' ', pad);
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static [u8] {
+        if negative {
+            b"-"
+        } else if self.plus {
+            b"+"
+        } else if self.space {
+            b" "
+        } else {
+            b""
+        }
+    }
+}
+
+fn pad_with(out: &mut impl Sink, byte: u8, count: usize) {
+    let chunk = [byte; 16];
+    let mut count = count;
+    while count > 0 {
+        let n = count.min(chunk.len());
+        out.put(&chunk[..n]);
+        count -= n;
+    }
+}
+
+fn format_int(out: &mut impl Sink, spec: &mut Spec, value: u64, negative: bool, conv: u8) {
+    let (base, digits): (u64, &[u8]) = match conv {
+        b'o' => (8, b"01234567"),
+        b'x' | b'p' => (16, b"0123456789abcdef"),
+        b'X' => (16, b"0123456789ABCDEF"),
+        _ => (10, b"0123456789"),
+    };
+    let mut buf = [0u8; 24];
+    let mut i = buf.len();
+    let mut v = value;
+    while v != 0 {
+        i -= 1;
+        buf[i] = digits[(v % base) as usize];
+        v /= base;
+    }
+    let body = &buf[i..];
+
+    let mut zeros = match spec.precision {
+        Some(precision) => {
+            spec.zero = false;
+            precision.saturating_sub(body.len())
+        }
+        // a `0` value is printed as `0`, unless the precision is `0`
+        None if body.is_empty() => 1,
+        None => 0,
+    };
+
+    let prefix: &[u8] = match conv {
+        b'd' | b'i' => spec.sign(negative),
+        b'p' => b"0x",
+        b'x' if spec.alt && value != 0 => b"0x",
+        b'X' if spec.alt && value != 0 => b"0X",
+        b'o' if spec.alt && zeros == 0 && value != 0 => {
+            zeros = 1;
+            b""
+        }
+        _ => b"",
+    };
+    spec.emit(out, prefix, zeros, body);
+}
+
+/// Change the exponent from `core::fmt` format (`1.5e3`) to C format (`1.5e+03`)
+fn c_exponent(formatted: &[u8], upper: bool, out: &mut FmtBuf) -> i32 {
+    let e = formatted.iter().position(|&b| b == b'e').unwrap();
+    let exp: i32 = core::str::from_utf8(&formatted[e + 1..])
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mantissa = core::str::from_utf8(&formatted[..e]).unwrap();
+    let _ = write!(
+        out,
+        "{mantissa}{}{}{:02}",
+        if upper { 'E' } else { 'e' },
+        if exp < 0 { '-' } else { '+' },
+        exp.unsigned_abs()
+    );
+    exp
+}
+
+/// Remove trailing zeros after the decimal point (and the point itself), for `%g`
+fn trim_fraction(body: &[u8]) -> &[u8] {
+    if !body.contains(&b'.') {
+        return body;
+    }
+    let mut end = body.len();
+    while body[end - 1] == b'0' {
+        end -= 1;
+    }
+    if body[end - 1] == b'.' {
+        end -= 1;
+    }
+    &body[..end]
+}
+
+fn format_float(out: &mut impl Sink, spec: &mut Spec, value: f64, conv: u8) {
+    let upper = conv.is_ascii_uppercase();
+    let prefix = spec.sign(value.is_sign_negative());
+    // `abs` is not available in `core`
+    let value = f64::from_bits(value.to_bits() & !(1 << 63));
+
+    if !value.is_finite() {
+        spec.zero = false;
+        let body: &[u8] = match (value.is_nan(), upper) {
+            (true, false) => b"nan",
+            (true, true) => b"NAN",
+            (false, false) => b"inf",
+            (false, true) => b"INF",
+        };
+        spec.emit(out, prefix, 0, body);
+        return;
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    let mut body = FmtBuf::new();
+    match conv.to_ascii_lowercase() {
+        b'f' => {
+            let _ = write!(body, "{value:.precision$}");
+        }
+        b'e' => {
+            let mut tmp = FmtBuf::new();
+            let _ = write!(tmp, "{value:.precision$e}");
+            c_exponent(tmp.as_bytes(), upper, &mut body);
+        }
+        _ => {
+            // `%g`, use `%e` if the exponent is less than -4 or not less than the precision
+            let precision = precision.max(1);
+            let mut tmp = FmtBuf::new();
+            let _ = write!(tmp, "{value:.*e}", precision - 1);
+            let mut e_body = FmtBuf::new();
+            let exp = c_exponent(tmp.as_bytes(), upper, &mut e_body);
+            if exp < -4 || exp >= precision as i32 {
+                if spec.alt {
+                    body = e_body;
+                } else {
+                    let e = e_body
+                        .as_bytes()
+                        .iter()
+                        .position(|&b| b == b'e' || b == b'E');
+                    let (mantissa, exponent) = e_body.as_bytes().split_at(e.unwrap());
+                    for part in [trim_fraction(mantissa), exponent] {
+                        let _ = body.write_str(core::str::from_utf8(part).unwrap());
+                    }
+                }
+            } else {
+                let precision = (precision as i32 - 1 - exp) as usize;
+                let _ = write!(body, "{value:.precision$}");
+                if !spec.alt {
+                    body.len = trim_fraction(body.as_bytes()).len();
+                }
+            }
+        }
+    }
+    spec.emit(out, prefix, 0, body.as_bytes());
+}
+
+/// Format `fmt` with the C `printf` rules into `out`.
+///
+/// Supports the flags `-+ #0`, width and precision (including `*`), the length modifiers
+/// `hh h l ll z j t`, and the conversions `d i u o x X c s p f F e E g G %`.
+unsafe fn format(out: &mut impl Sink, fmt: *const c_char, args: &mut VaListImpl<'_>) {
+    let mut p = fmt as *const u8;
+    loop {
+        let start = p;
+        while *p != 0 && *p != b'%' {
+            p = p.add(1);
+        }
+        out.put(core::slice::from_raw_parts(
+            start,
+            p.offset_from(start) as usize,
+        ));
+        if *p == 0 {
+            return;
+        }
+        p = p.add(1);
+
+        let mut spec = Spec::default();
+        loop {
+            match *p {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                _ => break,
+            }
+            p = p.add(1);
+        }
+
+        if *p == b'*' {
+            let width = args.arg::<c_int>();
+            // a negative width is taken as the `-` flag
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+            p = p.add(1);
+        } else {
+            while (*p).is_ascii_digit() {
+                spec.width = spec.width * 10 + (*p - b'0') as usize;
+                p = p.add(1);
+            }
+        }
+
+        if *p == b'.' {
+            p = p.add(1);
+            if *p == b'*' {
+                let precision = args.arg::<c_int>();
+                // a negative precision is taken as if it was omitted
+                spec.precision = usize::try_from(precision).ok();
+                p = p.add(1);
+            } else {
+                let mut precision = 0;
+                while (*p).is_ascii_digit() {
+                    precision = precision * 10 + (*p - b'0') as usize;
+                    p = p.add(1);
+                }
+                spec.precision = Some(precision);
+            }
+        }
+
+        // `hh` and `h` are promoted to `int` anyway, and `ll`, `z`, `j` and `t` are all 64 bit
+        let mut long = false;
+        while matches!(*p, b'h' | b'l' | b'z' | b'j' | b't' | b'L') {
+            long |= *p != b'h';
+            p = p.add(1);
+        }
+
+        let conv = *p;
+        if conv == 0 {
+            return;
+        }
+        p = p.add(1);
+
+        match conv {
+            b'd' | b'i' => {
+                let value = if long {
+                    args.arg::<i64>()
+                } else {
+                    args.arg::<c_int>() as i64
+                };
+                format_int(out, &mut spec, value.unsigned_abs(), value < 0, conv);
+            }
+            b'u' | b'o' | b'x' | b'X' => {
+                let value = if long {
+                    args.arg::<u64>()
+                } else {
+                    args.arg::<u32>() as u64
+                };
+                format_int(out, &mut spec, value, false, conv);
+            }
+            b'p' => {
+                let value = args.arg::<*const c_void>() as u64;
+                format_int(out, &mut spec, value, false, conv);
+            }
+            b'c' => {
+                spec.zero = false;
+                spec.emit(out, b"", 0, &[args.arg::<c_int>() as u8]);
+            }
+            b's' => {
+                let s = args.arg::<*const c_char>();
+                let s = if s.is_null() {
+                    b"(null)"
+                } else {
+                    let len = match spec.precision {
+                        Some(precision) => crate::string::strnlen(s, precision),
+                        None => crate::string::strlen(s),
+                    };
+                    core::slice::from_raw_parts(s as *const u8, len)
+                };
+                spec.zero = false;
+                spec.emit(out, b"", 0, s);
+            }
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                format_float(out, &mut spec, args.arg::<f64>(), conv);
+            }
+            b'%' => out.put(b"%"),
+            // unknown conversion, print it as is
+            _ => out.put(&[b'%', conv]),
+        }
+    }
+}
+
+unsafe fn fd_printf(fd: usize, fmt: *const c_char, args: &mut VaListImpl<'_>) -> c_int {
+    let mut out = FdSink::new(fd);
+    format(&mut out, fmt, args);
+    out.flush();
+    if out.failed {
+        -1
+    } else {
+        out.total as c_int
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn printf(fmt: *const c_char, mut args: ...) -> c_int {
+    fd_printf(FD_STDOUT, fmt, &mut args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vprintf(fmt: *const c_char, mut args: VaList) -> c_int {
+    fd_printf(FD_STDOUT, fmt, &mut args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dprintf(fd: c_int, fmt: *const c_char, mut args: ...) -> c_int {
+    fd_printf(fd as usize, fmt, &mut args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vdprintf(fd: c_int, fmt: *const c_char, mut args: VaList) -> c_int {
+    fd_printf(fd as usize, fmt, &mut args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sprintf(buf: *mut c_char, fmt: *const c_char, mut args: ...) -> c_int {
+    vsnprintf(buf, usize::MAX, fmt, args.as_va_list())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn snprintf(
+    buf: *mut c_char,
+    size: usize,
+    fmt: *const c_char,
+    mut args: ...
+) -> c_int {
+    vsnprintf(buf, size, fmt, args.as_va_list())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vsprintf(buf: *mut c_char, fmt: *const c_char, args: VaList) -> c_int {
+    vsnprintf(buf, usize::MAX, fmt, args)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vsnprintf(
+    buf: *mut c_char,
+    size: usize,
+    fmt: *const c_char,
+    mut args: VaList,
+) -> c_int {
+    let mut out = BufSink {
+        buf: buf as *mut u8,
+        cap: size,
+        len: 0,
+    };
+    format(&mut out, fmt, &mut args);
+    out.finish()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn putchar(c: c_int) -> c_int {
+    let mut out = FdSink::new(FD_STDOUT);
+    out.put(&[c as u8]);
+    out.flush();
+    if out.failed {
+        -1
+    } else {
+        c as u8 as c_int
+    }
+}
+
+/// Write `s` and a newline to stdout
+#[no_mangle]
+pub unsafe extern "C" fn puts(s: *const c_char) -> c_int {
+    let mut out = FdSink::new(FD_STDOUT);
+    out.put(CStr::from_ptr(s).to_bytes());
+    out.put(b"\n");
+    out.flush();
+    if out.failed {
+        -1
+    } else {
+        0
+    }
+}
@@ -0,0 +1,148 @@
+use core::{
+    alloc::Layout,
+    ffi::{c_char, c_int, c_void},
+    ptr,
+};
+
+use emerald_std::alloc::{alloc, alloc_zeroed, dealloc, realloc as alloc_realloc};
+
+use crate::errno::{self, ENOMEM};
+
+/// Alignment of all allocations, the same as `max_align_t`.
+/// The size of the allocation is stored in the first bytes, before the returned pointer
+const ALLOC_ALIGN: usize = 16;
+
+fn alloc_layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(ALLOC_ALIGN)?, ALLOC_ALIGN).ok()
+}
+
+unsafe fn alloc_with(size: usize, alloc_fn: unsafe fn(Layout) -> *mut u8) -> *mut c_void {
+    let Some(layout) = alloc_layout(size) else {
+        errno::set_errno(ENOMEM);
+        return ptr::null_mut();
+    };
+    let block = alloc_fn(layout);
+    if block.is_null() {
+        errno::set_errno(ENOMEM);
+        return ptr::null_mut();
+    }
+    (block as *mut usize).write(size);
+    block.add(ALLOC_ALIGN) as *mut c_void
+}
+
+/// Get the start of the allocated block and its original size
+unsafe fn block_of(ptr: *mut c_void) -> (*mut u8, usize) {
+    let block = (ptr as *mut u8).sub(ALLOC_ALIGN);
+    (block, (block as *mut usize).read())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn malloc(size: usize) -> *mut c_void {
+    alloc_with(size, alloc)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn calloc(count: usize, size: usize) -> *mut c_void {
+    let Some(size) = count.checked_mul(size) else {
+        errno::set_errno(ENOMEM);
+        return ptr::null_mut();
+    };
+    alloc_with(size, alloc_zeroed)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return malloc(size);
+    }
+    let (block, old_size) = block_of(ptr);
+    let Some(new_layout) = alloc_layout(size) else {
+        errno::set_errno(ENOMEM);
+        return ptr::null_mut();
+    };
+    let new_block = alloc_realloc(block, alloc_layout(old_size).unwrap(), new_layout.size());
+    if new_block.is_null() {
+        errno::set_errno(ENOMEM);
+        return ptr::null_mut();
+    }
+    (new_block as *mut usize).write(size);
+    new_block.add(ALLOC_ALIGN) as *mut c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let (block, size) = block_of(ptr);
+    dealloc(block, alloc_layout(size).unwrap());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn exit(status: c_int) -> ! {
+    // nothing is buffered, so nothing to flush
+    emerald_std::process::exit(status)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn abort() -> ! {
+    emerald_std::process::exit(134)
+}
+
+/// There are no environment variables, so this always returns `NULL`
+#[no_mangle]
+pub unsafe extern "C" fn getenv(_name: *const c_char) -> *mut c_char {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn abs(value: c_int) -> c_int {
+    value.wrapping_abs()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn atoi(s: *const c_char) -> c_int {
+    strtol(s, ptr::null_mut(), 10) as c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strtol(s: *const c_char, end: *mut *mut c_char, base: c_int) -> i64 {
+    let mut p = s as *const u8;
+    while matches!(*p, b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c') {
+        p = p.add(1);
+    }
+    let negative = *p == b'-';
+    if matches!(*p, b'-' | b'+') {
+        p = p.add(1);
+    }
+    let mut base = base as u32;
+    let has_hex_prefix = *p == b'0' && matches!(*p.add(1), b'x' | b'X');
+    if (base == 0 || base == 16) && has_hex_prefix && (*p.add(2) as char).is_ascii_hexdigit() {
+        p = p.add(2);
+        base = 16;
+    } else if base == 0 {
+        base = if *p == b'0' { 8 } else { 10 };
+    }
+
+    let digits_start = p;
+    let mut value: i64 = 0;
+    while let Some(digit) = (*p as char).to_digit(base) {
+        value = value
+            .saturating_mul(base as i64)
+            .saturating_add(digit as i64);
+        p = p.add(1);
+    }
+    if !end.is_null() {
+        *end = if p == digits_start {
+            s
+        } else {
+            p as *const c_char
+        } as *mut c_char;
+    }
+
+    if negative {
+        -value
+    } else {
+        value
+    }
+}
@@ -0,0 +1,138 @@
+use core::{
+    ffi::{c_char, c_int, c_void},
+    ptr,
+};
+
+use crate::stdlib::malloc;
+
+#[no_mangle]
+pub unsafe extern "C" fn strlen(s: *const c_char) -> usize {
+    let mut len = 0;
+    while *s.add(len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strnlen(s: *const c_char, max_len: usize) -> usize {
+    let mut len = 0;
+    while len < max_len && *s.add(len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strcmp(a: *const c_char, b: *const c_char) -> c_int {
+    strncmp(a, b, usize::MAX)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strncmp(a: *const c_char, b: *const c_char, n: usize) -> c_int {
+    for i in 0..n {
+        let (ca, cb) = (*a.add(i) as u8, *b.add(i) as u8);
+        if ca != cb || ca == 0 {
+            return ca as c_int - cb as c_int;
+        }
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strcpy(dst: *mut c_char, src: *const c_char) -> *mut c_char {
+    ptr::copy_nonoverlapping(src, dst, strlen(src) + 1);
+    dst
+}
+
+/// Copies at most `n` bytes, and pads the rest of `dst` with zeros, like in C this will not
+/// null terminate `dst` if `src` is `n` bytes or longer
+#[no_mangle]
+pub unsafe extern "C" fn strncpy(dst: *mut c_char, src: *const c_char, n: usize) -> *mut c_char {
+    let len = strnlen(src, n);
+    ptr::copy_nonoverlapping(src, dst, len);
+    ptr::write_bytes(dst.add(len), 0, n - len);
+    dst
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strcat(dst: *mut c_char, src: *const c_char) -> *mut c_char {
+    strcpy(dst.add(strlen(dst)), src);
+    dst
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strncat(dst: *mut c_char, src: *const c_char, n: usize) -> *mut c_char {
+    let end = dst.add(strlen(dst));
+    let len = strnlen(src, n);
+    ptr::copy_nonoverlapping(src, end, len);
+    *end.add(len) = 0;
+    dst
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strchr(s: *const c_char, c: c_int) -> *mut c_char {
+    let c = c as c_char;
+    let mut p = s;
+    loop {
+        if *p == c {
+            return p as *mut c_char;
+        }
+        if *p == 0 {
+            return ptr::null_mut();
+        }
+        p = p.add(1);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strrchr(s: *const c_char, c: c_int) -> *mut c_char {
+    let c = c as c_char;
+    let mut found = ptr::null_mut();
+    let mut p = s;
+    loop {
+        if *p == c {
+            found = p as *mut c_char;
+        }
+        if *p == 0 {
+            return found;
+        }
+        p = p.add(1);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strstr(haystack: *const c_char, needle: *const c_char) -> *mut c_char {
+    let needle_len = strlen(needle);
+    let mut p = haystack;
+    loop {
+        if strncmp(p, needle, needle_len) == 0 {
+            return p as *mut c_char;
+        }
+        if *p == 0 {
+            return ptr::null_mut();
+        }
+        p = p.add(1);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn strdup(s: *const c_char) -> *mut c_char {
+    let len = strlen(s);
+    let new = malloc(len + 1) as *mut c_char;
+    if !new.is_null() {
+        ptr::copy_nonoverlapping(s, new, len + 1);
+    }
+    new
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memchr(s: *const c_void, c: c_int, n: usize) -> *mut c_void {
+    let s = s as *const u8;
+    for i in 0..n {
+        if *s.add(i) == c as u8 {
+            return s.add(i) as *mut c_void;
+        }
+    }
+    ptr::null_mut()
+}
@@ -0,0 +1,53 @@
+use core::ffi::{c_int, c_void};
+
+use emerald_std::io::{
+    syscall_close, syscall_read, syscall_seek, syscall_write, SeekFrom, SeekWhence,
+};
+
+use crate::errno::{self, from_syscall_error, syscall_result, EINVAL};
+
+pub const SEEK_SET: c_int = 0;
+pub const SEEK_CUR: c_int = 1;
+pub const SEEK_END: c_int = 2;
+
+#[no_mangle]
+pub unsafe extern "C" fn read(fd: c_int, buf: *mut c_void, count: usize) -> isize {
+    let buf = core::slice::from_raw_parts_mut(buf as *mut u8, count);
+    syscall_result(syscall_read(fd as usize, buf))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn write(fd: c_int, buf: *const c_void, count: usize) -> isize {
+    let buf = core::slice::from_raw_parts(buf as *const u8, count);
+    syscall_result(syscall_write(fd as usize, buf))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn close(fd: c_int) -> c_int {
+    match syscall_close(fd as usize) {
+        Ok(()) => 0,
+        Err(error) => {
+            errno::set_errno(from_syscall_error(error));
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lseek(fd: c_int, offset: i64, whence: c_int) -> i64 {
+    let whence = match whence {
+        SEEK_SET => SeekWhence::Start,
+        SEEK_CUR => SeekWhence::Current,
+        SEEK_END => SeekWhence::End,
+        _ => {
+            errno::set_errno(EINVAL);
+            return -1;
+        }
+    };
+    syscall_result(syscall_seek(fd as usize, SeekFrom::new(offset, whence)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _exit(status: c_int) -> ! {
+    emerald_std::process::exit(status)
+}
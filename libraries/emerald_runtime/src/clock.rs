@@ -0,0 +1,30 @@
+use kernel_user_link::{
+    call_syscall,
+    syscalls::{SyscallError, SYS_TIMEZONE},
+};
+
+pub use kernel_user_link::clock::TimeZone;
+
+fn timezone_syscall(new: Option<&TimeZone>) -> Result<TimeZone, SyscallError> {
+    let mut old = TimeZone::default();
+    unsafe {
+        call_syscall!(
+            SYS_TIMEZONE,
+            new.map_or(0, |new| new as *const TimeZone as u64), // new
+            &mut old as *mut TimeZone as u64,                   // old
+        )?;
+    }
+    Ok(old)
+}
+
+/// Get the system time zone
+pub fn timezone() -> Result<TimeZone, SyscallError> {
+    timezone_syscall(None)
+}
+
+/// Set the system time zone, returns the previous one
+///
+/// If the RTC holds the local time, this will move the real time clock as well.
+pub fn set_timezone(timezone: TimeZone) -> Result<TimeZone, SyscallError> {
+    timezone_syscall(Some(&timezone))
+}
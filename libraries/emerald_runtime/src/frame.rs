@@ -0,0 +1,312 @@
+//! Frame pacing helpers for graphical programs
+//!
+//! [`FrameTimer`] keeps a steady frame rate and provides a fixed timestep for the update logic,
+//! and [`FpsCounter`] measures the frame rate actually achieved.
+
+use std::time::{Duration, Instant};
+
+/// The maximum number of update steps that can accumulate, if the program falls behind more than
+/// this, the extra time is dropped instead of running many updates in a row to catch up
+const MAX_ACCUMULATED_STEPS: u32 = 5;
+
+/// Below this, [`PacingStrategy::SleepSpin`] spins instead of sleeping
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// Average of the last `N` values added
+pub struct MovingAverage<const N: usize> {
+    values: [f64; N],
+    current_index: usize,
+    filled: usize,
+    sum: f64,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    pub fn new() -> Self {
+        Self {
+            values: [0.0; N],
+            current_index: 0,
+            filled: 0,
+            sum: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.sum -= self.values[self.current_index];
+        self.sum += value;
+        self.values[self.current_index] = value;
+        self.current_index = (self.current_index + 1) % self.values.len();
+        if self.filled < self.values.len() {
+            self.filled += 1;
+        }
+    }
+
+    /// The average of the values added, `0` if nothing was added yet
+    pub fn average(&self) -> f64 {
+        if self.filled == 0 {
+            return 0.0;
+        }
+        self.sum / self.filled as f64
+    }
+}
+
+impl<const N: usize> Default for MovingAverage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Measures the frames per second, averaged over the last `N` frames
+pub struct FpsCounter<const N: usize = 100> {
+    average: MovingAverage<N>,
+    last_frame: Option<Instant>,
+}
+
+impl<const N: usize> FpsCounter<N> {
+    pub fn new() -> Self {
+        Self {
+            average: MovingAverage::new(),
+            last_frame: None,
+        }
+    }
+
+    /// Mark the end of a frame, should be called once per frame
+    pub fn frame(&mut self) {
+        self.frame_at(Instant::now());
+    }
+
+    fn frame_at(&mut self, now: Instant) {
+        if let Some(last_frame) = self.last_frame {
+            let elapsed = now.saturating_duration_since(last_frame);
+            if !elapsed.is_zero() {
+                self.average.add(1.0 / elapsed.as_secs_f64());
+            }
+        }
+        self.last_frame = Some(now);
+    }
+
+    /// The average frames per second, `0` until two frames are marked
+    pub fn fps(&self) -> f64 {
+        self.average.average()
+    }
+}
+
+impl<const N: usize> Default for FpsCounter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How [`FrameTimer::wait`] waits for the next frame
+///
+/// There is no vertical sync signal from the display yet, so we can only wait using the clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PacingStrategy {
+    /// Sleep until the next frame, doesn't use the CPU, but depends on the timer precision of
+    /// the scheduler
+    #[default]
+    Sleep,
+    /// Sleep until shortly before the next frame, then spin until it, more precise but uses
+    /// the CPU while spinning
+    SleepSpin,
+}
+
+/// Keeps a steady frame rate, and provides a fixed timestep for the update logic
+///
+/// Usage in the main loop:
+/// ```ignore
+/// let mut timer = FrameTimer::new(60.0);
+/// loop {
+///     timer.update();
+///     while timer.step() {
+///         // update the state by `timer.timestep()`
+///     }
+///     // render, `timer.alpha()` can be used to interpolate between the last two states
+///     timer.wait();
+/// }
+/// ```
+pub struct FrameTimer {
+    timestep: Duration,
+    strategy: PacingStrategy,
+    /// The deadline of the next frame, absolute so that frames don't drift by the
+    /// oversleep of each frame
+    next_frame: Instant,
+    last_update: Instant,
+    accumulator: Duration,
+}
+
+impl FrameTimer {
+    /// Create a timer running at `fps` frames per second
+    pub fn new(fps: f64) -> Self {
+        assert!(fps > 0.0);
+        Self::with_timestep(Duration::from_secs_f64(1.0 / fps))
+    }
+
+    /// Create a timer with `timestep` per frame
+    pub fn with_timestep(timestep: Duration) -> Self {
+        Self::starting_at(timestep, Instant::now())
+    }
+
+    fn starting_at(timestep: Duration, now: Instant) -> Self {
+        assert!(!timestep.is_zero());
+        Self {
+            timestep,
+            strategy: PacingStrategy::default(),
+            next_frame: now + timestep,
+            last_update: now,
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: PacingStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn timestep(&self) -> Duration {
+        self.timestep
+    }
+
+    pub fn strategy(&self) -> PacingStrategy {
+        self.strategy
+    }
+
+    /// Add the time passed since the last update to be consumed by [`Self::step`],
+    /// should be called once per frame
+    pub fn update(&mut self) {
+        self.update_at(Instant::now());
+    }
+
+    fn update_at(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.last_update = now;
+        self.accumulator = (self.accumulator + elapsed).min(self.timestep * MAX_ACCUMULATED_STEPS);
+    }
+
+    /// Consume one timestep of the passed time, returns `false` when there is not enough left,
+    /// the update logic should run once for each `true`
+    pub fn step(&mut self) -> bool {
+        if self.accumulator >= self.timestep {
+            self.accumulator -= self.timestep;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How far we are into the next step, in `[0, 1)`, can be used to interpolate when rendering
+    pub fn alpha(&self) -> f64 {
+        self.accumulator.as_secs_f64() / self.timestep.as_secs_f64()
+    }
+
+    /// Wait until the next frame
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let Some(remaining) = self.advance_deadline(now) else {
+            return;
+        };
+
+        match self.strategy {
+            PacingStrategy::Sleep => std::thread::sleep(remaining),
+            PacingStrategy::SleepSpin => {
+                let deadline = now + remaining;
+                if remaining > SPIN_THRESHOLD {
+                    std::thread::sleep(remaining - SPIN_THRESHOLD);
+                }
+                while Instant::now() < deadline {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Move to the next frame deadline, and return how long to wait for the current one,
+    /// if we are behind by more than a frame, don't try to catch up
+    fn advance_deadline(&mut self, now: Instant) -> Option<Duration> {
+        let deadline = self.next_frame;
+        self.next_frame += self.timestep;
+        if self.next_frame < now {
+            self.next_frame = now + self.timestep;
+        }
+        deadline.checked_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn moving_average_window() {
+        let mut average = MovingAverage::<3>::new();
+        assert_eq!(average.average(), 0.0);
+        average.add(3.0);
+        assert_eq!(average.average(), 3.0);
+        average.add(6.0);
+        average.add(9.0);
+        assert_eq!(average.average(), 6.0);
+        // `3` is out of the window
+        average.add(12.0);
+        assert_eq!(average.average(), 9.0);
+    }
+
+    #[test]
+    fn fps_counter() {
+        let start = Instant::now();
+        let mut counter = FpsCounter::<10>::new();
+        counter.frame_at(start);
+        assert_eq!(counter.fps(), 0.0);
+        for i in 1..=10 {
+            counter.frame_at(start + MS * 20 * i);
+        }
+        assert!((counter.fps() - 50.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fixed_timestep() {
+        let start = Instant::now();
+        let mut timer = FrameTimer::starting_at(MS * 10, start);
+
+        timer.update_at(start + MS * 35);
+        let mut steps = 0;
+        while timer.step() {
+            steps += 1;
+        }
+        assert_eq!(steps, 3);
+        assert!((timer.alpha() - 0.5).abs() < 1e-6);
+
+        // the remaining 5ms are kept for the next frame
+        timer.update_at(start + MS * 40);
+        assert!(timer.step());
+        assert!(!timer.step());
+    }
+
+    #[test]
+    fn fixed_timestep_limits_catch_up() {
+        let start = Instant::now();
+        let mut timer = FrameTimer::starting_at(MS * 10, start);
+
+        timer.update_at(start + Duration::from_secs(10));
+        let mut steps = 0;
+        while timer.step() {
+            steps += 1;
+        }
+        assert_eq!(steps, MAX_ACCUMULATED_STEPS);
+    }
+
+    #[test]
+    fn deadlines_dont_drift() {
+        let start = Instant::now();
+        let mut timer = FrameTimer::starting_at(MS * 10, start);
+
+        assert_eq!(timer.advance_deadline(start + MS * 4), Some(MS * 6));
+        // overslept by 2ms, the next frame is shorter to make up for it
+        assert_eq!(timer.advance_deadline(start + MS * 12), Some(MS * 8));
+        // late, no wait
+        assert_eq!(timer.advance_deadline(start + MS * 35), None);
+        // behind by more than a frame, the next deadline starts from now
+        assert_eq!(timer.advance_deadline(start + MS * 80), None);
+        assert_eq!(timer.advance_deadline(start + MS * 85), Some(MS * 5));
+    }
+}
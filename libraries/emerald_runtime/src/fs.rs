@@ -0,0 +1,36 @@
+use std::ffi::CStr;
+
+use kernel_user_link::{
+    call_syscall,
+    syscalls::{SyscallError, SYS_FS_STAT, SYS_UNMOUNT},
+};
+
+pub use kernel_user_link::file::{unmount_flags, FileSystemStat};
+
+/// Get the size and free space of the filesystem containing `path`
+pub fn stat(path: &CStr) -> Result<FileSystemStat, SyscallError> {
+    let mut stat = FileSystemStat::default();
+    unsafe {
+        call_syscall!(
+            SYS_FS_STAT,
+            path.as_ptr() as u64,                    // path
+            &mut stat as *mut FileSystemStat as u64, // stat_ptr
+        )?;
+    }
+    Ok(stat)
+}
+
+/// Unmount the filesystem mounted at `path`, `flags` are from [`unmount_flags`]
+///
+/// Without [`unmount_flags::LAZY`], this fails with [`SyscallError::Busy`] if the filesystem
+/// is in use or has other filesystems mounted inside it.
+pub fn unmount(path: &CStr, flags: u64) -> Result<(), SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_UNMOUNT,
+            path.as_ptr() as u64, // path
+            flags,                // flags
+        )?;
+    }
+    Ok(())
+}
@@ -5,12 +5,16 @@ pub use kernel_user_link::keyboard::{modifier, Key, KeyType};
 
 pub struct Keyboard {
     file: File,
+    dropped_events: u64,
 }
 
 impl Keyboard {
     pub fn new() -> Self {
         let file = File::open(KEYBOARD_PATH).unwrap();
-        Keyboard { file }
+        Keyboard {
+            file,
+            dropped_events: 0,
+        }
     }
 
     pub fn get_key_event(&mut self) -> Option<Key> {
@@ -19,12 +23,23 @@ impl Keyboard {
             // Safety: we are using the same size as the Key struct
             // and this is provided by the kernel, so it must
             // be valid
-            Some(unsafe { Key::from_bytes(buf) })
+            let key = unsafe { Key::from_bytes(buf) };
+            if key.is_overflow_marker() {
+                self.dropped_events += key.sequence as u64;
+                return self.get_key_event();
+            }
+            Some(key)
         } else {
             None
         }
     }
 
+    /// Number of events missed since this was opened, because the kernel queue was full,
+    /// the overflow markers are counted here and not returned
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events
+    }
+
     pub fn iter_keys(&mut self) -> impl Iterator<Item = Key> + '_ {
         std::iter::from_fn(move || self.get_key_event())
     }
@@ -1,3 +1,7 @@
+pub mod clock;
+pub mod frame;
+pub mod fs;
 pub mod keyboard;
 pub mod mouse;
 pub mod power;
+pub mod process;
@@ -5,12 +5,16 @@ pub use kernel_user_link::mouse::{buttons, MouseEvent, ScrollType};
 
 pub struct Mouse {
     file: File,
+    dropped_events: u64,
 }
 
 impl Mouse {
     pub fn new() -> Self {
         let file = File::open(MOUSE_PATH).unwrap();
-        Mouse { file }
+        Mouse {
+            file,
+            dropped_events: 0,
+        }
     }
 
     pub fn get_event(&mut self) -> Option<MouseEvent> {
@@ -19,12 +23,23 @@ impl Mouse {
             // Safety: we are using the same size as the MouseEvent struct
             // and this is provided by the kernel, so it must
             // be valid
-            Some(unsafe { MouseEvent::from_bytes(buf) })
+            let event = unsafe { MouseEvent::from_bytes(buf) };
+            if event.is_overflow_marker() {
+                self.dropped_events += event.sequence as u64;
+                return self.get_event();
+            }
+            Some(event)
         } else {
             None
         }
     }
 
+    /// Number of events missed since this was opened, because the kernel queue was full,
+    /// the overflow markers are counted here and not returned
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events
+    }
+
     pub fn iter_events(&mut self) -> impl Iterator<Item = MouseEvent> + '_ {
         std::iter::from_fn(move || self.get_event())
     }
@@ -0,0 +1,77 @@
+use kernel_user_link::{
+    call_syscall,
+    syscalls::{SyscallError, SYS_GET_PROC_INFO, SYS_PROCESS_LIST},
+};
+
+pub use kernel_user_link::process::{PriorityLevel, ProcessInfo, ProcessState};
+
+/// Number of processes to get in each syscall
+const BATCH_SIZE: usize = 16;
+/// Initial buffer size for [`details`], grows if needed
+const DETAILS_BUFFER_SIZE: usize = 256;
+const MAX_DETAILS_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The command line information of a process
+#[derive(Debug, Clone)]
+pub struct ProcessDetails {
+    /// Path of the executable
+    pub path: String,
+    /// Current working directory
+    pub cwd: String,
+    pub argv: Vec<String>,
+}
+
+/// Get a snapshot of all the processes, sorted by `pid`.
+///
+/// The list is retrieved in batches, so processes created or exited in the middle may be missed.
+pub fn list() -> Result<Vec<ProcessInfo>, SyscallError> {
+    let mut list = Vec::new();
+    let mut batch = [ProcessInfo::default(); BATCH_SIZE];
+    loop {
+        let written = unsafe {
+            call_syscall!(
+                SYS_PROCESS_LIST,
+                list.len() as u64,         // offset
+                batch.as_mut_ptr() as u64, // entries_ptr
+                BATCH_SIZE as u64          // len
+            )?
+        } as usize;
+        list.extend_from_slice(&batch[..written]);
+        if written < BATCH_SIZE {
+            return Ok(list);
+        }
+    }
+}
+
+/// Get the executable path, current directory and arguments of the process `pid`
+pub fn details(pid: u64) -> Result<ProcessDetails, SyscallError> {
+    let mut buf = vec![0u8; DETAILS_BUFFER_SIZE];
+    let written = loop {
+        let result = unsafe {
+            call_syscall!(
+                SYS_GET_PROC_INFO,
+                pid,                     // pid
+                buf.as_mut_ptr() as u64, // buf
+                buf.len() as u64         // len
+            )
+        };
+        match result {
+            Ok(written) => break written as usize,
+            Err(SyscallError::BufferTooSmall(needed)) if needed <= MAX_DETAILS_BUFFER_SIZE => {
+                buf.resize(needed, 0);
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    let mut strings = buf[..written]
+        .split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned());
+    let path = strings.next().unwrap_or_default();
+    let cwd = strings.next().unwrap_or_default();
+    // the last one is empty, after the last null terminator
+    let mut argv = strings.collect::<Vec<_>>();
+    argv.pop();
+
+    Ok(ProcessDetails { path, cwd, argv })
+}
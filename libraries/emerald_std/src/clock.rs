@@ -1,7 +1,7 @@
-pub use kernel_user_link::clock::{ClockTime, ClockType};
+pub use kernel_user_link::clock::{ClockTime, ClockType, TimeZone};
 use kernel_user_link::{
     call_syscall,
-    syscalls::{SyscallError, SYS_GET_TIME, SYS_SLEEP},
+    syscalls::{SyscallError, SYS_GET_TIME, SYS_SLEEP, SYS_SLEEP_UNTIL, SYS_TIMEZONE},
 };
 
 /// # Safety
@@ -17,6 +17,23 @@ pub unsafe fn sleep(seconds: u64, nanoseconds: u64) -> Result<(), SyscallError>
     }
 }
 
+/// Sleep until the [`ClockType::SystemTime`] reaches `seconds` and `nanoseconds`, unlike [`sleep`],
+/// the deadline doesn't move if the process was not scheduled directly, so it can be used to run
+/// periodic work without drifting
+///
+/// # Safety
+/// This function assumes that `seconds` and `nanoseconds` are valid, nanoseconds should be less than 1_000_000_000.
+pub unsafe fn sleep_until(seconds: u64, nanoseconds: u64) -> Result<(), SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_SLEEP_UNTIL,
+            seconds,     // seconds
+            nanoseconds, // nanoseconds
+        )
+        .map(|e| assert!(e == 0))
+    }
+}
+
 /// # Safety
 /// There are no safety requirements for this function.
 /// Its just that it's a wrapper around a syscall.
@@ -35,3 +52,21 @@ pub unsafe fn get_time(time_type: ClockType) -> Result<ClockTime, SyscallError>
         .map(|_| time)
     }
 }
+
+/// Get the system time zone, and replace it with `new` if it's `Some`, returns the old value
+///
+/// # Safety
+/// There are no safety requirements for this function.
+/// Its just that it's a wrapper around a syscall.
+pub unsafe fn timezone(new: Option<&TimeZone>) -> Result<TimeZone, SyscallError> {
+    let mut old = TimeZone::default();
+    unsafe {
+        call_syscall!(
+            SYS_TIMEZONE,
+            new.map_or(0, |new| new as *const TimeZone as u64), // new
+            &mut old as *mut TimeZone as u64,                   // old
+        )
+        .map(|e| assert!(e == 0))
+        .map(|_| old)
+    }
+}
@@ -0,0 +1,123 @@
+//! Conversion of [`SyscallError`] to the error kinds of `std::io`
+//!
+//! `std` depends on this crate, so `std::io::ErrorKind` can't be used here, instead [`ErrorKind`]
+//! has the kinds we need, and `std` maps them one to one.
+//! `std` keeps the [`SyscallError::code`] as the raw OS error, and uses [`decode_error_kind`]
+//! and [`error_string`] on it.
+
+use kernel_user_link::syscalls::SyscallError;
+
+/// The `std::io::ErrorKind` variants that [`SyscallError`]s map to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    WouldBlock,
+    NotADirectory,
+    IsADirectory,
+    ResourceBusy,
+    NotSeekable,
+    StorageFull,
+    InvalidInput,
+    InvalidData,
+    InvalidFilename,
+    TimedOut,
+    UnexpectedEof,
+    Unsupported,
+    OutOfMemory,
+    Uncategorized,
+}
+
+pub fn error_kind(error: SyscallError) -> ErrorKind {
+    match error {
+        SyscallError::FileNotFound | SyscallError::PidNotFound | SyscallError::NoDevice => {
+            ErrorKind::NotFound
+        }
+        SyscallError::PermissionDenied => ErrorKind::PermissionDenied,
+        SyscallError::AlreadyExists => ErrorKind::AlreadyExists,
+        SyscallError::ProcessStillRunning => ErrorKind::WouldBlock,
+        SyscallError::IsNotDirectory => ErrorKind::NotADirectory,
+        SyscallError::IsDirectory => ErrorKind::IsADirectory,
+        SyscallError::Busy
+        | SyscallError::GraphicsNotAvailable
+        | SyscallError::GraphicsAlreadyTaken
+        | SyscallError::GraphicsNotOwned => ErrorKind::ResourceBusy,
+        SyscallError::NotSeekable => ErrorKind::NotSeekable,
+        SyscallError::NoSpace => ErrorKind::StorageFull,
+        SyscallError::InvalidArgument(..)
+        | SyscallError::InvalidFileIndex
+        | SyscallError::InvalidOffset
+        | SyscallError::InvalidGraphicsBuffer
+        | SyscallError::BufferTooSmall(_) => ErrorKind::InvalidInput,
+        SyscallError::CouldNotLoadElf(_) => ErrorKind::InvalidData,
+        SyscallError::InvalidPath => ErrorKind::InvalidFilename,
+        SyscallError::TimedOut => ErrorKind::TimedOut,
+        SyscallError::EndOfFile => ErrorKind::UnexpectedEof,
+        SyscallError::SyscallNotFound | SyscallError::OperationNotSupported => {
+            ErrorKind::Unsupported
+        }
+        SyscallError::CouldNotAllocateProcess | SyscallError::HeapRangesExceeded => {
+            ErrorKind::OutOfMemory
+        }
+        SyscallError::InvalidError
+        | SyscallError::CouldNotOpenFile
+        | SyscallError::CouldNotWriteToFile
+        | SyscallError::CouldNotReadFromFile
+        | SyscallError::IoError => ErrorKind::Uncategorized,
+        // new errors that are not categorized yet
+        _ => ErrorKind::Uncategorized,
+    }
+}
+
+fn error_from_code(code: i32) -> Option<SyscallError> {
+    u8::try_from(code).ok().and_then(SyscallError::from_code)
+}
+
+/// The kind of the raw OS error `code`, which is a [`SyscallError::code`]
+pub fn decode_error_kind(code: i32) -> ErrorKind {
+    error_from_code(code).map_or(ErrorKind::Uncategorized, error_kind)
+}
+
+/// The description of the raw OS error `code`, which is a [`SyscallError::code`]
+pub fn error_string(code: i32) -> &'static str {
+    let Some(error) = error_from_code(code) else {
+        return "unknown error";
+    };
+    match error {
+        SyscallError::SyscallNotFound => "syscall not found",
+        SyscallError::InvalidError => "invalid error",
+        SyscallError::CouldNotOpenFile => "could not open file",
+        SyscallError::InvalidFileIndex => "bad file descriptor",
+        SyscallError::CouldNotWriteToFile => "could not write to file",
+        SyscallError::CouldNotReadFromFile => "could not read from file",
+        SyscallError::CouldNotLoadElf(_) => "could not load executable",
+        SyscallError::CouldNotAllocateProcess => "could not allocate process",
+        SyscallError::HeapRangesExceeded => "heap ranges exceeded",
+        SyscallError::EndOfFile => "end of file",
+        SyscallError::FileNotFound => "file not found",
+        SyscallError::PidNotFound => "process not found",
+        SyscallError::ProcessStillRunning => "process still running",
+        SyscallError::IsNotDirectory => "not a directory",
+        SyscallError::IsDirectory => "is a directory",
+        SyscallError::BufferTooSmall(_) => "buffer too small",
+        SyscallError::GraphicsNotAvailable => "graphics not available",
+        SyscallError::GraphicsAlreadyTaken => "graphics already taken",
+        SyscallError::GraphicsNotOwned => "graphics not owned",
+        SyscallError::InvalidGraphicsBuffer => "invalid graphics buffer",
+        SyscallError::InvalidOffset => "invalid offset",
+        SyscallError::AlreadyExists => "already exists",
+        SyscallError::OperationNotSupported => "operation not supported",
+        SyscallError::TimedOut => "timed out",
+        SyscallError::NotSeekable => "not seekable",
+        SyscallError::PermissionDenied => "permission denied",
+        SyscallError::Busy => "resource busy",
+        SyscallError::IoError => "input/output error",
+        SyscallError::NoSpace => "no space left on device",
+        SyscallError::InvalidPath => "invalid path",
+        SyscallError::NoDevice => "no such device",
+        SyscallError::InvalidArgument(..) => "invalid argument",
+        _ => "unknown error",
+    }
+}
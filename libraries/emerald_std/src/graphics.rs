@@ -1,6 +1,9 @@
 use core::mem::MaybeUninit;
 
-pub use kernel_user_link::graphics::{FrameBufferInfo, GraphicsCommand};
+pub use kernel_user_link::graphics::{
+    CopyRectCommand, FillRectCommand, FrameBufferInfo, GraphicsCommand, Rect,
+    CAPTURE_BYTES_PER_PIXEL,
+};
 use kernel_user_link::{
     call_syscall,
     syscalls::{SyscallError, SYS_GRAPHICS},
@@ -12,6 +15,10 @@ pub struct BlitCommand<'a> {
     pub src: (usize, usize),
     pub dst: (usize, usize),
     pub size: (usize, usize),
+    /// The dirty rectangles to blit, in `memory` coordinates, each must be inside the region
+    /// of `src` and `size`, and is copied to the same offset from `dst`.
+    /// Empty to blit the whole region
+    pub damage: &'a [Rect],
 }
 
 impl BlitCommand<'_> {
@@ -31,6 +38,15 @@ impl BlitCommand<'_> {
             return false;
         }
 
+        // check the `damage`
+        if !self
+            .damage
+            .iter()
+            .all(|rect| rect.is_inside(self.src, self.size))
+        {
+            return false;
+        }
+
         // the `dst` relies on the framebuffer info of the kernel
         // we don't have that info here, so we can't check it
         true
@@ -61,6 +77,13 @@ pub fn release_ownership() -> Result<(), SyscallError> {
     unsafe { graphics(GraphicsCommand::ReleaseOwnership, 0) }
 }
 
+/// Release the ownership held by another process, i.e. a child that stopped responding,
+/// only allowed for the parent of the owner or `init`
+pub fn force_release_ownership() -> Result<(), SyscallError> {
+    // Safety: `ForceRelease` is a valid command, and doesn't require any extra data.
+    unsafe { graphics(GraphicsCommand::ForceRelease, 0) }
+}
+
 pub fn get_framebuffer_info() -> Result<FrameBufferInfo, SyscallError> {
     let mut info = MaybeUninit::<FrameBufferInfo>::uninit();
 
@@ -88,9 +111,46 @@ pub fn blit(command: &BlitCommand<'_>) -> Result<(), SyscallError> {
         src: command.src,
         dst: command.dst,
         size: command.size,
+        damage: command.damage.as_ptr(),
+        damage_len: command.damage.len(),
     };
 
     // Safety: `Blit` is a valid command, and requires a valid `BlitCommand` pointer.
     //         we just created one right now, so its valid.
     unsafe { graphics(GraphicsCommand::Blit, &converted_command as *const _ as u64) }
 }
+
+/// Fill a region of the graphics framebuffer with a single color, without sending any pixel data
+pub fn fill_rect(command: &FillRectCommand) -> Result<(), SyscallError> {
+    // Safety: `FillRect` is a valid command, and requires a valid `FillRectCommand` pointer.
+    //         which rust guarantees here.
+    unsafe { graphics(GraphicsCommand::FillRect, command as *const _ as u64) }
+}
+
+/// Copy a region of the graphics framebuffer into another position in it (i.e. scrolling),
+/// the regions can overlap
+pub fn copy_rect(command: &CopyRectCommand) -> Result<(), SyscallError> {
+    // Safety: `CopyRect` is a valid command, and requires a valid `CopyRectCommand` pointer.
+    //         which rust guarantees here.
+    unsafe { graphics(GraphicsCommand::CopyRect, command as *const _ as u64) }
+}
+
+/// Copy the currently displayed frame into `buffer`, doesn't require ownership.
+///
+/// The format is packed `RGB`, [`CAPTURE_BYTES_PER_PIXEL`] bytes per pixel, rows from top to bottom
+/// without padding, `buffer` must be at least [`FrameBufferInfo::capture_size`] long.
+pub fn capture_framebuffer(buffer: &mut [u8]) -> Result<(), SyscallError> {
+    let command = kernel_user_link::graphics::CaptureCommand {
+        memory: buffer.as_mut_ptr(),
+        len: buffer.len(),
+    };
+
+    // Safety: `CaptureFrameBuffer` is a valid command, and requires a valid `CaptureCommand` pointer.
+    //         we just created one right now, so its valid.
+    unsafe {
+        graphics(
+            GraphicsCommand::CaptureFrameBuffer,
+            &command as *const _ as u64,
+        )
+    }
+}
@@ -1,14 +1,19 @@
 use core::ffi::CStr;
 
+pub use kernel_user_link::file::watch_events;
 pub use kernel_user_link::file::BlockingMode;
 pub use kernel_user_link::file::DirEntry;
 pub use kernel_user_link::file::DirFilename;
 pub use kernel_user_link::file::FileMeta;
 pub use kernel_user_link::file::FileStat;
+pub use kernel_user_link::file::FileSystemStat;
 pub use kernel_user_link::file::FileType;
+pub use kernel_user_link::file::IoVec;
 pub use kernel_user_link::file::OpenOptions;
 pub use kernel_user_link::file::SeekFrom;
 pub use kernel_user_link::file::SeekWhence;
+pub use kernel_user_link::file::WatchEvent;
+pub use kernel_user_link::file::DIR_FD_CWD;
 pub use kernel_user_link::file::MAX_FILENAME_LEN;
 pub use kernel_user_link::FD_STDERR;
 pub use kernel_user_link::FD_STDIN;
@@ -19,16 +24,34 @@ use kernel_user_link::syscalls::SyscallError;
 use kernel_user_link::syscalls::SYS_CHDIR;
 use kernel_user_link::syscalls::SYS_CLOSE;
 use kernel_user_link::syscalls::SYS_CREATE_PIPE;
+use kernel_user_link::syscalls::SYS_DUP;
+use kernel_user_link::syscalls::SYS_DUP2;
+use kernel_user_link::syscalls::SYS_FALLOCATE;
+use kernel_user_link::syscalls::SYS_FSYNC;
+use kernel_user_link::syscalls::SYS_FS_STAT;
+use kernel_user_link::syscalls::SYS_FTRUNCATE;
 use kernel_user_link::syscalls::SYS_GET_CWD;
 use kernel_user_link::syscalls::SYS_GET_FILE_META;
 use kernel_user_link::syscalls::SYS_OPEN;
+use kernel_user_link::syscalls::SYS_OPENAT;
 use kernel_user_link::syscalls::SYS_OPEN_DIR;
+use kernel_user_link::syscalls::SYS_OPEN_DIR_AT;
+use kernel_user_link::syscalls::SYS_PREAD;
+use kernel_user_link::syscalls::SYS_PWRITE;
 use kernel_user_link::syscalls::SYS_READ;
+use kernel_user_link::syscalls::SYS_READV;
 use kernel_user_link::syscalls::SYS_READ_DIR;
+use kernel_user_link::syscalls::SYS_REALPATH;
 use kernel_user_link::syscalls::SYS_SEEK;
 use kernel_user_link::syscalls::SYS_SET_FILE_META;
+use kernel_user_link::syscalls::SYS_SPLICE;
 use kernel_user_link::syscalls::SYS_STAT;
+use kernel_user_link::syscalls::SYS_STATAT;
+use kernel_user_link::syscalls::SYS_SYNC;
+use kernel_user_link::syscalls::SYS_TRUNCATE;
+use kernel_user_link::syscalls::SYS_WATCH;
 use kernel_user_link::syscalls::SYS_WRITE;
+use kernel_user_link::syscalls::SYS_WRITEV;
 
 /// # Safety
 /// This function assumes that `fd` is a valid file descriptor.
@@ -77,6 +100,29 @@ pub unsafe fn syscall_open(
     }
 }
 
+/// Same as [`syscall_open`], but relative paths are resolved from the directory `dir_fd`,
+/// or the current directory if `dir_fd` is [`DIR_FD_CWD`].
+///
+/// # Safety
+/// This function assumes that `path` is a valid C string.
+pub unsafe fn syscall_openat(
+    dir_fd: usize,
+    path: &CStr,
+    open_options: OpenOptions,
+    flags: usize,
+) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_OPENAT,
+            dir_fd as u64,         // dir_fd
+            path.as_ptr() as u64,  // path
+            open_options.to_u64(), // open_options
+            flags as u64           // flags
+        )
+        .map(|fd| fd as usize)
+    }
+}
+
 /// # Safety
 /// This function assumes that `fd` is a valid file descriptor.
 pub unsafe fn syscall_close(fd: usize) -> Result<(), SyscallError> {
@@ -93,19 +139,55 @@ pub unsafe fn syscall_close(fd: usize) -> Result<(), SyscallError> {
 /// This function creates a pipe and return the descriptors.
 /// Callers must ensure to use the descriptors correctly.
 pub unsafe fn syscall_create_pipe() -> Result<(usize, usize), SyscallError> {
+    unsafe { syscall_create_pipe_with_capacity(0) }
+}
+
+/// Same as [`syscall_create_pipe`], but the pipe holds `capacity` bytes before writers wait,
+/// `0` is the default capacity, see [`PIPE_MAX_CAPACITY`](kernel_user_link::file::PIPE_MAX_CAPACITY)
+/// for the limits
+///
+/// # Safety
+/// This function creates a pipe and return the descriptors.
+/// Callers must ensure to use the descriptors correctly.
+pub unsafe fn syscall_create_pipe_with_capacity(
+    capacity: usize,
+) -> Result<(usize, usize), SyscallError> {
     let mut in_fd: u64 = 0;
     let mut out_fd: u64 = 0;
     unsafe {
         call_syscall!(
             SYS_CREATE_PIPE,
             &mut in_fd as *mut u64 as u64,  // in_fd
-            &mut out_fd as *mut u64 as u64  // out_fd
+            &mut out_fd as *mut u64 as u64, // out_fd
+            capacity as u64,                // capacity
         )?
     };
 
     Ok((in_fd as usize, out_fd as usize))
 }
 
+/// Move up to `len` bytes from `from_fd` to `to_fd` inside the kernel, one of them must be a pipe,
+/// i.e. `cat file | prog` without copying the data through a buffer.
+///
+/// Returns the number of bytes moved, `0` at the end of the source.
+///
+/// # Safety
+/// This function assumes that `from_fd` and `to_fd` are valid file descriptors.
+pub unsafe fn syscall_splice(
+    from_fd: usize,
+    to_fd: usize,
+    len: usize,
+) -> Result<u64, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_SPLICE,
+            from_fd as u64, // from_fd
+            to_fd as u64,   // to_fd
+            len as u64,     // len
+        )
+    }
+}
+
 /// # Safety
 /// This function assumes that `fd` is a valid file descriptor.
 #[deprecated(note = "Use `syscall_set_file_meta` instead")]
@@ -131,6 +213,44 @@ pub unsafe fn syscall_stat(path: &CStr, stat: &mut FileStat) -> Result<(), Sysca
     }
 }
 
+/// Same as [`syscall_stat`], but relative paths are resolved from the directory `dir_fd`,
+/// or the current directory if `dir_fd` is [`DIR_FD_CWD`].
+///
+/// # Safety
+/// This function assumes that `path` is a valid C string.
+pub unsafe fn syscall_statat(
+    dir_fd: usize,
+    path: &CStr,
+    stat: &mut FileStat,
+) -> Result<(), SyscallError> {
+    let stat_ptr = stat as *mut FileStat as u64;
+    unsafe {
+        call_syscall!(
+            SYS_STATAT,
+            dir_fd as u64,        // dir_fd
+            path.as_ptr() as u64, // path
+            stat_ptr              // stat_ptr
+        )
+        .map(|e| assert!(e == 0))
+    }
+}
+
+/// Get the size and free space of the filesystem containing `path`
+///
+/// # Safety
+/// This function assumes that `path` is a valid C string.
+pub unsafe fn syscall_fs_stat(path: &CStr, stat: &mut FileSystemStat) -> Result<(), SyscallError> {
+    let stat_ptr = stat as *mut FileSystemStat as u64;
+    unsafe {
+        call_syscall!(
+            SYS_FS_STAT,
+            path.as_ptr() as u64, // path
+            stat_ptr              // stat_ptr
+        )
+        .map(|e| assert!(e == 0))
+    }
+}
+
 /// # Safety
 /// This function assumes that `path` is a valid C string.
 pub unsafe fn syscall_open_dir(path: &CStr) -> Result<usize, SyscallError> {
@@ -143,6 +263,22 @@ pub unsafe fn syscall_open_dir(path: &CStr) -> Result<usize, SyscallError> {
     }
 }
 
+/// Same as [`syscall_open_dir`], but relative paths are resolved from the directory `dir_fd`,
+/// or the current directory if `dir_fd` is [`DIR_FD_CWD`].
+///
+/// # Safety
+/// This function assumes that `path` is a valid C string.
+pub unsafe fn syscall_open_dir_at(dir_fd: usize, path: &CStr) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_OPEN_DIR_AT,
+            dir_fd as u64,        // dir_fd
+            path.as_ptr() as u64, // path
+        )
+        .map(|fd| fd as usize)
+    }
+}
+
 /// # Safety
 /// This function assumes that `fd` is a valid file descriptor.
 /// Also assume `entry` is a valid pointer to a valid `DirEntry` struct.
@@ -236,3 +372,203 @@ pub unsafe fn syscall_seek(fd: usize, seek: SeekFrom) -> Result<u64, SyscallErro
         )
     }
 }
+
+/// Duplicate `fd` into a new file descriptor, the new one will have its own position (starting from
+/// the current position of `fd`) and won't be close-on-spawn
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+pub unsafe fn syscall_dup(fd: usize) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_DUP,
+            fd, // fd
+        )
+        .map(|fd| fd as usize)
+    }
+}
+
+/// Same as [`syscall_dup`], but puts the new file descriptor in `new_fd`, closing
+/// the file that was there before if any
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor, and that `new_fd` is not used
+/// by anything else in the process, as it will be closed.
+pub unsafe fn syscall_dup2(fd: usize, new_fd: usize) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_DUP2, fd,     // fd
+            new_fd, // new_fd
+        )
+        .map(|fd| fd as usize)
+    }
+}
+
+/// Read into multiple buffers in order, the returned count may be less than the total length
+/// of the buffers.
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+/// And that all the buffers in `io_vecs` are valid, and don't overlap.
+pub unsafe fn syscall_readv(fd: usize, io_vecs: &[IoVec]) -> Result<u64, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_READV,
+            fd,                      // fd
+            io_vecs.as_ptr() as u64, // io_vecs
+            io_vecs.len() as u64     // len
+        )
+    }
+}
+
+/// Write multiple buffers in order, the returned count may be less than the total length
+/// of the buffers.
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+/// And that all the buffers in `io_vecs` are valid.
+pub unsafe fn syscall_writev(fd: usize, io_vecs: &[IoVec]) -> Result<u64, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_WRITEV,
+            fd,                      // fd
+            io_vecs.as_ptr() as u64, // io_vecs
+            io_vecs.len() as u64     // len
+        )
+    }
+}
+
+/// Read from `offset` in the file, without using or changing the file position.
+/// This doesn't wait for data, even if the file is blocking.
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+/// And that `buf` is a valid buffer.
+pub unsafe fn syscall_pread(fd: usize, buf: &mut [u8], offset: u64) -> Result<u64, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_PREAD,
+            fd,                      // fd
+            buf.as_mut_ptr() as u64, // buf
+            buf.len() as u64,        // size
+            offset                   // offset
+        )
+    }
+}
+
+/// Write at `offset` in the file, without using or changing the file position.
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+/// And that `buf` is a valid buffer.
+pub unsafe fn syscall_pwrite(fd: usize, buf: &[u8], offset: u64) -> Result<u64, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_PWRITE,
+            fd,                  // fd
+            buf.as_ptr() as u64, // buf
+            buf.len() as u64,    // size
+            offset               // offset
+        )
+    }
+}
+
+/// Set the size of the file at `path`, if the file grows, the new space is zeroed
+///
+/// # Safety
+/// This function assumes that `path` is a valid C string.
+pub unsafe fn syscall_truncate(path: &CStr, size: u64) -> Result<(), SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_TRUNCATE,
+            path.as_ptr() as u64, // path
+            size                  // size
+        )
+        .map(|e| assert!(e == 0))
+    }
+}
+
+/// Set the size of the file, if the file grows, the new space is zeroed
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+pub unsafe fn syscall_ftruncate(fd: usize, size: u64) -> Result<(), SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_FTRUNCATE,
+            fd,   // fd
+            size  // size
+        )
+        .map(|e| assert!(e == 0))
+    }
+}
+
+/// Make sure the file is at least `size` bytes, without shrinking it
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+pub unsafe fn syscall_fallocate(fd: usize, size: u64) -> Result<(), SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_FALLOCATE,
+            fd,   // fd
+            size  // size
+        )
+        .map(|e| assert!(e == 0))
+    }
+}
+
+/// Write all the cached data of the file to disk
+///
+/// # Safety
+/// This function assumes that `fd` is a valid file descriptor.
+pub unsafe fn syscall_fsync(fd: usize) -> Result<(), SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_FSYNC,
+            fd, // fd
+        )
+        .map(|e| assert!(e == 0))
+    }
+}
+
+/// Write all the cached data of all filesystems to disk
+///
+/// # Safety
+/// This is generally safe, but its marked as unsafe because it's a syscall
+pub unsafe fn syscall_sync() -> Result<(), SyscallError> {
+    unsafe { call_syscall!(SYS_SYNC).map(|e| assert!(e == 0)) }
+}
+
+/// Watch `path` for the events in `mask` (from [`watch_events`]), returns a file to read
+/// [`WatchEvent`]s from, only whole events are read.
+///
+/// # Safety
+/// This function assumes that `path` is a valid C string.
+pub unsafe fn syscall_watch(path: &CStr, mask: u32) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_WATCH,
+            path.as_ptr() as u64, // path
+            mask as u64           // mask
+        )
+        .map(|fd| fd as usize)
+    }
+}
+
+/// Get the canonical absolute path of `path`, i.e. with `.` and `..` resolved, the path must exist.
+///
+/// # Safety
+/// This function assumes that `path` is a valid C string, and `buf` is a valid buffer.
+/// The result will be a string written in the buffer, NULL won't be written, but the written length will be returned
+pub unsafe fn syscall_realpath(path: &CStr, buf: &mut [u8]) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_REALPATH,
+            path.as_ptr() as u64,    // path
+            buf.as_mut_ptr() as u64, // buf
+            buf.len() as u64         // len
+        )
+        .map(|written| written as usize)
+    }
+}
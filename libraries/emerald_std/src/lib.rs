@@ -7,10 +7,12 @@
 
 pub mod alloc;
 pub mod clock;
+pub mod error;
 pub mod graphics;
 pub mod io;
 pub mod process;
 mod sync;
 
+pub use emerald_crypto as crypto;
 pub use kernel_user_link::syscalls::SyscallArgError;
 pub use kernel_user_link::syscalls::SyscallError;
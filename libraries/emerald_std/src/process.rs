@@ -1,11 +1,17 @@
 use core::ffi::{c_char, CStr};
 
 pub use kernel_user_link::process::{
-    process_metadata, PriorityLevel, ProcessMetadata, SpawnFileMapping,
+    process_metadata, spawn_redirect, MemInfo, PriorityLevel, ProcessInfo, ProcessMemoryStats,
+    ProcessMetadata, ProcessState, SchedulingClass, SpawnFileMapping, SpawnOptions,
+    SpawnStdioRedirect, ALL_CPUS_AFFINITY, KILLED_EXIT_CODE, SPAWN_OPTIONS_SIZE_V1,
 };
 use kernel_user_link::{
     call_syscall,
-    syscalls::{SyscallError, SYS_EXIT, SYS_PRIORITY, SYS_SPAWN, SYS_WAIT_PID},
+    syscalls::{
+        SyscallError, SYS_EXIT, SYS_GET_PROC_INFO, SYS_KILL, SYS_MEMINFO, SYS_PRIORITY,
+        SYS_PROCESS_LIST, SYS_PROC_VM_READ, SYS_PROC_VM_WRITE, SYS_SET_AFFINITY, SYS_SPAWN,
+        SYS_WAIT_PID,
+    },
 };
 
 /// # Safety
@@ -32,14 +38,77 @@ pub unsafe fn spawn(
     argv: &[*const c_char],
     file_mappings: &[SpawnFileMapping],
 ) -> Result<u64, SyscallError> {
+    let mut options = SpawnOptions::new(path, argv.as_ptr() as _);
+    options.file_mappings = file_mappings.as_ptr();
+    options.file_mappings_len = file_mappings.len();
+    unsafe { spawn_with_options(&mut options) }
+}
+
+/// Same as [`spawn`], but also connects the child's stdout/stderr to new files or pipes
+/// as described by `redirects`.
+///
+/// For pipe redirects, the fd of the read end is written to `pipe_read_fd`, and owned by the caller.
+///
+/// # Safety
+/// Same as [`spawn`], and the paths in `redirects` must be valid C strings.
+pub unsafe fn spawn_with_redirects(
+    path: &CStr,
+    argv: &[*const c_char],
+    file_mappings: &[SpawnFileMapping],
+    redirects: &mut [SpawnStdioRedirect],
+) -> Result<u64, SyscallError> {
+    let mut options = SpawnOptions::new(path, argv.as_ptr() as _);
+    options.file_mappings = file_mappings.as_ptr();
+    options.file_mappings_len = file_mappings.len();
+    options.redirects = redirects.as_mut_ptr();
+    options.redirects_len = redirects.len();
+    unsafe { spawn_with_options(&mut options) }
+}
+
+/// Spawn a process with all the options in [`SpawnOptions`], the other `spawn` functions are
+/// shortcuts for this.
+///
+/// # Safety
+/// All the pointers in `options` must be valid (or null where allowed), with the same requirements
+/// as [`spawn_with_redirects`].
+pub unsafe fn spawn_with_options(options: &mut SpawnOptions) -> Result<u64, SyscallError> {
     unsafe {
         call_syscall!(
             SYS_SPAWN,
-            path.as_ptr() as u64,          // path
-            argv.as_ptr() as u64,          // argv
-            file_mappings.as_ptr() as u64, // file_mappings
-            file_mappings.len() as u64     // file_mappings_len
+            options as *mut SpawnOptions as u64, // options
+        )
+    }
+}
+
+/// The environment given to this process by the kernel on creation, as `KEY=VALUE` strings
+pub fn initial_env() -> impl Iterator<Item = &'static CStr> {
+    let mut envp = process_metadata().envp as *const *const c_char;
+    core::iter::from_fn(move || {
+        // SAFETY: the kernel puts a null terminated array of valid C strings in the stack
+        unsafe {
+            if envp.is_null() || (*envp).is_null() {
+                return None;
+            }
+            let var = CStr::from_ptr(*envp);
+            envp = envp.add(1);
+            Some(var)
+        }
+    })
+}
+
+/// Terminate the process `pid`, its exit code will be [`KILLED_EXIT_CODE`].
+/// If `pid` is the current process, this doesn't return.
+///
+/// # Safety
+/// This is generally safe, it will return error if the pid is not valid, but its marked as unsafe
+/// because it's a syscall
+pub unsafe fn kill(pid: u64) -> Result<(), SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_KILL,
+            pid, // pid
         )
+        .map(|e| assert!(e == 0))
     }
 }
 
@@ -74,3 +143,119 @@ pub unsafe fn priority(
         .map(|x| PriorityLevel::from_u64(x).unwrap())
     }
 }
+
+/// Set the bitmask of CPUs the process `pid` can run on, if `affinity` is `Some`, and return the
+/// previous one, `None` just gets the current affinity
+///
+/// # Safety
+/// This is generally safe, it will return error if the pid or the mask is not valid, but its marked as unsafe
+/// because it's a syscall
+pub unsafe fn set_affinity(pid: u64, affinity: Option<u64>) -> Result<u64, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_SET_AFFINITY,
+            pid,                   // pid
+            affinity.unwrap_or(0)  // affinity
+        )
+    }
+}
+
+/// Get the system memory information, and the memory usage of the process `pid`
+/// use `process_metadata().pid` to get the current process information.
+///
+/// # Safety
+/// This is generally safe, it will return error if the pid is not valid, but its marked as unsafe
+/// because it's a syscall
+pub unsafe fn meminfo(pid: u64) -> Result<MemInfo, SyscallError> {
+    let mut info = MemInfo::default();
+    unsafe {
+        call_syscall!(
+            SYS_MEMINFO,
+            pid,                              // pid
+            &mut info as *mut MemInfo as u64  // info
+        )?;
+    }
+    Ok(info)
+}
+
+/// Get a snapshot of the processes sorted by `pid`, starting from the `offset`th process.
+/// Returns the number of entries written, if it's less than `entries.len()`, there are no more processes.
+///
+/// # Safety
+/// This is generally safe, but its marked as unsafe because it's a syscall
+pub unsafe fn process_list(
+    offset: usize,
+    entries: &mut [ProcessInfo],
+) -> Result<usize, SyscallError> {
+    let entries_ptr = entries.as_mut_ptr() as u64;
+    unsafe {
+        call_syscall!(
+            SYS_PROCESS_LIST,
+            offset as u64,        // offset
+            entries_ptr,          // entries_ptr
+            entries.len() as u64  // len
+        )
+        .map(|written| written as usize)
+    }
+}
+
+/// Get the executable path, current directory and arguments of the process `pid`, written to `buf`
+/// as null terminated strings `path\0cwd\0argv[0]\0argv[1]\0...`.
+/// Returns the number of bytes written, or [`SyscallError::BufferTooSmall`] if it doesn't fit.
+///
+/// # Safety
+/// This is generally safe, it will return error if the pid is not valid, but its marked as unsafe
+/// because it's a syscall
+pub unsafe fn get_proc_info(pid: u64, buf: &mut [u8]) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_GET_PROC_INFO,
+            pid,                     // pid
+            buf.as_mut_ptr() as u64, // buf
+            buf.len() as u64         // len
+        )
+        .map(|written| written as usize)
+    }
+}
+
+/// Read the memory of the process `pid` at `addr` into `buf`, only allowed for the process itself,
+/// its parent, and `init`.
+/// Returns the number of bytes read, less than `buf.len()` if it reached memory that is not
+/// mapped for the process.
+///
+/// # Safety
+/// This is generally safe, it will return error if the pid or the address is not valid, but its
+/// marked as unsafe because it's a syscall
+pub unsafe fn proc_vm_read(pid: u64, addr: usize, buf: &mut [u8]) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_PROC_VM_READ,
+            pid,                     // pid
+            addr as u64,             // addr
+            buf.as_mut_ptr() as u64, // buf
+            buf.len() as u64         // len
+        )
+        .map(|read| read as usize)
+    }
+}
+
+/// Write `buf` to the memory of the process `pid` at `addr`, only allowed for the process itself,
+/// its parent, and `init`. Read-only memory can't be written.
+/// Returns the number of bytes written, less than `buf.len()` if it reached memory that is not
+/// mapped as writable for the process.
+///
+/// # Safety
+/// The caller must make sure that changing the memory of the process is fine, the kernel only
+/// checks the permissions
+pub unsafe fn proc_vm_write(pid: u64, addr: usize, buf: &[u8]) -> Result<usize, SyscallError> {
+    unsafe {
+        call_syscall!(
+            SYS_PROC_VM_WRITE,
+            pid,                 // pid
+            addr as u64,         // addr
+            buf.as_ptr() as u64, // buf
+            buf.len() as u64     // len
+        )
+        .map(|written| written as usize)
+    }
+}
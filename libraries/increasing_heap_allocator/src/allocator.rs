@@ -493,3 +493,256 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::Layout, vec::Vec};
+
+    use super::*;
+
+    const PAGE_SIZE: usize = 4096;
+    const MAX_PAGES: usize = 64;
+
+    /// Gives out pages from a fixed region, one after the other
+    struct TestPageAllocator {
+        base: *mut u8,
+        allocated_pages: usize,
+    }
+
+    impl TestPageAllocator {
+        fn region_layout() -> Layout {
+            Layout::from_size_align(PAGE_SIZE * MAX_PAGES, PAGE_SIZE).unwrap()
+        }
+
+        fn new() -> Self {
+            let base = unsafe { std::alloc::alloc(Self::region_layout()) };
+            assert!(!base.is_null());
+            Self {
+                base,
+                allocated_pages: 0,
+            }
+        }
+    }
+
+    impl Drop for TestPageAllocator {
+        fn drop(&mut self) {
+            unsafe { std::alloc::dealloc(self.base, Self::region_layout()) };
+        }
+    }
+
+    impl PageAllocatorProvider<PAGE_SIZE> for TestPageAllocator {
+        fn allocate_pages(&mut self, pages: usize) -> Option<*mut u8> {
+            if self.allocated_pages + pages > MAX_PAGES {
+                return None;
+            }
+            let start = unsafe { self.base.add(self.allocated_pages * PAGE_SIZE) };
+            self.allocated_pages += pages;
+            Some(start)
+        }
+
+        fn deallocate_pages(&mut self, pages: usize) -> bool {
+            if pages > self.allocated_pages {
+                return false;
+            }
+            self.allocated_pages -= pages;
+            true
+        }
+    }
+
+    fn new_heap() -> HeapAllocator<PAGE_SIZE, TestPageAllocator> {
+        HeapAllocator::new(TestPageAllocator::new())
+    }
+
+    fn assert_consistent(heap: &HeapAllocator<PAGE_SIZE, TestPageAllocator>) {
+        let stats = heap.stats();
+        assert_eq!(stats.allocated + stats.free_size, stats.heap_size);
+        assert_eq!(
+            heap.debug_free_blocks()
+                .map(|(_, size)| size)
+                .sum::<usize>(),
+            stats.free_size
+        );
+
+        // sorted, and never adjacent (they would have been merged)
+        let blocks = heap.debug_free_blocks().collect::<Vec<_>>();
+        for window in blocks.windows(2) {
+            let (prev_addr, prev_size) = window[0];
+            let (next_addr, _) = window[1];
+            assert!(prev_addr + prev_size < next_addr, "{blocks:x?}");
+        }
+        assert!(!heap.check_issues());
+    }
+
+    #[test]
+    fn empty_heap() {
+        let heap = new_heap();
+        let stats = heap.stats();
+        assert_eq!(stats.heap_size, 0);
+        assert_eq!(stats.allocated, 0);
+        assert_eq!(heap.debug_free_blocks().count(), 0);
+    }
+
+    #[test]
+    fn alloc_dealloc_restores_heap() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        let ptr = unsafe { heap.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!(is_aligned(ptr as usize, 8));
+        assert_eq!(heap.stats().heap_size, PAGE_SIZE);
+        assert!(heap.stats().allocated >= 100 + KERNEL_HEAP_BLOCK_INFO_SIZE);
+        assert_consistent(&heap);
+
+        // the memory is usable
+        unsafe { ptr.write_bytes(0xAA, 100) };
+
+        unsafe { heap.dealloc(ptr, layout) };
+        assert_eq!(heap.stats().allocated, 0);
+        assert_eq!(heap.debug_free_blocks().count(), 1);
+        assert_consistent(&heap);
+    }
+
+    #[test]
+    fn freed_blocks_are_merged() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(64, 16).unwrap();
+
+        let ptrs = (0..16)
+            .map(|_| unsafe { heap.alloc(layout) })
+            .collect::<Vec<_>>();
+        assert_consistent(&heap);
+
+        // free every other one, none of them are adjacent
+        for ptr in ptrs.iter().step_by(2) {
+            unsafe { heap.dealloc(*ptr, layout) };
+            assert_consistent(&heap);
+        }
+        assert!(heap.debug_free_blocks().count() > 1);
+
+        // free the rest, filling the gaps
+        for ptr in ptrs.iter().skip(1).step_by(2) {
+            unsafe { heap.dealloc(*ptr, layout) };
+            assert_consistent(&heap);
+        }
+        assert_eq!(heap.debug_free_blocks().count(), 1);
+        assert_eq!(heap.stats().allocated, 0);
+    }
+
+    #[test]
+    fn reuses_freed_memory() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(256, 16).unwrap();
+
+        let first = unsafe { heap.alloc(layout) };
+        let _second = unsafe { heap.alloc(layout) };
+        unsafe { heap.dealloc(first, layout) };
+
+        // best fit, goes into the hole left by `first`
+        let third = unsafe { heap.alloc(layout) };
+        assert_eq!(third, first);
+        assert_consistent(&heap);
+    }
+
+    #[test]
+    fn grows_with_more_pages() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(PAGE_SIZE * 3, 16).unwrap();
+
+        let ptr = unsafe { heap.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(heap.stats().heap_size, PAGE_SIZE * 4);
+        assert_consistent(&heap);
+
+        let small = Layout::from_size_align(PAGE_SIZE / 2, 16).unwrap();
+        let ptrs = (0..8)
+            .map(|_| unsafe { heap.alloc(small) })
+            .collect::<Vec<_>>();
+        assert!(heap.stats().heap_size > PAGE_SIZE * 4);
+        assert_consistent(&heap);
+
+        for ptr in ptrs {
+            unsafe { heap.dealloc(ptr, small) };
+        }
+        unsafe { heap.dealloc(ptr, layout) };
+        assert_eq!(heap.stats().allocated, 0);
+        assert_eq!(heap.debug_free_blocks().count(), 1);
+        assert_consistent(&heap);
+    }
+
+    #[test]
+    fn large_alignments() {
+        let mut heap = new_heap();
+
+        // a small allocation first, to make the next free block not aligned to the larger alignments
+        let small = Layout::from_size_align(8, 8).unwrap();
+        let small_ptr = unsafe { heap.alloc(small) };
+
+        let mut allocations = Vec::new();
+        for align in [16, 32, 64, 128, 256, 512, 1024] {
+            for size in [1, 48, 512, 1000] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { heap.alloc(layout) };
+                assert!(is_aligned(ptr as usize, align), "{ptr:p} {layout:?}");
+                unsafe { ptr.write_bytes(0x55, size) };
+                allocations.push((ptr, layout));
+                assert_consistent(&heap);
+            }
+        }
+
+        for (ptr, layout) in allocations.into_iter().rev() {
+            unsafe { heap.dealloc(ptr, layout) };
+            assert_consistent(&heap);
+        }
+        unsafe { heap.dealloc(small_ptr, small) };
+        assert_eq!(heap.stats().allocated, 0);
+        assert_consistent(&heap);
+    }
+
+    #[test]
+    fn allocations_dont_overlap() {
+        let mut heap = new_heap();
+        // simple deterministic sizes, mixing small and large
+        let layouts = (1..64)
+            .map(|i| Layout::from_size_align((i * 37) % 700 + 1, 1 << (i % 7)).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut allocations = layouts
+            .iter()
+            .map(|layout| (unsafe { heap.alloc(*layout) }, *layout))
+            .collect::<Vec<_>>();
+
+        let mut ranges = allocations
+            .iter()
+            .map(|(ptr, layout)| (*ptr as usize, *ptr as usize + layout.size()))
+            .collect::<Vec<_>>();
+        ranges.sort();
+        for window in ranges.windows(2) {
+            assert!(window[0].1 <= window[1].0, "{:x?}", window);
+        }
+
+        // free in a mixed order
+        while !allocations.is_empty() {
+            let index = (allocations.len() * 7 / 3) % allocations.len();
+            let (ptr, layout) = allocations.swap_remove(index);
+            unsafe { heap.dealloc(ptr, layout) };
+            assert_consistent(&heap);
+        }
+        assert_eq!(heap.stats().allocated, 0);
+    }
+
+    // caught either by the magic of the freed block, or by finding it in the free list
+    #[test]
+    #[should_panic]
+    fn double_free() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let first = unsafe { heap.alloc(layout) };
+        let _second = unsafe { heap.alloc(layout) };
+
+        unsafe {
+            heap.dealloc(first, layout);
+            heap.dealloc(first, layout);
+        }
+    }
+}
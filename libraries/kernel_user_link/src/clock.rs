@@ -5,6 +5,19 @@ pub struct ClockTime {
     pub nanoseconds: u32,
 }
 
+/// The time zone of the system, the local time is `utc + offset_seconds`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Offset of the local time from UTC in seconds
+    pub offset_seconds: i64,
+    /// `1` if the RTC holds the local time instead of UTC, changing the offset will move
+    /// the real time clock as well, `0` otherwise.
+    ///
+    /// Not a `bool` since the kernel reads it from user memory, any other value is rejected.
+    pub rtc_is_localtime: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub enum ClockType {
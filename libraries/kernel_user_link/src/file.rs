@@ -77,8 +77,22 @@ pub struct FileStat {
     pub file_type: FileType,
 }
 
+/// The size and free space of a filesystem, see `SYS_FS_STAT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+pub struct FileSystemStat {
+    /// Size of the allocation unit in bytes (cluster in FAT)
+    pub block_size: u64,
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+}
+
 pub const MAX_FILENAME_LEN: usize = 255;
 
+/// Used as the `dir_fd` argument of the `*at` syscalls (`SYS_OPENAT`, ...),
+/// to resolve relative paths from the current directory of the process
+pub const DIR_FD_CWD: usize = usize::MAX;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DirFilename([u8; MAX_FILENAME_LEN + 1]);
 
@@ -95,11 +109,15 @@ impl DirFilename {
 }
 
 impl From<&str> for DirFilename {
+    /// Names longer than `MAX_FILENAME_LEN - 1` bytes are truncated, without splitting
+    /// a UTF-8 character
     fn from(s: &str) -> Self {
         let mut name = [0; MAX_FILENAME_LEN + 1];
-        let bytes = s.as_bytes();
-        assert!(bytes.len() < MAX_FILENAME_LEN);
-        name[..bytes.len()].copy_from_slice(bytes);
+        let mut len = s.len().min(MAX_FILENAME_LEN - 1);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        name[..len].copy_from_slice(&s.as_bytes()[..len]);
         Self(name)
     }
 }
@@ -117,12 +135,71 @@ impl DirEntry {
     }
 }
 
+/// Flags for `SYS_UNMOUNT`
+pub mod unmount_flags {
+    /// Detach the filesystem immediately, even if it's in use, and unmount it
+    /// when the last file using it is closed
+    pub const LAZY: u64 = 1 << 0;
+    pub const ALL: u64 = LAZY;
+}
+
+pub mod watch_events {
+    pub const CREATED: u32 = 1 << 0;
+    pub const DELETED: u32 = 1 << 1;
+    pub const MODIFIED: u32 = 1 << 2;
+    pub const RENAMED: u32 = 1 << 3;
+    pub const ALL: u32 = CREATED | DELETED | MODIFIED | RENAMED;
+}
+
+/// An event read from a watch file, see `SYS_WATCH`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+pub struct WatchEvent {
+    /// One of [`watch_events`]
+    pub kind: u32,
+    /// The name of the entry inside the watched directory,
+    /// empty if the event is for the watched path itself
+    pub name: DirFilename,
+}
+
+impl WatchEvent {
+    pub fn filename_cstr(&self) -> &CStr {
+        self.name.as_cstr()
+    }
+}
+
+/// A buffer for vectored IO (`readv`/`writev`)
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct IoVec {
+    pub base: *mut u8,
+    pub len: usize,
+}
+
+/// The maximum number of buffers in a single vectored IO syscall
+pub const MAX_IO_VECS: usize = 1024;
+
+/// The capacity of a pipe when `SYS_CREATE_PIPE` is given `0`
+pub const PIPE_DEFAULT_CAPACITY: usize = 64 * 1024;
+/// Smaller capacities given to `SYS_CREATE_PIPE` are rounded up to this
+pub const PIPE_MIN_CAPACITY: usize = 4 * 1024;
+/// Bigger capacities given to `SYS_CREATE_PIPE` are invalid
+pub const PIPE_MAX_CAPACITY: usize = 1024 * 1024;
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub enum FileMeta {
     BlockingMode(BlockingMode) = 0,
     IsTerminal(bool) = 1,
+    /// The fd is not inherited by spawned processes (unless explicitly mapped to them)
+    CloseOnSpawn(bool) = 2,
+    /// The maximum nanoseconds a blocking read waits for data before failing with
+    /// [`SyscallError::TimedOut`](crate::syscalls::SyscallError::TimedOut), `0` waits forever
+    ReadTimeout(u64) = 3,
+    /// The device edits the input a line at a time (with echo and backspace), and only gives
+    /// complete lines to readers, only supported by the console terminals
+    LineEditing(bool) = 4,
 }
 
 impl FileMeta {
@@ -130,6 +207,9 @@ impl FileMeta {
         match self {
             FileMeta::BlockingMode(_) => 0,
             FileMeta::IsTerminal(_) => 1,
+            FileMeta::CloseOnSpawn(_) => 2,
+            FileMeta::ReadTimeout(_) => 3,
+            FileMeta::LineEditing(_) => 4,
         }
     }
 
@@ -137,6 +217,9 @@ impl FileMeta {
         match self {
             FileMeta::BlockingMode(mode) => mode.to_u64(),
             FileMeta::IsTerminal(is_terminal) => *is_terminal as u64,
+            FileMeta::CloseOnSpawn(close_on_spawn) => *close_on_spawn as u64,
+            FileMeta::ReadTimeout(nanos) => *nanos,
+            FileMeta::LineEditing(line_editing) => *line_editing as u64,
         }
     }
 }
@@ -148,6 +231,9 @@ impl TryFrom<(u64, u64)> for FileMeta {
         match value.0 {
             0 => Ok(FileMeta::BlockingMode(BlockingMode::try_from(value.1)?)),
             1 => Ok(FileMeta::IsTerminal(value.1 != 0)),
+            2 => Ok(FileMeta::CloseOnSpawn(value.1 != 0)),
+            3 => Ok(FileMeta::ReadTimeout(value.1)),
+            4 => Ok(FileMeta::LineEditing(value.1 != 0)),
             _ => Err(()),
         }
     }
@@ -197,6 +283,8 @@ impl OpenOptions {
     pub const CREATE_NEW: Self = Self(1 << 3);
     pub const TRUNCATE: Self = Self(1 << 4);
     pub const APPEND: Self = Self(1 << 5);
+    /// See [`FileMeta::CloseOnSpawn`]
+    pub const CLOSE_ON_SPAWN: Self = Self(1 << 6);
 
     pub fn new() -> Self {
         Self(0)
@@ -256,6 +344,15 @@ impl OpenOptions {
         self
     }
 
+    pub fn close_on_spawn(&mut self, close_on_spawn: bool) -> &mut Self {
+        if close_on_spawn {
+            self.0 |= Self::CLOSE_ON_SPAWN.0;
+        } else {
+            self.0 &= !Self::CLOSE_ON_SPAWN.0;
+        }
+        self
+    }
+
     pub fn is_read(&self) -> bool {
         self.0 & Self::READ.0 != 0
     }
@@ -280,13 +377,18 @@ impl OpenOptions {
         self.0 & Self::APPEND.0 != 0
     }
 
+    pub fn is_close_on_spawn(&self) -> bool {
+        self.0 & Self::CLOSE_ON_SPAWN.0 != 0
+    }
+
     pub fn from_u64(flags: u64) -> Option<Self> {
         let all = (Self::READ.0
             | Self::WRITE.0
             | Self::CREATE.0
             | Self::CREATE_NEW.0
             | Self::TRUNCATE.0
-            | Self::APPEND.0) as u64;
+            | Self::APPEND.0
+            | Self::CLOSE_ON_SPAWN.0) as u64;
 
         if flags & !all != 0 {
             return None;
@@ -333,3 +435,162 @@ impl ops::BitAndAssign for OpenOptions {
         *self = *self & rhs;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocking_mode_from_flags() {
+        assert_eq!(BlockingMode::from_flags(0), BlockingMode::None);
+        assert_eq!(BlockingMode::from_flags(1), BlockingMode::Line);
+        // only bit 0 is used
+        assert_eq!(BlockingMode::from_flags(0b110), BlockingMode::None);
+        assert_eq!(BlockingMode::from_flags(0b111), BlockingMode::Line);
+    }
+
+    #[test]
+    fn blocking_mode_round_trip() {
+        for mode in [
+            BlockingMode::None,
+            BlockingMode::Line,
+            BlockingMode::Block(1),
+            BlockingMode::Block(0x1234),
+            BlockingMode::Block(u32::MAX),
+        ] {
+            assert_eq!(BlockingMode::try_from(mode.to_u64()), Ok(mode));
+        }
+    }
+
+    #[test]
+    fn blocking_mode_invalid() {
+        // `Block` must have a non-zero size
+        assert_eq!(BlockingMode::try_from(3), Err(()));
+        // mode `2` is not used
+        assert_eq!(BlockingMode::try_from(2), Err(()));
+        // `None` and `Line` don't have extra data
+        assert_eq!(BlockingMode::try_from(1 << 2), Err(()));
+        assert_eq!(BlockingMode::try_from(1 << 2 | 1), Err(()));
+        // the size must fit in `u32`
+        assert_eq!(BlockingMode::try_from((1 << 32) << 2 | 3), Err(()));
+    }
+
+    #[test]
+    fn parse_open_flags() {
+        assert_eq!(parse_flags(0), Some(BlockingMode::None));
+        assert_eq!(parse_flags(1), Some(BlockingMode::Line));
+        assert_eq!(parse_flags(2), None);
+        assert_eq!(parse_flags(1 << 63 | 1), None);
+    }
+
+    #[test]
+    fn open_options_setters() {
+        let mut options = OpenOptions::new();
+        assert_eq!(options.to_u64(), 0);
+
+        options.read(true).write(true).create(true);
+        assert!(options.is_read());
+        assert!(options.is_write());
+        assert!(options.is_create());
+        assert!(!options.is_create_new());
+        assert!(!options.is_truncate());
+        assert!(!options.is_append());
+        assert!(!options.is_close_on_spawn());
+        assert_eq!(
+            options,
+            OpenOptions::READ | OpenOptions::WRITE | OpenOptions::CREATE
+        );
+
+        options.write(false).create(false);
+        assert_eq!(options, OpenOptions::READ);
+
+        options.create_new(true).truncate(true).close_on_spawn(true);
+        assert!(options.is_create_new());
+        assert!(options.is_truncate());
+        assert!(options.is_close_on_spawn());
+
+        options
+            .read(false)
+            .create_new(false)
+            .truncate(false)
+            .close_on_spawn(false);
+        assert_eq!(options, OpenOptions::new());
+    }
+
+    #[test]
+    fn open_options_append_implies_write() {
+        let mut options = OpenOptions::new();
+        options.append(true);
+        assert!(options.is_append());
+        assert!(options.is_write());
+        // the write bit itself is not set
+        assert_eq!(options & OpenOptions::WRITE, OpenOptions::new());
+
+        options.append(false);
+        assert!(!options.is_write());
+    }
+
+    #[test]
+    fn open_options_bit_ops() {
+        let mut options = OpenOptions::READ;
+        options |= OpenOptions::APPEND;
+        assert_eq!(options.to_u64(), 1 | 1 << 5);
+        options &= OpenOptions::APPEND;
+        assert_eq!(options, OpenOptions::APPEND);
+        assert_eq!(OpenOptions::default(), OpenOptions::READ);
+    }
+
+    #[test]
+    fn open_options_from_u64() {
+        for bits in 0..(1 << 7) {
+            let options = OpenOptions::from_u64(bits).unwrap();
+            assert_eq!(options.to_u64(), bits);
+        }
+        assert_eq!(OpenOptions::from_u64(1 << 7), None);
+        assert_eq!(OpenOptions::from_u64(1 << 8 | 1), None);
+        assert_eq!(OpenOptions::from_u64(u64::MAX), None);
+    }
+
+    #[test]
+    fn file_meta_round_trip() {
+        for meta in [
+            FileMeta::BlockingMode(BlockingMode::Block(16)),
+            FileMeta::IsTerminal(true),
+            FileMeta::CloseOnSpawn(false),
+            FileMeta::ReadTimeout(1_000_000),
+            FileMeta::LineEditing(true),
+        ] {
+            assert_eq!(
+                FileMeta::try_from((meta.to_u64_meta_id(), meta.inner_u64())),
+                Ok(meta)
+            );
+        }
+        assert_eq!(FileMeta::try_from((0, 2)), Err(()));
+        assert_eq!(FileMeta::try_from((5, 0)), Err(()));
+    }
+
+    #[test]
+    fn seek_whence() {
+        assert_eq!(SeekWhence::try_from(0), Ok(SeekWhence::Start));
+        assert_eq!(SeekWhence::try_from(1), Ok(SeekWhence::Current));
+        assert_eq!(SeekWhence::try_from(2), Ok(SeekWhence::End));
+        assert_eq!(SeekWhence::try_from(3), Err(()));
+    }
+
+    #[test]
+    fn dir_filename_truncates_on_char_boundary() {
+        let name = DirFilename::from("hello");
+        assert_eq!(name.as_cstr().to_bytes(), b"hello");
+
+        let long = "a".repeat(MAX_FILENAME_LEN * 2);
+        let name = DirFilename::from(long.as_str());
+        assert_eq!(name.as_cstr().to_bytes().len(), MAX_FILENAME_LEN - 1);
+
+        // `é` is 2 bytes, and the limit falls in the middle of the last one
+        let long = format!("a{}", "é".repeat(MAX_FILENAME_LEN));
+        let name = DirFilename::from(long.as_str());
+        let bytes = name.as_cstr().to_bytes();
+        assert_eq!(bytes.len(), MAX_FILENAME_LEN - 2);
+        assert!(core::str::from_utf8(bytes).is_ok());
+    }
+}
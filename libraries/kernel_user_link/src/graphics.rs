@@ -15,6 +15,22 @@ pub enum GraphicsCommand {
     /// (must have ownership of the graphics device)
     /// &BlitCommand
     Blit,
+    /// Release the ownership from another process, only allowed for `init` (pid 0) and the parent
+    /// of the owner
+    /// No arguments
+    ForceRelease,
+    /// Copy the currently displayed frame into userspace memory, doesn't require ownership
+    /// The format is packed `RGB`, 3 bytes per pixel, rows from top to bottom without padding
+    /// &CaptureCommand
+    CaptureFrameBuffer,
+    /// Fill a region of the graphics framebuffer with a single color
+    /// (must have ownership of the graphics device)
+    /// &FillRectCommand
+    FillRect,
+    /// Copy a region of the graphics framebuffer into another position in it, the regions can overlap
+    /// (must have ownership of the graphics device)
+    /// &CopyRectCommand
+    CopyRect,
 }
 
 impl GraphicsCommand {
@@ -24,6 +40,10 @@ impl GraphicsCommand {
             1 => Some(Self::ReleaseOwnership),
             2 => Some(Self::GetFrameBufferInfo),
             3 => Some(Self::Blit),
+            4 => Some(Self::ForceRelease),
+            5 => Some(Self::CaptureFrameBuffer),
+            6 => Some(Self::FillRect),
+            7 => Some(Self::CopyRect),
             _ => None,
         }
     }
@@ -44,12 +64,32 @@ pub struct FrameBufferInfo {
     pub byte_per_pixel: u8,
 }
 
+/// The number of bytes per pixel in the output of [`GraphicsCommand::CaptureFrameBuffer`]
+pub const CAPTURE_BYTES_PER_PIXEL: usize = 3;
+
 impl FrameBufferInfo {
     /// The size of the memory buffer required to hold the framebuffer
     pub fn memory_size(&self) -> usize {
         self.pitch * self.height
     }
 
+    /// The size of the memory buffer required to capture the framebuffer
+    /// with [`GraphicsCommand::CaptureFrameBuffer`]
+    pub fn capture_size(&self) -> usize {
+        self.width * self.height * CAPTURE_BYTES_PER_PIXEL
+    }
+
+    /// Check that the region at `pos` with `size` (width, height) is inside the framebuffer
+    pub fn contains_rect(&self, pos: (usize, usize), size: (usize, usize)) -> bool {
+        pos.0
+            .checked_add(size.0)
+            .is_some_and(|end| end <= self.width)
+            && pos
+                .1
+                .checked_add(size.1)
+                .is_some_and(|end| end <= self.height)
+    }
+
     /// Get the position in the memory buffer for a given pixel
     /// Returns None if the position is out of bounds
     pub fn get_arr_pos(&self, pos: (usize, usize)) -> Option<usize> {
@@ -92,4 +132,62 @@ pub struct BlitCommand {
     pub dst: (usize, usize),
     /// The size of the region to blit (width, height)
     pub size: (usize, usize),
+    /// The dirty rectangles to blit, in the source framebuffer, each must be inside the region
+    /// of `src` and `size`, and is copied to the same offset from `dst`.
+    /// If `damage_len` is `0`, the whole region is copied
+    pub damage: *const Rect,
+    pub damage_len: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    /// The top-left corner (x, y)
+    pub pos: (usize, usize),
+    /// (width, height)
+    pub size: (usize, usize),
+}
+
+impl Rect {
+    /// Check that this rectangle is inside the region at `pos` with `size` (width, height)
+    pub fn is_inside(&self, pos: (usize, usize), size: (usize, usize)) -> bool {
+        self.pos.0 >= pos.0
+            && self.pos.1 >= pos.1
+            && self.pos.0 - pos.0 <= size.0
+            && self.pos.1 - pos.1 <= size.1
+            && self.size.0 <= size.0 - (self.pos.0 - pos.0)
+            && self.size.1 <= size.1 - (self.pos.1 - pos.1)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureCommand {
+    /// The memory buffer to write the frame into,
+    /// see [`GraphicsCommand::CaptureFrameBuffer`] for the format
+    pub memory: *mut u8,
+    /// The size of `memory`, must be at least [`FrameBufferInfo::capture_size`]
+    pub len: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FillRectCommand {
+    /// The position in the graphics framebuffer to start filling from
+    pub dst: (usize, usize),
+    /// The size of the region to fill (width, height)
+    pub size: (usize, usize),
+    /// The color to fill with (r, g, b)
+    pub color: (u8, u8, u8),
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CopyRectCommand {
+    /// The position in the graphics framebuffer to copy from
+    pub src: (usize, usize),
+    /// The position in the graphics framebuffer to copy to
+    pub dst: (usize, usize),
+    /// The size of the region to copy (width, height)
+    pub size: (usize, usize),
 }
@@ -195,10 +195,14 @@ pub struct Key {
     pub pressed: bool,
     pub modifiers: u8,
     pub key_type: KeyType,
+    /// Nanoseconds since boot when the key interrupt was handled, same as [`ClockType::SystemTime`](crate::clock::ClockType::SystemTime)
+    pub timestamp: u64,
+    /// Increases by one for each key event, a gap means the reader missed events
+    pub sequence: u32,
 }
 
 impl Key {
-    pub const BYTES_SIZE: usize = 2;
+    pub const BYTES_SIZE: usize = 14;
 
     /// # Safety
     /// The `bytes` must be a valid representation of a `Key`
@@ -209,22 +213,48 @@ impl Key {
         // Safety: we know that the `bytes` is a valid representation of `KeyType`
         //         responsibility of the caller to ensure that
         let key_type = core::mem::transmute(bytes[1]);
+        let timestamp = u64::from_le_bytes(bytes[2..10].try_into().unwrap());
+        let sequence = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
 
         Self {
             pressed,
             modifiers,
             key_type,
+            timestamp,
+            sequence,
         }
     }
 
-    pub fn as_bytes(&self) -> [u8; 2] {
-        let mut bytes = [0; 2];
+    pub fn as_bytes(&self) -> [u8; Self::BYTES_SIZE] {
+        let mut bytes = [0; Self::BYTES_SIZE];
         bytes[0] = (self.modifiers & !modifier::PRESSED)
             | if self.pressed { modifier::PRESSED } else { 0 };
         bytes[1] = self.key_type as u8;
+        bytes[2..10].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[10..14].copy_from_slice(&self.sequence.to_le_bytes());
         bytes
     }
 
+    /// An event telling the reader that `dropped` events were lost because it didn't read fast enough,
+    /// it comes before the events that came after the drop.
+    ///
+    /// The `sequence` of the marker is the number of dropped events, and `timestamp` is the time of
+    /// the last dropped event
+    pub fn overflow_marker(timestamp: u64, dropped: u32) -> Self {
+        Self {
+            pressed: false,
+            modifiers: 0,
+            // not a valid key
+            key_type: KeyType::_None1,
+            timestamp,
+            sequence: dropped,
+        }
+    }
+
+    pub fn is_overflow_marker(&self) -> bool {
+        self.key_type == KeyType::_None1
+    }
+
     pub fn virtual_char(&self) -> Option<u8> {
         let shifted = self.modifiers & modifier::SHIFT != 0;
         self.key_type.virtual_key(shifted)
@@ -24,10 +24,14 @@ pub struct MouseEvent {
     pub y: i16,
     pub scroll_type: ScrollType,
     pub buttons: u8,
+    /// Nanoseconds since boot when the mouse interrupt was handled, same as [`ClockType::SystemTime`](crate::clock::ClockType::SystemTime)
+    pub timestamp: u64,
+    /// Increases by one for each mouse event, a gap means the reader missed events
+    pub sequence: u32,
 }
 
 impl MouseEvent {
-    pub const BYTES_SIZE: usize = 5;
+    pub const BYTES_SIZE: usize = 17;
 
     /// # Safety
     /// The `bytes` must be a valid representation of a `MouseEvent`
@@ -44,15 +48,40 @@ impl MouseEvent {
             4 => ScrollType::HorizontalNegative,
             _ => panic!("invalid scroll type"),
         };
+        let timestamp = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+        let sequence = u32::from_le_bytes(bytes[13..17].try_into().unwrap());
 
         Self {
             x,
             y,
             buttons,
             scroll_type,
+            timestamp,
+            sequence,
         }
     }
 
+    /// An event telling the reader that `dropped` events were lost because it didn't read fast enough,
+    /// it comes before the events that came after the drop.
+    ///
+    /// The `sequence` of the marker is the number of dropped events, and `timestamp` is the time of
+    /// the last dropped event
+    pub fn overflow_marker(timestamp: u64, dropped: u32) -> Self {
+        Self {
+            // the mouse movement is 9 bits, so this can't be a real event
+            x: i16::MIN,
+            y: i16::MIN,
+            scroll_type: ScrollType::None,
+            buttons: 0,
+            timestamp,
+            sequence: dropped,
+        }
+    }
+
+    pub fn is_overflow_marker(&self) -> bool {
+        self.x == i16::MIN && self.y == i16::MIN
+    }
+
     pub fn as_bytes(&self) -> [u8; Self::BYTES_SIZE] {
         let mut bytes = [0; Self::BYTES_SIZE];
 
@@ -68,6 +97,9 @@ impl MouseEvent {
         let scroll_type = self.scroll_type as u8;
         bytes[4] = self.buttons & 0b11111 | (scroll_type << 5);
 
+        bytes[5..13].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[13..17].copy_from_slice(&self.sequence.to_le_bytes());
+
         bytes
     }
 }
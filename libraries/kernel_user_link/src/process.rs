@@ -1,3 +1,7 @@
+use core::ffi::CStr;
+
+use crate::file::DirFilename;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct SpawnFileMapping {
@@ -5,11 +9,142 @@ pub struct SpawnFileMapping {
     pub dst_fd: usize,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub mod spawn_redirect {
+    /// Create the file if needed, and truncate it
+    pub const FILE_TRUNCATE: u64 = 0;
+    /// Create the file if needed, and append to it
+    pub const FILE_APPEND: u64 = 1;
+    /// Create a pipe, the child gets the write end, and the read end is added to the parent
+    pub const PIPE: u64 = 2;
+}
+
+/// Connect `dst_fd` (stdout or stderr) of the spawned process to a newly opened file or pipe,
+/// done by the kernel at spawn time
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SpawnStdioRedirect {
+    pub dst_fd: usize,
+    /// One of [`spawn_redirect`]
+    pub kind: u64,
+    /// Null terminated path for the file kinds, relative paths use the parent's current directory
+    pub path: *const u8,
+    /// For [`spawn_redirect::PIPE`], the kernel writes here the fd of the read end in the parent
+    pub pipe_read_fd: usize,
+}
+
+impl SpawnStdioRedirect {
+    pub fn file(dst_fd: usize, path: &core::ffi::CStr, append: bool) -> Self {
+        Self {
+            dst_fd,
+            kind: if append {
+                spawn_redirect::FILE_APPEND
+            } else {
+                spawn_redirect::FILE_TRUNCATE
+            },
+            path: path.as_ptr() as _,
+            pipe_read_fd: 0,
+        }
+    }
+
+    pub fn pipe(dst_fd: usize) -> Self {
+        Self {
+            dst_fd,
+            kind: spawn_redirect::PIPE,
+            path: core::ptr::null(),
+            pipe_read_fd: 0,
+        }
+    }
+}
+
+/// Size of the first version of [`SpawnOptions`], the smallest size accepted by the kernel
+pub const SPAWN_OPTIONS_SIZE_V1: usize = 9 * 8;
+
+/// Arguments of the `spawn` syscall, passed by pointer.
+///
+/// `size` must be set to `size_of::<SpawnOptions>()` of the version the program was built with.
+/// New fields are only added at the end, and the kernel uses the default (zero) value for fields
+/// not covered by `size`. A `size` bigger than what the kernel knows is accepted only if the
+/// unknown fields are all zero.
+///
+/// The pointer fields that are null (or zero lengths) are defaults, see each field.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SpawnOptions {
+    pub size: u64,
+    /// Null terminated path of the executable, relative paths use the parent's current directory
+    pub path: *const u8,
+    /// Null terminated array of null terminated strings
+    pub argv: *const *const u8,
+    /// Files moved from the parent to the child, the parent loses these fds
+    pub file_mappings: *const SpawnFileMapping,
+    pub file_mappings_len: usize,
+    /// The kernel writes back `pipe_read_fd` for pipe redirects
+    pub redirects: *mut SpawnStdioRedirect,
+    pub redirects_len: usize,
+    /// Null terminated array of null terminated `KEY=VALUE` strings, inherited from the parent if null
+    pub env: *const *const u8,
+    /// Null terminated path of the current directory, inherited from the parent if null,
+    /// relative paths use the parent's current directory
+    pub current_dir: *const u8,
+    // the fields below are not included in `SPAWN_OPTIONS_SIZE_V1`
+    /// A [`PriorityLevel`] value, inherited from the parent if `0`, can't be higher than the parent's
+    pub priority: u64,
+    /// A [`SchedulingClass`] value, inherited from the parent if `0`, a [`SchedulingClass::Batch`]
+    /// parent can only spawn batch children
+    pub scheduling_class: u64,
+}
+
+impl SpawnOptions {
+    pub fn new(path: &CStr, argv: *const *const u8) -> Self {
+        Self {
+            path: path.as_ptr() as _,
+            argv,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for SpawnOptions {
+    fn default() -> Self {
+        Self {
+            size: core::mem::size_of::<Self>() as u64,
+            path: core::ptr::null(),
+            argv: core::ptr::null(),
+            file_mappings: core::ptr::null(),
+            file_mappings_len: 0,
+            redirects: core::ptr::null_mut(),
+            redirects_len: 0,
+            env: core::ptr::null(),
+            current_dir: core::ptr::null(),
+            priority: 0,
+            scheduling_class: 0,
+        }
+    }
+}
+
+/// The exit code of a process terminated by the `kill` syscall, the same as shells report for `SIGKILL`
+pub const KILLED_EXIT_CODE: i32 = 128 + 9;
+/// The exit code of a process terminated by an invalid memory access (i.e. page fault or general protection fault),
+/// the same as shells report for `SIGSEGV`
+pub const SEGMENTATION_FAULT_EXIT_CODE: i32 = 128 + 11;
+/// The exit code of a process terminated by an invalid instruction, the same as shells report for `SIGILL`
+pub const ILLEGAL_INSTRUCTION_EXIT_CODE: i32 = 128 + 4;
+/// The exit code of a process terminated by an arithmetic error (i.e. division by zero or floating point exception),
+/// the same as shells report for `SIGFPE`
+pub const ARITHMETIC_ERROR_EXIT_CODE: i32 = 128 + 8;
+/// The exit code of a process terminated by a misaligned access or a stack segment fault, the same as shells
+/// report for `SIGBUS`
+pub const BUS_ERROR_EXIT_CODE: i32 = 128 + 7;
+/// The exit code of a process terminated by a debug exception while not being debugged, the same as shells
+/// report for `SIGTRAP`
+pub const TRAP_EXIT_CODE: i32 = 128 + 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[repr(u8)]
 pub enum PriorityLevel {
     VeryLow = 1,
     Low = 2,
+    #[default]
     Normal = 3,
     High = 4,
     VeryHigh = 5,
@@ -32,6 +167,108 @@ impl PriorityLevel {
     }
 }
 
+/// How the scheduler treats a process, in addition to its [`PriorityLevel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum SchedulingClass {
+    #[default]
+    Normal = 1,
+    /// For non interactive background work, gets a smaller share of the CPU than a
+    /// normal process with the same priority
+    Batch = 2,
+}
+
+impl SchedulingClass {
+    pub fn from_u64(value: u64) -> Option<Self> {
+        match value {
+            1 => Some(Self::Normal),
+            2 => Some(Self::Batch),
+            _ => None,
+        }
+    }
+
+    pub fn to_u64(self) -> u64 {
+        self as u64
+    }
+}
+
+/// Affinity mask allowing the process to run on all CPUs, the default for new processes
+pub const ALL_CPUS_AFFINITY: u64 = u64::MAX;
+
+/// Memory usage of a single process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ProcessMemoryStats {
+    /// All user pages mapped for the process, this include the heap, stack, and the executable
+    pub resident_pages: u64,
+    /// In bytes
+    pub heap_size: u64,
+    /// In bytes
+    pub stack_size: u64,
+    /// Pages mapped from the executable file
+    pub file_mapped_pages: u64,
+}
+
+/// Result of the `meminfo` syscall, contains the system wide physical memory usage
+/// and the memory usage of the requested process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct MemInfo {
+    pub page_size: u64,
+    pub total_pages: u64,
+    pub free_pages: u64,
+    pub process: ProcessMemoryStats,
+}
+
+impl MemInfo {
+    pub fn used_pages(&self) -> u64 {
+        self.total_pages - self.free_pages
+    }
+}
+
+/// State of a process in the scheduler, see [`ProcessInfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u64)]
+pub enum ProcessState {
+    /// Currently running on a CPU
+    Running = 0,
+    /// Ready to run, waiting for its turn
+    #[default]
+    Scheduled = 1,
+    /// Waiting for another process to exit
+    WaitingForPid = 2,
+    /// Sleeping until some time
+    Sleeping = 3,
+    /// Waiting for a device to have data, i.e. reading the console
+    WaitingForIo = 4,
+}
+
+/// An entry returned by the `process_list` syscall, a snapshot of a process at the time of the call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ProcessInfo {
+    pub pid: u64,
+    pub parent_id: u64,
+    pub state: ProcessState,
+    pub priority: PriorityLevel,
+    /// Time spent running, in nanoseconds
+    pub cpu_time: u64,
+    /// In bytes
+    pub heap_size: u64,
+    /// Number of open file descriptors
+    pub open_files: u64,
+    /// Bitmask of the CPUs the process is allowed to run on
+    pub affinity: u64,
+    /// The file name of the executable
+    pub name: DirFilename,
+}
+
+impl ProcessInfo {
+    pub fn name_cstr(&self) -> &CStr {
+        self.name.as_cstr()
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ProcessMetadata {
     pub pid: u64,
@@ -42,6 +279,9 @@ pub struct ProcessMetadata {
     pub eh_frame_size: usize,
     pub text_address: usize,
     pub text_size: usize,
+    /// Address of the null terminated array of `KEY=VALUE` environment strings, placed in the stack
+    /// on process creation, this is also passed in `rdx` to the entry point
+    pub envp: usize,
 }
 
 impl ProcessMetadata {
@@ -6,7 +6,7 @@ mod types_conversions;
 /// user-kernel
 pub const SYSCALL_INTERRUPT_NUMBER: u8 = 0xFE;
 
-pub const NUM_SYSCALLS: usize = 22;
+pub const NUM_SYSCALLS: usize = 50;
 
 mod numbers {
     pub const SYS_OPEN: u64 = 0;
@@ -32,6 +32,34 @@ mod numbers {
     pub const SYS_GRAPHICS: u64 = 19;
     pub const SYS_SEEK: u64 = 20;
     pub const SYS_PRIORITY: u64 = 21;
+    pub const SYS_MEMINFO: u64 = 22;
+    pub const SYS_DUP: u64 = 23;
+    pub const SYS_DUP2: u64 = 24;
+    pub const SYS_READV: u64 = 25;
+    pub const SYS_WRITEV: u64 = 26;
+    pub const SYS_PREAD: u64 = 27;
+    pub const SYS_PWRITE: u64 = 28;
+    pub const SYS_TRUNCATE: u64 = 29;
+    pub const SYS_FTRUNCATE: u64 = 30;
+    pub const SYS_FALLOCATE: u64 = 31;
+    pub const SYS_FSYNC: u64 = 32;
+    pub const SYS_SYNC: u64 = 33;
+    pub const SYS_WATCH: u64 = 34;
+    pub const SYS_REALPATH: u64 = 35;
+    pub const SYS_OPENAT: u64 = 36;
+    pub const SYS_STATAT: u64 = 37;
+    pub const SYS_OPEN_DIR_AT: u64 = 38;
+    pub const SYS_PROCESS_LIST: u64 = 39;
+    pub const SYS_GET_PROC_INFO: u64 = 40;
+    pub const SYS_TIMEZONE: u64 = 41;
+    pub const SYS_SLEEP_UNTIL: u64 = 42;
+    pub const SYS_SET_AFFINITY: u64 = 43;
+    pub const SYS_FS_STAT: u64 = 44;
+    pub const SYS_KILL: u64 = 45;
+    pub const SYS_UNMOUNT: u64 = 46;
+    pub const SYS_SPLICE: u64 = 47;
+    pub const SYS_PROC_VM_READ: u64 = 48;
+    pub const SYS_PROC_VM_WRITE: u64 = 49;
 }
 pub use numbers::*;
 
@@ -207,6 +235,7 @@ pub enum SyscallArgError {
     InvalidHeapIncrement = 4,
     DuplicateFileMappings = 5,
     InvalidNanoseconds = 6,
+    InvalidStructSize = 7,
 }
 
 impl SyscallArgError {
@@ -219,11 +248,65 @@ impl SyscallArgError {
             4 => Ok(Some(SyscallArgError::InvalidHeapIncrement)),
             5 => Ok(Some(SyscallArgError::DuplicateFileMappings)),
             6 => Ok(Some(SyscallArgError::InvalidNanoseconds)),
+            7 => Ok(Some(SyscallArgError::InvalidStructSize)),
             _ => Err(()),
         }
     }
 }
 
+/// Why an executable was rejected, the payload of [`SyscallError::CouldNotLoadElf`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum ElfLoadErrorReason {
+    /// Not known, i.e. the error was converted back from only its code
+    Unknown = 0,
+    InvalidMagic = 1,
+    /// Not a 64bit little endian `x86_64` executable, or the header is invalid
+    NotSupported = 2,
+    UnexpectedEndOfFile = 3,
+    /// A table or segment points outside the file
+    OutOfFileBounds = 4,
+    InvalidSectionName = 5,
+    /// A segment has more data in the file than in memory, or its size overflows
+    InvalidSegmentSize = 6,
+    InvalidAlignment = 7,
+    /// A segment is mapped outside the user range
+    InvalidAddress = 8,
+    OverlappingSegments = 9,
+    /// The entry point is not inside a loaded segment
+    InvalidEntryPoint = 10,
+    NoLoadSegments = 11,
+    /// The program headers segment is not inside a loaded segment
+    InvalidProgramHeaderAddress = 12,
+    IntegrityCheckFailed = 13,
+}
+
+impl ElfLoadErrorReason {
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::InvalidMagic,
+            2 => Self::NotSupported,
+            3 => Self::UnexpectedEndOfFile,
+            4 => Self::OutOfFileBounds,
+            5 => Self::InvalidSectionName,
+            6 => Self::InvalidSegmentSize,
+            7 => Self::InvalidAlignment,
+            8 => Self::InvalidAddress,
+            9 => Self::OverlappingSegments,
+            10 => Self::InvalidEntryPoint,
+            11 => Self::NoLoadSegments,
+            12 => Self::InvalidProgramHeaderAddress,
+            13 => Self::IntegrityCheckFailed,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// The error of a syscall
+///
+/// Each error has a stable code (see [`SyscallError::code`]), which is what crosses the
+/// user-kernel boundary, and some carry a payload with more details.
 #[repr(align(8))]
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -235,7 +318,8 @@ pub enum SyscallError {
     InvalidFileIndex = 3,
     CouldNotWriteToFile = 4,
     CouldNotReadFromFile = 5,
-    CouldNotLoadElf = 6,
+    /// The executable is malformed or not supported, contains the reason
+    CouldNotLoadElf(ElfLoadErrorReason) = 6,
     CouldNotAllocateProcess = 7,
     HeapRangesExceeded = 8,
     EndOfFile = 9,
@@ -244,7 +328,8 @@ pub enum SyscallError {
     ProcessStillRunning = 12,
     IsNotDirectory = 13,
     IsDirectory = 14,
-    BufferTooSmall = 15,
+    /// The buffer given is too small, contains the size needed in bytes
+    BufferTooSmall(usize) = 15,
     GraphicsNotAvailable = 16,
     GraphicsAlreadyTaken = 17,
     GraphicsNotOwned = 18,
@@ -252,6 +337,22 @@ pub enum SyscallError {
     InvalidOffset = 20,
     AlreadyExists = 21,
     OperationNotSupported = 22,
+    /// A blocking read didn't get data within its [`ReadTimeout`](crate::file::FileMeta::ReadTimeout)
+    TimedOut = 23,
+    /// Seeking (or reading/writing at an offset) on a stream, i.e. a pipe or the console
+    NotSeekable = 24,
+    /// The process is not allowed to do this, e.g. spawn a child with a higher priority than its own
+    PermissionDenied = 25,
+    /// The resource is in use, e.g. unmounting a filesystem with open files
+    Busy = 26,
+    /// The device failed, or the filesystem on it is corrupted
+    IoError = 27,
+    /// No space left on the filesystem
+    NoSpace = 28,
+    /// The path is not valid, i.e. has empty components or goes above the root
+    InvalidPath = 29,
+    /// The device or partition table needed is not present
+    NoDevice = 30,
     InvalidArgument(
         Option<SyscallArgError>,
         Option<SyscallArgError>,
@@ -260,7 +361,135 @@ pub enum SyscallError {
         Option<SyscallArgError>,
         Option<SyscallArgError>,
         Option<SyscallArgError>,
-    ),
+    ) = 31,
+}
+
+/// The stable codes of [`SyscallError`], these never change, new errors get new codes
+pub mod error_codes {
+    pub const INVALID_ARGUMENT: u8 = 0;
+    pub const INVALID_ERROR: u8 = 1;
+    pub const COULD_NOT_OPEN_FILE: u8 = 2;
+    pub const INVALID_FILE_INDEX: u8 = 3;
+    pub const COULD_NOT_WRITE_TO_FILE: u8 = 4;
+    pub const COULD_NOT_READ_FROM_FILE: u8 = 5;
+    pub const COULD_NOT_LOAD_ELF: u8 = 6;
+    pub const COULD_NOT_ALLOCATE_PROCESS: u8 = 7;
+    pub const HEAP_RANGES_EXCEEDED: u8 = 8;
+    pub const END_OF_FILE: u8 = 9;
+    pub const FILE_NOT_FOUND: u8 = 10;
+    pub const PID_NOT_FOUND: u8 = 11;
+    pub const PROCESS_STILL_RUNNING: u8 = 12;
+    pub const IS_NOT_DIRECTORY: u8 = 13;
+    pub const IS_DIRECTORY: u8 = 14;
+    pub const BUFFER_TOO_SMALL: u8 = 15;
+    pub const GRAPHICS_NOT_AVAILABLE: u8 = 16;
+    pub const GRAPHICS_ALREADY_TAKEN: u8 = 17;
+    pub const GRAPHICS_NOT_OWNED: u8 = 18;
+    pub const INVALID_GRAPHICS_BUFFER: u8 = 19;
+    pub const INVALID_OFFSET: u8 = 20;
+    pub const ALREADY_EXISTS: u8 = 21;
+    pub const OPERATION_NOT_SUPPORTED: u8 = 22;
+    pub const TIMED_OUT: u8 = 23;
+    pub const NOT_SEEKABLE: u8 = 24;
+    pub const PERMISSION_DENIED: u8 = 25;
+    pub const BUSY: u8 = 26;
+    pub const IO_ERROR: u8 = 27;
+    pub const NO_SPACE: u8 = 28;
+    pub const INVALID_PATH: u8 = 29;
+    pub const NO_DEVICE: u8 = 30;
+    /// All bits set, as the whole result of a missing syscall is `-1`
+    pub const SYSCALL_NOT_FOUND: u8 = 0x7F;
+}
+
+/// The payload of an error, the bits below the code
+const ERROR_PAYLOAD_MASK: u64 = (1 << 56) - 1;
+
+impl SyscallError {
+    /// The stable code of this error, see [`error_codes`]
+    pub const fn code(&self) -> u8 {
+        match self {
+            SyscallError::SyscallNotFound => error_codes::SYSCALL_NOT_FOUND,
+            SyscallError::InvalidError => error_codes::INVALID_ERROR,
+            SyscallError::CouldNotOpenFile => error_codes::COULD_NOT_OPEN_FILE,
+            SyscallError::InvalidFileIndex => error_codes::INVALID_FILE_INDEX,
+            SyscallError::CouldNotWriteToFile => error_codes::COULD_NOT_WRITE_TO_FILE,
+            SyscallError::CouldNotReadFromFile => error_codes::COULD_NOT_READ_FROM_FILE,
+            SyscallError::CouldNotLoadElf(_) => error_codes::COULD_NOT_LOAD_ELF,
+            SyscallError::CouldNotAllocateProcess => error_codes::COULD_NOT_ALLOCATE_PROCESS,
+            SyscallError::HeapRangesExceeded => error_codes::HEAP_RANGES_EXCEEDED,
+            SyscallError::EndOfFile => error_codes::END_OF_FILE,
+            SyscallError::FileNotFound => error_codes::FILE_NOT_FOUND,
+            SyscallError::PidNotFound => error_codes::PID_NOT_FOUND,
+            SyscallError::ProcessStillRunning => error_codes::PROCESS_STILL_RUNNING,
+            SyscallError::IsNotDirectory => error_codes::IS_NOT_DIRECTORY,
+            SyscallError::IsDirectory => error_codes::IS_DIRECTORY,
+            SyscallError::BufferTooSmall(_) => error_codes::BUFFER_TOO_SMALL,
+            SyscallError::GraphicsNotAvailable => error_codes::GRAPHICS_NOT_AVAILABLE,
+            SyscallError::GraphicsAlreadyTaken => error_codes::GRAPHICS_ALREADY_TAKEN,
+            SyscallError::GraphicsNotOwned => error_codes::GRAPHICS_NOT_OWNED,
+            SyscallError::InvalidGraphicsBuffer => error_codes::INVALID_GRAPHICS_BUFFER,
+            SyscallError::InvalidOffset => error_codes::INVALID_OFFSET,
+            SyscallError::AlreadyExists => error_codes::ALREADY_EXISTS,
+            SyscallError::OperationNotSupported => error_codes::OPERATION_NOT_SUPPORTED,
+            SyscallError::TimedOut => error_codes::TIMED_OUT,
+            SyscallError::NotSeekable => error_codes::NOT_SEEKABLE,
+            SyscallError::PermissionDenied => error_codes::PERMISSION_DENIED,
+            SyscallError::Busy => error_codes::BUSY,
+            SyscallError::IoError => error_codes::IO_ERROR,
+            SyscallError::NoSpace => error_codes::NO_SPACE,
+            SyscallError::InvalidPath => error_codes::INVALID_PATH,
+            SyscallError::NoDevice => error_codes::NO_DEVICE,
+            SyscallError::InvalidArgument(..) => error_codes::INVALID_ARGUMENT,
+        }
+    }
+
+    /// The error of `code`, without the payload, so [`SyscallError::InvalidArgument`] has no
+    /// arguments, [`SyscallError::BufferTooSmall`] needs `0` and [`SyscallError::CouldNotLoadElf`]
+    /// has an [`Unknown`](ElfLoadErrorReason::Unknown) reason.
+    ///
+    /// Used to convert back from an error that only kept the code, i.e. an `errno`
+    pub const fn from_code(code: u8) -> Option<Self> {
+        let err = match code {
+            error_codes::INVALID_ARGUMENT => {
+                SyscallError::InvalidArgument(None, None, None, None, None, None, None)
+            }
+            error_codes::INVALID_ERROR => SyscallError::InvalidError,
+            error_codes::COULD_NOT_OPEN_FILE => SyscallError::CouldNotOpenFile,
+            error_codes::INVALID_FILE_INDEX => SyscallError::InvalidFileIndex,
+            error_codes::COULD_NOT_WRITE_TO_FILE => SyscallError::CouldNotWriteToFile,
+            error_codes::COULD_NOT_READ_FROM_FILE => SyscallError::CouldNotReadFromFile,
+            error_codes::COULD_NOT_LOAD_ELF => {
+                SyscallError::CouldNotLoadElf(ElfLoadErrorReason::Unknown)
+            }
+            error_codes::COULD_NOT_ALLOCATE_PROCESS => SyscallError::CouldNotAllocateProcess,
+            error_codes::HEAP_RANGES_EXCEEDED => SyscallError::HeapRangesExceeded,
+            error_codes::END_OF_FILE => SyscallError::EndOfFile,
+            error_codes::FILE_NOT_FOUND => SyscallError::FileNotFound,
+            error_codes::PID_NOT_FOUND => SyscallError::PidNotFound,
+            error_codes::PROCESS_STILL_RUNNING => SyscallError::ProcessStillRunning,
+            error_codes::IS_NOT_DIRECTORY => SyscallError::IsNotDirectory,
+            error_codes::IS_DIRECTORY => SyscallError::IsDirectory,
+            error_codes::BUFFER_TOO_SMALL => SyscallError::BufferTooSmall(0),
+            error_codes::GRAPHICS_NOT_AVAILABLE => SyscallError::GraphicsNotAvailable,
+            error_codes::GRAPHICS_ALREADY_TAKEN => SyscallError::GraphicsAlreadyTaken,
+            error_codes::GRAPHICS_NOT_OWNED => SyscallError::GraphicsNotOwned,
+            error_codes::INVALID_GRAPHICS_BUFFER => SyscallError::InvalidGraphicsBuffer,
+            error_codes::INVALID_OFFSET => SyscallError::InvalidOffset,
+            error_codes::ALREADY_EXISTS => SyscallError::AlreadyExists,
+            error_codes::OPERATION_NOT_SUPPORTED => SyscallError::OperationNotSupported,
+            error_codes::TIMED_OUT => SyscallError::TimedOut,
+            error_codes::NOT_SEEKABLE => SyscallError::NotSeekable,
+            error_codes::PERMISSION_DENIED => SyscallError::PermissionDenied,
+            error_codes::BUSY => SyscallError::Busy,
+            error_codes::IO_ERROR => SyscallError::IoError,
+            error_codes::NO_SPACE => SyscallError::NoSpace,
+            error_codes::INVALID_PATH => SyscallError::InvalidPath,
+            error_codes::NO_DEVICE => SyscallError::NoDevice,
+            error_codes::SYSCALL_NOT_FOUND => SyscallError::SyscallNotFound,
+            _ => return None,
+        };
+        Some(err)
+    }
 }
 
 pub type SyscallResult = Result<u64, SyscallError>;
@@ -311,6 +540,8 @@ where
     syscall_result_to_u64(result)
 }
 
+/// The error value is the msb set, then the error code in the next 7 bits, see [`error_codes`],
+/// and the payload of the error in the lower 56 bits
 pub fn syscall_result_to_u64(result: SyscallResult) -> u64 {
     match result {
         SyscallResult::Ok(value) => {
@@ -321,36 +552,19 @@ pub fn syscall_result_to_u64(result: SyscallResult) -> u64 {
             value
         }
         SyscallResult::Err(error) => {
-            let err_upper = match error {
-                SyscallError::SyscallNotFound => -1i64 as u64,
+            let payload = match error {
+                SyscallError::SyscallNotFound => ERROR_PAYLOAD_MASK,
                 SyscallError::InvalidArgument(arg1, arg2, arg3, arg4, arg5, arg6, arg7) => {
                     create_syscall_error(arg1, arg2, arg3, arg4, arg5, arg6, arg7)
                 }
-                SyscallError::CouldNotOpenFile => 2 << 56,
-                SyscallError::InvalidFileIndex => 3 << 56,
-                SyscallError::CouldNotWriteToFile => 4 << 56,
-                SyscallError::CouldNotReadFromFile => 5 << 56,
-                SyscallError::CouldNotLoadElf => 6 << 56,
-                SyscallError::CouldNotAllocateProcess => 7 << 56,
-                SyscallError::HeapRangesExceeded => 8 << 56,
-                SyscallError::EndOfFile => 9 << 56,
-                SyscallError::FileNotFound => 10 << 56,
-                SyscallError::PidNotFound => 11 << 56,
-                SyscallError::ProcessStillRunning => 12 << 56,
-                SyscallError::IsNotDirectory => 13 << 56,
-                SyscallError::IsDirectory => 14 << 56,
-                SyscallError::BufferTooSmall => 15 << 56,
-                SyscallError::GraphicsNotAvailable => 16 << 56,
-                SyscallError::GraphicsAlreadyTaken => 17 << 56,
-                SyscallError::GraphicsNotOwned => 18 << 56,
-                SyscallError::InvalidGraphicsBuffer => 19 << 56,
-                SyscallError::InvalidOffset => 20 << 56,
-                SyscallError::AlreadyExists => 21 << 56,
-                SyscallError::OperationNotSupported => 22 << 56,
+                // saturate, any size this large can't be allocated anyway
+                SyscallError::BufferTooSmall(needed) => (needed as u64).min(ERROR_PAYLOAD_MASK),
+                SyscallError::CouldNotLoadElf(reason) => reason as u64,
                 SyscallError::InvalidError => panic!("Should never be used"),
+                _ => 0,
             };
 
-            err_upper | (1 << 63)
+            ((error.code() as u64) << 56) | (payload & ERROR_PAYLOAD_MASK) | (1 << 63)
         }
     }
 }
@@ -363,11 +577,12 @@ pub fn syscall_result_from_u64(value: u64) -> SyscallResult {
         let value = value & !(1 << 63);
         // last byte
         let err_byte = (value >> 56) as u8;
+        let payload = value & ERROR_PAYLOAD_MASK;
 
         let invalid_error_code = |_| SyscallError::InvalidError;
 
         let err = match err_byte {
-            0 => {
+            error_codes::INVALID_ARGUMENT => {
                 let arg1 =
                     SyscallArgError::try_from((value & 0xFF) as u8).map_err(invalid_error_code)?;
                 let arg2 = SyscallArgError::try_from(((value >> 8) & 0xFF) as u8)
@@ -385,30 +600,154 @@ pub fn syscall_result_from_u64(value: u64) -> SyscallResult {
 
                 SyscallError::InvalidArgument(arg1, arg2, arg3, arg4, arg5, arg6, arg7)
             }
-            1 => SyscallError::InvalidError,
-            2 => SyscallError::CouldNotOpenFile,
-            3 => SyscallError::InvalidFileIndex,
-            4 => SyscallError::CouldNotWriteToFile,
-            5 => SyscallError::CouldNotReadFromFile,
-            6 => SyscallError::CouldNotLoadElf,
-            7 => SyscallError::CouldNotAllocateProcess,
-            8 => SyscallError::HeapRangesExceeded,
-            9 => SyscallError::EndOfFile,
-            10 => SyscallError::FileNotFound,
-            11 => SyscallError::PidNotFound,
-            12 => SyscallError::ProcessStillRunning,
-            13 => SyscallError::IsNotDirectory,
-            14 => SyscallError::IsDirectory,
-            15 => SyscallError::BufferTooSmall,
-            16 => SyscallError::GraphicsNotAvailable,
-            17 => SyscallError::GraphicsAlreadyTaken,
-            18 => SyscallError::GraphicsNotOwned,
-            19 => SyscallError::InvalidGraphicsBuffer,
-            20 => SyscallError::InvalidOffset,
-            21 => SyscallError::AlreadyExists,
-            22 => SyscallError::OperationNotSupported,
-            _ => SyscallError::InvalidError,
+            error_codes::BUFFER_TOO_SMALL => SyscallError::BufferTooSmall(payload as usize),
+            error_codes::COULD_NOT_LOAD_ELF => SyscallError::CouldNotLoadElf(
+                u8::try_from(payload)
+                    .map(ElfLoadErrorReason::from_u8)
+                    .unwrap_or(ElfLoadErrorReason::Unknown),
+            ),
+            code => SyscallError::from_code(code).unwrap_or(SyscallError::InvalidError),
         };
         SyscallResult::Err(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_results() {
+        for value in [0, 1, 0x1234_5678, (1 << 63) - 1] {
+            assert_eq!(syscall_result_to_u64(Ok(value)), value);
+            assert!(matches!(syscall_result_from_u64(value), Ok(v) if v == value));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn ok_result_with_msb() {
+        syscall_result_to_u64(Ok(1 << 63));
+    }
+
+    #[test]
+    fn error_codes_round_trip() {
+        for code in 0..=u8::MAX {
+            let Some(err) = SyscallError::from_code(code) else {
+                continue;
+            };
+            assert_eq!(err.code(), code);
+            // only used to report a corrupted result, never returned
+            if code == error_codes::INVALID_ERROR {
+                continue;
+            }
+
+            let value = syscall_result_to_u64(Err(err));
+            // always negative
+            assert!((value as i64) < 0);
+            let back = syscall_result_from_u64(value).unwrap_err();
+            assert_eq!(back.code(), code);
+        }
+        assert!(SyscallError::from_code(0x7E).is_none());
+    }
+
+    #[test]
+    fn syscall_not_found_is_minus_one() {
+        assert_eq!(
+            syscall_result_to_u64(Err(SyscallError::SyscallNotFound)) as i64,
+            -1
+        );
+        assert_eq!(
+            syscall_handler_wrapper(NUM_SYSCALLS as u64, || Ok(0)) as i64,
+            -1
+        );
+        assert_eq!(syscall_handler_wrapper(0, || Ok(5)), 5);
+    }
+
+    #[test]
+    fn buffer_too_small_payload() {
+        let value = syscall_result_to_u64(Err(SyscallError::BufferTooSmall(4096)));
+        assert!(matches!(
+            syscall_result_from_u64(value),
+            Err(SyscallError::BufferTooSmall(4096))
+        ));
+
+        // saturates instead of overflowing into the code
+        let value = syscall_result_to_u64(Err(SyscallError::BufferTooSmall(usize::MAX)));
+        assert!(matches!(
+            syscall_result_from_u64(value),
+            Err(SyscallError::BufferTooSmall(n)) if n as u64 == ERROR_PAYLOAD_MASK
+        ));
+    }
+
+    #[test]
+    fn could_not_load_elf_payload() {
+        for value in 0..=u8::MAX {
+            let reason = ElfLoadErrorReason::from_u8(value);
+            let result = syscall_result_to_u64(Err(SyscallError::CouldNotLoadElf(reason)));
+            assert!(matches!(
+                syscall_result_from_u64(result),
+                Err(SyscallError::CouldNotLoadElf(r)) if r == reason
+            ));
+        }
+        assert_eq!(
+            ElfLoadErrorReason::from_u8(ElfLoadErrorReason::OverlappingSegments as u8),
+            ElfLoadErrorReason::OverlappingSegments
+        );
+
+        // a reason that doesn't fit is not mistaken for another one
+        let value = (1 << 63) | ((error_codes::COULD_NOT_LOAD_ELF as u64) << 56) | 0x104;
+        assert!(matches!(
+            syscall_result_from_u64(value),
+            Err(SyscallError::CouldNotLoadElf(ElfLoadErrorReason::Unknown))
+        ));
+    }
+
+    #[test]
+    fn invalid_argument_payload() {
+        let err = SyscallError::InvalidArgument(
+            Some(SyscallArgError::GeneralInvalid),
+            None,
+            Some(SyscallArgError::InvalidUserPointer),
+            None,
+            None,
+            None,
+            Some(SyscallArgError::InvalidStructSize),
+        );
+        let value = syscall_result_to_u64(Err(err));
+        assert_eq!(value & 0xFF, SyscallArgError::GeneralInvalid as u64);
+        assert_eq!(
+            (value >> 16) & 0xFF,
+            SyscallArgError::InvalidUserPointer as u64
+        );
+
+        assert!(matches!(
+            syscall_result_from_u64(value),
+            Err(SyscallError::InvalidArgument(
+                Some(SyscallArgError::GeneralInvalid),
+                None,
+                Some(SyscallArgError::InvalidUserPointer),
+                None,
+                None,
+                None,
+                Some(SyscallArgError::InvalidStructSize),
+            ))
+        ));
+
+        // unknown argument errors are not silently dropped
+        let value = (1 << 63) | 0xFF;
+        assert!(matches!(
+            syscall_result_from_u64(value),
+            Err(SyscallError::InvalidError)
+        ));
+    }
+
+    #[test]
+    fn unknown_code_is_invalid_error() {
+        let value = (1 << 63) | (0x7E << 56);
+        assert!(matches!(
+            syscall_result_from_u64(value),
+            Err(SyscallError::InvalidError)
+        ));
+    }
+}
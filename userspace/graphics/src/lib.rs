@@ -3,45 +3,10 @@ use embedded_graphics::{
     geometry::{OriginDimensions, Size},
     pixelcolor::{Rgb888, RgbColor},
 };
-use emerald_std::graphics::{BlitCommand, FrameBufferInfo};
+use emerald_std::graphics::{BlitCommand, FrameBufferInfo, Rect};
 
-pub struct MovingAverage<const N: usize> {
-    values: [f64; N],
-    current_index: usize,
-    filled: usize,
-    sum: f64,
-}
-
-impl<const N: usize> MovingAverage<N> {
-    pub fn new() -> Self {
-        Self {
-            values: [0.0; N],
-            current_index: 0,
-            filled: 0,
-            sum: 0.0,
-        }
-    }
-
-    pub fn add(&mut self, value: f64) {
-        self.sum -= self.values[self.current_index];
-        self.sum += value;
-        self.values[self.current_index] = value;
-        self.current_index = (self.current_index + 1) % self.values.len();
-        if self.filled < self.values.len() {
-            self.filled += 1;
-        }
-    }
-
-    pub fn average(&self) -> f64 {
-        self.sum / self.filled as f64
-    }
-}
-
-impl<const N: usize> Default for MovingAverage<N> {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+/// The maximum number of separate changed regions to track, after that they are merged together
+const MAX_DAMAGE_RECTS: usize = 16;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -65,6 +30,8 @@ pub struct Graphics {
     framebuffer: Box<[u8]>,
     framebuffer_info: FrameBufferInfo,
     last_changed_rect: Option<(usize, usize, usize, usize)>,
+    /// The separate regions inside `last_changed_rect` that changed
+    damage: Vec<Rect>,
 }
 
 impl Graphics {
@@ -78,6 +45,10 @@ impl Graphics {
             framebuffer: memory,
             framebuffer_info: info,
             last_changed_rect: Some((0, 0, info.width as usize, info.height as usize)),
+            damage: vec![Rect {
+                pos: (0, 0),
+                size: (info.width, info.height),
+            }],
         }
     }
 
@@ -113,13 +84,7 @@ impl Graphics {
             return Some(());
         }
 
-        if let Some((x, y, w, h)) = self.last_changed_rect {
-            let (min_x, min_y) = (dest_x.min(x), dest_y.min(y));
-            let (max_x, max_y) = ((dest_x + width).max(x + w), (dest_y + height).max(y + h));
-            self.last_changed_rect = Some((min_x, min_y, max_x - min_x, max_y - min_y));
-        } else {
-            self.last_changed_rect = Some((dest_x, dest_y, width, height));
-        }
+        self.mark_changed((dest_x, dest_y, width, height));
 
         let line_chunk_size = width * self.framebuffer_info.byte_per_pixel as usize;
         let first_line_start = self.framebuffer_info.get_arr_pos((dest_x, dest_y)).unwrap();
@@ -148,23 +113,70 @@ impl Graphics {
         Some(())
     }
 
+    /// Add the region (x, y, width, height) to the changed regions
+    fn mark_changed(&mut self, rect: (usize, usize, usize, usize)) {
+        let (dest_x, dest_y, width, height) = rect;
+        if let Some((x, y, w, h)) = self.last_changed_rect {
+            let (min_x, min_y) = (dest_x.min(x), dest_y.min(y));
+            let (max_x, max_y) = ((dest_x + width).max(x + w), (dest_y + height).max(y + h));
+            self.last_changed_rect = Some((min_x, min_y, max_x - min_x, max_y - min_y));
+        } else {
+            self.last_changed_rect = Some(rect);
+        }
+
+        let new = Rect {
+            pos: (dest_x, dest_y),
+            size: (width, height),
+        };
+        // merge with a region it overlaps or touches, or with the last one if we have too many
+        let touching = self.damage.iter().position(|r| {
+            r.pos.0 <= new.pos.0 + new.size.0
+                && new.pos.0 <= r.pos.0 + r.size.0
+                && r.pos.1 <= new.pos.1 + new.size.1
+                && new.pos.1 <= r.pos.1 + r.size.1
+        });
+        let target = match touching {
+            Some(i) => &mut self.damage[i],
+            None if self.damage.len() < MAX_DAMAGE_RECTS => {
+                self.damage.push(new);
+                return;
+            }
+            None => self.damage.last_mut().unwrap(),
+        };
+        let (min_x, min_y) = (target.pos.0.min(new.pos.0), target.pos.1.min(new.pos.1));
+        let (max_x, max_y) = (
+            (target.pos.0 + target.size.0).max(new.pos.0 + new.size.0),
+            (target.pos.1 + target.size.1).max(new.pos.1 + new.size.1),
+        );
+        *target = Rect {
+            pos: (min_x, min_y),
+            size: (max_x - min_x, max_y - min_y),
+        };
+    }
+
     pub fn last_changed_rect(&self) -> Option<(usize, usize, usize, usize)> {
         self.last_changed_rect
     }
 
+    /// The separate regions that changed, all inside [`Self::last_changed_rect`]
+    pub fn changed_rects(&self) -> &[Rect] {
+        &self.damage
+    }
+
     pub fn clear_changed(&mut self) {
         self.last_changed_rect = None;
+        self.damage.clear();
     }
 
     pub fn merge_clear_rect(&mut self, rect: Option<(usize, usize, usize, usize)>) {
-        if let Some((x, y, w, h)) = self.last_changed_rect {
-            if let Some((dest_x, dest_y, width, height)) = rect {
-                let (min_x, min_y) = (dest_x.min(x), dest_y.min(y));
-                let (max_x, max_y) = ((dest_x + width).max(x + w), (dest_y + height).max(y + h));
-                self.last_changed_rect = Some((min_x, min_y, max_x - min_x, max_y - min_y));
-            }
-        } else {
-            self.last_changed_rect = rect;
+        if let Some(rect) = rect {
+            self.mark_changed(rect);
+        }
+    }
+
+    pub fn merge_changed_rects(&mut self, rects: &[Rect]) {
+        for rect in rects {
+            self.mark_changed((rect.pos.0, rect.pos.1, rect.size.0, rect.size.1));
         }
     }
 
@@ -174,16 +186,18 @@ impl Graphics {
         };
 
         let changed_xy = (dest_x, dest_y);
-        self.clear_changed();
 
+        // only copy the changed regions, not the whole bounding box
         emerald_std::graphics::blit(&BlitCommand {
             memory: &self.framebuffer,
             src_framebuffer_info: self.framebuffer_info,
             src: changed_xy,
             dst: changed_xy,
             size: (width, height),
+            damage: &self.damage,
         })
         .unwrap();
+        self.clear_changed();
     }
 
     // this is assumed to be rgb format
@@ -205,16 +219,7 @@ impl Graphics {
             }
         }
 
-        if let Some((x, y, w, h)) = self.last_changed_rect {
-            let (min_x, min_y) = (x.min(pos.0 as usize), y.min(pos.1 as usize));
-            let (max_x, max_y) = (
-                (x + w).max(pos.0 as usize + size.0),
-                (y + h).max(pos.1 as usize + size.1),
-            );
-            self.last_changed_rect = Some((min_x, min_y, max_x - min_x, max_y - min_y));
-        } else {
-            self.last_changed_rect = Some((pos.0 as usize, pos.1 as usize, size.0, size.1));
-        }
+        self.mark_changed((pos.0 as usize, pos.1 as usize, size.0, size.1));
     }
 }
 
@@ -233,20 +238,12 @@ impl DrawTarget for Graphics {
     where
         I: IntoIterator<Item = embedded_graphics::prelude::Pixel<Self::Color>>,
     {
-        let (mut min_x, mut min_y, mut max_x, mut max_y) =
-            if let Some((x, y, w, h)) = self.last_changed_rect {
-                let (min_x, min_y) = (x, y);
-                let (max_x, max_y) = (x + w, y + h);
-
-                (min_x, min_y, max_x, max_y)
-            } else {
-                (
-                    self.framebuffer_info.width,
-                    self.framebuffer_info.height,
-                    0,
-                    0,
-                )
-            };
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (
+            self.framebuffer_info.width,
+            self.framebuffer_info.height,
+            0,
+            0,
+        );
 
         for pixel in pixels {
             let pos = pixel.0;
@@ -280,7 +277,9 @@ impl DrawTarget for Graphics {
                 .ok_or(())?;
         }
 
-        self.last_changed_rect = Some((min_x, min_y, max_x - min_x, max_y - min_y));
+        if min_x <= max_x && min_y <= max_y {
+            self.mark_changed((min_x, min_y, max_x - min_x + 1, max_y - min_y + 1));
+        }
         Ok(())
     }
 
@@ -1,7 +1,5 @@
 //! This is a demo of using the graphics API to draw a bouncing circle and text on the screen.
 
-use std::thread::sleep;
-
 use embedded_graphics::{
     draw_target::DrawTarget,
     geometry::{Dimensions, OriginDimensions, Point, Size},
@@ -12,8 +10,13 @@ use embedded_graphics::{
     transform::Transform,
     Drawable,
 };
-use emerald_runtime::keyboard::Keyboard;
-use graphics::{Graphics, MovingAverage};
+use emerald_runtime::{
+    frame::{FpsCounter, FrameTimer},
+    keyboard::Keyboard,
+};
+use graphics::Graphics;
+
+const FPS: f64 = 60.0;
 
 fn main() {
     let mut graphics = Graphics::new();
@@ -24,17 +27,16 @@ fn main() {
 
     // Create a new character style
     let style = MonoTextStyle::new(&FONT_9X15, Rgb888::WHITE);
-    let mut fps_average = MovingAverage::<100>::new();
+    let mut fps_counter = FpsCounter::<100>::new();
     let mut fps_text = "FPS: 0".to_string();
 
     graphics.clear(Rgb888::BLACK).ok();
-    let mut changed_rect = graphics.last_changed_rect();
+    let mut changed_rects = graphics.changed_rects().to_vec();
 
     let mut keyboard = Keyboard::new();
+    let mut timer = FrameTimer::new(FPS);
 
     loop {
-        let time = std::time::SystemTime::now();
-
         // update
         {
             for key in keyboard.iter_keys() {
@@ -48,29 +50,35 @@ fn main() {
                 }
             }
 
-            // move the circle
-            circle.translate_mut(v);
+            // move in fixed steps, so the speed doesn't depend on the frame rate
+            timer.update();
+            while timer.step() {
+                // move the circle
+                circle.translate_mut(v);
 
-            // bounce the circle
-            if circle.bounding_box().top_left.x < 0
-                || circle.bounding_box().bottom_right().unwrap().x >= graphics.size().width as i32
-            {
-                v.x = -v.x;
-            }
-            if circle.bounding_box().top_left.y < 0
-                || circle.bounding_box().bottom_right().unwrap().y >= graphics.size().height as i32
-            {
-                v.y = -v.y;
+                // bounce the circle
+                if circle.bounding_box().top_left.x < 0
+                    || circle.bounding_box().bottom_right().unwrap().x
+                        >= graphics.size().width as i32
+                {
+                    v.x = -v.x;
+                }
+                if circle.bounding_box().top_left.y < 0
+                    || circle.bounding_box().bottom_right().unwrap().y
+                        >= graphics.size().height as i32
+                {
+                    v.y = -v.y;
+                }
             }
         }
         // render
-        let previous_changed_rect = changed_rect;
+        let previous_changed_rects = changed_rects;
         {
-            // only draw the changed part
-            if let Some((x, y, w, h)) = changed_rect {
+            // only draw the changed parts
+            for rect in &previous_changed_rects {
                 let rect = Rectangle {
-                    top_left: Point::new(x as i32, y as i32),
-                    size: Size::new(w as u32, h as u32),
+                    top_left: Point::new(rect.pos.0 as i32, rect.pos.1 as i32),
+                    size: Size::new(rect.size.0 as u32, rect.size.1 as u32),
                 };
                 graphics.fill_solid(&rect, Rgb888::BLACK).ok();
             }
@@ -80,16 +88,11 @@ fn main() {
             text.draw(&mut graphics).ok();
         }
         // take the changes before presenting, as it will be cleared after presenting
-        changed_rect = graphics.last_changed_rect();
-        graphics.merge_clear_rect(previous_changed_rect);
+        changed_rects = graphics.changed_rects().to_vec();
+        graphics.merge_changed_rects(&previous_changed_rects);
         graphics.present_changed();
-        let remaining =
-            std::time::Duration::from_millis(1000 / 60).checked_sub(time.elapsed().unwrap());
-        if let Some(remaining) = remaining {
-            sleep(remaining);
-        }
-        let fps = 1.0 / time.elapsed().unwrap().as_secs_f64();
-        fps_average.add(fps);
-        fps_text = format!("FPS: {:.2}", fps_average.average());
+        timer.wait();
+        fps_counter.frame();
+        fps_text = format!("FPS: {:.2}", fps_counter.fps());
     }
 }
@@ -0,0 +1,98 @@
+//! Capture the screen into a BMP file
+//!
+//! Usage: screenshot <file>
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    process::ExitCode,
+};
+
+use emerald_std::graphics::{self, CAPTURE_BYTES_PER_PIXEL};
+
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+
+/// Write a 24-bit BMP, `pixels` is packed `RGB` rows from top to bottom
+fn write_bmp(
+    writer: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> std::io::Result<()> {
+    let row_size = width * CAPTURE_BYTES_PER_PIXEL;
+    // rows in BMP are aligned to 4 bytes
+    let padding = (4 - row_size % 4) % 4;
+    let image_size = ((row_size + padding) * height) as u32;
+    let data_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+
+    // file header
+    writer.write_all(b"BM")?;
+    writer.write_all(&(data_offset + image_size).to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?; // reserved
+    writer.write_all(&data_offset.to_le_bytes())?;
+
+    // info header
+    writer.write_all(&INFO_HEADER_SIZE.to_le_bytes())?;
+    writer.write_all(&(width as i32).to_le_bytes())?;
+    writer.write_all(&(height as i32).to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // planes
+    writer.write_all(&24u16.to_le_bytes())?; // bits per pixel
+    writer.write_all(&0u32.to_le_bytes())?; // no compression
+    writer.write_all(&image_size.to_le_bytes())?;
+    writer.write_all(&2835i32.to_le_bytes())?; // 72 DPI horizontal
+    writer.write_all(&2835i32.to_le_bytes())?; // 72 DPI vertical
+    writer.write_all(&0u32.to_le_bytes())?; // colors in palette
+    writer.write_all(&0u32.to_le_bytes())?; // important colors
+
+    // pixels are stored bottom to top, in `BGR` order
+    let mut row = Vec::with_capacity(row_size + padding);
+    for line in pixels[..row_size * height].chunks_exact(row_size).rev() {
+        row.clear();
+        for pixel in line.chunks_exact(CAPTURE_BYTES_PER_PIXEL) {
+            row.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+        row.resize(row_size + padding, 0);
+        writer.write_all(&row)?;
+    }
+
+    writer.flush()
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().collect::<Vec<_>>();
+
+    if args.len() != 2 {
+        eprintln!("Usage: {} <file>", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let info = match graphics::get_framebuffer_info() {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("[!] error: could not get framebuffer info: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut pixels = vec![0; info.capture_size()];
+    if let Err(e) = graphics::capture_framebuffer(&mut pixels) {
+        eprintln!("[!] error: could not capture the screen: {e:?}");
+        return ExitCode::FAILURE;
+    }
+
+    let file = match File::create(&args[1]) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("[!] error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(e) = write_bmp(&mut BufWriter::new(file), info.width, info.height, &pixels) {
+        eprintln!("[!] error: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
@@ -8,14 +8,14 @@ use embedded_graphics::primitives::{
 };
 use embedded_graphics::text::{Baseline, Text};
 use embedded_graphics::Drawable;
+use emerald_runtime::frame::{FpsCounter, FrameTimer, PacingStrategy};
 use emerald_runtime::keyboard::Keyboard;
 use emerald_runtime::mouse::Mouse;
-use graphics::{Graphics, MovingAverage};
+use graphics::Graphics;
 use image::codecs::jpeg::JpegDecoder;
 use image::ImageDecoder;
 use std::fs::File;
 use std::io::BufReader;
-use std::thread::sleep;
 
 const RECT_WIDTH: i32 = 3;
 const PROGRESS_PADDING: u32 = 100;
@@ -63,7 +63,8 @@ fn main() {
     let mut graphics = Graphics::new();
     let mut keyboard = Keyboard::new();
     let mut mouse = Mouse::new();
-    let mut fps_average = MovingAverage::<100>::new();
+    let mut timer = FrameTimer::with_timestep(frame_time).with_strategy(PacingStrategy::SleepSpin);
+    let mut fps_counter = FpsCounter::<100>::new();
     let fps_text_style = MonoTextStyle::new(&FONT_9X15, Rgb888::RED);
     let progress_text_style = MonoTextStyle::new(&FONT_10X20, Rgb888::WHITE);
     let mut fps_text = "FPS: 0".to_string();
@@ -117,8 +118,6 @@ fn main() {
     // if we are paused
     let mut force_read = false;
     loop {
-        let time = std::time::SystemTime::now();
-
         // update
         {
             const INC_SIZE: usize = 10;
@@ -309,21 +308,8 @@ fn main() {
         }
 
         graphics.present_changed();
-        let remaining = frame_time.checked_sub(time.elapsed().unwrap());
-        if let Some(remaining) = remaining {
-            // if its 1ms or more, sleep
-            if remaining.as_millis() > 0 {
-                sleep(remaining);
-            } else {
-                // spin
-                let time = std::time::SystemTime::now();
-                while time.elapsed().unwrap() < remaining {
-                    core::hint::spin_loop();
-                }
-            }
-        }
-        let fps = 1.0 / time.elapsed().unwrap().as_secs_f64();
-        fps_average.add(fps);
-        fps_text = format!("FPS: {:.2}", fps_average.average());
+        timer.wait();
+        fps_counter.frame();
+        fps_text = format!("FPS: {:.2}", fps_counter.fps());
     }
 }
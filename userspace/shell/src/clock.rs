@@ -1,46 +1,68 @@
 //! Clock shell program
 //!
-//! Just print the clock incrementing every second until it is killed
+//! Just print the clock (in local time) incrementing every second until it is killed
 //! or a specific time is specified in the argument
 //!
-//! Usage: clock [seconds_to_run]
+//! Usage: clock [-w seconds_to_run] [-z offset_minutes]
 
 use std::{
     process::{exit, ExitCode},
     time::{Instant, SystemTime},
 };
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Utc};
+use emerald_runtime::clock;
+
+const USAGE: &str = "Usage: clock [-w seconds_to_run] [-z offset_minutes]";
 
 fn main() -> ExitCode {
     let args = std::env::args().collect::<Vec<_>>();
 
-    let seconds_to_run = if args.len() > 1 {
-        if args[1] == "help" || args[1] == "-h" || args[1] == "--help" {
-            println!("Usage: clock [-w seconds_to_run]");
-            return ExitCode::SUCCESS;
-        }
-
-        if args[1] != "-w" {
-            eprintln!("[!] error: unknown argument: {}", args[1]);
-            println!("Usage: clock [-w seconds_to_run]");
-
-            return ExitCode::FAILURE;
-        }
-
-        if args.len() < 3 {
-            eprintln!("[!] error: missing argument");
-            println!("Usage: clock [-w seconds_to_run]");
-            return ExitCode::FAILURE;
+    let mut seconds_to_run = None;
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "help" | "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            "-w" | "-z" => {
+                let Some(value) = args_iter.next() else {
+                    eprintln!("[!] error: missing argument");
+                    println!("{USAGE}");
+                    return ExitCode::FAILURE;
+                };
+                if arg == "-w" {
+                    seconds_to_run = Some(value.parse::<u64>().unwrap_or_else(|e| {
+                        eprintln!("[!] error: {}", e);
+                        exit(1); // TODO: replace with ExitCode::FAILURE
+                    }));
+                } else {
+                    let offset_minutes = value.parse::<i64>().unwrap_or_else(|e| {
+                        eprintln!("[!] error: {}", e);
+                        exit(1);
+                    });
+                    let mut timezone = clock::timezone().unwrap_or_default();
+                    timezone.offset_seconds = offset_minutes * 60;
+                    if let Err(e) = clock::set_timezone(timezone) {
+                        eprintln!("[!] error: could not set the timezone: {e:?}");
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            _ => {
+                eprintln!("[!] error: unknown argument: {}", arg);
+                println!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
         }
+    }
 
-        args[2].parse::<u64>().map(Some).unwrap_or_else(|e| {
-            eprintln!("[!] error: {}", e);
-            exit(1); // TODO: replace with ExitCode::FAILURE
-        })
-    } else {
-        None
-    };
+    let offset_seconds = clock::timezone().map_or(0, |tz| tz.offset_seconds);
+    let offset = i32::try_from(offset_seconds)
+        .ok()
+        .and_then(FixedOffset::east_opt)
+        .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
 
     let mut start = Instant::now();
     let mut seconds_passed = 0;
@@ -56,7 +78,8 @@ fn main() -> ExitCode {
 
             let system_time = SystemTime::now();
             let datetime: DateTime<Utc> = system_time.into();
-            println!("{}", datetime.format("%d/%m/%Y %T.%f (%s)"));
+            let datetime = datetime.with_timezone(&offset);
+            println!("{}", datetime.format("%d/%m/%Y %T.%f %:z (%s)"));
 
             if let Some(seconds_to_run) = seconds_to_run {
                 if seconds_passed >= seconds_to_run {
@@ -0,0 +1,55 @@
+//! Disk free shell program
+//!
+//! Print the size, used and free space of the filesystems containing the given paths (`/` by default)
+//!
+//! Usage: df [path...]
+
+use std::{ffi::CString, process::ExitCode};
+
+use emerald_runtime::fs;
+
+fn main() -> ExitCode {
+    let mut paths = std::env::args().skip(1).collect::<Vec<_>>();
+    if paths.is_empty() {
+        paths.push("/".to_string());
+    }
+
+    let mut result = ExitCode::SUCCESS;
+    println!(
+        "{:<16} {:>10} {:>10} {:>10} {:>5} {:>8}",
+        "PATH", "SIZE(kB)", "USED(kB)", "FREE(kB)", "USE%", "BLOCK"
+    );
+    for path in paths {
+        let stat = match CString::new(path.as_str()).map(|p| fs::stat(&p)) {
+            Ok(Ok(stat)) => stat,
+            Ok(Err(e)) => {
+                eprintln!("[!] error: {path}: {e:?}");
+                result = ExitCode::FAILURE;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("[!] error: {path}: {e}");
+                result = ExitCode::FAILURE;
+                continue;
+            }
+        };
+
+        let used_blocks = stat.total_blocks - stat.free_blocks;
+        let use_percent = if stat.total_blocks == 0 {
+            0
+        } else {
+            used_blocks * 100 / stat.total_blocks
+        };
+        println!(
+            "{:<16} {:>10} {:>10} {:>10} {:>4}% {:>8}",
+            path,
+            stat.total_blocks * stat.block_size / 1024,
+            used_blocks * stat.block_size / 1024,
+            stat.free_blocks * stat.block_size / 1024,
+            use_percent,
+            stat.block_size,
+        );
+    }
+
+    result
+}
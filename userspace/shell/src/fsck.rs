@@ -0,0 +1,95 @@
+//! Filesystem check shell program
+//!
+//! Check the FAT filesystem in a block device (`/devices/disk0p1` by default), and repair the free map with `-r`.
+//! The filesystem should not be in use while repairing, as the mounted filesystem doesn't see the changes.
+//!
+//! Usage: fsck [-r] [device]
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    process::ExitCode,
+};
+
+use emerald_fat_check::{Error, Volume};
+
+struct DeviceVolume(File);
+
+impl Volume for DeviceVolume {
+    type Error = io::Error;
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), io::Error> {
+        self.0.seek(SeekFrom::Start(offset))?;
+        self.0.read_exact(buf)
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> Result<(), io::Error> {
+        self.0.seek(SeekFrom::Start(offset))?;
+        self.0.write_all(buf)
+    }
+}
+
+fn main() -> ExitCode {
+    let mut repair = false;
+    let mut device = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-r" => repair = true,
+            _ if device.is_none() => device = Some(arg),
+            _ => {
+                eprintln!("Usage: fsck [-r] [device]");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let device = device.unwrap_or_else(|| "/devices/disk0p1".to_string());
+
+    let file = match OpenOptions::new().read(true).write(repair).open(&device) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("[!] error: {device}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let report = match emerald_fat_check::check(&mut DeviceVolume(file), repair) {
+        Ok(report) => report,
+        Err(Error::Volume(e)) => {
+            eprintln!("[!] error: {device}: {e}");
+            return ExitCode::FAILURE;
+        }
+        Err(Error::InvalidBootSector) => {
+            eprintln!("[!] error: {device}: not a FAT filesystem");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for problem in &report.problems {
+        println!("{problem}");
+    }
+    println!(
+        "{device}: {:?}, {} files, {} directories, {}/{} clusters free ({} bytes each)",
+        report.fat_type,
+        report.files,
+        report.directories,
+        report.free_clusters,
+        report.clusters,
+        report.bytes_per_cluster,
+    );
+
+    if report.is_clean() {
+        println!("{device}: clean");
+        ExitCode::SUCCESS
+    } else {
+        println!(
+            "{device}: {} problems{}",
+            report.problems.len(),
+            if report.repaired {
+                ", the free map was repaired"
+            } else {
+                ""
+            }
+        );
+        ExitCode::FAILURE
+    }
+}
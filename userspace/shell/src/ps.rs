@@ -0,0 +1,52 @@
+//! Process status shell program
+//!
+//! Print all the running processes, with their state, priority, CPU time, resource usage and command line
+//!
+//! Usage: ps
+
+use std::process::ExitCode;
+
+use emerald_runtime::process::{self, ProcessState};
+
+fn main() -> ExitCode {
+    let processes = match process::list() {
+        Ok(processes) => processes,
+        Err(e) => {
+            eprintln!("[!] error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!(
+        "{:>5} {:>5} {:<10} {:<9} {:>10} {:>8} {:>4} COMMAND",
+        "PID", "PPID", "STATE", "PRIORITY", "CPU(ms)", "HEAP(kB)", "FDS"
+    );
+    for info in processes {
+        let state = match info.state {
+            ProcessState::Running => "running",
+            ProcessState::Scheduled => "scheduled",
+            ProcessState::WaitingForPid => "waiting",
+            ProcessState::Sleeping => "sleeping",
+            ProcessState::WaitingForIo => "io-wait",
+        };
+        let priority = format!("{:?}", info.priority);
+        // the process may exit before we get its details
+        let command = match process::details(info.pid) {
+            Ok(details) if !details.argv.is_empty() => details.argv.join(" "),
+            _ => info.name_cstr().to_string_lossy().into_owned(),
+        };
+        println!(
+            "{:>5} {:>5} {:<10} {:<9} {:>10} {:>8} {:>4} {}",
+            info.pid,
+            info.parent_id,
+            state,
+            priority,
+            info.cpu_time / 1_000_000,
+            info.heap_size / 1024,
+            info.open_files,
+            command,
+        );
+    }
+
+    ExitCode::SUCCESS
+}
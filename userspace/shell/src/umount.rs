@@ -0,0 +1,41 @@
+//! Unmount shell program
+//!
+//! Unmount the filesystem mounted at the given path, fails if it's in use, unless `-l` is given,
+//! then it's detached immediately, and unmounted when the last file using it is closed.
+//!
+//! Usage: umount [-l] <path>
+
+use std::{ffi::CString, process::ExitCode};
+
+use emerald_runtime::fs::{self, unmount_flags};
+
+fn main() -> ExitCode {
+    let mut flags = 0;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-l" => flags |= unmount_flags::LAZY,
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("Usage: umount [-l] <path>");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("Usage: umount [-l] <path>");
+        return ExitCode::FAILURE;
+    };
+
+    match CString::new(path.as_str()).map(|p| fs::unmount(&p, flags)) {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(e)) => {
+            eprintln!("[!] error: {path}: {e:?}");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("[!] error: {path}: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use argh::FromArgs;
 
+use crate::kernel::build::KernelProfile;
+
 #[derive(FromArgs, Debug)]
 #[argh(description = "XTask - a task runner")]
 pub struct Args {
@@ -19,6 +21,8 @@ pub struct Args {
 pub enum Command {
     Run(RunKernel),
     Test(TestKernel),
+    TestHost(TestHost),
+    Trace(Trace),
     BuildIso(BuildIso),
     Kernel(Kernel),
     Userspace(Userspace),
@@ -33,10 +37,50 @@ pub struct RunKernel {
     #[argh(description = "run with gdb")]
     pub gdb: bool,
 
+    #[argh(switch, long = "gdb-stub")]
+    #[argh(description = "expose COM2 on tcp::1235 for the kernel GDB stub")]
+    pub gdb_stub: bool,
+
     #[argh(switch, long = "no-graphics")]
     #[argh(description = "disable graphics")]
     pub no_graphics: bool,
 
+    #[argh(switch, long = "text-mode")]
+    #[argh(description = "boot in VGA text mode, instead of a linear framebuffer")]
+    pub text_mode: bool,
+
+    #[argh(switch, long = "no-disk")]
+    #[argh(description = "run without a disk, `./filesystem` is loaded as an initrd instead")]
+    pub no_disk: bool,
+
+    #[argh(switch, long = "fresh")]
+    #[argh(
+        description = "rebuild the initrd and the ISO from scratch, instead of only what changed"
+    )]
+    pub fresh: bool,
+
+    #[argh(option, long = "kernel-profile", default = "Default::default()")]
+    #[argh(
+        description = "kernel features to build with: `full` (default), `headless` (no graphics) or `minimal` (no graphics and disk, implies `--no-disk`)"
+    )]
+    pub kernel_profile: KernelProfile,
+
+    #[argh(switch, long = "trace")]
+    #[argh(
+        description = "record the kernel tracepoints into `target/trace.bin`, decode them with `cargo xtask trace`"
+    )]
+    pub trace: bool,
+
+    #[argh(switch, long = "qmp")]
+    #[argh(description = "expose QMP on the unix socket `target/qmp.sock`")]
+    pub qmp: bool,
+
+    #[argh(option, long = "script")]
+    #[argh(
+        description = "a `.ron` script of commands (typing, screenshots, ...) to send through QMP while running"
+    )]
+    pub script: Option<String>,
+
     #[argh(positional)]
     pub extra: Vec<String>,
 }
@@ -53,10 +97,37 @@ pub struct TestKernel {
     pub extra: Vec<String>,
 }
 
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "test-host")]
+#[argh(description = "Run the unit tests of the shared libraries on the host, without QEMU")]
+pub struct TestHost {
+    #[argh(positional)]
+    pub extra: Vec<String>,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "trace")]
+#[argh(
+    description = "Decode the kernel tracepoints recorded with `run --trace` into Chrome trace-event JSON"
+)]
+pub struct Trace {
+    #[argh(option, long = "input", short = 'i')]
+    #[argh(description = "the recorded trace, `target/trace.bin` by default")]
+    pub input: Option<String>,
+
+    #[argh(option, long = "out", short = 'o')]
+    #[argh(description = "the JSON output, `target/trace.json` by default")]
+    pub out: Option<String>,
+}
+
 #[derive(FromArgs, Debug)]
 #[argh(subcommand, name = "build-iso")]
 #[argh(description = "Build the kernel ISO")]
-pub struct BuildIso {}
+pub struct BuildIso {
+    #[argh(switch, long = "fresh")]
+    #[argh(description = "rebuild the ISO from scratch, instead of only if it changed")]
+    pub fresh: bool,
+}
 
 #[derive(FromArgs, Debug)]
 #[argh(subcommand, name = "kernel")]
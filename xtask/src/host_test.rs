@@ -0,0 +1,34 @@
+use crate::{args::TestHost, utils::run_cmd, GlobalMeta};
+
+/// The libraries that are shared between the kernel and userspace, but don't depend on either,
+/// so they can be built and tested on the host
+///
+/// `emerald_fat_check` is not here, it has no tests yet, as they need FAT images to check
+const HOST_TESTABLE_PACKAGES: &[&str] = &[
+    "emerald_kernel_user_link",
+    "increasing_heap_allocator",
+    "emerald_crypto",
+    "emerald_runtime",
+];
+
+pub fn run(meta: &GlobalMeta, test: TestHost) -> anyhow::Result<()> {
+    let cargo = std::env::var("CARGO")?;
+
+    let mut cmd = std::process::Command::new(cargo);
+
+    cmd.current_dir(&meta.root_path).arg("test");
+
+    for package in HOST_TESTABLE_PACKAGES {
+        cmd.arg("--package").arg(package);
+    }
+
+    cmd.arg("--features").arg("std");
+
+    if meta.release {
+        cmd.arg("--release");
+    }
+
+    cmd.args(test.extra);
+
+    run_cmd(cmd)
+}
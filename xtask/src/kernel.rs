@@ -4,9 +4,14 @@ use crate::GlobalMeta;
 
 pub mod build;
 pub mod check;
+pub mod initrd;
 pub mod iso;
+pub mod qmp;
 pub mod run;
+pub mod script;
+pub mod telemetry;
 pub mod test;
+pub mod trace;
 
 fn grub_src_path(meta: &GlobalMeta) -> PathBuf {
     kernel_path(meta).join("grub.cfg")
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr};
 
 use crate::{
     args::Build,
@@ -6,7 +6,64 @@ use crate::{
     GlobalMeta,
 };
 
-pub fn build_kernel(meta: &GlobalMeta, build: Build) -> anyhow::Result<PathBuf> {
+/// Sets of kernel features, to build the kernel without some subsystems,
+/// i.e. to test memory/boot-time regressions, or for targets without those devices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KernelProfile {
+    /// The default features
+    #[default]
+    Full,
+    /// Without the `graphics` feature, the console still uses the framebuffer
+    Headless,
+    /// Without `graphics` and `ide`, the initrd is used as the root filesystem
+    Minimal,
+}
+
+impl KernelProfile {
+    fn cargo_args(self) -> &'static [&'static str] {
+        match self {
+            KernelProfile::Full => &[],
+            KernelProfile::Headless => {
+                &["--no-default-features", "--features", "spin_lock_debug,ide"]
+            }
+            KernelProfile::Minimal => &["--no-default-features", "--features", "spin_lock_debug"],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KernelProfile::Full => "full",
+            KernelProfile::Headless => "headless",
+            KernelProfile::Minimal => "minimal",
+        }
+    }
+
+    /// Does the kernel have a disk driver
+    pub fn has_disk(self) -> bool {
+        self != KernelProfile::Minimal
+    }
+}
+
+impl FromStr for KernelProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(KernelProfile::Full),
+            "headless" => Ok(KernelProfile::Headless),
+            "minimal" => Ok(KernelProfile::Minimal),
+            _ => Err(format!(
+                "unknown kernel profile `{s}`, expected `full`, `headless` or `minimal`"
+            )),
+        }
+    }
+}
+
+pub fn build_kernel(
+    meta: &GlobalMeta,
+    build: Build,
+    profile: KernelProfile,
+) -> anyhow::Result<PathBuf> {
     let kernel_path = super::kernel_path(meta);
     let elf_path = meta
         .target_path
@@ -16,7 +73,14 @@ pub fn build_kernel(meta: &GlobalMeta, build: Build) -> anyhow::Result<PathBuf>
 
     let cargo = std::env::var("CARGO")?;
 
-    if has_changed(kernel_path.join("src/**/*"), &elf_path)?
+    // the features the kernel was last built with, to rebuild it when the profile changes
+    let features_path = elf_path.with_extension("features");
+    let features = profile.cargo_args().join(" ");
+    let features_changed =
+        std::fs::read_to_string(&features_path).ok().as_deref() != Some(features.as_str());
+
+    if features_changed
+        || has_changed(kernel_path.join("src/**/*"), &elf_path)?
         || has_changed(kernel_path.join("Cargo.toml"), &elf_path)?
     {
         let mut cmd = std::process::Command::new(cargo);
@@ -25,9 +89,11 @@ pub fn build_kernel(meta: &GlobalMeta, build: Build) -> anyhow::Result<PathBuf>
             .arg("build")
             .arg("--profile")
             .arg(meta.profile_name())
+            .args(profile.cargo_args())
             .args(build.extra);
 
         run_cmd(cmd)?;
+        std::fs::write(features_path, features)?;
     } else {
         println!("[-] Kernel has not changed, skipping build");
     }
@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::Write,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use emerald_crypto::sha256::Sha256;
+
+const MODE_DIR: u32 = 0o040755;
+const MODE_FILE: u32 = 0o100644;
+
+/// The state of a file when the initrd was last written
+struct ManifestEntry {
+    /// `None` for directories
+    hash: Option<[u8; 32]>,
+    size: u64,
+    mtime: u128,
+}
+
+/// A file or directory in `input_folder`, in the order they are written into the archive
+struct ScanEntry {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+    size: u64,
+    mtime: u128,
+}
+
+/// Create or update the `newc` cpio archive `output` with the content of `input_folder`,
+/// used as the kernel initrd.
+///
+/// `manifest` keeps the size, modification time and hash of each file from the last time `output` was
+/// written. Files that didn't change are copied from the old archive without reading them, touched files
+/// with the same content don't cause a rewrite, and removed files are noticed (which the modification
+/// times alone can't do). With `fresh`, the old archive and manifest are ignored.
+pub fn sync_initrd(
+    input_folder: &Path,
+    output: &Path,
+    manifest: &Path,
+    fresh: bool,
+) -> anyhow::Result<()> {
+    assert!(input_folder.is_dir(), "Input folder does not exist");
+
+    let old_archive = if fresh {
+        None
+    } else {
+        std::fs::read(output).ok()
+    };
+    let old_contents = match &old_archive {
+        Some(archive) => read_entries(archive).unwrap_or_else(|e| {
+            println!("[!] Could not read the old initrd, rebuilding it: {e}");
+            HashMap::new()
+        }),
+        None => HashMap::new(),
+    };
+    let old_manifest = if old_contents.is_empty() {
+        HashMap::new()
+    } else {
+        read_manifest(manifest).unwrap_or_default()
+    };
+
+    let mut scanned = Vec::new();
+    scan_dir(&mut scanned, input_folder, "")?;
+
+    let (mut changed, mut added) = (0, 0);
+    let mut new_manifest = HashMap::new();
+    let mut contents = Vec::with_capacity(scanned.len());
+    for entry in &scanned {
+        let old = old_manifest.get(&entry.name);
+        let (hash, content) = if entry.is_dir {
+            (None, Vec::new())
+        } else {
+            match (old, old_contents.get(entry.name.as_str())) {
+                (Some(old), Some(content))
+                    if old.hash.is_some() && old.size == entry.size && old.mtime == entry.mtime =>
+                {
+                    (old.hash, content.to_vec())
+                }
+                _ => {
+                    let content = std::fs::read(&entry.path)?;
+                    (Some(Sha256::digest(&content)), content)
+                }
+            }
+        };
+
+        match old {
+            None => added += 1,
+            Some(old) if old.hash != hash => changed += 1,
+            _ => {}
+        }
+        new_manifest.insert(
+            entry.name.clone(),
+            ManifestEntry {
+                hash,
+                size: entry.size,
+                mtime: entry.mtime,
+            },
+        );
+        contents.push(content);
+    }
+    let removed = old_manifest
+        .keys()
+        .filter(|name| !new_manifest.contains_key(*name))
+        .count();
+
+    if !fresh && !old_manifest.is_empty() && changed + added + removed == 0 {
+        println!("[-] Initrd content has not changed, skipping creation");
+        // the modification times may have changed
+        write_manifest(manifest, &scanned, &new_manifest)?;
+        return Ok(());
+    }
+
+    if old_manifest.is_empty() {
+        println!("[+] Creating initrd {:?} from {:?}", output, input_folder);
+    } else {
+        println!(
+            "[+] Updating initrd {:?}: {changed} changed, {added} added, {removed} removed",
+            output
+        );
+    }
+
+    let mut archive = Vec::new();
+    for (ino, (entry, content)) in scanned.iter().zip(&contents).enumerate() {
+        let mode = if entry.is_dir { MODE_DIR } else { MODE_FILE };
+        write_entry(&mut archive, ino as u32 + 2, &entry.name, mode, content)?;
+    }
+    write_entry(&mut archive, 0, "TRAILER!!!", 0, &[])?;
+
+    std::fs::write(output, archive)?;
+    write_manifest(manifest, &scanned, &new_manifest)?;
+    Ok(())
+}
+
+fn scan_dir(scanned: &mut Vec<ScanEntry>, dir: &Path, prefix: &str) -> anyhow::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    // keep the archive reproducible
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        let metadata = entry.metadata()?;
+        if !metadata.is_dir() && !metadata.is_file() {
+            continue;
+        }
+        scanned.push(ScanEntry {
+            name: name.clone(),
+            path: entry.path(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            mtime: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos(),
+        });
+        if metadata.is_dir() {
+            scan_dir(scanned, &entry.path(), &format!("{name}/"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The manifest has a line for each entry: `<sha256 or -> <size> <mtime> <name>`
+fn read_manifest(path: &Path) -> anyhow::Result<HashMap<String, ManifestEntry>> {
+    let mut manifest = HashMap::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        let mut parts = line.splitn(4, ' ');
+        let (Some(hash), Some(size), Some(mtime), Some(name)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("invalid initrd manifest line: {line:?}");
+        };
+        let hash = if hash == "-" {
+            None
+        } else {
+            anyhow::ensure!(
+                hash.len() == 64,
+                "invalid hash in initrd manifest: {hash:?}"
+            );
+            let mut bytes = [0; 32];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16)?;
+            }
+            Some(bytes)
+        };
+        manifest.insert(
+            name.to_string(),
+            ManifestEntry {
+                hash,
+                size: size.parse()?,
+                mtime: mtime.parse()?,
+            },
+        );
+    }
+    Ok(manifest)
+}
+
+fn write_manifest(
+    path: &Path,
+    scanned: &[ScanEntry],
+    manifest: &HashMap<String, ManifestEntry>,
+) -> anyhow::Result<()> {
+    let mut output = String::new();
+    for entry in scanned {
+        let state = &manifest[&entry.name];
+        match state.hash {
+            Some(hash) => {
+                for byte in hash {
+                    write!(output, "{byte:02x}")?;
+                }
+            }
+            None => output.push('-'),
+        }
+        writeln!(output, " {} {} {}", state.size, state.mtime, entry.name)?;
+    }
+    std::fs::write(path, output)?;
+    Ok(())
+}
+
+/// Read the content of the files in a `newc` cpio archive written by us
+fn read_entries(mut archive: &[u8]) -> anyhow::Result<HashMap<&str, &[u8]>> {
+    const HEADER_SIZE: usize = 110;
+    let align4 = |n: usize| (n + 3) & !3;
+    let field = |header: &[u8], index: usize| -> anyhow::Result<usize> {
+        let offset = 6 + index * 8;
+        Ok(usize::from_str_radix(
+            std::str::from_utf8(&header[offset..offset + 8])?,
+            16,
+        )?)
+    };
+
+    let mut entries = HashMap::new();
+    loop {
+        anyhow::ensure!(
+            archive.len() >= HEADER_SIZE && archive.starts_with(b"070701"),
+            "invalid cpio header"
+        );
+        let file_size = field(archive, 6)?;
+        let name_size = field(archive, 11)?;
+        let name_end = HEADER_SIZE + name_size;
+        let content_start = align4(name_end);
+        let content_end = content_start + file_size;
+        anyhow::ensure!(
+            name_size > 0 && content_end <= archive.len(),
+            "truncated cpio entry"
+        );
+
+        // the name is null terminated
+        let name = std::str::from_utf8(&archive[HEADER_SIZE..name_end - 1])?;
+        if name == "TRAILER!!!" {
+            return Ok(entries);
+        }
+        entries.insert(name, &archive[content_start..content_end]);
+        archive = &archive[align4(content_end).min(archive.len())..];
+    }
+}
+
+fn write_entry(
+    archive: &mut Vec<u8>,
+    ino: u32,
+    name: &str,
+    mode: u32,
+    content: &[u8],
+) -> anyhow::Result<()> {
+    let name_size = name.len() + 1;
+    let nlink = if mode & 0o040000 != 0 { 2 } else { 1 };
+    write!(
+        archive,
+        "070701{ino:08X}{mode:08X}{:08X}{:08X}{nlink:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{name_size:08X}{:08X}",
+        0, // uid
+        0, // gid
+        0, // mtime
+        content.len(),
+        0, // devmajor
+        0, // devminor
+        0, // rdevmajor
+        0, // rdevminor
+        0, // check
+    )?;
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    pad4(archive);
+    archive.extend_from_slice(content);
+    pad4(archive);
+    Ok(())
+}
+
+fn pad4(archive: &mut Vec<u8>) {
+    while archive.len() % 4 != 0 {
+        archive.push(0);
+    }
+}
@@ -8,7 +8,10 @@ use crate::{
     GlobalMeta,
 };
 
-use super::{build::build_kernel, test::build_test_kernel};
+use super::{
+    build::{build_kernel, KernelProfile},
+    test::build_test_kernel,
+};
 
 fn iso_copy_grub_cfg(meta: &GlobalMeta, iso_folder: &Path) -> anyhow::Result<()> {
     copy_files(
@@ -17,18 +20,64 @@ fn iso_copy_grub_cfg(meta: &GlobalMeta, iso_folder: &Path) -> anyhow::Result<()>
     )
 }
 
+/// Put the initrd in the iso, and load it in `grub.cfg` (after the kernel)
+fn iso_add_initrd(meta: &GlobalMeta, iso_folder: &Path, fresh: bool) -> anyhow::Result<()> {
+    let initrd_path = iso_folder.join("boot").join("initrd");
+    // outside the iso folder, so it's not included in the iso
+    let manifest_path = meta
+        .target_path
+        .join(meta.profile_path())
+        .join("initrd.manifest");
+    super::initrd::sync_initrd(&meta.filesystem_path, &initrd_path, &manifest_path, fresh)?;
+
+    let grub_cfg_path = iso_folder.join("boot").join("grub").join("grub.cfg");
+    let grub_cfg = std::fs::read_to_string(&grub_cfg_path)?;
+    if grub_cfg.contains("module2") {
+        return Ok(());
+    }
+    let mut new_grub_cfg = String::new();
+    for line in grub_cfg.lines() {
+        new_grub_cfg.push_str(line);
+        new_grub_cfg.push('\n');
+        if line.trim_start().starts_with("multiboot2") {
+            new_grub_cfg.push_str("    module2 /boot/initrd initrd\n");
+        }
+    }
+    std::fs::write(grub_cfg_path, new_grub_cfg)?;
+
+    Ok(())
+}
+
+/// Make grub keep the VGA text mode, instead of setting the framebuffer the kernel asks for
+fn iso_set_text_mode(iso_folder: &Path) -> anyhow::Result<()> {
+    let grub_cfg_path = iso_folder.join("boot").join("grub").join("grub.cfg");
+    let grub_cfg = std::fs::read_to_string(&grub_cfg_path)?;
+    let mut new_grub_cfg = String::new();
+    for line in grub_cfg.lines() {
+        if line.trim_start().starts_with("multiboot2") {
+            new_grub_cfg.push_str("    set gfxpayload=text\n");
+        }
+        new_grub_cfg.push_str(line);
+        new_grub_cfg.push('\n');
+    }
+    std::fs::write(grub_cfg_path, new_grub_cfg)?;
+
+    Ok(())
+}
+
 fn iso_copy_kernel(elf_path: &Path, iso_folder: &Path) -> anyhow::Result<()> {
     copy_files(elf_path, iso_folder.join("boot").join("kernel"))
 }
 
-fn create_iso(input_folder: &Path, output_iso: &Path) -> anyhow::Result<()> {
+/// Create the iso if any of its content changed, or always if `fresh` is set
+fn create_iso(input_folder: &Path, output_iso: &Path, fresh: bool) -> anyhow::Result<()> {
     assert!(input_folder.is_dir(), "Input folder does not exist");
     assert!(
         output_iso.parent().unwrap().is_dir(),
         "Output folder does not exist"
     );
 
-    if !has_changed(input_folder.join("**/*"), output_iso)? {
+    if !fresh && !has_changed(input_folder.join("**/*"), output_iso)? {
         println!("[-] ISO content has not changed, skipping creation");
         return Ok(());
     }
@@ -48,12 +97,22 @@ fn common_finish_iso(
 ) -> anyhow::Result<()> {
     iso_copy_kernel(elf_path, iso_src)?;
     iso_copy_grub_cfg(meta, iso_src)?;
-    create_iso(iso_src, iso_dst)?;
+    create_iso(iso_src, iso_dst, false)?;
 
     Ok(())
 }
 
-pub fn build_normal_iso(meta: &GlobalMeta) -> anyhow::Result<PathBuf> {
+/// Build the kernel iso, if `with_initrd` is set, the content of `./filesystem` is added as an initrd.
+///
+/// With `fresh`, the initrd and the iso are rebuilt from scratch, instead of only when they changed.
+/// With `text_mode`, grub boots the kernel in VGA text mode.
+pub fn build_normal_iso(
+    meta: &GlobalMeta,
+    with_initrd: bool,
+    fresh: bool,
+    text_mode: bool,
+    profile: KernelProfile,
+) -> anyhow::Result<PathBuf> {
     let iso_src = meta.target_path.join(meta.profile_path()).join("iso");
     let iso_dst = meta
         .target_path
@@ -62,9 +121,23 @@ pub fn build_normal_iso(meta: &GlobalMeta) -> anyhow::Result<PathBuf> {
 
     std::fs::create_dir_all(iso_src.join("boot").join("grub"))?;
 
-    let elf_path = build_kernel(meta, Default::default())?;
+    let elf_path = build_kernel(meta, Default::default(), profile)?;
 
-    common_finish_iso(meta, &iso_src, &iso_dst, &elf_path)?;
+    let initrd_path = iso_src.join("boot").join("initrd");
+    if !with_initrd && initrd_path.exists() {
+        // remove leftovers from previous runs, and the `grub.cfg` that loads it
+        std::fs::remove_file(initrd_path)?;
+        std::fs::remove_file(iso_src.join("boot").join("grub").join("grub.cfg"))?;
+    }
+    iso_copy_kernel(&elf_path, &iso_src)?;
+    iso_copy_grub_cfg(meta, &iso_src)?;
+    if text_mode {
+        iso_set_text_mode(&iso_src)?;
+    }
+    if with_initrd {
+        iso_add_initrd(meta, &iso_src, fresh)?;
+    }
+    create_iso(&iso_src, &iso_dst, fresh)?;
 
     Ok(iso_dst)
 }
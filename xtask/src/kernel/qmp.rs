@@ -0,0 +1,248 @@
+//! A small client for the QEMU Machine Protocol (QMP), used to control a running QEMU,
+//! see <https://www.qemu.org/docs/master/interop/qemu-qmp-ref.html>
+//!
+//! QMP is line based JSON, we only send a few commands, so the JSON is written by hand,
+//! and the replies are only checked for errors.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for QEMU to create the socket
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    fn name(self) -> &'static str {
+        match self {
+            MouseButton::Left => "left",
+            MouseButton::Right => "right",
+            MouseButton::Middle => "middle",
+        }
+    }
+}
+
+pub struct Qmp {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl Qmp {
+    /// Connect to the QMP socket at `path`, retrying until QEMU creates it
+    pub fn connect(path: &Path) -> anyhow::Result<Self> {
+        let start = Instant::now();
+        let stream = loop {
+            match UnixStream::connect(path) {
+                Ok(stream) => break stream,
+                Err(e) if start.elapsed() > CONNECT_TIMEOUT => {
+                    anyhow::bail!("could not connect to QMP at {}: {e}", path.display())
+                }
+                Err(_) => std::thread::sleep(Duration::from_millis(100)),
+            }
+        };
+
+        let mut qmp = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+        };
+        // the greeting
+        let greeting = qmp.read_line()?;
+        anyhow::ensure!(
+            greeting.contains("\"QMP\""),
+            "unexpected QMP greeting: {greeting}"
+        );
+        qmp.execute("qmp_capabilities", None)?;
+
+        Ok(qmp)
+    }
+
+    fn read_line(&mut self) -> anyhow::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            anyhow::bail!("QMP connection closed");
+        }
+        Ok(line)
+    }
+
+    /// Execute `command` with `arguments` (a JSON object) and wait for the reply
+    pub fn execute(&mut self, command: &str, arguments: Option<&str>) -> anyhow::Result<()> {
+        let request = match arguments {
+            Some(arguments) => format!("{{\"execute\":\"{command}\",\"arguments\":{arguments}}}\n"),
+            None => format!("{{\"execute\":\"{command}\"}}\n"),
+        };
+        self.stream.write_all(request.as_bytes())?;
+
+        loop {
+            let line = self.read_line()?;
+            // asynchronous events can come before the reply
+            if line.contains("\"event\"") && line.contains("\"timestamp\"") {
+                continue;
+            }
+            if line.contains("\"error\"") {
+                anyhow::bail!("QMP command `{command}` failed: {}", line.trim());
+            }
+            return Ok(());
+        }
+    }
+
+    /// Save the screen into `path`, as PNG if it ends with `.png`, otherwise as PPM
+    pub fn screendump(&mut self, path: &Path) -> anyhow::Result<()> {
+        // QEMU may not have the same working directory
+        let path = std::env::current_dir()?.join(path);
+        let format = if path.extension().is_some_and(|ext| ext == "png") {
+            "png"
+        } else {
+            "ppm"
+        };
+        self.execute(
+            "screendump",
+            Some(&format!(
+                "{{\"filename\":{},\"format\":\"{format}\"}}",
+                json_string(&path.to_string_lossy())
+            )),
+        )
+    }
+
+    /// Press the keys together and release them, the keys are QEMU `QKeyCode`s, i.e. `ctrl`, `a`, `ret`
+    pub fn send_keys(&mut self, keys: &[&str]) -> anyhow::Result<()> {
+        let keys = keys
+            .iter()
+            .map(|key| format!("{{\"type\":\"qcode\",\"data\":{}}}", json_string(key)))
+            .collect::<Vec<_>>()
+            .join(",");
+        self.execute("send-key", Some(&format!("{{\"keys\":[{keys}]}}")))
+    }
+
+    /// Type `text` as if from a US keyboard
+    pub fn type_text(&mut self, text: &str) -> anyhow::Result<()> {
+        for c in text.chars() {
+            let Some((shift, key)) = char_to_qcode(c) else {
+                anyhow::bail!("can't type {c:?}, use `SendKeys` instead");
+            };
+            if shift {
+                self.send_keys(&["shift", key])?;
+            } else {
+                self.send_keys(&[key])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the mouse relative to its current position
+    pub fn mouse_move(&mut self, dx: i64, dy: i64) -> anyhow::Result<()> {
+        self.execute(
+            "input-send-event",
+            Some(&format!(
+                "{{\"events\":[\
+                {{\"type\":\"rel\",\"data\":{{\"axis\":\"x\",\"value\":{dx}}}}},\
+                {{\"type\":\"rel\",\"data\":{{\"axis\":\"y\",\"value\":{dy}}}}}]}}"
+            )),
+        )
+    }
+
+    pub fn mouse_button(&mut self, button: MouseButton, down: bool) -> anyhow::Result<()> {
+        self.execute(
+            "input-send-event",
+            Some(&format!(
+                "{{\"events\":[{{\"type\":\"btn\",\"data\":{{\"down\":{down},\"button\":\"{}\"}}}}]}}",
+                button.name()
+            )),
+        )
+    }
+
+    pub fn pause(&mut self) -> anyhow::Result<()> {
+        self.execute("stop", None)
+    }
+
+    pub fn resume(&mut self) -> anyhow::Result<()> {
+        self.execute("cont", None)
+    }
+
+    pub fn reset(&mut self) -> anyhow::Result<()> {
+        self.execute("system_reset", None)
+    }
+
+    pub fn quit(&mut self) -> anyhow::Result<()> {
+        // QEMU may exit before replying, so don't wait for it
+        self.stream.write_all(b"{\"execute\":\"quit\"}\n")?;
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Returns the `QKeyCode` of `c` and whether it needs `shift`
+fn char_to_qcode(c: char) -> Option<(bool, &'static str)> {
+    const LETTERS: [&str; 26] = [
+        "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r",
+        "s", "t", "u", "v", "w", "x", "y", "z",
+    ];
+    const DIGITS: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+    let key = match c {
+        'a'..='z' => (false, LETTERS[c as usize - 'a' as usize]),
+        'A'..='Z' => (true, LETTERS[c as usize - 'A' as usize]),
+        '0'..='9' => (false, DIGITS[c as usize - '0' as usize]),
+        ' ' => (false, "spc"),
+        '\n' => (false, "ret"),
+        '\t' => (false, "tab"),
+        '-' => (false, "minus"),
+        '=' => (false, "equal"),
+        '[' => (false, "bracket_left"),
+        ']' => (false, "bracket_right"),
+        ';' => (false, "semicolon"),
+        '\'' => (false, "apostrophe"),
+        '`' => (false, "grave_accent"),
+        '\\' => (false, "backslash"),
+        ',' => (false, "comma"),
+        '.' => (false, "dot"),
+        '/' => (false, "slash"),
+        '!' => (true, "1"),
+        '@' => (true, "2"),
+        '#' => (true, "3"),
+        '$' => (true, "4"),
+        '%' => (true, "5"),
+        '^' => (true, "6"),
+        '&' => (true, "7"),
+        '*' => (true, "8"),
+        '(' => (true, "9"),
+        ')' => (true, "0"),
+        '_' => (true, "minus"),
+        '+' => (true, "equal"),
+        '{' => (true, "bracket_left"),
+        '}' => (true, "bracket_right"),
+        ':' => (true, "semicolon"),
+        '"' => (true, "apostrophe"),
+        '~' => (true, "grave_accent"),
+        '|' => (true, "backslash"),
+        '<' => (true, "comma"),
+        '>' => (true, "dot"),
+        '?' => (true, "slash"),
+        _ => return None,
+    };
+    Some(key)
+}
@@ -1,11 +1,27 @@
 use std::{path::PathBuf, process::Command};
 
+use super::{
+    qmp::Qmp,
+    script::{self, ScriptCommand},
+};
+
 pub struct RunConfig {
     iso_path: PathBuf,
     pub enable_debug_port: bool,
     pub enable_gdb: bool,
+    /// Expose the second serial port on `tcp::1235`, for the kernel's GDB stub
+    pub enable_gdb_stub: bool,
     pub enable_serial: bool,
     pub enable_graphics: bool,
+    pub enable_disk: bool,
+    /// File to write the `isa-debugcon` records to, see [`super::telemetry`]
+    pub telemetry_path: Option<PathBuf>,
+    /// File to write the tracepoints dumps to, see [`super::trace`]
+    pub trace_path: Option<PathBuf>,
+    /// Unix socket to expose QMP on, see [`super::qmp`]
+    pub qmp_path: Option<PathBuf>,
+    /// Commands to send through QMP while running, requires `qmp_path`
+    pub script: Option<Vec<ScriptCommand>>,
 }
 
 #[allow(dead_code)]
@@ -15,8 +31,14 @@ impl RunConfig {
             iso_path,
             enable_debug_port: false,
             enable_gdb: false,
+            enable_gdb_stub: false,
             enable_serial: false,
             enable_graphics: true,
+            enable_disk: true,
+            telemetry_path: None,
+            trace_path: None,
+            qmp_path: None,
+            script: None,
         }
     }
 
@@ -30,6 +52,11 @@ impl RunConfig {
         self
     }
 
+    pub fn with_gdb_stub(mut self, enable_gdb_stub: bool) -> Self {
+        self.enable_gdb_stub = enable_gdb_stub;
+        self
+    }
+
     pub fn with_serial(mut self, enable_serial: bool) -> Self {
         self.enable_serial = enable_serial;
         self
@@ -40,6 +67,31 @@ impl RunConfig {
         self
     }
 
+    pub fn with_disk(mut self, enable_disk: bool) -> Self {
+        self.enable_disk = enable_disk;
+        self
+    }
+
+    pub fn with_telemetry(mut self, telemetry_path: Option<PathBuf>) -> Self {
+        self.telemetry_path = telemetry_path;
+        self
+    }
+
+    pub fn with_trace(mut self, trace_path: Option<PathBuf>) -> Self {
+        self.trace_path = trace_path;
+        self
+    }
+
+    pub fn with_qmp(mut self, qmp_path: Option<PathBuf>) -> Self {
+        self.qmp_path = qmp_path;
+        self
+    }
+
+    pub fn with_script(mut self, script: Option<Vec<ScriptCommand>>) -> Self {
+        self.script = script;
+        self
+    }
+
     pub fn run(self, extra_args: &[String]) -> anyhow::Result<i32> {
         let mut cmd = Command::new("qemu-system-x86_64");
 
@@ -48,9 +100,11 @@ impl RunConfig {
             .arg("-m")
             .arg("512")
             .arg("-boot")
-            .arg("d")
-            .arg("-drive")
-            .arg("format=raw,file=fat:rw:filesystem");
+            .arg("d");
+
+        if self.enable_disk {
+            cmd.arg("-drive").arg("format=raw,file=fat:rw:filesystem");
+        }
 
         if self.enable_serial {
             cmd.arg("-serial").arg("mon:stdio");
@@ -60,11 +114,49 @@ impl RunConfig {
             cmd.arg("-s").arg("-S");
         }
 
+        if self.enable_gdb_stub {
+            // must come after the first serial, so that it becomes `COM2`
+            cmd.arg("-serial").arg("tcp::1235,server,nowait");
+        }
+
         if self.enable_debug_port {
             cmd.arg("-device")
                 .arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
         }
 
+        if let Some(telemetry_path) = &self.telemetry_path {
+            // remove the old records, qemu appends to the file
+            if telemetry_path.exists() {
+                std::fs::remove_file(telemetry_path)?;
+            }
+            cmd.arg("-chardev")
+                .arg(format!(
+                    "file,id=telemetry,path={}",
+                    telemetry_path.display()
+                ))
+                .arg("-device")
+                .arg("isa-debugcon,iobase=0xe9,chardev=telemetry");
+        }
+
+        if let Some(trace_path) = &self.trace_path {
+            if trace_path.exists() {
+                std::fs::remove_file(trace_path)?;
+            }
+            // the kernel enables the tracepoints only if this device is present
+            cmd.arg("-chardev")
+                .arg(format!("file,id=trace,path={}", trace_path.display()))
+                .arg("-device")
+                .arg("isa-debugcon,iobase=0xea,chardev=trace");
+        }
+
+        if let Some(qmp_path) = &self.qmp_path {
+            if qmp_path.exists() {
+                std::fs::remove_file(qmp_path)?;
+            }
+            cmd.arg("-qmp")
+                .arg(format!("unix:{},server=on,wait=off", qmp_path.display()));
+        }
+
         if !self.enable_graphics {
             cmd.arg("-display").arg("none");
         }
@@ -73,8 +165,26 @@ impl RunConfig {
 
         println!("[+] Running the kernel: {:?}", cmd);
 
-        cmd.status()
-            .map(|status| status.code().unwrap_or(1))
-            .map_err(|e| e.into())
+        let Some(script) = &self.script else {
+            return cmd
+                .status()
+                .map(|status| status.code().unwrap_or(1))
+                .map_err(|e| e.into());
+        };
+        let qmp_path = self
+            .qmp_path
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("running a script requires QMP"))?;
+
+        let mut child = cmd.spawn()?;
+        let result = Qmp::connect(qmp_path).and_then(|mut qmp| script::run(script, &mut qmp));
+        if let Err(e) = result {
+            // don't leave QEMU running if the test failed
+            let _ = child.kill();
+            child.wait()?;
+            return Err(e.context("script failed"));
+        }
+
+        Ok(child.wait()?.code().unwrap_or(1))
     }
 }
@@ -0,0 +1,262 @@
+//! Scripts for `cargo xtask run --script <file.ron>`, a list of commands that are sent to QEMU
+//! through [`super::qmp`] while the kernel is running, to automate interactive tests of the shell
+//! and the graphics programs.
+//!
+//! The file is a RON list, for example:
+//! ```ron
+//! [
+//!     Wait(3000),
+//!     Type("ls\n"),
+//!     SendKeys(["ctrl", "c"]),
+//!     MouseMove(100, -20),
+//!     MouseClick(Left),
+//!     Screenshot("target/shell.png"),
+//!     Quit,
+//! ]
+//! ```
+//!
+//! Only the subset of RON that we need is parsed: unit and tuple variants, strings, integers,
+//! lists and `//` comments.
+
+use std::{path::PathBuf, time::Duration};
+
+use super::qmp::{MouseButton, Qmp};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptCommand {
+    /// Sleep for the number of milliseconds
+    Wait(u64),
+    /// Type the text, as if from a US keyboard
+    Type(String),
+    /// Press the keys together, they are QEMU `QKeyCode`s
+    SendKeys(Vec<String>),
+    /// Save the screen into the file, PNG if it ends with `.png`, otherwise PPM
+    Screenshot(PathBuf),
+    /// Move the mouse relative to its current position
+    MouseMove(i64, i64),
+    /// Press and release a mouse button
+    MouseClick(MouseButton),
+    Pause,
+    Resume,
+    Reset,
+    Quit,
+}
+
+impl ScriptCommand {
+    pub fn execute(&self, qmp: &mut Qmp) -> anyhow::Result<()> {
+        match self {
+            ScriptCommand::Wait(ms) => std::thread::sleep(Duration::from_millis(*ms)),
+            ScriptCommand::Type(text) => qmp.type_text(text)?,
+            ScriptCommand::SendKeys(keys) => {
+                qmp.send_keys(&keys.iter().map(String::as_str).collect::<Vec<_>>())?
+            }
+            ScriptCommand::Screenshot(path) => qmp.screendump(path)?,
+            ScriptCommand::MouseMove(dx, dy) => qmp.mouse_move(*dx, *dy)?,
+            ScriptCommand::MouseClick(button) => {
+                qmp.mouse_button(*button, true)?;
+                qmp.mouse_button(*button, false)?;
+            }
+            ScriptCommand::Pause => qmp.pause()?,
+            ScriptCommand::Resume => qmp.resume()?,
+            ScriptCommand::Reset => qmp.reset()?,
+            ScriptCommand::Quit => qmp.quit()?,
+        }
+        Ok(())
+    }
+}
+
+/// Run the commands in order, stops after `Quit`
+pub fn run(commands: &[ScriptCommand], qmp: &mut Qmp) -> anyhow::Result<()> {
+    for command in commands {
+        println!("[+] Script: {command:?}");
+        command.execute(qmp)?;
+        if *command == ScriptCommand::Quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub fn load(path: &std::path::Path) -> anyhow::Result<Vec<ScriptCommand>> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("could not read {}: {e}", path.display()))?;
+    parse(&src).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))
+}
+
+#[derive(Debug)]
+enum Value {
+    /// A unit or tuple variant
+    Variant(String, Vec<Value>),
+    String(String),
+    Integer(i64),
+    List(Vec<Value>),
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn line(&self) -> usize {
+        self.src[..self.pos].lines().count().max(1)
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = &self.src[self.pos..];
+            if rest.starts_with("//") {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if self.peek().is_some_and(char::is_whitespace) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> anyhow::Result<()> {
+        self.skip_whitespace();
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            c => anyhow::bail!("line {}: expected {expected:?}, found {c:?}", self.line()),
+        }
+    }
+
+    /// Parse values separated by `,` until `end`, allowing a trailing `,`
+    fn sequence(&mut self, end: char) -> anyhow::Result<Vec<Value>> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(end) {
+                self.bump();
+                return Ok(values);
+            }
+            values.push(self.value()?);
+            self.skip_whitespace();
+            if self.peek() != Some(end) {
+                self.expect(',')?;
+            }
+        }
+    }
+
+    fn value(&mut self) -> anyhow::Result<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('[') => {
+                self.bump();
+                Ok(Value::List(self.sequence(']')?))
+            }
+            Some('"') => {
+                self.bump();
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => return Ok(Value::String(s)),
+                        Some('\\') => s.push(match self.bump() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('0') => '\0',
+                            Some(c @ ('"' | '\\')) => c,
+                            c => anyhow::bail!("line {}: invalid escape {c:?}", self.line()),
+                        }),
+                        Some(c) => s.push(c),
+                        None => anyhow::bail!("unterminated string"),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                self.bump();
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '_') {
+                    self.bump();
+                }
+                let number = self.src[start..self.pos].replace('_', "");
+                number
+                    .parse()
+                    .map(Value::Integer)
+                    .map_err(|e| anyhow::anyhow!("line {}: invalid number: {e}", self.line()))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    self.bump();
+                }
+                let name = self.src[start..self.pos].to_string();
+                self.skip_whitespace();
+                let args = if self.peek() == Some('(') {
+                    self.bump();
+                    self.sequence(')')?
+                } else {
+                    Vec::new()
+                };
+                Ok(Value::Variant(name, args))
+            }
+            c => anyhow::bail!("line {}: unexpected {c:?}", self.line()),
+        }
+    }
+}
+
+fn parse(src: &str) -> anyhow::Result<Vec<ScriptCommand>> {
+    let mut parser = Parser { src, pos: 0 };
+    let Value::List(values) = parser.value()? else {
+        anyhow::bail!("the script must be a list of commands");
+    };
+    parser.skip_whitespace();
+    anyhow::ensure!(
+        parser.peek().is_none(),
+        "line {}: unexpected content after the commands",
+        parser.line()
+    );
+
+    values.into_iter().map(to_command).collect()
+}
+
+fn to_command(value: Value) -> anyhow::Result<ScriptCommand> {
+    let (name, args) = match value {
+        Value::Variant(name, args) => (name, args),
+        value => anyhow::bail!("expected a command, found {value:?}"),
+    };
+
+    let command = match (name.as_str(), args.as_slice()) {
+        ("Wait", [Value::Integer(ms)]) if *ms >= 0 => ScriptCommand::Wait(*ms as u64),
+        ("Type", [Value::String(text)]) => ScriptCommand::Type(text.clone()),
+        ("SendKeys", [Value::List(keys)]) => ScriptCommand::SendKeys(
+            keys.iter()
+                .map(|key| match key {
+                    Value::String(key) => Ok(key.clone()),
+                    _ => Err(anyhow::anyhow!("`SendKeys` expects strings, found {key:?}")),
+                })
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        ("Screenshot", [Value::String(path)]) => ScriptCommand::Screenshot(PathBuf::from(path)),
+        ("MouseMove", [Value::Integer(dx), Value::Integer(dy)]) => {
+            ScriptCommand::MouseMove(*dx, *dy)
+        }
+        ("MouseClick", [Value::Variant(button, button_args)]) if button_args.is_empty() => {
+            ScriptCommand::MouseClick(match button.as_str() {
+                "Left" => MouseButton::Left,
+                "Right" => MouseButton::Right,
+                "Middle" => MouseButton::Middle,
+                _ => anyhow::bail!("unknown mouse button `{button}`"),
+            })
+        }
+        ("Pause", []) => ScriptCommand::Pause,
+        ("Resume", []) => ScriptCommand::Resume,
+        ("Reset", []) => ScriptCommand::Reset,
+        ("Quit", []) => ScriptCommand::Quit,
+        _ => anyhow::bail!("invalid command `{name}` with arguments {args:?}"),
+    };
+    Ok(command)
+}
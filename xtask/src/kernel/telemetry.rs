@@ -0,0 +1,300 @@
+//! Parse the records sent by the kernel over the `isa-debugcon` device
+//!
+//! Each record is a flat JSON object, prefixed by its length as a little-endian `u32`
+//! (see `kernel/src/hw/debugcon.rs`).
+
+use std::{collections::BTreeMap, fmt::Write as _, path::Path};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Null,
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{s}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Record {
+    pub kind: String,
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl Record {
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.fields.get(name) {
+            Some(Value::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_u64(&self, name: &str) -> Option<u64> {
+        match self.fields.get(name) {
+            Some(Value::Number(n)) => Some(*n as u64),
+            _ => None,
+        }
+    }
+}
+
+/// A minimal parser for flat JSON objects, the kernel doesn't send anything else
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.input[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> anyhow::Result<()> {
+        match self.peek() {
+            Some(got) if got == c => {
+                self.pos += c.len_utf8();
+                Ok(())
+            }
+            got => anyhow::bail!("expected {c:?}, got {got:?} at {}", self.pos),
+        }
+    }
+
+    fn parse_string(&mut self) -> anyhow::Result<String> {
+        self.expect('"')?;
+        let mut result = String::new();
+        let mut chars = self.input[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(result);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => result.push('\n'),
+                    Some('r') => result.push('\r'),
+                    Some('t') => result.push('\t'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let code = u32::from_str_radix(&hex, 16)?;
+                        result.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    Some(c) => result.push(c),
+                    None => break,
+                },
+                c => result.push(c),
+            }
+        }
+        anyhow::bail!("unterminated string")
+    }
+
+    fn parse_value(&mut self) -> anyhow::Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.parse_string()?)),
+            Some(_) => {
+                let rest = &self.input[self.pos..];
+                let len = rest
+                    .find(|c: char| c == ',' || c == '}' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                self.pos += len;
+                match &rest[..len] {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Null),
+                    number => Ok(Value::Number(number.parse()?)),
+                }
+            }
+            None => anyhow::bail!("expected a value"),
+        }
+    }
+
+    fn parse_object(&mut self) -> anyhow::Result<BTreeMap<String, Value>> {
+        let mut fields = BTreeMap::new();
+        self.expect('{')?;
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(fields);
+        }
+        loop {
+            let name = self.parse_string()?;
+            self.expect(':')?;
+            fields.insert(name, self.parse_value()?);
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            } else {
+                self.expect('}')?;
+                return Ok(fields);
+            }
+        }
+    }
+}
+
+fn parse_record(data: &str) -> anyhow::Result<Record> {
+    let mut fields = Parser {
+        input: data,
+        pos: 0,
+    }
+    .parse_object()?;
+    let kind = match fields.remove("type") {
+        Some(Value::String(kind)) => kind,
+        _ => anyhow::bail!("record without a type: {data}"),
+    };
+    Ok(Record { kind, fields })
+}
+
+/// Read all the records in `path`, an incomplete record at the end (i.e. QEMU was killed) is ignored
+pub fn read_records(path: &Path) -> anyhow::Result<Vec<Record>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut records = Vec::new();
+    let mut rest = data.as_slice();
+    while rest.len() >= 4 {
+        let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        let Some(record) = rest.get(4..4 + len) else {
+            println!("[!] Incomplete telemetry record at the end");
+            break;
+        };
+        match std::str::from_utf8(record)
+            .map_err(anyhow::Error::from)
+            .and_then(parse_record)
+        {
+            Ok(record) => records.push(record),
+            Err(e) => println!("[!] Invalid telemetry record: {e}"),
+        }
+        rest = &rest[4 + len..];
+    }
+    Ok(records)
+}
+
+pub fn print_records(records: &[Record]) {
+    if records.is_empty() {
+        return;
+    }
+
+    println!("[+] Telemetry:");
+    for record in records {
+        match record.kind.as_str() {
+            "test" => {
+                let name = record.get_str("name").unwrap_or("?");
+                let result = record.get_str("result").unwrap_or("?");
+                println!("    test {name} ... {}", result.to_uppercase());
+                if let Some(output) = record.get_str("output") {
+                    for line in output.lines() {
+                        println!("        | {line}");
+                    }
+                }
+            }
+            "test_summary" => println!(
+                "    tests: {} passed; {} failed; {} ignored",
+                record.get_u64("passed").unwrap_or(0),
+                record.get_u64("failed").unwrap_or(0),
+                record.get_u64("ignored").unwrap_or(0),
+            ),
+            "panic" => println!(
+                "    PANIC: {}",
+                record.get_str("message").unwrap_or("<no message>")
+            ),
+            "boot_step" => {
+                let nanos = record.get_u64("duration").unwrap_or(0);
+                println!(
+                    "    boot {:<35} {:>10.3}ms",
+                    record.get_str("name").unwrap_or("?"),
+                    nanos as f64 / 1_000_000.0
+                );
+            }
+            "boot_total" => println!(
+                "    boot total {:>40.3}ms",
+                record.get_u64("nanos").unwrap_or(0) as f64 / 1_000_000.0
+            ),
+            "profile" => {
+                let nanos = record.get_u64("nanos").unwrap_or(0);
+                println!(
+                    "    profile {:<32} {:>10.3}ms",
+                    record.get_str("name").unwrap_or("?"),
+                    nanos as f64 / 1_000_000.0
+                );
+            }
+            kind => {
+                let fields = record
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                println!("    {kind}: {fields}");
+            }
+        }
+    }
+}
+
+/// Append the `boot_total` in `records` to the history file `path`, and print how it compares
+/// to the last run of another commit with the same `config` (the build and kernel profiles).
+///
+/// Each line of the history is `<commit> <config> <nanos>`, where `<commit>` is from `git describe`.
+pub fn track_boot_time(records: &[Record], path: &Path, config: &str) -> anyhow::Result<()> {
+    let Some(total) = records
+        .iter()
+        .find(|record| record.kind == "boot_total")
+        .and_then(|record| record.get_u64("nanos"))
+    else {
+        return Ok(());
+    };
+
+    let output = std::process::Command::new("git")
+        .args(["describe", "--always", "--dirty"])
+        .output()?;
+    anyhow::ensure!(output.status.success(), "`git describe` failed");
+    let commit = String::from_utf8(output.stdout)?.trim().to_string();
+
+    let mut history = match std::fs::read_to_string(path) {
+        Ok(history) => history,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let previous = history.lines().rev().find_map(|line| {
+        let mut parts = line.split(' ');
+        let (Some(line_commit), Some(line_config), Some(nanos)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        if line_commit == commit || line_config != config {
+            return None;
+        }
+        Some((line_commit.to_string(), nanos.parse::<u64>().ok()?))
+    });
+
+    let ms = |nanos: u64| nanos as f64 / 1_000_000.0;
+    match previous {
+        Some((previous_commit, previous_total)) => {
+            let change = (total as f64 - previous_total as f64) / previous_total as f64 * 100.;
+            println!(
+                "[+] Boot time: {:.3}ms, {change:+.1}% from {:.3}ms at {previous_commit} ({config})",
+                ms(total),
+                ms(previous_total)
+            );
+        }
+        None => println!("[+] Boot time: {:.3}ms ({config})", ms(total)),
+    }
+
+    writeln!(history, "{commit} {config} {total}")?;
+    std::fs::write(path, history)?;
+    Ok(())
+}
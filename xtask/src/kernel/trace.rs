@@ -0,0 +1,297 @@
+//! Decode the tracepoints dumps sent by the kernel over the trace `isa-debugcon` device
+//! (see `kernel/src/tracepoints.rs`) into the Chrome trace-event JSON format, which can be
+//! opened in `chrome://tracing` or <https://ui.perfetto.dev>.
+//!
+//! Each CPU is shown as a thread, with the running processes, syscalls and disk commands as
+//! nested spans, and page faults as instant events.
+
+use std::{fmt::Write, path::Path};
+
+const DUMP_MAGIC: &[u8; 8] = b"EMTRACE1";
+const HEADER_SIZE: usize = 48;
+const RECORD_SIZE: usize = 32;
+/// The pid of records that are not in a process
+const NO_PROCESS: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    SchedulerSwitchIn,
+    SchedulerSwitchOut,
+    SyscallEnter,
+    SyscallExit,
+    PageFault,
+    DiskReadStart,
+    DiskReadEnd,
+    DiskWriteStart,
+    DiskWriteEnd,
+}
+
+impl Event {
+    fn from_u16(value: u16) -> Option<Self> {
+        Some(match value {
+            1 => Event::SchedulerSwitchIn,
+            2 => Event::SchedulerSwitchOut,
+            3 => Event::SyscallEnter,
+            4 => Event::SyscallExit,
+            5 => Event::PageFault,
+            6 => Event::DiskReadStart,
+            7 => Event::DiskReadEnd,
+            8 => Event::DiskWriteStart,
+            9 => Event::DiskWriteEnd,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Record {
+    cpu: u32,
+    nanos: u64,
+    pid: u64,
+    arg: u64,
+    event: Event,
+    arg2: u32,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Parse all the dumps in `data`, an incomplete dump at the end (i.e. QEMU was killed) is ignored
+fn parse_dumps(mut data: &[u8]) -> anyhow::Result<Vec<Record>> {
+    let mut records = Vec::new();
+
+    while !data.is_empty() {
+        if data.len() < HEADER_SIZE {
+            println!("[!] Incomplete trace dump header at the end");
+            break;
+        }
+        anyhow::ensure!(
+            &data[..8] == DUMP_MAGIC,
+            "invalid trace dump magic {:?}",
+            &data[..8]
+        );
+        let cpu = u32_at(data, 8);
+        let count = u32_at(data, 12) as usize;
+        let (start_cycles, start_nanos) = (u64_at(data, 16), u64_at(data, 24));
+        let (end_cycles, end_nanos) = (u64_at(data, 32), u64_at(data, 40));
+
+        // the kernel sends the same point twice if it couldn't read the clocks at the end
+        let cycles_to_nanos = |cycles: u64| -> u64 {
+            if end_cycles <= start_cycles {
+                return cycles;
+            }
+            let elapsed = cycles as i128 - start_cycles as i128;
+            let nanos =
+                elapsed * (end_nanos - start_nanos) as i128 / (end_cycles - start_cycles) as i128;
+            (start_nanos as i128 + nanos).max(0) as u64
+        };
+
+        let Some(dump) = data.get(HEADER_SIZE..HEADER_SIZE + count * RECORD_SIZE) else {
+            println!("[!] Incomplete trace dump of CPU {cpu} at the end");
+            break;
+        };
+        for raw in dump.chunks_exact(RECORD_SIZE) {
+            let event = u16::from_le_bytes(raw[24..26].try_into().unwrap());
+            let Some(event) = Event::from_u16(event) else {
+                println!("[!] Unknown trace event {event} on CPU {cpu}");
+                continue;
+            };
+            records.push(Record {
+                cpu,
+                nanos: cycles_to_nanos(u64_at(raw, 0)),
+                pid: u64_at(raw, 8),
+                arg: u64_at(raw, 16),
+                event,
+                arg2: u32_at(raw, 28),
+            });
+        }
+
+        data = &data[HEADER_SIZE + count * RECORD_SIZE..];
+    }
+
+    records.sort_by_key(|record| (record.cpu, record.nanos));
+    Ok(records)
+}
+
+/// A span that started and didn't end yet
+struct OpenSpan {
+    category: &'static str,
+    name: String,
+    start: u64,
+    args: String,
+}
+
+struct ChromeTrace {
+    output: String,
+    first: bool,
+}
+
+impl ChromeTrace {
+    fn new() -> Self {
+        Self {
+            output: String::from("{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n"),
+            first: true,
+        }
+    }
+
+    fn push(&mut self, event: &str) {
+        if !self.first {
+            self.output.push_str(",\n");
+        }
+        self.first = false;
+        self.output.push_str(event);
+    }
+
+    fn thread_name(&mut self, cpu: u32) {
+        self.push(&format!(
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{cpu},\"args\":{{\"name\":\"CPU {cpu}\"}}}}"
+        ));
+    }
+
+    fn span(&mut self, cpu: u32, span: OpenSpan, end: u64) {
+        self.push(&format!(
+            "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":{cpu},\"ts\":{:.3},\"dur\":{:.3},\"args\":{{{}}}}}",
+            span.name,
+            span.category,
+            span.start as f64 / 1000.0,
+            (end - span.start) as f64 / 1000.0,
+            span.args,
+        ));
+    }
+
+    fn instant(&mut self, cpu: u32, category: &str, name: &str, at: u64, args: &str) {
+        self.push(&format!(
+            "{{\"name\":\"{name}\",\"cat\":\"{category}\",\"ph\":\"i\",\"s\":\"t\",\"pid\":0,\"tid\":{cpu},\"ts\":{:.3},\"args\":{{{args}}}}}",
+            at as f64 / 1000.0,
+        ));
+    }
+
+    fn finish(mut self) -> String {
+        self.output.push_str("\n]}\n");
+        self.output
+    }
+}
+
+fn pid_arg(pid: u64) -> String {
+    if pid == NO_PROCESS {
+        String::from("\"pid\":\"kernel\"")
+    } else {
+        format!("\"pid\":{pid}")
+    }
+}
+
+/// Convert the records (sorted by CPU and time) into spans, an end without a start (the start was
+/// overwritten in the ring buffer) is dropped, and spans without an end are closed at the last record
+fn to_chrome_trace(records: &[Record]) -> String {
+    let mut trace = ChromeTrace::new();
+    let mut open: Vec<OpenSpan> = Vec::new();
+    let mut current_cpu = None;
+    let mut last_time = 0;
+
+    for record in records {
+        if current_cpu != Some(record.cpu) {
+            if let Some(cpu) = current_cpu {
+                while let Some(span) = open.pop() {
+                    trace.span(cpu, span, last_time);
+                }
+            }
+            current_cpu = Some(record.cpu);
+            trace.thread_name(record.cpu);
+        }
+        last_time = record.nanos;
+
+        let start = |category, name: String, args: String| OpenSpan {
+            category,
+            name,
+            start: record.nanos,
+            args,
+        };
+        let disk_args = format!("\"sector\":{},\"sectors\":{}", record.arg, record.arg2);
+        let (span, end_category) = match record.event {
+            Event::SchedulerSwitchIn => (
+                Some(start(
+                    "sched",
+                    format!("pid {}", record.arg),
+                    pid_arg(record.arg),
+                )),
+                None,
+            ),
+            Event::SyscallEnter => (
+                Some(start(
+                    "syscall",
+                    format!("syscall {}", record.arg),
+                    pid_arg(record.pid),
+                )),
+                None,
+            ),
+            Event::DiskReadStart => (
+                Some(start("disk", String::from("disk read"), disk_args)),
+                None,
+            ),
+            Event::DiskWriteStart => (
+                Some(start("disk", String::from("disk write"), disk_args)),
+                None,
+            ),
+            Event::SchedulerSwitchOut => (None, Some("sched")),
+            Event::SyscallExit => (None, Some("syscall")),
+            Event::DiskReadEnd | Event::DiskWriteEnd => (None, Some("disk")),
+            Event::PageFault => {
+                let mut args = pid_arg(record.pid);
+                write!(
+                    args,
+                    ",\"address\":\"{:#x}\",\"error\":\"{:#x}\"",
+                    record.arg, record.arg2
+                )
+                .unwrap();
+                trace.instant(record.cpu, "fault", "page fault", record.nanos, &args);
+                (None, None)
+            }
+        };
+
+        if let Some(span) = span {
+            open.push(span);
+        }
+        if let Some(category) = end_category {
+            // close the inner spans as well, their end was not recorded (i.e. the syscall exited the process)
+            if let Some(index) = open.iter().rposition(|span| span.category == category) {
+                while open.len() > index {
+                    let span = open.pop().unwrap();
+                    trace.span(record.cpu, span, record.nanos);
+                }
+            }
+        }
+    }
+    if let Some(cpu) = current_cpu {
+        while let Some(span) = open.pop() {
+            trace.span(cpu, span, last_time);
+        }
+    }
+
+    trace.finish()
+}
+
+/// Decode the dumps in `input` into a Chrome trace-event JSON file in `output`
+pub fn decode(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let data = std::fs::read(input)
+        .map_err(|e| anyhow::anyhow!("could not read {}: {e}", input.display()))?;
+    let records = parse_dumps(&data)?;
+    if records.is_empty() {
+        println!(
+            "[!] No trace records in {}, was the kernel shut down cleanly?",
+            input.display()
+        );
+    }
+
+    std::fs::write(output, to_chrome_trace(&records))?;
+    println!(
+        "[+] Wrote {} trace records to {}, open it in `chrome://tracing` or https://ui.perfetto.dev",
+        records.len(),
+        output.display()
+    );
+    Ok(())
+}
@@ -1,10 +1,11 @@
 mod args;
+mod host_test;
 mod kernel;
 mod toolchain;
 mod userspace;
 mod utils;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use utils::NoDebug;
 
@@ -59,26 +60,76 @@ fn main() -> anyhow::Result<()> {
 
     match args.cmd {
         Command::Run(run) => {
-            let iso_path = kernel::iso::build_normal_iso(&meta)?;
+            // load the script first, so we don't build everything just to fail on a typo
+            let script = run
+                .script
+                .as_ref()
+                .map(|path| kernel::script::load(Path::new(path)))
+                .transpose()?;
+            // the programs must be built first, as they can be included in the initrd
             userspace::build_programs(&meta, Default::default())?;
+            // without a disk driver, the initrd is the root filesystem
+            let no_disk = run.no_disk || !run.kernel_profile.has_disk();
+            let iso_path = kernel::iso::build_normal_iso(
+                &meta,
+                no_disk,
+                run.fresh,
+                run.text_mode,
+                run.kernel_profile,
+            )?;
+            let telemetry_path = meta.target_path.join("telemetry.bin");
+            let trace_path = run.trace.then(|| meta.target_path.join("trace.bin"));
+            let qmp_path = (run.qmp || script.is_some()).then(|| meta.target_path.join("qmp.sock"));
             let result = kernel::run::RunConfig::new(iso_path)
                 .with_serial(true)
                 .with_gdb(run.gdb)
+                .with_gdb_stub(run.gdb_stub)
                 .with_debug_port(true)
                 .with_graphics(!run.no_graphics)
+                .with_disk(!no_disk)
+                .with_telemetry(Some(telemetry_path.clone()))
+                .with_trace(trace_path.clone())
+                .with_qmp(qmp_path)
+                .with_script(script)
                 .run(&run.extra)?;
 
+            let records = kernel::telemetry::read_records(&telemetry_path)?;
+            kernel::telemetry::print_records(&records);
+            kernel::telemetry::track_boot_time(
+                &records,
+                &meta.target_path.join("boot_times.txt"),
+                &format!("{}/{}", meta.profile_path(), run.kernel_profile.name()),
+            )?;
+            if let Some(trace_path) = trace_path {
+                println!(
+                    "[+] Trace recorded in {}, use `cargo xtask trace` to decode it",
+                    trace_path.display()
+                );
+            }
+
             std::process::exit(result);
         }
         Command::Test(test) => {
             let iso_path = kernel::iso::build_test_iso(&meta)?;
+            let telemetry_path = meta.target_path.join("telemetry_test.bin");
             let result = kernel::run::RunConfig::new(iso_path)
                 .with_serial(true)
                 .with_gdb(test.gdb)
                 .with_debug_port(true)
                 .with_graphics(false)
+                .with_telemetry(Some(telemetry_path.clone()))
                 .run(&test.extra)?;
 
+            let records = kernel::telemetry::read_records(&telemetry_path)?;
+            kernel::telemetry::print_records(&records);
+            // the kernel may exit with success if the tests didn't finish, i.e. a panic outside of them
+            if records.iter().any(|record| record.kind == "panic")
+                && !records.iter().any(|record| record.kind == "test_summary")
+            {
+                println!("Test failed! the kernel panicked before finishing the tests");
+                std::process::exit(1);
+            }
+
             let code = result >> 1;
 
             // custom exit code as qemu can't return 0
@@ -91,11 +142,33 @@ fn main() -> anyhow::Result<()> {
                 std::process::exit(1);
             }
         }
-        Command::BuildIso(_) => {
-            kernel::iso::build_normal_iso(&meta)?;
+        Command::TestHost(test) => {
+            host_test::run(&meta, test)?;
+        }
+        Command::Trace(trace) => {
+            let input = trace
+                .input
+                .map(PathBuf::from)
+                .unwrap_or_else(|| meta.target_path.join("trace.bin"));
+            let output = trace
+                .out
+                .map(PathBuf::from)
+                .unwrap_or_else(|| meta.target_path.join("trace.json"));
+            kernel::trace::decode(&input, &output)?;
+        }
+        Command::BuildIso(build_iso) => {
+            kernel::iso::build_normal_iso(
+                &meta,
+                false,
+                build_iso.fresh,
+                false,
+                Default::default(),
+            )?;
         }
         Command::Kernel(cmd) => match cmd.cmd {
-            RustMiscCmd::Build(build) => kernel::build::build_kernel(&meta, build).map(|_| ())?,
+            RustMiscCmd::Build(build) => {
+                kernel::build::build_kernel(&meta, build, Default::default()).map(|_| ())?
+            }
             RustMiscCmd::Check(check) => kernel::check::check(&meta, check)?,
             RustMiscCmd::Clippy(clippy) => kernel::check::clippy(&meta, clippy)?,
             RustMiscCmd::Fmt(fmt) => kernel::check::fmt(&meta, fmt)?,
@@ -1,6 +1,9 @@
 pub mod check;
 
+use std::fmt::Write;
+
 use cargo_metadata::Package;
+use emerald_crypto::sha256::Sha256;
 
 use crate::{
     args::Build,
@@ -9,6 +12,9 @@ use crate::{
 };
 
 const TARGET: &str = "x86_64-unknown-emerald";
+/// Manifest of the `SHA-256` hashes of all userspace binaries, used by the kernel
+/// to verify binaries before executing them (with `verify_binaries` cmdline flag)
+const BINARIES_MANIFEST: &str = "binaries.manifest";
 
 fn toolchain_path(meta: &GlobalMeta) -> std::path::PathBuf {
     meta.root_path
@@ -129,6 +135,7 @@ fn run_for_all_userspace_members(
 
 pub fn copy_to_filesystem(meta: &GlobalMeta) -> anyhow::Result<()> {
     let userspace_packages = userspace_packages(meta);
+    let mut binaries = Vec::new();
 
     for package in userspace_packages {
         for target in package
@@ -140,9 +147,30 @@ pub fn copy_to_filesystem(meta: &GlobalMeta) -> anyhow::Result<()> {
                 userspace_output_path(meta, &target.name),
                 meta.filesystem_path.join(target.name.as_str()),
             )?;
+            binaries.push(target.name.as_str());
+        }
+    }
+
+    write_binaries_manifest(meta, &binaries)
+}
+
+/// Write the hashes of the binaries in the same format as `sha256sum`,
+/// i.e. `<hash>  /<binary>` per line
+fn write_binaries_manifest(meta: &GlobalMeta, binaries: &[&str]) -> anyhow::Result<()> {
+    let mut manifest = String::new();
+
+    for name in binaries {
+        let content = std::fs::read(meta.filesystem_path.join(name))?;
+        for byte in Sha256::digest(&content) {
+            write!(manifest, "{byte:02x}")?;
         }
+        writeln!(manifest, "  /{name}")?;
     }
 
+    let manifest_path = meta.filesystem_path.join(BINARIES_MANIFEST);
+    println!("[+] Writing binaries manifest to {:?}", manifest_path);
+    std::fs::write(manifest_path, manifest)?;
+
     Ok(())
 }
 